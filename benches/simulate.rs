@@ -0,0 +1,28 @@
+//! Cycles per second of the bundled designs, see `hestia bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use hestia::bench::{measure, DESIGNS};
+
+const CYCLES: usize = 10000;
+
+fn simulate(c: &mut Criterion) {
+    hestia::common::init();
+    let mut group = c.benchmark_group("simulate");
+    group.throughput(Throughput::Elements(CYCLES as u64));
+    for design in DESIGNS {
+        group.bench_function(design.name, |b| {
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|_| match measure(design, CYCLES) {
+                        Ok(measure) => measure.time,
+                        Err(err) => panic!("{}: {}", design.name, err),
+                    })
+                    .sum()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, simulate);
+criterion_main!(benches);
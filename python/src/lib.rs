@@ -0,0 +1,227 @@
+//! Python bindings of the simulator, so that regression suites written in
+//! Python can load a design, drive it and assert on its memories.
+//!
+//! ```python
+//! import hestia
+//!
+//! sim = hestia.Simulator()
+//! sim.load("example/json/gemm/tor.json")
+//! sim.call("main")
+//! sim.run()
+//! assert not sim.failed
+//! ```
+
+use hestia_core::basetype::{parse_value, VALUE};
+use hestia_core::common::{level_name, State};
+use hestia_core::simulator;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyComplex;
+use pyo3::IntoPyObjectExt;
+
+fn to_python(py: Python<'_>, value: &VALUE) -> PyResult<Py<PyAny>> {
+    match *value {
+        VALUE::Bool(x) => x.into_py_any(py),
+        VALUE::U8(x) => x.into_py_any(py),
+        VALUE::I8(x) => x.into_py_any(py),
+        VALUE::U16(x) => x.into_py_any(py),
+        VALUE::I16(x) => x.into_py_any(py),
+        VALUE::U32(x) => x.into_py_any(py),
+        VALUE::I32(x) => x.into_py_any(py),
+        VALUE::U64(x) | VALUE::Index(x) => x.into_py_any(py),
+        VALUE::I64(x) => x.into_py_any(py),
+        VALUE::F16(x) => x.to_f32().into_py_any(py),
+        VALUE::BF16(x) => x.to_f32().into_py_any(py),
+        VALUE::F32(x) => x.into_py_any(py),
+        VALUE::F64(x) => x.into_py_any(py),
+        VALUE::Control | VALUE::ERROR | VALUE::X => Ok(py.None()),
+        VALUE::C32(x) => PyComplex::from_doubles(py, x.re as f64, x.im as f64).into_py_any(py),
+        VALUE::C64(x) => PyComplex::from_doubles(py, x.re, x.im).into_py_any(py),
+        VALUE::Vector(vector) => {
+            let lanes = (0..vector.lanes()).map(|index| vector.lane(index));
+            to_pythons(py, &lanes.collect::<Vec<_>>())?.into_py_any(py)
+        }
+    }
+}
+
+fn to_pythons(py: Python<'_>, values: &[VALUE]) -> PyResult<Vec<Py<PyAny>>> {
+    values.iter().map(|value| to_python(py, value)).collect()
+}
+
+/// Read a Python bool, int or float as a value of `data_type`.
+fn from_python(value: &Bound<'_, PyAny>, data_type: &str) -> PyResult<VALUE> {
+    let text = value.str()?.to_string();
+    let text = match text.as_str() {
+        "True" => "1".to_string(),
+        "False" => "0".to_string(),
+        _ => text,
+    };
+    match parse_value(data_type, &text) {
+        Some(VALUE::ERROR) => Err(PyValueError::new_err(format!("unknown type {}", data_type))),
+        Some(value) => Ok(value),
+        None => Err(PyValueError::new_err(format!(
+            "{} is not a valid {}",
+            text, data_type
+        ))),
+    }
+}
+
+fn parse_level(level: &str) -> PyResult<State> {
+    match level {
+        "software" => Ok(State::Software),
+        "tor" => Ok(State::ToR),
+        "hec" => Ok(State::HEC),
+        "cosim" => Ok(State::Cosim),
+        _ => Err(PyValueError::new_err(format!("unknown level {}", level))),
+    }
+}
+
+fn runtime_error(err: impl ToString) -> PyErr {
+    PyRuntimeError::new_err(err.to_string())
+}
+
+/// Each `Simulator` has its own design and run, while breakpoints are shared
+/// by all of them.
+#[pyclass(unsendable)]
+struct Simulator {
+    inner: simulator::Simulator,
+}
+
+#[pymethods]
+impl Simulator {
+    #[new]
+    fn new() -> Self {
+        Simulator {
+            inner: simulator::Simulator::new(),
+        }
+    }
+
+    /// Load an IR file of any level and return the level it switched to.
+    fn load(&mut self, path: &str) -> PyResult<&'static str> {
+        let level = self.inner.load(path).map_err(runtime_error)?;
+        Ok(level_name(level))
+    }
+
+    fn load_software(&mut self, path: &str) -> PyResult<()> {
+        self.inner.load_software(path).map_err(runtime_error)
+    }
+
+    fn load_tor(&mut self, path: &str) -> PyResult<()> {
+        self.inner.load_tor(path).map_err(runtime_error)
+    }
+
+    fn load_hec(&mut self, path: &str) -> PyResult<()> {
+        self.inner.load_hec(path).map_err(runtime_error)
+    }
+
+    fn merge_hec(&mut self, path: &str) -> PyResult<()> {
+        self.inner.merge_hec(path).map_err(runtime_error)
+    }
+
+    fn load_equal(&mut self, path: &str) -> PyResult<()> {
+        self.inner.load_equal(path).map_err(runtime_error)
+    }
+
+    #[getter]
+    fn level(&self) -> &'static str {
+        level_name(self.inner.level())
+    }
+
+    fn switch(&mut self, level: &str) -> PyResult<()> {
+        self.inner.switch(parse_level(level)?);
+        Ok(())
+    }
+
+    /// Start `function` of the current level, arguments are passed as text.
+    #[pyo3(signature = (function, *args))]
+    fn call(&mut self, function: &str, args: Vec<Bound<'_, PyAny>>) -> PyResult<()> {
+        let args = args
+            .iter()
+            .map(|arg| arg.str().map(|text| text.to_string()))
+            .collect::<PyResult<Vec<String>>>()?;
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+        self.inner.call(function, &args).map_err(runtime_error)
+    }
+
+    #[pyo3(signature = (count=1))]
+    fn step(&mut self, count: usize) {
+        self.inner.step(count);
+    }
+
+    fn run(&mut self) {
+        self.inner.run();
+    }
+
+    #[getter]
+    fn cycle(&self) -> usize {
+        self.inner.cycle()
+    }
+
+    /// Whether an operation has failed since the simulator was created.
+    #[getter]
+    fn failed(&self) -> bool {
+        self.inner.failed()
+    }
+
+    fn memories(&self) -> Vec<String> {
+        self.inner.memory_names()
+    }
+
+    /// The contents of a memory, unknown values read as None.
+    fn peek_memory(&self, py: Python<'_>, memory: &str) -> PyResult<Vec<Py<PyAny>>> {
+        match self.inner.read_memory(memory) {
+            Some(values) => to_pythons(py, &values),
+            None => Err(runtime_error(format!("No memory {}", memory))),
+        }
+    }
+
+    /// Overwrite a memory from its first element, keeping the type of the
+    /// values it holds.
+    fn poke_memory(&mut self, memory: &str, values: Vec<Bound<'_, PyAny>>) -> PyResult<()> {
+        let Some(mut store) = self.inner.read_memory(memory) else {
+            return Err(runtime_error(format!("No memory {}", memory)));
+        };
+        if values.len() > store.len() {
+            return Err(PyValueError::new_err(format!(
+                "{} values do not fit in {} of size {}",
+                values.len(),
+                memory,
+                store.len()
+            )));
+        }
+        for (slot, value) in store.iter_mut().zip(&values) {
+            let data_type = match slot.get_type().as_str() {
+                "error" | "i1000" => "i32".to_string(),
+                data_type => data_type.to_string(),
+            };
+            *slot = from_python(value, &data_type)?;
+        }
+        self.inner.write_memory(memory, &store);
+        Ok(())
+    }
+
+    /// Values of a variable in every active instance of the current level.
+    fn peek_variable(&self, py: Python<'_>, var: &str) -> PyResult<Vec<Py<PyAny>>> {
+        to_pythons(py, &self.inner.read_variable(var))
+    }
+
+    /// Append a value of `type` to a ToR stream or HEC FIFO.
+    #[pyo3(signature = (stream, value, r#type="i32"))]
+    fn poke_stream(&mut self, stream: &str, value: Bound<'_, PyAny>, r#type: &str) -> PyResult<()> {
+        let value = from_python(&value, r#type)?;
+        self.inner.push_stream(stream, value).map_err(runtime_error)
+    }
+
+    fn set_breakpoint(&mut self, name: &str) {
+        self.inner.set_breakpoint(name);
+    }
+
+    fn unset_breakpoint(&mut self, name: &str) {
+        self.inner.unset_breakpoint(name);
+    }
+}
+
+#[pymodule]
+fn hestia(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Simulator>()
+}
@@ -1,6 +1,6 @@
 extern crate proc_macro;
 use proc_macro::{TokenStream};
-use quote::{quote};
+use quote::{format_ident, quote};
 use syn::DeriveInput;
 use syn::{self, Data};
 
@@ -17,6 +17,14 @@ pub fn set_value_derive(input: TokenStream) -> TokenStream {
     let mut get_vector = quote!();
     let mut new_element = quote!();
     let mut valid = quote!();
+    let mut ports = quote!();
+    let mut consts = quote!();
+    let mut port_of = quote!();
+    let mut port_of_vector = quote!();
+    let mut port_value = quote!();
+    let mut set_port_value = quote!();
+    let mut port_name = quote!();
+    let mut field_count: u16 = 0;
     for f in s.fields.iter() {
         let (field_id, field_ty) = (&f.ident, &f.ty);
         if let Some(field_id) = field_id {
@@ -32,9 +40,17 @@ pub fn set_value_derive(input: TokenStream) -> TokenStream {
                     new_element.extend(quote! { #field_id: #field_ty::new(), });
                 }
                 "HandshakeValue" => {
+                    let (field, port) = (format_ident!("{}", id_str.to_uppercase()), field_count);
+                    field_count += 1;
+                    consts.extend(quote! { pub const #field: Port = Port::new(#port); });
+                    port_of.extend(quote! { #id_str => return Some(Self::#field), });
+                    port_value.extend(quote! { #port => self.#field_id, });
+                    set_port_value.extend(quote! { #port => self.#field_id = val, });
+                    port_name.extend(quote! { #port => #id_str.to_string(), });
                     element.extend(quote! { #id_str => { self.#field_id = val } });
                     get_element.extend(quote! { #id_str => { self.#field_id } });
                     new_element.extend(quote! { #field_id: #field_ty::empty(), });
+                    ports.extend(quote! { ports.push((#id_str.to_string(), self.#field_id)); });
                     valid.extend(quote! {
                         if self.#field_id.valid {
                             return true
@@ -42,6 +58,18 @@ pub fn set_value_derive(input: TokenStream) -> TokenStream {
                     })
                 }
                 "Vec < HandshakeValue >" => {
+                    let (field, port) = (format_ident!("{}", id_str.to_uppercase()), field_count);
+                    field_count += 1;
+                    consts.extend(quote! { pub const #field: Port = Port::new(#port); });
+                    port_of_vector.extend(quote! {
+                        if let Some(index) = name.strip_prefix(concat!(#id_str, ".")) {
+                            let index = index.parse::<usize>().ok()?;
+                            return (index < self.#field_id.len()).then(|| Self::#field.at(index));
+                        }
+                    });
+                    port_value.extend(quote! { #port => self.#field_id[port.index()], });
+                    set_port_value.extend(quote! { #port => self.#field_id[port.index()] = val, });
+                    port_name.extend(quote! { #port => format!("{}.{}", #id_str, port.index()), });
                     vector.extend(quote! {
                         if str.starts_with(#id_str) {
                             if let Some((_, b)) = str.split_once(".") {
@@ -58,6 +86,11 @@ pub fn set_value_derive(input: TokenStream) -> TokenStream {
                         }
                     });
                     new_element.extend(quote! { #field_id: <#field_ty>::default(), });
+                    ports.extend(quote! {
+                        for (i, data) in self.#field_id.iter().enumerate() {
+                            ports.push((format!("{}.{}", #id_str, i), *data));
+                        }
+                    });
                     valid.extend(quote! {
                         for data in &self.#field_id {
                             if data.valid {
@@ -66,7 +99,7 @@ pub fn set_value_derive(input: TokenStream) -> TokenStream {
                         }
                     })
                 }
-                "fn(& Vec < VALUE >) -> VALUE" => {
+                "fn(& [VALUE]) -> VALUE" => {
                     new_element.extend(quote! { #field_id: |x| VALUE::ERROR});
                 }
                 _ => {
@@ -78,6 +111,10 @@ pub fn set_value_derive(input: TokenStream) -> TokenStream {
     }
 
     quote! {
+        impl #id {
+            #consts
+        }
+
         impl New for #id {
             fn new() -> Self {
                 Self {
@@ -110,6 +147,42 @@ pub fn set_value_derive(input: TokenStream) -> TokenStream {
                 #valid
                 false
             }
+
+            fn ports(&self) -> Vec<(String, HandshakeValue)> {
+                let mut ports = vec![];
+                #ports
+                ports
+            }
+
+            fn port(&self, name: &str) -> Option<Port> {
+                match name {
+                    #port_of
+                    _ => (),
+                }
+                #port_of_vector
+                None
+            }
+
+            fn port_value(&self, port: Port) -> HandshakeValue {
+                match port.field() {
+                    #port_value
+                    _ => panic!("{:?}", port),
+                }
+            }
+
+            fn set_port_value(&mut self, port: Port, val: HandshakeValue) {
+                match port.field() {
+                    #set_port_value
+                    _ => panic!("{:?}", port),
+                }
+            }
+
+            fn port_name(&self, port: Port) -> String {
+                match port.field() {
+                    #port_name
+                    _ => panic!("{:?}", port),
+                }
+            }
         }
     }
     .into()
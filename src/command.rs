@@ -0,0 +1,944 @@
+use super::execute_macro;
+use super::script;
+use hestia::common::*;
+use hestia::hec;
+use hestia::perfetto;
+use hestia::profile;
+use hestia::rtl;
+use hestia::software;
+use hestia::tor;
+use hestia::vcd;
+use std::process;
+
+const ALL: &[&str] = &["software", "tor", "hec"];
+const SOFTWARE: &[&str] = &["software"];
+const TOR: &[&str] = &["tor"];
+const SOFTWARE_TOR: &[&str] = &["software", "tor"];
+const HEC: &[&str] = &["hec"];
+const TOR_HEC: &[&str] = &["tor", "hec"];
+
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub args: &'static str,
+    pub min_args: usize,
+    pub levels: &'static [&'static str],
+    pub help: &'static str,
+    handler: fn(&[&str]),
+}
+
+impl Command {
+    pub fn usage(&self) -> String {
+        if self.args.is_empty() {
+            self.name.to_string()
+        } else {
+            format!("{} {}", self.name, self.args)
+        }
+    }
+
+    pub fn execute(&self, list: &[&str]) {
+        if list.len() <= self.min_args {
+            println!("!!Usage: {}", self.usage());
+            fail(Failure::Command);
+            return;
+        }
+        (self.handler)(list)
+    }
+}
+
+/// The optional count argument of stepping commands, 1 if it is missing.
+fn count_arg(list: &[&str]) -> Option<usize> {
+    match list.get(1).map_or(Ok(1), |count| count.parse::<usize>()) {
+        Ok(count) => Some(count),
+        Err(_) => {
+            println!("!!Invalid count {}", list[1]);
+            fail(Failure::Command);
+            None
+        }
+    }
+}
+
+fn step_count(list: &[&str]) {
+    if let Some(count) = count_arg(list) {
+        step(count)
+    }
+}
+
+fn report<E: std::fmt::Display>(result: Result<(), E>, message: String) {
+    if let Err(err) = result {
+        println!("!!{}: {}", message, err);
+        fail(Failure::Command);
+    }
+}
+
+fn shell(command: &str) {
+    match process::Command::new("sh").arg("-c").arg(command).status() {
+        Ok(status) => {
+            let code = status.code().unwrap_or(-1);
+            set_script_variable("status", &code.to_string());
+            if !status.success() {
+                println!("!!Exit status {}", code);
+                fail(Failure::Command);
+            }
+        }
+        Err(err) => {
+            println!("!!Failed to run shell: {}", err);
+            fail(Failure::Command);
+        }
+    }
+}
+
+pub static REGISTRY: &[Command] = &[
+    Command {
+        name: "!",
+        aliases: &[],
+        args: "<shell command>",
+        min_args: 1,
+        levels: ALL,
+        help: "Run a shell command and store its exit status in $status.",
+        handler: |list| shell(&list[1..].join(" ")),
+    },
+    Command {
+        name: "alias",
+        aliases: &["define"],
+        args: "<name> <command>[; <command>...]",
+        min_args: 2,
+        levels: ALL,
+        help: "Define a macro running the given commands. A single-command alias forwards its arguments.",
+        handler: |list| {
+            if lookup(list[1]).is_some() {
+                println!("!!{} is a built-in command", list[1]);
+                fail(Failure::Command);
+                return;
+            }
+            let body = list[2..]
+                .join(" ")
+                .split(";")
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            define_macro(list[1], body);
+        },
+    },
+    Command {
+        name: "break_return",
+        aliases: &[],
+        args: "<function> when <comparison>",
+        min_args: 3,
+        levels: SOFTWARE_TOR,
+        help: "Stop continue when the function returns a value satisfying e.g. `when < 0`. Removed with unset_breakpoint.",
+        handler: |list| set_return_break(&list[1..]),
+    },
+    Command {
+        name: "breakpoint",
+        aliases: &["b"],
+        args: "<name> [if <condition>]",
+        min_args: 1,
+        levels: ALL,
+        help: "Stop continue when the named operation, function or state is reached, optionally only while a comparison such as `%i == 7` holds. The name may be a glob or /regex/.",
+        handler: |list| set_breakpoint(&list[1..]),
+    },
+    Command {
+        name: "call",
+        aliases: &[],
+        args: "<function> [<arg>...]",
+        min_args: 1,
+        levels: ALL,
+        help: "Start executing a function with the given arguments.",
+        handler: |list| {
+            if let Err(err) = call_function(list[1], &list[2..]) {
+                println!("{}", err);
+                fail(Failure::Command);
+            }
+        },
+    },
+    Command {
+        name: "checkpoint",
+        aliases: &[],
+        args: "[<name>]",
+        min_args: 0,
+        levels: ALL,
+        help: "Save the state of every loaded level under a name, or list the checkpoints.",
+        handler: |list| checkpoint(list.get(1).copied()),
+    },
+    Command {
+        name: "close_session",
+        aliases: &[],
+        args: "<name>",
+        min_args: 1,
+        levels: ALL,
+        help: "Drop a simulation session and the designs loaded in it.",
+        handler: |list| close_session(list[1]),
+    },
+    Command {
+        name: "continue",
+        aliases: &["c"],
+        args: "",
+        min_args: 0,
+        levels: ALL,
+        help: "Run until a breakpoint is hit or the design finishes.",
+        handler: |_| execute_continue(),
+    },
+    Command {
+        name: "cosim",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        levels: &["tor", "hec"],
+        help: "Run ToR and HEC together, comparing values at the equal-points from load_equal.",
+        handler: |_| cosim(),
+    },
+    Command {
+        name: "display",
+        aliases: &[],
+        args: "on|off",
+        min_args: 1,
+        levels: ALL,
+        help: "After every step, print the variables and ports that changed.",
+        handler: |list| set_display(list[1]),
+    },
+    Command {
+        name: "down",
+        aliases: &[],
+        args: "[<count>]",
+        min_args: 0,
+        levels: SOFTWARE,
+        help: "Select an inner frame of the control stack.",
+        handler: |list| {
+            if let Some(count) = count_arg(list) {
+                software::move_frame(-(count as isize))
+            }
+        },
+    },
+    Command {
+        name: "dump_memory_hex",
+        aliases: &[],
+        args: "<memory> <file>",
+        min_args: 2,
+        levels: ALL,
+        help: "Write a memory of the current level as hex words for $readmemh, which load_memory_file also reads.",
+        handler: |list| dump_memory_hex(list[1], list[2]),
+    },
+    Command {
+        name: "dump_perfetto",
+        aliases: &[],
+        args: "<file>|off",
+        min_args: 1,
+        levels: TOR_HEC,
+        help: "Record when each instance, pipeline stage and handshake unit is busy as a Chrome trace for the Perfetto UI.",
+        handler: |list| perfetto::dump_perfetto(list[1]),
+    },
+    Command {
+        name: "dump_vcd",
+        aliases: &[],
+        args: "<file>|off",
+        min_args: 1,
+        levels: TOR_HEC,
+        help: "Record the ToR or HEC signals of each cycle as a VCD waveform.",
+        handler: |list| vcd::dump_vcd(list[1]),
+    },
+    Command {
+        name: "exit",
+        aliases: &["quit", "q"],
+        args: "",
+        min_args: 0,
+        levels: ALL,
+        help: "Leave the debugger.",
+        handler: |_| process::exit(exit_code()),
+    },
+    Command {
+        name: "export_dot",
+        aliases: &[],
+        args: "<function> <file> [counts]",
+        min_args: 2,
+        levels: TOR_HEC,
+        help: "Write the time graph (tor) or state transition graph (hec) of a function as Graphviz DOT, with `counts` annotating the cycles the last run spent in each node or state.",
+        handler: |list| export_dot(list[1], list[2], list.get(3) == Some(&"counts")),
+    },
+    Command {
+        name: "export_mem",
+        aliases: &[],
+        args: "<memory> <file>",
+        min_args: 2,
+        levels: ALL,
+        help: "Write a memory of the current level to a CSV file of index,value rows.",
+        handler: |list| export_memory(list[1], list[2]),
+    },
+    Command {
+        name: "finish",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        levels: SOFTWARE_TOR,
+        help: "Run until the current function (software) or instance (tor) returns.",
+        handler: |_| step_out(),
+    },
+    Command {
+        name: "force",
+        aliases: &[],
+        args: "<memory> <index> <value>",
+        min_args: 3,
+        levels: ALL,
+        help: "Overwrite one memory element. Can be undone.",
+        handler: |list| force(list[1], list[2], list[3]),
+    },
+    Command {
+        name: "frame",
+        aliases: &["f"],
+        args: "[<n>]",
+        min_args: 0,
+        levels: SOFTWARE,
+        help: "Select the n-th frame of the control stack, 0 being the innermost, and scope show_var to its function or loop until the next step.",
+        handler: |list| match list.get(1).map(|n| n.parse::<usize>()) {
+            None => software::select_frame(None),
+            Some(Ok(frame)) => software::select_frame(Some(frame)),
+            Some(Err(_)) => {
+                println!("!!Invalid frame {}", list[1]);
+                fail(Failure::Command);
+            }
+        },
+    },
+    Command {
+        name: "goto_cycle",
+        aliases: &[],
+        args: "<cycle>",
+        min_args: 1,
+        levels: ALL,
+        help: "Go to a cycle (step at the software level) by re-executing from the last automatic checkpoint before it.",
+        handler: |list| {
+            if let Some(cycle) = count_arg(list) {
+                goto_cycle(cycle)
+            }
+        },
+    },
+    Command {
+        name: "help",
+        aliases: &[],
+        args: "[<command>]",
+        min_args: 0,
+        levels: ALL,
+        help: "List the commands, or describe one of them.",
+        handler: |list| show_help(&list[1..]),
+    },
+    Command {
+        name: "info",
+        aliases: &[],
+        args: "locals",
+        min_args: 1,
+        levels: SOFTWARE,
+        help: "Show the variables defined so far by the function or loop body of the selected frame.",
+        handler: |list| match list[1] {
+            "locals" => software::show_locals(),
+            _ => {
+                println!("!!Unknown info {}", list[1]);
+                fail(Failure::Command);
+            }
+        },
+    },
+    Command {
+        name: "invalid",
+        aliases: &[],
+        args: "<port>",
+        min_args: 1,
+        levels: HEC,
+        help: "Drop the valid signal of a top-level handshake port.",
+        handler: |list| hec::invalid(list[1]),
+    },
+    Command {
+        name: "load",
+        aliases: &["l"],
+        args: "<file> [<file>...]",
+        min_args: 1,
+        levels: ALL,
+        help: "Load software, ToR or HEC json, json5 or yaml files, or ToR or HEC mlir files, and switch to the level of the last. Further HEC files are merged into the first.",
+        handler: |list| load_files(&list[1..]),
+    },
+    Command {
+        name: "load_equal",
+        aliases: &[],
+        args: "<file>",
+        min_args: 1,
+        levels: &["tor", "hec"],
+        help: "Load the equal-point mapping used by cosim.",
+        handler: |list| report(parse_equal(list[1]), format!("Failed to load file {}", list[1])),
+    },
+    Command {
+        name: "load_memory",
+        aliases: &[],
+        args: "<memory> <value>...",
+        min_args: 2,
+        levels: ALL,
+        help: "Fill a memory from the given values. Can be undone.",
+        handler: |list| load_memory(list[1], &list[2..]),
+    },
+    Command {
+        name: "load_memory_file",
+        aliases: &[],
+        args: "<memory> <file>",
+        min_args: 2,
+        levels: ALL,
+        help: "Fill a memory from whitespace separated values in a file, or from hex words as $readmemh reads them in a .hex or .vmh file or one with comments or @ addresses.",
+        handler: |list| {
+            report(
+                load_memory_file(list[1], list[2]),
+                format!("Failed to load file {}", list[2]),
+            )
+        },
+    },
+    Command {
+        name: "load_session",
+        aliases: &[],
+        args: "<file>",
+        min_args: 1,
+        levels: ALL,
+        help: "Replace the breakpoints, watchpoints and tracepoints with those of a session file, read again after every load.",
+        handler: |list| report(load_session(list[1]), format!("Failed to load session {}", list[1])),
+    },
+    Command {
+        name: "load_state",
+        aliases: &[],
+        args: "<file>",
+        min_args: 1,
+        levels: ALL,
+        help: "Continue from the memories, streams, registers and instances of every level saved by save_state, with the same IR files loaded.",
+        handler: |list| report(load_state_file(list[1]), format!("Failed to load state {}", list[1])),
+    },
+    Command {
+        name: "mem_usage",
+        aliases: &[],
+        args: "[<pattern>...]",
+        min_args: 0,
+        levels: ALL,
+        help: "Show the size of all memories, or those matching a name, glob or /regex/, and the bytes allocated for the words written so far.",
+        handler: |list| show_memory_usage(&list[1..]),
+    },
+    Command {
+        name: "merge",
+        aliases: &[],
+        args: "<file>",
+        min_args: 1,
+        levels: HEC,
+        help: "Add the modules of another HEC json file to the loaded HEC design.",
+        handler: |list| report(merge_file(list[1]), format!("Failed to merge file {}", list[1])),
+    },
+    Command {
+        name: "next",
+        aliases: &["n"],
+        args: "",
+        min_args: 0,
+        levels: SOFTWARE_TOR,
+        help: "Step once, running a function call to its return as one step.",
+        handler: |_| step_over(),
+    },
+    Command {
+        name: "profile",
+        aliases: &[],
+        args: "on|off|report [count]",
+        min_args: 1,
+        levels: ALL,
+        help: "Time each op, unit and module as the simulation runs, and report those that take longest.",
+        handler: |list| profile::profile(&list[1..]),
+    },
+    Command {
+        name: "ready",
+        aliases: &[],
+        args: "<port>",
+        min_args: 1,
+        levels: HEC,
+        help: "Raise the ready signal of a top-level handshake port.",
+        handler: |list| hec::ready(list[1]),
+    },
+    Command {
+        name: "log",
+        aliases: &[],
+        args: "<file>|off",
+        min_args: 1,
+        levels: ALL,
+        help: "Copy all output to a file until log off.",
+        handler: |list| set_log(list[1]),
+    },
+    Command {
+        name: "record",
+        aliases: &[],
+        args: "<file>|off",
+        min_args: 1,
+        levels: ALL,
+        help: "Record loads, calls, pokes, edits and the cycles run to a script for replay.",
+        handler: |list| record(list[1]),
+    },
+    Command {
+        name: "redo",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        levels: ALL,
+        help: "Reapply the last undone edit.",
+        handler: |_| redo(),
+    },
+    Command {
+        name: "reload",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        levels: ALL,
+        help: "Re-read the loaded IR and equal files, keeping breakpoints and watchpoints.",
+        handler: |_| reload(),
+    },
+    Command {
+        name: "replay",
+        aliases: &[],
+        args: "<file>",
+        min_args: 1,
+        levels: ALL,
+        help: "Silently re-execute a recording and report the cycle it reached.",
+        handler: |list| script::replay(list[1]),
+    },
+    Command {
+        name: "repeat",
+        aliases: &[],
+        args: "<count> { <command>; ... }",
+        min_args: 2,
+        levels: ALL,
+        help: "Run a block of commands several times. In scripts the block may span lines.",
+        handler: |list| script::execute_repeat(&list.join(" ")),
+    },
+    Command {
+        name: "restore",
+        aliases: &[],
+        args: "<name>",
+        min_args: 1,
+        levels: ALL,
+        help: "Rewind every loaded level to a checkpoint.",
+        handler: |list| restore(list[1]),
+    },
+    Command {
+        name: "reverse_step",
+        aliases: &["rs"],
+        args: "[<count>]",
+        min_args: 0,
+        levels: ALL,
+        help: "Go back by count steps (software) or cycles (tor, hec, cosim), default 1.",
+        handler: |list| {
+            if let Some(count) = count_arg(list) {
+                reverse_step(count)
+            }
+        },
+    },
+    Command {
+        name: "rtl_connect",
+        aliases: &[],
+        args: "<address>|off",
+        min_args: 1,
+        levels: HEC,
+        help: "Run an RTL simulation listening on a TCP address or unix:<path> in lockstep with the dynamic HEC design and report every cycle its ports or memories differ.",
+        handler: |list| rtl::rtl_connect(list[1]),
+    },
+    Command {
+        name: "save_macros",
+        aliases: &[],
+        args: "<file>",
+        min_args: 1,
+        levels: ALL,
+        help: "Write the macros as define commands to a script file.",
+        handler: |list| report(save_macros(list[1]), format!("Failed to write file {}", list[1])),
+    },
+    Command {
+        name: "save_session",
+        aliases: &[],
+        args: "<file>",
+        min_args: 1,
+        levels: ALL,
+        help: "Write the breakpoints with their hit counts, watchpoints and tracepoints to a session file, read again after every load.",
+        handler: |list| report(save_session(list[1]), format!("Failed to write file {}", list[1])),
+    },
+    Command {
+        name: "save_state",
+        aliases: &[],
+        args: "<file>",
+        min_args: 1,
+        levels: ALL,
+        help: "Write the memories, streams, variables, registers and instances of every loaded level to a JSON file.",
+        handler: |list| report(save_state_file(list[1]), format!("Failed to write file {}", list[1])),
+    },
+    Command {
+        name: "session",
+        aliases: &[],
+        args: "[<name>]",
+        min_args: 0,
+        levels: ALL,
+        help: "Switch to a simulation session, creating it, or list the sessions. `@<name> <command>` runs one command in a session, e.g. `@ref step 10`.",
+        handler: |list| session(list.get(1).copied()),
+    },
+    Command {
+        name: "set",
+        aliases: &[],
+        args: "[<option>|<variable> <value>]",
+        min_args: 0,
+        levels: ALL,
+        help: "Set a debugger option or a script variable, or list both, e.g. `set max_cycles 1000` or `set N 4`, read as `$N`. A name a letter or two off an option is taken for a misspelling of it; `set $name <value>` always sets a variable. Options: output text|json, verbosity quiet|normal|trace, stall_print on|off, max_cycles <count>, float_tolerance <tolerance>, cosim_skew <count>, cosim_threads on|off, fast_forward on|off, parallel_instances on|off|check, software_backend interpreted|compiled, x_state on|off, overflow off|warn|halt, div_zero trap|error|ones, radix dec|hex|bin, strict on|off, log <filter>.",
+        handler: |list| {
+            if list.len() < 3 {
+                show_options();
+                show_script_variables();
+                return;
+            }
+            // `$` marks a script variable even if the name is that of an option
+            let (name, marked) = match list[1].strip_prefix('$') {
+                Some(name) => (name, true),
+                None => (list[1], false),
+            };
+            if !marked && set_option(name, list[2]) {
+                return;
+            }
+            let valid = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+            if let Some(option) = misspelled_option(name).filter(|_| !marked) {
+                print!("!!Unknown option {}, did you mean {}?", name, option);
+                if valid {
+                    print!(" Set a script variable of that name with `set ${} <value>`", name);
+                }
+                println!();
+                fail(Failure::Command);
+            } else if !valid {
+                println!("!!Invalid script variable name {}", list[1]);
+                fail(Failure::Command);
+            } else {
+                set_script_variable(name, &list[2..].join(" "));
+            }
+        },
+    },
+    Command {
+        name: "set_var",
+        aliases: &[],
+        args: "<variable> <value>",
+        min_args: 2,
+        levels: ALL,
+        help: "Overwrite a variable at the current level. Can be undone.",
+        handler: |list| set_var(list[1], list[2]),
+    },
+    Command {
+        name: "show",
+        aliases: &[],
+        args: "[<pattern>...]",
+        min_args: 0,
+        levels: SOFTWARE,
+        help: "List the loaded functions.",
+        handler: |list| software::show_function(&list[1..]),
+    },
+    Command {
+        name: "show_active",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        levels: &["tor", "hec"],
+        help: "Show the active instances and their current states.",
+        handler: |_| show_active(),
+    },
+    Command {
+        name: "show_breakpoint",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        levels: ALL,
+        help: "List the breakpoints.",
+        handler: |_| show_breakpoint(),
+    },
+    Command {
+        name: "show_macros",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        levels: ALL,
+        help: "List the macros and their bodies.",
+        handler: |_| show_macros(),
+    },
+    Command {
+        name: "show_mem",
+        aliases: &["mem"],
+        args: "[dec|hex|bin] [<pattern>...]",
+        min_args: 0,
+        levels: ALL,
+        help: "Show all memories, or those matching a name, glob or /regex/, in the radix given or that of the radix option.",
+        handler: |list| show_memory(&list[1..]),
+    },
+    Command {
+        name: "show_op",
+        aliases: &["op"],
+        args: "",
+        min_args: 0,
+        levels: SOFTWARE,
+        help: "Show the operation about to execute.",
+        handler: |_| software::show_operation(),
+    },
+    Command {
+        name: "show_stack",
+        aliases: &["stack"],
+        args: "",
+        min_args: 0,
+        levels: SOFTWARE,
+        help: "Show the control stack of functions, loops and branches.",
+        handler: |_| software::show_stack(),
+    },
+    Command {
+        name: "show_stall",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        levels: TOR,
+        help: "Show the stalled instances.",
+        handler: |_| tor::show_stall(),
+    },
+    Command {
+        name: "show_trace",
+        aliases: &[],
+        args: "[clear]",
+        min_args: 0,
+        levels: ALL,
+        help: "Print the tracepoints and the entries they logged, or clear the entries.",
+        handler: |list| show_trace(list.get(1) == Some(&"clear")),
+    },
+    Command {
+        name: "show_var",
+        aliases: &["var"],
+        args: "[dec|hex|bin] [<pattern>...]",
+        min_args: 0,
+        levels: ALL,
+        help: "Show all variables, or those matching a name, glob or /regex/, in the radix given or that of the radix option.",
+        handler: |list| show_variable(&list[1..]),
+    },
+    Command {
+        name: "show_watchpoint",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        levels: ALL,
+        help: "List the watchpoints.",
+        handler: |_| show_watchpoint(),
+    },
+    Command {
+        name: "step",
+        aliases: &["s"],
+        args: "[<count>]",
+        min_args: 0,
+        levels: ALL,
+        help: "Advance by count steps (software) or cycles (tor, hec), default 1.",
+        handler: step_count,
+    },
+    Command {
+        name: "stepi",
+        aliases: &["si"],
+        args: "[<count>]",
+        min_args: 0,
+        levels: ALL,
+        help: "Same as step, which descends into called functions.",
+        handler: step_count,
+    },
+    Command {
+        name: "source",
+        aliases: &[],
+        args: "<file> [<arg>...]",
+        min_args: 1,
+        levels: ALL,
+        help: "Execute the commands in a script file, binding $1.. to the arguments.",
+        handler: |list| script::source_file(list[1], &list[2..]),
+    },
+    Command {
+        name: "switch",
+        aliases: &[],
+        args: "software|tor|hec|cosim",
+        min_args: 1,
+        levels: ALL,
+        help: "Change the level the commands act on.",
+        handler: |list| switch(list[1]),
+    },
+    Command {
+        name: "trace",
+        aliases: &[],
+        args: "<name> <expr>",
+        min_args: 2,
+        levels: ALL,
+        help: "Log a variable or comparison with the cycle whenever the named operation or state executes, without stopping. See show_trace.",
+        handler: |list| set_tracepoint(&list[1..]),
+    },
+    Command {
+        name: "trace_file",
+        aliases: &[],
+        args: "<file>|off",
+        min_args: 1,
+        levels: ALL,
+        help: "Also append trace entries to a file until trace_file off.",
+        handler: |list| set_trace_file(list[1]),
+    },
+    Command {
+        name: "undefine",
+        aliases: &[],
+        args: "<name>",
+        min_args: 1,
+        levels: ALL,
+        help: "Remove a macro.",
+        handler: |list| undefine_macro(list[1]),
+    },
+    Command {
+        name: "undo",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        levels: ALL,
+        help: "Revert the last load_memory, force or set_var.",
+        handler: |_| undo(),
+    },
+    Command {
+        name: "until",
+        aliases: &[],
+        args: "<name>",
+        min_args: 1,
+        levels: ALL,
+        help: "Continue until the named operation or state is about to execute, without setting a breakpoint.",
+        handler: |list| until(list[1]),
+    },
+    Command {
+        name: "until_iter",
+        aliases: &[],
+        args: "<for_op> <k>",
+        min_args: 2,
+        levels: SOFTWARE_TOR,
+        help: "Continue until the induction variable of the for loop, named by a result or its induction variable, reaches k.",
+        handler: |list| until_iter(list[1], list[2]),
+    },
+    Command {
+        name: "unset_breakpoint",
+        aliases: &[],
+        args: "<name>",
+        min_args: 1,
+        levels: ALL,
+        help: "Remove a breakpoint.",
+        handler: |list| unset_breakpoint(list[1]),
+    },
+    Command {
+        name: "untrace",
+        aliases: &[],
+        args: "<name>",
+        min_args: 1,
+        levels: ALL,
+        help: "Remove the tracepoints of an operation or state.",
+        handler: |list| unset_tracepoint(list[1]),
+    },
+    Command {
+        name: "unset_watchpoint",
+        aliases: &[],
+        args: "<name>",
+        min_args: 1,
+        levels: ALL,
+        help: "Remove a watchpoint.",
+        handler: |list| unset_watchpoint(list[1]),
+    },
+    Command {
+        name: "up",
+        aliases: &[],
+        args: "[<count>]",
+        min_args: 0,
+        levels: SOFTWARE,
+        help: "Select an outer frame of the control stack.",
+        handler: |list| {
+            if let Some(count) = count_arg(list) {
+                software::move_frame(count as isize)
+            }
+        },
+    },
+    Command {
+        name: "valid",
+        aliases: &[],
+        args: "<port> <value>",
+        min_args: 2,
+        levels: HEC,
+        help: "Drive a top-level handshake port with a valid value.",
+        handler: |list| hec::valid(list[1], list[2]),
+    },
+    Command {
+        name: "watch",
+        aliases: &["w"],
+        args: "<variable> [change | when <comparison>]",
+        min_args: 1,
+        levels: ALL,
+        help: "Print the variable after every step, or stop when its value changes, optionally to one satisfying e.g. `when > 100`. The variable may be a sum, min or max over a memory slice such as `sum(A[0..16])`.",
+        handler: |list| set_watchpoint(&list[1..]),
+    },
+    Command {
+        name: "watch_csv",
+        aliases: &[],
+        args: "<file> [variable...]|off",
+        min_args: 1,
+        levels: ALL,
+        help: "Log the variables, or the watchpoints set so far, to a CSV file with a row per step or cycle.",
+        handler: |list| set_watch_csv(&list[1..]),
+    },
+    Command {
+        name: "watch_stream",
+        aliases: &[],
+        args: "<stream> [log|stop]",
+        min_args: 1,
+        levels: ALL,
+        help: "Report every token pushed to or popped from the stream, and stop unless `log` is given.",
+        handler: |list| set_stream_watch(&list[1..]),
+    },
+    Command {
+        name: "watch_table",
+        aliases: &[],
+        args: "on|off",
+        min_args: 1,
+        levels: ALL,
+        help: "Redraw watchpoints as a table in place instead of printing them every step.",
+        handler: |list| set_watch_table(list[1]),
+    },
+];
+
+pub fn lookup(name: &str) -> Option<&'static Command> {
+    REGISTRY
+        .iter()
+        .find(|command| command.name == name || command.aliases.contains(&name))
+}
+
+pub fn command_names() -> Vec<&'static str> {
+    REGISTRY.iter().map(|command| command.name).collect()
+}
+
+/// Run a command line, falling back to macros for unknown names.
+pub fn dispatch(list: &[&str]) {
+    match lookup(list[0]) {
+        Some(command) => command.execute(list),
+        None => match get_macro(list[0]) {
+            Some(body) => execute_macro(list[0], body, &list[1..]),
+            None => {
+                println!("!!Undefined instruction: {}", list.join(" "));
+                fail(Failure::Command);
+            }
+        },
+    }
+}
+
+fn show_help(names: &[&str]) {
+    if names.is_empty() {
+        for command in REGISTRY {
+            println!("{:<18} {}", command.name, command.help);
+        }
+        return;
+    }
+    for name in names {
+        match lookup(name) {
+            Some(command) => {
+                println!("Usage: {}", command.usage());
+                if !command.aliases.is_empty() {
+                    println!("Aliases: {}", command.aliases.join(", "));
+                }
+                println!("Levels: {}", command.levels.join(", "));
+                println!("{}", command.help);
+            }
+            None => match get_macro(name) {
+                Some(body) => println!("{} is a macro: {}", name, body.join("; ")),
+                None => {
+                    println!("!!Undefined instruction: {}", name);
+                    fail(Failure::Command);
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use super::command::command_names;
+use hestia::common::*;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper, Result};
+
+pub struct HestiaHelper {
+    filename: FilenameCompleter,
+}
+
+impl HestiaHelper {
+    pub fn new() -> Self {
+        HestiaHelper {
+            filename: FilenameCompleter::new(),
+        }
+    }
+}
+
+fn candidates(command: &str, position: usize) -> Option<Vec<String>> {
+    let names = match (command, position) {
+        ("call" | "break_return" | "export_dot", 1) => function_names(),
+        ("export_dot", 3) => vec!["counts".to_string()],
+        ("mem" | "show_mem" | "mem_usage", _) => memory_names(),
+        ("load_memory" | "force" | "dump_memory_hex", 1) => memory_names(),
+        ("load_memory_file" | "watch_stream" | "export_mem", 1) => memory_names(),
+        ("watch_stream", 2) => vec!["log".to_string(), "stop".to_string()],
+        ("b" | "breakpoint" | "until" | "w" | "watch" | "var" | "show_var", _)
+        | ("until_iter", 1) => signal_names(),
+        ("watch_csv", position) if position > 1 => signal_names(),
+        ("unset_breakpoint", 1) => breakpoint_names(),
+        ("unset_watchpoint", 1) => watchpoint_names(),
+        ("untrace", 1) => tracepoint_names(),
+        ("trace", 1) => signal_names(),
+        ("restore", 1) => checkpoint_names(),
+        ("session" | "close_session", 1) => session_names(),
+        ("info", 1) => vec!["locals".to_string()],
+        ("help", _) => command_names().iter().map(|s| s.to_string()).collect(),
+        ("set", 1) => option_names(),
+        ("set_var", 1) => signal_names(),
+        ("switch", 1) => vec!["software", "tor", "hec", "cosim"]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        ("undefine", 1) => macro_names(),
+        ("profile", 1) => vec!["on", "off", "report"]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        ("watch_table" | "display", 1) => vec!["on".to_string(), "off".to_string()],
+        _ => return None,
+    };
+    Some(names)
+}
+
+fn is_file_argument(command: &str, position: usize) -> bool {
+    match (command, position) {
+        ("l" | "load", _) => true,
+        ("load_equal" | "merge", 1) => true,
+        ("load_memory_file" | "export_mem" | "dump_memory_hex", 2) => true,
+        ("watch_csv", 1) | ("export_dot", 2) => true,
+        ("save_macros" | "source" | "log" | "trace_file" | "dump_vcd", 1) => true,
+        ("dump_perfetto", 1) => true,
+        ("save_session" | "load_session" | "save_state" | "load_state", 1) => true,
+        _ => false,
+    }
+}
+
+impl Completer for HestiaHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(' ').map_or(0, |idx| idx + 1);
+        let prefix = &line[start..pos];
+        let mut words = line[..start].split_whitespace().collect::<Vec<&str>>();
+        // `@ref step 10` completes like `step 10`
+        if words.first().is_some_and(|word| word.starts_with('@')) {
+            words.remove(0);
+        }
+
+        let names = match words.first() {
+            None if prefix.starts_with('@') => session_names()
+                .into_iter()
+                .map(|name| format!("@{}", name))
+                .collect(),
+            None => {
+                let mut names: Vec<String> =
+                    command_names().iter().map(|s| s.to_string()).collect();
+                names.extend(macro_names());
+                names
+            }
+            Some(command) => {
+                if is_file_argument(command, words.len()) {
+                    return self.filename.complete(line, pos, ctx);
+                }
+                match candidates(command, words.len()) {
+                    Some(names) => names,
+                    None => return Ok((start, vec![])),
+                }
+            }
+        };
+
+        let pairs = names
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Pair {
+                display: name.clone(),
+                replacement: name,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for HestiaHelper {
+    type Hint = String;
+}
+
+impl Highlighter for HestiaHelper {}
+
+impl Validator for HestiaHelper {}
+
+impl Helper for HestiaHelper {}
@@ -0,0 +1,249 @@
+use super::command::lookup;
+use super::server::reply;
+use hestia::common::*;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+/// The largest request body accepted, which is at most a command line.
+const MAX_BODY: usize = 1 << 20;
+
+/// How long a client may take to send its request, so that a stalled
+/// connection cannot hold the single-threaded server.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The commands a request may run, which inspect and step the simulation
+/// but never touch files, the shell, macros or other sessions.
+const ALLOWED: &[&str] = &[
+    "break_return",
+    "breakpoint",
+    "call",
+    "continue",
+    "down",
+    "finish",
+    "frame",
+    "help",
+    "info",
+    "mem_usage",
+    "next",
+    "show",
+    "show_active",
+    "show_breakpoint",
+    "show_mem",
+    "show_op",
+    "show_stack",
+    "show_stall",
+    "show_trace",
+    "show_var",
+    "show_watchpoint",
+    "step",
+    "stepi",
+    "until",
+    "until_iter",
+    "unset_breakpoint",
+    "unset_watchpoint",
+    "up",
+    "watch",
+];
+
+/// The show_* command that answers `GET /<resource>`.
+const RESOURCES: &[(&str, &str)] = &[
+    ("active", "show_active"),
+    ("breakpoints", "show_breakpoint"),
+    ("memory", "show_mem"),
+    ("stack", "show_stack"),
+    ("stall", "show_stall"),
+    ("trace", "show_trace"),
+    ("variable", "show_var"),
+    ("watchpoints", "show_watchpoint"),
+];
+
+struct Request {
+    method: String,
+    path: String,
+    body: String,
+}
+
+/// Decode the `%XX` escapes and `+` of a URL path segment.
+fn decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        let escape = segment
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (escape, bytes[index]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (None, b'+') => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            (None, byte) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+    reader.get_ref().set_read_timeout(Some(READ_TIMEOUT)).ok()?;
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut words = line.split_whitespace();
+    let method = words.next()?.to_string();
+    let target = words.next()?;
+    let path = target
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return None;
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    let body = String::from_utf8_lossy(&body).into_owned();
+    Some(Request { method, path, body })
+}
+
+/// The command line a request runs, or the status and reason it is refused.
+fn command(request: &Request) -> Result<String, (u16, String)> {
+    let segments: Vec<String> = request
+        .path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(decode)
+        .collect();
+    let Some((name, args)) = segments.split_first() else {
+        return Err((404, "no resource given".to_string()));
+    };
+    let line = match request.method.as_str() {
+        "GET" => match RESOURCES.iter().find(|(resource, _)| resource == name) {
+            Some((_, command)) => std::iter::once(command.to_string())
+                .chain(args.iter().cloned())
+                .collect(),
+            None => return Err((404, format!("unknown resource {}", name))),
+        },
+        "POST" if name == "command" && args.is_empty() => vec![request.body.trim().to_string()],
+        "POST" => segments.clone(),
+        method => return Err((405, format!("method {} not allowed", method))),
+    };
+    let line = line.join(" ");
+    let name = line.split(' ').next().unwrap_or_default();
+    if matches!(name, "quit" | "exit" | "q") {
+        return Ok(line);
+    }
+    match lookup(name) {
+        Some(command) if ALLOWED.contains(&command.name) => Ok(line),
+        _ => Err((403, format!("command {} not allowed over HTTP", name))),
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: u16, body: &Value) -> std::io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Error",
+    };
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Answer one request, returning false once the client asks to exit.
+fn serve(mut stream: TcpStream) -> std::io::Result<bool> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let Some(request) = read_request(&mut reader) else {
+        respond(
+            &mut stream,
+            400,
+            &json!({"ok": false, "errors": ["malformed request"]}),
+        )?;
+        return Ok(true);
+    };
+    if request.method == "GET" && matches!(request.path.as_str(), "/" | "/status") {
+        let status = json!({
+            "ok": true,
+            "level": level_name(debug_state()),
+            "cycle": current_cycle(),
+        });
+        respond(&mut stream, 200, &status)?;
+        return Ok(true);
+    }
+    let line = match command(&request) {
+        Ok(line) => line,
+        Err((status, error)) => {
+            respond(
+                &mut stream,
+                status,
+                &json!({"ok": false, "errors": [error]}),
+            )?;
+            return Ok(true);
+        }
+    };
+    if matches!(line.as_str(), "quit" | "exit" | "q") {
+        respond(&mut stream, 200, &json!({"ok": true}))?;
+        return Ok(false);
+    }
+    let answer = reply(&line);
+    let status = if answer["ok"] == true { 200 } else { 400 };
+    respond(&mut stream, status, &answer)?;
+    Ok(true)
+}
+
+/// Serve the simulation over HTTP on a TCP address such as
+/// `127.0.0.1:8080`, one request at a time, until `POST /exit`. `GET
+/// /<resource>` runs a show_* command and `POST /<command>/<arg>...` or
+/// `POST /command` with the line as the body runs one of the inspecting
+/// and stepping commands in `ALLOWED`, each answered with the JSON of the
+/// line server.
+pub fn listen(address: &str) {
+    set_option("color", "off");
+    set_option("output", "json");
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("!!Failed to listen on {}: {}", address, err);
+            return fail(Failure::Command);
+        }
+    };
+    println!("Serving HTTP on {}", address);
+    for stream in listener.incoming().flatten() {
+        if let Ok(false) = serve(stream) {
+            break;
+        }
+    }
+}
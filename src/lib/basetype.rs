@@ -1,32 +1,466 @@
 use super::common::*;
+use super::error::{
+    describe, division_by_zero, error_operand, fault, overflow, str_field, strings_field,
+    ErrorCode, HestiaError,
+};
+use super::intern::{Name, Slots};
+use half::{bf16, f16};
+use serde_json::{json, Value};
+use std::borrow::Borrow;
+use std::f64::consts::FRAC_2_SQRT_PI;
+use std::iter::zip;
+use std::ops::{Add, Mul, Neg, Range, Sub};
+use std::sync::Arc;
+
+/// Words per page of a [`PagedStore`].
+const PAGE: usize = 4096;
+
+/// A file of little-endian words mapped into memory, read as the initial
+/// contents of a memory so that large constant arrays are not parsed as text.
+pub struct Backing {
+    path: String,
+    map: memmap2::Mmap,
+    data_type: String,
+    width: usize,
+}
+
+impl Backing {
+    /// Map `path` as at least `size` words of `data_type`, which must be a
+    /// scalar type that `VALUE::from_bits` can read.
+    pub fn open(path: &str, data_type: &str, size: usize) -> Result<Self, String> {
+        let width = match data_type {
+            "bool" | "i8" | "u8" => 1,
+            "i16" | "u16" | "f16" | "bf16" => 2,
+            "i32" | "u32" | "f32" => 4,
+            "i64" | "u64" | "f64" | "index" => 8,
+            _ => return Err(format!("{}: cannot hold words of {}", path, data_type)),
+        };
+        let file = std::fs::File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+        // The file is only read, and a design that loads it expects it to stay put
+        let map =
+            unsafe { memmap2::Mmap::map(&file) }.map_err(|err| format!("{}: {}", path, err))?;
+        if map.len() < size * width {
+            return Err(format!(
+                "{} holds {} words of {}, not {}",
+                path,
+                map.len() / width,
+                data_type,
+                size
+            ));
+        }
+        Ok(Backing {
+            path: path.to_string(),
+            map,
+            data_type: data_type.to_string(),
+            width,
+        })
+    }
+
+    fn word(&self, index: usize) -> VALUE {
+        let bytes = &self.map[index * self.width..(index + 1) * self.width];
+        let bits = bytes
+            .iter()
+            .rev()
+            .fold(0, |bits, byte| bits << 8 | *byte as u64);
+        VALUE::from_bits(&self.data_type, bits)
+    }
+}
+
+impl std::fmt::Debug for Backing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Backing({})", self.path)
+    }
+}
+
+/// The words of a memory, allocated a page at a time when first written so
+/// that large arrays the design barely touches cost little. Words of pages
+/// never written read from the backing file, or else as the initial value, or
+/// as X when the store was made with `x_state` on.
+///
+/// A clone shares its pages with the original until either writes them, so
+/// a checkpoint costs a page table rather than a copy of the memory.
+#[derive(Clone)]
+pub struct PagedStore {
+    init: VALUE,
+    size: usize,
+    pages: Vec<Option<Arc<[VALUE]>>>,
+    backing: Option<Arc<Backing>>,
+    /// Whether words never written read as X rather than `init`
+    unknown: bool,
+}
+
+/// Whether two values are the same bits of the same type, unlike `==`, which
+/// equates 0.0 and -0.0.
+fn identical(x: &VALUE, y: &VALUE) -> bool {
+    match (x, y) {
+        (VALUE::Vector(x), VALUE::Vector(y)) => return x == y,
+        (VALUE::C64(x), VALUE::C64(y)) => {
+            return x.re.to_bits() == y.re.to_bits() && x.im.to_bits() == y.im.to_bits()
+        }
+        _ => (),
+    }
+    std::mem::discriminant(x) == std::mem::discriminant(y) && x.to_bits() == y.to_bits()
+}
+
+/// Words compared at once by `diff_words`, before looking for which differ.
+const LANES: usize = 16;
+
+/// The type and bits of a word in one integer, the same for two words exactly
+/// when they are `identical`, so that a run of words compares without
+/// branching on their types.
+fn packed(value: &VALUE) -> u128 {
+    let (tag, bits) = match *value {
+        VALUE::Bool(x) => (0, x as u64),
+        VALUE::U32(x) => (1, x as u64),
+        VALUE::I32(x) => (2, x as u32 as u64),
+        VALUE::U64(x) => (3, x),
+        VALUE::I64(x) => (4, x as u64),
+        VALUE::F32(x) => (5, x.to_bits() as u64),
+        VALUE::F64(x) => (6, x.to_bits()),
+        VALUE::Control => (7, 0),
+        VALUE::ERROR => (8, 0),
+        VALUE::U8(x) => (9, x as u64),
+        VALUE::I8(x) => (10, x as u8 as u64),
+        VALUE::U16(x) => (11, x as u64),
+        VALUE::I16(x) => (12, x as u16 as u64),
+        VALUE::F16(x) => (13, x.to_bits() as u64),
+        VALUE::BF16(x) => (14, x.to_bits() as u64),
+        VALUE::X => (15, 0),
+        VALUE::Vector(vector) => (16, vector.digest()),
+        VALUE::C32(x) => (17, (x.re.to_bits() as u64) << 32 | x.im.to_bits() as u64),
+        VALUE::C64(x) => (18, x.re.to_bits() ^ x.im.to_bits().rotate_left(32)),
+        VALUE::Index(x) => (19, x),
+    };
+    (tag as u128) << 64 | bits as u128
+}
+
+/// Push the indices, from `offset`, of the words of `x` and `y` that differ
+/// until `found` holds `limit`.
+fn diff_words(x: &[VALUE], y: &[VALUE], offset: usize, limit: usize, found: &mut Vec<usize>) {
+    for (chunk, (x, y)) in x.chunks(LANES).zip(y.chunks(LANES)).enumerate() {
+        let differs = zip(x, y).fold(0, |differs, (x, y)| differs | packed(x) ^ packed(y));
+        if differs == 0 {
+            continue;
+        }
+        for (index, (x, y)) in zip(x, y).enumerate() {
+            if packed(x) != packed(y) {
+                found.push(offset + chunk * LANES + index);
+                if found.len() >= limit {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// `diff_words` for the `words` of two backing files of one type, comparing
+/// their bytes a chunk at a time and the words of a chunk that differs, as
+/// bytes that differ may still read as the same word.
+fn diff_backed(
+    x: &Backing,
+    y: &Backing,
+    words: Range<usize>,
+    limit: usize,
+    found: &mut Vec<usize>,
+) {
+    let width = x.width;
+    let bytes = words.start * width..words.end * width;
+    let chunks = x.map[bytes.clone()]
+        .chunks(LANES * width)
+        .zip(y.map[bytes].chunks(LANES * width));
+    for (chunk, (a, b)) in chunks.enumerate() {
+        if a == b {
+            continue;
+        }
+        let start = words.start + chunk * LANES;
+        for index in start..words.end.min(start + LANES) {
+            if !identical(&x.word(index), &y.word(index)) {
+                found.push(index);
+                if found.len() >= limit {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl PagedStore {
+    pub fn new(init: VALUE, size: usize) -> Self {
+        PagedStore {
+            init,
+            size,
+            pages: vec![None; size.div_ceil(PAGE)],
+            backing: None,
+            unknown: options().x_state,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn init(&self) -> VALUE {
+        self.init
+    }
+
+    /// The word at `index` of a page never written.
+    fn unwritten(&self, index: usize) -> VALUE {
+        match &self.backing {
+            Some(backing) => backing.word(index),
+            None if self.unknown => VALUE::X,
+            None => self.init,
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<VALUE> {
+        if index >= self.size {
+            return None;
+        }
+        Some(match &self.pages[index / PAGE] {
+            Some(page) => page[index % PAGE],
+            None => self.unwritten(index),
+        })
+    }
+
+    /// Write a word, false if `index` is out of bounds.
+    pub fn set(&mut self, index: usize, value: VALUE) -> bool {
+        if index >= self.size {
+            return false;
+        }
+        let start = index / PAGE * PAGE;
+        if self.pages[index / PAGE].is_none() {
+            if identical(&value, &self.unwritten(index)) {
+                return true;
+            }
+            let words = start..self.size.min(start + PAGE);
+            self.pages[index / PAGE] = Some(words.map(|i| self.unwritten(i)).collect());
+        }
+        let page = self.pages[index / PAGE].as_mut().unwrap();
+        // Storing the word already there leaves a shared page shared
+        if !identical(&value, &page[index - start]) {
+            Arc::make_mut(page)[index - start] = value;
+        }
+        true
+    }
+
+    /// Exchange the addressed words with the values paired with them, which
+    /// are left holding what the words were. Addresses out of bounds are
+    /// skipped.
+    pub fn swap(&mut self, words: &mut [(usize, VALUE)]) {
+        for (index, value) in words {
+            if let Some(old) = self.get(*index) {
+                self.set(*index, *value);
+                *value = old;
+            }
+        }
+    }
+
+    /// Replace the words with `values`, of the same length, leaving the pages
+    /// that hold what was never written unallocated.
+    pub fn replace(&mut self, values: &[VALUE]) {
+        assert_eq!(values.len(), self.size);
+        for (page, words) in values.chunks(PAGE).enumerate() {
+            let start = page * PAGE;
+            let unwritten = words
+                .iter()
+                .enumerate()
+                .all(|(i, word)| identical(word, &self.unwritten(start + i)));
+            self.pages[page] = (!unwritten).then(|| words.into());
+        }
+    }
 
-#[derive(Debug)]
+    /// The words of `page`, borrowed if it was written.
+    fn page_words(&self, page: usize) -> std::borrow::Cow<'_, [VALUE]> {
+        match &self.pages[page] {
+            Some(words) => std::borrow::Cow::Borrowed(words),
+            None => {
+                let start = page * PAGE;
+                (start..self.size.min(start + PAGE))
+                    .map(|index| self.unwritten(index))
+                    .collect()
+            }
+        }
+    }
+
+    /// Whether the unwritten words of both read the same, from one backing
+    /// file or as one initial value.
+    fn same_unwritten(&self, other: &PagedStore) -> bool {
+        match (&self.backing, &other.backing) {
+            (Some(x), Some(y)) => Arc::ptr_eq(x, y),
+            (None, None) => self.unknown == other.unknown && identical(&self.init, &other.init),
+            _ => false,
+        }
+    }
+
+    /// The indices of up to `limit` words, in order, that differ between the
+    /// two stores in type or bits as `identical` tells, and those past the
+    /// end of the shorter one. Pages the two share, or that neither wrote and
+    /// both read alike, are skipped whole; words both read from backing files
+    /// of one type compare as raw bytes, and the rest as packed words, a
+    /// chunk at a time.
+    pub fn differences(&self, other: &PagedStore, limit: usize) -> Vec<usize> {
+        let mut found = vec![];
+        let size = self.size.min(other.size);
+        for page in 0..size.div_ceil(PAGE) {
+            if found.len() >= limit {
+                return found;
+            }
+            let start = page * PAGE;
+            let end = size.min(start + PAGE);
+            match (&self.pages[page], &other.pages[page]) {
+                (Some(x), Some(y)) if Arc::ptr_eq(x, y) => continue,
+                (None, None) if self.same_unwritten(other) => continue,
+                (None, None) => {
+                    if let (Some(x), Some(y)) = (&self.backing, &other.backing) {
+                        if x.data_type == y.data_type {
+                            let words = start..end;
+                            diff_backed(x, y, words, limit, &mut found);
+                            continue;
+                        }
+                    }
+                }
+                _ => (),
+            }
+            let (x, y) = (self.page_words(page), other.page_words(page));
+            diff_words(
+                &x[..end - start],
+                &y[..end - start],
+                start,
+                limit,
+                &mut found,
+            );
+        }
+        found.extend((size..self.size.max(other.size)).take(limit.saturating_sub(found.len())));
+        found
+    }
+
+    /// The first word that differs between the two stores, see `differences`.
+    pub fn first_difference(&self, other: &PagedStore) -> Option<usize> {
+        self.differences(other, 1).pop()
+    }
+
+    /// Read the words never written from `backing`.
+    pub fn set_backing(&mut self, backing: Arc<Backing>) {
+        self.backing = Some(backing);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = VALUE> + Clone + '_ {
+        (0..self.size).map(move |index| match &self.pages[index / PAGE] {
+            Some(page) => page[index % PAGE],
+            None => self.unwritten(index),
+        })
+    }
+
+    pub fn to_vec(&self) -> Vec<VALUE> {
+        self.iter().collect()
+    }
+
+    /// Bytes allocated for the pages written so far and the page table. The
+    /// mapped backing file is not counted, pages shared with a checkpoint are.
+    pub fn footprint(&self) -> usize {
+        let words: usize = self.pages.iter().flatten().map(|page| page.len()).sum();
+        words * std::mem::size_of::<VALUE>()
+            + self.pages.len() * std::mem::size_of::<Option<Arc<[VALUE]>>>()
+    }
+}
+
+/// An empty store, for units built before their size is known.
+impl Default for PagedStore {
+    fn default() -> Self {
+        PagedStore::new(VALUE::ERROR, 0)
+    }
+}
+
+/// Prints the words like the vector memories used to be.
+impl std::fmt::Debug for PagedStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Memory {
-    store: Vec<VALUE>,
+    store: PagedStore,
 }
 
 impl Memory {
     pub fn update(&mut self, index: usize, value: VALUE) {
-        self.store[index] = value.clone();
+        assert!(
+            self.store.set(index, value),
+            "index {} out of bounds",
+            index
+        );
     }
 
     pub fn get_value(&self, index: usize) -> VALUE {
-        self.store[index]
+        self.store.get(index).unwrap()
+    }
+
+    pub fn size(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn footprint(&self) -> usize {
+        self.store.footprint()
+    }
+
+    /// The words, to compare with those of another memory.
+    pub fn store(&self) -> &PagedStore {
+        &self.store
+    }
+
+    pub fn set_backing(&mut self, backing: Arc<Backing>) {
+        self.store.set_backing(backing);
     }
 
     pub fn set(&mut self, store: &[&str]) {
-        let data_type = self.store[0].get_type();
-        for (store, value) in self.store.iter_mut().zip(store) {
-            *store = build_value(&data_type, &String::from(value.to_owned()));
+        let data_type = self.store.init().get_type();
+        for (index, value) in store.iter().take(self.store.len()).enumerate() {
+            self.store
+                .set(index, build_value(&data_type, &String::from(*value)));
         }
     }
 
-    pub fn new(store: Vec<VALUE>) -> Self {
-        Memory { store: store }
+    pub fn dump(&self) -> Vec<VALUE> {
+        self.store.to_vec()
+    }
+
+    pub fn restore(&mut self, store: &[VALUE]) {
+        self.store.replace(store);
+    }
+
+    /// Exchange words with the values paired with their addresses.
+    pub fn swap(&mut self, words: &mut [(usize, VALUE)]) {
+        self.store.swap(words);
+    }
+
+    pub fn to_json(&self) -> Value {
+        values_to_json(self.store.iter())
+    }
+
+    pub fn to_state(&self) -> Value {
+        store_to_state(self.store.iter())
+    }
+
+    /// Replace the contents with a state of the same size, None if it is not.
+    pub fn restore_state(&mut self, state: &Value) -> Option<()> {
+        let store = store_from_state(state)?;
+        (store.len() == self.store.len()).then(|| self.store.replace(&store))
+    }
+
+    /// A memory of `size` words of `init`, allocated as they are written.
+    pub fn new(init: VALUE, size: usize) -> Self {
+        Memory {
+            store: PagedStore::new(init, size),
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Stream {
     store: VecDeque<VALUE>,
     depth: usize,
@@ -49,6 +483,20 @@ impl Stream {
         self.store.pop_front().unwrap()
     }
 
+    pub fn to_json(&self) -> Value {
+        values_to_json(self.store.iter())
+    }
+
+    pub fn to_state(&self) -> Value {
+        store_to_state(self.store.iter())
+    }
+
+    /// Replace the queued values, None if the state holds more than fit.
+    pub fn restore_state(&mut self, state: &Value) -> Option<()> {
+        let store = store_from_state(state)?;
+        (store.len() <= self.depth).then(|| self.store = store.into())
+    }
+
     pub fn new(depth: usize) -> Self {
         Stream {
             store: VecDeque::with_capacity(depth),
@@ -57,7 +505,7 @@ impl Stream {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FIFO {
     store: VecDeque<VALUE>,
     depth: usize,
@@ -68,18 +516,57 @@ pub struct FIFO {
 }
 
 impl FIFO {
-    pub fn update(&mut self) {
+    /// The port misused in this cycle, if the FIFO is read while empty or
+    /// written while full without a read freeing a slot.
+    pub fn check(&self) -> Option<&'static str> {
+        let read = self.r_en.get_value() == VALUE::Bool(true);
+        let write = self.w_en.get_value() == VALUE::Bool(true);
+        if read && self.store.is_empty() {
+            Some("underflow")
+        } else if write && !read && self.is_full() {
+            Some("overflow")
+        } else {
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Put a token in from outside the design, returns false when full.
+    pub fn push(&mut self, value: VALUE) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.store.push_back(value);
+        true
+    }
+
+    /// Returns the tokens popped and pushed in this cycle. A read of an empty
+    /// FIFO and a write to a full one are dropped, see `check`.
+    pub fn update(&mut self) -> (Option<VALUE>, Option<VALUE>) {
+        let mut popped = None;
+        let mut pushed = None;
         if self.r_en.get_value() == VALUE::Bool(true) {
-            let data = self.store.pop_front();
-            assert!(data.is_some());
-            self.r_data = data.unwrap();
+            if let Some(data) = self.store.pop_front() {
+                self.r_data = data;
+                popped = Some(data);
+            }
         }
-        if self.w_en.get_value() == VALUE::Bool(true) {
-            self.store.push_back(self.w_data.get_value())
+        if self.w_en.get_value() == VALUE::Bool(true) && !self.is_full() {
+            let data = self.w_data.get_value();
+            self.store.push_back(data);
+            pushed = Some(data);
         }
         self.r_en.update();
         self.w_en.update();
         self.w_data.update();
+        (popped, pushed)
     }
 
     pub fn set_port(&mut self, port: &String, val: VALUE) {
@@ -103,6 +590,32 @@ impl FIFO {
         self.store.len() == self.depth
     }
 
+    pub fn to_json(&self) -> Value {
+        values_to_json(self.store.iter())
+    }
+
+    pub fn to_state(&self) -> Value {
+        json!({
+            "store": store_to_state(self.store.iter()),
+            "r_en": self.r_en.to_state(),
+            "w_en": self.w_en.to_state(),
+            "r_data": value_to_state(&self.r_data),
+            "w_data": self.w_data.to_state(),
+        })
+    }
+
+    pub fn restore_state(&mut self, state: &Value) -> Option<()> {
+        let store = store_from_state(&state["store"])?;
+        if store.len() > self.depth {
+            return None;
+        }
+        self.store = store.into();
+        self.r_en.restore_state(&state["r_en"])?;
+        self.w_en.restore_state(&state["w_en"])?;
+        self.r_data = value_from_state(&state["r_data"])?;
+        self.w_data.restore_state(&state["w_data"])
+    }
+
     pub fn new(depth: usize, data_type: &String) -> Self {
         FIFO {
             store: VecDeque::with_capacity(depth),
@@ -115,9 +628,9 @@ impl FIFO {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HardwareMemory {
-    store: Vec<VALUE>,
+    store: PagedStore,
     r_en: ValueTiming,
     w_en: ValueTiming,
     addr: ValueTiming,
@@ -129,12 +642,21 @@ pub struct HardwareMemory {
 }
 
 impl HardwareMemory {
-    pub fn update(&mut self) {
-        if self.w_en.get_value() == VALUE::Bool(true) {
-            self.store[self.addr.get_new_value().as_u64() as usize] = self.data.get_value();
-        }
-        if self.w_en2.get_value() == VALUE::Bool(true) {
-            self.store[self.addr2.get_new_value().as_u64() as usize] = self.data2.get_value();
+    /// Clock the ports and write the enabled stores, or return the address of
+    /// a store out of bounds or at X.
+    pub fn update(&mut self) -> Result<(), VALUE> {
+        let mut result = Ok(());
+        for (w_en, addr, data) in [
+            (&self.w_en, &self.addr, &self.data),
+            (&self.w_en2, &self.addr2, &self.data2),
+        ] {
+            if w_en.get_value() != VALUE::Bool(true) {
+                continue;
+            }
+            let address = addr.get_new_value();
+            if address == VALUE::X || !self.store.set(address.as_u64() as usize, data.get_value()) {
+                result = Err(address);
+            }
         }
 
         self.r_en.update();
@@ -145,6 +667,18 @@ impl HardwareMemory {
         self.w_en2.update();
         self.addr2.update();
         self.data2.update();
+        result
+    }
+
+    /// The word at an address port, ERROR out of bounds and X at X.
+    fn read(&self, addr: &ValueTiming) -> VALUE {
+        let address = addr.get_value();
+        if address == VALUE::X {
+            return VALUE::X;
+        }
+        self.store
+            .get(address.as_u64() as usize)
+            .unwrap_or(VALUE::ERROR)
     }
 
     pub fn set_port(&mut self, port: &String, val: VALUE) {
@@ -165,22 +699,97 @@ impl HardwareMemory {
     pub fn get_value(&self, port: &String) -> VALUE {
         // println!("{:?} {:?}", self.addr.get_value(), self.store[self.addr.get_value().as_u64() as usize]);
         match port as &str {
-            "r_data" => self.store[self.addr.get_value().as_u64() as usize],
-            "r_data2" => self.store[self.addr2.get_value().as_u64() as usize],
+            "r_data" => self.read(&self.addr),
+            "r_data2" => self.read(&self.addr2),
             _ => panic!("can only get value from r_data or r_Data2, not {:?}", port),
         }
     }
 
     pub fn set(&mut self, store: &[&str]) {
-        let data_type = self.store[0].get_type();
-        for (store, value) in self.store.iter_mut().zip(store) {
-            *store = build_value(&data_type, &String::from(value.to_owned()));
+        let data_type = self.store.init().get_type();
+        for (index, value) in store.iter().take(self.store.len()).enumerate() {
+            self.store
+                .set(index, build_value(&data_type, &String::from(*value)));
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn footprint(&self) -> usize {
+        self.store.footprint()
+    }
+
+    /// The words, to compare with those of another memory.
+    pub fn store(&self) -> &PagedStore {
+        &self.store
+    }
+
+    pub fn set_backing(&mut self, backing: Arc<Backing>) {
+        self.store.set_backing(backing);
+    }
+
+    pub fn dump(&self) -> Vec<VALUE> {
+        self.store.to_vec()
+    }
+
+    pub fn restore(&mut self, store: &[VALUE]) {
+        self.store.replace(store);
+    }
+
+    /// Exchange words with the values paired with their addresses.
+    pub fn swap(&mut self, words: &mut [(usize, VALUE)]) {
+        self.store.swap(words);
+    }
+
+    pub fn to_json(&self) -> Value {
+        values_to_json(self.store.iter())
+    }
+
+    fn ports_mut(&mut self) -> [(&'static str, &mut ValueTiming); 8] {
+        [
+            ("r_en", &mut self.r_en),
+            ("w_en", &mut self.w_en),
+            ("addr", &mut self.addr),
+            ("w_data", &mut self.data),
+            ("r_en2", &mut self.r_en2),
+            ("w_en2", &mut self.w_en2),
+            ("addr2", &mut self.addr2),
+            ("w_data2", &mut self.data2),
+        ]
+    }
+
+    pub fn to_state(&self) -> Value {
+        json!({
+            "store": store_to_state(self.store.iter()),
+            "r_en": self.r_en.to_state(),
+            "w_en": self.w_en.to_state(),
+            "addr": self.addr.to_state(),
+            "w_data": self.data.to_state(),
+            "r_en2": self.r_en2.to_state(),
+            "w_en2": self.w_en2.to_state(),
+            "addr2": self.addr2.to_state(),
+            "w_data2": self.data2.to_state(),
+        })
+    }
+
+    pub fn restore_state(&mut self, state: &Value) -> Option<()> {
+        let store = store_from_state(&state["store"])?;
+        if store.len() != self.store.len() {
+            return None;
+        }
+        self.store.replace(&store);
+        for (port, timing) in self.ports_mut() {
+            timing.restore_state(&state[port])?;
         }
+        Some(())
     }
 
-    pub fn new(store: Vec<VALUE>, data_type: &String) -> Self {
+    /// A memory of `size` words of `init`, allocated as they are written.
+    pub fn new(init: VALUE, size: usize, data_type: &String) -> Self {
         HardwareMemory {
-            store,
+            store: PagedStore::new(init, size),
             r_en: ValueTiming::new(1, &"bool".to_string()),
             w_en: ValueTiming::new(0, &"bool".to_string()),
             addr: ValueTiming::new(1, &"u32".to_string()),
@@ -193,281 +802,1582 @@ impl HardwareMemory {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum VALUE {
     Bool(bool),
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
     U32(u32),
     I32(i32),
     U64(u64),
     I64(i64),
+    /// An `index`, 64 bits unsigned like the addresses it computes
+    Index(u64),
+    F16(f16),
+    BF16(bf16),
     F32(f32),
     F64(f64),
     Control,
     ERROR,
+    /// An unknown value: a register or memory word never written, read with
+    /// `x_state` on, and whatever is computed from one
+    X,
+    /// Lanes of one scalar type, operated on elementwise
+    Vector(Vector),
+    C32(Complex<f32>),
+    C64(Complex<f64>),
+}
+
+/// A complex float, typed `complex<f32>` or `complex<f64>` as in MLIR.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Complex<T> {
+    pub re: T,
+    pub im: T,
+}
+
+impl<T: Copy + Neg<Output = T>> Complex<T> {
+    pub fn conj(self) -> Self {
+        Complex {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+}
+
+impl<T: Add<Output = T>> Add for Complex<T> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Complex {
+            re: self.re + other.re,
+            im: self.im + other.im,
+        }
+    }
+}
+
+impl<T: Sub<Output = T>> Sub for Complex<T> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Complex {
+            re: self.re - other.re,
+            im: self.im - other.im,
+        }
+    }
+}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<Output = T>> Mul for Complex<T> {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+}
+
+/// Shown as `a+bi`, or `a-bi` when the imaginary part is negative.
+macro_rules! complex_debug {
+    ($float: ty) => {
+        impl std::fmt::Debug for Complex<$float> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                if self.im.is_sign_negative() {
+                    write!(f, "{:?}-{:?}i", self.re, -self.im)
+                } else {
+                    write!(f, "{:?}+{:?}i", self.re, self.im)
+                }
+            }
+        }
+    };
+}
+
+complex_debug!(f32);
+complex_debug!(f64);
+
+/// A complex literal such as `1.5`, `2i`, `1.5+2i` or `1.5e-3-2i`.
+fn parse_complex<T: std::str::FromStr + Default>(text: &str) -> Option<Complex<T>> {
+    let Some(text) = text.strip_suffix('i') else {
+        let re = text.parse().ok()?;
+        return Some(Complex {
+            re,
+            im: T::default(),
+        });
+    };
+    // The sign between the parts, not that of the real part or an exponent
+    let split = text
+        .char_indices()
+        .rev()
+        .find(|&(index, c)| {
+            index > 0 && matches!(c, '+' | '-') && !text[..index].ends_with(['e', 'E'])
+        })
+        .map(|(index, _)| index);
+    let (re, im) = match split {
+        Some(index) => (text[..index].parse().ok()?, &text[index..]),
+        None => (T::default(), text),
+    };
+    let im = im.strip_prefix('+').unwrap_or(im);
+    Some(Complex {
+        re,
+        im: im.parse().ok()?,
+    })
+}
+
+/// Up to 128 bits of lanes of one scalar type, lane 0 in the low bits, typed
+/// `vector<4xf32>` as in MLIR. The lanes are held inline to keep `VALUE`
+/// `Copy`.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Vector {
+    elem: DataType,
+    lanes: u8,
+    bits: [u64; 2],
+}
+
+impl Vector {
+    /// The lane type and count of a `vector<NxT>` type, None if `data_type`
+    /// is not one or does not fit 128 bits.
+    pub fn parse_type(data_type: &str) -> Option<(DataType, usize)> {
+        let shape = data_type.strip_prefix("vector<")?.strip_suffix('>')?;
+        let (lanes, elem) = shape.split_once('x')?;
+        let lanes = lanes.parse::<usize>().ok()?;
+        let fits = (1..=128).contains(&(lanes * bit_width(elem)));
+        let elem = DataType::parse(elem);
+        let scalar = !matches!(elem, DataType::Unknown | DataType::C32 | DataType::C64);
+        (scalar && fits).then_some((elem, lanes))
+    }
+
+    /// A vector of `lanes`, converted to `elem`. ERROR if a lane is ERROR, or
+    /// else X if a lane is X.
+    pub fn from_lanes(elem: DataType, lanes: &[VALUE]) -> VALUE {
+        if lanes.contains(&VALUE::ERROR) {
+            return VALUE::ERROR;
+        }
+        if unknown(lanes) {
+            return VALUE::X;
+        }
+        let mut vector = Vector {
+            elem,
+            lanes: lanes.len() as u8,
+            bits: [0; 2],
+        };
+        for (index, lane) in lanes.iter().enumerate() {
+            let lane = convert(*lane, &elem.name().to_string());
+            vector.set_lane(index, lane.to_bits().unwrap_or(0));
+        }
+        VALUE::Vector(vector)
+    }
+
+    pub fn lanes(&self) -> usize {
+        self.lanes as usize
+    }
+
+    pub fn type_name(&self) -> String {
+        format!("vector<{}x{}>", self.lanes, self.elem.name())
+    }
+
+    fn width(&self) -> usize {
+        bit_width(self.elem.name())
+    }
+
+    /// Lane `index`, ERROR past the last one.
+    pub fn lane(&self, index: usize) -> VALUE {
+        if index >= self.lanes() {
+            return VALUE::ERROR;
+        }
+        // The lane widths divide 64, so no lane straddles two words.
+        let offset = index * self.width();
+        let bits = self.bits[offset / 64] >> (offset % 64);
+        VALUE::from_bits(self.elem.name(), bits & low_mask(self.width()))
+    }
+
+    fn set_lane(&mut self, index: usize, bits: u64) {
+        let offset = index * self.width();
+        let mask = low_mask(self.width()) << (offset % 64);
+        let word = &mut self.bits[offset / 64];
+        *word = *word & !mask | bits << (offset % 64) & mask;
+    }
+
+    /// The vector with lane `index` replaced by `value`, ERROR past the last
+    /// lane.
+    pub fn with_lane(&self, index: usize, value: VALUE) -> VALUE {
+        let mut lanes = (0..self.lanes()).map(|i| self.lane(i)).collect::<Vec<_>>();
+        match lanes.get_mut(index) {
+            Some(lane) => *lane = value,
+            None => return VALUE::ERROR,
+        }
+        Vector::from_lanes(self.elem, &lanes)
+    }
+
+    /// The bits of the lanes folded into one word, for comparing memories
+    /// without branching.
+    fn digest(&self) -> u64 {
+        let shape = (self.elem as u64) << 8 | self.lanes as u64;
+        self.bits[0] ^ (self.bits[1] ^ shape).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    }
+}
+
+impl std::fmt::Debug for Vector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries((0..self.lanes()).map(|i| self.lane(i)))
+            .finish()
+    }
+}
+
+/// Prints like a derived `Debug` in the default radix, and integers and floats
+/// as their bit pattern with `radix hex` or `bin`, such as `F32(0x3fc00000)`.
+impl std::fmt::Debug for VALUE {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, field): (&str, &dyn std::fmt::Debug) = match self {
+            VALUE::Bool(x) => return f.debug_tuple("Bool").field(x).finish(),
+            VALUE::U8(x) => ("U8", x),
+            VALUE::I8(x) => ("I8", x),
+            VALUE::U16(x) => ("U16", x),
+            VALUE::I16(x) => ("I16", x),
+            VALUE::U32(x) => ("U32", x),
+            VALUE::I32(x) => ("I32", x),
+            VALUE::U64(x) => ("U64", x),
+            VALUE::I64(x) => ("I64", x),
+            VALUE::Index(x) => ("Index", x),
+            VALUE::F16(x) => ("F16", x),
+            VALUE::BF16(x) => ("BF16", x),
+            VALUE::F32(x) => ("F32", x),
+            VALUE::F64(x) => ("F64", x),
+            VALUE::Vector(x) => return f.debug_tuple("Vector").field(x).finish(),
+            VALUE::C32(x) => return f.debug_tuple("C32").field(x).finish(),
+            VALUE::C64(x) => return f.debug_tuple("C64").field(x).finish(),
+            VALUE::Control => return f.write_str("Control"),
+            VALUE::ERROR => return f.write_str("ERROR"),
+            VALUE::X => return f.write_str("X"),
+        };
+        let bits = self.to_bits().unwrap_or_default();
+        let width = bit_width(&self.get_type());
+        let digits = match options().radix {
+            Radix::Dec => return f.debug_tuple(name).field(field).finish(),
+            Radix::Hex => format!("0x{:0digits$x}", bits, digits = width.div_ceil(4)),
+            Radix::Bin => format!("0b{:0width$b}", bits, width = width),
+        };
+        f.debug_tuple(name)
+            .field(&format_args!("{}", digits))
+            .finish()
+    }
 }
 
 impl VALUE {
     pub fn get_type(&self) -> String {
+        if let VALUE::Vector(vector) = self {
+            return vector.type_name();
+        }
         String::from(match &self {
             VALUE::Bool(_) => "bool",
+            VALUE::U8(_) => "u8",
+            VALUE::I8(_) => "i8",
+            VALUE::U16(_) => "u16",
+            VALUE::I16(_) => "i16",
             VALUE::U32(_) => "u32",
             VALUE::U64(_) => "u64",
+            VALUE::Index(_) => "index",
             VALUE::I32(_) => "i32",
             VALUE::I64(_) => "i64",
+            VALUE::F16(_) => "f16",
+            VALUE::BF16(_) => "bf16",
             VALUE::F32(_) => "f32",
             VALUE::F64(_) => "f64",
             VALUE::Control => "i1000",
+            VALUE::X => "x",
+            VALUE::C32(_) => "complex<f32>",
+            VALUE::C64(_) => "complex<f64>",
             _ => "error",
         })
     }
-}
 
-pub fn convert(old_value: VALUE, new_type: &String) -> VALUE {
-    match &new_type as &str {
-        "bool" => VALUE::Bool(old_value.as_bool()),
-        "i32" => VALUE::I32(old_value.as_i32()),
-        "u32" => VALUE::U32(old_value.as_u32()),
-        "f32" => VALUE::F32(old_value.as_f32()),
-        "i64" => VALUE::I64(old_value.as_i64()),
-        "u64" => VALUE::U64(old_value.as_u64()),
-        "f64" => VALUE::F64(old_value.as_f64()),
-        _ => VALUE::ERROR,
+    pub fn to_json(&self) -> Value {
+        match *self {
+            VALUE::Bool(x) => json!(x),
+            VALUE::U8(x) => json!(x),
+            VALUE::I8(x) => json!(x),
+            VALUE::U16(x) => json!(x),
+            VALUE::I16(x) => json!(x),
+            VALUE::U32(x) => json!(x),
+            VALUE::I32(x) => json!(x),
+            VALUE::U64(x) => json!(x),
+            VALUE::Index(x) => json!(x),
+            VALUE::I64(x) => json!(x),
+            VALUE::F16(x) => json!(x.to_f32()),
+            VALUE::BF16(x) => json!(x.to_f32()),
+            VALUE::F32(x) => json!(x),
+            VALUE::F64(x) => json!(x),
+            VALUE::Control => json!("control"),
+            VALUE::ERROR => Value::Null,
+            VALUE::X => json!("x"),
+            VALUE::Vector(vector) => {
+                values_to_json((0..vector.lanes()).map(|index| vector.lane(index)))
+            }
+            VALUE::C32(x) => json!([x.re, x.im]),
+            VALUE::C64(x) => json!([x.re, x.im]),
+        }
+    }
+}
+
+pub fn values_to_json(values: impl Iterator<Item = impl Borrow<VALUE>>) -> Value {
+    Value::Array(values.map(|value| value.borrow().to_json()).collect())
+}
+
+/// The number or bool of a value for a state file, with the floats JSON cannot
+/// hold written as text.
+fn state_literal(value: &VALUE) -> Value {
+    match *value {
+        VALUE::F16(x) if !x.is_finite() => json!(x.to_string()),
+        VALUE::BF16(x) if !x.is_finite() => json!(x.to_string()),
+        VALUE::F32(x) if !x.is_finite() => json!(x.to_string()),
+        VALUE::F64(x) if !x.is_finite() => json!(x.to_string()),
+        _ => value.to_json(),
+    }
+}
+
+fn parse_literal(data_type: &str, literal: &Value) -> Option<VALUE> {
+    if let (Some((elem, lanes)), Value::Array(items)) = (Vector::parse_type(data_type), literal) {
+        let items = items.iter().map(|item| parse_literal(elem.name(), item));
+        let items = items.collect::<Option<Vec<_>>>()?;
+        return (items.len() == lanes).then(|| Vector::from_lanes(elem, &items));
+    }
+    if let (DataType::C32 | DataType::C64, Value::Array(pair)) =
+        (DataType::parse(data_type), literal)
+    {
+        let [re, im] = pair.as_slice() else {
+            return None;
+        };
+        return parse_value(data_type, &format!("{}+{}i", re.as_f64()?, im.as_f64()?));
+    }
+    let text = match literal {
+        Value::String(text) => text.clone(),
+        Value::Number(_) | Value::Bool(_) => literal.to_string(),
+        _ => return None,
+    };
+    parse_value(data_type, &text).filter(|value| *value != VALUE::ERROR)
+}
+
+/// A value for a state file as `[type, value]`, so that it reads back with
+/// its type. ERROR is null, Control is `"control"` and X is `"x"`.
+pub fn value_to_state(value: &VALUE) -> Value {
+    match value {
+        VALUE::ERROR => Value::Null,
+        VALUE::Control => json!("control"),
+        VALUE::X => json!("x"),
+        _ => json!([value.get_type(), state_literal(value)]),
+    }
+}
+
+pub fn value_from_state(state: &Value) -> Option<VALUE> {
+    match state {
+        Value::Null => Some(VALUE::ERROR),
+        Value::String(text) if text == "control" => Some(VALUE::Control),
+        Value::String(text) if text == "x" => Some(VALUE::X),
+        Value::Array(pair) if pair.len() == 2 => parse_literal(pair[0].as_str()?, &pair[1]),
+        _ => None,
+    }
+}
+
+/// The contents of a memory or queue for a state file. Their common type is
+/// given once, values of another type are written as `[type, value]`.
+pub fn store_to_state(values: impl Iterator<Item = impl Borrow<VALUE>> + Clone) -> Value {
+    let data_type = values
+        .clone()
+        .map(|value| *value.borrow())
+        .find(|value| !matches!(value, VALUE::ERROR | VALUE::Control | VALUE::X))
+        .map(|value| value.get_type());
+    let values = values
+        .map(|value| *value.borrow())
+        .map(|value| match &data_type {
+            Some(data_type) if value.get_type() == *data_type => state_literal(&value),
+            _ => value_to_state(&value),
+        })
+        .collect::<Vec<_>>();
+    json!({"type": data_type, "values": values})
+}
+
+pub fn store_from_state(state: &Value) -> Option<Vec<VALUE>> {
+    let data_type = state["type"].as_str();
+    state["values"]
+        .as_array()?
+        .iter()
+        .map(|value| match value {
+            Value::Null | Value::Array(_) => value_from_state(value),
+            Value::String(text) if text == "control" => Some(VALUE::Control),
+            Value::String(text) if text == "x" => Some(VALUE::X),
+            _ => parse_literal(data_type?, value),
+        })
+        .collect()
+}
+
+/// Collect the named values selected by `filter` into a JSON object.
+pub fn variables_to_json<'a, K: AsRef<str> + 'a>(
+    values: impl Iterator<Item = (&'a K, &'a VALUE)>,
+    filter: &NameFilter,
+) -> Value {
+    let mut object = serde_json::Map::new();
+    for (var, value) in values {
+        let var = var.as_ref();
+        if filter.matches(var) {
+            object.insert(var.to_string(), value.to_json());
+        }
+    }
+    Value::Object(object)
+}
+
+pub fn convert(old_value: VALUE, new_type: &String) -> VALUE {
+    if old_value == VALUE::X {
+        return VALUE::X;
+    }
+    match &new_type as &str {
+        "bool" => VALUE::Bool(old_value.as_bool()),
+        "i8" => VALUE::I8(old_value.as_i8()),
+        "u8" => VALUE::U8(old_value.as_u8()),
+        "i16" => VALUE::I16(old_value.as_i16()),
+        "u16" => VALUE::U16(old_value.as_u16()),
+        "i32" => VALUE::I32(old_value.as_i32()),
+        "u32" => VALUE::U32(old_value.as_u32()),
+        "f16" => VALUE::F16(f16::from_f64(old_value.as_f64())),
+        "bf16" => VALUE::BF16(bf16::from_f64(old_value.as_f64())),
+        "f32" => VALUE::F32(old_value.as_f32()),
+        "i64" => VALUE::I64(old_value.as_i64()),
+        "u64" => VALUE::U64(old_value.as_u64()),
+        "index" => VALUE::Index(old_value.as_u64()),
+        "f64" => VALUE::F64(old_value.as_f64()),
+        _ => VALUE::ERROR,
     }
 }
 
 pub fn build_value(data_type: &String, value: &String) -> VALUE {
-    match &data_type as &str {
-        "i32" => VALUE::I32(value.parse::<i32>().unwrap()),
-        "i64" => VALUE::I64(value.parse::<i64>().unwrap()),
-        "u32" => VALUE::U32(value.parse::<u32>().unwrap()),
-        "u64" => VALUE::U64(value.parse::<u64>().unwrap()),
-        "f32" => VALUE::F32(value.parse::<f32>().unwrap()),
-        "f64" => VALUE::F64(value.parse::<f64>().unwrap()),
-        "bool" => match value as &str {
+    parse_value(data_type, value).unwrap()
+}
+
+/// Like `build_value`, but returns None if `value` is not a valid `data_type`.
+pub fn parse_value(data_type: &str, value: &str) -> Option<VALUE> {
+    Some(match data_type {
+        "i8" => VALUE::I8(value.parse::<i8>().ok()?),
+        "u8" => VALUE::U8(value.parse::<u8>().ok()?),
+        "i16" => VALUE::I16(value.parse::<i16>().ok()?),
+        "u16" => VALUE::U16(value.parse::<u16>().ok()?),
+        "i32" => VALUE::I32(value.parse::<i32>().ok()?),
+        "i64" => VALUE::I64(value.parse::<i64>().ok()?),
+        "u32" => VALUE::U32(value.parse::<u32>().ok()?),
+        "u64" => VALUE::U64(value.parse::<u64>().ok()?),
+        "index" => VALUE::Index(value.parse::<u64>().ok()?),
+        "f16" => VALUE::F16(f16::from_f64(value.parse::<f64>().ok()?)),
+        "bf16" => VALUE::BF16(bf16::from_f64(value.parse::<f64>().ok()?)),
+        "f32" => VALUE::F32(value.parse::<f32>().ok()?),
+        "f64" => VALUE::F64(value.parse::<f64>().ok()?),
+        "complex<f32>" => VALUE::C32(parse_complex(value)?),
+        "complex<f64>" => VALUE::C64(parse_complex(value)?),
+        "bool" => match value {
             "0" => VALUE::Bool(false),
             "1" => VALUE::Bool(true),
-            _ => VALUE::Bool(value.parse::<bool>().unwrap()),
+            _ => VALUE::Bool(value.parse::<bool>().ok()?),
         },
         _ => VALUE::ERROR,
-    }
+    })
 }
 
 macro_rules! value_type {
     ($func_name: ident, $x: ty) => {
         pub fn $func_name(&self) -> $x {
             match &self {
+                VALUE::U8(x) => *x as $x,
+                VALUE::I8(x) => *x as $x,
+                VALUE::U16(x) => *x as $x,
+                VALUE::I16(x) => *x as $x,
                 VALUE::U32(x) => *x as $x,
                 VALUE::I32(x) => *x as $x,
                 VALUE::U64(x) => *x as $x,
+                VALUE::Index(x) => *x as $x,
                 VALUE::I64(x) => *x as $x,
                 VALUE::Bool(x) => *x as $x,
-                VALUE::ERROR => 0,
+                VALUE::ERROR | VALUE::X => 0,
                 _ => panic!(),
             }
         }
     };
 }
 
-impl VALUE {
-    value_type!(as_u32, u32);
-    value_type!(as_u64, u64);
-    value_type!(as_i32, i32);
-    value_type!(as_i64, i64);
+impl VALUE {
+    value_type!(as_u8, u8);
+    value_type!(as_i8, i8);
+    value_type!(as_u16, u16);
+    value_type!(as_i16, i16);
+    value_type!(as_u32, u32);
+    value_type!(as_u64, u64);
+    value_type!(as_i32, i32);
+    value_type!(as_i64, i64);
+
+    pub fn as_bool(&self) -> bool {
+        match &self {
+            VALUE::U8(x) => *x != 0,
+            VALUE::I8(x) => *x != 0,
+            VALUE::U16(x) => *x != 0,
+            VALUE::I16(x) => *x != 0,
+            VALUE::U32(x) => *x != 0,
+            VALUE::I32(x) => *x != 0,
+            VALUE::U64(x) => *x != 0,
+            VALUE::Index(x) => *x != 0,
+            VALUE::I64(x) => *x != 0,
+            VALUE::Bool(x) => *x,
+            VALUE::ERROR | VALUE::X => false,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_f32(&self) -> f32 {
+        match &self {
+            VALUE::F32(x) => *x,
+            VALUE::F64(x) => *x as f32,
+            VALUE::F16(x) => x.to_f32(),
+            VALUE::BF16(x) => x.to_f32(),
+            VALUE::U8(x) => *x as f32,
+            VALUE::I8(x) => *x as f32,
+            VALUE::U16(x) => *x as f32,
+            VALUE::I16(x) => *x as f32,
+            VALUE::U32(x) => *x as f32,
+            VALUE::I32(x) => *x as f32,
+            VALUE::U64(x) => *x as f32,
+            VALUE::Index(x) => *x as f32,
+            VALUE::I64(x) => *x as f32,
+            _ => 0.0,
+        }
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        match &self {
+            VALUE::F32(x) => *x as f64,
+            VALUE::F64(x) => *x,
+            VALUE::F16(x) => x.to_f64(),
+            VALUE::BF16(x) => x.to_f64(),
+            VALUE::U8(x) => *x as f64,
+            VALUE::I8(x) => *x as f64,
+            VALUE::U16(x) => *x as f64,
+            VALUE::I16(x) => *x as f64,
+            VALUE::U32(x) => *x as f64,
+            VALUE::I32(x) => *x as f64,
+            VALUE::U64(x) => *x as f64,
+            VALUE::Index(x) => *x as f64,
+            VALUE::I64(x) => *x as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// The bit pattern of the value as hardware holds it, None for ERROR and
+    /// Control.
+    pub fn to_bits(&self) -> Option<u64> {
+        Some(match *self {
+            VALUE::Bool(x) => x as u64,
+            VALUE::U8(x) => x as u64,
+            VALUE::I8(x) => x as u8 as u64,
+            VALUE::U16(x) => x as u64,
+            VALUE::I16(x) => x as u16 as u64,
+            VALUE::U32(x) => x as u64,
+            VALUE::I32(x) => x as u32 as u64,
+            VALUE::U64(x) => x,
+            VALUE::Index(x) => x,
+            VALUE::I64(x) => x as u64,
+            VALUE::F16(x) => x.to_bits() as u64,
+            VALUE::BF16(x) => x.to_bits() as u64,
+            VALUE::F32(x) => x.to_bits() as u64,
+            VALUE::F64(x) => x.to_bits(),
+            VALUE::Control
+            | VALUE::ERROR
+            | VALUE::X
+            | VALUE::Vector(_)
+            | VALUE::C32(_)
+            | VALUE::C64(_) => return None,
+        })
+    }
+
+    /// A value of `data_type` from its bit pattern, keeping the low bits.
+    pub fn from_bits(data_type: &str, bits: u64) -> VALUE {
+        match data_type {
+            "bool" => VALUE::Bool(bits & 1 != 0),
+            "u8" => VALUE::U8(bits as u8),
+            "i8" => VALUE::I8(bits as u8 as i8),
+            "u16" => VALUE::U16(bits as u16),
+            "i16" => VALUE::I16(bits as u16 as i16),
+            "u32" => VALUE::U32(bits as u32),
+            "i32" => VALUE::I32(bits as u32 as i32),
+            "u64" => VALUE::U64(bits),
+            "index" => VALUE::Index(bits),
+            "i64" => VALUE::I64(bits as i64),
+            "f16" => VALUE::F16(f16::from_bits(bits as u16)),
+            "bf16" => VALUE::BF16(bf16::from_bits(bits as u16)),
+            "f32" => VALUE::F32(f32::from_bits(bits as u32)),
+            "f64" => VALUE::F64(f64::from_bits(bits)),
+            _ => VALUE::ERROR,
+        }
+    }
+}
+
+/// The bit width of a value of `data_type` in hardware.
+pub fn bit_width(data_type: &str) -> usize {
+    match data_type {
+        "bool" => 1,
+        "u8" | "i8" => 8,
+        "u16" | "i16" | "f16" | "bf16" => 16,
+        "u64" | "i64" | "f64" | "index" => 64,
+        _ => 32,
+    }
+}
+
+/// A value type as the IR spells it, parsed once so that evaluating an
+/// operation does not compare type names.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataType {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    U64,
+    Index,
+    I32,
+    I64,
+    F16,
+    BF16,
+    F32,
+    F64,
+    C32,
+    C64,
+    Unknown,
+}
+
+impl DataType {
+    pub fn parse(data_type: &str) -> Self {
+        match data_type {
+            "bool" => DataType::Bool,
+            "u8" => DataType::U8,
+            "i8" => DataType::I8,
+            "u16" => DataType::U16,
+            "i16" => DataType::I16,
+            "u32" => DataType::U32,
+            "u64" => DataType::U64,
+            "index" => DataType::Index,
+            "i32" => DataType::I32,
+            "i64" => DataType::I64,
+            "f16" => DataType::F16,
+            "bf16" => DataType::BF16,
+            "f32" => DataType::F32,
+            "f64" => DataType::F64,
+            "complex<f32>" => DataType::C32,
+            "complex<f64>" => DataType::C64,
+            _ => DataType::Unknown,
+        }
+    }
+
+    /// The type as the IR spells it.
+    pub fn name(self) -> &'static str {
+        match self {
+            DataType::Bool => "bool",
+            DataType::U8 => "u8",
+            DataType::I8 => "i8",
+            DataType::U16 => "u16",
+            DataType::I16 => "i16",
+            DataType::U32 => "u32",
+            DataType::U64 => "u64",
+            DataType::Index => "index",
+            DataType::I32 => "i32",
+            DataType::I64 => "i64",
+            DataType::F16 => "f16",
+            DataType::BF16 => "bf16",
+            DataType::F32 => "f32",
+            DataType::F64 => "f64",
+            DataType::C32 => "complex<f32>",
+            DataType::C64 => "complex<f64>",
+            DataType::Unknown => "unknown",
+        }
+    }
+}
+
+/// Whether any operand is X, which makes the result X.
+fn unknown(values: &[VALUE]) -> bool {
+    values.contains(&VALUE::X)
+}
+
+macro_rules! binary_operation {
+    ($func_name: ident, $typed_name: ident, $x: expr) => {
+        pub fn $func_name(ret_type: &str, values: &[VALUE]) -> VALUE {
+            $typed_name(DataType::parse(ret_type), values)
+        }
+
+        pub fn $typed_name(ret_type: DataType, values: &[VALUE]) -> VALUE {
+            if unknown(values) {
+                return VALUE::X;
+            }
+            match ret_type {
+                DataType::U8 => VALUE::U8($x(values[0].as_u8(), values[1].as_u8())),
+                DataType::I8 => VALUE::I8($x(values[0].as_i8(), values[1].as_i8())),
+                DataType::U16 => VALUE::U16($x(values[0].as_u16(), values[1].as_u16())),
+                DataType::I16 => VALUE::I16($x(values[0].as_i16(), values[1].as_i16())),
+                DataType::U32 => VALUE::U32($x(values[0].as_u32(), values[1].as_u32())),
+                DataType::U64 => VALUE::U64($x(values[0].as_u64(), values[1].as_u64())),
+                DataType::Index => VALUE::Index($x(values[0].as_u64(), values[1].as_u64())),
+                DataType::I32 => VALUE::I32($x(values[0].as_i32(), values[1].as_i32())),
+                DataType::I64 => VALUE::I64($x(values[0].as_i64(), values[1].as_i64())),
+                _ => VALUE::ERROR,
+            }
+        }
+    };
+}
+
+macro_rules! binary_operation_fp {
+    ($func_name: ident, $typed_name: ident, $x: expr) => {
+        pub fn $func_name(ret_type: &str, values: &[VALUE]) -> VALUE {
+            $typed_name(DataType::parse(ret_type), values)
+        }
+
+        pub fn $typed_name(ret_type: DataType, values: &[VALUE]) -> VALUE {
+            if unknown(values) {
+                return VALUE::X;
+            }
+            match ret_type {
+                DataType::U8 => VALUE::U8($x(values[0].as_u8(), values[1].as_u8())),
+                DataType::I8 => VALUE::I8($x(values[0].as_i8(), values[1].as_i8())),
+                DataType::U16 => VALUE::U16($x(values[0].as_u16(), values[1].as_u16())),
+                DataType::I16 => VALUE::I16($x(values[0].as_i16(), values[1].as_i16())),
+                DataType::U32 => VALUE::U32($x(values[0].as_u32(), values[1].as_u32())),
+                DataType::U64 => VALUE::U64($x(values[0].as_u64(), values[1].as_u64())),
+                DataType::Index => VALUE::Index($x(values[0].as_u64(), values[1].as_u64())),
+                DataType::I32 => VALUE::I32($x(values[0].as_i32(), values[1].as_i32())),
+                DataType::I64 => VALUE::I64($x(values[0].as_i64(), values[1].as_i64())),
+                // f32 carries enough bits that rounding its result once more
+                // gives the correctly rounded half-precision result.
+                DataType::F16 => {
+                    VALUE::F16(f16::from_f32($x(values[0].as_f32(), values[1].as_f32())))
+                }
+                DataType::BF16 => {
+                    VALUE::BF16(bf16::from_f32($x(values[0].as_f32(), values[1].as_f32())))
+                }
+                DataType::F32 => VALUE::F32($x(values[0].as_f32(), values[1].as_f32())),
+                DataType::F64 => VALUE::F64($x(values[0].as_f64(), values[1].as_f64())),
+                _ => VALUE::ERROR,
+            }
+        }
+    };
+}
+
+macro_rules! boolean_binary_operation {
+    ($func_name: ident, $typed_name: ident, $x: expr) => {
+        pub fn $func_name(ret_type: &str, values: &[VALUE]) -> VALUE {
+            $typed_name(DataType::parse(ret_type), values)
+        }
+
+        pub fn $typed_name(ret_type: DataType, values: &[VALUE]) -> VALUE {
+            if unknown(values) {
+                return VALUE::X;
+            }
+            match ret_type {
+                DataType::U8 => VALUE::U8($x(values[0].as_u8(), values[1].as_u8())),
+                DataType::I8 => VALUE::I8($x(values[0].as_i8(), values[1].as_i8())),
+                DataType::U16 => VALUE::U16($x(values[0].as_u16(), values[1].as_u16())),
+                DataType::I16 => VALUE::I16($x(values[0].as_i16(), values[1].as_i16())),
+                DataType::U32 => VALUE::U32($x(values[0].as_u32(), values[1].as_u32())),
+                DataType::U64 => VALUE::U64($x(values[0].as_u64(), values[1].as_u64())),
+                DataType::Index => VALUE::Index($x(values[0].as_u64(), values[1].as_u64())),
+                DataType::I32 => VALUE::I32($x(values[0].as_i32(), values[1].as_i32())),
+                DataType::I64 => VALUE::I64($x(values[0].as_i64(), values[1].as_i64())),
+                DataType::Bool => VALUE::Bool($x(values[0].as_bool(), values[1].as_bool())),
+                _ => VALUE::ERROR,
+            }
+        }
+    };
+}
+
+/// Whether an integer value is signed, and its width.
+fn integer_kind(value: VALUE) -> Option<(bool, usize)> {
+    Some(match value {
+        VALUE::U8(_) => (false, 8),
+        VALUE::I8(_) => (true, 8),
+        VALUE::U16(_) => (false, 16),
+        VALUE::I16(_) => (true, 16),
+        VALUE::U32(_) => (false, 32),
+        VALUE::I32(_) => (true, 32),
+        VALUE::U64(_) => (false, 64),
+        VALUE::Index(_) => (false, 64),
+        VALUE::I64(_) => (true, 64),
+        _ => return None,
+    })
+}
+
+/// The type two operands are compared as: the wider of two integers of the
+/// same signedness, or the float type both have.
+pub fn merge_type(x: VALUE, y: VALUE) -> String {
+    let str = match (x, y) {
+        (VALUE::F16(_), VALUE::F16(_)) => "f16",
+        (VALUE::BF16(_), VALUE::BF16(_)) => "bf16",
+        (VALUE::F32(_), VALUE::F32(_)) => "f32",
+        (VALUE::F64(_), VALUE::F64(_)) => "f64",
+        _ => match (integer_kind(x), integer_kind(y)) {
+            (Some((signed, width)), Some((other, other_width))) if signed == other => {
+                let sign = if signed { "i" } else { "u" };
+                return format!("{}{}", sign, width.max(other_width));
+            }
+            _ => "error",
+        },
+    };
+    String::from(str)
+}
+
+macro_rules! boolean_operation {
+    ($func_name: ident, $x: expr) => {
+        pub fn $func_name(values: &[VALUE]) -> VALUE {
+            if unknown(values) {
+                return VALUE::X;
+            }
+            let operand_type = merge_type(values[0], values[1]);
+            match &operand_type as &str {
+                "u8" => VALUE::Bool($x(values[0].as_u8(), values[1].as_u8())),
+                "i8" => VALUE::Bool($x(values[0].as_i8(), values[1].as_i8())),
+                "u16" => VALUE::Bool($x(values[0].as_u16(), values[1].as_u16())),
+                "i16" => VALUE::Bool($x(values[0].as_i16(), values[1].as_i16())),
+                "u32" => VALUE::Bool($x(values[0].as_u32(), values[1].as_u32())),
+                "u64" => VALUE::Bool($x(values[0].as_u64(), values[1].as_u64())),
+                "i32" => VALUE::Bool($x(values[0].as_i32(), values[1].as_i32())),
+                "i64" => VALUE::Bool($x(values[0].as_i64(), values[1].as_i64())),
+                "bool" => VALUE::Bool($x(values[0].as_bool(), values[1].as_bool())),
+                "f16" | "bf16" | "f32" => VALUE::Bool($x(values[0].as_f32(), values[1].as_f32())),
+                "f64" => VALUE::Bool($x(values[0].as_f64(), values[1].as_f64())),
+                _ => VALUE::ERROR,
+            }
+        }
+    };
+}
+
+/// Shifts, rotations and unsigned arithmetic of an integer that read its
+/// bits the same whatever its signedness, with an operand of the same type.
+trait Bits: Sized {
+    /// Shift left filling with zeros, zero past the width
+    fn shift_left_by(self, by: Self) -> Self;
+    /// Shift right as the type does, copying the sign bit of a signed value
+    /// and filling an unsigned one with zeros, and past the width leaving
+    /// what shifting a bit at a time would
+    fn shift_right_by(self, by: Self) -> Self;
+    /// Shift right copying the sign bit, all sign bits past the width
+    fn shift_arithmetic(self, by: Self) -> Self;
+    /// Shift right filling with zeros, zero past the width
+    fn shift_logical(self, by: Self) -> Self;
+    fn rotate_left_by(self, by: Self) -> Self;
+    fn rotate_right_by(self, by: Self) -> Self;
+    /// Divide as unsigned, by a divisor that is not zero
+    fn divide_unsigned(self, by: Self) -> Self;
+    /// The remainder as unsigned, by a divisor that is not zero
+    fn remainder_unsigned(self, by: Self) -> Self;
+    fn min_unsigned(self, other: Self) -> Self;
+    fn max_unsigned(self, other: Self) -> Self;
+}
+
+macro_rules! bits {
+    ($int: ty, $signed: ty, $unsigned: ty) => {
+        impl Bits for $int {
+            fn shift_left_by(self, by: Self) -> Self {
+                let by = u32::try_from(by).ok();
+                by.and_then(|by| self.checked_shl(by)).unwrap_or(0)
+            }
+
+            fn shift_right_by(self, by: Self) -> Self {
+                let by = u32::try_from(by).ok();
+                // Shifting by one less than the width and then by one more
+                // leaves the sign bits, or zero when unsigned
+                by.and_then(|by| self.checked_shr(by))
+                    .unwrap_or(self >> (<$int>::BITS - 1) >> 1)
+            }
+
+            fn shift_arithmetic(self, by: Self) -> Self {
+                let x = self as $signed;
+                let by = u32::try_from(by).ok();
+                by.and_then(|by| x.checked_shr(by))
+                    .unwrap_or(if x < 0 { -1 } else { 0 }) as $int
+            }
+
+            fn shift_logical(self, by: Self) -> Self {
+                let x = self as $unsigned;
+                let by = u32::try_from(by).ok();
+                by.and_then(|by| x.checked_shr(by)).unwrap_or(0) as $int
+            }
+
+            // The widths divide 2^32, so truncating the amount keeps it the
+            // same modulo the width.
+            fn rotate_left_by(self, by: Self) -> Self {
+                self.rotate_left(by as u32)
+            }
+
+            fn rotate_right_by(self, by: Self) -> Self {
+                self.rotate_right(by as u32)
+            }
+
+            fn divide_unsigned(self, by: Self) -> Self {
+                (self as $unsigned / by as $unsigned) as $int
+            }
+
+            fn remainder_unsigned(self, by: Self) -> Self {
+                (self as $unsigned % by as $unsigned) as $int
+            }
+
+            fn min_unsigned(self, other: Self) -> Self {
+                (self as $unsigned).min(other as $unsigned) as $int
+            }
+
+            fn max_unsigned(self, other: Self) -> Self {
+                (self as $unsigned).max(other as $unsigned) as $int
+            }
+        }
+    };
+}
+
+bits!(u8, i8, u8);
+bits!(i8, i8, u8);
+bits!(u16, i16, u16);
+bits!(i16, i16, u16);
+bits!(u32, i32, u32);
+bits!(i32, i32, u32);
+bits!(u64, i64, u64);
+bits!(i64, i64, u64);
+
+/// Arithmetic that wraps integers around their width, in debug builds as in
+/// release ones, and leaves floats to IEEE rules.
+trait Arithmetic: Sized {
+    fn plus(self, other: Self) -> Self;
+    fn minus(self, other: Self) -> Self;
+    fn times(self, other: Self) -> Self;
+    /// Divide by a divisor that is not zero, the minimum by -1 wrapping to
+    /// the minimum
+    fn divided(self, by: Self) -> Self;
+    /// The remainder by a divisor that is not zero, 0 for the minimum by -1
+    fn remainder(self, by: Self) -> Self;
+}
+
+macro_rules! integer_arithmetic {
+    ($($int: ty),*) => {
+        $(impl Arithmetic for $int {
+            fn plus(self, other: Self) -> Self {
+                self.wrapping_add(other)
+            }
+
+            fn minus(self, other: Self) -> Self {
+                self.wrapping_sub(other)
+            }
+
+            fn times(self, other: Self) -> Self {
+                self.wrapping_mul(other)
+            }
+
+            fn divided(self, by: Self) -> Self {
+                self.wrapping_div(by)
+            }
+
+            fn remainder(self, by: Self) -> Self {
+                self.wrapping_rem(by)
+            }
+        })*
+    };
+}
+
+macro_rules! float_arithmetic {
+    ($($float: ty),*) => {
+        $(impl Arithmetic for $float {
+            fn plus(self, other: Self) -> Self {
+                self + other
+            }
+
+            fn minus(self, other: Self) -> Self {
+                self - other
+            }
+
+            fn times(self, other: Self) -> Self {
+                self * other
+            }
+
+            fn divided(self, by: Self) -> Self {
+                self / by
+            }
+
+            fn remainder(self, by: Self) -> Self {
+                self % by
+            }
+        })*
+    };
+}
+
+integer_arithmetic!(u8, i8, u16, i16, u32, i32, u64, i64);
+float_arithmetic!(f32, f64);
+
+binary_operation!(shift_left, shift_left_as, Bits::shift_left_by);
+binary_operation!(shift_right, shift_right_as, Bits::shift_right_by);
+binary_operation!(shrsi, shrsi_as, Bits::shift_arithmetic);
+binary_operation!(shrui, shrui_as, Bits::shift_logical);
+binary_operation!(rotl, rotl_as, Bits::rotate_left_by);
+binary_operation!(rotr, rotr_as, Bits::rotate_right_by);
+binary_operation!(divsi_nonzero, divsi_nonzero_as, Arithmetic::divided);
+binary_operation!(divui_nonzero, divui_nonzero_as, Bits::divide_unsigned);
+binary_operation!(remui_nonzero, remui_nonzero_as, Bits::remainder_unsigned);
+binary_operation!(minui, minui_as, Bits::min_unsigned);
+binary_operation!(maxui, maxui_as, Bits::max_unsigned);
+binary_operation_fp!(add, add_as, Arithmetic::plus);
+binary_operation_fp!(sub, sub_as, Arithmetic::minus);
+binary_operation_fp!(mul, mul_as, Arithmetic::times);
+binary_operation_fp!(div_nonzero, div_nonzero_as, Arithmetic::divided);
+binary_operation_fp!(rem_nonzero, rem_nonzero_as, Arithmetic::remainder);
+boolean_binary_operation!(and, and_as, |x, y| x & y);
+boolean_binary_operation!(or, or_as, |x, y| x | y);
+boolean_binary_operation!(xor, xor_as, |x, y| x ^ y);
+boolean_operation!(cmp_gte, |x, y| x >= y);
+boolean_operation!(cmp_gt, |x, y| x > y);
+boolean_operation!(cmp_lte, |x, y| x <= y);
+boolean_operation!(cmp_lt, |x, y| x < y);
+boolean_operation!(cmp_eq, |x, y| x == y);
+boolean_operation!(cmp_sle, |x, y| x <= y);
+boolean_operation!(cmp_ne, |x, y| x != y);
+
+/// Two integer operands read as unsigned at the width they are compared at,
+/// None for the others.
+fn unsigned_operands(values: &[VALUE]) -> Option<(u64, u64)> {
+    let operand_type = merge_type(values[0], values[1]);
+    if !operand_type.starts_with(['i', 'u']) {
+        return None;
+    }
+    let mask = low_mask(bit_width(&operand_type));
+    Some((
+        values[0].as_i64() as u64 & mask,
+        values[1].as_i64() as u64 & mask,
+    ))
+}
+
+/// Two float operands widened to f64, None for the others.
+fn float_operands(values: &[VALUE]) -> Option<(f64, f64)> {
+    match &merge_type(values[0], values[1]) as &str {
+        "f16" | "bf16" | "f32" | "f64" => Some((values[0].as_f64(), values[1].as_f64())),
+        _ => None,
+    }
+}
+
+/// A comparison with a `u` predicate, which `cmpi` makes unsigned and `cmpf`
+/// unordered: integers are compared as unsigned, and floats hold when either
+/// is NaN.
+macro_rules! unsigned_operation {
+    ($func_name: ident, $x: expr) => {
+        pub fn $func_name(values: &[VALUE]) -> VALUE {
+            if unknown(values) {
+                return VALUE::X;
+            }
+            if let Some((x, y)) = float_operands(values) {
+                return VALUE::Bool(x.is_nan() || y.is_nan() || $x(x, y));
+            }
+            match unsigned_operands(values) {
+                Some((x, y)) => VALUE::Bool($x(x, y)),
+                None => VALUE::ERROR,
+            }
+        }
+    };
+}
+
+/// A `cmpf` predicate on whether the operands are ordered, that is neither is
+/// NaN.
+macro_rules! ordered_operation {
+    ($func_name: ident, $x: expr) => {
+        pub fn $func_name(values: &[VALUE]) -> VALUE {
+            if unknown(values) {
+                return VALUE::X;
+            }
+            match float_operands(values) {
+                Some((x, y)) => VALUE::Bool($x(!x.is_nan() && !y.is_nan())),
+                None => VALUE::ERROR,
+            }
+        }
+    };
+}
+
+unsigned_operation!(cmp_uge, |x, y| x >= y);
+unsigned_operation!(cmp_ugt, |x, y| x > y);
+unsigned_operation!(cmp_ule, |x, y| x <= y);
+unsigned_operation!(cmp_ult, |x, y| x < y);
+unsigned_operation!(cmp_ueq, |x, y| x == y);
+ordered_operation!(cmp_ord, |ordered| ordered);
+ordered_operation!(cmp_uno, |ordered: bool| !ordered);
+ordered_operation!(cmp_false, |_| false);
+ordered_operation!(cmp_true, |_| true);
+
+/// Ordered and not equal, false when either operand is NaN unlike `cmp_ne`.
+pub fn cmp_one(values: &[VALUE]) -> VALUE {
+    match cmp_uno(values) {
+        VALUE::Bool(true) => VALUE::Bool(false),
+        _ => cmp_ne(values),
+    }
+}
+
+/// The range of an integer type, None for the others.
+fn integer_range(data_type: DataType) -> Option<(i128, i128)> {
+    Some(match data_type {
+        DataType::U8 => (0, u8::MAX as i128),
+        DataType::I8 => (i8::MIN as i128, i8::MAX as i128),
+        DataType::U16 => (0, u16::MAX as i128),
+        DataType::I16 => (i16::MIN as i128, i16::MAX as i128),
+        DataType::U32 => (0, u32::MAX as i128),
+        DataType::I32 => (i32::MIN as i128, i32::MAX as i128),
+        DataType::U64 => (0, u64::MAX as i128),
+        DataType::Index => (0, u64::MAX as i128),
+        DataType::I64 => (i64::MIN as i128, i64::MAX as i128),
+        _ => return None,
+    })
+}
+
+/// An operand as the integer arithmetic on `data_type` reads it.
+fn exact(data_type: DataType, value: VALUE) -> i128 {
+    match data_type {
+        DataType::U8 => value.as_u8() as i128,
+        DataType::I8 => value.as_i8() as i128,
+        DataType::U16 => value.as_u16() as i128,
+        DataType::I16 => value.as_i16() as i128,
+        DataType::U32 => value.as_u32() as i128,
+        DataType::I32 => value.as_i32() as i128,
+        DataType::U64 => value.as_u64() as i128,
+        DataType::Index => value.as_u64() as i128,
+        _ => value.as_i64() as i128,
+    }
+}
+
+/// Whether an integer `add`, `sub` or `mul` of `values` has a result out of
+/// the range of `ret_type`, which the arithmetic above wraps silently.
+pub fn wraps(op_type: &str, ret_type: DataType, values: &[VALUE]) -> bool {
+    let Some((min, max)) = integer_range(ret_type) else {
+        return false;
+    };
+    if unknown(values) {
+        return false;
+    }
+    let x = exact(ret_type, values[0]);
+    let y = exact(ret_type, values[1]);
+    let result = match op_type {
+        "add" => x.checked_add(y),
+        "sub" => x.checked_sub(y),
+        "mul" => x.checked_mul(y),
+        _ => return false,
+    };
+    !matches!(result, Some(result) if (min..=max).contains(&result))
+}
+
+/// The value of an integer division or remainder of `values` by zero, as the
+/// `div_zero` option asks, or None if the divisor is not an integer zero.
+fn divided_by_zero(ret_type: DataType, values: &[VALUE]) -> Option<VALUE> {
+    integer_range(ret_type)?;
+    if unknown(values) || exact(ret_type, values[1]) != 0 {
+        return None;
+    }
+    Some(match options().div_zero {
+        DivZero::Trap => {
+            fault(division_by_zero(values[0]));
+            VALUE::ERROR
+        }
+        DivZero::Error => VALUE::ERROR,
+        DivZero::Ones => match ret_type {
+            DataType::U8 => VALUE::U8(u8::MAX),
+            DataType::I8 => VALUE::I8(-1),
+            DataType::U16 => VALUE::U16(u16::MAX),
+            DataType::I16 => VALUE::I16(-1),
+            DataType::U32 => VALUE::U32(u32::MAX),
+            DataType::I32 => VALUE::I32(-1),
+            DataType::U64 => VALUE::U64(u64::MAX),
+            DataType::Index => VALUE::Index(u64::MAX),
+            _ => VALUE::I64(-1),
+        },
+    })
+}
+
+macro_rules! division {
+    ($func_name: ident, $typed_name: ident, $nonzero: ident) => {
+        pub fn $func_name(ret_type: &str, values: &[VALUE]) -> VALUE {
+            $typed_name(DataType::parse(ret_type), values)
+        }
+
+        pub fn $typed_name(ret_type: DataType, values: &[VALUE]) -> VALUE {
+            divided_by_zero(ret_type, values).unwrap_or_else(|| $nonzero(ret_type, values))
+        }
+    };
+}
+
+division!(div, div_as, div_nonzero_as);
+division!(divsi, divsi_as, divsi_nonzero_as);
+division!(divui, divui_as, divui_nonzero_as);
+division!(rem, rem_as, rem_nonzero_as);
+division!(remui, remui_as, remui_nonzero_as);
+
+/// The low `width` bits set.
+fn low_mask(width: usize) -> u64 {
+    u64::MAX.checked_shr(64 - width as u32).unwrap_or(0)
+}
+
+/// `bits` shifted by an amount that may reach past 64 bits.
+fn shifted(bits: u64, by: u64, left: bool) -> u64 {
+    let by = u32::try_from(by).ok();
+    let shift = if left {
+        u64::checked_shl
+    } else {
+        u64::checked_shr
+    };
+    by.and_then(|by| shift(bits, by)).unwrap_or(0)
+}
+
+/// The bit patterns of the operands of a bit manipulation, None if one has
+/// none.
+fn operand_bits(values: &[VALUE]) -> Option<Vec<u64>> {
+    values.iter().map(VALUE::to_bits).collect()
+}
+
+/// Apply a bit manipulation to the bit patterns of `values` and the widths
+/// of their types, giving the bits of a `ret_type` value.
+fn bit_manipulation(
+    ret_type: DataType,
+    values: &[VALUE],
+    f: impl FnOnce(&[u64], &[usize]) -> u64,
+) -> VALUE {
+    if unknown(values) {
+        return VALUE::X;
+    }
+    let Some(bits) = operand_bits(values) else {
+        return VALUE::ERROR;
+    };
+    let widths = values
+        .iter()
+        .map(|value| bit_width(&value.get_type()))
+        .collect::<Vec<_>>();
+    VALUE::from_bits(ret_type.name(), f(&bits, &widths))
+}
+
+/// The number of bits of the operand set.
+pub fn ctpop_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    bit_manipulation(ret_type, values, |bits, _| bits[0].count_ones() as u64)
+}
+
+/// The number of bits of the operand clear above its highest set bit, within
+/// the width of its type.
+pub fn ctlz_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    bit_manipulation(ret_type, values, |bits, widths| {
+        (bits[0].leading_zeros() as usize - (64 - widths[0])) as u64
+    })
+}
+
+/// The bits of the first operand from the position of the second up, as
+/// many as `ret_type` holds.
+pub fn extract_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    bit_manipulation(ret_type, values, |bits, _| shifted(bits[0], bits[1], false))
+}
 
-    pub fn as_bool(&self) -> bool {
-        match &self {
-            VALUE::U32(x) => *x != 0,
-            VALUE::I32(x) => *x != 0,
-            VALUE::U64(x) => *x != 0,
-            VALUE::I64(x) => *x != 0,
-            VALUE::Bool(x) => *x,
-            VALUE::ERROR => false,
-            _ => panic!(),
-        }
-    }
+/// The first operand with the bits from the position of the third up
+/// replaced by the second, as many as its type holds.
+pub fn insert_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    bit_manipulation(ret_type, values, |bits, widths| {
+        let mask = shifted(low_mask(widths[1]), bits[2], true);
+        bits[0] & !mask | shifted(bits[1], bits[2], true) & mask
+    })
+}
 
-    pub fn as_f32(&self) -> f32 {
-        match &self {
-            VALUE::F32(x) => *x,
-            VALUE::F64(x) => *x as f32,
-            VALUE::U32(x) => *x as f32,
-            VALUE::I32(x) => *x as f32,
-            VALUE::U64(x) => *x as f32,
-            VALUE::I64(x) => *x as f32,
-            _ => 0.0,
-        }
-    }
+/// The bits of the first operand above those of the second.
+pub fn concat_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    bit_manipulation(ret_type, values, |bits, widths| {
+        shifted(bits[0], widths[1] as u64, true) | bits[1]
+    })
+}
 
-    pub fn as_f64(&self) -> f64 {
-        match &self {
-            VALUE::F32(x) => *x as f64,
-            VALUE::F64(x) => *x,
-            VALUE::U32(x) => *x as f64,
-            VALUE::I32(x) => *x as f64,
-            VALUE::U64(x) => *x as f64,
-            VALUE::I64(x) => *x as f64,
-            _ => 0.0,
-        }
+pub fn not(values: &[VALUE]) -> VALUE {
+    if unknown(values) {
+        return VALUE::X;
     }
+    VALUE::Bool(!(values[0].as_bool()))
 }
 
-macro_rules! binary_operation {
-    ($func_name: ident, $x: expr) => {
-        pub fn $func_name(ret_type: &String, values: &Vec<VALUE>) -> VALUE {
-            match &ret_type as &str {
-                "u32" => VALUE::U32($x(values[0].as_u32(), values[1].as_u32())),
-                "u64" => VALUE::U64($x(values[0].as_u64(), values[1].as_u64())),
-                "i32" => VALUE::I32($x(values[0].as_i32(), values[1].as_i32())),
-                "i64" => VALUE::I64($x(values[0].as_i64(), values[1].as_i64())),
-                _ => VALUE::ERROR,
-            }
+/// The error function, to within a few ulps: a series of positive terms
+/// near zero, where the alternating Taylor series would cancel, and a
+/// continued fraction of its complement further out.
+fn erf_f64(x: f64) -> f64 {
+    if x.is_nan() {
+        return x;
+    }
+    let a = x.abs();
+    let erf = if a < 3.0 {
+        let ratio = 2.0 * a * a;
+        let (mut term, mut sum, mut n) = (a, a, 0.0);
+        while term > sum * f64::EPSILON / 4.0 {
+            n += 1.0;
+            term *= ratio / (2.0 * n + 1.0);
+            sum += term;
+        }
+        FRAC_2_SQRT_PI * (-a * a).exp() * sum
+    } else if a < 6.0 {
+        let mut fraction = a;
+        for n in (1..=60).rev() {
+            fraction = a + n as f64 / 2.0 / fraction;
         }
+        1.0 - FRAC_2_SQRT_PI / 2.0 * (-a * a).exp() / fraction
+    } else {
+        1.0
     };
+    erf.copysign(x)
 }
 
-macro_rules! binary_operation_fp {
+/// A function of one float operand, evaluated in f64 and rounded to the type
+/// of the operand.
+macro_rules! float_function {
     ($func_name: ident, $x: expr) => {
-        pub fn $func_name(ret_type: &String, values: &Vec<VALUE>) -> VALUE {
-            match &ret_type as &str {
-                "u32" => VALUE::U32($x(values[0].as_u32(), values[1].as_u32())),
-                "u64" => VALUE::U64($x(values[0].as_u64(), values[1].as_u64())),
-                "i32" => VALUE::I32($x(values[0].as_i32(), values[1].as_i32())),
-                "i64" => VALUE::I64($x(values[0].as_i64(), values[1].as_i64())),
-                "f32" => VALUE::F32($x(values[0].as_f32(), values[1].as_f32())),
-                "f64" => VALUE::F64($x(values[0].as_f64(), values[1].as_f64())),
+        pub fn $func_name(values: &[VALUE]) -> VALUE {
+            if unknown(values) {
+                return VALUE::X;
+            }
+            let x = values[0].as_f64();
+            match &values[0].get_type() as &str {
+                "f16" => VALUE::F16(f16::from_f64($x(x))),
+                "bf16" => VALUE::BF16(bf16::from_f64($x(x))),
+                "f32" => VALUE::F32($x(x) as f32),
+                "f64" => VALUE::F64($x(x)),
                 _ => VALUE::ERROR,
             }
         }
     };
 }
 
-macro_rules! boolean_binary_operation {
-    ($func_name: ident, $x: expr) => {
-        pub fn $func_name(ret_type: &String, values: &Vec<VALUE>) -> VALUE {
-            match &ret_type as &str {
-                "u32" => VALUE::U32($x(values[0].as_u32(), values[1].as_u32())),
-                "u64" => VALUE::U64($x(values[0].as_u64(), values[1].as_u64())),
-                "i32" => VALUE::I32($x(values[0].as_i32(), values[1].as_i32())),
-                "i64" => VALUE::I64($x(values[0].as_i64(), values[1].as_i64())),
-                "bool" => VALUE::Bool($x(values[0].as_bool(), values[1].as_bool())),
-                _ => VALUE::ERROR,
-            }
-        }
-    };
+float_function!(exp, f64::exp);
+float_function!(erf, erf_f64);
+float_function!(sqrt, f64::sqrt);
+float_function!(log, f64::ln);
+float_function!(sin, f64::sin);
+float_function!(cos, f64::cos);
+float_function!(tanh, f64::tanh);
+
+binary_operation_fp!(min, min_as, |x, y| if y < x { y } else { x });
+binary_operation_fp!(max, max_as, |x, y| if y > x { y } else { x });
+
+/// The magnitude of the operand, wrapping the most negative integer to
+/// itself as hardware does.
+pub fn abs_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    if unknown(values) {
+        return VALUE::X;
+    }
+    let x = values[0];
+    match ret_type {
+        DataType::U8 => VALUE::U8(x.as_u8()),
+        DataType::I8 => VALUE::I8(x.as_i8().wrapping_abs()),
+        DataType::U16 => VALUE::U16(x.as_u16()),
+        DataType::I16 => VALUE::I16(x.as_i16().wrapping_abs()),
+        DataType::U32 => VALUE::U32(x.as_u32()),
+        DataType::I32 => VALUE::I32(x.as_i32().wrapping_abs()),
+        DataType::U64 => VALUE::U64(x.as_u64()),
+        DataType::Index => VALUE::Index(x.as_u64()),
+        DataType::I64 => VALUE::I64(x.as_i64().wrapping_abs()),
+        DataType::F16 => VALUE::F16(f16::from_f32(x.as_f32().abs())),
+        DataType::BF16 => VALUE::BF16(bf16::from_f32(x.as_f32().abs())),
+        DataType::F32 => VALUE::F32(x.as_f32().abs()),
+        DataType::F64 => VALUE::F64(x.as_f64().abs()),
+        _ => VALUE::ERROR,
+    }
 }
 
-pub fn merge_type(x: VALUE, y: VALUE) -> String {
-    let str = match x {
-        VALUE::U32(_) => match y {
-            VALUE::U32(_) => "u32",
-            VALUE::U64(_) => "u64",
-            _ => "error",
-        },
-        VALUE::U64(_) => match y {
-            VALUE::U32(_) | VALUE::U64(_) => "u64",
-            _ => "error",
-        },
-        VALUE::I32(_) => match y {
-            VALUE::I32(_) => "i32",
-            VALUE::I64(_) => "i64",
-            _ => "error",
-        },
-        VALUE::I64(_) => match y {
-            VALUE::I32(_) | VALUE::I64(_) => "i64",
-            _ => "error",
-        },
-        VALUE::F32(_) => match y {
-            VALUE::F32(_) => "f32",
-            _ => "error",
-        },
-        VALUE::F64(_) => match y {
-            VALUE::F64(_) => "f64",
-            _ => "error",
-        },
-        _ => "error",
-    };
-    String::from(str)
+pub fn powf(values: &[VALUE]) -> VALUE {
+    if unknown(values) {
+        return VALUE::X;
+    }
+    match &values[0].get_type() as &str {
+        "f16" => VALUE::F16(f16::from_f32(f32::powf(
+            values[0].as_f32(),
+            values[1].as_f32(),
+        ))),
+        "bf16" => VALUE::BF16(bf16::from_f32(f32::powf(
+            values[0].as_f32(),
+            values[1].as_f32(),
+        ))),
+        "f32" => VALUE::F32(f32::powf(values[0].as_f32(), values[1].as_f32())),
+        "f64" => VALUE::F64(f64::powf(values[0].as_f64(), values[1].as_f64())),
+        _ => VALUE::ERROR,
+    }
 }
 
-macro_rules! boolean_operation {
+/// A complex number of the type `ret_type` from its real and imaginary
+/// parts.
+pub fn complex_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    if unknown(values) {
+        return VALUE::X;
+    }
+    let (re, im) = (values[0], values[1]);
+    match ret_type {
+        DataType::C32 => VALUE::C32(Complex {
+            re: re.as_f32(),
+            im: im.as_f32(),
+        }),
+        DataType::C64 => VALUE::C64(Complex {
+            re: re.as_f64(),
+            im: im.as_f64(),
+        }),
+        _ => VALUE::ERROR,
+    }
+}
+
+macro_rules! complex_operation {
     ($func_name: ident, $x: expr) => {
-        pub fn $func_name(values: &Vec<VALUE>) -> VALUE {
-            let operand_type = merge_type(values[0], values[1]);
-            match &operand_type as &str {
-                "u32" => VALUE::Bool($x(values[0].as_u32(), values[1].as_u32())),
-                "u64" => VALUE::Bool($x(values[0].as_u64(), values[1].as_u64())),
-                "i32" => VALUE::Bool($x(values[0].as_i32(), values[1].as_i32())),
-                "i64" => VALUE::Bool($x(values[0].as_i64(), values[1].as_i64())),
-                "bool" => VALUE::Bool($x(values[0].as_bool(), values[1].as_bool())),
-                "f32" => VALUE::Bool($x(values[0].as_f32(), values[1].as_f32())),
-                "f64" => VALUE::Bool($x(values[0].as_f64(), values[1].as_f64())),
+        pub fn $func_name(values: &[VALUE]) -> VALUE {
+            match values[..2] {
+                [VALUE::X, _] | [_, VALUE::X] => VALUE::X,
+                [VALUE::C32(x), VALUE::C32(y)] => VALUE::C32($x(x, y)),
+                [VALUE::C64(x), VALUE::C64(y)] => VALUE::C64($x(x, y)),
                 _ => VALUE::ERROR,
             }
         }
     };
 }
 
-binary_operation!(shift_left, |x, y| x << y);
-binary_operation!(shift_right, |x, y| x >> y);
-binary_operation!(divsi, |x, y| x / y);
-binary_operation_fp!(add, |x, y| x + y);
-binary_operation_fp!(sub, |x, y| x - y);
-binary_operation_fp!(mul, |x, y| x * y);
-binary_operation_fp!(div, |x, y| x / y);
-boolean_binary_operation!(and, |x, y| x & y);
-boolean_binary_operation!(or, |x, y| x | y);
-boolean_operation!(cmp_gte, |x, y| x >= y);
-boolean_operation!(cmp_gt, |x, y| x > y);
-boolean_operation!(cmp_lte, |x, y| x <= y);
-boolean_operation!(cmp_lt, |x, y| x < y);
-boolean_operation!(cmp_eq, |x, y| x == y);
-boolean_operation!(cmp_sle, |x, y| x <= y);
-boolean_operation!(cmp_ne, |x, y| x != y);
+complex_operation!(cadd, |x, y| x + y);
+complex_operation!(csub, |x, y| x - y);
+complex_operation!(cmul, |x, y| x * y);
 
-pub fn not(values: &Vec<VALUE>) -> VALUE {
-    VALUE::Bool(!(values[0].as_bool()))
+pub fn conj(values: &[VALUE]) -> VALUE {
+    match values[0] {
+        VALUE::C32(x) => VALUE::C32(x.conj()),
+        VALUE::C64(x) => VALUE::C64(x.conj()),
+        VALUE::X => VALUE::X,
+        _ => VALUE::ERROR,
+    }
 }
 
-pub fn exp(values: &Vec<VALUE>) -> VALUE {
-    match &values[0].get_type() as &str {
-        "f32" => VALUE::F32(f32::powf(2.71828, values[0].as_f32())),
-        "f64" => VALUE::F64(f64::powf(2.71828, values[0].as_f64())),
+/// The real part of a complex number.
+pub fn re(values: &[VALUE]) -> VALUE {
+    match values[0] {
+        VALUE::C32(x) => VALUE::F32(x.re),
+        VALUE::C64(x) => VALUE::F64(x.re),
+        VALUE::X => VALUE::X,
         _ => VALUE::ERROR,
     }
 }
 
-pub fn erf(values: &Vec<VALUE>) -> VALUE {
-    match &values[0].get_type() as &str {
-        "f32" => VALUE::F32(0.0),
-        "f64" => VALUE::F64(0.0),
+/// The imaginary part of a complex number.
+pub fn im(values: &[VALUE]) -> VALUE {
+    match values[0] {
+        VALUE::C32(x) => VALUE::F32(x.im),
+        VALUE::C64(x) => VALUE::F64(x.im),
+        VALUE::X => VALUE::X,
         _ => VALUE::ERROR,
     }
 }
 
-pub fn sqrt(values: &Vec<VALUE>) -> VALUE {
-    match &values[0].get_type() as &str {
-        "f32" => VALUE::F32(f32::sqrt(values[0].as_f32())),
-        "f64" => VALUE::F64(f64::sqrt(values[0].as_f64())),
+/// Lane `values[1]` of the vector `values[0]`.
+pub fn vector_extract(values: &[VALUE]) -> VALUE {
+    match values[..2] {
+        [VALUE::X, _] | [_, VALUE::X] => VALUE::X,
+        [VALUE::Vector(vector), index] => vector.lane(index.as_u64() as usize),
         _ => VALUE::ERROR,
     }
 }
 
-pub fn powf(values: &Vec<VALUE>) -> VALUE {
-    match &values[0].get_type() as &str {
-        "f32" => VALUE::F32(f32::powf(values[0].as_f32(), values[1].as_f32())),
-        "f64" => VALUE::F64(f64::powf(values[0].as_f64(), values[1].as_f64())),
+/// The vector `values[0]` with lane `values[2]` replaced by `values[1]`.
+pub fn vector_insert(values: &[VALUE]) -> VALUE {
+    match values[..3] {
+        [VALUE::X, ..] | [_, _, VALUE::X] => VALUE::X,
+        [VALUE::Vector(vector), value, index] => vector.with_lane(index.as_u64() as usize, value),
         _ => VALUE::ERROR,
     }
 }
 
-pub fn select(values: &Vec<VALUE>) -> VALUE {
-    if values[0].as_bool() {
+pub fn select(values: &[VALUE]) -> VALUE {
+    if values[0] == VALUE::X {
+        VALUE::X
+    } else if values[0].as_bool() {
         values[1]
     } else {
         values[2]
     }
 }
 
+fn sitofp_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    if unknown(values) {
+        return VALUE::X;
+    }
+    match ret_type {
+        DataType::F16 => VALUE::F16(f16::from_f64(values[0].as_f64())),
+        DataType::BF16 => VALUE::BF16(bf16::from_f64(values[0].as_f64())),
+        DataType::F32 => VALUE::F32(values[0].as_f32()),
+        DataType::F64 => VALUE::F64(values[0].as_f64()),
+        _ => VALUE::ERROR,
+    }
+}
+
+/// The operand widened to `ret_type` copying its sign bit.
+pub fn extsi_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    bit_manipulation(ret_type, values, |bits, widths| {
+        let unused = 64 - widths[0] as u32;
+        ((bits[0] << unused) as i64 >> unused) as u64
+    })
+}
+
+/// The operand widened to `ret_type` filling with zeros.
+pub fn extui_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    bit_manipulation(ret_type, values, |bits, _| bits[0])
+}
+
+/// The float operand rounded toward zero to the signed integer of the width
+/// of `ret_type`, saturating at its bounds and giving 0 for NaN.
+pub fn fptosi_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    if unknown(values) {
+        return VALUE::X;
+    }
+    let width = bit_width(ret_type.name()) as u32;
+    let max = (low_mask(width as usize) >> 1) as i64;
+    let value = (values[0].as_f64() as i64).clamp(-max - 1, max);
+    VALUE::from_bits(ret_type.name(), value as u64)
+}
+
+/// The float operand rounded toward zero to the unsigned integer of the
+/// width of `ret_type`, saturating at its bounds and giving 0 for NaN.
+pub fn fptoui_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    if unknown(values) {
+        return VALUE::X;
+    }
+    let max = low_mask(bit_width(ret_type.name()));
+    VALUE::from_bits(ret_type.name(), (values[0].as_f64() as u64).min(max))
+}
+
+pub fn trunc(ret_type: &str, values: &[VALUE]) -> VALUE {
+    trunc_as(DataType::parse(ret_type), values)
+}
+
+/// The low bits of the operand, as many as `ret_type` holds.
+pub fn trunc_as(ret_type: DataType, values: &[VALUE]) -> VALUE {
+    bit_manipulation(ret_type, values, |bits, _| bits[0])
+}
+
+/// `bits` of `width` widened to 64 bits, copying the sign bit if `signed`.
+fn extended(bits: u64, width: usize, signed: bool) -> u64 {
+    let unused = 64 - width as u32;
+    match signed {
+        true => ((bits << unused) as i64 >> unused) as u64,
+        false => bits & low_mask(width),
+    }
+}
+
+/// Whether an `index_cast` (`signed`) or `index_castui` of `value` to
+/// `ret_type` drops bits, so that casting back does not restore it.
+fn cast_loses(ret_type: DataType, value: VALUE, signed: bool) -> bool {
+    let Some(bits) = value.to_bits() else {
+        return false;
+    };
+    let exact = extended(bits, bit_width(&value.get_type()), signed);
+    exact != extended(exact, bit_width(ret_type.name()), signed)
+}
+
+/// Whether an integer `add`, `sub` or `mul` returning an index reads an
+/// integer of another type, which it would silently take as an index.
+fn mixes_index(ret_type: DataType, values: &[VALUE]) -> Option<VALUE> {
+    if ret_type != DataType::Index {
+        return None;
+    }
+    values
+        .iter()
+        .find(|value| integer_kind(**value).is_some() && !matches!(value, VALUE::Index(_)))
+        .copied()
+}
+
 use std::collections::VecDeque;
 
 #[derive(Debug, Clone)]
@@ -525,6 +2435,13 @@ impl ValueTiming {
         }
     }
 
+    /// Hold X, as a register does before it is first written.
+    pub fn set_unknown(&mut self) {
+        for val in self.queue.iter_mut() {
+            *val = VALUE::X;
+        }
+    }
+
     pub fn has_value(&self) -> bool {
         for val in &self.queue {
             if *val != VALUE::ERROR {
@@ -533,9 +2450,29 @@ impl ValueTiming {
         }
         false
     }
-}
 
-use serde_json::Value;
+    /// The value written in this cycle followed by the ones in flight.
+    pub fn to_state(&self) -> Value {
+        let mut values = vec![value_to_state(&self.new_value)];
+        values.extend(self.queue.iter().map(value_to_state));
+        Value::Array(values)
+    }
+
+    pub fn restore_state(&mut self, state: &Value) -> Option<()> {
+        let values = state
+            .as_array()?
+            .iter()
+            .map(value_from_state)
+            .collect::<Option<Vec<VALUE>>>()?;
+        let (new_value, queue) = values.split_first()?;
+        if queue.len() != self.depth {
+            return None;
+        }
+        self.new_value = *new_value;
+        self.queue = queue.iter().copied().collect();
+        Some(())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Constant {
@@ -545,87 +2482,513 @@ pub struct Constant {
 }
 
 impl Constant {
-    pub fn new(ir: &Value) -> Self {
-        Constant {
-            name: String::from(ir["name"].as_str().unwrap()),
-            value: String::from(ir["operands"].as_str().unwrap()),
-            ret_type: String::from(ir["type"].as_str().unwrap()),
+    pub fn new(ir: &Value) -> Result<Self, HestiaError> {
+        let constant = Constant {
+            name: str_field(ir, "name")?.to_string(),
+            value: str_field(ir, "operands")?.to_string(),
+            ret_type: str_field(ir, "type")?.to_string(),
+        };
+        if parse_value(&constant.ret_type, &constant.value).is_none() {
+            return Err(HestiaError::new(
+                ErrorCode::InvalidConstant,
+                format!(
+                    "constant '{}' has invalid {} literal '{}'",
+                    constant.name, constant.ret_type, constant.value
+                ),
+            ));
+        }
+        Ok(constant)
+    }
+}
+
+/// An operand an operation reads: a variable, or a constant of the IR bound
+/// to its value when the operation is loaded, so that reading it does not
+/// look the constant up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Var(Name),
+    Const(Name, VALUE),
+}
+
+impl Operand {
+    pub fn new(name: &str) -> Self {
+        Operand::Var(Name::new(name))
+    }
+
+    pub fn list(ir: &Value) -> Vec<Operand> {
+        Name::list(ir).into_iter().map(Operand::Var).collect()
+    }
+
+    pub fn name(&self) -> &Name {
+        match self {
+            Operand::Var(name) | Operand::Const(name, _) => name,
+        }
+    }
+
+    /// Bind the operand to the value of the constant it names, if any.
+    pub fn bind(&mut self, constants: &Slots<VALUE>) {
+        let name = *self.name();
+        if let Some(value) = constants.get(name) {
+            *self = Operand::Const(name, *value);
         }
     }
 }
 
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Computation {
-    pub operands: Vec<String>,
+    pub operands: Vec<Operand>,
     pub op_type: String,
-    pub name: String,
+    pub name: Name,
     pub ret_type: String,
+    eval: Eval,
+    /// Whether it is an integer `add`, `sub` or `mul`, which the `overflow`
+    /// option checks
+    arithmetic: bool,
+    /// Whether it is an `index_cast` or `index_castui`, which the `overflow`
+    /// option checks for bits dropped
+    cast: Option<bool>,
+    /// The lane type and count of a vector return type, whose lanes are
+    /// computed one at a time on those of the operands
+    lanes: Option<(DataType, usize)>,
+}
+
+/// An operation on operand values giving a value of the type passed.
+pub type TypedFunction = fn(DataType, &[VALUE]) -> VALUE;
+
+/// How a computation is evaluated, resolved from its `op_type` and return
+/// type when it is loaded so that evaluating it is a direct call.
+#[derive(Debug, Clone, Copy)]
+enum Eval {
+    /// The literal of a constant
+    Constant(VALUE),
+    /// The value of the only operand, as for `broadcast`
+    Copy,
+    /// A function of the operand values returning the computation type
+    Typed(TypedFunction, DataType),
+    /// A function of the operand values alone
+    Untyped(fn(&[VALUE]) -> VALUE),
+}
+
+impl Eval {
+    fn new(op_type: &str, ret_type: &str, operands: &[Operand]) -> Self {
+        let data_type = DataType::parse(ret_type);
+        let typed = |function| Eval::Typed(function, data_type);
+        match op_type {
+            "constant" => Eval::Constant(build_value(&ret_type.to_string(), operands[0].name())),
+            "shift_left" => typed(shift_left_as),
+            "shift_right" => typed(shift_right_as),
+            "shrsi" => typed(shrsi_as),
+            "shrui" => typed(shrui_as),
+            "rotl" => typed(rotl_as),
+            "rotr" => typed(rotr_as),
+            "add" => typed(add_as),
+            "sub" => typed(sub_as),
+            "mul" => typed(mul_as),
+            "div" => typed(div_as),
+            "divsi" => typed(divsi_as),
+            "divui" => typed(divui_as),
+            "rem" => typed(rem_as),
+            "remui" => typed(remui_as),
+            "and" => typed(and_as),
+            "or" => typed(or_as),
+            "xor" => typed(xor_as),
+            "ctpop" => typed(ctpop_as),
+            "ctlz" => typed(ctlz_as),
+            "extract" => typed(extract_as),
+            "insert" => typed(insert_as),
+            "concat" => typed(concat_as),
+            "sitofp" => typed(sitofp_as),
+            "cmp_gte" | "cmp_oge" | "cmp_sge" => Eval::Untyped(cmp_gte),
+            "cmp_gt" | "cmp_sgt" | "cmp_ogt" => Eval::Untyped(cmp_gt),
+            "cmp_lte" | "cmp_ole" => Eval::Untyped(cmp_lte),
+            "cmp_lt" | "cmp_slt" | "cmp_olt" => Eval::Untyped(cmp_lt),
+            "cmp_eq" | "cmp_oeq" => Eval::Untyped(cmp_eq),
+            "cmp_sle" => Eval::Untyped(cmp_sle),
+            "cmp_ne" | "cmp_une" => Eval::Untyped(cmp_ne),
+            "cmp_uge" => Eval::Untyped(cmp_uge),
+            "cmp_ugt" => Eval::Untyped(cmp_ugt),
+            "cmp_ule" => Eval::Untyped(cmp_ule),
+            "cmp_ult" => Eval::Untyped(cmp_ult),
+            "cmp_ueq" => Eval::Untyped(cmp_ueq),
+            "cmp_one" => Eval::Untyped(cmp_one),
+            "cmp_ord" => Eval::Untyped(cmp_ord),
+            "cmp_uno" => Eval::Untyped(cmp_uno),
+            "cmp_false" => Eval::Untyped(cmp_false),
+            "cmp_true" => Eval::Untyped(cmp_true),
+            "not" => Eval::Untyped(not),
+            "index_cast" => typed(extsi_as),
+            "index_castui" => typed(extui_as),
+            "select" => Eval::Untyped(select),
+            "exp" => Eval::Untyped(exp),
+            "erf" => Eval::Untyped(erf),
+            "sqrt" => Eval::Untyped(sqrt),
+            "log" => Eval::Untyped(log),
+            "sin" => Eval::Untyped(sin),
+            "cos" => Eval::Untyped(cos),
+            "tanh" => Eval::Untyped(tanh),
+            "abs" => typed(abs_as),
+            "extsi" | "sext" => typed(extsi_as),
+            "extui" | "zext" => typed(extui_as),
+            "fptosi" => typed(fptosi_as),
+            "fptoui" => typed(fptoui_as),
+            "min" => typed(min_as),
+            "max" => typed(max_as),
+            "minui" => typed(minui_as),
+            "maxui" => typed(maxui_as),
+            "broadcast" => Eval::Copy,
+            "complex" => typed(complex_as),
+            "cadd" => Eval::Untyped(cadd),
+            "csub" => Eval::Untyped(csub),
+            "cmul" => Eval::Untyped(cmul),
+            "conj" => Eval::Untyped(conj),
+            "re" => Eval::Untyped(re),
+            "im" => Eval::Untyped(im),
+            "vector_extract" => Eval::Untyped(vector_extract),
+            "vector_insert" => Eval::Untyped(vector_insert),
+            "powf" => Eval::Untyped(powf),
+            "trunc" => typed(trunc_as),
+            _ => unreachable!("{} is not in OPERATIONS", op_type),
+        }
+    }
 }
 
+/// The operations `operation_env` evaluates.
+const OPERATIONS: &[&str] = &[
+    "abs",
+    "add",
+    "and",
+    "broadcast",
+    "cadd",
+    "cmp_eq",
+    "cmp_false",
+    "cmp_gt",
+    "cmp_gte",
+    "cmp_lt",
+    "cmp_lte",
+    "cmp_ne",
+    "cmp_oeq",
+    "cmp_oge",
+    "cmp_ogt",
+    "cmp_ole",
+    "cmp_olt",
+    "cmp_one",
+    "cmp_ord",
+    "cmp_sge",
+    "cmp_sgt",
+    "cmp_sle",
+    "cmp_slt",
+    "cmp_true",
+    "cmp_ueq",
+    "cmp_uge",
+    "cmp_ugt",
+    "cmp_ule",
+    "cmp_ult",
+    "cmp_une",
+    "cmp_uno",
+    "cmul",
+    "complex",
+    "concat",
+    "conj",
+    "constant",
+    "cos",
+    "csub",
+    "ctlz",
+    "ctpop",
+    "div",
+    "divsi",
+    "divui",
+    "erf",
+    "exp",
+    "extract",
+    "extsi",
+    "extui",
+    "fptosi",
+    "fptoui",
+    "im",
+    "index_cast",
+    "index_castui",
+    "insert",
+    "log",
+    "max",
+    "maxui",
+    "min",
+    "minui",
+    "mul",
+    "not",
+    "or",
+    "powf",
+    "re",
+    "rem",
+    "remui",
+    "rotl",
+    "rotr",
+    "select",
+    "sext",
+    "shift_left",
+    "shift_right",
+    "shrsi",
+    "shrui",
+    "sin",
+    "sitofp",
+    "sqrt",
+    "sub",
+    "tanh",
+    "trunc",
+    "vector_extract",
+    "vector_insert",
+    "xor",
+    "zext",
+];
+
 impl Computation {
-    pub fn new(ir: &Value) -> Self {
-        Computation {
-            name: String::from(ir["name"].as_str().unwrap()),
-            op_type: String::from(ir["op_type"].as_str().unwrap()),
-            ret_type: String::from(ir["type"].as_str().unwrap()),
-            operands: construct(&ir["operands"]),
+    pub fn new(ir: &Value) -> Result<Self, HestiaError> {
+        let mut compute = Computation {
+            name: Name::new(str_field(ir, "name")?),
+            op_type: str_field(ir, "op_type")?.to_string(),
+            ret_type: str_field(ir, "type")?.to_string(),
+            operands: strings_field(ir, "operands")?
+                .iter()
+                .map(|operand| Operand::new(operand))
+                .collect(),
+            eval: Eval::Copy,
+            arithmetic: false,
+            cast: None,
+            lanes: None,
+        };
+        if !OPERATIONS.contains(&compute.op_type.as_str()) {
+            return Err(HestiaError::new(
+                ErrorCode::UnknownOperation,
+                format!("{} has unknown type '{}'", describe(ir), compute.op_type),
+            ));
+        }
+        let vector = Vector::parse_type(&compute.ret_type);
+        if compute.ret_type.starts_with("vector<") && vector.is_none() {
+            return Err(HestiaError::new(
+                ErrorCode::UnknownType,
+                format!(
+                    "{} has unsupported vector type {}",
+                    describe(ir),
+                    compute.ret_type
+                ),
+            ));
+        }
+        if compute.op_type != "vector_insert" {
+            compute.lanes = vector;
+        }
+        // An elementwise operation is resolved for its lanes
+        let ret_type = match compute.lanes {
+            Some((elem, _)) => elem.name(),
+            None => compute.ret_type.as_str(),
+        };
+        let supported = match compute.op_type.as_str() {
+            "sitofp" => matches!(ret_type, "f16" | "bf16" | "f32" | "f64"),
+            "complex" => matches!(ret_type, "complex<f32>" | "complex<f64>"),
+            "trunc" | "extsi" | "sext" | "extui" | "zext" | "fptosi" | "fptoui" | "index_cast"
+            | "index_castui" => matches!(
+                ret_type,
+                "bool" | "u8" | "i8" | "u16" | "i16" | "u32" | "i32" | "u64" | "i64" | "index"
+            ),
+            _ => true,
+        };
+        if !supported {
+            return Err(HestiaError::new(
+                ErrorCode::UnknownType,
+                format!("{} converts to unsupported type {}", describe(ir), ret_type),
+            ));
+        }
+        if compute.op_type == "constant" {
+            let literal = compute
+                .operands
+                .first()
+                .map(|literal| literal.name().as_str());
+            if literal
+                .and_then(|literal| parse_value(ret_type, literal))
+                .is_none()
+            {
+                return Err(HestiaError::new(
+                    ErrorCode::InvalidConstant,
+                    format!("{} has no valid {} literal", describe(ir), ret_type),
+                ));
+            }
+        }
+        compute.eval = Eval::new(&compute.op_type, ret_type, &compute.operands);
+        compute.arithmetic = matches!(compute.op_type.as_str(), "add" | "sub" | "mul")
+            && integer_range(DataType::parse(ret_type)).is_some();
+        compute.cast = match compute.op_type.as_str() {
+            "index_cast" => Some(true),
+            "index_castui" => Some(false),
+            _ => None,
+        };
+        Ok(compute)
+    }
+
+    /// The operands that evaluating the computation reads, none for a
+    /// constant, whose operand is its literal.
+    pub fn read_operands(&self) -> &[Operand] {
+        match self.eval {
+            Eval::Constant(_) => &[],
+            Eval::Copy => &self.operands[..1],
+            Eval::Typed(..) | Eval::Untyped(_) => &self.operands,
+        }
+    }
+
+    /// The first operand it reads whose value is ERROR, which the `strict`
+    /// option stops on.
+    pub fn poisoned(&self, values: &[VALUE]) -> Option<&Operand> {
+        zip(self.read_operands(), values)
+            .find(|(_, value)| **value == VALUE::ERROR)
+            .map(|(operand, _)| operand)
+    }
+
+    /// Bind the operands it reads that name constants to their values.
+    pub fn bind(&mut self, constants: &Slots<VALUE>) {
+        let read = self.read_operands().len();
+        for operand in &mut self.operands[..read] {
+            operand.bind(constants);
         }
     }
+
+    /// The value of the computation on the values of its `read_operands`.
+    pub fn evaluate(&self, values: &[VALUE]) -> VALUE {
+        match self.lanes {
+            Some((elem, lanes)) => self.elementwise(elem, lanes, values),
+            None => self.scalar(values),
+        }
+    }
+
+    /// A vector of the computation on each lane of the operands, a scalar
+    /// operand standing for every lane.
+    fn elementwise(&self, elem: DataType, lanes: usize, values: &[VALUE]) -> VALUE {
+        let lane = |index| {
+            let values = values.iter().map(|value| match value {
+                VALUE::Vector(vector) => vector.lane(index),
+                scalar => *scalar,
+            });
+            self.scalar(&values.collect::<Vec<_>>())
+        };
+        Vector::from_lanes(elem, &(0..lanes).map(lane).collect::<Vec<_>>())
+    }
+
+    fn scalar(&self, values: &[VALUE]) -> VALUE {
+        match self.eval {
+            Eval::Constant(value) => value,
+            Eval::Copy => values[0],
+            Eval::Typed(function, ret_type) => {
+                self.check_overflow(ret_type, values);
+                function(ret_type, values)
+            }
+            Eval::Untyped(function) => function(values),
+        }
+    }
+
+    /// Warn of or fault on an integer `add`, `sub` or `mul` of `values` that
+    /// wraps or mixes an index with another integer type, or an index cast
+    /// that drops bits, as the `overflow` option asks.
+    fn check_overflow(&self, ret_type: DataType, values: &[VALUE]) {
+        let mode = options().overflow;
+        if mode == Overflow::Off || unknown(values) {
+            return;
+        }
+        let Some(message) = self.overflow(ret_type, values) else {
+            return;
+        };
+        match mode {
+            Overflow::Warn => println!("!!Overflow: {} at cycle {}", message, current_cycle()),
+            _ => fault(overflow(message)),
+        }
+    }
+
+    fn overflow(&self, ret_type: DataType, values: &[VALUE]) -> Option<String> {
+        if let Some(signed) = self.cast {
+            return cast_loses(ret_type, values[0], signed).then(|| {
+                format!(
+                    "{} {} {} {} to {} drops bits",
+                    self.name,
+                    self.op_type,
+                    values[0].get_type(),
+                    values[0].to_json(),
+                    self.ret_type
+                )
+            });
+        }
+        if !self.arithmetic {
+            return None;
+        }
+        if let Some(value) = mixes_index(ret_type, values) {
+            return Some(format!(
+                "{} {} of index and {} {}",
+                self.name,
+                self.op_type,
+                value.get_type(),
+                value.to_json()
+            ));
+        }
+        wraps(&self.op_type, ret_type, values).then(|| {
+            format!(
+                "{} {} {} {} wraps around {}",
+                self.name,
+                values[0].to_json(),
+                self.op_type,
+                values[1].to_json(),
+                self.ret_type
+            )
+        })
+    }
 }
 
 pub trait IsEnv {
-    fn set_value(&mut self, port: &String, val: VALUE);
-    fn get_all_value(&self, ops: &Vec<String>) -> Vec<VALUE>;
-    fn get_value(&self, port: &String) -> VALUE;
+    fn set_value(&mut self, port: Name, val: VALUE);
+    fn get_value(&self, port: Name) -> VALUE;
+    /// The function or instance whose operations use the env, for messages
+    fn instance(&self) -> String;
+
+    fn read(&self, operand: Operand) -> VALUE {
+        match operand {
+            Operand::Var(name) => self.get_value(name),
+            Operand::Const(_, value) => value,
+        }
+    }
+
+    fn read_all(&self, operands: &[Operand]) -> Vec<VALUE> {
+        operands.iter().map(|operand| self.read(*operand)).collect()
+    }
 }
 
 pub fn operation_env(compute: &Computation, env: &mut impl IsEnv) {
-    let name = &compute.name;
-    let ret_type = &compute.ret_type;
     let ops = &compute.operands;
-    match &(compute.op_type) as &str {
-        "constant" => env.set_value(name, build_value(ret_type, &ops[0])),
-        "shift_left" => env.set_value(name, shift_left(ret_type, &env.get_all_value(ops))),
-        "shift_right" => env.set_value(name, shift_right(ret_type, &env.get_all_value(ops))),
-        "shrsi" => env.set_value(name, shift_right(ret_type, &env.get_all_value(ops))),
-        "add" => env.set_value(name, add(ret_type, &env.get_all_value(ops))),
-        "sub" => env.set_value(name, sub(ret_type, &env.get_all_value(ops))),
-        "mul" => env.set_value(name, mul(ret_type, &env.get_all_value(ops))),
-        "div" => env.set_value(name, div(ret_type, &env.get_all_value(ops))),
-        "divsi" => env.set_value(name, divsi(ret_type, &env.get_all_value(ops))),
-        "and" => env.set_value(name, and(ret_type, &env.get_all_value(ops))),
-        "or" => env.set_value(name, or(ret_type, &env.get_all_value(ops))),
-        "cmp_gte" | "cmp_oge" | "cmp_sge" => env.set_value(name, cmp_gte(&env.get_all_value(ops))),
-        "cmp_gt" | "cmp_ugt" | "cmp_sgt" | "cmp_ogt" => {
-            env.set_value(name, cmp_gt(&env.get_all_value(ops)))
-        }
-        "cmp_lte" => env.set_value(name, cmp_lte(&env.get_all_value(ops))),
-        "cmp_lt" | "cmp_ult" | "cmp_slt" | "cmp_olt" => {
-            env.set_value(name, cmp_lt(&env.get_all_value(ops)))
-        }
-        "cmp_eq" => env.set_value(name, cmp_eq(&env.get_all_value(ops))),
-        "cmp_sle" => env.set_value(name, cmp_sle(&env.get_all_value(ops))),
-        "cmp_ne" => env.set_value(name, cmp_ne(&env.get_all_value(ops))),
-        "not" => env.set_value(name, not(&env.get_all_value(ops))),
-        "index_cast" => env.set_value(name, env.get_value(&ops[0])),
-        "select" => env.set_value(name, select(&env.get_all_value(ops))),
-        "exp" => env.set_value(name, exp(&env.get_all_value(ops))),
-        "erf" => env.set_value(name, erf(&env.get_all_value(ops))),
-        "sqrt" => env.set_value(name, sqrt(&env.get_all_value(ops))),
-        "powf" => env.set_value(name, powf(&env.get_all_value(ops))),
-        "sitofp" => match ret_type as &str {
-            "f64" => env.set_value(name, VALUE::F64(env.get_value(&ops[0]).as_f64())),
-            "f32" => env.set_value(name, VALUE::F32(env.get_value(&ops[0]).as_f32())),
-            _ => panic!(),
-        },
-        "trunc" => match ret_type as &str {
-            "bool" => env.set_value(name, VALUE::Bool(env.get_value(&ops[0]).as_bool())),
-            _ => panic!(),
-        },
-        _ => {
-            panic!("{:#?}", compute);
+    if options().strict {
+        let values = env.read_all(compute.read_operands());
+        if let Some(operand) = compute.poisoned(&values) {
+            fault(error_operand(
+                &compute.name,
+                &compute.op_type,
+                operand.name(),
+                &env.instance(),
+            ));
         }
     }
+    if compute.lanes.is_some() {
+        let value = compute.evaluate(&env.read_all(compute.read_operands()));
+        env.set_value(compute.name, value);
+        return;
+    }
+    let value = match compute.eval {
+        Eval::Constant(value) => value,
+        Eval::Copy => env.read(ops[0]),
+        Eval::Typed(function, ret_type) => {
+            let values = env.read_all(ops);
+            compute.check_overflow(ret_type, &values);
+            function(ret_type, &values)
+        }
+        Eval::Untyped(function) => function(&env.read_all(ops)),
+    };
+    env.set_value(compute.name, value);
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -678,4 +3041,350 @@ impl HandshakeValue {
     pub fn set_ready_from(&mut self, token: HandshakeValue) -> bool {
         self.set_ready(token.ready)
     }
+
+    pub fn to_json(&self) -> Value {
+        json!({"valid": self.valid, "ready": self.ready, "data": self.data.to_json()})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::take_fault;
+
+    #[test]
+    fn unsigned_operations_on_signed_types() {
+        let (x, y) = (VALUE::I8(-16), VALUE::I8(3));
+        assert_eq!(divui_as(DataType::I8, &[x, y]), VALUE::I8((240 / 3) as i8));
+        assert_eq!(remui_as(DataType::I8, &[x, y]), VALUE::I8(0));
+        assert_eq!(minui_as(DataType::I8, &[x, y]), y);
+        assert_eq!(maxui_as(DataType::I8, &[x, y]), x);
+        let (x, y) = (VALUE::I16(-1), VALUE::I16(7));
+        assert_eq!(divui_as(DataType::I16, &[x, y]), VALUE::I16(9362));
+        assert_eq!(remui_as(DataType::I16, &[x, y]), VALUE::I16(1));
+        let (x, y) = (VALUE::I32(i32::MIN), VALUE::I32(-1));
+        assert_eq!(divui_as(DataType::I32, &[x, y]), VALUE::I32(0));
+        assert_eq!(remui_as(DataType::I32, &[x, y]), x);
+        assert_eq!(minui_as(DataType::I32, &[x, y]), x);
+        assert_eq!(maxui_as(DataType::I32, &[x, y]), y);
+        let (x, y) = (VALUE::I64(-2), VALUE::I64(2));
+        assert_eq!(divui_as(DataType::I64, &[x, y]), VALUE::I64(i64::MAX));
+        assert_eq!(minui_as(DataType::I64, &[x, y]), y);
+        // The signed forms for comparison
+        assert_eq!(divsi_as(DataType::I64, &[x, y]), VALUE::I64(-1));
+        assert_eq!(min_as(DataType::I64, &[x, y]), x);
+    }
+
+    /// Check `shrsi`, `shrui`, `rotl` and `rotr` on `x` by each amount
+    /// against `expected` (shrsi, shrui, rotl, rotr), as signed and
+    /// unsigned values of one width.
+    macro_rules! check_shifts {
+        ($signed: ident, $unsigned: ident, $int: ty, $uint: ty, $x: expr, $cases: expr) => {
+            for (by, expected) in $cases {
+                let (x, by): ($int, $int) = ($x, by);
+                let [shrsi, shrui, rotl, rotr]: [$int; 4] = expected;
+                let functions: [TypedFunction; 4] = [shrsi_as, shrui_as, rotl_as, rotr_as];
+                for (function, expected) in functions.iter().zip([shrsi, shrui, rotl, rotr]) {
+                    let signed =
+                        function(DataType::$signed, &[VALUE::$signed(x), VALUE::$signed(by)]);
+                    assert_eq!(signed, VALUE::$signed(expected), "{} by {}", x, by);
+                    let values = [VALUE::$unsigned(x as $uint), VALUE::$unsigned(by as $uint)];
+                    let unsigned = function(DataType::$unsigned, &values);
+                    assert_eq!(
+                        unsigned,
+                        VALUE::$unsigned(expected as $uint),
+                        "{} by {}",
+                        x,
+                        by
+                    );
+                }
+            }
+        };
+    }
+
+    /// Check `shift_left` and `shift_right` on `x` of `$type` by each amount
+    /// against `expected` (left, right).
+    macro_rules! check_plain_shifts {
+        ($type: ident, $x: expr, $cases: expr) => {
+            for (by, [left, right]) in $cases {
+                let values = [VALUE::$type($x), VALUE::$type(by)];
+                let shifted = shift_left_as(DataType::$type, &values);
+                assert_eq!(shifted, VALUE::$type(left), "{} << {}", $x, by);
+                let shifted = shift_right_as(DataType::$type, &values);
+                assert_eq!(shifted, VALUE::$type(right), "{} >> {}", $x, by);
+            }
+        };
+    }
+
+    #[test]
+    fn plain_shifts_past_the_width() {
+        check_plain_shifts!(
+            I8,
+            -122,
+            [
+                (1, [0x0c, -61]),
+                (7, [0, -1]),
+                (8, [0, -1]),
+                (9, [0, -1]),
+                (-1, [0, -1])
+            ]
+        );
+        check_plain_shifts!(U8, 0x86, [(1, [0x0c, 0x43]), (8, [0, 0]), (200, [0, 0])]);
+        check_plain_shifts!(I16, 3, [(15, [i16::MIN, 0]), (16, [0, 0])]);
+        check_plain_shifts!(U16, 3, [(15, [0x8000, 0]), (17, [0, 0])]);
+        check_plain_shifts!(
+            I32,
+            1,
+            [
+                (31, [i32::MIN, 0]),
+                (32, [0, 0]),
+                (40, [0, 0]),
+                (-1, [0, 0])
+            ]
+        );
+        check_plain_shifts!(I32, -1, [(31, [i32::MIN, -1]), (40, [0, -1])]);
+        check_plain_shifts!(U32, u32::MAX, [(31, [1 << 31, 1]), (32, [0, 0])]);
+        check_plain_shifts!(I64, i64::MIN, [(1, [0, i64::MIN / 2]), (64, [0, -1])]);
+        check_plain_shifts!(U64, 5, [(63, [1 << 63, 0]), (64, [0, 0]), (65, [0, 0])]);
+    }
+
+    #[test]
+    fn shifts_and_rotations_8() {
+        // 0b1000_0110
+        check_shifts!(
+            I8,
+            U8,
+            i8,
+            u8,
+            -122,
+            [
+                (0, [-122, -122, -122, -122]),
+                (1, [-61, 0x43, 0x0d, 0x43]),
+                (7, [-1, 1, 0x43, 0x0d]),
+                (8, [-1, 0, -122, -122]),
+                (9, [-1, 0, 0x0d, 0x43]),
+                (-1, [-1, 0, 0x43, 0x0d]),
+                (-8, [-1, 0, -122, -122]),
+            ]
+        );
+        check_shifts!(
+            I8,
+            U8,
+            i8,
+            u8,
+            0x06,
+            [
+                (1, [3, 3, 0x0c, 3]),
+                (8, [0, 0, 6, 6]),
+                (-1, [0, 0, 3, 0x0c])
+            ]
+        );
+    }
+
+    #[test]
+    fn shifts_and_rotations_16() {
+        // 0x8006
+        check_shifts!(
+            I16,
+            U16,
+            i16,
+            u16,
+            -0x7ffa,
+            [
+                (0, [-0x7ffa, -0x7ffa, -0x7ffa, -0x7ffa]),
+                (1, [-0x3ffd, 0x4003, 0x000d, 0x4003]),
+                (15, [-1, 1, 0x4003, 0x000d]),
+                (16, [-1, 0, -0x7ffa, -0x7ffa]),
+                (17, [-1, 0, 0x000d, 0x4003]),
+                (-1, [-1, 0, 0x4003, 0x000d]),
+                (i16::MIN, [-1, 0, -0x7ffa, -0x7ffa]),
+            ]
+        );
+        check_shifts!(
+            I16,
+            U16,
+            i16,
+            u16,
+            0x0006,
+            [
+                (1, [3, 3, 0x0c, 3]),
+                (16, [0, 0, 6, 6]),
+                (-1, [0, 0, 3, 0x0c])
+            ]
+        );
+    }
+
+    #[test]
+    fn shifts_and_rotations_32() {
+        // 0x8000_0006
+        check_shifts!(
+            I32,
+            U32,
+            i32,
+            u32,
+            -0x7fff_fffa,
+            [
+                (0, [-0x7fff_fffa, -0x7fff_fffa, -0x7fff_fffa, -0x7fff_fffa]),
+                (1, [-0x3fff_fffd, 0x4000_0003, 0x0000_000d, 0x4000_0003]),
+                (31, [-1, 1, 0x4000_0003, 0x0000_000d]),
+                (32, [-1, 0, -0x7fff_fffa, -0x7fff_fffa]),
+                (33, [-1, 0, 0x0000_000d, 0x4000_0003]),
+                (-1, [-1, 0, 0x4000_0003, 0x0000_000d]),
+                (-32, [-1, 0, -0x7fff_fffa, -0x7fff_fffa]),
+            ]
+        );
+        check_shifts!(
+            I32,
+            U32,
+            i32,
+            u32,
+            6,
+            [
+                (1, [3, 3, 0x0c, 3]),
+                (32, [0, 0, 6, 6]),
+                (-1, [0, 0, 3, 0x0c])
+            ]
+        );
+    }
+
+    #[test]
+    fn shifts_and_rotations_64() {
+        // 0x8000_0000_0000_0006
+        let x = -0x7fff_ffff_ffff_fffa;
+        check_shifts!(
+            I64,
+            U64,
+            i64,
+            u64,
+            x,
+            [
+                (0, [x, x, x, x]),
+                (
+                    1,
+                    [
+                        -0x3fff_ffff_ffff_fffd,
+                        0x4000_0000_0000_0003,
+                        0x0d,
+                        0x4000_0000_0000_0003
+                    ]
+                ),
+                (63, [-1, 1, 0x4000_0000_0000_0003, 0x0d]),
+                (64, [-1, 0, x, x]),
+                (65, [-1, 0, 0x0d, 0x4000_0000_0000_0003]),
+                (-1, [-1, 0, 0x4000_0000_0000_0003, 0x0d]),
+                (i64::MIN, [-1, 0, x, x]),
+            ]
+        );
+        check_shifts!(
+            I64,
+            U64,
+            i64,
+            u64,
+            6,
+            [
+                (1, [3, 3, 0x0c, 3]),
+                (64, [0, 0, 6, 6]),
+                (-1, [0, 0, 3, 0x0c])
+            ]
+        );
+    }
+
+    #[test]
+    fn unsigned_division_by_zero() {
+        let values = [VALUE::I32(5), VALUE::I32(0)];
+        assert_eq!(divui_as(DataType::I32, &values), VALUE::ERROR);
+        assert!(take_fault().is_some());
+        assert_eq!(remui_as(DataType::I32, &values), VALUE::ERROR);
+        assert!(take_fault().is_some());
+    }
+
+    #[test]
+    fn backing_file_widths() {
+        let path = std::env::temp_dir().join(format!("hestia-{}.bin", std::process::id()));
+        std::fs::write(&path, [1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        let path = path.to_str().unwrap();
+        let backing = Backing::open(path, "u16", 4).unwrap();
+        assert_eq!(backing.word(3), VALUE::U16(0x0807));
+        let backing = Backing::open(path, "index", 1).unwrap();
+        assert_eq!(backing.word(0), VALUE::Index(0x0807_0605_0403_0201));
+        assert!(Backing::open(path, "u32", 3).is_err());
+        assert!(Backing::open(path, "c64", 1).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn integer_arithmetic_wraps() {
+        let (x, y) = (VALUE::I32(i32::MAX), VALUE::I32(2));
+        assert_eq!(add_as(DataType::I32, &[x, y]), VALUE::I32(i32::MIN + 1));
+        assert_eq!(mul_as(DataType::I32, &[x, y]), VALUE::I32(-2));
+        let (x, y) = (VALUE::I64(i64::MIN), VALUE::I64(1));
+        assert_eq!(sub_as(DataType::I64, &[x, y]), VALUE::I64(i64::MAX));
+        let (x, y) = (VALUE::U32(0), VALUE::U32(1));
+        assert_eq!(sub_as(DataType::U32, &[x, y]), VALUE::U32(u32::MAX));
+        let (x, y) = (VALUE::U64(u64::MAX), VALUE::U64(u64::MAX));
+        assert_eq!(mul_as(DataType::U64, &[x, y]), VALUE::U64(1));
+        assert_eq!(add_as(DataType::Index, &[x, y]), VALUE::Index(u64::MAX - 1));
+        let (x, y) = (VALUE::F32(f32::MAX), VALUE::F32(2.0));
+        assert_eq!(mul_as(DataType::F32, &[x, y]), VALUE::F32(f32::INFINITY));
+    }
+
+    #[test]
+    fn signed_division_overflow_wraps() {
+        let (x, y) = (VALUE::I32(i32::MIN), VALUE::I32(-1));
+        assert_eq!(div_as(DataType::I32, &[x, y]), x);
+        assert_eq!(divsi_as(DataType::I32, &[x, y]), x);
+        assert_eq!(rem_as(DataType::I32, &[x, y]), VALUE::I32(0));
+        let (x, y) = (VALUE::I8(i8::MIN), VALUE::I8(-1));
+        assert_eq!(divsi_as(DataType::I8, &[x, y]), x);
+        assert_eq!(rem_as(DataType::I8, &[x, y]), VALUE::I8(0));
+        let (x, y) = (VALUE::I64(i64::MIN), VALUE::I64(-1));
+        assert_eq!(div_as(DataType::I64, &[x, y]), x);
+        assert_eq!(rem_as(DataType::I64, &[x, y]), VALUE::I64(0));
+        let (x, y) = (VALUE::I16(-7), VALUE::I16(2));
+        assert_eq!(divsi_as(DataType::I16, &[x, y]), VALUE::I16(-3));
+        assert_eq!(rem_as(DataType::I16, &[x, y]), VALUE::I16(-1));
+    }
+
+    #[test]
+    fn narrow_arithmetic_wraps() {
+        let (x, y) = (VALUE::I8(100), VALUE::I8(100));
+        assert_eq!(mul_as(DataType::I8, &[x, y]), VALUE::I8(16));
+        assert_eq!(add_as(DataType::I8, &[x, y]), VALUE::I8(-56));
+        let (x, y) = (VALUE::U8(200), VALUE::U8(100));
+        assert_eq!(add_as(DataType::U8, &[x, y]), VALUE::U8(44));
+        assert_eq!(sub_as(DataType::U8, &[y, x]), VALUE::U8(156));
+        let (x, y) = (VALUE::I16(i16::MIN), VALUE::I16(1));
+        assert_eq!(sub_as(DataType::I16, &[x, y]), VALUE::I16(i16::MAX));
+        let (x, y) = (VALUE::U16(300), VALUE::U16(300));
+        assert_eq!(mul_as(DataType::U16, &[x, y]), VALUE::U16(24464));
+    }
+
+    #[test]
+    fn unsigned_and_unordered_comparisons() {
+        let (minus_one, one) = (VALUE::I32(-1), VALUE::I32(1));
+        assert_eq!(cmp_lt(&[minus_one, one]), VALUE::Bool(true));
+        assert_eq!(cmp_ult(&[minus_one, one]), VALUE::Bool(false));
+        assert_eq!(cmp_ugt(&[minus_one, one]), VALUE::Bool(true));
+        assert_eq!(cmp_uge(&[minus_one, minus_one]), VALUE::Bool(true));
+        assert_eq!(
+            cmp_ule(&[VALUE::I8(-1), VALUE::I16(255)]),
+            VALUE::Bool(false)
+        );
+        let (nan, one) = (VALUE::F32(f32::NAN), VALUE::F32(1.0));
+        for (compare, with_nan, without) in [
+            (cmp_eq as fn(&[VALUE]) -> VALUE, false, false),
+            (cmp_one, false, true),
+            (cmp_ne, true, true),
+            (cmp_ueq, true, false),
+            (cmp_ult, true, true),
+            (cmp_lt, false, true),
+            (cmp_uno, true, false),
+            (cmp_ord, false, true),
+        ] {
+            assert_eq!(compare(&[nan, one]), VALUE::Bool(with_nan));
+            assert_eq!(compare(&[VALUE::F32(0.0), one]), VALUE::Bool(without));
+        }
+        assert_eq!(cmp_one(&[one, one]), VALUE::Bool(false));
+        assert_eq!(cmp_ult(&[VALUE::X, one]), VALUE::X);
+    }
 }
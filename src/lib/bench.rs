@@ -0,0 +1,177 @@
+//! Representative designs bundled into the binary and a timer that steps
+//! them for a number of cycles, behind the `bench` subcommand and the
+//! criterion benchmarks.
+
+use super::common::*;
+use super::hec;
+use super::simulator::scratch;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+pub struct Design {
+    pub name: &'static str,
+    /// The kind of control the design exercises, e.g. static or handshake.
+    pub kind: &'static str,
+    pub path: &'static str,
+    text: &'static str,
+    /// Start the design after it is loaded.
+    start: fn() -> Result<(), String>,
+}
+
+fn call_main() -> Result<(), String> {
+    call_function("main", &[])
+}
+
+// A handshake design runs once its input token is accepted
+fn call_handshake() -> Result<(), String> {
+    call_function("main", &[])?;
+    hec::valid("in", "control");
+    hec::ready("out0");
+    step(1);
+    hec::invalid("in");
+    Ok(())
+}
+
+pub const DESIGNS: &[Design] = &[
+    Design {
+        name: "gemm-scf",
+        kind: "software",
+        path: "example/json/gemm/scf.json",
+        text: include_str!("../../example/json/gemm/scf.json"),
+        start: call_main,
+    },
+    Design {
+        name: "gemm-tor",
+        kind: "static, pipelined",
+        path: "example/json/gemm/tor.json",
+        text: include_str!("../../example/json/gemm/tor.json"),
+        start: call_main,
+    },
+    Design {
+        name: "gemm-hec",
+        kind: "static, pipelined",
+        path: "example/json/gemm/hec.json",
+        text: include_str!("../../example/json/gemm/hec.json"),
+        start: call_main,
+    },
+    Design {
+        name: "aeloss-hec",
+        kind: "handshake",
+        path: "example/json/aeloss_pull/hec_dyn.json",
+        text: include_str!("../../example/json/aeloss_pull/hec_dyn.json"),
+        start: call_handshake,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Design> {
+    DESIGNS.iter().find(|design| design.name == name)
+}
+
+pub struct Measure {
+    pub level: State,
+    pub cycles: usize,
+    /// Time spent stepping, without loading and restarting the design.
+    pub time: Duration,
+}
+
+impl Measure {
+    pub fn cycles_per_second(&self) -> f64 {
+        self.cycles as f64 / self.time.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// Step `design` for `cycles` cycles on a simulation of its own, starting it
+/// over whenever it finishes. Stops early if the design makes no progress.
+pub fn measure(design: &Design, cycles: usize) -> Result<Measure, String> {
+    scratch(|| {
+        // Fast-forward would skip the cycles being timed
+        step_every_cycle();
+        let mut result = Err(format!("failed to load {}", design.path));
+        capture(|| result = run_design(design, cycles));
+        result
+    })
+}
+
+fn run_design(design: &Design, cycles: usize) -> Result<Measure, String> {
+    let (level, ir) = parse_ir(design.path, design.text).map_err(|err| err.to_string())?;
+    load_ir(design.path, level, &ir).map_err(|err| err.to_string())?;
+    let mut measure = Measure {
+        level,
+        cycles: 0,
+        time: Duration::ZERO,
+    };
+    while measure.cycles < cycles {
+        (design.start)()?;
+        let before = cycle_count(level);
+        let start = Instant::now();
+        step(cycles - measure.cycles);
+        measure.time += start.elapsed();
+        let stepped = cycle_count(level).saturating_sub(before);
+        if stepped == 0 {
+            break;
+        }
+        measure.cycles += stepped;
+    }
+    Ok(measure)
+}
+
+/// `hestia bench`: time the named designs, or all of them, and print their
+/// cycles per second.
+pub fn bench(names: &[String], cycles: usize) {
+    let mut designs = vec![];
+    for name in names {
+        match find(name) {
+            Some(design) => designs.push(design),
+            None => {
+                println!("!!Unknown design {}", name);
+                return fail(Failure::Command);
+            }
+        }
+    }
+    if designs.is_empty() {
+        designs = DESIGNS.iter().collect();
+    }
+    let mut results = vec![];
+    for design in designs {
+        match measure(design, cycles) {
+            Ok(measure) => results.push((design, measure)),
+            Err(err) => {
+                println!("!!Failed to run {}: {}", design.name, err);
+                fail(Failure::Command);
+            }
+        }
+    }
+    if json_output() {
+        let designs = results
+            .iter()
+            .map(|(design, measure)| {
+                json!({
+                    "design": design.name,
+                    "kind": design.kind,
+                    "level": format!("{:?}", measure.level),
+                    "cycles": measure.cycles,
+                    "seconds": measure.time.as_secs_f64(),
+                    "cycles_per_second": measure.cycles_per_second(),
+                })
+            })
+            .collect::<Vec<_>>();
+        return print_json(json!(designs));
+    }
+    for (design, measure) in &results {
+        println!("{} ({:?}, {})", design.name, measure.level, design.kind);
+        print_table(
+            "  ",
+            &[
+                ("cycles".to_string(), measure.cycles.to_string()),
+                (
+                    "seconds".to_string(),
+                    format!("{:.3}", measure.time.as_secs_f64()),
+                ),
+                (
+                    "cycles/s".to_string(),
+                    format!("{:.0}", measure.cycles_per_second()),
+                ),
+            ],
+        );
+    }
+}
@@ -1,229 +1,3516 @@
+use super::basetype::{
+    add, bit_width, build_value, cmp_eq, cmp_gt, cmp_gte, cmp_lt, cmp_lte, cmp_ne, parse_value,
+    PagedStore, VALUE,
+};
+use super::decompress;
 use super::equal;
+use super::error::{ErrorCode, HestiaError};
 use super::hec;
+use super::json5;
+use super::logging;
+use super::mlir;
+use super::perfetto;
+use super::profile;
+use super::rtl;
+use super::schema;
+use super::simulator;
 use super::software;
 use super::tor;
+use super::vcd;
+use super::yaml;
+use regex::Regex;
 use serde_json::Value;
-use std::collections::HashSet;
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
 use std::io::BufReader;
-use std::io::Read;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-static mut HAS_SOFTWARE: bool = false;
-static mut HAS_TOR: bool = false;
-static mut HAS_HEC: bool = false;
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum State {
+    #[default]
+    Software,
+    ToR,
+    HEC,
+    Cosim,
+}
+
+/// What a simulation has loaded and how far it has run, kept with the state
+/// of its levels in a `simulator::Simulation`.
+#[derive(Default)]
+pub struct RunState {
+    level: State,
+    has_software: bool,
+    has_tor: bool,
+    has_hec: bool,
+    /// Simulated cycles (steps for software) since each level was loaded.
+    cycle: [usize; 3],
+    /// IR files per level and the equal file, in load order, for `reload`.
+    files: Vec<(State, String)>,
+    equal_file: Option<String>,
+    /// Last value of each halting watchpoint, keyed by `{var} in {instance}`.
+    watch_previous: HashMap<String, VALUE>,
+    /// Consecutive cycles each stalled instance has been waiting, with the
+    /// stream it waits for. Instances that were not reported in a cycle made
+    /// progress. Locked because independent ToR instances may step on
+    /// several threads.
+    stalls: Mutex<HashMap<String, (usize, String)>>,
+    stalled: Mutex<HashSet<String>>,
+    checkpoints: BTreeMap<String, Rc<Checkpoint>>,
+    /// Checkpoints taken every `checkpoint_interval` cycles, keyed by the
+    /// cycle counter they belong to and the cycle, for `goto_cycle` to
+    /// re-execute from.
+    history: BTreeMap<(usize, usize), Rc<Checkpoint>>,
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    /// Set on a simulation whose every cycle has to be stepped, as one being
+    /// timed, whatever the `fast_forward` option.
+    every_cycle: bool,
+}
+
+fn run_state() -> RefMut<'static, RunState> {
+    simulator::current().run.borrow_mut()
+}
+
+fn cycle_index(level: State) -> usize {
+    match level {
+        State::Software => 0,
+        State::ToR | State::Cosim => 1,
+        State::HEC => 2,
+    }
+}
+
+pub fn advance_cycle(level: State) {
+    advance_cycles(level, 1)
+}
+
+pub fn advance_cycles(level: State, count: usize) {
+    run_state().cycle[cycle_index(level)] += count
+}
+
+pub fn cycle_count(level: State) -> usize {
+    run_state().cycle[cycle_index(level)]
+}
+
+pub fn current_cycle() -> usize {
+    if let Some(cycle) = BUFFER_CYCLE.get() {
+        return cycle;
+    }
+    cycle_count(debug_state())
+}
+
+pub fn debug_state() -> State {
+    run_state().level
+}
+
+pub fn level_name(level: State) -> &'static str {
+    match level {
+        State::Software => "software",
+        State::ToR => "tor",
+        State::HEC => "hec",
+        State::Cosim => "cosim",
+    }
+}
+
+pub fn prompt() -> String {
+    let level = level_name(run_state().level);
+    match simulator::session_name().as_str() {
+        simulator::MAIN_SESSION => format!("[{} @{}]> ", level, current_cycle()),
+        session => format!("[{}:{} @{}]> ", session, level, current_cycle()),
+    }
+}
+
+/// Failures that make a batch run exit non-zero, the discriminant is the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Failure {
+    Command = 1,
+    Mismatch = 2,
+    Panic = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Trace,
+}
+
+/// How the software level runs a `continue`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoftwareBackend {
+    /// One operation per step, as `step` does
+    Interpreted,
+    /// The functions lowered to slots once at load, used when nothing has to
+    /// be checked between the steps
+    Compiled,
+}
+
+/// Whether the ToR level steps the active instances of a cycle on threads of
+/// their own when they share no memory or stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParallelInstances {
+    Off,
+    On,
+    /// Step them on threads, then again one after another, and report a
+    /// cycle in which the two differ
+    Check,
+}
+
+/// What an integer `add`, `sub` or `mul` whose result does not fit its type
+/// does besides wrapping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Off,
+    /// Print the operation and the cycle, and go on with the wrapped value
+    Warn,
+    /// Stop with a fault
+    Halt,
+}
+
+/// What an integer `div`, `divsi`, `divui`, `rem` or `remui` by zero gives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DivZero {
+    /// ERROR, and a fault that stops the run
+    Trap,
+    Error,
+    /// The all-ones word of the type, as many hardware dividers give
+    Ones,
+}
+
+/// How values print in `{:?}` dumps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Radix {
+    Dec,
+    /// The bit pattern in hex digits, floats included
+    Hex,
+    /// The bit pattern in binary digits
+    Bin,
+}
+
+impl Radix {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "dec" => Some(Radix::Dec),
+            "hex" => Some(Radix::Hex),
+            "bin" => Some(Radix::Bin),
+            _ => None,
+        }
+    }
+}
+
+/// Simulator options changed at runtime with `set <name> <value>`.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Print the show_* commands as JSON instead of `{:?}` dumps
+    pub json_output: bool,
+    pub verbosity: Verbosity,
+    /// Print stream and FIFO stalls even below trace verbosity
+    pub stall_print: bool,
+    /// Cycle (or step) limit of `continue`
+    pub max_cycles: usize,
+    /// Relative tolerance for float comparisons in cosim
+    pub float_tolerance: f64,
+    /// How many values one level of a cosim may run ahead of the other
+    pub cosim_skew: isize,
+    /// Color values, stalls and mismatches with ANSI escapes
+    pub color: bool,
+    /// Cycles between the automatic checkpoints used by `goto_cycle`, 0 disables them
+    pub checkpoint_interval: usize,
+    /// Halt when an instance stalls on a stream for more cycles in a row, 0 never halts
+    pub stall_limit: usize,
+    /// Units a dynamic HEC network needs to settle its regions in parallel, 0 never does
+    pub parallel_units: usize,
+    pub parallel_instances: ParallelInstances,
+    /// Skip cycles in bulk while nothing in the design changes
+    pub fast_forward: bool,
+    pub software_backend: SoftwareBackend,
+    /// Step HEC on a thread of its own during cosim, which stops a mismatch
+    /// up to `cosim_skew` cycles after it
+    pub cosim_threads: bool,
+    /// Read the memory words and registers loaded after it is set as X until
+    /// they are written, rather than as 0
+    pub x_state: bool,
+    pub overflow: Overflow,
+    pub div_zero: DivZero,
+    pub radix: Radix,
+    /// Stop on any operation that reads ERROR, rather than computing with
+    /// it as 0
+    pub strict: bool,
+}
+
+/// The options of the process, read by the worker threads as well.
+static OPTIONS: RwLock<Options> = RwLock::new(Options {
+    json_output: false,
+    verbosity: Verbosity::Normal,
+    stall_print: false,
+    max_cycles: 1000000000,
+    float_tolerance: 0.0,
+    cosim_skew: 10,
+    color: true,
+    checkpoint_interval: 10000,
+    stall_limit: 0,
+    parallel_units: 1024,
+    parallel_instances: ParallelInstances::Off,
+    fast_forward: true,
+    software_backend: SoftwareBackend::Interpreted,
+    cosim_threads: false,
+    x_state: false,
+    overflow: Overflow::Off,
+    div_zero: DivZero::Trap,
+    radix: Radix::Dec,
+    strict: false,
+});
+
+const OPTION_NAMES: &[&str] = &[
+    "checkpoint_interval",
+    "color",
+    "cosim_skew",
+    "cosim_threads",
+    "div_zero",
+    "fast_forward",
+    "float_tolerance",
+    "log",
+    "max_cycles",
+    "output",
+    "overflow",
+    "parallel_instances",
+    "parallel_units",
+    "radix",
+    "software_backend",
+    "stall_limit",
+    "stall_print",
+    "strict",
+    "verbosity",
+    "x_state",
+];
+
+pub fn options() -> Options {
+    *OPTIONS.read().unwrap()
+}
+
+/// Whether messages of the given level are printed. Per-cycle messages such as
+/// pipeline steps and stalls are `Trace`, summaries such as cycle counts `Normal`.
+pub fn verbose(level: Verbosity) -> bool {
+    options().verbosity >= level
+}
+
+pub fn stall_print() -> bool {
+    options().stall_print || verbose(Verbosity::Trace)
+}
+
+pub fn json_output() -> bool {
+    options().json_output
+}
+
+pub fn print_json(value: Value) {
+    println!("{}", value)
+}
+
+pub fn option_names() -> Vec<String> {
+    OPTION_NAMES.iter().map(|name| name.to_string()).collect()
+}
+
+/// The option `name` is a misspelling of, at most two letters off, so that
+/// `set` does not take a mistyped option for a script variable.
+pub fn misspelled_option(name: &str) -> Option<&'static str> {
+    let name = name.chars().collect::<Vec<char>>();
+    OPTION_NAMES.iter().copied().find(|option| {
+        // Levenshtein distance, a row at a time
+        let option = option.chars().collect::<Vec<char>>();
+        let mut row = (0..=option.len()).collect::<Vec<usize>>();
+        for (i, c) in name.iter().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = i + 1;
+            for (j, d) in option.iter().enumerate() {
+                let substituted = diagonal + (c != d) as usize;
+                diagonal = row[j + 1];
+                row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+            }
+        }
+        row[option.len()] <= 2
+    })
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+    match value {
+        "on" | "true" | "1" => Some(true),
+        "off" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Counts such as `1e6` are accepted for `max_cycles`.
+fn parse_count(value: &str) -> Option<usize> {
+    value.parse::<usize>().ok().or_else(|| {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|v| *v >= 0.0)
+            .map(|v| v as usize)
+    })
+}
+
+/// Set a debugger option, returning false if `name` is not an option.
+pub fn set_option(name: &str, value: &str) -> bool {
+    if !OPTION_NAMES.contains(&name) {
+        return false;
+    }
+    let valid = match name {
+        "log" => logging::set_log_filter(value).then_some(()),
+        _ => parse_option(&mut OPTIONS.write().unwrap(), name, value),
+    };
+    if valid.is_none() {
+        println!("!!Invalid value {} for {}", value, name);
+        fail(Failure::Command);
+    } else if matches!(
+        name,
+        "cosim_skew" | "cosim_threads" | "float_tolerance" | "x_state"
+    ) {
+        record_input(&format!("set {} {}", name, value));
+    }
+    true
+}
+
+/// Set the option `name` of `options` to `value`, None if it is invalid.
+fn parse_option(options: &mut Options, name: &str, value: &str) -> Option<()> {
+    match name {
+        "output" => match value {
+            "json" => Some(true),
+            "text" => Some(false),
+            _ => None,
+        }
+        .map(|json| options.json_output = json),
+        "verbosity" => match value {
+            "quiet" => Some(Verbosity::Quiet),
+            "normal" => Some(Verbosity::Normal),
+            "trace" => Some(Verbosity::Trace),
+            _ => None,
+        }
+        .map(|level| options.verbosity = level),
+        "stall_print" => parse_switch(value).map(|on| options.stall_print = on),
+        "color" => parse_switch(value).map(|on| options.color = on),
+        "fast_forward" => parse_switch(value).map(|on| options.fast_forward = on),
+        "cosim_threads" => parse_switch(value).map(|on| options.cosim_threads = on),
+        "x_state" => parse_switch(value).map(|on| options.x_state = on),
+        "strict" => parse_switch(value).map(|on| options.strict = on),
+        "max_cycles" => parse_count(value).map(|count| options.max_cycles = count),
+        "checkpoint_interval" => {
+            parse_count(value).map(|count| options.checkpoint_interval = count)
+        }
+        "stall_limit" => parse_count(value).map(|count| options.stall_limit = count),
+        "parallel_units" => parse_count(value).map(|count| options.parallel_units = count),
+        "parallel_instances" => match value {
+            "check" => Some(ParallelInstances::Check),
+            _ => parse_switch(value).map(|on| match on {
+                true => ParallelInstances::On,
+                false => ParallelInstances::Off,
+            }),
+        }
+        .map(|mode| options.parallel_instances = mode),
+        "overflow" => match value {
+            "off" => Some(Overflow::Off),
+            "warn" => Some(Overflow::Warn),
+            "halt" => Some(Overflow::Halt),
+            _ => None,
+        }
+        .map(|mode| options.overflow = mode),
+        "div_zero" => match value {
+            "trap" => Some(DivZero::Trap),
+            "error" => Some(DivZero::Error),
+            "ones" => Some(DivZero::Ones),
+            _ => None,
+        }
+        .map(|policy| options.div_zero = policy),
+        "radix" => Radix::parse(value).map(|radix| options.radix = radix),
+        "software_backend" => match value {
+            "interpreted" => Some(SoftwareBackend::Interpreted),
+            "compiled" => Some(SoftwareBackend::Compiled),
+            _ => None,
+        }
+        .map(|backend| options.software_backend = backend),
+        "float_tolerance" => value
+            .parse::<f64>()
+            .ok()
+            .filter(|v| *v >= 0.0)
+            .map(|tolerance| options.float_tolerance = tolerance),
+        "cosim_skew" => value
+            .parse::<isize>()
+            .ok()
+            .filter(|v| *v > 0)
+            .map(|skew| options.cosim_skew = skew),
+        _ => None,
+    }
+}
+
+pub fn show_options() {
+    let options = options();
+    let output = if options.json_output { "json" } else { "text" };
+    let verbosity = format!("{:?}", options.verbosity).to_lowercase();
+    let stall_print = if options.stall_print { "on" } else { "off" };
+    println!("output {}", output);
+    println!("verbosity {}", verbosity);
+    println!("stall_print {}", stall_print);
+    println!("max_cycles {}", options.max_cycles);
+    println!("float_tolerance {}", options.float_tolerance);
+    println!("cosim_skew {}", options.cosim_skew);
+    let cosim_threads = if options.cosim_threads { "on" } else { "off" };
+    println!("cosim_threads {}", cosim_threads);
+    println!("color {}", if options.color { "on" } else { "off" });
+    println!("checkpoint_interval {}", options.checkpoint_interval);
+    println!("stall_limit {}", options.stall_limit);
+    println!("parallel_units {}", options.parallel_units);
+    let parallel_instances = format!("{:?}", options.parallel_instances).to_lowercase();
+    println!("parallel_instances {}", parallel_instances);
+    let fast_forward = if options.fast_forward { "on" } else { "off" };
+    println!("fast_forward {}", fast_forward);
+    let software_backend = format!("{:?}", options.software_backend).to_lowercase();
+    println!("software_backend {}", software_backend);
+    println!("x_state {}", if options.x_state { "on" } else { "off" });
+    println!(
+        "overflow {}",
+        format!("{:?}", options.overflow).to_lowercase()
+    );
+    println!(
+        "div_zero {}",
+        format!("{:?}", options.div_zero).to_lowercase()
+    );
+    println!("radix {}", format!("{:?}", options.radix).to_lowercase());
+    println!("strict {}", if options.strict { "on" } else { "off" });
+    println!("log {}", logging::log_filter());
+}
+
+/// Run `f` printing values in `radix` if one is given, as the `show_var` and
+/// `show_mem` that name one do.
+fn with_radix(radix: Option<Radix>, f: impl FnOnce()) {
+    let saved = options().radix;
+    OPTIONS.write().unwrap().radix = radix.unwrap_or(saved);
+    f();
+    OPTIONS.write().unwrap().radix = saved;
+}
+
+/// The radix a `show_var` or `show_mem` names before its patterns, if any,
+/// and the patterns.
+fn radix_override<'a, 'b>(args: &'a [&'b str]) -> (Option<Radix>, &'a [&'b str]) {
+    match args.first().and_then(|arg| Radix::parse(arg)) {
+        Some(radix) => (Some(radix), &args[1..]),
+        None => (None, args),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Color {
+    Red = 31,
+    Green = 32,
+    Yellow = 33,
+}
+
+/// Values are green, stalls yellow and mismatches red, unless `color` is off.
+pub fn paint<T: std::fmt::Display>(color: Color, text: T) -> String {
+    if options().color {
+        format!("\x1b[{}m{}\x1b[0m", color as u8, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Print `name value` rows with the values aligned in one column.
+pub fn print_table(indent: &str, rows: &[(String, String)]) {
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, value) in rows {
+        println!(
+            "{}{:<width$} {}",
+            indent,
+            name,
+            paint(Color::Green, value),
+            width = width
+        );
+    }
+}
+
+fn strip_color(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| *c == 'm');
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+thread_local! {
+    /// The file `log` copies the output to.
+    static LOG: RefCell<Option<File>> = const { RefCell::new(None) };
+    /// Output collected by `capture` instead of printed.
+    static CAPTURE: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Output of a level stepping on a thread of its own, and the cycle it
+    /// reports, see `buffer_output`.
+    static BUFFER: RefCell<Option<String>> = const { RefCell::new(None) };
+    static BUFFER_CYCLE: Cell<Option<usize>> = const { Cell::new(None) };
+    /// The worst `Failure` on this thread, see `failed_on_thread`.
+    static THREAD_FAILURE: Cell<i32> = const { Cell::new(0) };
+    /// Set when a FIFO was read while empty or written while full in this cycle.
+    static FIFO_ERROR: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f` with its output kept for the caller to print in order, for a
+/// level of a cosim or a run of a sweep stepping on a thread of its own.
+/// `current_cycle` reports `cycle` meanwhile, if given.
+pub fn buffer_output<F: FnOnce()>(cycle: Option<usize>, f: F) -> String {
+    let outer = BUFFER.replace(Some(String::new()));
+    let outer_cycle = BUFFER_CYCLE.replace(cycle);
+    f();
+    BUFFER_CYCLE.set(outer_cycle);
+    BUFFER.replace(outer).unwrap_or_default()
+}
+
+/// Write simulator output to stdout and, if `log` is on, to the log file.
+/// `println!` and `print!` are routed here by the macros in main.rs.
+pub fn tee(args: std::fmt::Arguments) {
+    if is_replaying() {
+        return;
+    }
+    let buffered = BUFFER.with_borrow_mut(|buffer| {
+        buffer
+            .as_mut()
+            .map(|buffer| buffer.push_str(&args.to_string()))
+    });
+    if buffered.is_some() {
+        return;
+    }
+    let captured = CAPTURE.with_borrow_mut(|capture| {
+        capture
+            .as_mut()
+            .map(|capture| capture.push_str(&args.to_string()))
+    });
+    if captured.is_none() {
+        std::print!("{}", args);
+    }
+    LOG.with_borrow_mut(|log| {
+        if let Some(log) = log {
+            if options().color {
+                let _ = log.write_all(strip_color(&args.to_string()).as_bytes());
+            } else {
+                let _ = log.write_fmt(args);
+            }
+        }
+    });
+}
+
+pub fn set_log(path: &str) {
+    if path == "off" {
+        LOG.set(None);
+        return;
+    }
+    match File::create(path) {
+        Ok(file) => LOG.set(Some(file)),
+        Err(_) => {
+            println!("!!Failed to write file {}", path);
+            fail(Failure::Command);
+        }
+    }
+}
+
+/// The worst `Failure` so far as its exit code, 0 for none. Atomic as the
+/// levels of a cosim may fail on threads of their own.
+static FAILURE: AtomicI32 = AtomicI32::new(0);
+static BATCH: AtomicBool = AtomicBool::new(false);
+
+pub fn fail(failure: Failure) {
+    FAILURE.fetch_max(failure as i32, Ordering::Relaxed);
+    THREAD_FAILURE.set(THREAD_FAILURE.get().max(failure as i32));
+}
+
+pub fn exit_code() -> i32 {
+    FAILURE.load(Ordering::Relaxed)
+}
+
+/// Run `f` with its output collected instead of printed, for the machine
+/// interfaces. Also returns whether `f` failed, leaving earlier failures in
+/// place for the exit code.
+pub fn capture<F: FnOnce()>(f: F) -> (String, bool) {
+    let failure = FAILURE.swap(0, Ordering::Relaxed);
+    CAPTURE.set(Some(String::new()));
+    f();
+    let output = CAPTURE.take().unwrap_or_default();
+    let failed = exit_code() != 0;
+    FAILURE.fetch_max(failure, Ordering::Relaxed);
+    (output, failed)
+}
+
+/// Run `f` and return whether it failed on this thread, which other threads
+/// failing meanwhile does not change.
+pub fn failed_on_thread<F: FnOnce()>(f: F) -> bool {
+    thread_failure(f).is_some()
+}
+
+/// Run `f` and return the worst `Failure` it had on this thread, for a thread
+/// to pass on to the one it works for.
+pub fn thread_failure<F: FnOnce()>(f: F) -> Option<Failure> {
+    let failure = THREAD_FAILURE.replace(0);
+    f();
+    let worst = THREAD_FAILURE.get();
+    THREAD_FAILURE.set(worst.max(failure));
+    [Failure::Command, Failure::Mismatch, Failure::Panic]
+        .into_iter()
+        .find(|failure| *failure as i32 == worst)
+}
+
+pub fn set_batch() {
+    BATCH.store(true, Ordering::Relaxed)
+}
+
+/// In batch mode a script stops at the first failed command.
+pub fn should_stop() -> bool {
+    BATCH.load(Ordering::Relaxed) && exit_code() != 0
+}
+
+pub fn construct(ir: &Value) -> Vec<String> {
+    let ops = ir.as_array().unwrap();
+    ops.iter()
+        .map(|x| String::from(x.as_str().unwrap()))
+        .collect()
+}
+
+fn record_loaded(level: State, path: &str, merge: bool) {
+    let loaded = &mut run_state().files;
+    if !merge {
+        loaded.retain(|(loaded_level, _)| *loaded_level != level);
+    }
+    loaded.push((level, path.to_string()));
+}
+
+/// The IR files loaded at `level`, the first loaded and the others merged
+/// into it.
+pub fn loaded_files(level: State) -> Vec<String> {
+    let run = run_state();
+    let files = run.files.iter().filter(|(loaded, _)| *loaded == level);
+    files.map(|(_, path)| path.clone()).collect()
+}
+
+/// Re-parse every loaded IR file, keeping breakpoints, watchpoints and the
+/// current level.
+pub fn reload() {
+    let state = run_state().level;
+    let loaded = run_state().files.clone();
+    if loaded.is_empty() {
+        println!("!!Nothing loaded");
+        fail(Failure::Command);
+        return;
+    }
+    let paths = loaded
+        .iter()
+        .map(|(_, path)| path.as_str())
+        .collect::<Vec<&str>>();
+    load_files(&paths);
+    if let Some(path) = run_state().equal_file.clone() {
+        if let Err(err) = parse_equal(&path) {
+            println!("!!Failed to load file {}: {}", path, err);
+            fail(Failure::Command);
+        }
+    }
+    run_state().level = state;
+    for path in paths {
+        println!("Reloaded {}", path);
+    }
+}
+
+/// Parse an IR or other input file by its extension, or else by its content.
+/// Strict JSON is tried before JSON5, which is slower but takes comments and
+/// trailing commas.
+pub fn parse_document(path: &str, text: &str) -> Result<Value, HestiaError> {
+    let extension = std::path::Path::new(decompress::strip_extension(path)).extension();
+    match extension.and_then(|extension| extension.to_str()) {
+        Some("yaml" | "yml") => yaml::parse(text),
+        Some("json5") => json5::parse(text),
+        Some("json") => serde_json::from_str(text).or_else(|_| json5::parse(text)),
+        _ if text.trim_start().starts_with(['{', '[', '/']) => {
+            serde_json::from_str(text).or_else(|_| json5::parse(text))
+        }
+        _ => yaml::parse(text),
+    }
+}
+
+/// Make the backing files of memories relative to the IR file that names them.
+fn resolve_backing(path: &str, ir: &mut Value) {
+    let dir = std::path::Path::new(path)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    let memorys = ir.get_mut("memory").and_then(Value::as_array_mut);
+    for memory in memorys.into_iter().flatten() {
+        if let Some(backing) = memory["backing"].as_str() {
+            memory["backing"] = Value::from(dir.join(backing).to_string_lossy());
+        }
+    }
+}
+
+pub fn read_ir(path: &str) -> Result<(State, Value), Box<dyn Error>> {
+    if decompress::strip_extension(path).ends_with(".mlir") {
+        let (level, ir) = mlir::read(&decompress::read_to_string(path)?)?;
+        schema::validate(level, &ir)?;
+        return Ok((level, ir));
+    }
+    parse_ir(path, &decompress::read_to_string(path)?)
+}
+
+/// The IR in `text`, read as if from the file `path`, which picks its format
+/// and the directory of its backing files.
+pub fn parse_ir(path: &str, text: &str) -> Result<(State, Value), Box<dyn Error>> {
+    let mut ir = parse_document(path, text)?;
+    resolve_backing(path, &mut ir);
+    let level = match ir["level"].as_str() {
+        Some("software") => State::Software,
+        Some("tor") => State::ToR,
+        Some("hec") => State::HEC,
+        _ => {
+            let message = format!("unknown level {}", ir["level"]);
+            return Err(HestiaError::new(ErrorCode::UnknownLevel, message).into());
+        }
+    };
+    schema::validate(level, &ir)?;
+    Ok((level, ir))
+}
+
+pub fn parse_file(path: &str) -> Result<(), Box<dyn Error>> {
+    let (level, ir) = read_ir(path)?;
+    load_ir(path, level, &ir)
+}
+
+/// Load the IR of a level read from `path`, replacing what was loaded at it.
+pub fn load_ir(path: &str, level: State, ir: &Value) -> Result<(), Box<dyn Error>> {
+    record_loaded(level, path, false);
+    record_input(&format!("load {}", path));
+    {
+        let mut run = run_state();
+        run.level = level;
+        run.cycle[cycle_index(level)] = 0;
+        run.watch_previous.clear();
+        run.checkpoints.clear();
+        run.history.clear();
+        run.stalls.lock().unwrap().clear();
+    }
+    let result = match level {
+        State::Software => software::parse_software(ir),
+        State::ToR => tor::parse_tor(ir),
+        _ => hec::parse_hec(ir),
+    };
+    // A level that fails to parse is left unloaded rather than half built
+    let mut run = run_state();
+    let loaded = match level {
+        State::Software => &mut run.has_software,
+        State::ToR => &mut run.has_tor,
+        _ => &mut run.has_hec,
+    };
+    *loaded = result.is_ok();
+    result
+}
+
+/// Add the modules of another HEC file to the loaded HEC design.
+pub fn merge_file(path: &str) -> Result<(), Box<dyn Error>> {
+    let (level, ir) = read_ir(path)?;
+    if level != State::HEC {
+        return Err("only hec files can be merged".into());
+    }
+    hec::merge_hec(&ir)?;
+    record_loaded(level, path, true);
+    record_input(&format!("merge {}", path));
+    Ok(())
+}
+
+/// Load IR files in order. A file of a level already loaded by an earlier
+/// file in `paths` is merged into it instead of replacing it.
+pub fn load_files(paths: &[&str]) {
+    let mut levels = vec![];
+    for path in paths {
+        let level = match read_ir(path) {
+            Ok((level, _)) => level,
+            Err(err) => {
+                println!("!!Failed to load file {}: {}", path, err);
+                fail(Failure::Command);
+                continue;
+            }
+        };
+        let result = if levels.contains(&level) {
+            merge_file(path)
+        } else {
+            parse_file(path)
+        };
+        if let Err(err) = result {
+            println!("!!Failed to load file {}: {}", path, err);
+            fail(Failure::Command);
+            continue;
+        }
+        levels.push(level);
+    }
+    reload_session();
+}
+
+fn collect_loaded(
+    software: fn() -> Vec<String>,
+    tor: fn() -> Vec<String>,
+    hec: fn() -> Vec<String>,
+) -> Vec<String> {
+    let mut names = vec![];
+    if run_state().has_software {
+        names.extend(software());
+    }
+    if run_state().has_tor {
+        names.extend(tor());
+    }
+    if run_state().has_hec {
+        names.extend(hec());
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+pub fn function_names() -> Vec<String> {
+    collect_loaded(
+        software::function_names,
+        tor::function_names,
+        hec::function_names,
+    )
+}
+
+pub fn memory_names() -> Vec<String> {
+    collect_loaded(software::memory_names, tor::memory_names, hec::memory_names)
+}
+
+pub fn signal_names() -> Vec<String> {
+    collect_loaded(software::signal_names, tor::signal_names, hec::signal_names)
+}
+
+/// How many times continue stopped at each breakpoint.
+pub fn breakpoint_hits() -> HashMap<String, usize> {
+    debugger()
+        .breakpoint
+        .iter()
+        .map(|(name, breakpoint)| (name.clone(), breakpoint.hits))
+        .collect()
+}
+
+pub fn breakpoint_names() -> Vec<String> {
+    let mut names: Vec<String> = debugger().breakpoint.keys().cloned().collect();
+    names.extend(debugger().return_break.keys().cloned());
+    names.sort();
+    names.dedup();
+    names
+}
+
+pub fn watchpoint_names() -> Vec<String> {
+    let mut names: Vec<String> = debugger().watchpoint.iter().cloned().collect();
+    names.extend(debugger().halt_watchpoint.keys().cloned());
+    names.extend(debugger().stream_watch.keys().cloned());
+    names.sort();
+    names.dedup();
+    names
+}
+
+pub fn checkpoint_names() -> Vec<String> {
+    run_state().checkpoints.keys().cloned().collect()
+}
+
+/// Names selected by a listing command: exact names, globs such as `A_*` or
+/// `%arg?`, and regexes written between slashes such as `/^op_1[0-9]$/`.
+pub struct NameFilter {
+    patterns: Vec<Regex>,
+}
+
+impl NameFilter {
+    /// Returns None, after reporting it, if one of the regexes is invalid.
+    pub fn new(patterns: &[&str]) -> Option<Self> {
+        let mut compiled = vec![];
+        for pattern in patterns {
+            let regex = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+                Some(regex) if !regex.is_empty() => regex.to_string(),
+                _ => glob_to_regex(strip_sigil(pattern)),
+            };
+            match Regex::new(&regex) {
+                Ok(regex) => compiled.push(regex),
+                Err(_) => {
+                    println!("!!Invalid pattern {}", pattern);
+                    fail(Failure::Command);
+                    return None;
+                }
+            }
+        }
+        Some(NameFilter { patterns: compiled })
+    }
+
+    pub fn all() -> Self {
+        NameFilter { patterns: vec![] }
+    }
+
+    /// Whether `text` is a glob or regex rather than a plain name.
+    pub fn is_pattern(text: &str) -> bool {
+        text.contains(['*', '?', '['])
+            || (text.len() > 2 && text.starts_with('/') && text.ends_with('/'))
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.patterns.is_empty() || self.patterns.iter().any(|regex| regex.is_match(name))
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' | ']' => regex.push(c),
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+pub fn show_variable(vars: &[&str]) {
+    let (radix, vars) = radix_override(vars);
+    let Some(filter) = NameFilter::new(vars) else {
+        return;
+    };
+    with_radix(radix, || match debug_state() {
+        State::Software => software::show_variable(&filter),
+        State::ToR => tor::show_variable(&filter),
+        State::HEC => hec::show_variable(&filter),
+        _ => panic!("Cosim mode"),
+    })
+}
+
+pub(crate) fn memory_levels() -> Vec<State> {
+    match debug_state() {
+        State::HEC => vec![State::HEC],
+        _ => {
+            let mut levels = vec![];
+            if run_state().has_software {
+                levels.push(State::Software);
+            }
+            if run_state().has_tor {
+                levels.push(State::ToR);
+            }
+            levels
+        }
+    }
+}
+
+pub fn dump_memory(level: State, memory: &str) -> Option<Vec<VALUE>> {
+    match level {
+        State::Software => software::dump_memory(memory),
+        State::ToR => tor::dump_memory(memory),
+        State::HEC => hec::dump_memory(memory),
+        _ => None,
+    }
+}
+
+/// The words of a memory, in a copy that shares their pages, to compare
+/// without copying them out.
+pub fn memory_store(level: State, memory: &str) -> Option<PagedStore> {
+    match level {
+        State::Software => software::memory_store(memory),
+        State::ToR => tor::memory_store(memory),
+        State::HEC => hec::memory_store(memory),
+        _ => None,
+    }
+}
+
+pub fn restore_memory(level: State, memory: &str, store: &[VALUE]) {
+    match level {
+        State::Software => software::restore_memory(memory, store),
+        State::ToR => tor::restore_memory(memory, store),
+        State::HEC => hec::restore_memory(memory, store),
+        _ => (),
+    }
+}
+
+/// Feed a value into a stream (ToR) or FIFO (HEC) from outside the design.
+pub fn push_stream(level: State, stream: &str, value: VALUE) -> Result<(), String> {
+    match level {
+        State::ToR => tor::push_stream(stream, value),
+        State::HEC => hec::push_stream(stream, value),
+        _ => Err(format!("No streams at the {} level", level_name(level))),
+    }
+}
+
+pub fn dump_variable(level: State, var: &str) -> Vec<VALUE> {
+    match level {
+        State::Software => software::dump_variable(var),
+        State::ToR => tor::dump_variable(var),
+        State::HEC => hec::dump_variable(var),
+        _ => vec![],
+    }
+}
+
+pub fn restore_variable(level: State, var: &str, values: &[VALUE]) {
+    match level {
+        State::Software => software::restore_variable(var, values),
+        State::ToR => tor::restore_variable(var, values),
+        State::HEC => hec::restore_variable(var, values),
+        _ => (),
+    }
+}
+
+/// Exchange the addressed words of a memory with the values paired with them.
+pub fn swap_memory(level: State, memory: &str, words: &mut [(usize, VALUE)]) {
+    match level {
+        State::Software => software::swap_memory(memory, words),
+        State::ToR => tor::swap_memory(memory, words),
+        State::HEC => hec::swap_memory(memory, words),
+        _ => (),
+    }
+}
+
+/// The words of a memory at `indices` within it, paired with their
+/// addresses, None for an undefined memory.
+fn memory_words(
+    level: State,
+    memory: &str,
+    indices: impl Iterator<Item = usize>,
+) -> Option<Vec<(usize, VALUE)>> {
+    let store = memory_store(level, memory)?;
+    Some(indices.map_while(|i| Some((i, store.get(i)?))).collect())
+}
+
+pub fn load_memory(memory: &str, store: &[&str]) {
+    let mut edits = vec![];
+    for level in memory_levels() {
+        let before = memory_words(level, memory, 0..store.len());
+        match level {
+            State::Software => software::load_memory(memory, store),
+            State::ToR => tor::load_memory(memory, store),
+            State::HEC => hec::load_memory(memory, store),
+            _ => (),
+        }
+        if let Some(before) = before {
+            edits.push(Edit::Memory(level, memory.to_string(), before));
+        }
+    }
+    if !edits.is_empty() {
+        record_input(&format!("load_memory {} {}", memory, store.join(" ")));
+    }
+    record_edits(edits);
+}
+
+pub fn force(memory: &str, index: &str, value: &str) {
+    let index = match index.parse::<usize>() {
+        Ok(index) => index,
+        Err(_) => {
+            println!("!!Invalid index {}", index);
+            fail(Failure::Command);
+            return;
+        }
+    };
+    let mut edits = vec![];
+    for level in memory_levels() {
+        if let Some(store) = memory_store(level, memory) {
+            let Some(before) = store.get(index) else {
+                println!("!!Index {} out of range for {}", index, memory);
+                fail(Failure::Command);
+                return;
+            };
+            let mut words = vec![(index, build_value(&before.get_type(), &value.to_string()))];
+            swap_memory(level, memory, &mut words);
+            edits.push(Edit::Memory(level, memory.to_string(), words));
+        }
+    }
+    if edits.is_empty() {
+        println!("!!Undefined memory {}", memory);
+        fail(Failure::Command);
+    } else {
+        record_input(&format!("force {} {} {}", memory, index, value));
+    }
+    record_edits(edits);
+}
+
+pub fn set_var(var: &str, value: &str) {
+    let level = run_state().level;
+    let before = dump_variable(level, var);
+    if before.is_empty() {
+        println!("!!Undefined variable {}", var);
+        fail(Failure::Command);
+        return;
+    }
+    let after = before
+        .iter()
+        .map(|old| build_value(&old.get_type(), &value.to_string()))
+        .collect::<Vec<VALUE>>();
+    restore_variable(level, var, &after);
+    record_input(&format!("set_var {} {}", var, value));
+    record_edits(vec![Edit::Variable(level, var.to_string(), before, after)]);
+}
+
+pub fn show_memory(memorys: &[&str]) {
+    let (radix, memorys) = radix_override(memorys);
+    let Some(filter) = NameFilter::new(memorys) else {
+        return;
+    };
+    with_radix(radix, || match debug_state() {
+        State::Software => software::show_memory(&filter),
+        State::ToR => tor::show_memory(&filter),
+        State::HEC => hec::show_memory(&filter),
+        _ => panic!("Cosim mode"),
+    })
+}
+
+/// Show how many words each memory holds and how many bytes its pages
+/// written so far take.
+pub fn show_memory_usage(memorys: &[&str]) {
+    let Some(filter) = NameFilter::new(memorys) else {
+        return;
+    };
+    let mut usage = match debug_state() {
+        State::Software => software::memory_usage(),
+        State::ToR => tor::memory_usage(),
+        State::HEC => hec::memory_usage(),
+        _ => panic!("Cosim mode"),
+    };
+    usage.retain(|(name, _, _)| filter.matches(name));
+    usage.sort();
+    if json_output() {
+        let usage = usage.into_iter().map(|(name, words, bytes)| {
+            (name, serde_json::json!({"words": words, "bytes": bytes}))
+        });
+        return print_json(Value::Object(usage.collect()));
+    }
+    println!("!!MEMORY USAGE:");
+    let rows = usage
+        .iter()
+        .map(|(name, words, bytes)| {
+            (
+                name.clone(),
+                format!("{} words, {} bytes allocated", words, bytes),
+            )
+        })
+        .collect::<Vec<_>>();
+    print_table("\t", &rows);
+    let total: usize = usage.iter().map(|(_, _, bytes)| bytes).sum();
+    println!("Total: {} bytes allocated", total);
+}
+
+pub fn execute_continue() {
+    run(|| match debug_state() {
+        State::Software => software::execute_continue(),
+        State::ToR => tor::execute_continue(),
+        State::HEC => hec::execute_continue(),
+        _ => (),
+    })
+}
+
+/// Continue until the named op or state, which may be a glob or regex, is
+/// about to execute, leaving the breakpoints as they are.
+pub fn until(location: &str) {
+    let Some(filter) = NameFilter::new(&[location]) else {
+        return;
+    };
+    UNTIL.set(Some(filter));
+    execute_continue();
+    UNTIL.set(None);
+}
+
+/// Continue until the induction variable of the named for loop becomes `k`,
+/// through a halting watchpoint that replaces any watch on it meanwhile.
+pub fn until_iter(name: &str, k: &str) {
+    let name = strip_sigil(name);
+    let iter_name = match debug_state() {
+        State::Software => software::loop_iter_name(name),
+        State::ToR => tor::loop_iter_name(name),
+        _ => None,
+    };
+    let Some(iter_name) = iter_name else {
+        println!("!!No for loop {}", name);
+        fail(Failure::Command);
+        return;
+    };
+    let predicate = format!("== {}", k);
+    let Some(condition) = Condition::parse(&format!("{} {}", iter_name, predicate)) else {
+        println!("!!Invalid iteration {}", k);
+        fail(Failure::Command);
+        return;
+    };
+    let previous = debugger()
+        .halt_watchpoint
+        .insert(iter_name.clone(), WatchHalt::When(condition, predicate));
+    execute_continue();
+    let halts = &mut debugger().halt_watchpoint;
+    match previous {
+        Some(halt) => halts.insert(iter_name, halt),
+        None => halts.remove(&iter_name),
+    };
+}
+
+/// An active instance, or a software control frame, as front-ends see it: the
+/// function, where it is, the op it is at if that has a name, and the
+/// variables it can see.
+pub struct Frame {
+    pub function: String,
+    pub location: String,
+    pub op: Option<String>,
+    pub variables: Vec<(String, VALUE)>,
+}
+
+/// Software control frames from the innermost one, or the active instances.
+pub fn frames() -> Vec<Frame> {
+    match debug_state() {
+        State::Software => software::frames(),
+        State::ToR | State::Cosim => tor::frames(),
+        State::HEC => hec::frames(),
+    }
+}
+
+/// The IR file loaded for the current level that defines `name`, with the line
+/// of its definition, or of its first mention if none is found.
+pub fn source_line(name: &str) -> Option<(String, usize)> {
+    let level = match debug_state() {
+        State::Cosim => State::ToR,
+        level => level,
+    };
+    let quoted = format!("\"{}\"", name);
+    let definitions = [
+        format!("\"name\": {}", quoted),
+        format!("\"state\": {}", quoted),
+    ];
+    for (loaded_level, path) in run_state().files.iter() {
+        if *loaded_level != level {
+            continue;
+        }
+        let Ok(text) = decompress::read_to_string(path) else {
+            continue;
+        };
+        let line = text
+            .lines()
+            .position(|line| definitions.iter().any(|def| line.contains(def.as_str())))
+            .or_else(|| text.lines().position(|line| line.contains(&quoted)));
+        if let Some(line) = line {
+            return Some((path.clone(), line + 1));
+        }
+    }
+    None
+}
+
+pub fn show_active() {
+    match debug_state() {
+        State::Software => (),
+        State::ToR => tor::show_active(),
+        State::HEC => hec::show_active(),
+        _ => panic!("Cosim mode"),
+    }
+}
+
+pub fn call_function(function_name: &str, args: &[&str]) -> Result<(), String> {
+    let result = match debug_state() {
+        State::Software => software::call_function(function_name, args),
+        State::ToR => tor::call_function(function_name, args),
+        State::HEC => hec::call_function(function_name, args),
+        _ => panic!("Cosim mode"),
+    };
+    if result.is_ok() {
+        record_input(format!("call {} {}", function_name, args.join(" ")).trim_end());
+    }
+    rebase_history();
+    result
+}
+
+pub fn step(depth: usize) {
+    run(|| match debug_state() {
+        State::Software => {
+            software::step(depth, false);
+        }
+        State::ToR => {
+            tor::step(depth, false);
+        }
+        State::HEC => {
+            hec::step(depth, false);
+        }
+        State::Cosim => equal::cosim_until(cycle_count(State::Cosim) + depth),
+    })
+}
+
+/// Whether the function called at `level` has returned.
+pub fn finished(level: State) -> bool {
+    match level {
+        State::Software => software::finish(),
+        State::ToR => tor::finish(),
+        State::HEC => hec::finish(),
+        State::Cosim => tor::finish() && hec::finish(),
+    }
+}
+
+/// `next`: like `step`, but a call is run to its return as one step.
+pub fn step_over() {
+    run(|| match debug_state() {
+        State::Software => software::step_over(),
+        State::ToR => tor::step_over(),
+        _ => (),
+    })
+}
+
+/// `finish`: run until the current function (software) or instance (tor) returns.
+pub fn step_out() {
+    run(|| match debug_state() {
+        State::Software => software::step_out(),
+        State::ToR => tor::step_out(),
+        _ => (),
+    })
+}
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+fn simulate<F: FnOnce()>(f: F) {
+    INTERRUPT.store(false, Ordering::SeqCst);
+    RUNNING.store(true, Ordering::SeqCst);
+    let start = Instant::now();
+    f();
+    profile::add_total(start.elapsed());
+    RUNNING.store(false, Ordering::SeqCst);
+    vcd::flush();
+    perfetto::flush();
+}
+
+/// Simulate and record how far the current level advanced as a `step`, which
+/// replays the same cycles whether they were run by step, continue or until.
+fn run<F: FnOnce()>(f: F) {
+    let level = run_state().level;
+    let before = cycle_count(level);
+    simulate(f);
+    let after = cycle_count(level);
+    if after > before {
+        record_input(&format!("step {}", after - before));
+    }
+}
+
+/// Set by Ctrl-C while a simulation is running; the step loops poll it at
+/// every cycle boundary and stop with all state intact.
+pub fn interrupted() -> bool {
+    INTERRUPT.load(Ordering::Relaxed)
+}
+
+pub struct Progress {
+    start: Instant,
+    last: Instant,
+    last_cycle: usize,
+    /// Cycles fast-forwarded over, reported when the step ends.
+    skipped: usize,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Progress {
+            start: Instant::now(),
+            last: Instant::now(),
+            last_cycle: 0,
+            skipped: 0,
+        }
+    }
+
+    pub fn skip(&mut self, cycles: usize) {
+        self.skipped += cycles;
+    }
+
+    /// Written straight to stderr so it stays out of captured output, the
+    /// server replies and the transcript.
+    pub fn report(&mut self, cycle: usize, active: usize) {
+        let elapsed = self.last.elapsed();
+        if elapsed < PROGRESS_INTERVAL || !verbose(Verbosity::Normal) {
+            return;
+        }
+        eprintln!(
+            "Progress: {} cycles, {} active, {:.0} cycles/s ({:.1}s elapsed)",
+            cycle,
+            active,
+            (cycle - self.last_cycle) as f64 / elapsed.as_secs_f64(),
+            self.start.elapsed().as_secs_f64()
+        );
+        self.last = Instant::now();
+        self.last_cycle = cycle;
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.skipped > 0 && verbose(Verbosity::Normal) {
+            println!("Skipped {} quiescent cycles", self.skipped);
+        }
+    }
+}
+
+/// Whether the step loops may skip ahead over cycles in which nothing
+/// changes. Skipped cycles are not sampled, logged or counted as stalls, so
+/// this is off while anything records every cycle.
+pub fn fast_forward() -> bool {
+    options().fast_forward
+        && !run_state().every_cycle
+        && options().stall_limit == 0
+        && WATCH_CSV.with_borrow(Option::is_none)
+        && debugger().watchpoint.is_empty()
+        && !vcd::vcd_enabled()
+        && !perfetto::perfetto_enabled()
+        && !rtl::rtl_connected()
+}
+
+/// Step every cycle of the current simulation, not fast-forwarding over any.
+pub(crate) fn step_every_cycle() {
+    run_state().every_cycle = true;
+}
+
+/// The checks a step loop makes around every cycle, worked out once before
+/// the loop so that a long `continue` with nothing set skips them all.
+#[derive(Debug, Clone, Copy)]
+pub struct StepChecks {
+    /// Whether the active operations have to be tested for breakpoints
+    pub breakpoints: bool,
+    /// Whether a returning function has to be tested for `break_return`
+    pub returns: bool,
+    /// Whether anything is watched, displayed or logged after each cycle
+    pub watches: bool,
+    pub fast_forward: bool,
+    /// Whether tracepoints print the operations that run
+    pub tracepoints: bool,
+}
+
+pub fn step_checks(breakpoint: bool) -> StepChecks {
+    let fast_forward = fast_forward();
+    let debugger = debugger();
+    let until = UNTIL.with_borrow(Option::is_some);
+    StepChecks {
+        breakpoints: breakpoint && (until || !debugger.breakpoint.is_empty()),
+        returns: breakpoint && !debugger.return_break.is_empty(),
+        watches: !debugger.watchpoint.is_empty()
+            || !debugger.halt_watchpoint.is_empty()
+            || !debugger.aggregate_watch.is_empty()
+            || WATCH_CSV.with_borrow(Option::is_some)
+            || display_enabled()
+            || watch_table_enabled(),
+        fast_forward,
+        tracepoints: !debugger.tracepoint.is_empty(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compare {
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+}
+
+const COMPARES: &[(&str, Compare)] = &[
+    ("==", Compare::Eq),
+    ("!=", Compare::Ne),
+    ("<=", Compare::Le),
+    (">=", Compare::Ge),
+    ("<", Compare::Lt),
+    (">", Compare::Gt),
+];
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Variable(String),
+    Literal(String),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        if text == "true" || text == "false" || text.parse::<f64>().is_ok() {
+            Some(Operand::Literal(text.to_string()))
+        } else {
+            Some(Operand::Variable(strip_sigil(text).to_string()))
+        }
+    }
+
+    fn variable(&self, lookup: &dyn Fn(&str) -> Option<VALUE>) -> Option<VALUE> {
+        match self {
+            Operand::Variable(var) => lookup(var),
+            Operand::Literal(_) => None,
+        }
+    }
+
+    /// Literals take the type of the other operand, `f64` if both are literals.
+    fn eval(&self, variable: Option<VALUE>, other: Option<VALUE>) -> Option<VALUE> {
+        match self {
+            Operand::Variable(_) => variable,
+            Operand::Literal(text) => {
+                let data_type = other.map_or(String::from("f64"), |value| value.get_type());
+                parse_value(&data_type, text)
+            }
+        }
+    }
+}
+
+/// A comparison such as `%i == 7`, evaluated against an instance's variables
+/// with the `cmp_*` operations, so that both sides must have the same type.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    lhs: Operand,
+    compare: Compare,
+    rhs: Operand,
+    text: String,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Option<Self> {
+        let (position, symbol, compare) = COMPARES
+            .iter()
+            .filter_map(|(symbol, compare)| text.find(symbol).map(|at| (at, *symbol, *compare)))
+            .min_by_key(|(at, symbol, _)| (*at, usize::MAX - symbol.len()))?;
+        Some(Condition {
+            lhs: Operand::parse(&text[..position])?,
+            compare,
+            rhs: Operand::parse(&text[position + symbol.len()..])?,
+            text: text.trim().to_string(),
+        })
+    }
+
+    /// Variables that are missing or hold no value or X make the condition
+    /// false.
+    pub fn eval(&self, lookup: &dyn Fn(&str) -> Option<VALUE>) -> bool {
+        let lhs_variable = self.lhs.variable(lookup);
+        let rhs_variable = self.rhs.variable(lookup);
+        let lhs = self.lhs.eval(lhs_variable, rhs_variable);
+        let rhs = self.rhs.eval(rhs_variable, lhs_variable);
+        let (Some(lhs), Some(rhs)) = (lhs, rhs) else {
+            return false;
+        };
+        if matches!(lhs, VALUE::ERROR | VALUE::Control | VALUE::X)
+            || matches!(rhs, VALUE::ERROR | VALUE::Control | VALUE::X)
+        {
+            return false;
+        }
+        let values = vec![lhs, rhs];
+        let result = match self.compare {
+            Compare::Eq => cmp_eq(&values),
+            Compare::Ne => cmp_ne(&values),
+            Compare::Le => cmp_lte(&values),
+            Compare::Ge => cmp_gte(&values),
+            Compare::Lt => cmp_lt(&values),
+            Compare::Gt => cmp_gt(&values),
+        };
+        result == VALUE::Bool(true)
+    }
+}
+
+/// Names may be written MLIR-style, `%op_12` is the same as `op_12`.
+fn strip_sigil(name: &str) -> &str {
+    name.strip_prefix('%').unwrap_or(name)
+}
+
+struct Breakpoint {
+    /// Set when the breakpoint is a glob or regex over names
+    filter: Option<NameFilter>,
+    condition: Option<Condition>,
+    /// Times continue stopped at the breakpoint
+    hits: usize,
+}
+
+impl Breakpoint {
+    fn hit(&self, lookup: &dyn Fn(&str) -> Option<VALUE>) -> bool {
+        match &self.condition {
+            Some(condition) => condition.eval(lookup),
+            None => true,
+        }
+    }
+}
+
+/// Breakpoints, watchpoints, tracepoints, macros and script variables, which
+/// are shared by every simulation.
+#[derive(Default)]
+struct Debugger {
+    breakpoint: HashMap<String, Breakpoint>,
+    /// Conditions of `break_return` on the value returned by each function,
+    /// with the predicate as written after `when`.
+    return_break: BTreeMap<String, (Condition, String)>,
+    watchpoint: HashSet<String>,
+    /// Watchpoints that stop stepping instead of printing every cycle.
+    halt_watchpoint: BTreeMap<String, WatchHalt>,
+    /// Watchpoints on reductions over memories, keyed by the expression.
+    aggregate_watch: BTreeMap<String, Aggregate>,
+    stream_watch: BTreeMap<String, StreamWatch>,
+    watch_rows: BTreeMap<String, WatchEntry>,
+    tracepoint: BTreeMap<String, Vec<Tracepoint>>,
+    trace_log: Vec<String>,
+    script_variable: BTreeMap<String, String>,
+    macros: BTreeMap<String, Vec<String>>,
+    /// Values printed by the last display, to report only what changed.
+    display_shadow: BTreeMap<String, String>,
+}
+
+thread_local! {
+    /// The debugger of the shell on this thread.
+    static DEBUGGER: RefCell<Debugger> = RefCell::new(Debugger::default());
+    /// Location of a running `until`, checked like a breakpoint but not listed.
+    static UNTIL: RefCell<Option<NameFilter>> = const { RefCell::new(None) };
+    /// Whether some breakpoint is a pattern, so that names missing from the
+    /// breakpoints still have to be matched against them.
+    static PATTERN_BREAKPOINT: Cell<bool> = const { Cell::new(false) };
+}
+
+fn debugger() -> RefMut<'static, Debugger> {
+    simulator::thread_cell(&DEBUGGER).borrow_mut()
+}
+
+/// A reduction over a slice of a memory such as `sum(A[0..16])`, watched like
+/// a variable named by the expression.
+#[derive(Clone)]
+struct Aggregate {
+    reduce: String,
+    memory: String,
+    start: usize,
+    end: usize,
+}
+
+impl Aggregate {
+    fn parse(text: &str) -> Option<Self> {
+        let pattern = Regex::new(r"^(sum|min|max)\(%?([\w.]+)\[(\d+)\.\.(\d+)\]\)$").unwrap();
+        let captures = pattern.captures(text)?;
+        let aggregate = Aggregate {
+            reduce: captures[1].to_string(),
+            memory: captures[2].to_string(),
+            start: captures[3].parse().ok()?,
+            end: captures[4].parse().ok()?,
+        };
+        (aggregate.start < aggregate.end).then_some(aggregate)
+    }
+
+    /// None if the memory does not exist at `level` or is too small.
+    fn eval(&self, level: State) -> Option<VALUE> {
+        let store = dump_memory(level, &self.memory)?;
+        let values = store.get(self.start..self.end)?;
+        let mut result = values[0];
+        for value in &values[1..] {
+            if matches!(result, VALUE::ERROR | VALUE::Control | VALUE::X) {
+                break;
+            }
+            if matches!(value, VALUE::ERROR | VALUE::Control) {
+                return Some(VALUE::ERROR);
+            }
+            if *value == VALUE::X {
+                return Some(VALUE::X);
+            }
+            let pair = vec![*value, result];
+            result = match self.reduce.as_str() {
+                "sum" => add(&result.get_type(), &pair),
+                "min" if cmp_lt(&pair) == VALUE::Bool(true) => *value,
+                "max" if cmp_gt(&pair) == VALUE::Bool(true) => *value,
+                _ => result,
+            };
+        }
+        Some(result)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum WatchHalt {
+    Change,
+    /// Stop when the value changes to one satisfying the condition, the
+    /// string is the condition as written after `when`
+    When(Condition, String),
+}
+
+fn update_pattern_breakpoint() {
+    let breakpoints = &debugger().breakpoint;
+    PATTERN_BREAKPOINT.set(breakpoints.values().any(|b| b.filter.is_some()));
+}
+
+/// `breakpoint <name> [if <condition>]`
+pub fn set_breakpoint(args: &[&str]) {
+    let condition = match args.get(1) {
+        None => None,
+        Some(&"if") if args.len() > 2 => match Condition::parse(&args[2..].join(" ")) {
+            Some(condition) => Some(condition),
+            None => {
+                println!("!!Invalid condition {}", args[2..].join(" "));
+                fail(Failure::Command);
+                return;
+            }
+        },
+        Some(_) => {
+            println!("!!Usage: breakpoint <name> [if <condition>]");
+            fail(Failure::Command);
+            return;
+        }
+    };
+    let filter = match NameFilter::is_pattern(args[0]) {
+        true => match NameFilter::new(&args[..1]) {
+            Some(filter) => Some(filter),
+            None => return,
+        },
+        false => None,
+    };
+    let breakpoint = Breakpoint {
+        filter,
+        condition,
+        hits: 0,
+    };
+    debugger()
+        .breakpoint
+        .insert(strip_sigil(args[0]).to_string(), breakpoint);
+    update_pattern_breakpoint();
+}
+
+pub fn unset_breakpoint(b: &str) {
+    debugger().breakpoint.remove(strip_sigil(b));
+    debugger().return_break.remove(b);
+    update_pattern_breakpoint();
+}
+
+/// `break_return <function> when <comparison>`
+pub fn set_return_break(args: &[&str]) {
+    if args.len() < 3 || args[1] != "when" {
+        println!("!!Usage: break_return <function> when <comparison>");
+        fail(Failure::Command);
+        return;
+    }
+    let predicate = args[2..].join(" ");
+    match Condition::parse(&format!("ret {}", predicate)) {
+        Some(condition) => {
+            debugger()
+                .return_break
+                .insert(args[0].to_string(), (condition, predicate));
+        }
+        None => {
+            println!("!!Invalid condition {}", predicate);
+            fail(Failure::Command);
+        }
+    }
+}
+
+/// Whether one of the values returned by `function` satisfies its
+/// `break_return` condition, reporting the return if so.
+pub fn test_return_break(function: &str, values: &[VALUE]) -> bool {
+    let Some((condition, predicate)) = debugger().return_break.get(function).cloned() else {
+        return false;
+    };
+    let hit = values
+        .iter()
+        .any(|value| condition.eval(&|var| (var == "ret").then_some(*value)));
+    if hit {
+        println!("Return from {} {}: {:?}", function, predicate, values);
+    }
+    hit
+}
+
+pub fn show_breakpoint() {
+    let names = breakpoint_names();
+    let debugger = debugger();
+    let breakpoints = &debugger.breakpoint;
+    if json_output() {
+        let breakpoints = names
+            .into_iter()
+            .map(|name| {
+                let Some(breakpoint) = breakpoints.get(&name) else {
+                    let predicate = &debugger.return_break[&name].1;
+                    return serde_json::json!({"name": name, "return": predicate});
+                };
+                let condition = breakpoint.condition.as_ref().map(|c| &c.text);
+                serde_json::json!({"name": name, "condition": condition, "hits": breakpoint.hits})
+            })
+            .collect();
+        return print_json(Value::Array(breakpoints));
+    }
+    for name in names {
+        if let Some(breakpoint) = breakpoints.get(&name) {
+            let hits = match breakpoint.hits {
+                0 => String::new(),
+                hits => format!(" ({} hits)", hits),
+            };
+            match &breakpoint.condition {
+                Some(condition) => println!("{} if {}{}", name, condition.text, hits),
+                None => println!("{}{}", name, hits),
+            }
+        }
+        if let Some((_, predicate)) = debugger.return_break.get(&name) {
+            println!("{} return {}", name, predicate);
+        }
+    }
+}
+
+/// Whether `b` is a breakpoint, or matches a glob or regex breakpoint, whose
+/// condition, if any, holds in the environment seen through `lookup`.
+pub fn test_breakpoint(b: &String, lookup: &dyn Fn(&str) -> Option<VALUE>) -> bool {
+    if TRACING.get() {
+        log_trace(b, lookup);
+        return false;
+    }
+    if UNTIL.with_borrow(|until| until.as_ref().is_some_and(|until| until.matches(b))) {
+        return true;
+    }
+    let breakpoints = &mut debugger().breakpoint;
+    if let Some(breakpoint) = breakpoints.get_mut(b) {
+        if breakpoint.filter.is_none() && breakpoint.hit(lookup) {
+            breakpoint.hits += 1;
+            return true;
+        }
+    }
+    if !PATTERN_BREAKPOINT.get() {
+        return false;
+    }
+    breakpoints.values_mut().any(|breakpoint| {
+        let hit = breakpoint
+            .filter
+            .as_ref()
+            .is_some_and(|filter| filter.matches(b))
+            && breakpoint.hit(lookup);
+        breakpoint.hits += hit as usize;
+        hit
+    })
+}
+
+/// An expression logged by `trace`, a variable or a comparison on variables.
+#[derive(Clone)]
+struct Tracepoint {
+    variable: Option<Operand>,
+    condition: Option<Condition>,
+    text: String,
+}
+
+impl Tracepoint {
+    fn eval(&self, lookup: &dyn Fn(&str) -> Option<VALUE>) -> String {
+        if let Some(condition) = &self.condition {
+            return condition.eval(lookup).to_string();
+        }
+        match self
+            .variable
+            .as_ref()
+            .and_then(|variable| variable.variable(lookup))
+        {
+            Some(value) => format!("{:?}", value),
+            None => String::from("?"),
+        }
+    }
+}
+
+thread_local! {
+    static TRACE_FILE: RefCell<Option<File>> = const { RefCell::new(None) };
+    /// Set while the ops about to execute are walked for tracepoints, during
+    /// which `test_breakpoint` logs them and never breaks.
+    static TRACING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// `trace <name> <expr>`
+pub fn set_tracepoint(args: &[&str]) {
+    let text = args[1..].join(" ");
+    let condition = Condition::parse(&text);
+    let variable = match condition {
+        Some(_) => None,
+        None => Operand::parse(&text),
+    };
+    if !matches!(variable, None | Some(Operand::Variable(_))) {
+        println!("!!Invalid trace expression {}", text);
+        fail(Failure::Command);
+        return;
+    }
+    let tracepoint = Tracepoint {
+        variable,
+        condition,
+        text,
+    };
+    debugger()
+        .tracepoint
+        .entry(strip_sigil(args[0]).to_string())
+        .or_default()
+        .push(tracepoint);
+}
+
+pub fn unset_tracepoint(name: &str) {
+    debugger().tracepoint.remove(strip_sigil(name));
+}
+
+pub fn tracepoint_names() -> Vec<String> {
+    debugger().tracepoint.keys().cloned().collect()
+}
+
+/// `show_trace [clear]`: the tracepoints and the entries logged so far.
+pub fn show_trace(clear: bool) {
+    let mut guard = debugger();
+    let debugger = &mut *guard;
+    let log = &mut debugger.trace_log;
+    if clear {
+        log.clear();
+        return;
+    }
+    if json_output() {
+        let tracepoints: Vec<Value> = debugger
+            .tracepoint
+            .iter()
+            .flat_map(|(name, tracepoints)| {
+                tracepoints
+                    .iter()
+                    .map(move |t| serde_json::json!({"name": name, "expr": t.text}))
+            })
+            .collect();
+        return print_json(serde_json::json!({"tracepoints": tracepoints, "log": log}));
+    }
+    for (name, tracepoints) in &debugger.tracepoint {
+        for tracepoint in tracepoints {
+            println!("{}: {}", name, tracepoint.text);
+        }
+    }
+    for entry in log.iter() {
+        println!("{}", entry);
+    }
+}
+
+/// `trace_file <file>|off`: also append trace entries to a file.
+pub fn set_trace_file(path: &str) {
+    if path == "off" {
+        TRACE_FILE.set(None);
+        return;
+    }
+    match File::options().create(true).append(true).open(path) {
+        Ok(file) => TRACE_FILE.set(Some(file)),
+        Err(_) => {
+            println!("!!Failed to write file {}", path);
+            fail(Failure::Command);
+        }
+    }
+}
+
+fn log_trace(name: &str, lookup: &dyn Fn(&str) -> Option<VALUE>) {
+    let Some(tracepoints) = debugger().tracepoint.get(name).cloned() else {
+        return;
+    };
+    for tracepoint in tracepoints {
+        let entry = format!(
+            "@{} {}: {} = {}",
+            current_cycle(),
+            name,
+            tracepoint.text,
+            tracepoint.eval(lookup)
+        );
+        TRACE_FILE.with_borrow_mut(|file| {
+            if let Some(file) = file {
+                let _ = writeln!(file, "{}", entry);
+            }
+        });
+        debugger().trace_log.push(entry);
+    }
+}
+
+/// Log the tracepoints of the ops about to execute, walked by `mark` through
+/// the same `test_breakpoint` calls that check breakpoints.
+pub fn trace_ops<F: FnOnce()>(mark: F) {
+    if debugger().tracepoint.is_empty() || is_replaying() {
+        return;
+    }
+    TRACING.set(true);
+    mark();
+    TRACING.set(false);
+}
+
+thread_local! {
+    /// Session file holding the breakpoints, watchpoints and tracepoints, read
+    /// again after every load so that they survive regenerating the IR.
+    static SESSION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// `save_session <file>`
+pub fn save_session(path: &str) -> std::io::Result<()> {
+    let breakpoints: Vec<Value> = debugger()
+        .breakpoint
+        .iter()
+        .map(|(name, breakpoint)| {
+            let condition = breakpoint.condition.as_ref().map(|c| &c.text);
+            serde_json::json!({"name": name, "condition": condition, "hits": breakpoint.hits})
+        })
+        .collect();
+    let return_breaks: Vec<Value> = debugger().return_break
+        .iter()
+        .map(|(function, (_, predicate))| serde_json::json!({"function": function, "when": predicate}))
+        .collect();
+    let mut watchpoints: Vec<Value> = debugger()
+        .watchpoint
+        .iter()
+        .map(|name| serde_json::json!({"name": name}))
+        .collect();
+    for (name, halt) in &debugger().halt_watchpoint {
+        watchpoints.push(match halt {
+            WatchHalt::Change => serde_json::json!({"name": name, "halt": "change"}),
+            WatchHalt::When(_, predicate) => {
+                serde_json::json!({"name": name, "halt": "when", "condition": predicate})
+            }
+        });
+    }
+    for (name, mode) in &debugger().stream_watch {
+        let mode = format!("{:?}", mode).to_lowercase();
+        watchpoints.push(serde_json::json!({"name": name, "stream": mode}));
+    }
+    let tracepoints: Vec<Value> = debugger()
+        .tracepoint
+        .iter()
+        .flat_map(|(name, tracepoints)| {
+            tracepoints
+                .iter()
+                .map(move |t| serde_json::json!({"name": name, "expr": t.text}))
+        })
+        .collect();
+    let session = serde_json::json!({
+        "breakpoints": breakpoints,
+        "return_breaks": return_breaks,
+        "watchpoints": watchpoints,
+        "tracepoints": tracepoints,
+    });
+    std::fs::write(path, serde_json::to_string_pretty(&session)?)?;
+    SESSION.set(Some(path.to_string()));
+    Ok(())
+}
+
+/// `load_session <file>`: replace the breakpoints, watchpoints and
+/// tracepoints with those saved in the file, which is read again after later
+/// loads.
+pub fn load_session(path: &str) -> Result<(), Box<dyn Error>> {
+    let session: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let entries = |key: &str| session[key].as_array().cloned().unwrap_or_default();
+    let text = |entry: &Value, key: &str| entry[key].as_str().map(String::from);
+    debugger().breakpoint.clear();
+    debugger().return_break.clear();
+    debugger().watchpoint.clear();
+    debugger().halt_watchpoint.clear();
+    debugger().stream_watch.clear();
+    debugger().aggregate_watch.clear();
+    debugger().tracepoint.clear();
+    run_state().watch_previous.clear();
+    for entry in entries("breakpoints") {
+        let Some(name) = text(&entry, "name") else {
+            continue;
+        };
+        let mut args = vec![name.clone()];
+        if let Some(condition) = text(&entry, "condition") {
+            args.push(String::from("if"));
+            args.push(condition);
+        }
+        set_breakpoint(&args.iter().map(|s| s.as_str()).collect::<Vec<&str>>());
+        if let Some(breakpoint) = debugger().breakpoint.get_mut(strip_sigil(&name)) {
+            breakpoint.hits = entry["hits"].as_u64().unwrap_or(0) as usize;
+        }
+    }
+    for entry in entries("return_breaks") {
+        if let (Some(function), Some(predicate)) = (text(&entry, "function"), text(&entry, "when"))
+        {
+            set_return_break(&[&function, "when", &predicate]);
+        }
+    }
+    for entry in entries("watchpoints") {
+        let Some(name) = text(&entry, "name") else {
+            continue;
+        };
+        match (text(&entry, "stream"), text(&entry, "halt")) {
+            (Some(mode), _) => set_stream_watch(&[&name, &mode]),
+            (None, Some(halt)) => {
+                let condition = text(&entry, "condition").unwrap_or_default();
+                set_watchpoint(&[&name, &halt, &condition]);
+            }
+            (None, None) => set_watchpoint(&[&name]),
+        }
+    }
+    for entry in entries("tracepoints") {
+        if let (Some(name), Some(expr)) = (text(&entry, "name"), text(&entry, "expr")) {
+            set_tracepoint(&[&name, &expr]);
+        }
+    }
+    SESSION.set(Some(path.to_string()));
+    Ok(())
+}
+
+/// Read the session file again, if any, after loading a design.
+fn reload_session() {
+    let Some(path) = SESSION.with_borrow(Clone::clone) else {
+        return;
+    };
+    if !std::path::Path::new(&path).exists() {
+        return;
+    }
+    match load_session(&path) {
+        Ok(()) => println!("Restored session {}", path),
+        Err(err) => {
+            println!("!!Failed to load session {}: {}", path, err);
+            fail(Failure::Command);
+        }
+    }
+}
+
+pub fn get_watchpoint() -> HashSet<String> {
+    debugger().watchpoint.clone()
+}
+
+/// `watch <variable> [change | when <comparison>]`, e.g. `watch %sum when > 100`
+pub fn set_watchpoint(args: &[&str]) {
+    let var = strip_sigil(args[0]).to_string();
+    if var.contains('(') {
+        let Some(aggregate) = Aggregate::parse(&var) else {
+            println!("!!Invalid aggregate {}, expected e.g. sum(A[0..16])", var);
+            fail(Failure::Command);
+            return;
+        };
+        if aggregate.eval(debug_state()).is_none() {
+            println!(
+                "!!No memory {} with {} elements",
+                aggregate.memory, aggregate.end
+            );
+            fail(Failure::Command);
+            return;
+        }
+        debugger().aggregate_watch.insert(var.clone(), aggregate);
+    }
+    match args.get(1) {
+        None => {
+            debugger().watchpoint.insert(var);
+        }
+        Some(&"change") if args.len() == 2 => {
+            debugger().halt_watchpoint.insert(var, WatchHalt::Change);
+        }
+        Some(&"when") if args.len() > 2 => {
+            let predicate = args[2..].join(" ");
+            match Condition::parse(&format!("{} {}", var, predicate)) {
+                Some(condition) => {
+                    let halt = WatchHalt::When(condition, predicate);
+                    debugger().halt_watchpoint.insert(var, halt);
+                }
+                None => {
+                    println!("!!Invalid condition {}", predicate);
+                    fail(Failure::Command);
+                }
+            }
+        }
+        Some(_) => {
+            println!("!!Usage: watch <variable> [change | when <comparison>]");
+            fail(Failure::Command);
+        }
+    }
+}
+
+pub fn unset_watchpoint(b: &str) {
+    let b = strip_sigil(b);
+    debugger().watchpoint.remove(b);
+    debugger().halt_watchpoint.remove(b);
+    debugger().stream_watch.remove(b);
+    debugger().aggregate_watch.remove(b);
+    let watched = |key: &String| key == b || key.starts_with(&(b.to_owned() + " "));
+    debugger().watch_rows.retain(|key, _| !watched(key));
+    run_state().watch_previous.retain(|key, _| !watched(key));
+}
 
-enum State {
-    Software,
-    ToR,
-    HEC,
-    Cosim,
+pub fn show_watchpoint() {
+    let names = watchpoint_names();
+    let debugger = debugger();
+    let halts = &debugger.halt_watchpoint;
+    let streams = &debugger.stream_watch;
+    if json_output() {
+        let watchpoints = names
+            .into_iter()
+            .map(|name| {
+                let (halt, condition) = match halts.get(&name) {
+                    Some(WatchHalt::Change) => (Some("change"), None),
+                    Some(WatchHalt::When(_, predicate)) => (Some("when"), Some(predicate)),
+                    None => (None, None),
+                };
+                let stream = streams
+                    .get(&name)
+                    .map(|mode| format!("{:?}", mode).to_lowercase());
+                serde_json::json!({
+                    "name": name,
+                    "halt": halt,
+                    "condition": condition,
+                    "stream": stream,
+                })
+            })
+            .collect();
+        return print_json(Value::Array(watchpoints));
+    }
+    for name in names {
+        if let Some(mode) = streams.get(&name) {
+            println!("{} stream {}", name, format!("{:?}", mode).to_lowercase());
+            continue;
+        }
+        match halts.get(&name) {
+            Some(WatchHalt::Change) => println!("{} change", name),
+            Some(WatchHalt::When(_, predicate)) => println!("{} when {}", name, predicate),
+            None => println!("{}", name),
+        }
+    }
 }
 
-static mut DEBUG_STATE: State = State::Software;
+/// What happens when a token is pushed to or popped from a watched stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamWatch {
+    Log,
+    Stop,
+}
 
-pub fn construct(ir: &Value) -> Vec<String> {
-    let ops = ir.as_array().unwrap();
-    ops.iter()
-        .map(|x| String::from(x.as_str().unwrap()))
-        .collect()
+/// Set when a `stop` stream watch fired during the current cycle.
+static STREAM_HIT: AtomicBool = AtomicBool::new(false);
+
+/// `watch_stream <stream> [log|stop]`
+pub fn set_stream_watch(args: &[&str]) {
+    let mode = match args.get(1) {
+        None | Some(&"stop") => StreamWatch::Stop,
+        Some(&"log") => StreamWatch::Log,
+        Some(mode) => {
+            println!("!!Unknown stream watch mode: {}", mode);
+            fail(Failure::Command);
+            return;
+        }
+    };
+    debugger().stream_watch.insert(args[0].to_string(), mode);
 }
 
-pub fn parse_file(path: &str) -> Result<(), Box<dyn Error>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let ir: Value = serde_json::from_reader(reader)?;
-    match ir["level"].as_str().unwrap() {
-        "software" => {
-            unsafe {
-                HAS_SOFTWARE = true;
-                DEBUG_STATE = State::Software;
+pub fn have_stream_watch() -> bool {
+    !debugger().stream_watch.is_empty()
+}
+
+/// Report a token pushed to or popped from `stream` by `op` if it is watched.
+pub fn record_stream(stream: &str, event: &str, value: VALUE, op: &str) {
+    let Some(mode) = debugger().stream_watch.get(stream).copied() else {
+        return;
+    };
+    println!(
+        "Stream {} {} {:?} by {} @{}",
+        stream,
+        event,
+        value,
+        op,
+        current_cycle()
+    );
+    if mode == StreamWatch::Stop {
+        STREAM_HIT.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Whether a `stop` stream watch fired since the last call.
+pub fn take_stream_hit() -> bool {
+    STREAM_HIT.swap(false, Ordering::Relaxed) && !is_replaying()
+}
+
+/// Report a FIFO `error` (underflow or overflow) caused by `op`.
+pub fn report_fifo_error(fifo: &str, error: &str, op: &str, occupancy: usize, depth: usize) {
+    println!(
+        "{}",
+        paint(
+            Color::Red,
+            format!(
+                "!!FIFO {} {} by {}, occupancy {}/{} @{}",
+                fifo,
+                error,
+                op,
+                occupancy,
+                depth,
+                current_cycle()
+            )
+        )
+    );
+    FIFO_ERROR.set(true);
+}
+
+/// Whether a FIFO error was reported since the last call.
+pub fn take_fifo_error() -> bool {
+    FIFO_ERROR.replace(false) && !is_replaying()
+}
+
+/// Called when `instance` cannot advance in this cycle because of `stream`.
+pub fn report_stall(instance: &str, stream: &str) {
+    if !run_state()
+        .stalled
+        .lock()
+        .unwrap()
+        .insert(instance.to_string())
+        || options().stall_limit == 0
+    {
+        return;
+    }
+    let run = run_state();
+    let mut stalls = run.stalls.lock().unwrap();
+    let entry = stalls
+        .entry(instance.to_string())
+        .or_insert((0, String::new()));
+    entry.0 += 1;
+    entry.1 = stream.to_string();
+}
+
+/// Whether every one of the `instances` reported a stall in this cycle, to
+/// be asked before `test_stall` starts the next one.
+pub fn all_stalled(instances: &[String]) -> bool {
+    let run = run_state();
+    let stalled = run.stalled.lock().unwrap();
+    !instances.is_empty() && instances.iter().all(|instance| stalled.contains(instance))
+}
+
+/// Called at the end of each cycle, reports and returns true if an instance
+/// has been stalled for more than `stall_limit` cycles in a row.
+pub fn test_stall() -> bool {
+    let limit = options().stall_limit;
+    let run = run_state();
+    let mut stalled = run.stalled.lock().unwrap();
+    let mut stalls = run.stalls.lock().unwrap();
+    stalls.retain(|instance, _| stalled.contains(instance));
+    stalled.clear();
+    if limit == 0 || is_replaying() {
+        return false;
+    }
+    let mut hit = false;
+    for (instance, (count, stream)) in stalls.iter_mut() {
+        if *count > limit {
+            println!(
+                "{}",
+                paint(
+                    Color::Yellow,
+                    format!("{} blocked on {} for {} cycles", instance, stream, count)
+                )
+            );
+            *count = 0;
+            hit = true;
+        }
+    }
+    hit
+}
+
+pub fn have_halt_watchpoint() -> bool {
+    !debugger().halt_watchpoint.is_empty() && !is_replaying()
+}
+
+/// Check the halting watchpoints of one instance against the values seen in
+/// the previous cycle, reporting and returning true if one of them fires.
+/// `instance` is empty at the software level, which has a single environment.
+pub fn test_watch_halt(instance: &str, lookup: &dyn Fn(&str) -> Option<VALUE>) -> bool {
+    let halt_watchpoint = debugger().halt_watchpoint.clone();
+    let mut hit = false;
+    for (var, halt) in halt_watchpoint.iter() {
+        let Some(value) = lookup(var) else {
+            continue;
+        };
+        let key = match instance {
+            "" => var.clone(),
+            _ => format!("{} in {}", var, instance),
+        };
+        let old = run_state().watch_previous.get(&key).copied();
+        let fired = match halt {
+            WatchHalt::Change => matches!(old, Some(old) if old != value),
+            WatchHalt::When(condition, _) => old != Some(value) && condition.eval(lookup),
+        };
+        if fired {
+            match old {
+                Some(old) => println!("Watchpoint {}: {:?} -> {:?}", key, old, value),
+                None => println!("Watchpoint {}: {:?}", key, value),
             }
-            software::parse_software(&ir)
+            hit = true;
+        }
+        run_state().watch_previous.insert(key, value);
+    }
+    hit
+}
+
+/// Print or record the watched reductions over the memories of `level`, and
+/// return true if a halting one fires.
+pub fn watch_memory(level: State) -> bool {
+    let aggregates = debugger().aggregate_watch.clone();
+    if aggregates.is_empty() {
+        return false;
+    }
+    let values: BTreeMap<String, VALUE> = aggregates
+        .iter()
+        .filter_map(|(name, aggregate)| Some((name.clone(), aggregate.eval(level)?)))
+        .collect();
+    for (name, value) in &values {
+        if !debugger().watchpoint.contains(name) {
+            continue;
+        }
+        if watch_table_enabled() {
+            record_watch(name.clone(), *value);
+        } else {
+            println!("\t{} {:?}", name, value);
+        }
+    }
+    have_halt_watchpoint() && test_watch_halt("", &|var| values.get(var).copied())
+}
+
+pub fn test_watchpoint(b: &String) -> bool {
+    debugger().watchpoint.contains(b)
+}
+
+pub fn set_script_variable(name: &str, value: &str) {
+    debugger()
+        .script_variable
+        .insert(name.to_string(), value.to_string());
+}
+
+pub fn get_script_variable(name: &str) -> Option<String> {
+    debugger().script_variable.get(name).cloned()
+}
+
+/// Remove and return `$0`, `$1`, ... so a nested script gets its own arguments.
+pub fn take_positional_variables() -> Vec<(String, String)> {
+    let variables = &mut debugger().script_variable;
+    let positional = variables
+        .keys()
+        .filter(|name| name.parse::<usize>().is_ok())
+        .cloned()
+        .collect::<Vec<String>>();
+    positional
+        .into_iter()
+        .map(|name| {
+            let value = variables.remove(&name).unwrap();
+            (name, value)
+        })
+        .collect()
+}
+
+pub fn show_script_variables() {
+    for (name, value) in debugger().script_variable.iter() {
+        println!("${} {}", name, value)
+    }
+}
+
+/// Substitute `$name` and `${name}` with script variables.
+pub fn expand_variables(command: &str) -> String {
+    let mut result = String::new();
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
         }
-        "tor" => {
-            unsafe {
-                HAS_TOR = true;
-                DEBUG_STATE = State::ToR;
+        let braced = chars.peek() == Some(&'{');
+        if braced {
+            chars.next();
+        }
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_alphanumeric() || c == '_' {
+                name.push(c);
+                chars.next();
+            } else {
+                break;
             }
-            tor::parse_tor(&ir)
         }
-        "hec" => {
-            unsafe {
-                HAS_HEC = true;
-                DEBUG_STATE = State::HEC;
+        if braced && chars.peek() == Some(&'}') {
+            chars.next();
+        }
+        // A `$` not followed by a name, as in `/^op_1$/`, is kept as is
+        if name.is_empty() && !braced {
+            result.push('$');
+            continue;
+        }
+        match debugger().script_variable.get(&name) {
+            Some(value) => result.push_str(value),
+            None => {
+                println!("!!Undefined script variable ${}", name);
+                fail(Failure::Command);
+                result.push('$');
+                result.push_str(&name);
             }
-            hec::parse_hec(&ir)
         }
-        _ => Ok(()),
     }
+    result
 }
 
-use std::alloc::Layout;
+pub fn define_macro(name: &str, body: Vec<String>) {
+    if body.is_empty() {
+        println!("!!Empty definition for {}", name);
+        fail(Failure::Command);
+        return;
+    }
+    debugger().macros.insert(name.to_string(), body);
+}
 
-pub fn init_global<T, C: Default + std::iter::FromIterator<T>>(var: &mut *mut C) {
-    unsafe {
-        let layout = Layout::new::<C>();
-        let ptr = std::alloc::alloc(layout) as *mut C;
-        std::ptr::write(ptr, C::default());
-        *var = ptr;
+pub fn undefine_macro(name: &str) {
+    if debugger().macros.remove(name).is_none() {
+        println!("!!Undefined macro {}", name);
+        fail(Failure::Command);
     }
 }
 
-pub fn show_variable(vars: &[&str]) {
-    match unsafe { &DEBUG_STATE } {
-        State::Software => software::show_variable(vars),
-        State::ToR => tor::show_variable(vars),
-        State::HEC => hec::show_variable(vars),
-        _ => panic!("Cosim mode"),
+pub fn get_macro(name: &str) -> Option<Vec<String>> {
+    debugger().macros.get(name).cloned()
+}
+
+pub fn macro_names() -> Vec<String> {
+    debugger().macros.keys().cloned().collect()
+}
+
+pub fn show_macros() {
+    for (name, body) in debugger().macros.iter() {
+        println!("{}: {}", name, body.join("; "))
     }
 }
 
-pub fn load_memory(memory: &str, store: &[&str]) {
-    match unsafe { &DEBUG_STATE } {
-        State::HEC => hec::load_memory(memory, store),
+/// Write the macro table as `define` lines so it can be replayed by a script.
+pub fn save_macros(path: &str) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    for (name, body) in debugger().macros.iter() {
+        writeln!(file, "define {} {}", name, body.join("; "))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct WatchEntry {
+    value: VALUE,
+    last: VALUE,
+    changed: usize,
+}
+
+thread_local! {
+    static DISPLAY: Cell<bool> = const { Cell::new(false) };
+}
+
+fn snapshot() -> BTreeMap<String, String> {
+    match debug_state() {
+        State::Software => software::snapshot(),
+        State::ToR => tor::snapshot(),
+        State::HEC => hec::snapshot(),
+        State::Cosim => BTreeMap::new(),
+    }
+}
+
+pub fn set_display(mode: &str) {
+    match mode {
+        "on" => {
+            DISPLAY.set(true);
+            debugger().display_shadow = snapshot();
+        }
+        "off" => {
+            DISPLAY.set(false);
+            debugger().display_shadow.clear();
+        }
+        _ => {
+            println!("!!Unknown display mode: {}", mode);
+            fail(Failure::Command);
+        }
+    }
+}
+
+pub fn display_enabled() -> bool {
+    DISPLAY.get() && !is_replaying()
+}
+
+/// Print the values that differ from the previous display.
+pub fn display_changes(values: BTreeMap<String, String>) {
+    let shadow = &mut debugger().display_shadow;
+    let mut header = false;
+    for (name, value) in values.iter() {
+        let old = shadow.get(name);
+        if old == Some(value) {
+            continue;
+        }
+        if !header {
+            println!("Cycle {}:", current_cycle());
+            header = true;
+        }
+        match old {
+            Some(old) => println!("\t{}: {} -> {}", name, old, value),
+            None => println!("\t{}: {}", name, value),
+        }
+    }
+    *shadow = values;
+}
+
+thread_local! {
+    static WATCH_TABLE: Cell<bool> = const { Cell::new(false) };
+    /// Lines of the watch table last drawn, to draw the next one over them.
+    static WATCH_LINES: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn set_watch_table(mode: &str) {
+    match mode {
+        "on" => WATCH_TABLE.set(true),
+        "off" => {
+            WATCH_TABLE.set(false);
+            WATCH_LINES.set(0);
+            debugger().watch_rows.clear();
+        }
         _ => {
-            if unsafe { HAS_SOFTWARE } {
-                software::load_memory(memory, store);
+            println!("!!Unknown watch_table mode: {}", mode);
+            fail(Failure::Command);
+        }
+    }
+}
+
+pub fn watch_table_enabled() -> bool {
+    WATCH_TABLE.get()
+}
+
+pub fn record_watch(name: String, value: VALUE) {
+    let cycle = current_cycle();
+    let mut debugger = debugger();
+    match debugger.watch_rows.get_mut(&name) {
+        Some(entry) => {
+            if entry.value != value {
+                entry.last = entry.value;
+                entry.value = value;
+                entry.changed = cycle;
             }
-            if unsafe { HAS_TOR } {
-                tor::load_memory(memory, store);
+        }
+        None => {
+            debugger.watch_rows.insert(
+                name,
+                WatchEntry {
+                    value,
+                    last: VALUE::ERROR,
+                    changed: cycle,
+                },
+            );
+        }
+    }
+}
+
+pub fn draw_watch_table() {
+    let rows = &debugger().watch_rows;
+    if rows.is_empty() {
+        return;
+    }
+    if WATCH_LINES.get() > 0 {
+        // Move back over the previous table and clear it before redrawing
+        std::print!("\x1b[{}A\x1b[J", WATCH_LINES.get());
+    }
+    println!(
+        "{:<40} {:<24} {:<24} {}",
+        "watch", "value", "last", "changed"
+    );
+    for (name, entry) in rows.iter() {
+        println!(
+            "{:<40} {:<24} {:<24} {}",
+            name,
+            format!("{:?}", entry.value),
+            format!("{:?}", entry.last),
+            entry.changed
+        );
+    }
+    WATCH_LINES.set(rows.len() + 1);
+}
+
+/// A value as a CSV field: empty for ERROR, `x` for X, and the values of
+/// every active instance separated by `;`.
+fn csv_field(values: &[VALUE]) -> String {
+    values
+        .iter()
+        .map(|value| match value {
+            VALUE::ERROR => String::new(),
+            VALUE::Control => "control".to_string(),
+            VALUE::X => "x".to_string(),
+            value => value.to_json().to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+/// Quote and escape a DOT identifier or label, with its lines centered.
+pub fn dot_quote(text: &str) -> String {
+    let text = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", text)
+}
+
+/// `export_dot <function> <file> [counts]`: write the time graph (ToR) or state
+/// transition graph (HEC) of a function as Graphviz DOT, with the cycles the
+/// last run spent in each node or state when `counts` is given.
+pub fn export_dot(function: &str, path: &str, counts: bool) {
+    let dot = match debug_state() {
+        State::ToR | State::Cosim => tor::dot(function, counts),
+        State::HEC => hec::dot(function, counts),
+        State::Software => Err("The software level has no time graphs".to_string()),
+    };
+    let dot = match dot {
+        Ok(dot) => dot,
+        Err(err) => {
+            println!("!!{}", err);
+            fail(Failure::Command);
+            return;
+        }
+    };
+    if std::fs::write(path, dot).is_err() {
+        println!("!!Failed to write file {}", path);
+        fail(Failure::Command);
+    }
+}
+
+/// `export_mem <memory> <file>`: write the memory of the current level as
+/// `index,value` rows.
+pub fn export_memory(memory: &str, path: &str) {
+    let Some(store) = dump_memory(debug_state(), memory) else {
+        println!("!!No memory {}", memory);
+        fail(Failure::Command);
+        return;
+    };
+    let mut text = String::from("index,value\n");
+    for (index, value) in store.iter().enumerate() {
+        text.push_str(&format!("{},{}\n", index, csv_field(&[*value])));
+    }
+    if std::fs::write(path, text).is_err() {
+        println!("!!Failed to write file {}", path);
+        fail(Failure::Command);
+    }
+}
+
+struct WatchCsv {
+    file: File,
+    columns: Vec<(String, Option<Aggregate>)>,
+}
+
+thread_local! {
+    static WATCH_CSV: RefCell<Option<WatchCsv>> = const { RefCell::new(None) };
+}
+
+/// `watch_csv <file> [variable...]|off`: write a row per step or cycle with the
+/// values of the variables, or of the watchpoints set so far when none are
+/// given. Memory reductions such as `sum(A[0..16])` are columns too.
+pub fn set_watch_csv(args: &[&str]) {
+    if args[0] == "off" {
+        WATCH_CSV.set(None);
+        return;
+    }
+    let names: Vec<String> = if args.len() > 1 {
+        args[1..]
+            .iter()
+            .map(|name| strip_sigil(name).to_string())
+            .collect()
+    } else {
+        let mut names: Vec<String> = debugger().watchpoint.iter().cloned().collect();
+        names.extend(debugger().halt_watchpoint.keys().cloned());
+        names.sort();
+        names.dedup();
+        names
+    };
+    if names.is_empty() {
+        println!("!!No watchpoints to log, name the variables");
+        fail(Failure::Command);
+        return;
+    }
+    let mut columns = vec![];
+    for name in names {
+        let aggregate = if name.contains('(') {
+            let Some(aggregate) = Aggregate::parse(&name) else {
+                println!("!!Invalid aggregate {}, expected e.g. sum(A[0..16])", name);
+                fail(Failure::Command);
+                return;
+            };
+            Some(aggregate)
+        } else {
+            None
+        };
+        columns.push((name, aggregate));
+    }
+    let header: Vec<&str> = columns.iter().map(|(name, _)| name.as_str()).collect();
+    let created = File::create(args[0]).and_then(|mut file| {
+        writeln!(file, "cycle,{}", header.join(","))?;
+        Ok(file)
+    });
+    match created {
+        Ok(file) => WATCH_CSV.set(Some(WatchCsv { file, columns })),
+        Err(_) => {
+            println!("!!Failed to write file {}", args[0]);
+            fail(Failure::Command);
+        }
+    }
+}
+
+/// Append the row of the step or cycle just simulated at `level`. Replayed
+/// cycles were logged when they first ran.
+pub fn log_watch_csv(level: State) {
+    if is_replaying() {
+        return;
+    }
+    WATCH_CSV.with_borrow_mut(|csv| {
+        let Some(csv) = csv else {
+            return;
+        };
+        let mut row = vec![cycle_count(level).to_string()];
+        for (name, aggregate) in &csv.columns {
+            let values = match aggregate {
+                Some(aggregate) => aggregate.eval(level).into_iter().collect(),
+                None => dump_variable(level, name),
+            };
+            row.push(csv_field(&values));
+        }
+        let _ = writeln!(csv.file, "{}", row.join(","));
+    })
+}
+
+/// An edit that can be undone and redone. A memory edit holds only the words
+/// it overwrote, by address, and applying it exchanges them with those in
+/// the memory, so that it then holds what to apply the other way.
+#[derive(Debug, Clone)]
+enum Edit {
+    Memory(State, String, Vec<(usize, VALUE)>),
+    Variable(State, String, Vec<VALUE>, Vec<VALUE>),
+}
+
+impl Edit {
+    fn apply(&mut self, undo: bool) {
+        match self {
+            Edit::Memory(level, name, words) => swap_memory(*level, name, words),
+            Edit::Variable(level, name, before, after) => {
+                restore_variable(*level, name, if undo { before } else { after })
             }
         }
     }
+
+    /// The values the edit keeps.
+    fn size(&self) -> usize {
+        match self {
+            Edit::Memory(_, _, words) => words.len(),
+            Edit::Variable(_, _, before, after) => before.len() + after.len(),
+        }
+    }
 }
 
-pub fn show_memory(memorys: &[&str]) {
-    match unsafe { &DEBUG_STATE } {
-        State::Software => software::show_memory(memorys),
-        State::ToR => tor::show_memory(memorys),
-        State::HEC => hec::show_memory(memorys),
-        _ => panic!("Cosim mode"),
+/// Values kept by the edits that can be undone, past which the oldest are
+/// forgotten. The last edit is always kept.
+const UNDO_LIMIT: usize = 1 << 20;
+
+fn record_edits(edits: Vec<Edit>) {
+    if edits.is_empty() {
+        return;
     }
+    {
+        let mut run = run_state();
+        let undo = &mut run.undo;
+        undo.push(edits);
+        let mut size = 0;
+        let kept = undo
+            .iter()
+            .rev()
+            .take_while(|edits| {
+                size += edits.iter().map(Edit::size).sum::<usize>();
+                size <= UNDO_LIMIT
+            })
+            .count();
+        undo.drain(..undo.len() - kept.max(1));
+        run.redo.clear();
+    }
+    rebase_history();
 }
 
-pub fn execute_continue() {
-    match unsafe { &DEBUG_STATE } {
-        State::Software => software::execute_continue(),
-        State::ToR => tor::execute_continue(),
-        State::HEC => hec::execute_continue(),
-        _ => (),
+pub fn undo() {
+    let edits = run_state().undo.pop();
+    match edits {
+        None => println!("!!Nothing to undo"),
+        Some(mut edits) => {
+            record_input("undo");
+            for edit in edits.iter_mut().rev() {
+                edit.apply(true);
+            }
+            run_state().redo.push(edits);
+            rebase_history();
+        }
     }
 }
 
-pub fn show_active() {
-    match unsafe { &DEBUG_STATE } {
-        State::Software => (),
-        State::ToR => tor::show_active(),
-        State::HEC => hec::show_active(),
-        _ => panic!("Cosim mode"),
+pub fn redo() {
+    let edits = run_state().redo.pop();
+    match edits {
+        None => println!("!!Nothing to redo"),
+        Some(mut edits) => {
+            record_input("redo");
+            for edit in edits.iter_mut() {
+                edit.apply(false);
+            }
+            run_state().undo.push(edits);
+            rebase_history();
+        }
     }
 }
 
-pub fn call_function(function_name: &str, args: &[&str]) -> Result<(), String> {
-    match unsafe { &DEBUG_STATE } {
-        State::Software => software::call_function(function_name, args),
-        State::ToR => tor::call_function(function_name, args),
-        State::HEC => hec::call_function(function_name, args),
-        _ => panic!("Cosim mode"),
+/// Simulator state of every loaded level, saved by `checkpoint`.
+struct Checkpoint {
+    cycle: [usize; 3],
+    software: Option<software::Checkpoint>,
+    tor: Option<tor::Checkpoint>,
+    hec: Option<hec::Checkpoint>,
+    equal: Option<equal::Checkpoint>,
+}
+
+/// Automatic checkpoints kept per cycle counter, the first one is never dropped.
+const HISTORY_LIMIT: usize = 64;
+thread_local! {
+    /// Set while `goto_cycle` re-executes, which prints nothing and ignores
+    /// watchpoints.
+    static REPLAYING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn save_state() -> Rc<Checkpoint> {
+    let (cycle, has_software, has_tor, has_hec) = {
+        let run = run_state();
+        (run.cycle, run.has_software, run.has_tor, run.has_hec)
+    };
+    Rc::new(Checkpoint {
+        cycle,
+        software: has_software.then(software::checkpoint),
+        tor: has_tor.then(tor::checkpoint),
+        hec: has_hec.then(hec::checkpoint),
+        equal: equal::checkpoint(),
+    })
+}
+
+fn load_state(checkpoint: &Checkpoint) {
+    if let Some(state) = &checkpoint.software {
+        software::restore(state);
+    }
+    if let Some(state) = &checkpoint.tor {
+        tor::restore(state);
+    }
+    if let Some(state) = &checkpoint.hec {
+        hec::restore(state);
+    }
+    if let Some(state) = &checkpoint.equal {
+        equal::restore(state);
+    }
+    {
+        let mut run = run_state();
+        run.cycle = checkpoint.cycle;
+        run.watch_previous.clear();
+        run.stalls.lock().unwrap().clear();
+    }
+    if DISPLAY.get() {
+        let shadow = snapshot();
+        debugger().display_shadow = shadow;
     }
 }
 
-pub fn step(depth: usize) {
-    match unsafe { &DEBUG_STATE } {
-        State::Software => {
-            software::step(depth, false);
+/// Save the state of every loaded level under `name`, or list the saved
+/// checkpoints when no name is given.
+pub fn checkpoint(name: Option<&str>) {
+    let Some(name) = name else {
+        let run = run_state();
+        let checkpoints = &run.checkpoints;
+        let level = cycle_index(run.level);
+        if json_output() {
+            let list = checkpoints
+                .iter()
+                .map(|(name, checkpoint)| {
+                    serde_json::json!({"name": name, "cycle": checkpoint.cycle[level]})
+                })
+                .collect();
+            return print_json(Value::Array(list));
         }
-        State::ToR => {
-            tor::step(depth, false);
+        for (name, checkpoint) in checkpoints.iter() {
+            println!("{} @{}", name, checkpoint.cycle[level]);
         }
-        State::HEC => {
-            hec::step(depth, false);
+        return;
+    };
+    let checkpoint = save_state();
+    run_state().checkpoints.insert(name.to_string(), checkpoint);
+    record_input(&format!("checkpoint {}", name));
+    println!("Checkpoint {} at cycle {}", name, current_cycle());
+}
+
+/// Rewind every level to the state saved by `checkpoint name`. The
+/// checkpoint is kept so it can be restored again.
+pub fn restore(name: &str) {
+    let Some(checkpoint) = run_state().checkpoints.get(name).cloned() else {
+        println!("!!Unknown checkpoint {}", name);
+        fail(Failure::Command);
+        return;
+    };
+    load_state(&checkpoint);
+    record_input(&format!("restore {}", name));
+    rebase_history();
+    println!("Restored checkpoint {} at cycle {}", name, current_cycle());
+}
+
+pub fn session_names() -> Vec<String> {
+    simulator::session_names()
+}
+
+/// `session [<name>]`: switch to the simulation session `name`, creating an
+/// empty one, or list the sessions. Each session has its own designs and
+/// runs, while breakpoints, watchpoints, macros and options are shared.
+pub fn session(name: Option<&str>) {
+    let Some(name) = name else {
+        let current = simulator::session_name();
+        if json_output() {
+            let list = session_names()
+                .into_iter()
+                .map(|name| serde_json::json!({"name": name, "current": name == current}))
+                .collect();
+            return print_json(Value::Array(list));
         }
-        _ => (),
+        for name in session_names() {
+            let marker = if name == current { "*" } else { " " };
+            println!("{} {}", marker, name);
+        }
+        return;
+    };
+    if simulator::switch_session(name) {
+        println!("Created session {}", name);
+    } else {
+        println!("Session {}", name);
     }
 }
 
-static mut BREAKPOINT: *mut HashSet<String> = std::ptr::null_mut();
-static mut WATCHPOINT: *mut HashSet<String> = std::ptr::null_mut();
+/// `close_session <name>`: drop a session and the designs loaded in it.
+pub fn close_session(name: &str) {
+    if simulator::session_in_use(name) {
+        println!("!!Cannot close session {} while it is in use", name);
+        fail(Failure::Command);
+    } else if !simulator::close_session(name) {
+        println!("!!Unknown session {}", name);
+        fail(Failure::Command);
+    } else {
+        println!("Closed session {}", name);
+    }
+}
 
-pub fn set_breakpoint(b: String) {
-    unsafe { &mut (*BREAKPOINT) }.insert(b);
+/// Run a command in the session `name`, e.g. for `@ref step 10`, and return
+/// to the current session.
+pub fn in_session(name: &str, f: impl FnOnce()) {
+    simulator::in_session(name, f)
 }
 
-pub fn unset_breakpoint(b: &str) {
-    unsafe { &mut (*BREAKPOINT) }.remove(b);
+/// `save_state <file>`: write the memories, streams, variables, registers and
+/// instances of every loaded level to a JSON file, for inspection or to carry
+/// on elsewhere with `load_state`.
+pub fn save_state_file(path: &str) -> std::io::Result<()> {
+    let checkpoint = save_state();
+    let files: Vec<Value> = run_state()
+        .files
+        .iter()
+        .map(|(level, path)| serde_json::json!({"level": level_name(*level), "path": path}))
+        .collect();
+    let state = serde_json::json!({
+        "level": level_name(run_state().level),
+        "cycle": checkpoint.cycle,
+        "files": files,
+        "software": checkpoint.software.as_ref().map(software::Checkpoint::to_json),
+        "tor": checkpoint.tor.as_ref().map(tor::Checkpoint::to_json),
+        "hec": checkpoint.hec.as_ref().map(hec::Checkpoint::to_json),
+        "equal": checkpoint.equal.as_ref().map(equal::Checkpoint::to_json),
+    });
+    std::fs::write(path, serde_json::to_string_pretty(&state)?)?;
+    println!("Saved state at cycle {} to {}", current_cycle(), path);
+    Ok(())
 }
 
-pub fn show_breakpoint() {
-    for b in unsafe { &(*BREAKPOINT) }.iter() {
-        println!("{}", b)
+/// `load_state <file>`: continue from a state written by `save_state`. The
+/// same IR files must be loaded, nothing changes if the state does not fit
+/// them.
+pub fn load_state_file(path: &str) -> Result<(), Box<dyn Error>> {
+    let state: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let levels = {
+        let run = run_state();
+        [
+            ("software", run.has_software),
+            ("tor", run.has_tor),
+            ("hec", run.has_hec),
+        ]
+    };
+    for (level, loaded) in levels {
+        if !loaded && !state[level].is_null() {
+            let files = state["files"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|file| file["level"] == level)
+                .filter_map(|file| file["path"].as_str())
+                .collect::<Vec<&str>>();
+            return Err(format!("Load the {} level first: {}", level, files.join(" ")).into());
+        }
     }
+    let checkpoint = Checkpoint {
+        cycle: serde_json::from_value(state["cycle"].clone())?,
+        software: match &state["software"] {
+            Value::Null => None,
+            level => Some(software::Checkpoint::from_json(level)?),
+        },
+        tor: match &state["tor"] {
+            Value::Null => None,
+            level => Some(tor::Checkpoint::from_json(level)?),
+        },
+        hec: match &state["hec"] {
+            Value::Null => None,
+            level => Some(hec::Checkpoint::from_json(level)?),
+        },
+        equal: match &state["equal"] {
+            Value::Null => None,
+            equal => Some(equal::Checkpoint::from_json(equal)?),
+        },
+    };
+    load_state(&checkpoint);
+    let level = match state["level"].as_str() {
+        Some("tor") => State::ToR,
+        Some("hec") => State::HEC,
+        Some("cosim") => State::Cosim,
+        _ => State::Software,
+    };
+    run_state().level = level;
+    record_input(&format!("load_state {}", path));
+    rebase_history();
+    println!("Loaded state at cycle {} from {}", current_cycle(), path);
+    Ok(())
 }
 
-pub fn test_breakpoint(b: &String) -> bool {
-    unsafe {
-        return (*BREAKPOINT).get(b).is_some();
+/// Called by the step loops at every cycle boundary, takes an automatic
+/// checkpoint once `checkpoint_interval` cycles passed since the last one.
+pub fn auto_checkpoint(level: State) {
+    let interval = options().checkpoint_interval;
+    if interval == 0 || level != run_state().level {
+        return;
+    }
+    let index = cycle_index(level);
+    let cycle = cycle_count(level);
+    let last = run_state()
+        .history
+        .range((index, 0)..=(index, usize::MAX))
+        .next_back()
+        .map(|((_, last), _)| *last);
+    if last.is_some_and(|last| cycle < last + interval) {
+        return;
+    }
+    let checkpoint = save_state();
+    let mut run = run_state();
+    let history = &mut run.history;
+    history.insert((index, cycle), checkpoint);
+    if history.range((index, 0)..=(index, usize::MAX)).count() > HISTORY_LIMIT {
+        let second = history
+            .range((index, 0)..=(index, usize::MAX))
+            .nth(1)
+            .map(|(key, _)| *key)
+            .unwrap();
+        history.remove(&second);
     }
 }
 
-pub fn get_watchpoint() -> *mut HashSet<String> {
-    unsafe { WATCHPOINT }
+/// Cycles of `level` until `auto_checkpoint` takes its next checkpoint, for
+/// a loop that steps several cycles between calls.
+pub fn cycles_to_checkpoint(level: State) -> usize {
+    let interval = options().checkpoint_interval;
+    if interval == 0 || level != run_state().level {
+        return usize::MAX;
+    }
+    let index = cycle_index(level);
+    let cycle = cycle_count(level);
+    let last = run_state()
+        .history
+        .range((index, 0)..=(index, usize::MAX))
+        .next_back()
+        .map(|((_, last), _)| *last);
+    match last {
+        Some(last) => (last + interval).saturating_sub(cycle),
+        None => 0,
+    }
 }
 
-pub fn set_watchpoint(b: String) {
-    unsafe { &mut (*WATCHPOINT) }.insert(b);
+/// The state was changed from outside the simulation, so the automatic
+/// checkpoints after this cycle no longer lead here. Replace them with one of
+/// the current state.
+pub fn rebase_history() {
+    let level = run_state().level;
+    if options().checkpoint_interval == 0 {
+        return;
+    }
+    let index = cycle_index(level);
+    let cycle = cycle_count(level);
+    let checkpoint = save_state();
+    let history = &mut run_state().history;
+    history.retain(|(key_index, key_cycle), _| *key_index != index || *key_cycle < cycle);
+    history.insert((index, cycle), checkpoint);
 }
 
-pub fn unset_watchpoint(b: &str) {
-    unsafe { &mut (*WATCHPOINT) }.remove(b);
+/// Restore the latest automatic checkpoint at or before `cycle` and re-execute
+/// from it without breakpoints until the cycle count reaches `cycle`.
+pub fn goto_cycle(cycle: usize) {
+    let level = run_state().level;
+    let index = cycle_index(level);
+    let Some(checkpoint) = run_state()
+        .history
+        .range((index, 0)..=(index, cycle))
+        .next_back()
+        .map(|(_, checkpoint)| checkpoint.clone())
+    else {
+        println!("!!No checkpoint at or before cycle {}", cycle);
+        fail(Failure::Command);
+        return;
+    };
+    record_input(&format!("goto_cycle {}", cycle));
+    load_state(&checkpoint);
+    let depth = cycle - current_cycle();
+    if depth > 0 {
+        replaying(|| {
+            simulate(|| match level {
+                State::Software => {
+                    software::step(depth, false);
+                }
+                State::ToR => {
+                    tor::step(depth, false);
+                }
+                State::HEC => {
+                    hec::step(depth, false);
+                }
+                State::Cosim => equal::cosim_until(cycle),
+            })
+        });
+    }
+    if interrupted() {
+        println!("!!Interrupted at cycle {}", current_cycle());
+    } else if current_cycle() != cycle {
+        println!(
+            "Stopped at cycle {} before reaching cycle {}",
+            current_cycle(),
+            cycle
+        );
+    } else {
+        println!("At cycle {}", cycle);
+    }
 }
 
-pub fn show_watchpoint() {
-    for b in unsafe { &(*WATCHPOINT) }.iter() {
-        println!("{}", b)
+/// Go back `count` cycles (steps at the software level).
+pub fn reverse_step(count: usize) {
+    goto_cycle(current_cycle().saturating_sub(count));
+}
+
+thread_local! {
+    /// Script written by `record`, holding every input that is not decided by
+    /// the simulation itself.
+    static RECORD: RefCell<Option<File>> = const { RefCell::new(None) };
+}
+
+/// Append a command to the recording, unless it is being re-executed.
+pub fn record_input(line: &str) {
+    if is_replaying() {
+        return;
     }
+    RECORD.with_borrow_mut(|file| {
+        if let Some(file) = file {
+            let _ = writeln!(file, "{}", line);
+        }
+    });
 }
 
-pub fn test_watchpoint(b: &String) -> bool {
-    unsafe {
-        return (*WATCHPOINT).get(b).is_some();
+/// Start recording the loads, calls, pokes, memory and variable edits and the
+/// cycles run in between to a script that `replay` re-executes, or stop with
+/// `off`. The files loaded so far are written first.
+pub fn record(path: &str) {
+    if path == "off" {
+        RECORD.set(None);
+        return;
+    }
+    let mut file = match File::create(path) {
+        Ok(file) => file,
+        Err(_) => {
+            println!("!!Failed to write file {}", path);
+            fail(Failure::Command);
+            return;
+        }
+    };
+    let _ = writeln!(file, "# hestia recording");
+    let mut levels = vec![];
+    for (level, loaded) in run_state().files.iter() {
+        let command = if levels.contains(level) {
+            "merge"
+        } else {
+            "load"
+        };
+        let _ = writeln!(file, "{} {}", command, loaded);
+        levels.push(*level);
+    }
+    if let Some(equal) = &run_state().equal_file {
+        let _ = writeln!(file, "load_equal {}", equal);
+    }
+    if !levels.is_empty() {
+        let _ = writeln!(file, "switch {}", level_name(run_state().level));
+    }
+    RECORD.set(Some(file));
+    if run_state().cycle.iter().any(|cycle| *cycle > 0) {
+        println!(
+            "!!Recording from cycle {}, the replay starts from cycle 0",
+            current_cycle()
+        );
     }
 }
 
-pub fn init() {
-    unsafe {
-        init_global(&mut BREAKPOINT);
-        init_global(&mut WATCHPOINT);
+/// Whether the cycles being simulated are re-executed ones.
+pub fn is_replaying() -> bool {
+    REPLAYING.get()
+}
+
+/// Run `f` without printing or halting on watchpoints, as when re-executing.
+pub fn replaying<R, F: FnOnce() -> R>(f: F) -> R {
+    let previous = REPLAYING.replace(true);
+    let result = f();
+    REPLAYING.set(previous);
+    run_state().watch_previous.clear();
+    if DISPLAY.get() {
+        debugger().display_shadow = snapshot();
     }
+    result
+}
+
+pub fn init() {
+    logging::init();
+    let _ = ctrlc::set_handler(|| {
+        if RUNNING.load(Ordering::SeqCst) {
+            INTERRUPT.store(true, Ordering::SeqCst);
+        } else {
+            std::process::exit(130);
+        }
+    });
 }
 
 pub fn switch(str: &str) {
-    match str {
-        "software" => unsafe { DEBUG_STATE = State::Software },
-        "tor" => unsafe { DEBUG_STATE = State::ToR },
-        "hec" => unsafe { DEBUG_STATE = State::HEC },
-        _ => (),
-    }
+    let level = match str {
+        "software" => State::Software,
+        "tor" => State::ToR,
+        "hec" => State::HEC,
+        "cosim" => State::Cosim,
+        _ => return,
+    };
+    record_input(&format!("switch {}", str));
+    run_state().level = level;
 }
 
 pub fn cosim() {
-    unsafe { DEBUG_STATE = State::Cosim }
-    equal::cosim()
+    record_input("cosim");
+    run_state().level = State::Cosim;
+    simulate(equal::cosim)
 }
 
+/// Fill a memory from a file of whitespace separated values, or of hex words
+/// as read by `$readmemh` when it is a `.hex` or `.vmh` file or has comments
+/// or `@` addresses. `.gz` and `.zst` files are decompressed.
 pub fn load_memory_file(memory: &str, path: &str) -> std::io::Result<()> {
-    let mut file = File::open(path)?;
-    let mut lines = String::new();
-    file.read_to_string(&mut lines).unwrap();
+    let lines = decompress::read_to_string(path)?;
+    let uncompressed = decompress::strip_extension(path);
+    let hex = uncompressed.ends_with(".hex")
+        || uncompressed.ends_with(".vmh")
+        || lines.contains("//")
+        || lines.contains("/*")
+        || lines.trim_start().starts_with('@');
+    if hex {
+        let words = parse_memh(&lines)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        return load_memory_words(memory, path, &words)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err));
+    }
     let store = lines.split_whitespace().collect::<Vec<&str>>();
     load_memory(memory, &store);
     Ok(())
 }
 
+/// The words of a `$readmemh` file by address, None for a word with `x` or
+/// `z` digits.
+fn parse_memh(text: &str) -> Result<BTreeMap<usize, Option<u64>>, String> {
+    let comments = Regex::new(r"(?s)/\*.*?\*/|//[^\n]*").unwrap();
+    let text = comments.replace_all(text, " ");
+    let mut words = BTreeMap::new();
+    let mut address = 0;
+    for token in text.split_whitespace() {
+        let token = token.replace('_', "");
+        if let Some(target) = token.strip_prefix('@') {
+            address = usize::from_str_radix(target, 16)
+                .map_err(|_| format!("Invalid address {}", token))?;
+            continue;
+        }
+        let word = if token.chars().any(|c| matches!(c, 'x' | 'X' | 'z' | 'Z')) {
+            None
+        } else {
+            Some(
+                u64::from_str_radix(&token, 16)
+                    .map_err(|_| format!("Invalid hex word {}", token))?,
+            )
+        };
+        words.insert(address, word);
+        address += 1;
+    }
+    Ok(words)
+}
+
+/// Overwrite the addressed elements of a memory with hex words read as the
+/// type of its elements, as an edit that can be undone.
+fn load_memory_words(
+    memory: &str,
+    path: &str,
+    words: &BTreeMap<usize, Option<u64>>,
+) -> Result<(), String> {
+    let mut edits = vec![];
+    for level in memory_levels() {
+        let Some(store) = memory_store(level, memory) else {
+            continue;
+        };
+        if let Some(address) = words.keys().next_back().filter(|a| **a >= store.len()) {
+            return Err(format!(
+                "Address {:x} out of range for {} of size {}",
+                address,
+                memory,
+                store.len()
+            ));
+        }
+        let data_type = memory_type(store.iter());
+        let mut after = words
+            .iter()
+            .map(|(address, word)| {
+                let value = match word {
+                    Some(bits) => VALUE::from_bits(&data_type, *bits),
+                    None => VALUE::X,
+                };
+                (*address, value)
+            })
+            .collect::<Vec<_>>();
+        swap_memory(level, memory, &mut after);
+        edits.push(Edit::Memory(level, memory.to_string(), after));
+    }
+    if edits.is_empty() {
+        return Err(format!("Undefined memory {}", memory));
+    }
+    record_input(&format!("load_memory_file {} {}", memory, path));
+    record_edits(edits);
+    Ok(())
+}
+
+/// The type of the elements of a memory, i32 while none is known.
+fn memory_type(mut store: impl Iterator<Item = VALUE>) -> String {
+    store
+        .find(|value| !matches!(value, VALUE::ERROR | VALUE::Control | VALUE::X))
+        .map_or("i32".to_string(), |value| value.get_type())
+}
+
+/// `dump_memory_hex <memory> <file>`: write a memory of the current level as
+/// hex words that `$readmemh` reads, one per line. Unknown values are `x`.
+pub fn dump_memory_hex(memory: &str, path: &str) {
+    let Some(store) = dump_memory(debug_state(), memory) else {
+        println!("!!No memory {}", memory);
+        fail(Failure::Command);
+        return;
+    };
+    let data_type = memory_type(store.iter().copied());
+    let digits = bit_width(&data_type).div_ceil(4);
+    let mut text = format!("// {}: {} x {}\n", memory, store.len(), data_type);
+    for value in &store {
+        match value.to_bits() {
+            Some(bits) => text.push_str(&format!("{:0width$x}\n", bits, width = digits)),
+            None => text.push_str(&format!("{}\n", "x".repeat(digits))),
+        }
+    }
+    if std::fs::write(path, text).is_err() {
+        println!("!!Failed to write file {}", path);
+        fail(Failure::Command);
+    }
+}
+
 pub fn parse_equal(path: &str) -> Result<(), Box<dyn Error>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let ir: Value = serde_json::from_reader(reader)?;
+    let ir: Value = serde_json::from_reader(decompress::open(path)?)?;
+    run_state().equal_file = Some(path.to_string());
+    record_input(&format!("load_equal {}", path));
     equal::parse_equal(&ir)
 }
@@ -0,0 +1,66 @@
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{BufReader, Read, Result};
+
+/// The extensions of the compressed files read as they are decompressed.
+const EXTENSIONS: &[&str] = &[".gz", ".zst"];
+
+/// `path` without its `.gz` or `.zst` extension, to tell the format of what
+/// it holds.
+pub fn strip_extension(path: &str) -> &str {
+    let stripped = EXTENSIONS
+        .iter()
+        .find_map(|extension| path.strip_suffix(extension));
+    stripped.unwrap_or(path)
+}
+
+/// Open `path` for reading, decompressing `.gz` and `.zst` files as they are
+/// read so that the whole file is never held compressed and decompressed at
+/// once.
+pub fn open(path: &str) -> Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(path)?);
+    if path.ends_with(".gz") {
+        // Concatenated members read as one stream, as with `gzip -d`
+        Ok(Box::new(MultiGzDecoder::new(file)))
+    } else if path.ends_with(".zst") {
+        Ok(Box::new(zstd::Decoder::with_buffer(file)?))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+pub fn read_to_string(path: &str) -> Result<String> {
+    let mut text = String::new();
+    open(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    #[test]
+    fn compressed_files() {
+        let path = std::env::temp_dir().join(format!("hestia-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut gzip = Vec::new();
+        for part in ["1\n", "2\n"] {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            gzip.extend(encoder.finish().unwrap());
+        }
+        let zstd = zstd::encode_all("1\n2\n".as_bytes(), 0).unwrap();
+        for (extension, bytes) in [(".gz", gzip), (".zst", zstd)] {
+            let path = format!("{}{}", path, extension);
+            std::fs::write(&path, bytes).unwrap();
+            assert_eq!(strip_extension(&path).len(), path.len() - extension.len());
+            assert_eq!(read_to_string(&path).unwrap(), "1\n2\n");
+            std::fs::write(&path, "1\n").unwrap();
+            assert!(read_to_string(&path).is_err());
+            std::fs::remove_file(&path).unwrap();
+        }
+        assert!(open("missing.gz").is_err());
+    }
+}
@@ -2,35 +2,102 @@ use std::{collections::VecDeque, iter::zip, vec};
 
 use super::basetype::*;
 use downcast_rs::{impl_downcast, DowncastSync};
+use half::{bf16, f16};
 use set_value_derive::SetValue;
 
+/// A handshake port of a unit by the number of its field and the index in a
+/// vector port, so that propagation reports changes without building names.
+/// Each unit has a constant for each field, such as `Fork::DATA_OUT.at(1)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Port {
+    field: u16,
+    index: u16,
+}
+
+impl Port {
+    pub const fn new(field: u16) -> Self {
+        Port { field, index: 0 }
+    }
+
+    /// The element `index` of a vector port.
+    pub fn at(self, index: usize) -> Self {
+        Port {
+            index: index as u16,
+            ..self
+        }
+    }
+
+    pub fn field(self) -> u16 {
+        self.field
+    }
+
+    pub fn index(self) -> usize {
+        self.index as usize
+    }
+}
+
 pub trait SetValue {
     fn set_value(&mut self, port: &str, val: HandshakeValue);
     fn get_value(&mut self, port: &str) -> HandshakeValue;
     fn is_valid(&self) -> bool;
+    /// Every handshake port with its current value; vector ports are `name.i`.
+    fn ports(&self) -> Vec<(String, HandshakeValue)>;
+    /// The port named `name`, None if the unit has no such port.
+    fn port(&self, name: &str) -> Option<Port>;
+    fn port_value(&self, port: Port) -> HandshakeValue;
+    fn set_port_value(&mut self, port: Port, val: HandshakeValue);
+    fn port_name(&self, port: Port) -> String;
 }
 
-pub trait IsHandshake: std::fmt::Debug + SetValue + DowncastSync {
-    fn init(&mut self) {}
-    fn propagate(&mut self) -> Vec<String> {
-        vec![]
+pub trait CloneHandshake {
+    fn clone_box(&self) -> Box<dyn IsHandshake>;
+}
+
+impl<T: IsHandshake + Clone> CloneHandshake for T {
+    fn clone_box(&self) -> Box<dyn IsHandshake> {
+        Box::new(self.clone())
     }
+}
+
+pub trait IsHandshake: std::fmt::Debug + SetValue + DowncastSync + CloneHandshake {
+    fn init(&mut self) {}
+    /// Settle the outputs of the unit on its inputs, adding the ports whose
+    /// value changed to `changed`.
+    fn propagate(&mut self, _changed: &mut Vec<Port>) {}
     fn update(&mut self) -> bool {
         false
     }
+    /// Whether the outputs depend only on registers, so that the unit
+    /// separates the regions of a network that settle in parallel.
+    fn registered(&self) -> bool {
+        false
+    }
+    /// How many of the next clock edges leave the ports as they are while the
+    /// inputs do not change. Units with registers must override it.
+    fn quiet(&self) -> usize {
+        usize::MAX
+    }
+    /// Take `cycles` clock edges within `quiet`, without propagating.
+    fn skip(&mut self, _cycles: usize) {}
 }
 impl_downcast!(sync IsHandshake);
 
+impl Clone for Box<dyn IsHandshake> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
 pub trait New {
     fn new() -> Self;
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct Empty {}
 
 impl IsHandshake for Empty {}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Join {
     data_in: Vec<HandshakeValue>,
     data_out: HandshakeValue,
@@ -108,6 +175,11 @@ impl Reg {
 }
 
 impl Reg {
+    /// Whether the clock edge keeps the value.
+    fn stable(&self) -> bool {
+        self.enable != Some(true) || self.value == self.new_value
+    }
+
     fn update(&mut self) -> bool {
         match self.enable {
             Some(true) => {
@@ -126,7 +198,7 @@ impl Reg {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct TEHB {
     data_in: HandshakeValue,
     data_out: HandshakeValue,
@@ -154,36 +226,33 @@ impl TEHB {
         self.propagate();
     }
 
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
-
+    fn propagate(&mut self) {
         let full_reg = self.full_reg.get_value().as_bool();
-        if self.data_in.set_ready(!full_reg) {
-            update_list.push("data_in".to_string());
-        }
+        self.data_in.set_ready(!full_reg);
         let reg_en = self.data_in.ready & self.data_in.valid & !self.data_out.ready;
         self.data_reg.set_enable(reg_en, self.data_in.data);
-        if self.data_out.set_valid(
+        self.data_out.set_valid(
             self.data_in.valid | full_reg,
             if full_reg {
                 self.data_reg.get_value()
             } else {
                 self.data_in.data
             },
-        ) {
-            update_list.push("data_out".to_string());
-        }
+        );
         self.full_reg
             .set_value(VALUE::Bool(self.data_out.valid & (!self.data_out.ready)));
-        update_list
     }
 
     fn update(&mut self) -> bool {
         self.full_reg.update() | self.data_reg.update()
     }
+
+    fn stable(&self) -> bool {
+        self.full_reg.stable() && self.data_reg.stable()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct OEHB {
     data_in: HandshakeValue,
     data_out: HandshakeValue,
@@ -211,29 +280,43 @@ impl OEHB {
         self.propagate();
     }
 
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
-
+    fn propagate(&mut self) {
         let full_reg = self.full_reg.get_value().as_bool();
-        if self.data_in.set_ready((!full_reg) | self.data_out.ready) {
-            update_list.push("data_in".to_string());
-        }
+        self.data_in.set_ready((!full_reg) | self.data_out.ready);
         let reg_en = self.data_in.ready & self.data_in.valid;
         self.data_reg.set_enable(reg_en, self.data_in.data);
         self.full_reg
             .set_value(VALUE::Bool(self.data_in.valid | (!self.data_in.ready)));
-        if self.data_out.set_valid(full_reg, self.data_reg.get_value()) {
-            update_list.push("data_out".to_string());
-        }
-        update_list
+        self.data_out.set_valid(full_reg, self.data_reg.get_value());
     }
 
     fn update(&mut self) -> bool {
         self.full_reg.update() | self.data_reg.update()
     }
+
+    fn stable(&self) -> bool {
+        self.full_reg.stable() && self.data_reg.stable()
+    }
 }
 
-#[derive(Debug, SetValue)]
+/// Propagate a unit within another, which reports the changes of its own
+/// ports instead, so those of the inner unit are dropped from `changed`.
+fn propagate_within(unit: &mut impl IsHandshake, changed: &mut Vec<Port>) {
+    let len = changed.len();
+    unit.propagate(changed);
+    changed.truncate(len);
+}
+
+/// `quiet` of a unit whose registers only change on the next clock edge.
+fn quiet_if(stable: bool) -> usize {
+    if stable {
+        usize::MAX
+    } else {
+        0
+    }
+}
+
+#[derive(Debug, Clone, SetValue)]
 pub struct ElasticBuffer {
     pub data_in: HandshakeValue,
     pub data_out: HandshakeValue,
@@ -255,9 +338,7 @@ impl IsHandshake for ElasticBuffer {
         self.oehb.init();
     }
 
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
-
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         let new_ready = self.oehb.get_ready(self.data_out.ready);
         if self.tehb.data_in.set_valid_from(self.data_in) | self.tehb.data_out.set_ready(new_ready)
         {
@@ -271,13 +352,11 @@ impl IsHandshake for ElasticBuffer {
         assert_eq!(new_ready, self.oehb.data_in.ready);
 
         if self.data_in.set_ready_from(self.tehb.data_in) {
-            update_list.push("data_in".to_string());
+            changed.push(Self::DATA_IN);
         }
         if self.data_out.set_valid_from(self.oehb.data_out) {
-            update_list.push("data_out".to_string());
+            changed.push(Self::DATA_OUT);
         }
-
-        update_list
     }
 
     fn update(&mut self) -> bool {
@@ -292,9 +371,17 @@ impl IsHandshake for ElasticBuffer {
         }
         flag
     }
+
+    fn registered(&self) -> bool {
+        true
+    }
+
+    fn quiet(&self) -> usize {
+        quiet_if(self.tehb.stable() && self.oehb.stable())
+    }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct Merge {
     pub data_in: Vec<HandshakeValue>,
     pub data_out: HandshakeValue,
@@ -306,9 +393,7 @@ impl IsHandshake for Merge {
         self.tehb.init();
     }
 
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
-
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         let mut tmp_data_out = VALUE::ERROR;
         let mut tmp_valid = false;
 
@@ -326,16 +411,14 @@ impl IsHandshake for Merge {
             self.tehb.propagate();
         }
         if self.data_out.set_valid_from(self.tehb.data_out) {
-            update_list.push("data_out".to_string());
+            changed.push(Self::DATA_OUT);
         }
 
         self.data_in.iter_mut().enumerate().for_each(|(idx, data)| {
             if data.set_ready_from(self.tehb.data_in) {
-                update_list.push("data_in.".to_string() + &idx.to_string())
+                changed.push(Self::DATA_IN.at(idx))
             }
         });
-
-        update_list
     }
 
     fn update(&mut self) -> bool {
@@ -346,9 +429,13 @@ impl IsHandshake for Merge {
             false
         }
     }
+
+    fn quiet(&self) -> usize {
+        quiet_if(self.tehb.stable())
+    }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct MergeNoTehb {
     pub data_in: Vec<HandshakeValue>,
     pub data_out: HandshakeValue,
@@ -363,8 +450,7 @@ impl MergeNoTehb {
 }
 
 impl IsHandshake for MergeNoTehb {
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         let mut tmp_data_out = VALUE::ERROR;
         let mut tmp_valid = false;
 
@@ -378,18 +464,17 @@ impl IsHandshake for MergeNoTehb {
 
         self.data_in.iter_mut().enumerate().for_each(|(idx, data)| {
             if data.set_ready_from(self.data_out) {
-                update_list.push("data_in.".to_string() + &idx.to_string())
+                changed.push(Self::DATA_IN.at(idx))
             }
         });
 
         if self.data_out.set_valid(tmp_valid, tmp_data_out) {
-            update_list.push("data_out".to_string())
+            changed.push(Self::DATA_OUT)
         }
-        update_list
     }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct Branch {
     pub data_in: HandshakeValue,
     pub data_out: Vec<HandshakeValue>,
@@ -407,9 +492,7 @@ impl Branch {
 }
 
 impl IsHandshake for Branch {
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
-
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         let condition = self.condition.data.as_bool();
         if self.join.data_in[0].set_valid_only(self.condition)
             | self.join.data_in[1].set_valid_only(self.data_in)
@@ -420,22 +503,21 @@ impl IsHandshake for Branch {
             self.join.propagate();
         }
         if self.data_out[0].set_valid(condition & self.join.data_out.valid, self.data_in.data) {
-            update_list.push("data_out.0".to_string());
+            changed.push(Self::DATA_OUT.at(0));
         }
         if self.data_out[1].set_valid((!condition) & self.join.data_out.valid, self.data_in.data) {
-            update_list.push("data_out.1".to_string());
+            changed.push(Self::DATA_OUT.at(1));
         }
         if self.condition.set_ready_from(self.join.data_in[0]) {
-            update_list.push("condition".to_string());
+            changed.push(Self::CONDITION);
         }
         if self.data_in.set_ready_from(self.join.data_in[1]) {
-            update_list.push("data_in".to_string());
+            changed.push(Self::DATA_IN);
         }
-        update_list
     }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct Fork {
     pub data_in: HandshakeValue,
     pub data_out: Vec<HandshakeValue>,
@@ -465,8 +547,7 @@ impl IsHandshake for Fork {
         }
     }
 
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         let block_stop = self
             .sub_reg
             .iter()
@@ -474,7 +555,7 @@ impl IsHandshake for Fork {
             .map(|(idx, r)| {
                 let reg = r.get_value().as_bool();
                 if self.data_out[idx].set_valid(reg & self.data_in.valid, self.data_in.data) {
-                    update_list.push("data_out.".to_string() + &idx.to_string());
+                    changed.push(Self::DATA_OUT.at(idx));
                 }
                 reg & !self.data_out[idx].ready
             })
@@ -488,9 +569,8 @@ impl IsHandshake for Fork {
             });
 
         if self.data_in.set_ready(!any_stop) {
-            update_list.push("data_in".to_string());
+            changed.push(Self::DATA_IN);
         }
-        update_list
     }
 
     fn update(&mut self) -> bool {
@@ -499,9 +579,13 @@ impl IsHandshake for Fork {
             .map(|reg| reg.update())
             .fold(true, |acc, data| acc || data)
     }
+
+    fn quiet(&self) -> usize {
+        quiet_if(self.sub_reg.iter().all(Reg::stable))
+    }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct ControlMerge {
     pub data_in: Vec<HandshakeValue>,
     pub data_out: HandshakeValue,
@@ -528,22 +612,21 @@ impl IsHandshake for ControlMerge {
         self.tehb.data_reg = Reg::init(VALUE::Bool(false));
         self.tehb.propagate();
         self.fork_c.init();
-        self.fork_c.propagate();
-        self.phi_c.propagate();
+        propagate_within(&mut self.fork_c, &mut vec![]);
+        propagate_within(&mut self.phi_c, &mut vec![]);
     }
 
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         let phi_c_nready = self.tehb.get_ready();
         if self.phi_c.data_out.set_ready(phi_c_nready)
             | self.phi_c.data_in[0].set_valid_from(self.data_in[0])
             | self.phi_c.data_in[1].set_valid_from(self.data_in[1])
         {
-            self.phi_c.propagate();
+            propagate_within(&mut self.phi_c, changed);
         }
         for idx in 0..2 {
             if self.data_in[idx].set_ready_from(self.phi_c.data_in[idx]) {
-                update_list.push("data_in.".to_string() + &idx.to_string());
+                changed.push(Self::DATA_IN.at(idx));
             }
         }
 
@@ -565,7 +648,7 @@ impl IsHandshake for ControlMerge {
             | self.fork_c.data_out[0].set_ready_from(self.condition)
             | self.fork_c.data_out[1].set_ready_from(self.data_out)
         {
-            self.fork_c.propagate();
+            propagate_within(&mut self.fork_c, changed);
         }
         assert_eq!(tehb_nready, self.fork_c.data_in.ready);
 
@@ -573,16 +656,14 @@ impl IsHandshake for ControlMerge {
             .condition
             .set_valid(self.fork_c.data_out[0].valid, self.tehb.data_out.data)
         {
-            update_list.push("condition".to_string());
+            changed.push(Self::CONDITION);
         }
         if self
             .data_out
             .set_valid(self.fork_c.data_out[1].valid, self.tehb.data_out.data)
         {
-            update_list.push("data_out".to_string());
+            changed.push(Self::DATA_OUT);
         }
-
-        update_list
     }
 
     fn update(&mut self) -> bool {
@@ -593,13 +674,17 @@ impl IsHandshake for ControlMerge {
         }
         if self.fork_c.update() {
             flag = true;
-            self.fork_c.propagate();
+            propagate_within(&mut self.fork_c, &mut vec![]);
         }
         flag
     }
+
+    fn quiet(&self) -> usize {
+        self.fork_c.quiet().min(quiet_if(self.tehb.stable()))
+    }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct MuxDynamic {
     pub data_in: Vec<HandshakeValue>,
     pub data_out: HandshakeValue,
@@ -621,9 +706,7 @@ impl IsHandshake for MuxDynamic {
         self.tehb.init();
     }
 
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
-
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         let mut tmp_data = VALUE::ERROR;
         let mut tmp_valid = false;
         let condition_idx = self.condition.data.as_u32() as usize;
@@ -641,7 +724,7 @@ impl IsHandshake for MuxDynamic {
         }
 
         if self.data_out.set_valid_from(self.tehb.data_out) {
-            update_list.push("data_out".to_string());
+            changed.push(Self::DATA_OUT);
         }
 
         self.data_in.iter_mut().enumerate().for_each(|(idx, data)| {
@@ -651,7 +734,7 @@ impl IsHandshake for MuxDynamic {
                 && self.tehb.data_in.ready)
                 || !data.valid;
             if data.set_ready(new_ready) {
-                update_list.push("data_in.".to_string() + &idx.to_string());
+                changed.push(Self::DATA_IN.at(idx));
             }
         });
 
@@ -659,18 +742,20 @@ impl IsHandshake for MuxDynamic {
             .condition
             .set_ready(!self.condition.valid || (tmp_valid && self.tehb.data_in.ready))
         {
-            update_list.push("condition".to_string());
+            changed.push(Self::CONDITION);
         }
-
-        update_list
     }
 
     fn update(&mut self) -> bool {
         self.tehb.update()
     }
+
+    fn quiet(&self) -> usize {
+        quiet_if(self.tehb.stable())
+    }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct Select {
     pub data_in: Vec<HandshakeValue>,
     pub data_out: HandshakeValue,
@@ -689,9 +774,7 @@ impl Select {
 }
 
 impl IsHandshake for Select {
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
-
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         if self.join.data_in[0].set_valid_only(self.data_in[0])
             | self.join.data_in[1].set_valid_only(self.data_in[1])
             | self.join.data_in[2].set_valid_only(self.condition)
@@ -701,13 +784,13 @@ impl IsHandshake for Select {
         }
 
         if self.data_in[0].set_ready_from(self.join.data_in[0]) {
-            update_list.push("data_in.0".to_string());
+            changed.push(Self::DATA_IN.at(0));
         }
         if self.data_in[1].set_ready_from(self.join.data_in[1]) {
-            update_list.push("data_in.1".to_string());
+            changed.push(Self::DATA_IN.at(1));
         }
         if self.condition.set_ready_from(self.join.data_in[2]) {
-            update_list.push("condition".to_string());
+            changed.push(Self::CONDITION);
         }
         if self.data_out.set_valid(
             self.join.data_out.valid,
@@ -717,13 +800,12 @@ impl IsHandshake for Select {
                 VALUE::ERROR
             },
         ) {
-            update_list.push("data_out".to_string());
+            changed.push(Self::DATA_OUT);
         }
-        update_list
     }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct Load {
     pub address_in: HandshakeValue,
     pub data_out: HandshakeValue,
@@ -733,29 +815,26 @@ pub struct Load {
 }
 
 impl IsHandshake for Load {
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
-
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         if self.address_out.set_valid_from(self.address_in) {
-            update_list.push("address_out".to_string());
+            changed.push(Self::ADDRESS_OUT);
         }
         if self.address_in.set_ready_from(self.address_out) {
-            update_list.push("address_in".to_string());
+            changed.push(Self::ADDRESS_IN);
         }
         if self.data_out.set_valid_from(self.data_in) {
-            update_list.push("data_out".to_string());
+            changed.push(Self::DATA_OUT);
         }
         if self.data_in.set_ready_from(self.data_out) {
-            update_list.push("data_in".to_string());
+            changed.push(Self::DATA_IN);
         }
         if self.control.set_ready(true) {
-            update_list.push("control".to_string());
+            changed.push(Self::CONTROL);
         }
-        update_list
     }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct Store {
     pub address_in: HandshakeValue,
     pub data_out: HandshakeValue,
@@ -769,57 +848,59 @@ pub struct Store {
 impl IsHandshake for Store {
     fn init(&mut self) {
         self.addr.init();
-        self.addr.propagate();
+        propagate_within(&mut self.addr, &mut vec![]);
         self.data.init();
-        self.data.propagate();
+        propagate_within(&mut self.data, &mut vec![]);
     }
 
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         if self.addr.data_in.set_valid_from(self.address_in)
             | self.addr.data_out.set_ready_from(self.address_out)
         {
-            self.addr.propagate();
+            propagate_within(&mut self.addr, changed);
         }
         if self.address_out.set_valid_from(self.addr.data_out) {
-            update_list.push("address_out".to_string());
+            changed.push(Self::ADDRESS_OUT);
         }
         if self.address_in.set_ready_from(self.addr.data_in) {
-            update_list.push("address_in".to_string());
+            changed.push(Self::ADDRESS_IN);
         }
 
         if self.data.data_in.set_valid_from(self.data_in)
             | self.data.data_out.set_ready_from(self.data_out)
         {
-            self.data.propagate();
+            propagate_within(&mut self.data, changed);
         }
         if self.data_out.set_valid_from(self.data.data_out) {
-            update_list.push("data_out".to_string());
+            changed.push(Self::DATA_OUT);
         }
         if self.data_in.set_ready_from(self.data.data_in) {
-            update_list.push("data_in".to_string());
+            changed.push(Self::DATA_IN);
         }
         if self.control.set_ready(true) {
-            update_list.push("control".to_string());
+            changed.push(Self::CONTROL);
         }
-        update_list
     }
 
     fn update(&mut self) -> bool {
         let mut flag = false;
         if self.addr.update() {
-            self.addr.propagate();
+            propagate_within(&mut self.addr, &mut vec![]);
             flag = true;
         }
         if self.data.update() {
-            self.data.propagate();
+            propagate_within(&mut self.data, &mut vec![]);
             flag = true;
         }
         flag
     }
+
+    fn quiet(&self) -> usize {
+        self.addr.quiet().min(self.data.quiet())
+    }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct ElasticFIFO {
     pub data_in: HandshakeValue,
     pub data_out: HandshakeValue,
@@ -837,21 +918,17 @@ impl ElasticFIFO {
 }
 
 impl IsHandshake for ElasticFIFO {
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
-
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         if self.data_in.set_ready(self.queue.len() < self.depth) {
-            update_list.push("data_in".to_string());
+            changed.push(Self::DATA_IN);
         }
         if self.data_out.set_valid(
             !self.queue.is_empty(),
             self.queue.front().unwrap_or(&VALUE::ERROR).to_owned(),
         ) {
-            update_list.push("data_out".to_string());
+            changed.push(Self::DATA_OUT);
         }
         self.new_value = self.data_in.data;
-
-        update_list
     }
 
     fn update(&mut self) -> bool {
@@ -865,15 +942,25 @@ impl IsHandshake for ElasticFIFO {
         }
         do_enq | do_deq
     }
+
+    fn registered(&self) -> bool {
+        true
+    }
+
+    fn quiet(&self) -> usize {
+        let do_enq = self.data_in.valid & self.data_in.ready;
+        let do_deq = self.data_out.valid & self.data_out.ready;
+        quiet_if(!do_enq && !do_deq)
+    }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct DynMem {
     pub load_address: Vec<HandshakeValue>,
     pub load_data: Vec<HandshakeValue>,
     pub store_address: Vec<HandshakeValue>,
     pub store_data: Vec<HandshakeValue>,
-    mem: Vec<VALUE>,
+    mem: PagedStore,
     w_en: bool,
     r_en: bool,
     addr: usize,
@@ -890,35 +977,60 @@ impl DynMem {
         unit.load_data = vec![HandshakeValue::empty(); load];
         unit.store_address = vec![HandshakeValue::empty(); store];
         unit.store_data = vec![HandshakeValue::empty(); store];
-        unit.mem = vec![
+        unit.mem = PagedStore::new(
             match data_type as &str {
+                "i8" => VALUE::I8(0),
+                "u8" => VALUE::U8(0),
+                "i16" => VALUE::I16(0),
+                "u16" => VALUE::U16(0),
                 "i32" => VALUE::I32(0),
                 "i64" => VALUE::I64(0),
                 "u32" => VALUE::U32(0),
                 "u64" => VALUE::U64(0),
+                "index" => VALUE::Index(0),
+                "f16" => VALUE::F16(f16::ZERO),
+                "bf16" => VALUE::BF16(bf16::ZERO),
                 "f32" => VALUE::F32(0.0),
                 "f64" => VALUE::F64(0.0),
                 "bool" => VALUE::Bool(false),
                 _ => panic!(),
-            };
-            size
-        ];
+            },
+            size,
+        );
         unit.arb_out_valid = Reg::init(VALUE::U64(10000000));
         unit.buffer = (0..load).map(|_| TEHB::new()).collect();
         unit
     }
 
     pub fn set_mem(&mut self, store: &[&str]) {
-        let data_type = self.mem[0].get_type();
-        for (store, value) in self.mem.iter_mut().zip(store) {
-            *store = build_value(&data_type, &String::from(value.to_owned()));
+        let data_type = self.mem.init().get_type();
+        for (index, value) in store.iter().take(self.mem.len()).enumerate() {
+            self.mem.set(
+                index,
+                build_value(&data_type, &String::from(value.to_owned())),
+            );
         }
     }
+
+    pub fn dump_mem(&self) -> Vec<VALUE> {
+        self.mem.to_vec()
+    }
+
+    pub fn store(&self) -> &PagedStore {
+        &self.mem
+    }
+
+    pub fn restore_mem(&mut self, store: &[VALUE]) {
+        self.mem.replace(store);
+    }
+
+    pub fn swap_mem(&mut self, words: &mut [(usize, VALUE)]) {
+        self.mem.swap(words);
+    }
 }
 
 impl IsHandshake for DynMem {
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         let load_num = self.load_address.len();
         let store_num = self.store_address.len();
 
@@ -936,10 +1048,10 @@ impl IsHandshake for DynMem {
             self.addr = self.store_address[0].data.as_u64() as usize;
             self.w_data = self.store_data[0].data;
             if self.store_address[0].set_ready_from(join.data_in[0]) {
-                update_list.push("store_address.0".to_string());
+                changed.push(Self::STORE_ADDRESS.at(0));
             }
             if self.store_data[0].set_ready_from(join.data_in[1]) {
-                update_list.push("store_data.0".to_string());
+                changed.push(Self::STORE_DATA.at(0));
             }
         } else if store_num == 0 {
             self.w_en = false;
@@ -953,10 +1065,13 @@ impl IsHandshake for DynMem {
                 .enumerate()
                 .for_each(|(idx, (data, buffer))| {
                     let new_valid = self.arb_out_valid.get_value().as_u64() as usize == idx;
-                    let new_data = if new_valid {
-                        self.mem[self.address.get_value().as_u64() as usize]
-                    } else {
+                    let address = self.address.get_value();
+                    let new_data = if !new_valid {
                         VALUE::ERROR
+                    } else if address == VALUE::X {
+                        VALUE::X
+                    } else {
+                        self.mem.get(address.as_u64() as usize).unwrap()
                     };
                     if buffer.data_out.set_ready_from(*data)
                         | buffer.data_in.set_valid(new_valid, new_data)
@@ -969,12 +1084,13 @@ impl IsHandshake for DynMem {
                 .enumerate()
                 .for_each(|(idx, (buffer, data))| {
                     if data.set_valid_from(buffer.data_out) {
-                        update_list.push("load_data.".to_string() + &idx.to_string());
+                        changed.push(Self::LOAD_DATA.at(idx));
                     }
                 });
             self.arb_out_valid
                 .set_value(arb_out_valid.map_or(VALUE::U64(100000000), |x| VALUE::U64(x as u64)));
             if let Some(idx) = arb_out_valid {
+                tracing::trace!(port = idx, address = ?self.load_address[idx].data, "load");
                 self.address.set_value(self.load_address[idx].data);
             }
 
@@ -991,7 +1107,7 @@ impl IsHandshake for DynMem {
                 .enumerate()
                 .for_each(|(idx, (ready, address))| {
                     if address.set_ready(ready) {
-                        update_list.push("load_address.".to_string() + &idx.to_string());
+                        changed.push(Self::LOAD_ADDRESS.at(idx));
                     }
                 });
         } else if load_num == 1 && store_num == 1 {
@@ -999,14 +1115,17 @@ impl IsHandshake for DynMem {
         } else {
             panic!()
         }
-
-        update_list
     }
 
     fn update(&mut self) -> bool {
         let mut flag = false;
         if self.w_en {
-            self.mem[self.addr] = self.w_data;
+            tracing::trace!(address = self.addr, value = ?self.w_data, "store");
+            assert!(
+                self.mem.set(self.addr, self.w_data),
+                "index {} out of bounds",
+                self.addr
+            );
         }
         self.buffer.iter_mut().for_each(|buffer| {
             flag = flag | buffer.update();
@@ -1015,9 +1134,20 @@ impl IsHandshake for DynMem {
         flag = flag | self.arb_out_valid.update();
         flag
     }
+
+    fn quiet(&self) -> usize {
+        // Storing the word a memory already holds changes nothing
+        let store = self.w_en && self.mem.get(self.addr).unwrap() != self.w_data;
+        quiet_if(
+            !store
+                && self.buffer.iter().all(TEHB::stable)
+                && self.address.stable()
+                && self.arb_out_valid.stable(),
+        )
+    }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct ConstantElastic {
     pub control: HandshakeValue,
     pub data_in: VALUE,
@@ -1025,28 +1155,26 @@ pub struct ConstantElastic {
 }
 
 impl IsHandshake for ConstantElastic {
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         if self.control.set_ready_from(self.data_out) {
-            update_list.push("control".to_string());
+            changed.push(Self::CONTROL);
         }
         if self.data_out.set_valid(self.control.valid, self.data_in) {
-            update_list.push("data_out".to_string());
+            changed.push(Self::DATA_OUT);
         }
-        update_list
     }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct BinaryUnit {
     pub operand0: HandshakeValue,
     pub operand1: HandshakeValue,
     pub result: HandshakeValue,
-    func: fn(&Vec<VALUE>) -> VALUE,
+    func: fn(&[VALUE]) -> VALUE,
 }
 
 impl BinaryUnit {
-    pub fn set(func: fn(&Vec<VALUE>) -> VALUE) -> Self {
+    pub fn set(func: fn(&[VALUE]) -> VALUE) -> Self {
         let mut unit = BinaryUnit::new();
         unit.func = func;
         unit
@@ -1054,32 +1182,76 @@ impl BinaryUnit {
 }
 
 impl IsHandshake for BinaryUnit {
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
-
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         if self
             .operand0
             .set_ready(self.operand1.valid & self.result.ready)
         {
-            update_list.push("operand0".to_string());
+            changed.push(Self::OPERAND0);
         }
         if self
             .operand1
             .set_ready(self.operand0.valid & self.result.ready)
         {
-            update_list.push("operand1".to_string());
+            changed.push(Self::OPERAND1);
         }
         if self.result.set_valid(
             self.operand0.valid & self.operand1.valid,
             (self.func)(&vec![self.operand0.data, self.operand1.data]),
         ) {
-            update_list.push("result".to_string());
+            changed.push(Self::RESULT);
+        }
+    }
+}
+
+/// A bit manipulation of one to three operands, which gives a value of the
+/// type of its result port rather than of its first operand.
+#[derive(Debug, Clone, SetValue)]
+pub struct BitUnit {
+    pub operand: Vec<HandshakeValue>,
+    pub result: HandshakeValue,
+    eval: Option<(TypedFunction, DataType)>,
+}
+
+impl BitUnit {
+    pub fn set(op_type: &str, ret_type: &str) -> Self {
+        let (func, operands): (TypedFunction, usize) = match op_type {
+            "ctpop" => (ctpop_as, 1),
+            "ctlz" => (ctlz_as, 1),
+            "extract" => (extract_as, 2),
+            "concat" => (concat_as, 2),
+            "insert" => (insert_as, 3),
+            _ => panic!("Undefined bit unit: {}", op_type),
+        };
+        let mut unit = BitUnit::new();
+        unit.operand = vec![HandshakeValue::empty(); operands];
+        unit.eval = Some((func, DataType::parse(ret_type)));
+        unit
+    }
+}
+
+impl IsHandshake for BitUnit {
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
+        let valid = self.operand.iter().map(|operand| operand.valid);
+        let valid = valid.collect::<Vec<bool>>();
+        for (idx, operand) in self.operand.iter_mut().enumerate() {
+            let others = valid.iter().enumerate().all(|(i, v)| i == idx || *v);
+            if operand.set_ready(others & self.result.ready) {
+                changed.push(Self::OPERAND.at(idx));
+            }
+        }
+        let (func, ret_type) = self.eval.unwrap();
+        let data = self.operand.iter().map(|operand| operand.data);
+        if self.result.set_valid(
+            valid.iter().all(|v| *v),
+            func(ret_type, &data.collect::<Vec<VALUE>>()),
+        ) {
+            changed.push(Self::RESULT);
         }
-        update_list
     }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct BinaryUnitSeq {
     pub operand0: HandshakeValue,
     pub operand1: HandshakeValue,
@@ -1087,11 +1259,11 @@ pub struct BinaryUnitSeq {
     join: Join,
     delay: VecDeque<(bool, VALUE)>,
     oehb: OEHB,
-    func: fn(&Vec<VALUE>) -> VALUE,
+    func: fn(&[VALUE]) -> VALUE,
 }
 
 impl BinaryUnitSeq {
-    pub fn set(func: fn(&Vec<VALUE>) -> VALUE, latency: usize) -> Self {
+    pub fn set(func: fn(&[VALUE]) -> VALUE, latency: usize) -> Self {
         let mut unit = Self::new();
         unit.func = func;
         unit.delay = VecDeque::from(vec![(false, VALUE::ERROR); latency - 1]);
@@ -1101,11 +1273,18 @@ impl BinaryUnitSeq {
         unit.join.propagate();
         unit
     }
+
+    /// What enters the pipeline on the next clock edge.
+    fn entry(&self) -> (bool, VALUE) {
+        (
+            self.join.data_out.valid,
+            (self.func)(&vec![self.operand0.data, self.operand1.data]),
+        )
+    }
 }
 
 impl IsHandshake for BinaryUnitSeq {
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         let delay = self.delay.back().unwrap();
         if self.oehb.data_in.set_valid(delay.0, delay.1)
             | self.oehb.data_out.set_ready_from(self.result)
@@ -1121,25 +1300,21 @@ impl IsHandshake for BinaryUnitSeq {
         }
 
         if self.operand0.set_ready_from(self.join.data_in[0]) {
-            update_list.push("operand0".to_string());
+            changed.push(Self::OPERAND0);
         }
         if self.operand1.set_ready_from(self.join.data_in[1]) {
-            update_list.push("operand1".to_string());
+            changed.push(Self::OPERAND1);
         }
         if self.result.set_valid_from(self.oehb.data_out) {
-            update_list.push("result".to_string());
+            changed.push(Self::RESULT);
         }
-        update_list
     }
 
     fn update(&mut self) -> bool {
         let mut flag = false;
         if self.oehb.data_in.ready {
             flag = true;
-            self.delay.push_front((
-                self.join.data_out.valid,
-                (self.func)(&vec![self.operand0.data, self.operand1.data]),
-            ));
+            self.delay.push_front(self.entry());
             self.delay.pop_back();
         }
         if self.oehb.update() {
@@ -1148,26 +1323,62 @@ impl IsHandshake for BinaryUnitSeq {
         }
         flag
     }
+
+    /// The pipeline shifts on every edge the buffer is ready, which shows
+    /// only once a different token reaches its end.
+    fn quiet(&self) -> usize {
+        if !self.oehb.stable() {
+            return 0;
+        }
+        if !self.oehb.data_in.ready {
+            return usize::MAX;
+        }
+        let last = self.delay.back().unwrap();
+        let same = self.delay.iter().rev().skip(1);
+        let same = same.take_while(|entry| *entry == last).count();
+        if same + 1 < self.delay.len() || self.entry() != *last {
+            return same;
+        }
+        usize::MAX
+    }
+
+    fn skip(&mut self, cycles: usize) {
+        if !self.oehb.data_in.ready {
+            return;
+        }
+        let entry = self.entry();
+        for _ in 0..cycles.min(self.delay.len()) {
+            self.delay.push_front(entry);
+            self.delay.pop_back();
+        }
+    }
 }
 
-#[derive(Debug, SetValue)]
+#[derive(Debug, Clone, SetValue)]
 pub struct Trunc {
     pub operand: HandshakeValue,
     pub result: HandshakeValue,
+    ret_type: String,
+}
+
+impl Trunc {
+    pub fn set(ret_type: &str) -> Self {
+        let mut unit = Trunc::new();
+        unit.ret_type = ret_type.to_string();
+        unit
+    }
 }
 
 impl IsHandshake for Trunc {
-    fn propagate(&mut self) -> Vec<String> {
-        let mut update_list = vec![];
+    fn propagate(&mut self, changed: &mut Vec<Port>) {
         if self.operand.set_ready_from(self.result) {
-            update_list.push("operand".to_string());
+            changed.push(Self::OPERAND);
         }
-        if self
-            .result
-            .set_valid(self.operand.valid, VALUE::Bool(self.operand.data.as_bool()))
-        {
-            update_list.push("result".to_string());
+        if self.result.set_valid(
+            self.operand.valid,
+            trunc(&self.ret_type, &[self.operand.data]),
+        ) {
+            changed.push(Self::RESULT);
         }
-        update_list
     }
 }
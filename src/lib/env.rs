@@ -1,11 +1,17 @@
 use super::basetype::*;
 use super::common::*;
 use super::elastic::*;
+use super::error::{fault, take_fault, HestiaError};
+use super::intern::{Layout, Name, Slots};
+use super::profile::measure;
+use super::simulator;
 use multimap::MultiMap;
+use rayon::prelude::*;
 use serde_json::Value;
 use std::collections::VecDeque;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::iter::zip;
+use std::sync::{Arc, LazyLock};
 use std::vec;
 
 #[derive(Debug, Clone)]
@@ -25,10 +31,34 @@ impl Unit {
     }
 }
 
-#[derive(Debug, Clone)]
+static REG: LazyLock<Name> = LazyLock::new(|| Name::new("reg"));
+static OPERAND: LazyLock<Name> = LazyLock::new(|| Name::new("operand"));
+static OPERAND0: LazyLock<Name> = LazyLock::new(|| Name::new("operand0"));
+static OPERAND1: LazyLock<Name> = LazyLock::new(|| Name::new("operand1"));
+static RESULT: LazyLock<Name> = LazyLock::new(|| Name::new("result"));
+
+/// The ports of every primitive unit, laid out once for all of them.
+static PORTS: LazyLock<Arc<Layout>> = LazyLock::new(|| {
+    let mut layout = Layout::default();
+    for port in [*REG, *OPERAND, *OPERAND0, *OPERAND1, *RESULT] {
+        layout.insert(port);
+    }
+    Arc::new(layout)
+});
+
+#[derive(Clone)]
 pub struct UnitEnv {
     op_type: String,
-    env: HashMap<String, ValueTiming>,
+    env: Slots<ValueTiming>,
+}
+
+impl std::fmt::Debug for UnitEnv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnitEnv")
+            .field("op_type", &self.op_type)
+            .field("env", &self.env.iter().collect::<BTreeMap<_, _>>())
+            .finish()
+    }
 }
 
 impl UnitEnv {
@@ -41,16 +71,19 @@ impl UnitEnv {
             "sub_float" | "add_float" => match &unit.types[2] as &str {
                 "f64" => 13,
                 "f32" => 8,
+                "f16" | "bf16" => 5,
                 _ => panic!(),
             },
             "mul_float" => match &unit.types[2] as &str {
                 "f64" => 9,
                 "f32" => 4,
+                "f16" | "bf16" => 3,
                 _ => panic!(),
             },
             "div_float" => match &unit.types[2] as &str {
                 "f64" => 30,
                 "f32" => 20,
+                "f16" | "bf16" => 10,
                 _ => panic!(),
             },
             str => {
@@ -63,47 +96,62 @@ impl UnitEnv {
         };
 
         let values = match &unit.op_type as &str {
-            "register" => vec![("reg", 1)],
+            "register" => vec![(*REG, 1)],
             "div_float" | "mul_float" | "sub_float" | "add_float" | "mul_integer"
             | "div_integer" => {
-                vec![("operand0", 0), ("operand1", 0), ("result", latency)]
+                vec![(*OPERAND0, 0), (*OPERAND1, 0), (*RESULT, latency)]
             }
-            "truncf" | "sitofp" | "extf" => vec![("operand", 0), ("result", latency)],
+            "truncf" | "sitofp" | "extf" => vec![(*OPERAND, 0), (*RESULT, latency)],
             str => {
                 if str.starts_with("cmp_float") {
-                    vec![("operand0", 0), ("operand1", 0), ("result", latency)]
+                    vec![(*OPERAND0, 0), (*OPERAND1, 0), (*RESULT, latency)]
                 } else {
                     panic!()
                 }
             }
         };
 
-        let mut env = HashMap::new();
+        let mut env = Slots::new(PORTS.clone());
         for (data_type, (name, depth)) in zip(&unit.types, values) {
             let value_timing = ValueTiming::new(depth, data_type);
-            env.insert(String::from(name), value_timing);
+            env.insert(name, value_timing);
         }
 
-        UnitEnv {
+        let mut unit_env = UnitEnv {
             op_type: unit.op_type.clone(),
             env,
+        };
+        unit_env.forget_register();
+        unit_env
+    }
+
+    /// Hold X in a register until it is written, with `x_state` on.
+    fn forget_register(&mut self) {
+        if self.op_type == "register" && options().x_state {
+            self.env.get_mut(*REG).unwrap().set_unknown();
         }
     }
 
-    pub fn set_value(&mut self, port: &String, val: VALUE) {
+    pub fn set_value(&mut self, port: Name, val: VALUE) {
         let val_timing = self.env.get_mut(port).unwrap();
         val_timing.set_value(val);
     }
 
-    pub fn get_value(&self, port: &String) -> VALUE {
+    pub fn get_value(&self, port: Name) -> VALUE {
         self.env.get(port).unwrap().get_value()
     }
 
-    pub fn get_new_value(&self, port: &String) -> VALUE {
+    /// The value of a port named at the shell, if the unit has it.
+    pub fn try_get_value(&self, port: &str) -> Option<VALUE> {
+        let port = Name::find(port)?;
+        self.env.get(port).map(|val| val.get_value())
+    }
+
+    pub fn get_new_value(&self, port: Name) -> VALUE {
         self.env.get(port).unwrap().get_new_value()
     }
 
-    pub fn get_type(&self, port: &String) -> String {
+    pub fn get_type(&self, port: Name) -> String {
         self.env.get(port).unwrap().get_type()
     }
 
@@ -112,43 +160,37 @@ impl UnitEnv {
             "div_float" | "div_integer" | "mul_float" | "sub_float" | "add_float"
             | "mul_integer" | "cmp_float_ugt" | "cmp_float_oge" | "cmp_float_ogt"
             | "cmp_float_olt" | "cmp_float_une" => {
-                let op0 = self.get_value(&"operand0".to_string());
-                let op1 = self.get_value(&"operand1".to_string());
+                let op0 = self.get_value(*OPERAND0);
+                let op1 = self.get_value(*OPERAND1);
                 if op0 != VALUE::ERROR && op1 != VALUE::ERROR {
                     let result = match &self.op_type as &str {
-                        "div_float" | "div_integer" => {
-                            div(&self.get_type(&"result".to_string()), &vec![op0, op1])
-                        }
-                        "mul_float" | "mul_integer" => {
-                            mul(&self.get_type(&"result".to_string()), &vec![op0, op1])
-                        }
+                        "div_float" | "div_integer" => div(&self.get_type(*RESULT), &[op0, op1]),
+                        "mul_float" | "mul_integer" => mul(&self.get_type(*RESULT), &[op0, op1]),
 
-                        "add_float" => add(&self.get_type(&"result".to_string()), &vec![op0, op1]),
-                        "sub_float" => sub(&self.get_type(&"result".to_string()), &vec![op0, op1]),
+                        "add_float" => add(&self.get_type(*RESULT), &[op0, op1]),
+                        "sub_float" => sub(&self.get_type(*RESULT), &[op0, op1]),
                         "cmp_float_ugt" | "cmp_float_ogt" => cmp_gt(&vec![op0, op1]),
                         "cmp_float_oge" => cmp_gte(&vec![op0, op1]),
                         "cmp_float_olt" => cmp_lt(&vec![op0, op1]),
                         "cmp_float_une" => cmp_ne(&vec![op0, op1]),
                         _ => panic!(),
                     };
-                    self.set_value(&"result".to_string(), result);
+                    self.set_value(*RESULT, result);
                 }
             }
             "register" => {
-                if let VALUE::ERROR = self.get_new_value(&"reg".to_string()) {
+                if let VALUE::ERROR = self.get_new_value(*REG) {
                     return;
                 }
             }
             "truncf" | "sitofp" | "extf" => {
-                let op = self.get_value(&"operand".to_string());
+                let op = self.get_value(*OPERAND);
                 if op != VALUE::ERROR {
                     let result = match &self.op_type as &str {
-                        "truncf" | "sitofp" | "extf" => {
-                            convert(op, &self.get_type(&"result".to_string()))
-                        }
+                        "truncf" | "sitofp" | "extf" => convert(op, &self.get_type(*RESULT)),
                         _ => panic!("{:#?}", self),
                     };
-                    self.set_value(&"result".to_string(), result);
+                    self.set_value(*RESULT, result);
                 }
             }
             _ => {
@@ -156,34 +198,379 @@ impl UnitEnv {
             }
         }
 
-        for item in &mut self.env {
-            item.1.update();
+        for value in self.env.values_mut() {
+            value.update();
         }
     }
 
     pub fn clear(&mut self) {
-        for item in &mut self.env {
-            item.1.clear();
+        for value in self.env.values_mut() {
+            value.clear();
         }
+        self.forget_register();
     }
 
     pub fn has_value(&self) -> bool {
-        for item in &self.env {
+        for item in self.env.iter() {
             if item.1.has_value() {
                 return true;
             }
         }
         false
     }
+
+    pub fn ports(&self) -> Vec<(&Name, VALUE)> {
+        self.env
+            .iter()
+            .map(|(port, value)| (port, value.get_value()))
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut ports = serde_json::Map::new();
+        for (port, value) in self.env.iter() {
+            ports.insert(port.to_string(), value.get_value().to_json());
+        }
+        Value::Object(ports)
+    }
+
+    pub fn to_state(&self) -> Value {
+        let mut ports = serde_json::Map::new();
+        for (port, value) in self.env.iter() {
+            ports.insert(port.to_string(), value.to_state());
+        }
+        Value::Object(ports)
+    }
+
+    pub fn restore_state(&mut self, state: &Value) -> Option<()> {
+        for (port, value) in state.as_object()? {
+            self.env.get_mut(Name::find(port)?)?.restore_state(value)?;
+        }
+        Some(())
+    }
+}
+
+/// The units a propagation writes to, which may be only some of a network.
+trait Units {
+    fn unit(&mut self, name: &str) -> Option<&mut Box<dyn IsHandshake>>;
+}
+
+impl Units for HashMap<String, Box<dyn IsHandshake>> {
+    fn unit(&mut self, name: &str) -> Option<&mut Box<dyn IsHandshake>> {
+        self.get_mut(name)
+    }
 }
 
-#[derive(Debug)]
+impl Units for HashMap<&str, &mut Box<dyn IsHandshake>> {
+    fn unit(&mut self, name: &str) -> Option<&mut Box<dyn IsHandshake>> {
+        self.get_mut(name).map(|unit| &mut **unit)
+    }
+}
+
+/// A port value for a unit outside the units being propagated.
+type Deferred = (String, Port, HandshakeValue);
+
+/// The units waiting to propagate by their level, lowest first.
+type Queue = BTreeSet<(usize, String)>;
+
+/// Where the value of a changed port goes: to a port of another unit or out
+/// of a top-level port.
+#[derive(Debug, Clone)]
+enum Route {
+    Unit(String, Port),
+    Output(String),
+}
+
+/// The routes of the ports of each unit, worked out from the assigns once so
+/// that settling looks ports up by id instead of by name.
+type Routes = HashMap<String, HashMap<Port, Vec<Route>>>;
+
+fn route(env: &HashMap<String, Box<dyn IsHandshake>>, assign: &MultiMap<String, String>) -> Routes {
+    let port = |unit: &str, name: &str| {
+        let port = env[unit].port(name);
+        port.unwrap_or_else(|| panic!("{}", name))
+    };
+    let mut routes = Routes::new();
+    for (src, dsts) in assign.iter_all() {
+        let Some((src_unit, src_port)) = src.split_once('.') else {
+            continue;
+        };
+        if !env.contains_key(src_unit) {
+            continue;
+        }
+        let targets = dsts.iter().filter_map(|dst| match dst.split_once('.') {
+            Some((unit, name)) => env
+                .contains_key(unit)
+                .then(|| Route::Unit(unit.to_string(), port(unit, name))),
+            None => Some(Route::Output(dst.clone())),
+        });
+        routes
+            .entry(src_unit.to_string())
+            .or_default()
+            .insert(port(src_unit, src_port), targets.collect());
+    }
+    routes
+}
+
+/// Propagate the units in `queue` until none of their ports change, each
+/// unit after the lower levels driving it. A unit is queued again only when
+/// the value of one of its ports changes. Values of the top-level outputs
+/// that become valid are added to `printed`, values for units not in `units`
+/// to `deferred`.
+fn settle(
+    units: &mut impl Units,
+    routes: &Routes,
+    level: &HashMap<String, usize>,
+    queue: &mut Queue,
+    printed: &mut Vec<(String, VALUE)>,
+    deferred: &mut Vec<Deferred>,
+) {
+    let mut changed = vec![];
+    while let Some((_, unit_name)) = queue.pop_first() {
+        let _unit = tracing::trace_span!("unit", name = %unit_name).entered();
+        let unit = units.unit(&unit_name).unwrap();
+        changed.clear();
+        measure("propagate", &unit_name, || unit.propagate(&mut changed));
+        let routes = routes.get(&unit_name);
+        for &port in &changed {
+            let unit = units.unit(&unit_name).unwrap();
+            let val = unit.port_value(port);
+            tracing::trace!(port = unit.port_name(port), valid = val.valid, ready = val.ready, data = ?val.data, "changed");
+            let Some(targets) = routes.and_then(|routes| routes.get(&port)) else {
+                continue;
+            };
+            for target in targets {
+                match target {
+                    Route::Unit(a, b) => {
+                        let Some(unit) = units.unit(a) else {
+                            deferred.push((a.clone(), *b, val));
+                            continue;
+                        };
+                        if unit.port_value(*b) == val {
+                            continue;
+                        }
+                        unit.set_port_value(*b, val);
+                        queue.insert((level.get(a).copied().unwrap_or(0), a.clone()));
+                    }
+                    Route::Output(port) => {
+                        if val.valid {
+                            printed.push((port.clone(), val.data));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Print the top-level ports that became valid in a cycle, each value once
+/// however often the port was propagated.
+fn print_outputs(printed: &[(String, VALUE)]) {
+    for (index, (port, data)) in printed.iter().enumerate() {
+        if !printed[..index].contains(&printed[index]) {
+            println!("{} {:?}", port, data);
+        }
+    }
+}
+
+/// Units not separated by registered units, with their queue, and the
+/// output and fault of settling them on a thread of their own.
+#[derive(Default)]
+struct Region<'a> {
+    units: HashMap<&'a str, &'a mut Box<dyn IsHandshake>>,
+    queue: Queue,
+    printed: Vec<(String, VALUE)>,
+    deferred: Vec<Deferred>,
+    output: String,
+    fault: Option<HestiaError>,
+}
+
+impl Region<'_> {
+    /// Set a value crossing over from another region, queueing its unit if
+    /// it changes.
+    fn set_value(&mut self, level: &HashMap<String, usize>, deferred: Deferred) {
+        let (unit_name, port, value) = deferred;
+        let unit = self.units.unit(&unit_name).unwrap();
+        if unit.port_value(port) != value {
+            unit.set_port_value(port, value);
+            self.queue
+                .insert((level.get(&unit_name).copied().unwrap_or(0), unit_name));
+        }
+    }
+
+    fn settle(&mut self, routes: &Routes, level: &HashMap<String, usize>) {
+        settle(
+            &mut self.units,
+            routes,
+            level,
+            &mut self.queue,
+            &mut self.printed,
+            &mut self.deferred,
+        );
+    }
+
+    /// Settle on a worker, which must not reach the simulation, keeping the
+    /// output and the fault for `land` to report on the main thread.
+    fn settle_detached(&mut self, routes: &Routes, level: &HashMap<String, usize>, cycle: usize) {
+        simulator::detached(|| {
+            let output = buffer_output(Some(cycle), || self.settle(routes, level));
+            self.output.push_str(&output);
+            self.fault = self.fault.take().or_else(take_fault);
+        });
+    }
+
+    /// Print the output of the last `settle_detached` and report its fault.
+    fn land(&mut self) {
+        print!("{}", std::mem::take(&mut self.output));
+        if let Some(error) = self.fault.take() {
+            fault(error);
+        }
+    }
+}
+
+/// Number the regions that the registered units separate the others into,
+/// in the order of their first unit name.
+fn partition(
+    env: &HashMap<String, Box<dyn IsHandshake>>,
+    assign: &MultiMap<String, String>,
+) -> HashMap<String, usize> {
+    let mut parent: HashMap<&str, &str> = env
+        .iter()
+        .filter(|(_, unit)| !unit.registered())
+        .map(|(name, _)| (name.as_str(), name.as_str()))
+        .collect();
+    fn root<'a>(parent: &mut HashMap<&'a str, &'a str>, name: &'a str) -> &'a str {
+        let mut root = name;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        parent.insert(name, root);
+        root
+    }
+    for (src, dsts) in assign.iter_all() {
+        let Some((src, _)) = src.split_once('.') else {
+            continue;
+        };
+        for (dst, _) in dsts.iter().filter_map(|dst| dst.split_once('.')) {
+            if parent.contains_key(src) && parent.contains_key(dst) {
+                let (src, dst) = (root(&mut parent, src), root(&mut parent, dst));
+                parent.insert(src.max(dst), src.min(dst));
+            }
+        }
+    }
+    let mut names: Vec<&str> = parent.keys().copied().collect();
+    names.sort();
+    let mut index = HashMap::new();
+    let mut region = HashMap::new();
+    for name in names {
+        let root = root(&mut parent, name);
+        let count = index.len();
+        let number = *index.entry(root).or_insert(count);
+        region.insert(name.to_string(), number);
+    }
+    region
+}
+
+/// Level the units by the assigns from `src` to `dst` ports, so that a unit
+/// is above the units driving it. The units of a loop share a level.
+fn levelize<'a>(
+    env: &HashMap<String, Box<dyn IsHandshake>>,
+    edges: impl Iterator<Item = (&'a str, &'a str)>,
+) -> HashMap<String, usize> {
+    let mut names: Vec<&str> = env.keys().map(String::as_str).collect();
+    names.sort();
+    let index: HashMap<&str, usize> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (*name, i))
+        .collect();
+    let mut next = vec![vec![]; names.len()];
+    let mut prev = vec![vec![]; names.len()];
+    for (src, dst) in edges {
+        let src = src.split_once('.').and_then(|(unit, _)| index.get(unit));
+        let dst = dst.split_once('.').and_then(|(unit, _)| index.get(unit));
+        if let (Some(&src), Some(&dst)) = (src, dst) {
+            next[src].push(dst);
+            prev[dst].push(src);
+        }
+    }
+    // Kosaraju: the loops are found in topological order of the units
+    // driving them.
+    let mut finished = vec![];
+    let mut seen = vec![false; names.len()];
+    for start in 0..names.len() {
+        if seen[start] {
+            continue;
+        }
+        seen[start] = true;
+        let mut stack = vec![(start, 0)];
+        while let Some((node, child)) = stack.pop() {
+            match next[node].get(child) {
+                Some(&succ) => {
+                    stack.push((node, child + 1));
+                    if !seen[succ] {
+                        seen[succ] = true;
+                        stack.push((succ, 0));
+                    }
+                }
+                None => finished.push(node),
+            }
+        }
+    }
+    let mut component = vec![usize::MAX; names.len()];
+    let mut members = vec![];
+    for &start in finished.iter().rev() {
+        if component[start] != usize::MAX {
+            continue;
+        }
+        component[start] = members.len();
+        let mut stack = vec![start];
+        let mut nodes = vec![];
+        while let Some(node) = stack.pop() {
+            nodes.push(node);
+            for &pred in &prev[node] {
+                if component[pred] == usize::MAX {
+                    component[pred] = members.len();
+                    stack.push(pred);
+                }
+            }
+        }
+        members.push(nodes);
+    }
+    let mut depth = vec![0; members.len()];
+    for (current, nodes) in members.iter().enumerate() {
+        for &node in nodes {
+            for &succ in &next[node] {
+                if component[succ] != current {
+                    depth[component[succ]] = depth[component[succ]].max(depth[current] + 1);
+                }
+            }
+        }
+    }
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.to_string(), depth[component[i]]))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
 pub struct HandshakeEnv {
     pub env: HashMap<String, Box<dyn IsHandshake>>,
     pub assign: MultiMap<String, String>,
     pub update_list: VecDeque<String>,
     pub visit: HashSet<String>,
     pub need_update: HashSet<String>,
+    /// The top-level ports driven from outside and the unit ports they feed.
+    pub inputs: MultiMap<String, String>,
+    /// The top-level ports read from outside and the unit ports driving them.
+    pub outputs: MultiMap<String, String>,
+    routes: Routes,
+    /// The region of each unit that is not registered, see `partition`.
+    region: HashMap<String, usize>,
+    regions: usize,
+    /// The level of each unit, see `levelize`.
+    level: HashMap<String, usize>,
 }
 
 impl HandshakeEnv {
@@ -197,69 +584,141 @@ impl HandshakeEnv {
             update_list.push_back(name.to_owned());
             visit.insert(name.to_owned());
         }
+        let routes = route(&env, &assign);
+        let region = partition(&env, &assign);
+        let regions = region.values().max().map_or(0, |last| last + 1);
         HandshakeEnv {
             env,
             assign,
             update_list,
             visit,
             need_update: HashSet::new(),
+            inputs: MultiMap::new(),
+            outputs: MultiMap::new(),
+            routes,
+            region,
+            regions,
+            level: HashMap::new(),
         }
     }
 
+    /// Propagate the units in the order of the assigns from `src` to `dst`
+    /// ports, instead of by name.
+    pub fn levelize<'a>(&mut self, edges: impl Iterator<Item = (&'a str, &'a str)>) {
+        self.level = levelize(&self.env, edges);
+    }
+
+    /// Move the units queued since the last propagation to a queue by level.
+    fn queue(&mut self) -> Queue {
+        self.visit.clear();
+        let level = &self.level;
+        self.update_list
+            .drain(..)
+            .map(|name| (level.get(&name).copied().unwrap_or(0), name))
+            .collect()
+    }
+
+    /// Settle the network for this cycle. Large networks settle their
+    /// regions in parallel, see the `parallel_units` option.
     pub fn propagate(&mut self) {
-        while !self.update_list.is_empty() {
-            let unit_name = self.update_list.pop_front().unwrap();
-            self.visit.remove(&unit_name);
-            let unit = self.env.get_mut(&unit_name).unwrap();
-            // println!("Propagate: {}", unit_name);
-            let update_port = unit.propagate();
-            // println!("Changed: {:?}", update_port);
-            let value = update_port
-                .iter()
-                .map(|name| unit.get_value(name))
-                .collect::<Vec<HandshakeValue>>();
-            // println!("Value {:?}", value);
-            zip(update_port, value).for_each(|(name, val)| {
-                let port_name = unit_name.clone() + "." + &name;
-                // println!("{}", port_name);
-                let pairs = self.assign.get_vec(&port_name);
-                match pairs {
-                    None => {
-                        // println!("Not found {}", port_name);
-                    }
-                    Some(ports) => {
-                        assert!(!ports.is_empty());
-                        for port in ports {
-                            if let Some((a, b)) = port.split_once(".") {
-                                // println!("Found {} {}", a, b);
-                                self.env.get_mut(a).unwrap().set_value(b, val);
-                                let unit_name = a.to_string();
-                                // self.need_update.insert(unit_name.clone());
-                                if !self.visit.contains(&unit_name) {
-                                    self.update_list.push_back(unit_name.clone());
-                                    self.visit.insert(unit_name);
-                                }
-                            } else {
-                                if val.valid {
-                                    println!("{} {:?}", port, val.data);
-                                }
-                            }
-                        }
-                    }
+        let threshold = options().parallel_units;
+        if threshold > 0 && self.env.len() >= threshold && self.regions > 1 {
+            return self.propagate_parallel();
+        }
+        let mut queue = self.queue();
+        let mut printed = vec![];
+        settle(
+            &mut self.env,
+            &self.routes,
+            &self.level,
+            &mut queue,
+            &mut printed,
+            &mut vec![],
+        );
+        print_outputs(&printed);
+    }
+
+    /// Settle the registered units, whose outputs do not change until their
+    /// registers do, then the regions between them in parallel, until no
+    /// value crosses from one to the other. Values crossing over are set in
+    /// the order of the regions, so the result does not depend on the threads.
+    fn propagate_parallel(&mut self) {
+        let span = tracing::Span::current();
+        let cycle = current_cycle();
+        let queue = self.queue();
+        let mut regions: Vec<Region> = (0..self.regions).map(|_| Region::default()).collect();
+        let mut registered = Region::default();
+        for (name, unit) in self.env.iter_mut() {
+            match self.region.get(name) {
+                Some(&index) => regions[index].units.insert(name, unit),
+                None => registered.units.insert(name, unit),
+            };
+        }
+        for entry in queue {
+            match self.region.get(&entry.1) {
+                Some(&index) => regions[index].queue.insert(entry),
+                None => registered.queue.insert(entry),
+            };
+        }
+        let mut printed = vec![];
+        loop {
+            registered.settle(&self.routes, &self.level);
+            printed.append(&mut registered.printed);
+            for deferred in registered.deferred.drain(..) {
+                regions[self.region[&deferred.0]].set_value(&self.level, deferred);
+            }
+            regions
+                .par_iter_mut()
+                .filter(|region| !region.queue.is_empty())
+                .for_each(|region| {
+                    let _cycle = span.enter();
+                    region.settle_detached(&self.routes, &self.level, cycle);
+                });
+            for region in &mut regions {
+                region.land();
+                printed.append(&mut region.printed);
+                for deferred in region.deferred.drain(..) {
+                    registered.set_value(&self.level, deferred);
                 }
-            });
+            }
+            if registered.queue.is_empty() {
+                break;
+            }
         }
+        print_outputs(&printed);
     }
 
     pub fn update(&mut self) {
         for (unit_name, unit) in self.env.iter_mut() {
-            if unit.update() {
+            let _unit = tracing::trace_span!("unit", name = %unit_name).entered();
+            if measure("update", unit_name, || unit.update()) {
+                tracing::trace!("updated");
                 self.update_list.push_back(unit_name.to_owned());
                 self.visit.insert(unit_name.to_owned());
             }
         }
     }
 
+    /// How many of the next clock edges change no port of the settled
+    /// network, the least over its units.
+    pub fn quiet(&self) -> usize {
+        let mut quiet = usize::MAX;
+        for unit in self.env.values() {
+            quiet = quiet.min(unit.quiet());
+            if quiet == 0 {
+                break;
+            }
+        }
+        quiet
+    }
+
+    /// Take `cycles` clock edges within `quiet` at once.
+    pub fn skip(&mut self, cycles: usize) {
+        for unit in self.env.values_mut() {
+            unit.skip(cycles);
+        }
+    }
+
     pub fn set_port_ready(&mut self, port: &String, ready: bool) {
         if let Some((unit_name, port)) = port.split_once('.') {
             let unit = self.env.get_mut(unit_name).unwrap();
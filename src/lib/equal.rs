@@ -1,11 +1,15 @@
 use super::basetype::*;
 use super::common::*;
 use super::hec;
+use super::intern::Name;
+use super::simulator;
 use super::tor;
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::cell::{Cell, RefMut};
 use std::collections::VecDeque;
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 
 #[derive(Debug, Clone)]
 struct Mapping {
@@ -30,6 +34,35 @@ impl Mapping {
     }
 }
 
+/// Floats are compared relative to their magnitude with `float_tolerance`.
+fn values_match(high: VALUE, low: VALUE) -> bool {
+    let tolerance = options().float_tolerance;
+    match (high, low) {
+        (VALUE::F32(a), VALUE::F32(b)) if tolerance > 0.0 => {
+            within_tolerance(a as f64, b as f64, tolerance)
+        }
+        (VALUE::F64(a), VALUE::F64(b)) if tolerance > 0.0 => within_tolerance(a, b, tolerance),
+        (VALUE::F16(a), VALUE::F16(b)) if tolerance > 0.0 => {
+            within_tolerance(a.to_f64(), b.to_f64(), tolerance)
+        }
+        (VALUE::BF16(a), VALUE::BF16(b)) if tolerance > 0.0 => {
+            within_tolerance(a.to_f64(), b.to_f64(), tolerance)
+        }
+        (VALUE::C32(a), VALUE::C32(b)) if tolerance > 0.0 => {
+            within_tolerance(a.re as f64, b.re as f64, tolerance)
+                && within_tolerance(a.im as f64, b.im as f64, tolerance)
+        }
+        (VALUE::C64(a), VALUE::C64(b)) if tolerance > 0.0 => {
+            within_tolerance(a.re, b.re, tolerance) && within_tolerance(a.im, b.im, tolerance)
+        }
+        _ => high == low,
+    }
+}
+
+fn within_tolerance(a: f64, b: f64, tolerance: f64) -> bool {
+    (a - b).abs() <= tolerance * a.abs().max(b.abs()).max(1.0)
+}
+
 #[derive(Debug, Clone)]
 struct Compare {
     store: VecDeque<VALUE>,
@@ -66,7 +99,20 @@ impl Compare {
         self.size() <= 0
     }
 
-    fn push_high(&mut self, value: VALUE) {
+    fn check(&self, high: VALUE, low: VALUE) -> bool {
+        if !values_match(high, low) {
+            let message = format!(
+                "!!Value Mismatch: operation \"{}\" and primitive \"{}\" at state @{}: tor {:?}, hec {:?}",
+                self.op, self.primitive, self.state, high, low
+            );
+            println!("{}", paint(Color::Red, message));
+            fail(Failure::Mismatch);
+            return false;
+        }
+        true
+    }
+
+    fn push_high(&mut self, value: VALUE) -> bool {
         if self.high {
             self.store.push_back(value)
         } else {
@@ -74,19 +120,14 @@ impl Compare {
                 self.high = true;
                 self.store.push_back(value)
             } else {
-                assert_eq!(
-                    self.store.pop_front().unwrap(),
-                    value,
-                    "Value Mismatch: operation \"{}\" and primitive \"{}\" at state @{}",
-                    self.op,
-                    self.primitive,
-                    self.state
-                )
+                let low = self.store.pop_front().unwrap();
+                return self.check(value, low);
             }
         }
+        true
     }
 
-    fn push_low(&mut self, value: VALUE) {
+    fn push_low(&mut self, value: VALUE) -> bool {
         if !self.high {
             self.store.push_back(value)
         } else {
@@ -94,86 +135,506 @@ impl Compare {
                 self.high = false;
                 self.store.push_back(value)
             } else {
-                assert_eq!(self.store.pop_front().unwrap(), value)
+                let high = self.store.pop_front().unwrap();
+                return self.check(high, value);
             }
         }
+        true
     }
 }
 
-static mut LOW_MAPPING: *mut HashMap<(String, String), Mapping> = std::ptr::null_mut();
-static mut HIGH_MAPPING: *mut HashMap<String, Mapping> = std::ptr::null_mut();
-static mut COMPARISON: *mut HashMap<String, Compare> = std::ptr::null_mut();
+/// The cosimulation: the equal points of the ToR and HEC designs and the
+/// values of each waiting to be compared.
+#[derive(Default)]
+pub struct EqualSim {
+    low_mapping: HashMap<(String, String), Mapping>,
+    high_mapping: HashMap<String, Mapping>,
+    /// One comparison per compared operation, indexed as the equal points of
+    /// both levels are.
+    comparison: Vec<Compare>,
+    index: HashMap<String, usize>,
+    /// The comparison of each HEC equal point, as several may compare one
+    /// operation.
+    low_comparison: Vec<usize>,
+    /// Most HEC equal points mapped to one operation, the values a cycle may
+    /// push to one comparison.
+    fan_in: usize,
+}
+
+fn sim() -> RefMut<'static, EqualSim> {
+    simulator::current().equal.borrow_mut()
+}
 
 pub fn parse_equal(ir: &Value) -> Result<(), Box<dyn Error>> {
-    unsafe {
-        init_global(&mut LOW_MAPPING);
-        init_global(&mut HIGH_MAPPING);
-        init_global(&mut COMPARISON);
-    }
+    let mut sim = sim();
+    *sim = EqualSim::default();
+    let mut tor_points = HashMap::new();
+    let mut hec_points: HashMap<String, HashMap<String, usize>> = HashMap::new();
     if let Value::Array(mappings) = &ir {
         for mapping in mappings {
             let map = Mapping::new(mapping);
-            tor::set_equalpoint(map.op.clone());
-            hec::set_equalpoint(map.end.clone(), map.primitive.clone());
-            unsafe { &mut *LOW_MAPPING }
+            let compare = Compare::new(map.op.clone(), map.end.clone(), map.primitive.clone());
+            let index = match sim.index.get(&map.op) {
+                Some(&index) => {
+                    sim.comparison[index] = compare;
+                    index
+                }
+                None => {
+                    sim.comparison.push(compare);
+                    let index = sim.comparison.len() - 1;
+                    sim.index.insert(map.op.clone(), index);
+                    index
+                }
+            };
+            tor_points.insert(Name::new(&map.op), index);
+            let ops = hec_points.entry(map.end.clone()).or_default();
+            match ops.get(&map.primitive) {
+                Some(&point) => sim.low_comparison[point] = index,
+                None => {
+                    let point = sim.low_comparison.len();
+                    ops.insert(map.primitive.clone(), point);
+                    sim.low_comparison.push(index);
+                }
+            }
+            sim.low_mapping
                 .insert((map.end.clone(), map.primitive.clone()), map.clone());
-            unsafe { &mut *HIGH_MAPPING }.insert(map.op.clone(), map.clone());
-            unsafe { &mut *COMPARISON }.insert(
-                map.op.clone(),
-                Compare::new(map.op.clone(), map.end.clone(), map.primitive.clone()),
-            );
+            sim.high_mapping.insert(map.op.clone(), map.clone());
         }
     }
+    let mut fan_in = vec![0; sim.comparison.len()];
+    for index in &sim.low_comparison {
+        fan_in[*index] += 1;
+    }
+    sim.fan_in = fan_in.into_iter().max().unwrap_or(1);
+    let (count, low_count) = (sim.comparison.len(), sim.low_comparison.len());
+    drop(sim);
+    tor::set_equalpoints(tor_points, count);
+    hec::set_equalpoints(hec_points, low_count);
     //unsafe {
     //    println!("{:#?}", *COMPARISON);
     //}
     Ok(())
 }
 
+/// Saved comparison queues of a cosimulation.
+pub struct Checkpoint {
+    comparison: Vec<Compare>,
+}
+
+pub fn checkpoint() -> Option<Checkpoint> {
+    if sim().comparison.is_empty() {
+        return None;
+    }
+    Some(Checkpoint {
+        comparison: sim().comparison.clone(),
+    })
+}
+
+pub fn restore(checkpoint: &Checkpoint) {
+    sim().comparison = checkpoint.comparison.clone();
+}
+
+impl Checkpoint {
+    pub fn to_json(&self) -> Value {
+        let mut comparison = serde_json::Map::new();
+        for compare in &self.comparison {
+            let state = json!({
+                "high": compare.high,
+                "store": compare.store.iter().map(value_to_state).collect::<Vec<_>>(),
+            });
+            comparison.insert(compare.op.clone(), state);
+        }
+        Value::Object(comparison)
+    }
+
+    /// The queues saved by `to_json`, for the loaded equal points.
+    pub fn from_json(state: &Value) -> Result<Checkpoint, String> {
+        let Some(mut checkpoint) = checkpoint() else {
+            return Err("No equal points loaded".to_string());
+        };
+        for (op, compare_state) in state.as_object().into_iter().flatten() {
+            let Some(&index) = sim().index.get(op) else {
+                return Err(format!("No equal point {}", op));
+            };
+            let compare = &mut checkpoint.comparison[index];
+            let store = compare_state["store"]
+                .as_array()
+                .and_then(|store| store.iter().map(value_from_state).collect());
+            match (compare_state["high"].as_bool(), store) {
+                (Some(high), Some(store)) => (compare.high, compare.store) = (high, store),
+                _ => return Err(format!("Malformed state of equal point {}", op)),
+            }
+        }
+        Ok(checkpoint)
+    }
+}
+
+/// Values a level reached at its equal points in a cycle, kept in a slot per
+/// equal point allocated when they are loaded. A value reached twice in a
+/// cycle replaces the first, and `drain` yields them in the order reached.
+#[derive(Default)]
+pub struct EqualValues {
+    slots: Vec<Option<VALUE>>,
+    reached: Vec<usize>,
+}
+
+impl EqualValues {
+    pub fn new(points: usize) -> Self {
+        EqualValues {
+            slots: vec![None; points],
+            reached: vec![],
+        }
+    }
+
+    /// The number of equal points there are slots for.
+    pub fn points(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn insert(&mut self, point: usize, value: VALUE) {
+        let slot = &mut self.slots[point];
+        if slot.is_none() {
+            self.reached.push(point);
+        }
+        *slot = Some(value);
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = (usize, VALUE)> + '_ {
+        let slots = &mut self.slots;
+        self.reached
+            .drain(..)
+            .map(move |point| (point, slots[point].take().unwrap()))
+    }
+}
+
 pub fn cosim() {
+    cosim_until(usize::MAX)
+}
+
+/// Queue the values ToR reached at its equal points, false on a mismatch.
+fn push_high_values(values: impl Iterator<Item = (usize, VALUE)>) -> bool {
+    let mut matched = true;
+    for (index, value) in values {
+        matched &= sim().comparison[index].push_high(value)
+    }
+    matched
+}
+
+/// Queue the values HEC reached at its equal points, false on a mismatch.
+fn push_low_values(values: impl Iterator<Item = (usize, VALUE)>) -> bool {
+    let mut matched = true;
+    for (point, value) in values {
+        let index = sim().low_comparison[point];
+        matched &= sim().comparison[index].push_low(value)
+    }
+    matched
+}
+
+/// Cosimulate until both levels finish, a value mismatches or the ToR level
+/// reaches `cycle`. With `cosim_threads` on, HEC steps on a thread of its own
+/// that loads the HEC files again and takes over the state of the level, for
+/// as long as the skew window lets both levels run.
+pub fn cosim_until(cycle: usize) {
+    if !options().cosim_threads || !threads_allowed() {
+        return cosim_loop(cycle, None);
+    }
+    let files = loaded_files(State::HEC);
+    let handoff = hec::hand_off();
+    let finished = hec::finish();
+    std::thread::scope(|scope| {
+        let (requests, worker_requests) = sync_channel(1);
+        let (worker_replies, replies) = sync_channel(1);
+        scope.spawn(move || hec_worker(&files, handoff, worker_requests, worker_replies));
+        match replies.recv() {
+            Ok(Reply::Ready) => {}
+            Ok(Reply::Failed(error)) => {
+                tracing::debug!(error, "cosim on one thread");
+                return cosim_loop(cycle, None);
+            }
+            _ => panic!("cosim HEC thread stopped"),
+        }
+        let worker = Worker {
+            requests,
+            replies,
+            finished: Cell::new(finished),
+            stale: Cell::new(false),
+        };
+        cosim_loop(cycle, Some(&worker));
+        worker.hand_back();
+    })
+}
+
+/// What the cosim asks of the thread stepping HEC.
+enum Request {
+    /// Step up to `span` cycles, the first printed as ToR cycle `cycle`.
+    Step { span: usize, cycle: usize },
+    /// Send back the state of the level.
+    HandBack,
+}
+
+/// What the thread stepping HEC answers.
+enum Reply {
+    /// The level was loaded and took over the state it was handed.
+    Ready,
+    Failed(String),
+    /// The cycles of a span, and whether the level finished.
+    Span {
+        cycles: Vec<LevelCycle>,
+        finished: bool,
+    },
+    State(hec::Handoff),
+}
+
+/// The output of one cycle of a level, the values it reached at its equal
+/// points and the worst failure it had.
+struct LevelCycle {
+    output: String,
+    values: Vec<(usize, VALUE)>,
+    failure: Option<Failure>,
+}
+
+/// The HEC level of a cosim stepping on a thread of its own. It steps a span
+/// ahead of what was compared at most, which the skew window bounds.
+struct Worker {
+    requests: SyncSender<Request>,
+    replies: Receiver<Reply>,
+    /// Whether HEC finished by the end of the last span.
+    finished: Cell<bool>,
+    /// Set while the HEC level of this thread is behind that of the worker.
+    stale: Cell<bool>,
+}
+
+impl Worker {
+    fn reply(&self) -> Reply {
+        self.replies.recv().expect("cosim HEC thread stopped")
+    }
+
+    fn request(&self, request: Request) {
+        self.requests
+            .send(request)
+            .expect("cosim HEC thread stopped")
+    }
+
+    /// Bring the HEC level of this thread up to that of the worker.
+    fn hand_back(&self) {
+        if !self.stale.replace(false) {
+            return;
+        }
+        self.request(Request::HandBack);
+        let Reply::State(handoff) = self.reply() else {
+            panic!("cosim HEC thread out of step");
+        };
+        hec::take_over(handoff).expect("HEC state handed back by the cosim thread");
+    }
+}
+
+/// Load the HEC files on this thread, take over `handoff` and step the level
+/// as asked until the cosim drops its end of `requests`.
+fn hec_worker(
+    files: &[String],
+    handoff: hec::Handoff,
+    requests: Receiver<Request>,
+    replies: SyncSender<Reply>,
+) {
+    let mut loaded = Ok(());
+    buffer_output(None, || loaded = hec::load_handoff(files, handoff));
+    let ready = match loaded {
+        Ok(()) => Reply::Ready,
+        Err(error) => Reply::Failed(error.to_string()),
+    };
+    if replies.send(ready).is_err() {
+        return;
+    }
+    for request in requests {
+        let sent = match request {
+            Request::Step { span, cycle } => step_span(span, cycle, &replies),
+            Request::HandBack => replies.send(Reply::State(hec::hand_off())).is_ok(),
+        };
+        if !sent {
+            break;
+        }
+    }
+}
+
+/// Step HEC up to `span` cycles or until it finishes, keeping the output of
+/// each cycle instead of printing it.
+fn step_span(span: usize, cycle: usize, replies: &SyncSender<Reply>) -> bool {
+    let mut cycles = vec![];
+    for index in 0..span {
+        if hec::finish() || interrupted() {
+            break;
+        }
+        let mut output = String::new();
+        let failure = thread_failure(|| {
+            output = buffer_output(Some(cycle + index), || {
+                hec::step(1, false);
+            });
+        });
+        let values = hec::take_equal_values();
+        cycles.push(LevelCycle {
+            output,
+            values,
+            failure,
+        });
+    }
+    let finished = hec::finish();
+    replies.send(Reply::Span { cycles, finished }).is_ok()
+}
+
+/// Whether nothing set has to see HEC step on the thread of the shell,
+/// which watches, traces and recorders do. Neither is a handshake design,
+/// whose tokens a handoff loses.
+fn threads_allowed() -> bool {
+    let checks = step_checks(false);
+    !checks.watches
+        && !checks.tracepoints
+        && !have_stream_watch()
+        && options().stall_limit == 0
+        && !is_replaying()
+        && !super::vcd::vcd_enabled()
+        && !super::perfetto::perfetto_enabled()
+        && !super::rtl::rtl_connected()
+        && hec::with_top_dynamic(|_| ()).is_none()
+}
+
+/// Iterations of `cosim_loop` that step both levels whatever values they
+/// reach, as a cycle changes a comparison by at most `fan_in` values: those
+/// the skew window allows for the comparison furthest inside it, up to
+/// `cycle` and the next automatic checkpoint.
+fn epoch(cycle: usize) -> usize {
+    let skew = options().cosim_skew;
+    let window = {
+        let sim = sim();
+        let fan_in = sim.fan_in.max(1) as isize;
+        sim.comparison
+            .iter()
+            .map(|compare| skew - 1 - compare.size().abs())
+            .filter(|margin| *margin >= 0)
+            .map(|margin| (margin / fan_in + 1) as usize)
+            .max()
+            .unwrap_or(0)
+    };
+    let count = cycle_count(State::Cosim);
+    window
+        .min(cycle.saturating_sub(count))
+        .min(cycles_to_checkpoint(State::Cosim))
+}
+
+/// Step ToR if `high` and HEC on the worker if `low` for `span` iterations of
+/// `cosim_loop` at once, then print and compare what they reached in the
+/// order the iterations would. False on a mismatch, found after the whole
+/// span.
+fn step_worker(worker: &Worker, span: usize, high: bool, low: bool) -> bool {
+    let start = cycle_count(State::Cosim);
+    if low {
+        let cycle = if high { start + 1 } else { start };
+        worker.request(Request::Step { span, cycle });
+        worker.stale.set(true);
+    }
+    let mut high_cycles = vec![];
+    for _ in 0..span {
+        if !high || tor::finish() || interrupted() {
+            break;
+        }
+        let output = buffer_output(None, || {
+            tor::step(1, false);
+        });
+        let values = tor::take_equal_values();
+        high_cycles.push((output, values));
+    }
+    let mut low_cycles = vec![];
+    if low {
+        let Reply::Span { cycles, finished } = worker.reply() else {
+            panic!("cosim HEC thread out of step");
+        };
+        worker.finished.set(finished);
+        low_cycles = cycles;
+    }
+    let mut matched = true;
+    let mut high_cycles = high_cycles.into_iter();
+    let mut low_cycles = low_cycles.into_iter();
+    loop {
+        let (high, low) = (high_cycles.next(), low_cycles.next());
+        if high.is_none() && low.is_none() {
+            return matched;
+        }
+        if let Some((output, values)) = high {
+            print!("{}", output);
+            matched &= push_high_values(values.into_iter());
+        }
+        if let Some(reached) = low {
+            print!("{}", reached.output);
+            if let Some(failure) = reached.failure {
+                fail(failure);
+            }
+            advance_cycle(State::HEC);
+            matched &= push_low_values(reached.values.into_iter());
+        }
+    }
+}
+
+fn cosim_loop(cycle: usize, worker: Option<&Worker>) {
     while true {
+        if interrupted() {
+            println!("!!Cosimulation interrupted");
+            return;
+        }
+        if let Some(worker) = worker {
+            if cycles_to_checkpoint(State::Cosim) == 0 {
+                worker.hand_back();
+            }
+        }
+        auto_checkpoint(State::Cosim);
+        if cycle_count(State::Cosim) >= cycle {
+            return;
+        }
         let high = tor::finish();
-        let low = hec::finish();
+        let low = match worker {
+            Some(worker) => worker.finished.get(),
+            None => hec::finish(),
+        };
         if high && low {
             break;
         }
+        if let (Some(worker), false, false) = (worker, high, low) {
+            let span = epoch(cycle);
+            if span > 1 {
+                if !step_worker(worker, span, true, true) {
+                    println!("{}", paint(Color::Red, "!!Cosimulation failed"));
+                    return;
+                }
+                continue;
+            }
+        }
         let mut sim_high = false;
         let mut sim_low = false;
-        for compare in unsafe { &mut *COMPARISON }.values() {
+        let mut matched = true;
+        let skew = options().cosim_skew;
+        for compare in &sim().comparison {
             let size = compare.size();
-            if size < 10 {
+            if size < skew {
                 sim_low = true;
             }
-            if size > -10 {
+            if size > -skew {
                 sim_high = true;
             }
         }
-        if !high && sim_high {
-            tor::step(1, false);
-            let high_value = tor::get_equalvalue();
-            unsafe {
-                for pair in (*high_value).iter() {
-                    if let Some(compare) = (&mut *COMPARISON).get_mut(pair.0) {
-                        compare.push_high(pair.1.clone())
-                    }
-                }
-                (*high_value).clear();
+        if let Some(worker) = worker {
+            matched = step_worker(worker, 1, !high && sim_high, !low && sim_low);
+        } else {
+            if !high && sim_high {
+                tor::step(1, false);
+                matched &= push_high_values(tor::take_equal_values().into_iter());
             }
-        }
-        if !low && sim_low {
-            hec::step(1, false);
-            let low_value = hec::get_equalvalue();
-            unsafe {
-                for pair in (*low_value).iter() {
-                    if let Some(compare) =
-                        (&mut *COMPARISON).get_mut(&(*LOW_MAPPING).get(pair.0).unwrap().op)
-                    {
-                        compare.push_low(pair.1.clone())
-                    }
-                }
-                (*low_value).clear();
+            if !low && sim_low {
+                hec::step(1, false);
+                matched &= push_low_values(hec::take_equal_values().into_iter());
             }
         }
+        if !matched {
+            println!("{}", paint(Color::Red, "!!Cosimulation failed"));
+            return;
+        }
     }
     println!("Cosimulation success");
 }
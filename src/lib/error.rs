@@ -0,0 +1,317 @@
+use super::basetype::{Backing, Memory, VALUE};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// What went wrong, shown as `E0006` in front of the message. Codes below 100
+/// are found while loading the IR, the others while simulating it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    /// A field of the IR is missing or of the wrong type
+    MalformedIr = 1,
+    UnknownLevel = 2,
+    UnknownOperation = 3,
+    UnknownType = 4,
+    /// A constant whose literal does not fit its type
+    InvalidConstant = 5,
+    UndefinedOperand = 6,
+    UndefinedFunction = 7,
+    UndefinedMemory = 8,
+    /// An operation bound to two nodes of a time graph with no path between them
+    UnboundOperation = 9,
+    /// MLIR text that does not parse
+    InvalidSyntax = 10,
+    /// A memory backing file that cannot be mapped or is too short
+    BackingFile = 11,
+    OutOfBounds = 101,
+    /// A variable read before any operation writes it
+    Unassigned = 102,
+    /// ToR instances stepped on threads that end up unlike those stepped in
+    /// order, see the `parallel_instances` option
+    ParallelMismatch = 103,
+    /// A store at an X index, which could write any word of the memory
+    UnknownIndex = 104,
+    /// An integer `add`, `sub` or `mul` whose result does not fit its type,
+    /// with `overflow halt`
+    Overflow = 105,
+    /// An integer division or remainder by zero, with `div_zero trap`
+    DivisionByZero = 106,
+    /// An operation reading ERROR, such as the value of an empty port or
+    /// a load out of bounds, with `strict on`
+    ErrorOperand = 107,
+}
+
+/// An error in the IR or a fault of the design, with a stable code to look up
+/// and a message naming the operation and function at fault.
+#[derive(Debug, Clone)]
+pub struct HestiaError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl HestiaError {
+    pub fn new(code: ErrorCode, message: String) -> Self {
+        HestiaError { code, message }
+    }
+}
+
+impl fmt::Display for HestiaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{:04}: {}", self.code as u32, self.message)
+    }
+}
+
+impl std::error::Error for HestiaError {}
+
+/// How an IR object is named in messages: by the value it defines, or else by
+/// its operation.
+pub fn describe(ir: &Value) -> String {
+    match (ir["name"].as_str(), ir["op_type"].as_str()) {
+        (Some(name), None) => format!("'{}'", name),
+        (Some(name), _) => format!("operation '{}'", name),
+        (None, Some(op_type)) => format!("'{}' operation", op_type),
+        (None, None) => "IR object".to_string(),
+    }
+}
+
+fn malformed(ir: &Value, key: &str, expected: &str) -> HestiaError {
+    HestiaError::new(
+        ErrorCode::MalformedIr,
+        format!("{} has no {} field '{}'", describe(ir), expected, key),
+    )
+}
+
+/// The string field `key` of `ir`.
+pub fn str_field<'a>(ir: &'a Value, key: &str) -> Result<&'a str, HestiaError> {
+    ir[key].as_str().ok_or_else(|| malformed(ir, key, "string"))
+}
+
+/// The field `key` of `ir`, an array of strings.
+pub fn strings_field(ir: &Value, key: &str) -> Result<Vec<String>, HestiaError> {
+    let strings = ir[key].as_array().and_then(|items| {
+        items
+            .iter()
+            .map(|item| item.as_str().map(str::to_string))
+            .collect::<Option<Vec<String>>>()
+    });
+    strings.ok_or_else(|| malformed(ir, key, "string array"))
+}
+
+/// The field `key` of `ir`, a type memories and streams can hold.
+pub fn type_field<'a>(ir: &'a Value, key: &str) -> Result<&'a str, HestiaError> {
+    let data_type = str_field(ir, key)?;
+    match data_type {
+        "i8" | "u8" | "i16" | "u16" | "i32" | "i64" | "u32" | "u64" | "index" | "f16" | "bf16"
+        | "f32" | "f64" | "bool" => Ok(data_type),
+        _ => Err(HestiaError::new(
+            ErrorCode::UnknownType,
+            format!("{} has unknown type '{}'", describe(ir), data_type),
+        )),
+    }
+}
+
+/// The file mapped as the initial contents of the memory `ir`, if it names
+/// one with a `backing` field.
+pub fn backing_field(ir: &Value, size: usize) -> Result<Option<Arc<Backing>>, HestiaError> {
+    let Some(path) = ir["backing"].as_str() else {
+        return Ok(None);
+    };
+    let backing = Backing::open(path, str_field(ir, "type")?, size).map_err(|err| {
+        HestiaError::new(
+            ErrorCode::BackingFile,
+            format!("{} has an unusable backing file {}", describe(ir), err),
+        )
+    })?;
+    Ok(Some(Arc::new(backing)))
+}
+
+thread_local! {
+    /// The first fault of the design in the current cycle, such as an access
+    /// out of the bounds of a memory. Kept per thread, as the levels of a
+    /// cosim may step on threads of their own.
+    static FAULT: RefCell<Option<HestiaError>> = const { RefCell::new(None) };
+}
+
+/// Report a fault of the design. The interpreter carries on with an ERROR
+/// value and stops at the end of the cycle.
+pub fn fault(error: HestiaError) {
+    FAULT.with_borrow_mut(|first| {
+        if first.is_none() {
+            *first = Some(error);
+        }
+    })
+}
+
+/// Whether a fault was reported and not yet taken.
+pub fn fault_pending() -> bool {
+    FAULT.with_borrow(|first| first.is_some())
+}
+
+pub fn take_fault() -> Option<HestiaError> {
+    FAULT.with_borrow_mut(|first| first.take())
+}
+
+/// Whether `index` is within `memory`, reporting a fault if not.
+pub fn in_bounds(name: &str, memory: &Memory, index: usize) -> bool {
+    if index < memory.size() {
+        return true;
+    }
+    fault(HestiaError::new(
+        ErrorCode::OutOfBounds,
+        format!(
+            "index {} out of bounds of memory '{}' of size {}",
+            index,
+            name,
+            memory.size()
+        ),
+    ));
+    false
+}
+
+pub fn unknown_index(memory: &str) -> HestiaError {
+    HestiaError::new(
+        ErrorCode::UnknownIndex,
+        format!("store to memory '{}' at an unknown index", memory),
+    )
+}
+
+pub fn overflow(message: String) -> HestiaError {
+    HestiaError::new(ErrorCode::Overflow, message)
+}
+
+pub fn division_by_zero(dividend: VALUE) -> HestiaError {
+    HestiaError::new(
+        ErrorCode::DivisionByZero,
+        format!(
+            "{} {} divided by zero",
+            dividend.get_type(),
+            dividend.to_json()
+        ),
+    )
+}
+
+pub fn error_operand(operation: &str, op_type: &str, operand: &str, instance: &str) -> HestiaError {
+    HestiaError::new(
+        ErrorCode::ErrorOperand,
+        format!(
+            "operation '{}' ({}) in '{}' reads ERROR from '{}'",
+            operation, op_type, instance, operand
+        ),
+    )
+}
+
+pub fn unassigned(var: &str) -> HestiaError {
+    HestiaError::new(
+        ErrorCode::Unassigned,
+        format!("'{}' is read before it is assigned", var),
+    )
+}
+
+/// Names an operation of a software or ToR function defines.
+fn collect_defined(ops: &Value, defined: &mut HashSet<String>) {
+    for op in ops.as_array().into_iter().flatten() {
+        defined.extend(op["name"].as_str().map(str::to_string));
+        defined.extend(op["iter_name"].as_str().map(str::to_string));
+        for key in ["names", "iter_args"] {
+            let names = op[key].as_array().into_iter().flatten();
+            defined.extend(names.filter_map(|name| name.as_str().map(str::to_string)));
+        }
+        for body in ["body", "body0", "body1"] {
+            collect_defined(&op[body], defined);
+        }
+    }
+}
+
+/// The values an operation reads. A constant's operand is its literal.
+fn operands(op: &Value) -> Vec<&str> {
+    let mut operands = vec![];
+    for key in ["index", "value", "condition", "lb", "ub", "step"] {
+        operands.extend(op[key].as_str());
+    }
+    let mut lists = vec!["iter_inits"];
+    if op["op_type"] != "constant" {
+        lists.push("operands");
+    }
+    for key in lists {
+        let names = op[key].as_array().into_iter().flatten();
+        operands.extend(names.filter_map(Value::as_str));
+    }
+    operands
+}
+
+fn check_operations(
+    ops: &Value,
+    function: &str,
+    defined: &HashSet<String>,
+    memories: &HashSet<String>,
+    functions: &HashSet<String>,
+) -> Result<(), HestiaError> {
+    for op in ops.as_array().into_iter().flatten() {
+        for operand in operands(op) {
+            if !defined.contains(operand) {
+                return Err(HestiaError::new(
+                    ErrorCode::UndefinedOperand,
+                    format!(
+                        "operand '{}' of {} undefined in function '{}'",
+                        operand,
+                        describe(op),
+                        function
+                    ),
+                ));
+            }
+        }
+        if let Some(memory) = op["memory"].as_str() {
+            if !memories.contains(memory) {
+                return Err(HestiaError::new(
+                    ErrorCode::UndefinedMemory,
+                    format!(
+                        "memory '{}' of {} undefined in function '{}'",
+                        memory,
+                        describe(op),
+                        function
+                    ),
+                ));
+            }
+        }
+        if let Some(callee) = op["function"].as_str() {
+            if !functions.contains(callee) {
+                return Err(HestiaError::new(
+                    ErrorCode::UndefinedFunction,
+                    format!(
+                        "function '{}' called in '{}' is undefined",
+                        callee, function
+                    ),
+                ));
+            }
+        }
+        for body in ["body", "body0", "body1"] {
+            check_operations(&op[body], function, defined, memories, functions)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that every operand of the software or ToR functions in `ir` is an
+/// argument, a constant or a value some operation of the function defines,
+/// and that the memories and functions they use exist.
+pub fn check_functions(ir: &Value) -> Result<(), HestiaError> {
+    let names = |key: &str| -> HashSet<String> {
+        let items = ir[key].as_array().into_iter().flatten();
+        items
+            .filter_map(|item| item["name"].as_str().map(str::to_string))
+            .collect()
+    };
+    let constants = names("constants");
+    let memories = names("memory");
+    let functions = names("modules");
+    for module in ir["modules"].as_array().into_iter().flatten() {
+        let function = str_field(module, "name")?;
+        let mut defined = constants.clone();
+        defined.extend(strings_field(module, "args")?);
+        collect_defined(&module["body"], &mut defined);
+        check_operations(&module["body"], function, &defined, &memories, &functions)?;
+    }
+    Ok(())
+}
@@ -2,10 +2,20 @@ use super::basetype::*;
 use super::common::*;
 use super::elastic::*;
 use super::env::*;
+use super::equal::EqualValues;
+use super::error::{
+    backing_field, fault, str_field, take_fault, type_field, unknown_index, ErrorCode, HestiaError,
+};
+use super::intern::{Layout, Name, Slots};
+use super::perfetto::{activity, perfetto_enabled};
+use super::profile;
+use super::simulator;
+use super::vcd::{sample, vcd_enabled, Sample};
 use core::panic;
+use half::{bf16, f16};
 use multimap::MultiMap;
-use serde_json::Value;
-use std::collections::{HashMap, HashSet, VecDeque};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::error::Error;
 use std::iter::zip;
 use std::vec;
@@ -26,14 +36,19 @@ impl Handshake {
                 .iter()
                 .map(|x| {
                     let mut assign = Assignment::new(x);
-                    assign.src = assign
-                        .src
-                        .replace("dataIn", "data_in")
-                        .replace("dataOut", "data_out");
-                    assign.dst = assign
-                        .dst
-                        .replace("dataIn", "data_in")
-                        .replace("dataOut", "data_out");
+                    assign.src = Operand::new(
+                        &assign
+                            .src
+                            .name()
+                            .replace("dataIn", "data_in")
+                            .replace("dataOut", "data_out"),
+                    );
+                    assign.dst = Name::new(
+                        &assign
+                            .dst
+                            .replace("dataIn", "data_in")
+                            .replace("dataOut", "data_out"),
+                    );
                     assign
                 })
                 .collect(),
@@ -49,6 +64,33 @@ impl Handshake {
     }
 }
 
+/// Whether the FIFO ports enabled by a stage or state can be used in this
+/// cycle, reporting the first one that stalls `owner`.
+fn check_fifos(streams: &[String], owner: &str) -> bool {
+    for name in streams {
+        let (a, b) = name.split_once(".").unwrap();
+        let (empty, full) = {
+            let sim = sim();
+            let fifo = sim.fifo.get(a).unwrap();
+            (fifo.is_empty(), fifo.is_full())
+        };
+        let blocked = match b {
+            "r_en" if empty => "reading",
+            "w_en" if full => "writing",
+            _ => continue,
+        };
+        if stall_print() {
+            println!(
+                "{}",
+                paint(Color::Yellow, format!("Stall for {} {}", blocked, a))
+            );
+        }
+        report_stall(owner, &format!("{} {}", blocked, a));
+        return false;
+    }
+    true
+}
+
 #[derive(Debug, Clone)]
 struct Stage {
     ops: Vec<Operation>,
@@ -57,34 +99,18 @@ struct Stage {
 }
 
 impl Stage {
-    fn check_stream(&self) -> bool {
-        for name in &self.streams {
-            let (a, b) = name.split_once(".").unwrap();
-            let fifo = unsafe { &mut *FIFO }.get(&a.to_string()).unwrap();
-            if b == "r_en" {
-                if fifo.is_empty() {
-                    println!("Stall for reading {}", a);
-                    return false;
-                }
-            }
-            if b == "w_en" {
-                if fifo.is_full() {
-                    println!("Stall for writing {}", a);
-                    return false;
-                }
-            }
-        }
-        true
+    fn check_stream(&self, owner: &str) -> bool {
+        check_fifos(&self.streams, owner)
     }
 
-    fn new(ir: &Value) -> Self {
-        Stage {
+    fn new(ir: &Value) -> Result<Self, HestiaError> {
+        Ok(Stage {
             ops: ir["ops"]
                 .as_array()
                 .unwrap()
                 .iter()
                 .map(|x| Operation::new(x))
-                .collect(),
+                .collect::<Result<_, _>>()?,
             streams: ir["ops"]
                 .as_array()
                 .unwrap()
@@ -97,32 +123,32 @@ impl Stage {
                 .map(|x| x["port"].as_str().unwrap().to_string())
                 .collect(),
             name: ir["stage"].as_str().unwrap().to_string(),
-        }
+        })
     }
 }
 
 #[derive(Debug, Clone)]
 struct PipeFor {
-    inits: Vec<(String, String)>,
+    inits: Vec<(Name, Operand)>,
     stages: Vec<Stage>,
     cur_stages: VecDeque<usize>,
     ii: usize,
     control: Vec<VALUE>,
-    args: Vec<String>,
+    args: Vec<Name>,
 }
 
 impl PipeFor {
-    fn new(ir: &Value, args: Vec<String>) -> Self {
+    fn new(ir: &Value, args: Vec<Name>) -> Result<Self, HestiaError> {
         assert_eq!(ir["wires"]["name"].as_str().unwrap(), "i");
-        PipeFor {
+        Ok(PipeFor {
             inits: ir["inits"]
                 .as_array()
                 .unwrap()
                 .iter()
                 .map(|x| {
                     (
-                        String::from(x["dst"].as_str().unwrap()),
-                        String::from(x["src"].as_str().unwrap()),
+                        Name::new(x["dst"].as_str().unwrap()),
+                        Operand::new(x["src"].as_str().unwrap()),
                     )
                 })
                 .collect(),
@@ -131,19 +157,19 @@ impl PipeFor {
                 .unwrap()
                 .iter()
                 .map(|x| Stage::new(x))
-                .collect(),
+                .collect::<Result<_, _>>()?,
             cur_stages: VecDeque::new(),
             ii: ir["ii"].as_u64().unwrap() as usize,
             control: vec![],
             args: args,
-        }
+        })
     }
 
-    fn check_stream(&self) -> bool {
+    fn check_stream(&self, owner: &str) -> bool {
         let cur_stages = &self.cur_stages;
         for cur in cur_stages.iter() {
             let stage = &self.stages[*cur];
-            if !stage.check_stream() {
+            if !stage.check_stream(owner) {
                 return false;
             }
         }
@@ -153,7 +179,7 @@ impl PipeFor {
     fn step(&mut self, env: &mut StaticEnv) -> bool {
         let cur_stages = &mut self.cur_stages;
 
-        env.set_value(&"done".to_string(), VALUE::Bool(false));
+        env.set_value(*DONE, VALUE::Bool(false));
 
         if cur_stages.is_empty() {
             return false;
@@ -163,13 +189,14 @@ impl PipeFor {
 
         for cur in cur_stages.iter_mut() {
             let stage = &self.stages[*cur];
+            sim().enabling.clone_from(&stage.name);
             for op in &stage.ops {
                 if let EVAL::Deliver(deliver) = &op.eval(env) {
-                    let val = env.get_value(&deliver.src);
+                    let val = env.read(deliver.src);
                     if stage_num > 1 {
-                        env.set_value(&deliver.dst_reg, val);
+                        env.set_value(deliver.dst_reg, val);
                     } else {
-                        env.set_value(&deliver.dst_port, val);
+                        env.set_value(deliver.dst_port, val);
                     }
                 }
             }
@@ -177,10 +204,10 @@ impl PipeFor {
         }
 
         if *cur_stages.front().unwrap() == self.ii {
-            let iter = env.get_value(&"i".to_string());
+            let iter = env.get_value(*ITER);
             let new_iter = add(&iter.get_type(), vec![iter, self.control[2]].as_ref());
             if let VALUE::Bool(true) = cmp_lte(vec![new_iter, self.control[1]].as_ref()) {
-                env.set_value(&"i".to_string(), new_iter);
+                env.set_value(*ITER, new_iter);
                 cur_stages.push_front(0);
             }
         }
@@ -188,16 +215,16 @@ impl PipeFor {
         if *cur_stages.back().unwrap() == self.stages.len() {
             cur_stages.pop_back();
             if cur_stages.is_empty() {
-                env.set_value(&"done".to_string(), VALUE::Bool(true));
+                env.set_value(*DONE, VALUE::Bool(true));
                 return false;
             }
         }
         true
     }
 
-    fn mark(&self) -> bool {
+    fn mark(&self, env: &StaticEnv) -> bool {
         for cur in &self.cur_stages {
-            if test_breakpoint(&self.stages[*cur].name) {
+            if test_breakpoint(&self.stages[*cur].name, &|var| env.lookup(var)) {
                 return true;
             }
         }
@@ -207,18 +234,18 @@ impl PipeFor {
     fn init(&mut self, env: &mut StaticEnv) {
         self.control = self.args[..=2]
             .iter()
-            .map(|arg| env.get_value_inner(arg))
+            .map(|arg| env.get_value_inner(*arg))
             .collect();
 
         if let VALUE::Bool(true) = cmp_lte(vec![self.control[0], self.control[1]].as_ref()) {
             self.cur_stages = VecDeque::from(vec![0]);
             for (dst, src) in &self.inits {
-                env.set_value(dst, env.get_value(src))
+                env.set_value(*dst, env.read(*src))
             }
-            env.set_value(&"i".to_string(), self.control[0]);
-            env.set_value(&"done".to_string(), VALUE::Bool(false));
+            env.set_value(*ITER, self.control[0]);
+            env.set_value(*DONE, VALUE::Bool(false));
         } else {
-            env.set_value(&"done".to_string(), VALUE::Bool(true));
+            env.set_value(*DONE, VALUE::Bool(true));
         }
     }
 }
@@ -234,54 +261,54 @@ enum Strategy {
 
 #[derive(Debug, Clone)]
 struct Go {
-    instance: String,
+    instance: Name,
 }
 
 impl Go {
     fn new(ir: &Value) -> Self {
         Go {
-            instance: String::from(ir["instance"].as_str().unwrap()),
+            instance: Name::new(ir["instance"].as_str().unwrap()),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 struct Deliver {
-    dst_port: String,
-    dst_reg: String,
-    src: String,
+    dst_port: Name,
+    dst_reg: Name,
+    src: Operand,
 }
 
 impl Deliver {
     fn new(ir: &Value) -> Self {
         Deliver {
-            dst_port: String::from(ir["dst_port"].as_str().unwrap()),
-            dst_reg: String::from(ir["dst_reg"].as_str().unwrap()),
-            src: String::from(ir["src"].as_str().unwrap()),
+            dst_port: Name::new(ir["dst_port"].as_str().unwrap()),
+            dst_reg: Name::new(ir["dst_reg"].as_str().unwrap()),
+            src: Operand::new(ir["src"].as_str().unwrap()),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 struct Assignment {
-    dst: String,
-    src: String,
-    guard: Option<String>,
+    dst: Name,
+    src: Operand,
+    guard: Option<Operand>,
 }
 
 impl Assignment {
     fn new(ir: &Value) -> Self {
         Assignment {
-            dst: ir["dst"].as_str().unwrap().to_string(),
-            src: ir["src"].as_str().unwrap().to_string(),
-            guard: ir["condition"].as_str().map(|s| s.to_string()),
+            dst: Name::new(ir["dst"].as_str().unwrap()),
+            src: Operand::new(ir["src"].as_str().unwrap()),
+            guard: ir["condition"].as_str().map(Operand::new),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 enum Operation {
-    Enable(String),
+    Enable(Name),
     Assign(Assignment),
     Compute(Computation),
     Go(Go),
@@ -290,18 +317,32 @@ enum Operation {
 
 enum EVAL {
     Nothing,
-    Go(String),
+    Go(Name),
     Deliver(Deliver),
 }
 
 impl Operation {
-    fn new(ir: &Value) -> Self {
-        match ir["op_type"].as_str().unwrap() {
+    fn new(ir: &Value) -> Result<Self, HestiaError> {
+        Ok(match str_field(ir, "op_type")? {
             "go" => Operation::Go(Go::new(ir)),
             "deliver" => Operation::Deliver(Deliver::new(ir)),
             "assign" => Operation::Assign(Assignment::new(ir)),
-            "enable" => Operation::Enable(ir["port"].as_str().unwrap().to_string()),
-            _ => Operation::Compute(Computation::new(ir)),
+            "enable" => Operation::Enable(Name::new(str_field(ir, "port")?)),
+            _ => Operation::Compute(Computation::new(ir)?),
+        })
+    }
+
+    fn bind(&mut self, constants: &Slots<VALUE>) {
+        match self {
+            Operation::Compute(compute) => compute.bind(constants),
+            Operation::Deliver(deliver) => deliver.src.bind(constants),
+            Operation::Assign(assign) => {
+                assign.src.bind(constants);
+                if let Some(guard) = &mut assign.guard {
+                    guard.bind(constants);
+                }
+            }
+            Operation::Enable(_) | Operation::Go(_) => (),
         }
     }
 
@@ -311,19 +352,19 @@ impl Operation {
                 operation_env(compute, env);
                 EVAL::Nothing
             }
-            Operation::Go(go) => EVAL::Go(go.instance.to_owned()),
+            Operation::Go(go) => EVAL::Go(go.instance),
             Operation::Deliver(deliver) => EVAL::Deliver(deliver.clone()),
             Operation::Assign(assign) => {
-                if let Some(cond) = &assign.guard {
-                    if env.get_value(cond) != VALUE::Bool(true) {
+                if let Some(cond) = assign.guard {
+                    if env.read(cond) != VALUE::Bool(true) {
                         return EVAL::Nothing;
                     }
                 }
-                env.set_value(&assign.dst, env.get_value(&assign.src));
+                env.set_value(assign.dst, env.read(assign.src));
                 EVAL::Nothing
             }
             Operation::Enable(port) => {
-                env.set_value(port, VALUE::Bool(true));
+                env.set_value(*port, VALUE::Bool(true));
                 // env.print(0);
                 EVAL::Nothing
             }
@@ -336,39 +377,23 @@ struct State {
     ops: Vec<Operation>,
     streams: Vec<String>,
     default: Option<String>,
-    transition: Vec<(String, String)>,
-    done: Option<Vec<String>>,
+    transition: Vec<(String, Operand)>,
+    done: Option<Vec<Operand>>,
 }
 
 impl State {
-    fn check_stream(&self) -> bool {
-        for name in &self.streams {
-            let (a, b) = name.split_once(".").unwrap();
-            let fifo = unsafe { &mut *FIFO }.get(&a.to_string()).unwrap();
-            if b == "r_en" {
-                if fifo.is_empty() {
-                    println!("Stall for reading {}", a);
-                    return false;
-                }
-            }
-            if b == "w_en" {
-                if fifo.is_full() {
-                    println!("Stall for writing {}", a);
-                    return false;
-                }
-            }
-        }
-        true
+    fn check_stream(&self, owner: &str) -> bool {
+        check_fifos(&self.streams, owner)
     }
 
-    fn new(ir: &Value) -> Self {
-        State {
+    fn new(ir: &Value) -> Result<Self, HestiaError> {
+        Ok(State {
             ops: ir["ops"]
                 .as_array()
                 .unwrap()
                 .iter()
                 .map(|x| Operation::new(x))
-                .collect(),
+                .collect::<Result<_, _>>()?,
             streams: ir["ops"]
                 .as_array()
                 .unwrap()
@@ -388,65 +413,66 @@ impl State {
                     .map(|x| {
                         (
                             String::from(x["dest"].as_str().unwrap()),
-                            String::from(x["cond"].as_str().unwrap()),
+                            Operand::new(x["cond"].as_str().unwrap()),
                         )
                     })
                     .collect(),
             },
             done: ir["transition"]["done"].as_array().map(|done| {
                 done.iter()
-                    .map(|val| val.as_str().unwrap().to_string().to_owned())
+                    .map(|val| Operand::new(val.as_str().unwrap()))
                     .collect()
             }),
-        }
+        })
     }
 }
 
 #[derive(Debug, Clone)]
 struct STG {
     stg: HashMap<String, State>,
-    _init_state: String,
+    init_state: String,
     state: String,
-    args: Vec<String>,
+    args: Vec<Name>,
     num_in: usize,
 }
 
 impl STG {
-    fn new(ir: &Value, args: Vec<String>, num_in: usize) -> Self {
+    fn new(ir: &Value, args: Vec<Name>, num_in: usize) -> Result<Self, HestiaError> {
         let mut stg = HashMap::new();
-        ir["states"].as_array().unwrap().iter().for_each(|x| {
-            stg.insert(String::from(x["state"].as_str().unwrap()), State::new(x));
-        });
+        for x in ir["states"].as_array().unwrap() {
+            stg.insert(String::from(x["state"].as_str().unwrap()), State::new(x)?);
+        }
         let init_state = ir["init_state"].as_str().unwrap().to_string();
-        STG {
+        Ok(STG {
             stg,
-            _init_state: init_state.clone(),
+            init_state: init_state.clone(),
             state: init_state,
             args: args,
             num_in,
-        }
+        })
     }
 
-    fn check_stream(&self) -> bool {
+    fn check_stream(&self, owner: &str) -> bool {
         let cur_state = &self.state;
         let state = self.stg.get(cur_state).unwrap();
         match &state.done {
-            None => state.check_stream(),
+            None => state.check_stream(owner),
             _ => true,
         }
     }
 
     fn step(&mut self, env: &mut StaticEnv) -> bool {
         let cur_state = &mut self.state;
-        env.set_value(&"done".to_string(), VALUE::Bool(false));
+        env.set_value(*DONE, VALUE::Bool(false));
 
         let state = self.stg.get(cur_state).unwrap();
         match &state.done {
             None => {
+                sim().enabling.clone_from(cur_state);
                 for op in &state.ops {
                     if let EVAL::Go(instance_name) = op.eval(env) {
                         if let Component::Module(module_ref) =
-                            env.sub_env.get(&instance_name).unwrap()
+                            env.sub_env.get(instance_name).unwrap()
                         {
                             let mut module = module_ref.borrow_mut();
                             match &mut *module {
@@ -455,22 +481,20 @@ impl STG {
                                         instance.active = true;
                                         // instance
                                         //     .env
-                                        //     .set_value(&"done".to_string(), VALUE::Bool(false));
+                                        //     .set_value(*DONE, VALUE::Bool(false));
                                         instance.stg.step(&mut instance.env);
-                                        unsafe { &mut (*ACTIVE) }.push(module_ref.clone());
-                                        unsafe { &mut (*NEW_INSTANCE) }.push(module_ref.clone());
+                                        sim().active.push(module_ref.clone());
+                                        sim().new_instance.push(module_ref.clone());
                                     }
                                 }
                                 Instance::PipeFor(instance, _) => {
                                     if !instance.active {
                                         instance.active = true;
                                         // instance.pipeline.init(&mut instance.env);
-                                        instance
-                                            .env
-                                            .set_value(&"done".to_string(), VALUE::Bool(false));
+                                        instance.env.set_value(*DONE, VALUE::Bool(false));
                                         // instance.pipeline.step(&mut instance.env);
-                                        unsafe { &mut (*ACTIVE) }.push(module_ref.clone());
-                                        unsafe { &mut (*NEW_INSTANCE) }.push(module_ref.clone());
+                                        sim().active.push(module_ref.clone());
+                                        sim().new_instance.push(module_ref.clone());
                                     }
                                 }
                             }
@@ -480,7 +504,7 @@ impl STG {
 
                 let mut has_jump = false;
                 for (jump, cond) in &state.transition {
-                    if env.get_value(cond) == VALUE::Bool(true) {
+                    if env.read(*cond) == VALUE::Bool(true) {
                         *cur_state = jump.to_owned();
                         has_jump = true;
                         break;
@@ -494,24 +518,24 @@ impl STG {
                 true
             }
             Some(done) => {
-                let return_val: Vec<VALUE> = done.iter().map(|val| env.get_value(val)).collect();
-                // println!("Return {:?}", return_val);
+                let return_val: Vec<VALUE> = done.iter().map(|val| env.read(*val)).collect();
+                tracing::trace!(values = ?return_val, "return");
                 for (arg, val) in zip(&self.args[self.num_in..self.args.len() - 1], return_val) {
-                    env.set_value(arg, val)
+                    env.set_value(*arg, val)
                 }
-                env.set_value(&"done".to_string(), VALUE::Bool(true));
-                *cur_state = self._init_state.clone();
+                env.set_value(*DONE, VALUE::Bool(true));
+                *cur_state = self.init_state.clone();
                 false
             }
         }
     }
 
-    fn mark(&self) -> bool {
-        test_breakpoint(&self.state)
+    fn mark(&self, env: &StaticEnv) -> bool {
+        test_breakpoint(&self.state, &|var| env.lookup(var))
     }
 
     fn _init(&mut self) {
-        self.state = self._init_state.clone()
+        self.state = self.init_state.clone()
     }
 }
 
@@ -533,53 +557,97 @@ impl Submodule {
 #[derive(Debug, Clone)]
 struct Module {
     strategy: Strategy,
-    args: Vec<String>,
+    args: Vec<Name>,
     types: Vec<String>,
     units: Vec<Unit>,
     instances: Vec<Submodule>,
+    /// The slot of each unit and submodule instance in an environment.
+    components: Arc<Layout>,
     _num_in: usize,
     name: String,
 }
 
 impl Module {
-    fn new(ir: &Value) -> Self {
+    fn new(ir: &Value) -> Result<Self, HestiaError> {
         let strategy = match ir["style"].as_str().unwrap() {
             "dataflow" | "STG" => Strategy::STG(STG::new(
                 ir,
-                construct(&ir["args"]),
+                Name::list(&ir["args"]),
                 ir["num_in"].as_u64().unwrap() as usize,
-            )),
+            )?),
             "pipeline" => match ir["pipeline_style"].as_str().unwrap() {
-                "for" => Strategy::PipelineFor(PipeFor::new(ir, construct(&ir["args"]))),
+                "for" => Strategy::PipelineFor(PipeFor::new(ir, Name::list(&ir["args"]))?),
                 "function" => Strategy::Pipeline,
                 _ => Strategy::_TEMP,
             },
             "handshake" => Strategy::Handshake(Handshake::new(ir, construct(&ir["args"]))),
             _str => Strategy::_TEMP,
         };
-        Module {
+        let units: Vec<Unit> = ir["units"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(Unit::new)
+            .collect();
+        let instances: Vec<Submodule> = ir["instances"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(Submodule::new)
+            .collect();
+        let mut components = Layout::default();
+        for name in units.iter().map(|unit| &unit.name) {
+            components.insert(Name::new(name));
+        }
+        for name in instances.iter().map(|instance| &instance.name) {
+            components.insert(Name::new(name));
+        }
+        Ok(Module {
             strategy,
-            args: construct(&ir["args"]),
+            args: Name::list(&ir["args"]),
             types: construct(&ir["types"]),
-            units: ir["units"]
-                .as_array()
-                .unwrap()
-                .iter()
-                .map(|x| Unit::new(x))
-                .collect(),
-            instances: ir["instances"]
-                .as_array()
-                .unwrap()
-                .iter()
-                .map(|x| Submodule::new(x))
-                .collect(),
+            units,
+            instances,
+            components: Arc::new(components),
             _num_in: ir["num_in"].as_u64().unwrap() as usize,
             name: ir["name"].as_str().unwrap().to_string(),
+        })
+    }
+
+    /// Bind the operands of the states and stages that name constants to
+    /// their values. A handshake module binds its constants to the constant
+    /// units when it is instantiated.
+    fn bind(&mut self, constants: &Slots<VALUE>) {
+        match &mut self.strategy {
+            Strategy::STG(stg) => {
+                for state in stg.stg.values_mut() {
+                    for op in &mut state.ops {
+                        op.bind(constants);
+                    }
+                    for (_, cond) in &mut state.transition {
+                        cond.bind(constants);
+                    }
+                    for val in state.done.iter_mut().flatten() {
+                        val.bind(constants);
+                    }
+                }
+            }
+            Strategy::PipelineFor(pipeline) => {
+                for (_, src) in &mut pipeline.inits {
+                    src.bind(constants);
+                }
+                for stage in &mut pipeline.stages {
+                    for op in &mut stage.ops {
+                        op.bind(constants);
+                    }
+                }
+            }
+            _ => (),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct STGInstance {
     stg: STG,
     active: bool,
@@ -587,7 +655,7 @@ struct STGInstance {
     stall: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct PipeForInstance {
     pipeline: PipeFor,
     active: bool,
@@ -598,53 +666,71 @@ struct PipeForInstance {
 #[derive(Debug)]
 struct HandshakeInstance {}
 
-use std::cell::RefCell;
-use std::sync::Arc;
-
-static mut MODULE: *mut HashMap<String, Module> = std::ptr::null_mut();
-static mut MEMORY: *mut HashMap<String, HardwareMemory> = std::ptr::null_mut();
-static mut FIFO: *mut HashMap<String, FIFO> = std::ptr::null_mut();
-static mut CONSTANT: *mut HashMap<String, VALUE> = std::ptr::null_mut();
-static mut ACTIVE: *mut Vec<Arc<RefCell<Instance>>> = std::ptr::null_mut();
-static mut NEW_INSTANCE: *mut Vec<Arc<RefCell<Instance>>> = std::ptr::null_mut();
-static mut TOP_DYNAMIC: Option<HandshakeEnv> = None;
-
-static mut EQUALPOINT: *mut HashMap<String, HashSet<String>> = std::ptr::null_mut();
-static mut EQUALVALUE: *mut HashMap<(String, String), VALUE> = std::ptr::null_mut();
+use std::cell::{RefCell, RefMut};
+use std::rc::Rc;
+use std::sync::{Arc, LazyLock};
+
+/// The HEC simulation: the loaded modules, memories and FIFOs, the instances
+/// running them and the handshake network of a dynamic top-level module.
+#[derive(Default)]
+pub struct HecSim {
+    module: HashMap<String, Rc<Module>>,
+    memory: HashMap<String, HardwareMemory>,
+    fifo: HashMap<String, FIFO>,
+    /// Names of the memories and FIFOs, whose ports `name.port` are not
+    /// ports of a unit.
+    globals: Layout,
+    /// Last state or stage that enabled the read and write port of each FIFO.
+    fifo_access: HashMap<String, (String, String)>,
+    /// State or stage whose operations are being evaluated.
+    enabling: String,
+    constant: Slots<VALUE>,
+    active: Vec<Rc<RefCell<Instance>>>,
+    /// Cycles spent in each state or pipeline stage of each module since the
+    /// run was started by `call`.
+    state_cycles: HashMap<String, HashMap<String, usize>>,
+    new_instance: Vec<Rc<RefCell<Instance>>>,
+    top_dynamic: Option<HandshakeEnv>,
+    /// The result ports compared at each state, with their comparisons.
+    equal_point: HashMap<String, Vec<(Name, usize)>>,
+    equal_value: EqualValues,
+}
 
-pub fn set_equalpoint(state: String, op: String) {
-    if let Some(ops) = unsafe { &mut (*EQUALPOINT) }.get_mut(&state) {
-        ops.insert(op);
-    } else {
-        let mut ops = HashSet::new();
-        ops.insert(op);
-        unsafe { &mut (*EQUALPOINT) }.insert(state, ops);
-    }
+fn sim() -> RefMut<'static, HecSim> {
+    simulator::current().hec.borrow_mut()
 }
 
-pub fn have_equalpoint() -> bool {
-    !unsafe { &*EQUALPOINT }.is_empty()
+/// Compare the operations of `points` in a cosim, by state and with their
+/// comparisons, with `count` comparisons.
+pub fn set_equalpoints(points: HashMap<String, HashMap<String, usize>>, count: usize) {
+    sim().equal_point = points
+        .into_iter()
+        .map(|(state, ops)| {
+            let results = ops
+                .into_iter()
+                .map(|(op, index)| (Name::new(&(op + ".result")), index))
+                .collect();
+            (state, results)
+        })
+        .collect();
+    sim().equal_value = EqualValues::new(count);
 }
 
-pub fn contain_equalpoint(state: &String, op: &String) -> bool {
-    if let Some(ops) = unsafe { &*EQUALPOINT }.get(state) {
-        ops.contains(op)
-    } else {
-        false
-    }
+pub fn have_equalpoint() -> bool {
+    !sim().equal_point.is_empty()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Instance {
-    Static(STGInstance, Option<Arc<RefCell<Instance>>>),
-    PipeFor(PipeForInstance, Option<Arc<RefCell<Instance>>>),
+    Static(STGInstance, Option<Rc<RefCell<Instance>>>),
+    PipeFor(PipeForInstance, Option<Rc<RefCell<Instance>>>),
 }
 
 impl Instance {
     fn step(&mut self) -> bool {
         match self {
             Instance::Static(instance, _) => {
-                if instance.stg.check_stream() {
+                if instance.stg.check_stream(&instance.env.name) {
                     instance.stall = false;
                     let step = instance.stg.step(&mut instance.env);
                     if !step {
@@ -652,9 +738,7 @@ impl Instance {
                     }
                     step
                 } else {
-                    instance
-                        .env
-                        .set_value(&"done".to_string(), VALUE::Bool(false));
+                    instance.env.set_value(*DONE, VALUE::Bool(false));
                     instance.stall = true;
                     true
                 }
@@ -663,7 +747,7 @@ impl Instance {
                 if instance.pipeline.cur_stages.is_empty() {
                     instance.pipeline.init(&mut instance.env);
                 }
-                if instance.pipeline.check_stream() {
+                if instance.pipeline.check_stream(&instance.env.name) {
                     instance.stall = false;
                     let step = instance.pipeline.step(&mut instance.env);
                     if !step {
@@ -671,9 +755,7 @@ impl Instance {
                     }
                     step
                 } else {
-                    instance
-                        .env
-                        .set_value(&"done".to_string(), VALUE::Bool(false));
+                    instance.env.set_value(*DONE, VALUE::Bool(false));
                     instance.stall = true;
                     true
                 }
@@ -712,119 +794,194 @@ impl Instance {
 
     fn mark(&self) -> bool {
         match self {
-            Instance::Static(instance, _) => instance.stg.mark(),
-            Instance::PipeFor(instance, _) => instance.pipeline.mark(),
+            Instance::Static(instance, _) => instance.stg.mark(&instance.env),
+            Instance::PipeFor(instance, _) => instance.pipeline.mark(&instance.env),
         }
     }
 }
 
 pub fn parse_hec(ir: &Value) -> Result<(), Box<dyn Error>> {
-    unsafe {
-        init_global(&mut MODULE);
-        init_global(&mut MEMORY);
-        init_global(&mut FIFO);
-        init_global(&mut CONSTANT);
-        init_global(&mut ACTIVE);
-        init_global(&mut NEW_INSTANCE);
+    *sim() = HecSim::default();
+    merge_hec(ir)
+}
 
-        init_global(&mut EQUALPOINT);
-        init_global(&mut EQUALVALUE);
+/// Names in `ir` that clash with what is already loaded. Memories and
+/// constants may be shared between files as long as the declarations agree.
+fn check_duplicates(ir: &Value) -> Result<Vec<String>, HestiaError> {
+    let mut duplicates = vec![];
+    if let Value::Array(modules) = &ir["modules"] {
+        for module in modules {
+            let name = module["name"].as_str().unwrap();
+            if sim().module.contains_key(name) {
+                duplicates.push(format!("module {}", name));
+            }
+        }
     }
-
     if let Value::Array(memorys) = &ir["memory"] {
         for memory in memorys {
             let name = memory["name"].as_str().unwrap();
-            let size = memory["size"].as_u64().unwrap();
-            unsafe {
-                let data_type = memory["type"].as_str().unwrap();
-                match data_type {
-                    "i32" => (*MEMORY).insert(
-                        String::from(name),
-                        HardwareMemory::new(vec![VALUE::I32(0); size as usize], &"i32".to_string()),
-                    ),
-                    "i64" => (*MEMORY).insert(
-                        String::from(name),
-                        HardwareMemory::new(vec![VALUE::I64(0); size as usize], &"i64".to_string()),
-                    ),
-                    "u32" => (*MEMORY).insert(
-                        String::from(name),
-                        HardwareMemory::new(vec![VALUE::U32(0); size as usize], &"u32".to_string()),
-                    ),
-                    "u64" => (*MEMORY).insert(
-                        String::from(name),
-                        HardwareMemory::new(vec![VALUE::U64(0); size as usize], &"u64".to_string()),
-                    ),
-                    "f32" => (*MEMORY).insert(
-                        String::from(name),
-                        HardwareMemory::new(
-                            vec![VALUE::F32(0.0); size as usize],
-                            &"f32".to_string(),
-                        ),
-                    ),
-                    "f64" => (*MEMORY).insert(
-                        String::from(name),
-                        HardwareMemory::new(
-                            vec![VALUE::F64(0.0); size as usize],
-                            &"f64".to_string(),
-                        ),
-                    ),
-                    "bool" => (*MEMORY).insert(
-                        String::from(name),
-                        HardwareMemory::new(
-                            vec![VALUE::Bool(false); size as usize],
-                            &"bool".to_string(),
-                        ),
-                    ),
-                    _ => None,
-                };
+            if let Some(loaded) = sim().memory.get(name) {
+                let store = loaded.dump();
+                if store.len() as u64 != memory["size"].as_u64().unwrap()
+                    || store[0].get_type() != memory["type"].as_str().unwrap()
+                {
+                    duplicates.push(format!("memory {}", name));
+                }
             }
         }
     }
-
     if let Value::Array(streams) = &ir["stream"] {
         for stream in streams {
             let name = stream["name"].as_str().unwrap();
-            let depth = stream["depth"].as_u64().unwrap();
-            let data_type = stream["type"].as_str().unwrap();
-            unsafe {
-                (*FIFO).insert(
-                    String::from(name),
-                    FIFO::new(depth as usize, &data_type.to_string()),
-                );
+            if sim().fifo.contains_key(name) {
+                duplicates.push(format!("stream {}", name));
             }
         }
     }
-
     if let Value::Array(constants) = &ir["constants"] {
         for constant in constants {
-            let constant = Constant::new(&constant);
-            unsafe {
-                (*CONSTANT).insert(
-                    constant.name,
-                    build_value(&constant.ret_type, &constant.value),
-                );
+            let constant = Constant::new(constant)?;
+            if let Some(value) = sim().constant.get(Name::new(&constant.name)) {
+                if *value != build_value(&constant.ret_type, &constant.value) {
+                    duplicates.push(format!("constant {}", constant.name));
+                }
             }
         }
     }
+    Ok(duplicates)
+}
 
-    if let Value::Array(modules) = &ir["modules"] {
-        for module in modules {
-            unsafe {
-                (*MODULE).insert(
-                    String::from(module["name"].as_str().unwrap()),
-                    Module::new(&module),
-                );
+/// Add the memories, streams, constants and modules of another HEC file to
+/// the loaded design. Nothing is added if any name conflicts.
+pub fn merge_hec(ir: &Value) -> Result<(), Box<dyn Error>> {
+    let duplicates = check_duplicates(ir)?;
+    if !duplicates.is_empty() {
+        return Err(format!("duplicate {}", duplicates.join(", ")).into());
+    }
+    let mut constants = sim().constant.clone();
+    for constant in ir["constants"].as_array().into_iter().flatten() {
+        let constant = Constant::new(constant)?;
+        constants.insert(
+            Name::new(&constant.name),
+            build_value(&constant.ret_type, &constant.value),
+        );
+    }
+    let mut modules = vec![];
+    for module in ir["modules"].as_array().into_iter().flatten() {
+        modules.push((
+            str_field(module, "name")?.to_string(),
+            Rc::new(Module::new(module)?),
+        ));
+    }
+    for memory in ir["memory"].as_array().into_iter().flatten() {
+        type_field(memory, "type")?;
+    }
+
+    if let Value::Array(memorys) = &ir["memory"] {
+        for memory in memorys {
+            let name = memory["name"].as_str().unwrap();
+            if sim().memory.contains_key(name) {
+                continue;
+            }
+            let size = memory["size"].as_u64().unwrap();
+            let data_type = memory["type"].as_str().unwrap();
+            match data_type {
+                "i8" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::I8(0), size as usize, &"i8".to_string()),
+                ),
+                "u8" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::U8(0), size as usize, &"u8".to_string()),
+                ),
+                "i16" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::I16(0), size as usize, &"i16".to_string()),
+                ),
+                "u16" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::U16(0), size as usize, &"u16".to_string()),
+                ),
+                "i32" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::I32(0), size as usize, &"i32".to_string()),
+                ),
+                "i64" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::I64(0), size as usize, &"i64".to_string()),
+                ),
+                "u32" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::U32(0), size as usize, &"u32".to_string()),
+                ),
+                "u64" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::U64(0), size as usize, &"u64".to_string()),
+                ),
+                "index" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::Index(0), size as usize, &"index".to_string()),
+                ),
+                "f16" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::F16(f16::ZERO), size as usize, &"f16".to_string()),
+                ),
+                "bf16" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(
+                        VALUE::BF16(bf16::ZERO),
+                        size as usize,
+                        &"bf16".to_string(),
+                    ),
+                ),
+                "f32" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::F32(0.0), size as usize, &"f32".to_string()),
+                ),
+                "f64" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::F64(0.0), size as usize, &"f64".to_string()),
+                ),
+                "bool" => sim().memory.insert(
+                    String::from(name),
+                    HardwareMemory::new(VALUE::Bool(false), size as usize, &"bool".to_string()),
+                ),
+                _ => None,
+            };
+            if let Some(backing) = backing_field(memory, size as usize)? {
+                sim().memory.get_mut(name).unwrap().set_backing(backing);
             }
+            sim().globals.insert(Name::new(name));
+        }
+    }
+
+    if let Value::Array(streams) = &ir["stream"] {
+        for stream in streams {
+            let name = stream["name"].as_str().unwrap();
+            let depth = stream["depth"].as_u64().unwrap();
+            let data_type = stream["type"].as_str().unwrap();
+            sim().fifo.insert(
+                String::from(name),
+                FIFO::new(depth as usize, &data_type.to_string()),
+            );
+            sim().globals.insert(Name::new(name));
         }
     }
 
+    sim().module.extend(modules);
+    // Modules of earlier files may read constants of this one
+    for module in sim().module.values_mut() {
+        Rc::make_mut(module).bind(&constants);
+    }
+    sim().constant = constants;
+
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Component {
     Primitive(UnitEnv),
-    Module(Arc<RefCell<Instance>>),
+    Module(Rc<RefCell<Instance>>),
 }
 
 impl Component {
@@ -846,6 +1003,15 @@ impl Component {
         }
     }
 
+    fn to_json(&self) -> Value {
+        match self {
+            Component::Primitive(env) => env.to_json(),
+            Component::Module(instance_ref) => {
+                instance_ref.borrow().get_env().to_json(&NameFilter::all())
+            }
+        }
+    }
+
     fn clear(&mut self) {
         match self {
             Component::Primitive(env) => env.clear(),
@@ -857,54 +1023,65 @@ impl Component {
     }
 }
 
-#[derive(Debug)]
+/// The ports every module environment holds.
+static DONE: LazyLock<Name> = LazyLock::new(|| Name::new("done"));
+static ITER: LazyLock<Name> = LazyLock::new(|| Name::new("i"));
+
+#[derive(Debug, Clone)]
 struct StaticEnv {
-    env: HashMap<String, VALUE>,
-    sub_env: HashMap<String, Component>,
+    env: Slots<VALUE>,
+    sub_env: Slots<Component>,
     name: String,
 }
 
 impl IsEnv for StaticEnv {
-    fn set_value(&mut self, port: &String, val: VALUE) {
-        // println!("Set {} {:?}", port, val);
-        match port.split_once(".") {
+    fn set_value(&mut self, port: Name, val: VALUE) {
+        tracing::trace!(port = %port, value = ?val, "set");
+        match port.split() {
+            Some((a, b)) if sim().globals.slot(a).is_none() => {
+                match self.sub_env.get_mut(a).unwrap() {
+                    Component::Primitive(env) => env.set_value(b, val),
+                    Component::Module(instance_ref) => {
+                        let mut instance = instance_ref.borrow_mut();
+                        let env = instance.get_env_mut();
+                        env.set_value(b, val)
+                    }
+                }
+            }
             Some((a, b)) => {
-                if let Some(mem) = unsafe { &mut *MEMORY }.get_mut(&a.to_string()) {
-                    mem.set_port(&b.to_string(), val);
-                } else if let Some(fifo) = unsafe { &mut *FIFO }.get_mut(&a.to_string()) {
-                    fifo.set_port(&b.to_string(), val);
-                } else {
-                    let component = self.sub_env.get_mut(&a.to_string()).unwrap();
-                    match component {
-                        Component::Primitive(env) => env.set_value(&b.to_string(), val),
-                        Component::Module(instance_ref) => {
-                            let mut instance = instance_ref.borrow_mut();
-                            let env = instance.get_env_mut();
-                            env.set_value(&b.to_string(), val)
+                let mut guard = sim();
+                let sim = &mut *guard;
+                if let Some(mem) = sim.memory.get_mut(&*a) {
+                    mem.set_port(&b, val);
+                } else if let Some(fifo) = sim.fifo.get_mut(&*a) {
+                    if val == VALUE::Bool(true) {
+                        let access = sim.fifo_access.entry(a.to_string()).or_default();
+                        let op = format!("{} in {}", &sim.enabling, self.name);
+                        match b.as_str() {
+                            "r_en" => access.0 = op,
+                            "w_en" => access.1 = op,
+                            _ => (),
                         }
                     }
+                    fifo.set_port(&b, val);
                 }
             }
             None => {
-                self.env.insert(port.to_owned(), val);
+                self.env.insert(port, val);
             }
         }
     }
 
-    fn get_value(&self, port: &String) -> VALUE {
-        if let Some(val) = unsafe { &*CONSTANT }.get(port) {
-            val.clone()
-        } else {
-            self.get_value_inner(port)
-        }
+    fn get_value(&self, port: Name) -> VALUE {
+        self.get_value_inner(port)
     }
 
-    fn get_all_value(&self, ops: &Vec<String>) -> Vec<VALUE> {
-        ops.iter().map(|x| self.get_value(x)).collect()
+    fn instance(&self) -> String {
+        self.name.clone()
     }
 }
 
-fn create_function_same_type(op_type: &str) -> fn(&Vec<VALUE>) -> VALUE {
+fn create_function_same_type(op_type: &str) -> fn(&[VALUE]) -> VALUE {
     match op_type {
         "add_integer" | "add_float" => move |v| add(&v.first().unwrap().get_type(), v),
         "sub_integer" | "sub_float" => move |v| sub(&v.first().unwrap().get_type(), v),
@@ -913,7 +1090,8 @@ fn create_function_same_type(op_type: &str) -> fn(&Vec<VALUE>) -> VALUE {
         "cmp_integer_sle" => cmp_sle,
         "cmp_integer_ne" => cmp_ne,
         "cmp_float_ugt" => cmp_gt,
-        "and" => move |v: &Vec<VALUE>| and(&v.first().unwrap().get_type(), v),
+        "and" => move |v: &[VALUE]| and(&v.first().unwrap().get_type(), v),
+        "xor" => move |v: &[VALUE]| xor(&v.first().unwrap().get_type(), v),
         "div_float" => move |v| div(&v.first().unwrap().get_type(), v),
         str => panic!("Undefined module: {}", str),
     }
@@ -942,7 +1120,10 @@ fn create_handshake(unit: &Unit) -> Box<dyn IsHandshake> {
             create_function_same_type(&unit.op_type),
             2,
         )),
-        "trunc_integer" => Box::new(Trunc::new()),
+        "trunc_integer" => Box::new(Trunc::set(unit.types.last().unwrap())),
+        "ctpop" | "ctlz" | "extract" | "insert" | "concat" => {
+            Box::new(BitUnit::set(&unit.op_type, unit.types.last().unwrap()))
+        }
         "select" => Box::new(Select::set()),
         str => {
             if str.starts_with("fork") {
@@ -987,11 +1168,20 @@ impl HandshakeEnv {
             env.insert(unit.name.clone(), new_module);
         }
         let mut assign_map = MultiMap::new();
+        let mut inputs = MultiMap::new();
+        let mut outputs = MultiMap::new();
         if let Strategy::Handshake(handshake) = &module.strategy {
             for assign in &handshake.assign {
-                if !assign.src.contains(".") {
+                match (assign.src.name().contains('.'), assign.dst.contains('.')) {
+                    (false, true) if sim().constant.get(*assign.src.name()).is_none() => {
+                        inputs.insert(assign.src.to_string(), assign.dst.to_string())
+                    }
+                    (true, false) => outputs.insert(assign.dst.to_string(), assign.src.to_string()),
+                    _ => (),
+                }
+                if !assign.src.name().contains(".") {
                     // println!("{}", assign.src);
-                    if let Some(val) = unsafe { &mut *CONSTANT }.get(&assign.src) {
+                    if let Some(val) = sim().constant.get(*assign.src.name()) {
                         if let Some((unit_name, port)) = assign.dst.split_once(".") {
                             let unit = env.get_mut(unit_name).unwrap();
                             let constant = unit.downcast_mut::<ConstantElastic>().unwrap();
@@ -1000,15 +1190,23 @@ impl HandshakeEnv {
                         } else {
                         }
                     } else {
-                        assign_map.insert(assign.src.clone(), assign.dst.clone());
-                        assign_map.insert(assign.dst.clone(), assign.src.clone());
+                        assign_map.insert(assign.src.to_string(), assign.dst.to_string());
+                        assign_map.insert(assign.dst.to_string(), assign.src.to_string());
                     }
                 } else {
-                    assign_map.insert(assign.src.clone(), assign.dst.clone());
-                    assign_map.insert(assign.dst.clone(), assign.src.clone());
+                    assign_map.insert(assign.src.to_string(), assign.dst.to_string());
+                    assign_map.insert(assign.dst.to_string(), assign.src.to_string());
                 }
             }
             let mut env = HandshakeEnv::create_env(env, assign_map);
+            env.levelize(
+                handshake
+                    .assign
+                    .iter()
+                    .map(|assign| (assign.src.name().as_str(), assign.dst.as_str())),
+            );
+            env.inputs = inputs;
+            env.outputs = outputs;
             for sink in &handshake.sinks {
                 if !env.assign.contains_key(sink) {
                     env.set_port_ready(sink, true);
@@ -1028,9 +1226,12 @@ impl HandshakeEnv {
 impl StaticEnv {
     fn new(module: &Module) -> Self {
         let mut dsp = 0;
-        let mut sub_env = HashMap::new();
+        let mut sub_env = Slots::new(module.components.clone());
         for unit in &module.units {
-            sub_env.insert(unit.name.clone(), Component::Primitive(UnitEnv::new(unit)));
+            sub_env.insert(
+                Name::new(&unit.name),
+                Component::Primitive(UnitEnv::new(unit)),
+            );
             match &unit.op_type as &str {
                 "mul_integer" => dsp = dsp + 2,
                 "add_float" | "sub_float" => dsp = dsp + 3,
@@ -1040,8 +1241,8 @@ impl StaticEnv {
         }
         // println!("DSP: {}", dsp);
         for instance in &module.instances {
-            let sub_module = unsafe { &(*MODULE) }.get(&instance.module_name).unwrap();
-            let module_env = StaticEnv::new(sub_module);
+            let sub_module = sim().module[&instance.module_name].clone();
+            let module_env = StaticEnv::new(&sub_module);
 
             match &sub_module.strategy {
                 Strategy::STG(stg) => {
@@ -1053,8 +1254,8 @@ impl StaticEnv {
                     };
                     let new_instance = Instance::Static(new_module, None);
                     sub_env.insert(
-                        instance.name.to_owned(),
-                        Component::Module(Arc::new(RefCell::new(new_instance))),
+                        Name::new(&instance.name),
+                        Component::Module(Rc::new(RefCell::new(new_instance))),
                     );
                 }
                 Strategy::PipelineFor(pipeline) => {
@@ -1066,40 +1267,40 @@ impl StaticEnv {
                     };
                     let new_instance = Instance::PipeFor(new_module, None);
                     sub_env.insert(
-                        instance.name.to_owned(),
-                        Component::Module(Arc::new(RefCell::new(new_instance))),
+                        Name::new(&instance.name),
+                        Component::Module(Rc::new(RefCell::new(new_instance))),
                     );
                 }
                 _ => (),
             }
         }
         StaticEnv {
-            env: HashMap::new(),
+            env: Slots::default(),
             sub_env,
             name: module.name.clone(),
         }
     }
 
-    fn get_value_inner(&self, port: &String) -> VALUE {
+    fn get_value_inner(&self, port: Name) -> VALUE {
         // println!("Get value: {:?}", port);
-        match port.split_once(".") {
+        match port.split() {
+            Some((a, b)) if sim().globals.slot(a).is_none() => match self.sub_env.get(a).unwrap() {
+                Component::Primitive(env) => env.get_value(b),
+                Component::Module(instance_ref) => {
+                    let mut instance = instance_ref.borrow_mut();
+                    let env = instance.get_env_mut();
+                    env.get_value_inner(b)
+                }
+            },
             Some((a, b)) => {
-                if let Some(mem) = unsafe { &mut *MEMORY }.get_mut(&a.to_string()) {
+                let mut sim = sim();
+                if let Some(mem) = sim.memory.get_mut(&*a) {
                     // assert_eq!(b, "r_data");
-                    mem.get_value(&b.to_string())
-                } else if let Some(fifo) = unsafe { &mut *FIFO }.get_mut(&a.to_string()) {
+                    mem.get_value(&b)
+                } else {
+                    let fifo = sim.fifo.get_mut(&*a).unwrap();
                     assert_eq!(b, "r_data");
                     fifo.get_value()
-                } else {
-                    let component = self.sub_env.get(&a.to_string()).unwrap();
-                    match component {
-                        Component::Primitive(env) => env.get_value(&b.to_string()),
-                        Component::Module(instance_ref) => {
-                            let mut instance = instance_ref.borrow_mut();
-                            let env = instance.get_env_mut();
-                            env.get_value_inner(&b.to_string())
-                        }
-                    }
                 }
             }
             None => match self.env.get(port) {
@@ -1109,35 +1310,72 @@ impl StaticEnv {
         }
     }
 
+    fn lookup(&self, var: &str) -> Option<VALUE> {
+        match var.split_once(".") {
+            Some((a, b)) => match Name::find(a).and_then(|a| self.sub_env.get(a)) {
+                Some(Component::Primitive(env)) => env.try_get_value(b),
+                Some(Component::Module(instance_ref)) => instance_ref.borrow().get_env().lookup(b),
+                None => None,
+            },
+            None => Name::find(var).and_then(|var| self.env.get(var)).copied(),
+        }
+    }
+
     fn update(&mut self, keep: bool) {
         if keep {
-            let i = self.env.get("i").copied();
+            let i = self.env.get(*ITER).copied();
             if let Some(i) = i {
                 self.env.clear();
-                self.env.insert("i".to_string(), i.to_owned());
+                self.env.insert(*ITER, i.to_owned());
             }
         } else {
             self.env.clear();
         }
-        for item in &mut self.sub_env {
-            item.1.update();
+        for component in self.sub_env.values_mut() {
+            component.update();
         }
     }
 
-    fn print(&self, depth: usize, vars: &[&str]) {
-        for var in &self.env {
-            println!("{}{} {:?}", " ".repeat(depth), var.0, var.1);
+    fn print(&self, depth: usize, filter: &NameFilter) {
+        let mut rows = self
+            .env
+            .iter()
+            .filter(|(var, _)| filter.matches(var))
+            .map(|(var, value)| (var.to_string(), format!("{:?}", value)))
+            .collect::<Vec<_>>();
+        rows.sort();
+        print_table(&" ".repeat(depth), &rows);
+        for (name, component) in self.sub_env.iter() {
+            if filter.matches(name) {
+                println!("{}{}:", " ".repeat(depth), name);
+                component.print(depth + 1)
+            }
         }
-        if vars.len() == 0 {
-            for submodule in &self.sub_env {
-                println!("{}{}:", " ".repeat(depth), submodule.0);
-                submodule.1.print(depth + 1)
+    }
+
+    fn to_json(&self, filter: &NameFilter) -> Value {
+        let mut units = serde_json::Map::new();
+        for (name, component) in self.sub_env.iter() {
+            if filter.matches(name) {
+                units.insert(name.to_string(), component.to_json());
             }
-        } else {
-            for var in vars {
-                if let Some(submodule) = self.sub_env.get(*var) {
-                    println!("{}{}:", " ".repeat(depth), var);
-                    submodule.print(depth + 1)
+        }
+        json!({
+            "name": self.name,
+            "values": variables_to_json(self.env.iter(), filter),
+            "units": units,
+        })
+    }
+
+    fn snapshot(&self, values: &mut BTreeMap<String, String>) {
+        for (var, value) in self.env.iter() {
+            values.insert(format!("{} in {}", var, self.name), format!("{:?}", value));
+        }
+        for (unit_name, component) in self.sub_env.iter() {
+            if let Component::Primitive(unit) = component {
+                for (port, value) in unit.ports() {
+                    let name = format!("{}.{} in {}", unit_name, port, self.name);
+                    values.insert(name, format!("{:?}", value));
                 }
             }
         }
@@ -1145,29 +1383,30 @@ impl StaticEnv {
 
     fn clear(&mut self) {
         self.env.clear();
-        self.set_value(&"done".to_string(), VALUE::Bool(false));
+        self.set_value(*DONE, VALUE::Bool(false));
 
-        for item in &mut self.sub_env {
-            item.1.clear();
+        for component in self.sub_env.values_mut() {
+            component.clear();
         }
     }
 }
 
 pub fn call_function(function_name: &str, args: &[&str]) -> Result<(), String> {
-    // println!("Call {} with arguments: {:?}", function_name, args);
-    let function = unsafe { (*MODULE).get(&String::from(function_name)) };
+    tracing::debug!(function = function_name, ?args, "call");
+    let function = sim().module.get(function_name).cloned();
     match function {
         None => Err(String::from("!!Undefined function")),
         Some(module) => {
+            if sim().active.is_empty() {
+                sim().state_cycles.clear();
+            }
             if let Strategy::Handshake(_) = &module.strategy {
-                unsafe {
-                    TOP_DYNAMIC = Some(HandshakeEnv::new(module));
-                }
+                sim().top_dynamic = Some(HandshakeEnv::new(&module));
                 return Ok(());
             }
-            let mut env = StaticEnv::new(module);
+            let mut env = StaticEnv::new(&module);
             for ((arg, arg_type), value) in zip(zip(&module.args, &module.types), args) {
-                env.set_value(arg, build_value(arg_type, &String::from(value.to_owned())))
+                env.set_value(*arg, build_value(arg_type, &String::from(value.to_owned())))
             }
 
             match &module.strategy {
@@ -1179,7 +1418,7 @@ pub fn call_function(function_name: &str, args: &[&str]) -> Result<(), String> {
                         stall: false,
                     };
                     let instance = Instance::Static(new_module, None);
-                    unsafe { &mut (*ACTIVE) }.push(Arc::new(RefCell::new(instance)));
+                    sim().active.push(Rc::new(RefCell::new(instance)));
                 }
                 Strategy::PipelineFor(pipefor) => {
                     let mut pipeline = pipefor.clone();
@@ -1191,7 +1430,7 @@ pub fn call_function(function_name: &str, args: &[&str]) -> Result<(), String> {
                         stall: false,
                     };
                     let instance = Instance::PipeFor(new_module, None);
-                    unsafe { &mut (*ACTIVE) }.push(Arc::new(RefCell::new(instance)));
+                    sim().active.push(Rc::new(RefCell::new(instance)));
                 }
                 _ => (),
             }
@@ -1201,36 +1440,100 @@ pub fn call_function(function_name: &str, args: &[&str]) -> Result<(), String> {
 }
 
 pub fn step(depth: usize, breakpoint: bool) -> bool {
-    if let Some(handshake) = unsafe { &mut TOP_DYNAMIC } {
-        for cycle in 0..depth {
-            handshake.propagate();
-            handshake.update();
-            if cycle < depth - 1 {
-                let mut flag = false;
-                for unit in handshake.env.values() {
-                    if unit.is_valid() {
-                        flag = true;
-                        break;
+    let mut progress = Progress::new();
+    let checks = step_checks(breakpoint);
+    if sim().top_dynamic.is_some() {
+        let mut cycle = 0;
+        while cycle < depth {
+            if interrupted() {
+                println!("!!Interrupted with cycle count: {}", cycle);
+                return false;
+            }
+            auto_checkpoint(super::common::State::HEC);
+            let _cycle =
+                tracing::trace_span!("cycle", cycle = cycle_count(super::common::State::HEC))
+                    .entered();
+            with_top_dynamic(|handshake| {
+                progress.report(cycle, handshake.update_list.len());
+                handshake.propagate();
+            });
+            if perfetto_enabled() {
+                sample_perfetto();
+            }
+            let matched = super::rtl::exchange();
+            // Wait out latencies at once, e.g. of a `BinaryUnitSeq`
+            if checks.fast_forward && cycle + 1 < depth {
+                let skip = with_top_dynamic(|handshake| {
+                    let skip = handshake.quiet().min(depth - cycle - 1);
+                    let waiting = handshake.env.values().any(|unit| unit.is_valid());
+                    if skip > 0 && waiting {
+                        handshake.skip(skip);
+                        return skip;
                     }
+                    0
+                });
+                if let Some(skip @ 1..) = skip {
+                    advance_cycles(super::common::State::HEC, skip);
+                    progress.skip(skip);
+                    cycle += skip;
                 }
-                if !flag {
-                    println!("Cycle count: {}", cycle);
+            }
+            with_top_dynamic(HandshakeEnv::update);
+            advance_cycle(super::common::State::HEC);
+            if vcd_enabled() {
+                sample_vcd();
+            }
+            if checks.watches {
+                log_watch_csv(super::common::State::HEC);
+            }
+            if !matched {
+                println!("RTL mismatch with cycle count: {}", cycle);
+                return false;
+            }
+            if checks.watches && display_enabled() {
+                display_changes(snapshot());
+            }
+            if cycle < depth - 1 {
+                let flag = with_top_dynamic(|handshake| {
+                    handshake.env.values().any(|unit| unit.is_valid())
+                });
+                if flag != Some(true) {
+                    if verbose(Verbosity::Normal) {
+                        println!("Cycle count: {}", cycle);
+                    }
                     return false;
                 }
             }
+            cycle += 1;
         }
         return true;
     }
+    // The state after the last cycle, if every instance stalled in it
+    let mut stalled_state = None;
+    let watchpoint = get_watchpoint();
+    let watched: Vec<&str> = watchpoint.iter().map(|s| s.as_str()).collect();
+    let filter = NameFilter::new(&watched).unwrap_or_else(NameFilter::all);
     for cycle in 0..depth {
-        if unsafe { &(*ACTIVE) }.is_empty() {
+        if interrupted() {
+            println!("!!Interrupted with cycle count: {}", cycle);
+            return false;
+        }
+        auto_checkpoint(super::common::State::HEC);
+        let _cycle =
+            tracing::trace_span!("cycle", cycle = cycle_count(super::common::State::HEC)).entered();
+        progress.report(cycle, sim().active.len());
+        if sim().active.is_empty() {
             if breakpoint {
-                println!("Cycle count: {}", cycle);
+                if verbose(Verbosity::Normal) {
+                    println!("Cycle count: {}", cycle);
+                }
             }
             return false;
         }
 
-        if breakpoint {
-            for instance_ref in unsafe { &mut (*ACTIVE) } {
+        let active = sim().active.clone();
+        if checks.breakpoints {
+            for instance_ref in &active {
                 let instance = instance_ref.borrow();
                 if instance.mark() {
                     println!("Break with cycle count: {}", cycle);
@@ -1238,116 +1541,500 @@ pub fn step(depth: usize, breakpoint: bool) -> bool {
                 }
             }
         }
+        trace_ops(|| {
+            for instance_ref in &active {
+                instance_ref.borrow().mark();
+            }
+        });
+        advance_cycle(super::common::State::HEC);
 
         let mut instances = vec![];
         let mut clear_instances = vec![];
 
-        // unsafe {
-        //     println!("Old Active");
-        //     for active in &mut *ACTIVE {
-        //         print!("{:?}", active.borrow_mut().get_env_mut().name);
-        //     }
-        //     println!("");
-        // }
-
-        let old_active: Vec<_> = unsafe { &mut (*ACTIVE) }.drain(..).collect();
+        let old_active: Vec<_> = sim().active.drain(..).collect();
         old_active.iter().for_each(|instance| {
-            // println!("Pop {:?}", instance.borrow().get_env().name);
+            let _instance =
+                tracing::trace_span!("instance", module = %instance.borrow().get_env().name)
+                    .entered();
+            let timer = profile::timer();
             let step = instance.borrow_mut().step();
+            profile::charge(timer, "module", &instance.borrow().get_env().name);
             if step {
-                // println!("Push {:?}", instance.borrow().get_env().name);
+                tracing::trace!("active");
                 instances.push(instance.clone());
-                unsafe { &mut (*ACTIVE) }.push(instance.to_owned());
+                sim().active.push(instance.to_owned());
             } else {
-                // println!("Clear {:?}", instance.borrow().get_env().name);
+                tracing::trace!("done");
                 clear_instances.push(instance.clone());
             }
         });
 
-        // unsafe {
-        //     println!("New Active");
-        //     for active in &mut *ACTIVE {
-        //         print!("{:?}", active.borrow_mut().get_env_mut().name);
-        //     }
-        //     println!("");
-        // }
-
         instances.drain(..).for_each(|instance| {
+            let timer = profile::timer();
             instance.borrow_mut().update();
+            profile::charge(timer, "update", &instance.borrow().get_env().name);
         });
 
         clear_instances.drain(..).for_each(|instance| {
             instance.borrow_mut().get_env_mut().clear();
         });
 
-        // unsafe {
-        //     println!("New");
-        //     for new in &mut *NEW_INSTANCE {
-        //         print!("{:?}", new.borrow_mut().get_env_mut().name);
-        //     }
-        //     println!("");
-        // }
-
-        unsafe { &mut (*NEW_INSTANCE) }
-            .drain(..)
-            .for_each(|instance| {
-                instance.borrow_mut().update();
-            });
+        let new_instances: Vec<_> = sim().new_instance.drain(..).collect();
+        new_instances.into_iter().for_each(|instance| {
+            tracing::trace!(module = %instance.borrow().get_env().name, "start");
+            instance.borrow_mut().update();
+        });
 
         // show_memory(&[]);
 
-        unsafe { &mut *MEMORY }
+        sim()
+            .memory
             .iter_mut()
-            .for_each(|mem| mem.1.update());
-
-        unsafe { &mut *FIFO }
-            .iter_mut()
-            .for_each(|fifo: (&String, &mut FIFO)| fifo.1.update());
+            .for_each(|(name, mem)| match mem.update() {
+                Ok(()) => (),
+                Err(VALUE::X) => fault(unknown_index(name)),
+                Err(address) => fault(HestiaError::new(
+                    ErrorCode::OutOfBounds,
+                    format!(
+                        "store to {} out of bounds of memory '{}' of size {}",
+                        address.as_u64(),
+                        name,
+                        mem.dump().len()
+                    ),
+                )),
+            });
 
-        // show_memory(&[]);
-        if unsafe { !(*get_watchpoint()).is_empty() } {
-            let vars: Vec<&str> = unsafe { &*get_watchpoint() }
-                .iter()
-                .map(|s| s.as_str())
-                .collect();
-            for instance in unsafe { &(*ACTIVE) }.iter() {
-                match &*(instance.borrow()) {
-                    Instance::Static(instance, _) => instance.env.print(0, &vars[..]),
-                    Instance::PipeFor(instance, _) => instance.env.print(0, &vars[..]),
+        {
+            let mut guard = sim();
+            let sim = &mut *guard;
+            sim.fifo.iter_mut().for_each(|fifo: (&String, &mut FIFO)| {
+                let access = sim.fifo_access.get(fifo.0).cloned().unwrap_or_default();
+                if let Some(error) = fifo.1.check() {
+                    let op = if error == "underflow" {
+                        &access.0
+                    } else {
+                        &access.1
+                    };
+                    report_fifo_error(fifo.0, error, op, fifo.1.len(), fifo.1.depth());
                 }
-            }
+                let (popped, pushed) = fifo.1.update();
+                if have_stream_watch() {
+                    if let Some(value) = popped {
+                        record_stream(fifo.0, "pop", value, &access.0);
+                    }
+                    if let Some(value) = pushed {
+                        record_stream(fifo.0, "push", value, &access.1);
+                    }
+                }
+            });
+        }
+
+        if vcd_enabled() {
+            sample_vcd();
+        }
+        if perfetto_enabled() {
+            sample_perfetto();
         }
+        if !is_replaying() {
+            count_state_cycles(1);
+        }
+
+        // show_memory(&[]);
+        let memory_hit = checks.watches && watch_cycle(&watched, &filter);
 
         if have_equalpoint() {
-            for instance in unsafe { &(*ACTIVE) }.iter() {
+            let active = sim().active.clone();
+            for instance in active {
                 match &*(instance.borrow()) {
                     Instance::Static(instance, _) => {
-                        if let Some(ops) = unsafe { &*EQUALPOINT }.get(&instance.stg.state) {
-                            for op in ops {
-                                unsafe { &mut *EQUALVALUE }.insert(
-                                    (instance.stg.state.clone(), op.clone()),
-                                    instance.env.get_value_inner(&(op.clone() + ".result")),
-                                );
-                            }
+                        let results = sim().equal_point.get(&instance.stg.state).cloned();
+                        for (result, index) in results.into_iter().flatten() {
+                            let value = instance.env.get_value_inner(result);
+                            sim().equal_value.insert(index, value);
                         }
                     }
                     Instance::PipeFor(instance, _) => (),
                 }
             }
         }
-    }
-    if breakpoint {
-        println!("Cycle count (finish): {}", depth);
-    }
-    true
-}
-
-pub fn load_memory(memory: &str, store: &[&str]) {
-    if let Some(memory) = unsafe { (*MEMORY).get_mut(memory) } {
-        memory.set(store)
-    } else {
-        if let Some(handshake) = unsafe { &mut TOP_DYNAMIC } {
-            let unit = handshake.env.get_mut(memory).unwrap();
+        if let Some(error) = take_fault() {
+            println!("!!{} with cycle count: {}", error, cycle + 1);
+            fail(Failure::Command);
+            return false;
+        }
+        if have_halt_watchpoint() {
+            let mut hit = false;
+            let active = sim().active.clone();
+            for instance in active {
+                let instance = instance.borrow();
+                let env = instance.get_env();
+                hit |= test_watch_halt(&env.name, &|var| env.lookup(var));
+            }
+            if hit {
+                println!("Watch with cycle count: {}", cycle + 1);
+                return false;
+            }
+        }
+        if memory_hit {
+            println!("Watch with cycle count: {}", cycle + 1);
+            return false;
+        }
+        if take_stream_hit() {
+            println!("Stream watch with cycle count: {}", cycle + 1);
+            return false;
+        }
+        let stalled = checks.fast_forward && all_stalled(&active_modules());
+        if test_stall() {
+            println!("Stall with cycle count: {}", cycle + 1);
+            return false;
+        }
+        if take_fifo_error() {
+            println!("FIFO error with cycle count: {}", cycle + 1);
+            return false;
+        }
+        if !stalled {
+            stalled_state = None;
+            continue;
+        }
+        // Every instance waits for a FIFO and nothing changed, so nothing will
+        let state = checkpoint().to_json();
+        if stalled_state.as_ref() == Some(&state) {
+            let skip = depth - cycle - 1;
+            advance_cycles(super::common::State::HEC, skip);
+            if !is_replaying() {
+                count_state_cycles(skip);
+            }
+            progress.skip(skip);
+            break;
+        }
+        stalled_state = Some(state);
+    }
+    if breakpoint {
+        if verbose(Verbosity::Normal) {
+            println!("Cycle count (finish): {}", depth);
+        }
+    }
+    true
+}
+
+/// Print the watchpoints of the active instances and the displays after a
+/// cycle, true if a watched memory stops the step. The watchpoints and their
+/// filter are worked out once per step.
+fn watch_cycle(watched: &[&str], filter: &NameFilter) -> bool {
+    log_watch_csv(super::common::State::HEC);
+    let memory_hit = watch_memory(super::common::State::HEC);
+    if !watched.is_empty() {
+        let active = sim().active.clone();
+        for instance in active {
+            let instance = instance.borrow();
+            let env = instance.get_env();
+            if watch_table_enabled() {
+                for var in watched {
+                    if let Some(value) = env.lookup(var) {
+                        record_watch(format!("{} in {}", var, env.name), value);
+                    }
+                }
+            } else {
+                env.print(0, filter);
+            }
+        }
+        if watch_table_enabled() {
+            draw_watch_table();
+        }
+    }
+    if display_enabled() {
+        display_changes(snapshot());
+    }
+    memory_hit
+}
+
+fn add_state_cycles(module: &String, state: &String, cycles: usize) {
+    let counts = &mut sim().state_cycles;
+    if !counts.contains_key(module) {
+        counts.insert(module.clone(), HashMap::new());
+    }
+    let states = counts.get_mut(module).unwrap();
+    match states.get_mut(state) {
+        Some(count) => *count += cycles,
+        None => {
+            states.insert(state.clone(), cycles);
+        }
+    }
+}
+
+/// The modules of the active instances.
+fn active_modules() -> Vec<String> {
+    sim()
+        .active
+        .iter()
+        .map(|instance| instance.borrow().get_env().name.clone())
+        .collect()
+}
+
+fn count_state_cycles(cycles: usize) {
+    let active = sim().active.clone();
+    for instance in active {
+        match &*(instance.borrow()) {
+            Instance::Static(instance, _) => {
+                add_state_cycles(&instance.env.name, &instance.stg.state, cycles)
+            }
+            Instance::PipeFor(instance, _) => {
+                let pipeline = &instance.pipeline;
+                for cur in &pipeline.cur_stages {
+                    if let Some(stage) = pipeline.stages.get(*cur) {
+                        add_state_cycles(&instance.env.name, &stage.name, cycles);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The state transition graph of an STG module, or the stages of a pipelined
+/// loop, as DOT. With `counts`, states and stages show the cycles the last
+/// run spent in them and unvisited ones are grey.
+pub fn dot(module_name: &str, counts: bool) -> Result<String, String> {
+    let Some(module) = sim().module.get(module_name).cloned() else {
+        return Err(format!("No module {}", module_name));
+    };
+    let cycles = sim().state_cycles.get(module_name).cloned();
+    let node = |name: &String, shape: &str| {
+        let mut label = name.clone();
+        let mut attributes = vec![format!("shape={}", shape)];
+        if counts {
+            let count = cycles
+                .as_ref()
+                .and_then(|cycles| cycles.get(name))
+                .map_or(0, |count| *count);
+            label.push_str(&format!("\n{} cycles", count));
+            if count == 0 {
+                attributes.push("color=grey fontcolor=grey".to_string());
+            }
+        }
+        attributes.insert(0, format!("label={}", dot_quote(&label)));
+        format!("    {} [{}];\n", dot_quote(name), attributes.join(" "))
+    };
+    let edge = |from: &String, to: &String, label: &str| {
+        format!(
+            "    {} -> {} [label={}];\n",
+            dot_quote(from),
+            dot_quote(to),
+            dot_quote(label)
+        )
+    };
+    let mut dot = format!("digraph {} {{\n", dot_quote(module_name));
+    match &module.strategy {
+        Strategy::STG(stg) => {
+            let mut states: Vec<(&String, &State)> = stg.stg.iter().collect();
+            states.sort_by_key(|(name, _)| *name);
+            dot.push_str("    __init [shape=point];\n");
+            dot.push_str(&format!("    __init -> {};\n", dot_quote(&stg.init_state)));
+            for (name, state) in &states {
+                let shape = match state.done {
+                    Some(_) => "doublecircle",
+                    None => "circle",
+                };
+                dot.push_str(&node(name, shape));
+            }
+            for (name, state) in &states {
+                for (dest, cond) in &state.transition {
+                    dot.push_str(&edge(name, dest, cond.name()));
+                }
+                if let Some(dest) = &state.default {
+                    dot.push_str(&edge(name, dest, "default"));
+                }
+            }
+        }
+        Strategy::PipelineFor(pipeline) => {
+            dot.push_str("    rankdir=LR;\n");
+            for stage in &pipeline.stages {
+                dot.push_str(&node(&stage.name, "box"));
+            }
+            for pair in pipeline.stages.windows(2) {
+                dot.push_str(&edge(&pair[0].name, &pair[1].name, ""));
+            }
+            if let Some(first) = pipeline.stages.first() {
+                let ii = format!("II = {}", pipeline.ii);
+                dot.push_str(&format!(
+                    "    {} -> {} [style=dashed label={}];\n",
+                    dot_quote(&first.name),
+                    dot_quote(&first.name),
+                    dot_quote(&ii)
+                ));
+            }
+        }
+        _ => {
+            return Err(format!(
+                "{} is neither an STG nor a pipelined loop",
+                module_name
+            ))
+        }
+    }
+    dot.push_str("}\n");
+    Ok(dot)
+}
+
+/// Mark in the trace each handshake unit holding a token as firing or stalled,
+/// or each active instance busy in its state and each pipeline stage in flight
+/// busy with its name.
+fn sample_perfetto() {
+    if let Some(handshake) = &sim().top_dynamic {
+        for (unit_name, unit) in handshake.env.iter() {
+            let ports = unit.ports();
+            let label = if ports.iter().any(|(_, value)| value.valid && value.ready) {
+                "fire"
+            } else if ports.iter().any(|(_, value)| value.valid) {
+                "stall"
+            } else {
+                continue;
+            };
+            activity("handshake", unit_name.clone(), label.to_string());
+        }
+    }
+    let active = sim().active.clone();
+    for instance in &active {
+        match &*(instance.borrow()) {
+            Instance::Static(instance, _) => {
+                let label = match instance.stall {
+                    true => "stall".to_string(),
+                    false => instance.stg.state.clone(),
+                };
+                activity("hec", instance.env.name.clone(), label);
+            }
+            Instance::PipeFor(instance, _) => {
+                let pipeline = &instance.pipeline;
+                for cur in &pipeline.cur_stages {
+                    let Some(stage) = pipeline.stages.get(*cur) else {
+                        continue;
+                    };
+                    let label = match instance.stall {
+                        true => "stall".to_string(),
+                        false => stage.name.clone(),
+                    };
+                    activity("hec", format!("{} stage {}", instance.env.name, cur), label);
+                }
+            }
+        }
+    }
+    super::perfetto::end_cycles(1);
+}
+
+/// Sample the valid, ready and data of every handshake port, or the state and
+/// registers of the active instances, into the waveform.
+fn sample_vcd() {
+    if let Some(handshake) = &sim().top_dynamic {
+        for (unit_name, unit) in handshake.env.iter() {
+            for (port, value) in unit.ports() {
+                let name = format!("{}.{}", unit_name, port);
+                sample(
+                    format!("{}.valid", name),
+                    Sample::Value(VALUE::Bool(value.valid)),
+                );
+                sample(
+                    format!("{}.ready", name),
+                    Sample::Value(VALUE::Bool(value.ready)),
+                );
+                sample(format!("{}.data", name), Sample::Value(value.data));
+            }
+        }
+    }
+    let active = sim().active.clone();
+    for instance in &active {
+        let instance = instance.borrow();
+        let env = instance.get_env();
+        if let Instance::Static(instance, _) = &*instance {
+            sample(
+                format!("{}.state", env.name),
+                Sample::Text(instance.stg.state.clone()),
+            );
+        }
+        for (unit_name, component) in env.sub_env.iter() {
+            if let Component::Primitive(unit) = component {
+                if let Some(value) = unit.try_get_value("reg") {
+                    sample(format!("{}.{}", env.name, unit_name), Sample::Value(value));
+                }
+            }
+        }
+    }
+    super::vcd::end_cycles(1);
+}
+
+impl StaticEnv {
+    /// The wires of the instance followed by its registers, each sorted.
+    fn variables(&self) -> Vec<(String, VALUE)> {
+        let mut variables: Vec<(String, VALUE)> = self
+            .env
+            .iter()
+            .map(|(var, value)| (var.to_string(), *value))
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut registers = vec![];
+        for (unit_name, component) in self.sub_env.iter() {
+            if let Component::Primitive(unit) = component {
+                if let Some(value) = unit.try_get_value("reg") {
+                    registers.push((unit_name.to_string(), value));
+                }
+            }
+        }
+        registers.sort_by(|a, b| a.0.cmp(&b.0));
+        variables.extend(registers);
+        variables
+    }
+}
+
+/// The active instances with their state or pipeline stages, or a single
+/// frame for a handshake design.
+pub fn frames() -> Vec<Frame> {
+    if let Some(handshake) = &sim().top_dynamic {
+        let valid = handshake
+            .env
+            .values()
+            .filter(|unit| unit.is_valid())
+            .count();
+        return vec![Frame {
+            function: String::from("handshake"),
+            location: format!("{} valid units", valid),
+            op: None,
+            variables: vec![],
+        }];
+    }
+    let mut frames = vec![];
+    let active = sim().active.clone();
+    for instance_ref in &active {
+        frames.push(match &*instance_ref.borrow() {
+            Instance::Static(instance, _) => Frame {
+                function: instance.env.name.clone(),
+                location: format!("state {}", instance.stg.state),
+                op: Some(instance.stg.state.clone()),
+                variables: instance.env.variables(),
+            },
+            Instance::PipeFor(instance, _) => {
+                let pipeline = &instance.pipeline;
+                let stages: Vec<&str> = pipeline
+                    .cur_stages
+                    .iter()
+                    .map(|stage| pipeline.stages[*stage].name.as_str())
+                    .collect();
+                Frame {
+                    function: instance.env.name.clone(),
+                    location: format!("stages {}", stages.join(", ")),
+                    op: stages.first().map(|stage| stage.to_string()),
+                    variables: instance.env.variables(),
+                }
+            }
+        });
+    }
+    frames
+}
+
+pub fn load_memory(memory: &str, store: &[&str]) {
+    let mut guard = sim();
+    let sim = &mut *guard;
+    if let Some(memory) = sim.memory.get_mut(memory) {
+        memory.set(store)
+    } else {
+        if let Some(handshake) = &mut sim.top_dynamic {
+            let unit = handshake.env.get_mut(memory).unwrap();
             let memory = unit.downcast_mut::<DynMem>().unwrap();
             memory.set_mem(store);
         }
@@ -1355,86 +2042,177 @@ pub fn load_memory(memory: &str, store: &[&str]) {
 }
 
 pub fn ready(port: &str) {
-    if let Some(handshake) = unsafe { &mut TOP_DYNAMIC } {
+    if let Some(handshake) = &mut sim().top_dynamic {
         handshake.set_ready(&port.to_string(), true);
     }
+    record_input(&format!("ready {}", port));
+    rebase_history();
 }
 
 pub fn valid(port: &str, val: &str) {
-    if let Some(handshake) = unsafe { &mut TOP_DYNAMIC } {
+    if let Some(handshake) = &mut sim().top_dynamic {
         match val {
             "control" => handshake.set_valid(&port.to_string(), VALUE::Control, true),
             _ => panic!(),
         }
     }
+    record_input(&format!("valid {} {}", port, val));
+    rebase_history();
 }
 
 pub fn invalid(port: &str) {
-    if let Some(handshake) = unsafe { &mut TOP_DYNAMIC } {
+    if let Some(handshake) = &mut sim().top_dynamic {
         handshake.set_valid(&port.to_string(), VALUE::ERROR, false);
     }
+    record_input(&format!("invalid {}", port));
+    rebase_history();
 }
 
-pub fn show_memory(memorys: &[&str]) {
+pub fn show_memory(filter: &NameFilter) {
+    if json_output() {
+        let mut values = serde_json::Map::new();
+        for (mem, value) in sim().memory.iter() {
+            if filter.matches(mem) {
+                values.insert(mem.clone(), value.to_json());
+            }
+        }
+        for (fifo, value) in sim().fifo.iter() {
+            if filter.matches(fifo) {
+                values.insert(fifo.clone(), value.to_json());
+            }
+        }
+        return print_json(Value::Object(values));
+    }
     println!("!!SHOW MEMORY:");
-    if memorys.len() == 0 {
-        for (mem, value) in unsafe { &(*MEMORY) }.iter() {
-            println!("\t{} {:?}", mem, value)
+    let mut rows = vec![];
+    for (mem, value) in sim().memory.iter() {
+        if filter.matches(mem) {
+            rows.push((mem.clone(), format!("{:?}", value)));
         }
-        for (fifo, value) in unsafe { &(*FIFO) }.iter() {
-            println!("\t{} {:?}", fifo, value)
+    }
+    for (fifo, value) in sim().fifo.iter() {
+        if filter.matches(fifo) {
+            rows.push((fifo.clone(), format!("{:?}", value)));
         }
-    } else {
-        for mem in memorys {
-            if let Some(memory) = unsafe { &(*MEMORY) }.get(*mem) {
-                println!("\t{} {:?}", mem, memory);
-            }
-            if let Some(stream) = unsafe { &(*FIFO) }.get(*mem) {
-                println!("\t{} {:?}", mem, stream);
+    }
+    print_table("\t", &rows);
+}
+
+fn unit_json(unit: &dyn IsHandshake) -> Value {
+    let tokens = |values: &Vec<HandshakeValue>| -> Value {
+        Value::Array(values.iter().map(|value| value.to_json()).collect())
+    };
+    match unit.downcast_ref::<DynMem>() {
+        Some(memory) => json!({
+            "load_address": tokens(&memory.load_address),
+            "load_data": tokens(&memory.load_data),
+            "store_address": tokens(&memory.store_address),
+            "store_data": tokens(&memory.store_data),
+        }),
+        None => json!(format!("{:?}", unit)),
+    }
+}
+
+/// Values of the active instances, or the state of every handshake unit.
+pub fn snapshot() -> BTreeMap<String, String> {
+    let mut values = BTreeMap::new();
+    if let Some(handshake) = &sim().top_dynamic {
+        for (unit_name, unit) in handshake.env.iter() {
+            let value = match unit_json(unit.as_ref()) {
+                Value::String(state) => state,
+                state => state.to_string(),
+            };
+            values.insert(unit_name.clone(), value);
+        }
+        return values;
+    }
+    let active = sim().active.clone();
+    for instance in &active {
+        instance.borrow().get_env().snapshot(&mut values);
+    }
+    values
+}
+
+fn variables_json(filter: &NameFilter) -> Value {
+    if let Some(handshake) = &sim().top_dynamic {
+        let mut units = serde_json::Map::new();
+        for (unit_name, unit) in handshake.env.iter() {
+            if filter.matches(unit_name) {
+                units.insert(unit_name.clone(), unit_json(unit.as_ref()));
             }
         }
+        return Value::Object(units);
     }
+    let instances = sim().active.clone();
+    Value::Array(
+        instances
+            .iter()
+            .map(|instance| instance.borrow().get_env().to_json(filter))
+            .collect(),
+    )
 }
 
-pub fn show_variable(vars: &[&str]) {
+pub fn show_variable(filter: &NameFilter) {
+    if json_output() {
+        return print_json(variables_json(filter));
+    }
     println!("!!SHOW VALUE:");
-    if let Some(handshake) = unsafe { &mut TOP_DYNAMIC } {
-        if vars.len() == 0 {
-            for (unit_name, unit) in handshake.env.iter_mut() {
-                if let Some(memory) = unit.downcast_mut::<DynMem>() {
-                    println!("Load address: {:?}", memory.load_address);
-                    println!("Load data: {:?}", memory.load_data);
-                    println!("Store address: {:?}", memory.store_address);
-                    println!("Store data: {:?}", memory.store_data);
-                } else {
-                    println!("{} {:#?}", unit_name, unit);
-                }
+    let mut sim = sim();
+    if let Some(handshake) = &mut sim.top_dynamic {
+        for (unit_name, unit) in handshake.env.iter_mut() {
+            if !filter.matches(unit_name) {
+                continue;
             }
-        } else {
-            for unit_name in vars {
-                let unit = handshake.env.get_mut(&unit_name.to_string()).unwrap();
-                if let Some(memory) = unit.downcast_mut::<DynMem>() {
-                    println!("Load address: {:?}", memory.load_address);
-                    println!("Load data: {:?}", memory.load_data);
-                    println!("Store address: {:?}", memory.store_address);
-                    println!("Store data: {:?}", memory.store_data);
-                } else {
-                    println!("{} {:#?}", unit_name, unit);
-                }
+            if let Some(memory) = unit.downcast_mut::<DynMem>() {
+                println!("Load address: {:?}", memory.load_address);
+                println!("Load data: {:?}", memory.load_data);
+                println!("Store address: {:?}", memory.store_address);
+                println!("Store data: {:?}", memory.store_data);
+            } else {
+                println!("{} {:#?}", unit_name, unit);
             }
         }
     } else {
-        for instance in unsafe { &(*ACTIVE) }.iter() {
+        for instance in sim.active.iter() {
             match &*(instance.borrow()) {
-                Instance::Static(instance, _) => instance.env.print(0, vars),
-                Instance::PipeFor(instance, _) => instance.env.print(0, vars),
+                Instance::Static(instance, _) => instance.env.print(0, filter),
+                Instance::PipeFor(instance, _) => instance.env.print(0, filter),
             }
         }
     }
 }
 
+fn active_json() -> Value {
+    let handshake = sim().top_dynamic.as_ref().map(|handshake| {
+        let mut active = handshake
+            .env
+            .iter()
+            .filter(|(_, unit)| unit.is_valid())
+            .map(|(unit_name, _)| unit_name.clone())
+            .collect::<Vec<String>>();
+        active.sort();
+        active
+    });
+    let mut instances = vec![];
+    let active = sim().active.clone();
+    for instance_ref in &active {
+        let instance = instance_ref.borrow();
+        let name = &instance.get_env().name;
+        instances.push(match &*instance {
+            Instance::Static(stg, _) => json!({"name": name, "state": stg.stg.state}),
+            Instance::PipeFor(pipeline, _) => {
+                json!({"name": name, "stages": pipeline.pipeline.cur_stages})
+            }
+        });
+    }
+    json!({"handshake": handshake, "instances": instances})
+}
+
 pub fn show_active() {
-    if let Some(handshake) = unsafe { &mut TOP_DYNAMIC } {
+    if json_output() {
+        return print_json(active_json());
+    }
+    if let Some(handshake) = &mut sim().top_dynamic {
         let mut active = Vec::new();
         for (unit_name, unit) in &handshake.env {
             if unit.is_valid() {
@@ -1445,7 +2223,8 @@ pub fn show_active() {
         active.sort();
         println!("{:?}", active);
     }
-    for instance_ref in unsafe { &(*ACTIVE) }.iter() {
+    let active = sim().active.clone();
+    for instance_ref in &active {
         let instance = instance_ref.borrow();
         print!("{:?}: ", instance.get_env().name);
         match &*instance {
@@ -1460,13 +2239,571 @@ pub fn show_active() {
 }
 
 pub fn execute_continue() {
-    step(1000000000, true);
+    if step(options().max_cycles, true) {
+        println!("!!Stopped at max_cycles {}", options().max_cycles);
+    }
 }
 
 pub fn finish() -> bool {
-    unsafe { &(*ACTIVE) }.is_empty()
+    sim().active.is_empty()
+}
+
+/// The values reached at equal points since the last call.
+pub fn take_equal_values() -> Vec<(usize, VALUE)> {
+    sim().equal_value.drain().collect()
+}
+
+/// Run `f` on the handshake network of the dynamic top-level module, if one
+/// is loaded. `f` must not reach back into the HEC simulation.
+pub fn with_top_dynamic<R>(f: impl FnOnce(&mut HandshakeEnv) -> R) -> Option<R> {
+    sim().top_dynamic.as_mut().map(f)
+}
+
+pub fn dump_memory(memory: &str) -> Option<Vec<VALUE>> {
+    if let Some(mem) = sim().memory.get(memory) {
+        return Some(mem.dump());
+    }
+    if let Some(handshake) = &mut sim().top_dynamic {
+        if let Some(unit) = handshake.env.get_mut(memory) {
+            return unit.downcast_mut::<DynMem>().map(|mem| mem.dump_mem());
+        }
+    }
+    None
+}
+
+/// The words of a memory, of the design or of the dynamic top-level module.
+pub fn memory_store(memory: &str) -> Option<PagedStore> {
+    let sim = sim();
+    if let Some(mem) = sim.memory.get(memory) {
+        return Some(mem.store().clone());
+    }
+    let unit = sim.top_dynamic.as_ref()?.env.get(memory)?;
+    unit.downcast_ref::<DynMem>().map(|mem| mem.store().clone())
+}
+
+pub fn restore_memory(memory: &str, store: &[VALUE]) {
+    let mut guard = sim();
+    let sim = &mut *guard;
+    if let Some(mem) = sim.memory.get_mut(memory) {
+        mem.restore(store)
+    } else if let Some(handshake) = &mut sim.top_dynamic {
+        if let Some(unit) = handshake.env.get_mut(memory) {
+            if let Some(mem) = unit.downcast_mut::<DynMem>() {
+                mem.restore_mem(store)
+            }
+        }
+    }
+}
+
+pub fn swap_memory(memory: &str, words: &mut [(usize, VALUE)]) {
+    let mut guard = sim();
+    let sim = &mut *guard;
+    if let Some(mem) = sim.memory.get_mut(memory) {
+        mem.swap(words)
+    } else if let Some(handshake) = &mut sim.top_dynamic {
+        if let Some(unit) = handshake.env.get_mut(memory) {
+            if let Some(mem) = unit.downcast_mut::<DynMem>() {
+                mem.swap_mem(words)
+            }
+        }
+    }
+}
+
+pub fn push_stream(stream: &str, value: VALUE) -> Result<(), String> {
+    let mut sim = sim();
+    let Some(fifo) = sim.fifo.get_mut(stream) else {
+        return Err(format!("No FIFO {}", stream));
+    };
+    if !fifo.push(value) {
+        return Err(format!("FIFO {} is full", stream));
+    }
+    Ok(())
+}
+
+type InstanceCopies = HashMap<*const RefCell<Instance>, Rc<RefCell<Instance>>>;
+
+/// Copy an instance together with its submodule instances, an instance that
+/// is both active and a submodule is copied only once.
+fn clone_instance(
+    instance: &Rc<RefCell<Instance>>,
+    copies: &mut InstanceCopies,
+) -> Rc<RefCell<Instance>> {
+    let key = Rc::as_ptr(instance);
+    if let Some(copy) = copies.get(&key) {
+        return copy.clone();
+    }
+    let mut copy = instance.borrow().clone();
+    for component in copy.get_env_mut().sub_env.values_mut() {
+        if let Component::Module(module_ref) = component {
+            *module_ref = clone_instance(module_ref, copies);
+        }
+    }
+    match &mut copy {
+        Instance::Static(_, father) | Instance::PipeFor(_, father) => {
+            if let Some(father_ref) = father {
+                *father_ref = clone_instance(father_ref, copies);
+            }
+        }
+    }
+    let copy = Rc::new(RefCell::new(copy));
+    copies.insert(key, copy.clone());
+    copy
+}
+
+fn clone_active(active: &[Rc<RefCell<Instance>>]) -> Vec<Rc<RefCell<Instance>>> {
+    let mut copies = HashMap::new();
+    active
+        .iter()
+        .map(|instance| clone_instance(instance, &mut copies))
+        .collect()
+}
+
+/// Saved memories, FIFOs, instances and handshake units of the HEC simulator.
+pub struct Checkpoint {
+    memory: HashMap<String, HardwareMemory>,
+    fifo: HashMap<String, FIFO>,
+    fifo_access: HashMap<String, (String, String)>,
+    active: Vec<Rc<RefCell<Instance>>>,
+    top_dynamic: Option<HandshakeEnv>,
+}
+
+pub fn checkpoint() -> Checkpoint {
+    let sim = sim();
+    Checkpoint {
+        memory: sim.memory.clone(),
+        fifo: sim.fifo.clone(),
+        fifo_access: sim.fifo_access.clone(),
+        active: clone_active(&sim.active),
+        top_dynamic: sim.top_dynamic.clone(),
+    }
+}
+
+pub fn restore(checkpoint: &Checkpoint) {
+    sim().memory = checkpoint.memory.clone();
+    sim().fifo = checkpoint.fifo.clone();
+    sim().fifo_access = checkpoint.fifo_access.clone();
+    sim().active = clone_active(&checkpoint.active);
+    sim().top_dynamic = checkpoint.top_dynamic.clone();
+}
+
+/// The state of a HEC simulation, apart from its modules, handed to another
+/// thread that loaded the same IR files. Unlike a checkpoint it shares
+/// nothing with the simulation it was taken from, so that a cosim with
+/// `cosim_threads` can step HEC on a thread that owns all it steps.
+pub struct Handoff {
+    state: Value,
+    state_cycles: HashMap<String, HashMap<String, usize>>,
+    equal_point: HashMap<String, Vec<(Name, usize)>>,
+    equal_count: usize,
+}
+
+pub fn hand_off() -> Handoff {
+    let state = checkpoint().to_json();
+    let sim = sim();
+    Handoff {
+        state,
+        state_cycles: sim.state_cycles.clone(),
+        equal_point: sim.equal_point.clone(),
+        equal_count: sim.equal_value.points(),
+    }
+}
+
+/// Continue from the state of `handoff`, taken from a simulation of the
+/// same modules.
+pub fn take_over(handoff: Handoff) -> Result<(), String> {
+    restore(&Checkpoint::from_json(&handoff.state)?);
+    let mut sim = sim();
+    sim.state_cycles = handoff.state_cycles;
+    sim.equal_point = handoff.equal_point;
+    sim.equal_value = EqualValues::new(handoff.equal_count);
+    Ok(())
+}
+
+/// Load the modules of `files`, merging all after the first, and continue
+/// from the state of `handoff` on them.
+pub fn load_handoff(files: &[String], handoff: Handoff) -> Result<(), Box<dyn Error>> {
+    for (index, path) in files.iter().enumerate() {
+        let (_, ir) = read_ir(path)?;
+        if index == 0 {
+            parse_hec(&ir)?;
+        } else {
+            merge_hec(&ir)?;
+        }
+    }
+    Ok(take_over(handoff)?)
+}
+
+fn env_to_state(
+    env: &StaticEnv,
+    ids: &mut HashMap<*const RefCell<Instance>, usize>,
+    states: &mut Vec<Value>,
+) -> Value {
+    let mut values = serde_json::Map::new();
+    for (var, value) in env.env.iter() {
+        values.insert(var.to_string(), value_to_state(value));
+    }
+    let mut units = serde_json::Map::new();
+    let mut instances = serde_json::Map::new();
+    for (name, component) in env.sub_env.iter() {
+        match component {
+            Component::Primitive(unit) => {
+                units.insert(name.to_string(), unit.to_state());
+            }
+            Component::Module(instance) => {
+                instances.insert(
+                    name.to_string(),
+                    json!(instance_to_state(instance, ids, states)),
+                );
+            }
+        }
+    }
+    json!({"values": values, "units": units, "instances": instances})
+}
+
+/// Append the state of an instance after the ones of its submodule instances,
+/// returning its index in `states`.
+fn instance_to_state(
+    instance: &Rc<RefCell<Instance>>,
+    ids: &mut HashMap<*const RefCell<Instance>, usize>,
+    states: &mut Vec<Value>,
+) -> usize {
+    let key = Rc::as_ptr(instance);
+    if let Some(id) = ids.get(&key) {
+        return *id;
+    }
+    let state = match &*instance.borrow() {
+        Instance::Static(inner, _) => json!({
+            "kind": "stg",
+            "module": inner.env.name,
+            "active": inner.active,
+            "stall": inner.stall,
+            "state": inner.stg.state,
+            "env": env_to_state(&inner.env, ids, states),
+        }),
+        Instance::PipeFor(inner, _) => json!({
+            "kind": "pipe_for",
+            "module": inner.env.name,
+            "active": inner.active,
+            "stall": inner.stall,
+            "stages": inner.pipeline.cur_stages,
+            "control": inner.pipeline.control.iter().map(value_to_state).collect::<Vec<_>>(),
+            "env": env_to_state(&inner.env, ids, states),
+        }),
+    };
+    ids.insert(key, states.len());
+    states.push(state);
+    states.len() - 1
+}
+
+/// A new instance of a loaded module, with its submodule instances.
+fn new_instance(module: &Module) -> Option<Instance> {
+    let env = StaticEnv::new(module);
+    match &module.strategy {
+        Strategy::STG(stg) => Some(Instance::Static(
+            STGInstance {
+                stg: stg.clone(),
+                active: false,
+                env,
+                stall: false,
+            },
+            None,
+        )),
+        Strategy::PipelineFor(pipeline) => Some(Instance::PipeFor(
+            PipeForInstance {
+                pipeline: pipeline.clone(),
+                active: false,
+                env,
+                stall: false,
+            },
+            None,
+        )),
+        _ => None,
+    }
+}
+
+/// Apply the state `id` to an instance and its submodule instances, which the
+/// instance already has, recording each of them in `built`.
+fn restore_instance(
+    instance: &Rc<RefCell<Instance>>,
+    id: usize,
+    states: &[Value],
+    built: &mut HashMap<usize, Rc<RefCell<Instance>>>,
+) -> Option<()> {
+    let state = states.get(id)?;
+    if built.insert(id, instance.clone()).is_some() {
+        return None;
+    }
+    let mut inner = instance.borrow_mut();
+    if inner.get_env().name != state["module"].as_str()? {
+        return None;
+    }
+    let active = state["active"].as_bool()?;
+    let stall = state["stall"].as_bool()?;
+    match (&mut *inner, state["kind"].as_str()?) {
+        (Instance::Static(inner, _), "stg") => {
+            let current = state["state"].as_str()?;
+            if !inner.stg.stg.contains_key(current) {
+                return None;
+            }
+            inner.stg.state = current.to_string();
+            (inner.active, inner.stall) = (active, stall);
+        }
+        (Instance::PipeFor(inner, _), "pipe_for") => {
+            let stages: VecDeque<usize> = serde_json::from_value(state["stages"].clone()).ok()?;
+            if stages
+                .iter()
+                .any(|stage| *stage >= inner.pipeline.stages.len())
+            {
+                return None;
+            }
+            inner.pipeline.cur_stages = stages;
+            inner.pipeline.control = state["control"]
+                .as_array()?
+                .iter()
+                .map(value_from_state)
+                .collect::<Option<Vec<VALUE>>>()?;
+            (inner.active, inner.stall) = (active, stall);
+        }
+        _ => return None,
+    }
+    let env = inner.get_env_mut();
+    env.env.clear();
+    for (var, value) in state["env"]["values"].as_object()? {
+        env.env.insert(Name::new(var), value_from_state(value)?);
+    }
+    for (name, unit_state) in state["env"]["units"].as_object()? {
+        match env.sub_env.get_mut(Name::find(name)?)? {
+            Component::Primitive(unit) => unit.restore_state(unit_state)?,
+            Component::Module(_) => return None,
+        }
+    }
+    let mut submodules = vec![];
+    for (name, sub_id) in state["env"]["instances"].as_object()? {
+        match env.sub_env.get(Name::find(name)?)? {
+            Component::Module(submodule) => {
+                submodules.push((sub_id.as_u64()? as usize, submodule.clone()))
+            }
+            Component::Primitive(_) => return None,
+        }
+    }
+    drop(inner);
+    for (sub_id, submodule) in submodules {
+        restore_instance(&submodule, sub_id, states, built)?;
+    }
+    Some(())
+}
+
+fn handshake_to_state(handshake: &HandshakeEnv) -> Value {
+    let mut units = serde_json::Map::new();
+    let mut memory = serde_json::Map::new();
+    for (name, unit) in &handshake.env {
+        let mut ports = serde_json::Map::new();
+        for (port, value) in unit.ports() {
+            let port_state = json!({
+                "valid": value.valid,
+                "ready": value.ready,
+                "data": value_to_state(&value.data),
+            });
+            ports.insert(port, port_state);
+        }
+        units.insert(name.clone(), Value::Object(ports));
+        if let Some(mem) = unit.downcast_ref::<DynMem>() {
+            memory.insert(name.clone(), store_to_state(mem.dump_mem().iter()));
+        }
+    }
+    json!({"units": units, "memory": memory})
+}
+
+impl Checkpoint {
+    pub fn to_json(&self) -> Value {
+        let mut memory = serde_json::Map::new();
+        for (name, mem) in &self.memory {
+            memory.insert(name.clone(), mem.to_state());
+        }
+        let mut fifo = serde_json::Map::new();
+        for (name, queue) in &self.fifo {
+            fifo.insert(name.clone(), queue.to_state());
+        }
+        let mut ids = HashMap::new();
+        let mut instances = vec![];
+        let active: Vec<usize> = self
+            .active
+            .iter()
+            .map(|instance| instance_to_state(instance, &mut ids, &mut instances))
+            .collect();
+        json!({
+            "memory": memory,
+            "fifo": fifo,
+            "fifo_access": self.fifo_access,
+            "instances": instances,
+            "active": active,
+            "handshake": self.top_dynamic.as_ref().map(handshake_to_state),
+        })
+    }
+
+    /// The state saved by `to_json`, applied to the loaded modules. The units
+    /// of a handshake design only get their memories back, the tokens inside
+    /// them are not restored.
+    pub fn from_json(state: &Value) -> Result<Checkpoint, String> {
+        let mut checkpoint = checkpoint();
+        for (name, mem_state) in state["memory"].as_object().into_iter().flatten() {
+            let Some(mem) = checkpoint.memory.get_mut(name) else {
+                return Err(format!("No memory {}", name));
+            };
+            mem.restore_state(mem_state)
+                .ok_or(format!("Malformed state of memory {}", name))?;
+        }
+        for (name, fifo_state) in state["fifo"].as_object().into_iter().flatten() {
+            let Some(fifo) = checkpoint.fifo.get_mut(name) else {
+                return Err(format!("No FIFO {}", name));
+            };
+            fifo.restore_state(fifo_state)
+                .ok_or(format!("Malformed state of FIFO {}", name))?;
+        }
+        checkpoint.fifo_access = serde_json::from_value(state["fifo_access"].clone())
+            .map_err(|_| "Malformed FIFO accesses")?;
+
+        let states = state["instances"].as_array().cloned().unwrap_or_default();
+        let submodules: HashSet<u64> = states
+            .iter()
+            .filter_map(|state| state["env"]["instances"].as_object())
+            .flat_map(|instances| instances.values().filter_map(|id| id.as_u64()))
+            .collect();
+        let mut built = HashMap::new();
+        for (id, instance_state) in states.iter().enumerate() {
+            if submodules.contains(&(id as u64)) {
+                continue;
+            }
+            let instance = instance_state["module"]
+                .as_str()
+                .and_then(|module| sim().module.get(module).cloned())
+                .and_then(|module| new_instance(&module))
+                .map(|instance| Rc::new(RefCell::new(instance)));
+            instance
+                .and_then(|instance| restore_instance(&instance, id, &states, &mut built))
+                .ok_or(format!("Instance {} does not match the loaded modules", id))?;
+        }
+        checkpoint.active = state["active"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|id| {
+                id.as_u64()
+                    .and_then(|id| built.get(&(id as usize)))
+                    .cloned()
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or("Malformed list of active instances")?;
+
+        if let Some(memories) = state["handshake"]["memory"].as_object() {
+            let Some(handshake) = &mut checkpoint.top_dynamic else {
+                return Err("Call the handshake module before loading its state".to_string());
+            };
+            for (name, mem_state) in memories {
+                let mem = handshake
+                    .env
+                    .get_mut(name)
+                    .and_then(|unit| unit.downcast_mut::<DynMem>())
+                    .ok_or(format!("No memory {}", name))?;
+                match store_from_state(mem_state) {
+                    Some(store) if store.len() == mem.dump_mem().len() => mem.restore_mem(&store),
+                    _ => return Err(format!("Malformed state of memory {}", name)),
+                }
+            }
+            println!("!!Handshake units are not restored, only their memories");
+        }
+        Ok(checkpoint)
+    }
+}
+
+pub fn dump_variable(var: &str) -> Vec<VALUE> {
+    sim()
+        .active
+        .iter()
+        .filter_map(|instance| instance.borrow().get_env().lookup(var))
+        .collect()
+}
+
+pub fn restore_variable(var: &str, values: &[VALUE]) {
+    let mut values = values.iter();
+    let active = sim().active.clone();
+    for instance in active {
+        let mut instance = instance.borrow_mut();
+        let env = instance.get_env_mut();
+        if env.lookup(var).is_some() {
+            if let Some(value) = values.next() {
+                env.set_value(Name::new(var), *value);
+            }
+        }
+    }
+}
+
+fn collect_names(ops: &Vec<Operation>, names: &mut Vec<String>) {
+    for op in ops {
+        if let Operation::Compute(compute) = op {
+            names.push(compute.name.to_string());
+        }
+    }
+}
+
+pub fn function_names() -> Vec<String> {
+    sim().module.keys().cloned().collect()
 }
 
-pub fn get_equalvalue() -> *mut HashMap<(String, String), VALUE> {
-    unsafe { EQUALVALUE }
+/// The size in words and the bytes allocated of each memory.
+pub fn memory_usage() -> Vec<(String, usize, usize)> {
+    sim()
+        .memory
+        .iter()
+        .map(|(name, memory)| (name.clone(), memory.size(), memory.footprint()))
+        .collect()
+}
+
+pub fn memory_names() -> Vec<String> {
+    let mut names: Vec<String> = sim().memory.keys().cloned().collect();
+    names.extend(sim().fifo.keys().cloned());
+    for module in sim().module.values() {
+        for unit in &module.units {
+            if unit.op_type.starts_with("dyn_Mem") {
+                names.push(unit.name.clone());
+            }
+        }
+    }
+    names
+}
+
+pub fn signal_names() -> Vec<String> {
+    let mut names = vec![];
+    for module in sim().module.values() {
+        names.extend(module.args.iter().map(|arg| arg.to_string()));
+        names.extend(module.units.iter().map(|unit| unit.name.clone()));
+        names.extend(
+            module
+                .instances
+                .iter()
+                .map(|instance| instance.name.clone()),
+        );
+        match &module.strategy {
+            Strategy::STG(stg) => {
+                for (name, state) in &stg.stg {
+                    names.push(name.clone());
+                    collect_names(&state.ops, &mut names);
+                }
+            }
+            Strategy::PipelineFor(pipeline) => {
+                for stage in &pipeline.stages {
+                    names.push(stage.name.clone());
+                    collect_names(&stage.ops, &mut names);
+                }
+            }
+            Strategy::Handshake(handshake) => {
+                for assign in &handshake.assign {
+                    names.push(assign.src.to_string());
+                    names.push(assign.dst.to_string());
+                }
+            }
+            _ => (),
+        }
+    }
+    names
 }
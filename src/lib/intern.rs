@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::zip;
+use std::ops::Deref;
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// A port or variable name, interned once while loading the IR so that
+/// environments compare and look up names by id instead of hashing strings.
+#[derive(Clone, Copy)]
+pub struct Name(&'static Entry);
+
+struct Entry {
+    id: u32,
+    text: String,
+    /// The names on either side of the first `.`, as in `unit.port`.
+    split: Option<(Name, Name)>,
+}
+
+static NAMES: LazyLock<Mutex<HashMap<&'static str, Name>>> = LazyLock::new(Default::default);
+
+fn intern(names: &mut HashMap<&'static str, Name>, text: &str) -> Name {
+    if let Some(name) = names.get(text) {
+        return *name;
+    }
+    let split = text
+        .split_once('.')
+        .map(|(a, b)| (intern(names, a), intern(names, b)));
+    let entry: &'static Entry = Box::leak(Box::new(Entry {
+        id: names.len() as u32,
+        text: text.to_string(),
+        split,
+    }));
+    let name = Name(entry);
+    names.insert(&entry.text, name);
+    name
+}
+
+impl Name {
+    pub fn new(text: &str) -> Self {
+        intern(&mut NAMES.lock().unwrap(), text)
+    }
+
+    /// The name interned for `text`, if any, without interning it. Names typed
+    /// at the shell are looked up this way so that a typo does not grow the table.
+    pub fn find(text: &str) -> Option<Self> {
+        NAMES.lock().unwrap().get(text).copied()
+    }
+
+    /// The interned names of a JSON array of strings.
+    pub fn list(ir: &serde_json::Value) -> Vec<Name> {
+        ir.as_array()
+            .unwrap()
+            .iter()
+            .map(|x| Name::new(x.as_str().unwrap()))
+            .collect()
+    }
+
+    pub fn id(self) -> usize {
+        self.0.id as usize
+    }
+
+    /// `unit.port` as `(unit, port)`, worked out when the name was interned.
+    pub fn split(self) -> Option<(Name, Name)> {
+        self.0.split
+    }
+}
+
+impl Deref for Name {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.0.text
+    }
+}
+
+impl AsRef<str> for Name {
+    fn as_ref(&self) -> &str {
+        &self.0.text
+    }
+}
+
+impl From<&str> for Name {
+    fn from(text: &str) -> Self {
+        Name::new(text)
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.id == other.0.id
+    }
+}
+
+impl Eq for Name {}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.0.text == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        self.0.text == *other
+    }
+}
+
+impl PartialEq<String> for Name {
+    fn eq(&self, other: &String) -> bool {
+        self.0.text == *other
+    }
+}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u32(self.0.id);
+    }
+}
+
+/// Names order by their text, so sorted listings read as before interning.
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Name {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.text.cmp(&other.0.text)
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0.text, f)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0.text, f)
+    }
+}
+
+/// The slot of each name an environment holds, indexed by name id. Built while
+/// loading the IR and shared by the environments of a function; a name met
+/// later gets the next slot in a copy of the layout.
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    slots: Vec<u32>,
+    names: Vec<Name>,
+}
+
+const NO_SLOT: u32 = u32::MAX;
+
+impl Layout {
+    pub fn insert(&mut self, name: Name) -> usize {
+        if let Some(slot) = self.slot(name) {
+            return slot;
+        }
+        if self.slots.len() <= name.id() {
+            self.slots.resize(name.id() + 1, NO_SLOT);
+        }
+        self.slots[name.id()] = self.names.len() as u32;
+        self.names.push(name);
+        self.names.len() - 1
+    }
+
+    pub fn slot(&self, name: Name) -> Option<usize> {
+        match self.slots.get(name.id()) {
+            Some(&slot) if slot != NO_SLOT => Some(slot as usize),
+            _ => None,
+        }
+    }
+}
+
+/// Values stored by the slot of their name in a [`Layout`].
+#[derive(Debug, Clone)]
+pub struct Slots<V> {
+    layout: Arc<Layout>,
+    values: Vec<Option<V>>,
+}
+
+impl<V> Default for Slots<V> {
+    fn default() -> Self {
+        Slots::new(Arc::default())
+    }
+}
+
+impl<V> Slots<V> {
+    pub fn new(layout: Arc<Layout>) -> Self {
+        let values = layout.names.iter().map(|_| None).collect();
+        Slots { layout, values }
+    }
+
+    pub fn get(&self, name: Name) -> Option<&V> {
+        let slot = self.layout.slot(name)?;
+        self.values[slot].as_ref()
+    }
+
+    pub fn get_mut(&mut self, name: Name) -> Option<&mut V> {
+        let slot = self.layout.slot(name)?;
+        self.values[slot].as_mut()
+    }
+
+    pub fn insert(&mut self, name: Name, value: V) {
+        let slot = match self.layout.slot(name) {
+            Some(slot) => slot,
+            None => {
+                let slot = Arc::make_mut(&mut self.layout).insert(name);
+                self.values.push(None);
+                slot
+            }
+        };
+        self.values[slot] = Some(value);
+    }
+
+    pub fn clear(&mut self) {
+        for value in &mut self.values {
+            *value = None;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Name, &V)> {
+        zip(&self.layout.names, &self.values)
+            .filter_map(|(name, value)| Some((name, value.as_ref()?)))
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.values.iter_mut().flatten()
+    }
+}
@@ -0,0 +1,264 @@
+use super::error::{ErrorCode, HestiaError};
+use serde_json::{Map, Number, Value};
+
+/// The integer written with `digits` in `radix`, negated if `negative`,
+/// None if it does not fit in 64 bits.
+pub(super) fn integer(negative: bool, digits: &str, radix: u32) -> Option<Number> {
+    match negative {
+        true => i64::from_str_radix(&format!("-{}", digits), radix)
+            .ok()
+            .map(Number::from),
+        false => u64::from_str_radix(digits, radix).ok().map(Number::from),
+    }
+}
+
+/// Whether `digits` are all digits of `radix`, and at least one.
+pub(super) fn is_integer(digits: &str, radix: u32) -> bool {
+    !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, expected: &str) -> HestiaError {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        let column = self.pos - self.text[..self.pos].rfind('\n').map_or(0, |i| i + 1) + 1;
+        let message = format!("line {}, column {}: expected {}", line, column, expected);
+        HestiaError::new(ErrorCode::InvalidSyntax, message)
+    }
+
+    /// Skip whitespace and `//` and `/* */` comments.
+    fn skip(&mut self) -> Result<(), HestiaError> {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                let Some(end) = trimmed.find("*/") else {
+                    return Err(self.error("the end of the comment"));
+                };
+                self.pos += end + 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<char>, HestiaError> {
+        self.skip()?;
+        Ok(self.rest().chars().next())
+    }
+
+    fn eat(&mut self, c: char) -> Result<bool, HestiaError> {
+        if self.peek()? == Some(c) {
+            self.pos += 1;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn string(&mut self, quote: char) -> Result<String, HestiaError> {
+        self.pos += 1;
+        let mut string = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((index, c)) = chars.next() {
+            if c == quote {
+                self.pos += index + 1;
+                return Ok(string);
+            }
+            if c != '\\' {
+                string.push(c);
+                continue;
+            }
+            match chars.next().map(|(_, c)| c) {
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                Some('r') => string.push('\r'),
+                Some('b') => string.push('\u{8}'),
+                Some('f') => string.push('\u{c}'),
+                Some('0') => string.push('\0'),
+                Some('\n') => (),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    let code = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                    string.push(code.unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                Some(c) => string.push(c),
+                None => break,
+            }
+        }
+        Err(self.error(&format!("a closing {}", quote)))
+    }
+
+    fn identifier(&mut self) -> &'a str {
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+            .unwrap_or(rest.len());
+        self.pos += length;
+        &rest[..length]
+    }
+
+    fn number(&mut self) -> Result<Value, HestiaError> {
+        let rest = self.rest();
+        let length = rest
+            .char_indices()
+            .find(|&(index, c)| {
+                let sign = "+-".contains(c) && (index == 0 || rest[..index].ends_with(['e', 'E']));
+                !(c.is_ascii_alphanumeric() || c == '.' || sign)
+            })
+            .map_or(rest.len(), |(index, _)| index);
+        let text = &rest[..length];
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (radix, digits) = match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+            Some(hex) => (16, hex),
+            None => (10, digits),
+        };
+        let number = if is_integer(digits, radix) {
+            match integer(negative, digits, radix) {
+                Some(number) => number,
+                None => return Err(self.error("an integer within 64 bits")),
+            }
+        } else {
+            let value = digits.parse::<f64>().ok().filter(|value| value.is_finite());
+            let value = value.map(|value| if negative { -value } else { value });
+            match value.and_then(Number::from_f64) {
+                Some(number) => number,
+                None => return Err(self.error("a finite number")),
+            }
+        };
+        self.pos += length;
+        Ok(Value::Number(number))
+    }
+
+    fn value(&mut self) -> Result<Value, HestiaError> {
+        match self.peek()? {
+            Some('{') => {
+                self.pos += 1;
+                let mut object = Map::new();
+                while !self.eat('}')? {
+                    let key = match self.peek()? {
+                        Some(quote @ ('"' | '\'')) => self.string(quote)?,
+                        _ => self.identifier().to_string(),
+                    };
+                    if key.is_empty() || !self.eat(':')? {
+                        return Err(self.error("a key and ':'"));
+                    }
+                    object.insert(key, self.value()?);
+                    if !self.eat(',')? && self.peek()? != Some('}') {
+                        return Err(self.error("',' or '}'"));
+                    }
+                }
+                Ok(Value::Object(object))
+            }
+            Some('[') => {
+                self.pos += 1;
+                let mut array = vec![];
+                while !self.eat(']')? {
+                    array.push(self.value()?);
+                    if !self.eat(',')? && self.peek()? != Some(']') {
+                        return Err(self.error("',' or ']'"));
+                    }
+                }
+                Ok(Value::Array(array))
+            }
+            Some(quote @ ('"' | '\'')) => Ok(Value::String(self.string(quote)?)),
+            Some(c) if c.is_ascii_digit() || "+-.".contains(c) => self.number(),
+            Some(_) => match self.identifier() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                "null" => Ok(Value::Null),
+                _ => Err(self.error("a value")),
+            },
+            None => Err(self.error("a value")),
+        }
+    }
+}
+
+/// Parse JSON5, JSON with comments, trailing commas, unquoted keys,
+/// single-quoted strings and hexadecimal numbers.
+pub fn parse(text: &str) -> Result<Value, HestiaError> {
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.value()?;
+    if parser.peek()?.is_some() {
+        return Err(parser.error("the end of the file"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn extensions() {
+        let text = "
+// a sweep
+{
+  load: ['a.json', \"b.json\"], /* trailing */
+  runs: [{name: 'it\\'s', args: [+1, -.5, 1e3,],},],
+}
+";
+        let expected = json!({
+            "load": ["a.json", "b.json"],
+            "runs": [{"name": "it's", "args": [1, -0.5, 1000.0]}],
+        });
+        assert_eq!(parse(text).unwrap(), expected);
+    }
+
+    #[test]
+    fn integers() {
+        let text = "[0x7f, -0x10, 0XFF, 18446744073709551615, -9223372036854775808]";
+        let expected = json!([127, -16, 255, u64::MAX, i64::MIN]);
+        assert_eq!(parse(text).unwrap(), expected);
+    }
+
+    #[test]
+    fn integers_out_of_range() {
+        for text in [
+            "18446744073709551616",
+            "[1,\n -9223372036854775809]",
+            "-0x8000000000000001",
+            "0x10000000000000000",
+        ] {
+            let message = parse(text).unwrap_err().to_string();
+            assert!(
+                message.contains("expected an integer within 64 bits"),
+                "{}",
+                message
+            );
+        }
+        let message = parse("[1,\n -9223372036854775809]")
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("line 2, column 2"), "{}", message);
+    }
+
+    #[test]
+    fn errors() {
+        for (text, expected) in [
+            ("{a 1}", "line 1, column 4: expected a key and ':'"),
+            ("[1 2]", "line 1, column 4: expected ',' or ']'"),
+            ("'open", "line 1, column 2: expected a closing '"),
+            ("/* open", "expected the end of the comment"),
+            ("[1] 2", "line 1, column 5: expected the end of the file"),
+            ("0xg", "expected a finite number"),
+        ] {
+            let message = parse(text).unwrap_err().to_string();
+            assert!(message.contains(expected), "{}", message);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::Mutex;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// The environment variable holding the initial log filter.
+const LOG_VARIABLE: &str = "HESTIA_LOG";
+
+/// The filter of the structured logs, replaced by `set log`.
+static FILTER: Mutex<Option<(String, reload::Handle<EnvFilter, Registry>)>> = Mutex::new(None);
+
+/// Send the `tracing` events of the interpreters to stderr, filtered by
+/// `HESTIA_LOG` with the `RUST_LOG` syntax, e.g.
+/// `hestia::env[unit{name=mul_0}]=trace` for a single HEC unit. Nothing is
+/// logged by default.
+pub fn init() {
+    let mut directives = std::env::var(LOG_VARIABLE).unwrap_or_else(|_| "off".to_string());
+    let filter = EnvFilter::try_new(&directives).unwrap_or_else(|err| {
+        println!("!!Invalid {} {}: {}", LOG_VARIABLE, directives, err);
+        directives = "off".to_string();
+        EnvFilter::new("off")
+    });
+    let (filter, handle) = reload::Layer::new(filter);
+    let output = fmt::layer()
+        .with_writer(std::io::stderr)
+        .without_time()
+        .with_ansi(false);
+    if tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .try_init()
+        .is_ok()
+    {
+        *FILTER.lock().unwrap() = Some((directives, handle));
+    }
+}
+
+/// The log filter in effect, `off` when nothing is logged.
+pub fn log_filter() -> String {
+    match &*FILTER.lock().unwrap() {
+        Some((directives, _)) => directives.clone(),
+        None => "off".to_string(),
+    }
+}
+
+/// Replace the log filter, false if `directives` do not parse.
+pub fn set_log_filter(directives: &str) -> bool {
+    let Ok(filter) = EnvFilter::try_new(directives) else {
+        return false;
+    };
+    let mut filters = FILTER.lock().unwrap();
+    let Some((current, handle)) = filters.as_mut() else {
+        return false;
+    };
+    if handle.reload(filter).is_err() {
+        return false;
+    }
+    *current = directives.to_string();
+    true
+}
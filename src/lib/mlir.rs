@@ -0,0 +1,1298 @@
+use super::common::State;
+use super::error::{ErrorCode, HestiaError};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+enum Attr {
+    Int(i64),
+    /// A float literal as printed, e.g. `9.234000e+00`
+    Float(String),
+    Bool(bool),
+    Str(String),
+    Symbol(String),
+    Array(Vec<Attr>),
+    Dict(Vec<(String, Attr)>),
+    /// A type, or any other attribute kept as its text
+    Raw(String),
+    Unit,
+}
+
+impl Attr {
+    fn to_json(&self) -> Value {
+        match self {
+            Attr::Int(value) => json!(value),
+            Attr::Float(text) => json!(text.parse::<f64>().unwrap_or(0.0)),
+            Attr::Bool(value) => json!(value),
+            Attr::Str(text) | Attr::Symbol(text) => json!(text),
+            Attr::Array(items) => Value::Array(items.iter().map(Attr::to_json).collect()),
+            Attr::Dict(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(key, attr)| (key.clone(), attr.to_json()));
+                Value::Object(fields.collect())
+            }
+            Attr::Raw(text) => json!(lower_type(text)),
+            Attr::Unit => json!(true),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Block {
+    args: Vec<(String, String)>,
+    ops: Vec<Op>,
+}
+
+/// An operation in MLIR's generic form, e.g.
+/// `%3 = "tor.addi"(%1, %2) {starttime = 6 : i32} : (i32, i32) -> i32`.
+#[derive(Debug)]
+struct Op {
+    name: String,
+    line: usize,
+    results: Vec<String>,
+    operands: Vec<String>,
+    attrs: Vec<(String, Attr)>,
+    regions: Vec<Vec<Block>>,
+    outputs: Vec<String>,
+}
+
+impl Op {
+    fn attr(&self, key: &str) -> Option<&Attr> {
+        self.attrs
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, attr)| attr)
+    }
+
+    fn int(&self, key: &str) -> Option<i64> {
+        match self.attr(key) {
+            Some(Attr::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn string(&self, key: &str) -> Option<&str> {
+        match self.attr(key) {
+            Some(Attr::Str(text) | Attr::Symbol(text)) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The operations of all blocks of region `index`, none if it is absent.
+    fn ops(&self, index: usize) -> impl Iterator<Item = &Op> {
+        let blocks = self.regions.get(index).into_iter().flatten();
+        blocks.flat_map(|block| block.ops.iter())
+    }
+
+    fn block_args(&self, index: usize) -> &[(String, String)] {
+        match self.regions.get(index).and_then(|blocks| blocks.first()) {
+            Some(block) => &block.args,
+            None => &[],
+        }
+    }
+
+    /// The name without its dialect, `addi` for `arith.addi`.
+    fn short_name(&self) -> &str {
+        self.name
+            .split_once('.')
+            .map_or(self.name.as_str(), |(_, name)| name)
+    }
+
+    fn error(&self, code: ErrorCode, message: String) -> HestiaError {
+        let message = format!("line {}: {} in '{}'", self.line, message, self.name);
+        HestiaError::new(code, message)
+    }
+
+    fn result(&self) -> Result<&str, HestiaError> {
+        match self.results.as_slice() {
+            [result] => Ok(result),
+            _ => Err(self.error(
+                ErrorCode::MalformedIr,
+                format!("{} results instead of one", self.results.len()),
+            )),
+        }
+    }
+
+    fn operand(&self, index: usize) -> Result<&str, HestiaError> {
+        self.operands
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| self.error(ErrorCode::MalformedIr, format!("missing operand {}", index)))
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_$.-".contains(c)
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn line(&self) -> usize {
+        self.text[..self.pos].matches('\n').count() + 1
+    }
+
+    fn error(&self, expected: &str) -> HestiaError {
+        let column = self.pos - self.text[..self.pos].rfind('\n').map_or(0, |i| i + 1) + 1;
+        let found: String = self.rest().chars().take(20).collect();
+        let message = format!(
+            "line {}, column {}: expected {}, found '{}'",
+            self.line(),
+            column,
+            expected,
+            found.lines().next().unwrap_or_default()
+        );
+        HestiaError::new(ErrorCode::InvalidSyntax, message)
+    }
+
+    /// Skip whitespace and `//` comments.
+    fn skip(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if !trimmed.starts_with("//") {
+                return;
+            }
+            self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip();
+        self.rest().chars().next()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), HestiaError> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(self.error(&format!("'{}'", token))),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<&'a str, HestiaError> {
+        self.skip();
+        let rest = self.rest();
+        let length = rest.find(|c| !is_identifier(c)).unwrap_or(rest.len());
+        if length == 0 {
+            return Err(self.error("an identifier"));
+        }
+        self.pos += length;
+        Ok(&rest[..length])
+    }
+
+    /// A value use or definition, `%name` or `%name#1`.
+    fn value(&mut self) -> Result<String, HestiaError> {
+        self.expect("%")?;
+        let mut name = self.identifier()?.to_string();
+        if self.rest().starts_with('#') {
+            self.pos += 1;
+            name = format!("{}#{}", name, self.identifier()?);
+        }
+        Ok(name)
+    }
+
+    fn string(&mut self) -> Result<String, HestiaError> {
+        self.expect("\"")?;
+        let mut string = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += index + 1;
+                    return Ok(string);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 't')) => string.push('\t'),
+                    Some((_, c @ ('"' | '\\'))) => string.push(c),
+                    Some((_, high)) => {
+                        let low = chars.next().map_or('0', |(_, c)| c);
+                        let byte = u8::from_str_radix(&format!("{}{}", high, low), 16);
+                        string.push(byte.unwrap_or(b'?') as char);
+                    }
+                    None => break,
+                },
+                c => string.push(c),
+            }
+        }
+        Err(self.error("a closing '\"'"))
+    }
+
+    /// Skip the balanced brackets starting at the cursor.
+    fn balanced(&mut self) -> Result<(), HestiaError> {
+        let mut depth = 0;
+        loop {
+            let rest = self.rest();
+            let Some(c) = rest.chars().next() else {
+                return Err(self.error("a closing bracket"));
+            };
+            if c == '"' {
+                self.string()?;
+                continue;
+            }
+            if rest.starts_with("->") {
+                self.pos += 2;
+                continue;
+            }
+            self.pos += c.len_utf8();
+            match c {
+                '<' | '(' | '[' | '{' => depth += 1,
+                '>' | ')' | ']' | '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// A type such as `i32`, `!tor.memref<4096xf64, [], "r">` or
+    /// `(i32) -> f64`, as its text.
+    fn type_(&mut self) -> Result<String, HestiaError> {
+        self.skip();
+        let start = self.pos;
+        if self.rest().starts_with('(') {
+            self.function_type()?;
+            return Ok(self.text[start..self.pos].to_string());
+        }
+        loop {
+            let rest = self.rest();
+            let length = rest
+                .find(|c: char| c.is_whitespace() || ",()[]{}<>\"".contains(c))
+                .unwrap_or(rest.len());
+            self.pos += length;
+            if !self.rest().starts_with('<') {
+                break;
+            }
+            self.balanced()?;
+        }
+        if self.pos == start {
+            return Err(self.error("a type"));
+        }
+        Ok(self.text[start..self.pos].to_string())
+    }
+
+    fn type_list(&mut self) -> Result<Vec<String>, HestiaError> {
+        self.expect("(")?;
+        let mut types = vec![];
+        if self.eat(")") {
+            return Ok(types);
+        }
+        loop {
+            types.push(self.type_()?);
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect(")")?;
+        Ok(types)
+    }
+
+    /// `(inputs) -> outputs`, where a single output needs no parentheses.
+    fn function_type(&mut self) -> Result<(Vec<String>, Vec<String>), HestiaError> {
+        let inputs = self.type_list()?;
+        self.expect("->")?;
+        let outputs = match self.peek() {
+            Some('(') => self.type_list()?,
+            _ => vec![self.type_()?],
+        };
+        Ok((inputs, outputs))
+    }
+
+    fn skip_location(&mut self) -> Result<(), HestiaError> {
+        self.skip();
+        if self.rest().starts_with("loc(") {
+            self.pos += 3;
+            self.balanced()?;
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<Attr, HestiaError> {
+        let rest = self.rest();
+        let length = rest
+            .char_indices()
+            .find(|&(index, c)| {
+                let sign = c == '-' && (index == 0 || rest[..index].ends_with(['e', 'E']));
+                !(c.is_ascii_alphanumeric() || c == '.' || c == '+' || sign)
+            })
+            .map_or(rest.len(), |(index, _)| index);
+        let text = &rest[..length];
+        let digits = text.strip_prefix('-').unwrap_or(text);
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok().map(Attr::Int)
+        } else if digits.contains(['.', 'e', 'E']) {
+            text.parse::<f64>()
+                .ok()
+                .map(|_| Attr::Float(text.to_string()))
+        } else {
+            text.parse::<i64>().ok().map(Attr::Int)
+        };
+        let Some(value) = value else {
+            return Err(self.error("a number"));
+        };
+        self.pos += length;
+        if self.eat(":") {
+            self.type_()?;
+        }
+        Ok(value)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let rest = self.rest();
+        let found = rest.starts_with(keyword)
+            && !rest[keyword.len()..].starts_with(|c: char| is_identifier(c));
+        if found {
+            self.pos += keyword.len();
+        }
+        found
+    }
+
+    fn attr(&mut self) -> Result<Attr, HestiaError> {
+        match self.peek() {
+            Some('"') => Ok(Attr::Str(self.string()?)),
+            Some('@') => {
+                let mut symbol = String::new();
+                while self.eat("@") {
+                    let name = match self.peek() {
+                        Some('"') => self.string()?,
+                        _ => self.identifier()?.to_string(),
+                    };
+                    symbol.push_str(&name);
+                    if !self.eat("::") {
+                        break;
+                    }
+                    symbol.push_str("::");
+                }
+                Ok(Attr::Symbol(symbol))
+            }
+            Some('[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                if self.eat("]") {
+                    return Ok(Attr::Array(items));
+                }
+                loop {
+                    items.push(self.attr()?);
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                self.expect("]")?;
+                Ok(Attr::Array(items))
+            }
+            Some('{') => Ok(Attr::Dict(self.dict()?)),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ if self.keyword("true") => Ok(Attr::Bool(true)),
+            _ if self.keyword("false") => Ok(Attr::Bool(false)),
+            _ if self.keyword("unit") => Ok(Attr::Unit),
+            _ if self.rest().starts_with("array<") => {
+                self.pos += "array<".len();
+                self.type_()?;
+                let mut items = vec![];
+                if self.eat(":") {
+                    loop {
+                        items.push(self.number()?);
+                        if !self.eat(",") {
+                            break;
+                        }
+                    }
+                }
+                self.expect(">")?;
+                Ok(Attr::Array(items))
+            }
+            _ => Ok(Attr::Raw(self.type_()?)),
+        }
+    }
+
+    fn dict(&mut self) -> Result<Vec<(String, Attr)>, HestiaError> {
+        self.expect("{")?;
+        let mut attrs = vec![];
+        if self.eat("}") {
+            return Ok(attrs);
+        }
+        loop {
+            let key = match self.peek() {
+                Some('"') => self.string()?,
+                _ => self.identifier()?.to_string(),
+            };
+            let attr = match self.eat("=") {
+                true => self.attr()?,
+                false => Attr::Unit,
+            };
+            attrs.push((key, attr));
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect("}")?;
+        Ok(attrs)
+    }
+
+    fn region(&mut self) -> Result<Vec<Block>, HestiaError> {
+        self.expect("{")?;
+        let mut blocks = vec![];
+        let mut block = Block::default();
+        while !self.eat("}") {
+            if self.peek() != Some('^') {
+                block.ops.push(self.op()?);
+                continue;
+            }
+            if !blocks.is_empty() || !block.ops.is_empty() {
+                blocks.push(std::mem::take(&mut block));
+            }
+            self.pos += 1;
+            self.identifier()?;
+            if self.eat("(") && !self.eat(")") {
+                loop {
+                    let name = self.value()?;
+                    self.expect(":")?;
+                    block.args.push((name, self.type_()?));
+                    self.skip_location()?;
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                self.expect(")")?;
+            }
+            self.expect(":")?;
+        }
+        blocks.push(block);
+        Ok(blocks)
+    }
+
+    fn op(&mut self) -> Result<Op, HestiaError> {
+        let mut results = vec![];
+        if self.peek() == Some('%') {
+            loop {
+                let name = self.value()?;
+                if self.eat(":") {
+                    let count = self.identifier()?;
+                    let Ok(count) = count.parse::<usize>() else {
+                        return Err(self.error("a result count"));
+                    };
+                    results.extend((0..count).map(|index| format!("{}#{}", name, index)));
+                } else {
+                    results.push(name);
+                }
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect("=")?;
+        }
+        self.skip();
+        let line = self.line();
+        let mut op = Op {
+            name: String::new(),
+            line,
+            results,
+            operands: vec![],
+            attrs: vec![],
+            regions: vec![],
+            outputs: vec![],
+        };
+        if self.keyword("module") {
+            op.name = "builtin.module".to_string();
+            if self.peek() == Some('@') {
+                self.attr()?;
+            }
+            if self.keyword("attributes") {
+                op.attrs = self.dict()?;
+            }
+            op.regions.push(self.region()?);
+            self.skip_location()?;
+            return Ok(op);
+        }
+        if self.peek() != Some('"') {
+            return Err(self.error("an operation in generic form"));
+        }
+        op.name = self.string()?;
+        self.expect("(")?;
+        if !self.eat(")") {
+            loop {
+                op.operands.push(self.value()?);
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect(")")?;
+        }
+        if self.peek() == Some('[') {
+            return Err(self.error("no successors, branches are not supported"));
+        }
+        self.skip();
+        if self.rest().starts_with("<{") {
+            self.pos += 1;
+            op.attrs.extend(self.dict()?);
+            self.expect(">")?;
+        }
+        if self.eat("(") {
+            loop {
+                op.regions.push(self.region()?);
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect(")")?;
+        }
+        if self.peek() == Some('{') {
+            op.attrs.extend(self.dict()?);
+        }
+        self.expect(":")?;
+        (_, op.outputs) = self.function_type()?;
+        self.skip_location()?;
+        Ok(op)
+    }
+
+    /// The top-level operations, skipping attribute and type aliases and
+    /// dialect resources.
+    fn parse(&mut self) -> Result<Vec<Op>, HestiaError> {
+        let mut ops = vec![];
+        while let Some(c) = self.peek() {
+            if self.rest().starts_with("{-#") {
+                let end = self.rest().find("#-}").map_or(self.rest().len(), |i| i + 3);
+                self.pos += end;
+            } else if c == '#' || c == '!' {
+                self.pos += self.rest().find('\n').unwrap_or(self.rest().len());
+            } else {
+                ops.push(self.op()?);
+            }
+        }
+        Ok(ops)
+    }
+}
+
+/// The JSON name of a type, `bool` for `i1` and `vector<4xbool>` for
+/// `vector<4xi1>`.
+fn lower_type(ty: &str) -> String {
+    let shape = ty
+        .strip_prefix("vector<")
+        .and_then(|ty| ty.strip_suffix('>'));
+    if let Some((lanes, element)) = shape.and_then(|shape| shape.split_once('x')) {
+        return format!("vector<{}x{}>", lanes, lower_type(element));
+    }
+    match ty {
+        "i1" => "bool".to_string(),
+        _ => ty.to_string(),
+    }
+}
+
+/// The size and element type of a memref type, such as 4096 and `f64` for
+/// `!tor.memref<4096xf64, [], "r">` or `memref<64x64xf64>`.
+fn memref_shape(ty: &str) -> Option<(u64, String)> {
+    let (_, inner) = ty.split_once('<')?;
+    let shape = inner.split([',', '>']).next()?;
+    let mut dims: Vec<&str> = shape.trim().split('x').collect();
+    let element = dims.pop()?;
+    let mut size = 1;
+    for dim in dims {
+        size *= dim.parse::<u64>().ok()?;
+    }
+    Some((size, lower_type(element)))
+}
+
+fn is_constant(op: &Op) -> bool {
+    matches!(
+        op.name.as_str(),
+        "arith.constant" | "std.constant" | "tor.constant" | "hec.constant"
+    )
+}
+
+/// The interpreter's name of a computation, `add` for `arith.addi` and
+/// `cmp_slt` for a `cmpi` with predicate `slt`.
+fn operation_type(op: &Op) -> Result<String, HestiaError> {
+    const CMPI: [&str; 10] = [
+        "eq", "ne", "slt", "sle", "sgt", "sge", "ult", "ule", "ugt", "uge",
+    ];
+    const CMPF: [&str; 16] = [
+        "false", "oeq", "ogt", "oge", "olt", "ole", "one", "ord", "ueq", "ugt", "uge", "ult",
+        "ule", "une", "uno", "true",
+    ];
+    if let Some(name) = op.name.strip_prefix("complex.") {
+        return Ok(match name {
+            "create" => "complex",
+            "add" => "cadd",
+            "sub" => "csub",
+            "mul" => "cmul",
+            _ => name,
+        }
+        .to_string());
+    }
+    let name = op.short_name();
+    let predicates: &[&str] = match name {
+        "cmpi" => &CMPI,
+        "cmpf" => &CMPF,
+        "addi" | "addf" => return Ok("add".to_string()),
+        "subi" | "subf" => return Ok("sub".to_string()),
+        "muli" | "mulf" => return Ok("mul".to_string()),
+        "divf" => return Ok("div".to_string()),
+        "remf" | "remsi" => return Ok("rem".to_string()),
+        "shli" => return Ok("shift_left".to_string()),
+        "andi" => return Ok("and".to_string()),
+        "ori" => return Ok("or".to_string()),
+        "xori" => return Ok("xor".to_string()),
+        "extractelement" => return Ok("vector_extract".to_string()),
+        "insertelement" => return Ok("vector_insert".to_string()),
+        "absf" | "absi" => return Ok("abs".to_string()),
+        "minimumf" | "minnumf" | "minsi" => return Ok("min".to_string()),
+        "maximumf" | "maxnumf" | "maxsi" => return Ok("max".to_string()),
+        "trunci" => return Ok("trunc".to_string()),
+        _ => return Ok(name.to_string()),
+    };
+    let predicate = match op.attr("predicate") {
+        Some(Attr::Str(predicate)) => Some(predicate.as_str()),
+        Some(Attr::Int(index)) => predicates.get(*index as usize).copied(),
+        _ => None,
+    };
+    match predicate {
+        Some(predicate) => Ok(format!("cmp_{}", predicate)),
+        None => Err(op.error(
+            ErrorCode::MalformedIr,
+            "missing or unknown predicate".to_string(),
+        )),
+    }
+}
+
+/// Builds the JSON of one design. Constants are global in the JSON, so a
+/// constant whose name another function already gives a different value is
+/// renamed.
+#[derive(Default)]
+struct Lowering {
+    constants: Vec<Value>,
+    defined: HashMap<String, (String, String)>,
+    renamed: HashMap<String, String>,
+    /// The start and end of the enclosing `for` or `if`, and whether it loops
+    scope: Option<(i64, i64, bool)>,
+    /// The end of the time graph of the function, when its return happens
+    end: Option<i64>,
+}
+
+impl Lowering {
+    fn name(&self, value: &str) -> Value {
+        json!(self.renamed.get(value).map_or(value, String::as_str))
+    }
+
+    fn names(&self, values: &[String]) -> Value {
+        Value::Array(values.iter().map(|value| self.name(value)).collect())
+    }
+
+    fn constant(&mut self, op: &Op, function: Option<&str>) -> Result<(), HestiaError> {
+        let name = op.result()?;
+        let ty = lower_type(op.outputs.first().map_or("", String::as_str));
+        let literal = match op.attr("value") {
+            Some(Attr::Int(value)) if ty == "bool" => ((*value != 0) as u8).to_string(),
+            Some(Attr::Int(value)) => value.to_string(),
+            Some(Attr::Bool(value)) => (*value as u8).to_string(),
+            Some(Attr::Float(text)) => text.clone(),
+            _ => {
+                return Err(op.error(
+                    ErrorCode::InvalidConstant,
+                    "no integer, float or boolean value".to_string(),
+                ))
+            }
+        };
+        let mut unique = name.to_string();
+        while let Some(defined) = self.defined.get(&unique) {
+            if *defined == (literal.clone(), ty.clone()) {
+                break;
+            }
+            unique = format!("{}_{}", function.unwrap_or("design"), unique);
+        }
+        if unique != name {
+            self.renamed.insert(name.to_string(), unique.clone());
+        }
+        if !self.defined.contains_key(&unique) {
+            self.defined
+                .insert(unique.clone(), (literal.clone(), ty.clone()));
+            self.constants
+                .push(json!({"name": unique, "operands": literal, "type": ty}));
+        }
+        Ok(())
+    }
+
+    /// Move the constants of `function` to the design, before any of its
+    /// operations is lowered.
+    fn hoist_constants<'a>(
+        &mut self,
+        ops: impl Iterator<Item = &'a Op>,
+        function: &str,
+    ) -> Result<(), HestiaError> {
+        for op in ops {
+            if is_constant(op) {
+                self.constant(op, Some(function))?;
+            }
+            for index in 0..op.regions.len() {
+                self.hoist_constants(op.ops(index), function)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn memory(&self, op: &Op) -> Result<Value, HestiaError> {
+        let ty = op.outputs.first().map_or("", String::as_str);
+        let Some((size, element)) = memref_shape(ty) else {
+            return Err(op.error(
+                ErrorCode::UnknownType,
+                format!("'{}' is not a memref type", ty),
+            ));
+        };
+        let name = match op.string("sym_name") {
+            Some(name) => json!(name),
+            None => self.name(op.result()?),
+        };
+        Ok(json!({"name": name, "size": size, "type": element}))
+    }
+
+    fn stream(&self, op: &Op) -> Result<Value, HestiaError> {
+        let name = match op.string("sym_name") {
+            Some(name) => json!(name),
+            None => self.name(op.result()?),
+        };
+        let ty = lower_type(op.outputs.first().map_or("", String::as_str));
+        Ok(json!({"name": name, "depth": op.int("depth"), "type": ty}))
+    }
+
+    fn computation(&self, op: &Op) -> Result<Map<String, Value>, HestiaError> {
+        if !op.regions.is_empty() {
+            return Err(op.error(
+                ErrorCode::UnknownOperation,
+                "unsupported operation".to_string(),
+            ));
+        }
+        let mut ir = Map::new();
+        ir.insert("op_type".to_string(), json!(operation_type(op)?));
+        ir.insert("name".to_string(), self.name(op.result()?));
+        ir.insert("operands".to_string(), self.names(&op.operands));
+        let ty = op.outputs.first().map_or("", String::as_str);
+        ir.insert("type".to_string(), json!(lower_type(ty)));
+        Ok(ir)
+    }
+
+    fn tor_ops<'a>(&mut self, ops: impl Iterator<Item = &'a Op>) -> Result<Value, HestiaError> {
+        let mut body = vec![];
+        for op in ops {
+            if !is_constant(op) && op.name != "tor.timegraph" {
+                body.push(Value::Object(self.tor_op(op)?));
+            }
+        }
+        Ok(Value::Array(body))
+    }
+
+    fn tor_op(&mut self, op: &Op) -> Result<Map<String, Value>, HestiaError> {
+        let start = op.int("starttime").or(op.int("start"));
+        let end = op.int("endtime").or(op.int("end"));
+        let mut ir = match op.name.as_str() {
+            "tor.for" => {
+                let args = op.block_args(0);
+                let [lb, ub, step, iter_inits @ ..] = op.operands.as_slice() else {
+                    return Err(
+                        op.error(ErrorCode::MalformedIr, "missing bounds or step".to_string())
+                    );
+                };
+                let Some(((iter_name, _), iter_args)) = args.split_first() else {
+                    return Err(
+                        op.error(ErrorCode::MalformedIr, "no induction variable".to_string())
+                    );
+                };
+                let iter_args: Vec<String> = iter_args.iter().map(|(arg, _)| arg.clone()).collect();
+                let scope = self
+                    .scope
+                    .replace((start.unwrap_or(0), end.unwrap_or(0), true));
+                let body = self.tor_ops(op.ops(0));
+                self.scope = scope;
+                let mut ir = Map::new();
+                ir.insert("op_type".to_string(), json!("for"));
+                ir.insert("names".to_string(), self.names(&op.results));
+                ir.insert("iter_name".to_string(), self.name(iter_name));
+                ir.insert("iter_args".to_string(), self.names(&iter_args));
+                ir.insert("iter_inits".to_string(), self.names(iter_inits));
+                ir.insert("lb".to_string(), self.name(lb));
+                ir.insert("ub".to_string(), self.name(ub));
+                ir.insert("step".to_string(), self.name(step));
+                ir.insert("body".to_string(), body?);
+                ir
+            }
+            "tor.if" => {
+                let scope = self
+                    .scope
+                    .replace((start.unwrap_or(0), end.unwrap_or(0), false));
+                let bodies = (self.tor_ops(op.ops(0)), self.tor_ops(op.ops(1)));
+                self.scope = scope;
+                let mut ir = Map::new();
+                ir.insert("op_type".to_string(), json!("if"));
+                ir.insert("condition".to_string(), self.name(op.operand(0)?));
+                ir.insert("names".to_string(), self.names(&op.results));
+                ir.insert("body0".to_string(), bodies.0?);
+                ir.insert("body1".to_string(), bodies.1?);
+                ir
+            }
+            "tor.yield" => {
+                let mut ir = Map::new();
+                ir.insert("op_type".to_string(), json!("yield"));
+                ir.insert("operands".to_string(), self.names(&op.operands));
+                if let Some((start, end, looped)) = self.scope {
+                    ir.insert("time".to_string(), json!(end));
+                    if looped {
+                        ir.insert("jump".to_string(), json!(start));
+                    }
+                }
+                ir
+            }
+            "tor.return" => {
+                let mut ir = Map::new();
+                ir.insert("op_type".to_string(), json!("return"));
+                ir.insert("operands".to_string(), self.names(&op.operands));
+                ir.insert("time".to_string(), json!(op.int("time").or(self.end)));
+                ir
+            }
+            "tor.call" => {
+                let mut ir = Map::new();
+                ir.insert("op_type".to_string(), json!("call"));
+                ir.insert("function".to_string(), json!(op.string("callee")));
+                ir.insert("names".to_string(), self.names(&op.results));
+                ir.insert("operands".to_string(), self.names(&op.operands));
+                ir
+            }
+            "tor.load" | "tor.store" => {
+                let load = op.name == "tor.load";
+                let (memory, index) = match load {
+                    true => (0, 1),
+                    false => (1, 2),
+                };
+                if op.operands.len() > index + 1 {
+                    return Err(op.error(
+                        ErrorCode::MalformedIr,
+                        "more than one index, memories must be flattened".to_string(),
+                    ));
+                }
+                let mut ir = Map::new();
+                ir.insert("op_type".to_string(), json!(op.short_name()));
+                ir.insert("memory".to_string(), self.name(op.operand(memory)?));
+                ir.insert("index".to_string(), self.name(op.operand(index)?));
+                match load {
+                    true => ir.insert("name".to_string(), self.name(op.result()?)),
+                    false => ir.insert("value".to_string(), self.name(op.operand(0)?)),
+                };
+                ir
+            }
+            "tor.read" => {
+                let mut ir = Map::new();
+                ir.insert("op_type".to_string(), json!("read"));
+                ir.insert("name".to_string(), self.name(op.result()?));
+                ir.insert("stream".to_string(), self.name(op.operand(0)?));
+                ir
+            }
+            "tor.write" => {
+                let mut ir = Map::new();
+                ir.insert("op_type".to_string(), json!("write"));
+                ir.insert("data".to_string(), self.name(op.operand(0)?));
+                ir.insert("stream".to_string(), self.name(op.operand(1)?));
+                ir
+            }
+            _ => self.computation(op)?,
+        };
+        if let Some(start) = start {
+            ir.insert("start".to_string(), json!(start));
+        }
+        if let Some(end) = end {
+            ir.insert("end".to_string(), json!(end));
+        }
+        Ok(ir)
+    }
+
+    /// The time graph of a function, with an edge from each of the points of
+    /// a `tor.succ` to its time.
+    fn time_graph(&self, op: &Op) -> Result<Value, HestiaError> {
+        let mut edges = vec![];
+        for succ in op.ops(0) {
+            let (Some(to), Some(Attr::Array(points)), Some(Attr::Array(attrs))) =
+                (succ.int("time"), succ.attr("points"), succ.attr("edges"))
+            else {
+                return Err(succ.error(
+                    ErrorCode::MalformedIr,
+                    "missing time, points or edges".to_string(),
+                ));
+            };
+            for (point, attrs) in points.iter().zip(attrs) {
+                let mut edge = match attrs.to_json() {
+                    Value::Object(edge) => edge,
+                    _ => Map::new(),
+                };
+                edge.insert("from".to_string(), point.to_json());
+                edge.insert("to".to_string(), json!(to));
+                edges.push(Value::Object(edge));
+            }
+        }
+        Ok(json!({
+            "start": op.int("starttime"),
+            "end": op.int("endtime"),
+            "edge": edges,
+        }))
+    }
+
+    fn tor_function(&mut self, op: &Op) -> Result<Value, HestiaError> {
+        let name = op.string("sym_name").unwrap_or_default();
+        self.renamed.clear();
+        self.hoist_constants(op.ops(0), name)?;
+        let strategy = match (op.string("strategy"), op.string("pipeline"), op.int("II")) {
+            (Some(strategy), _, _) => strategy.to_string(),
+            (None, Some(pipeline), Some(ii)) => format!("pipeline {} {}", pipeline, ii),
+            _ => "static".to_string(),
+        };
+        let args = op.block_args(0);
+        let mut function = Map::new();
+        function.insert("name".to_string(), json!(name));
+        function.insert("strategy".to_string(), json!(strategy));
+        let names: Vec<String> = args.iter().map(|(arg, _)| arg.clone()).collect();
+        function.insert("args".to_string(), self.names(&names));
+        let types = args.iter().map(|(_, ty)| lower_type(ty));
+        function.insert("types".to_string(), json!(types.collect::<Vec<_>>()));
+        let ret_types = match op.attr("function_type") {
+            Some(Attr::Raw(ty)) => function_type(ty)?.1,
+            _ => vec![],
+        };
+        let ret_types = ret_types.iter().map(|ty| lower_type(ty));
+        function.insert(
+            "ret_types".to_string(),
+            json!(ret_types.collect::<Vec<_>>()),
+        );
+        function.insert("return_vals".to_string(), json!([]));
+        self.end = None;
+        if let Some(graph) = op.ops(0).find(|op| op.name == "tor.timegraph") {
+            self.end = graph.int("endtime");
+            function.insert("graph".to_string(), self.time_graph(graph)?);
+        }
+        function.insert("body".to_string(), self.tor_ops(op.ops(0))?);
+        Ok(Value::Object(function))
+    }
+
+    fn tor_design(&mut self, design: &Op) -> Result<Value, HestiaError> {
+        let (mut memory, mut stream, mut modules) = (vec![], vec![], vec![]);
+        for op in design.ops(0) {
+            match op.name.as_str() {
+                "tor.alloc" => memory.push(self.memory(op)?),
+                "tor.stream" => stream.push(self.stream(op)?),
+                "tor.func" => modules.push(self.tor_function(op)?),
+                _ if is_constant(op) => self.constant(op, None)?,
+                _ => {
+                    return Err(op.error(
+                        ErrorCode::UnknownOperation,
+                        "unexpected operation in design".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(json!({
+            "level": "tor",
+            "memory": memory,
+            "stream": stream,
+            "constants": self.constants,
+            "modules": modules,
+        }))
+    }
+
+    fn hec_ops<'a>(&mut self, ops: impl Iterator<Item = &'a Op>) -> Result<Value, HestiaError> {
+        let mut body = vec![];
+        for op in ops {
+            if !is_constant(op) && op.name != "hec.transition" {
+                body.push(self.hec_op(op)?);
+            }
+        }
+        Ok(Value::Array(body))
+    }
+
+    fn hec_op(&self, op: &Op) -> Result<Value, HestiaError> {
+        let ir = match op.name.as_str() {
+            "hec.assign" => {
+                let mut ir = json!({
+                    "op_type": "assign",
+                    "dst": self.name(op.operand(0)?),
+                    "src": self.name(op.operand(1)?),
+                });
+                if let Some(condition) = op.operands.get(2) {
+                    ir["condition"] = self.name(condition);
+                }
+                ir
+            }
+            "hec.enable" => {
+                let mut ir = json!({"op_type": "enable", "port": self.name(op.operand(0)?)});
+                if let Some(Attr::Bool(true) | Attr::Unit) = op.attr("stream") {
+                    ir["stream"] = json!(true);
+                }
+                ir
+            }
+            "hec.go" => json!({"op_type": "go", "instance": op.string("instanceName")}),
+            "hec.deliver" => json!({
+                "op_type": "deliver",
+                "dst_port": self.name(op.operand(0)?),
+                "dst_reg": self.name(op.operand(1)?),
+                "src": self.name(op.operand(2)?),
+            }),
+            _ => Value::Object(self.computation(op)?),
+        };
+        Ok(ir)
+    }
+
+    /// The operations of a state and the transition that ends it.
+    fn hec_state(&mut self, op: &Op) -> Result<Value, HestiaError> {
+        let mut jump = vec![];
+        let mut transition = Map::new();
+        let transitions = op.ops(0).filter(|op| op.name == "hec.transition");
+        for op in transitions.flat_map(|op| op.ops(0)) {
+            match (op.name.as_str(), op.operands.first()) {
+                ("hec.goto", None) => {
+                    transition.insert("default".to_string(), json!(op.string("dest")));
+                }
+                ("hec.goto", Some(cond)) => {
+                    jump.push(json!({"dest": op.string("dest"), "cond": self.name(cond)}));
+                }
+                ("hec.done", _) => {
+                    transition.insert("done".to_string(), self.names(&op.operands));
+                }
+                _ => {
+                    return Err(op.error(
+                        ErrorCode::UnknownOperation,
+                        "unexpected operation in transition".to_string(),
+                    ))
+                }
+            }
+        }
+        transition.insert("jump".to_string(), Value::Array(jump));
+        Ok(json!({
+            "state": op.string("sym_name"),
+            "ops": self.hec_ops(op.ops(0))?,
+            "transition": transition,
+        }))
+    }
+
+    fn hec_component(&mut self, op: &Op) -> Result<Value, HestiaError> {
+        let name = op.string("sym_name").unwrap_or_default();
+        self.renamed.clear();
+        self.hoist_constants(op.ops(0), name)?;
+        let args = op.block_args(0);
+        let names: Vec<String> = args.iter().map(|(arg, _)| arg.clone()).collect();
+        let types: Vec<String> = args.iter().map(|(_, ty)| lower_type(ty)).collect();
+        let num_in = match op.attr("function_type") {
+            Some(Attr::Raw(ty)) => Some(function_type(ty)?.0.len() as i64),
+            _ => op.int("num_in"),
+        };
+        let mut module = json!({
+            "name": name,
+            "style": op.string("style"),
+            "args": self.names(&names),
+            "types": types,
+            "num_in": num_in,
+            "ret_types": [],
+            "return_vals": [],
+        });
+        let (mut units, mut instances, mut inits) = (vec![], vec![], vec![]);
+        let (mut graph, mut sinks) = (vec![], vec![]);
+        for op in op.ops(0) {
+            match op.name.as_str() {
+                "hec.primitive" => {
+                    let types = op.outputs.iter().map(|ty| lower_type(ty));
+                    units.push(json!({
+                        "op_type": op.string("primitiveName"),
+                        "name": op.string("instanceName"),
+                        "types": types.collect::<Vec<_>>(),
+                    }));
+                }
+                "hec.instance" => instances.push(json!({
+                    "instance_name": op.string("instanceName"),
+                    "module_name": op.string("moduleName"),
+                    "names": [],
+                })),
+                "hec.init" => inits.push(json!({
+                    "dst": self.name(op.operand(0)?),
+                    "src": self.name(op.operand(1)?),
+                })),
+                "hec.stateset" => {
+                    let states = op.ops(0).map(|state| self.hec_state(state));
+                    module["states"] = Value::Array(states.collect::<Result<_, _>>()?);
+                    let initial = op.ops(0).find(|state| state.attr("initial").is_some());
+                    let initial = initial.or(op.ops(0).next());
+                    module["init_state"] =
+                        json!(initial.and_then(|state| state.string("sym_name")));
+                }
+                "hec.stageset" => {
+                    let mut stages = vec![];
+                    for stage in op.ops(0) {
+                        stages.push(json!({
+                            "stage": stage.string("sym_name"),
+                            "ops": self.hec_ops(stage.ops(0))?,
+                        }));
+                    }
+                    module["stages"] = Value::Array(stages);
+                }
+                "hec.sink" => sinks.extend(op.operands.iter().map(|sink| self.name(sink))),
+                "hec.assign" => graph.push(self.hec_op(op)?),
+                _ if is_constant(op) => (),
+                _ => {
+                    return Err(op.error(
+                        ErrorCode::UnknownOperation,
+                        "unexpected operation in component".to_string(),
+                    ))
+                }
+            }
+        }
+        module["units"] = Value::Array(units);
+        module["instances"] = Value::Array(instances);
+        match op.string("style") {
+            Some("pipeline") => {
+                let pipeline = op.string("pipeline_style").or(op.string("pipeline"));
+                module["pipeline_style"] = json!(pipeline);
+                if pipeline == Some("for") {
+                    module["ii"] = json!(op.int("ii").or(op.int("II")));
+                    module["inits"] = Value::Array(inits);
+                    module["wires"] = json!({"name": "i"});
+                }
+            }
+            Some("handshake") => {
+                module["graph"] = Value::Array(graph);
+                module["sinks"] = Value::Array(sinks);
+            }
+            _ => (),
+        }
+        Ok(module)
+    }
+
+    fn hec_design(&mut self, design: &Op) -> Result<Value, HestiaError> {
+        let (mut memory, mut stream, mut modules) = (vec![], vec![], vec![]);
+        for op in design.ops(0) {
+            match op.name.as_str() {
+                "hec.memory" => memory.push(self.memory(op)?),
+                "hec.stream" => stream.push(self.stream(op)?),
+                "hec.component" => modules.push(self.hec_component(op)?),
+                _ if is_constant(op) => self.constant(op, None)?,
+                _ => {
+                    return Err(op.error(
+                        ErrorCode::UnknownOperation,
+                        "unexpected operation in design".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(json!({
+            "level": "hec",
+            "memory": memory,
+            "stream": stream,
+            "constants": self.constants,
+            "modules": modules,
+        }))
+    }
+}
+
+/// The inputs and outputs of a function type attribute.
+fn function_type(text: &str) -> Result<(Vec<String>, Vec<String>), HestiaError> {
+    let mut parser = Parser { text, pos: 0 };
+    parser.function_type()
+}
+
+fn find_design(ops: &[Op]) -> Option<&Op> {
+    for op in ops {
+        if op.name == "tor.design" || op.name == "hec.design" {
+            return Some(op);
+        }
+        let blocks = op.regions.iter().flatten();
+        if let Some(design) = blocks.filter_map(|block| find_design(&block.ops)).next() {
+            return Some(design);
+        }
+    }
+    None
+}
+
+/// Read a design of the tor or hec dialect from MLIR text in the generic
+/// operation form, as printed with `--mlir-print-op-generic`, into the same
+/// JSON the Hector flow exports.
+pub fn read(text: &str) -> Result<(State, Value), HestiaError> {
+    let mut parser = Parser { text, pos: 0 };
+    let ops = parser.parse()?;
+    let Some(design) = find_design(&ops) else {
+        return Err(HestiaError::new(
+            ErrorCode::UnknownLevel,
+            "no tor.design or hec.design operation".to_string(),
+        ));
+    };
+    let mut lowering = Lowering::default();
+    match design.name.as_str() {
+        "tor.design" => Ok((State::ToR, lowering.tor_design(design)?)),
+        _ => Ok((State::HEC, lowering.hec_design(design)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basetype::Computation;
+
+    fn lowered(name: &str) -> String {
+        lowered_op(&format!(
+            "%2 = \"arith.{}\"(%0, %1) : (i32, i32) -> i32",
+            name
+        ))
+    }
+
+    fn lowered_op(text: &str) -> String {
+        let ops = Parser { text, pos: 0 }.parse().unwrap();
+        operation_type(&ops[0]).unwrap()
+    }
+
+    #[test]
+    fn signedness_of_operations() {
+        for (name, op_type) in [
+            ("divsi", "divsi"),
+            ("divui", "divui"),
+            ("divf", "div"),
+            ("remsi", "rem"),
+            ("remui", "remui"),
+            ("minsi", "min"),
+            ("minui", "minui"),
+            ("maxsi", "max"),
+            ("maxui", "maxui"),
+            ("shrsi", "shrsi"),
+            ("shrui", "shrui"),
+        ] {
+            assert_eq!(lowered(name), op_type, "{}", name);
+        }
+    }
+
+    #[test]
+    fn comparisons_load() {
+        for (name, predicates) in [("cmpi", 10), ("cmpf", 16)] {
+            for index in 0..predicates {
+                let text = format!(
+                    "%2 = \"arith.{}\"(%0, %1) {{predicate = {} : i64}} : (i32, i32) -> i1",
+                    name, index
+                );
+                let op_type = lowered_op(&text);
+                let ir = json!({"name": "%2", "op_type": op_type, "type": "bool", "operands": ["%0", "%1"]});
+                assert!(Computation::new(&ir).is_ok(), "{} {}", name, op_type);
+            }
+        }
+        assert_eq!(
+            lowered_op("%2 = \"arith.cmpi\"(%0, %1) {predicate = 6 : i64} : (i32, i32) -> i1"),
+            "cmp_ult"
+        );
+    }
+}
@@ -1,8 +1,44 @@
+//! The hestia simulator: software, ToR and HEC interpreters with the
+//! breakpoints, watchpoints and cosimulation the shell is built on. Tools that
+//! embed it start from [`simulator::Simulator`].
+
+// Shadow the std printing macros so that all output can be copied to the
+// `log` file, see `common::tee`.
+macro_rules! println {
+    () => {
+        $crate::common::tee(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::common::tee(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::common::tee(format_args!($($arg)*))
+    };
+}
+
 pub mod basetype;
+pub mod bench;
 pub mod common;
+pub mod decompress;
 pub mod elastic;
 pub mod env;
 pub mod equal;
+pub mod error;
 pub mod hec;
+pub mod intern;
+pub mod json5;
+pub mod logging;
+pub mod mlir;
+pub mod perfetto;
+pub mod profile;
+pub mod rtl;
+pub mod schema;
+pub mod simulator;
 pub mod software;
+pub mod sweep;
 pub mod tor;
+pub mod vcd;
+pub mod yaml;
@@ -0,0 +1,269 @@
+use super::common::{fail, Failure};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+#[derive(Debug)]
+struct Track {
+    process: usize,
+    thread: usize,
+    /// The label of the slice in progress.
+    open: Option<String>,
+}
+
+/// A trace streamed to its file in the JSON array format of Chrome traces.
+/// Events are written as they are made. `flush` ends the slices in progress
+/// and closes the array so that the file is complete between runs, and the
+/// next event is written over that ending.
+#[derive(Debug)]
+struct Trace {
+    path: String,
+    file: BufWriter<File>,
+    /// Events written so far
+    written: usize,
+    /// The length of the file and the events written before `flush` ended it
+    ended: Option<(u64, usize)>,
+    processes: BTreeMap<String, usize>,
+    tracks: BTreeMap<String, Track>,
+    active: HashMap<String, String>,
+    instances: HashMap<usize, (String, String)>,
+    time: usize,
+}
+
+thread_local! {
+    /// The Chrome trace being recorded. Time is in cycles, shown as
+    /// microseconds.
+    static TRACE: RefCell<Option<Trace>> = const { RefCell::new(None) };
+}
+
+/// Run `f` on the trace being recorded, None if there is none. A trace that
+/// cannot be written is reported and stopped.
+fn with_trace<R>(f: impl FnOnce(&mut Trace) -> std::io::Result<R>) -> Option<R> {
+    TRACE.with_borrow_mut(|trace| {
+        let recording = trace.as_mut()?;
+        match f(recording) {
+            Ok(result) => Some(result),
+            Err(_) => {
+                println!("!!Failed to write file {}", recording.path);
+                fail(Failure::Command);
+                *trace = None;
+                None
+            }
+        }
+    })
+}
+
+impl Trace {
+    fn write(&mut self, event: Value) -> std::io::Result<()> {
+        if let Some((length, written)) = self.ended.take() {
+            self.file.flush()?;
+            let file = self.file.get_mut();
+            file.set_len(length)?;
+            file.seek(SeekFrom::End(0))?;
+            self.written = written;
+        }
+        if self.written > 0 {
+            self.file.write_all(b",\n")?;
+        }
+        serde_json::to_writer(&mut self.file, &event)?;
+        self.written += 1;
+        Ok(())
+    }
+}
+
+/// Start recording instance activity as a Chrome trace for the Perfetto UI to
+/// `path`, or stop with `off`. A trace already in progress is written out
+/// first.
+pub fn dump_perfetto(path: &str) {
+    flush();
+    if path == "off" {
+        TRACE.set(None);
+        return;
+    }
+    let file = File::create(path).and_then(|file| {
+        let mut file = BufWriter::new(file);
+        file.write_all(b"[\n")?;
+        Ok(file)
+    });
+    let Ok(file) = file else {
+        println!("!!Failed to write file {}", path);
+        fail(Failure::Command);
+        return;
+    };
+    TRACE.set(Some(Trace {
+        path: path.to_string(),
+        file,
+        written: 0,
+        ended: None,
+        processes: BTreeMap::new(),
+        tracks: BTreeMap::new(),
+        active: HashMap::new(),
+        instances: HashMap::new(),
+        time: 0,
+    }));
+}
+
+pub fn perfetto_enabled() -> bool {
+    TRACE.with_borrow(Option::is_some)
+}
+
+/// Mark the track `name` of `process`, such as an instance or a pipeline
+/// stage, busy in the current cycle with `label`, such as its state.
+pub fn activity(process: &str, name: String, label: String) {
+    with_trace(|trace| {
+        if !trace.tracks.contains_key(&name) {
+            let pid = match trace.processes.get(process) {
+                Some(&pid) => pid,
+                None => {
+                    let pid = trace.processes.len() + 1;
+                    trace.processes.insert(process.to_string(), pid);
+                    trace.write(json!({
+                        "name": "process_name", "ph": "M", "pid": pid, "args": {"name": process}
+                    }))?;
+                    pid
+                }
+            };
+            let tid = trace.tracks.len() + 1;
+            trace.tracks.insert(
+                name.clone(),
+                Track {
+                    process: pid,
+                    thread: tid,
+                    open: None,
+                },
+            );
+            trace.write(json!({
+                "name": "thread_name", "ph": "M", "pid": pid, "tid": tid, "args": {"name": name}
+            }))?;
+        }
+        trace.active.insert(name, label);
+        Ok(())
+    });
+}
+
+/// The track name of the instance at `key` running `function`: the function
+/// name, numbered from the second instance of it seen.
+pub fn instance_track(key: usize, function: &str) -> String {
+    with_trace(|trace| {
+        if let Some((known, name)) = trace.instances.get(&key) {
+            if known == function {
+                return Ok(name.clone());
+            }
+        }
+        let count = trace
+            .instances
+            .values()
+            .filter(|(known, _)| known == function)
+            .count();
+        let name = match count {
+            0 => function.to_string(),
+            count => format!("{} #{}", function, count + 1),
+        };
+        trace
+            .instances
+            .insert(key, (function.to_string(), name.clone()));
+        Ok(name)
+    })
+    .unwrap_or_else(|| function.to_string())
+}
+
+fn event(phase: &str, label: &str, time: usize, track: &Track) -> Value {
+    json!({"name": label, "ph": phase, "ts": time, "pid": track.process, "tid": track.thread})
+}
+
+/// Close the current cycle after `cycles` cycles: a track ends its slice when
+/// it goes idle or changes label, and begins one when it becomes busy.
+pub fn end_cycles(cycles: usize) {
+    with_trace(|trace| {
+        let mut events = vec![];
+        for (name, track) in trace.tracks.iter_mut() {
+            let label = trace.active.get(name);
+            if let Some(open) = &track.open {
+                if label == Some(open) {
+                    continue;
+                }
+                events.push(event("E", open, trace.time, track));
+                track.open = None;
+            }
+            if let Some(label) = label {
+                events.push(event("B", label, trace.time, track));
+                track.open = Some(label.clone());
+            }
+        }
+        for event in events {
+            trace.write(event)?;
+        }
+        trace.active.clear();
+        trace.time += cycles;
+        Ok(())
+    });
+}
+
+/// End the slices in progress and close the array, so that the trace can be
+/// opened between runs.
+pub fn flush() {
+    with_trace(|trace| {
+        if trace.ended.is_some() {
+            return Ok(());
+        }
+        trace.file.flush()?;
+        let length = trace.file.get_mut().seek(SeekFrom::End(0))?;
+        let written = trace.written;
+        let ends: Vec<Value> = trace
+            .tracks
+            .values()
+            .filter_map(|track| {
+                let label = track.open.as_ref()?;
+                Some(event("E", label, trace.time, track))
+            })
+            .collect();
+        for end in ends {
+            trace.write(end)?;
+        }
+        trace.file.write_all(b"\n]\n")?;
+        trace.file.flush()?;
+        trace.ended = Some((length, written));
+        Ok(())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_between_runs() {
+        let path = std::env::temp_dir().join(format!("hestia-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        dump_perfetto(path);
+        activity("tor", "main".to_string(), "@1".to_string());
+        end_cycles(2);
+        flush();
+        let events = |path| -> Vec<Value> {
+            let text = std::fs::read_to_string(path).unwrap();
+            serde_json::from_str(&text).unwrap()
+        };
+        let first = events(path);
+        assert_eq!(first.len(), 4);
+        assert_eq!(
+            first[3],
+            json!({"name": "@1", "ph": "E", "pid": 1, "tid": 1, "ts": 2})
+        );
+        activity("tor", "main".to_string(), "@1".to_string());
+        end_cycles(1);
+        end_cycles(1);
+        flush();
+        dump_perfetto("off");
+        let second = events(path);
+        std::fs::remove_file(path).unwrap();
+        // The slice goes on across the runs
+        assert_eq!(second.len(), 4);
+        assert_eq!(second[..3], first[..3]);
+        assert_eq!(
+            second[3],
+            json!({"name": "@1", "ph": "E", "pid": 1, "tid": 1, "ts": 3})
+        );
+    }
+}
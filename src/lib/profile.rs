@@ -0,0 +1,157 @@
+use super::common::{fail, json_output, print_json, print_table, Failure};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Calls of one function of the simulator on one op, unit or module, and the
+/// wall time they took, including what they called.
+#[derive(Debug, Default, Clone, Copy)]
+struct Entry {
+    calls: u64,
+    time: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Profile {
+    /// Entries by what ran, such as `compute` or `propagate`, and on what.
+    entries: HashMap<&'static str, HashMap<String, Entry>>,
+    /// The time spent stepping while profiling.
+    total: Duration,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Locked because HEC regions settle on several threads.
+static PROFILE: Mutex<Option<Profile>> = Mutex::new(None);
+
+pub fn profile_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The start of something to time, None while not profiling.
+pub fn timer() -> Option<Instant> {
+    profile_enabled().then(Instant::now)
+}
+
+/// Charge the time since `timer` to `name` under `kind`, such as a unit
+/// under `propagate`.
+pub fn charge(timer: Option<Instant>, kind: &'static str, name: &str) {
+    let Some(start) = timer else {
+        return;
+    };
+    let time = start.elapsed();
+    if let Some(profile) = PROFILE.lock().unwrap().as_mut() {
+        let entries = profile.entries.entry(kind).or_default();
+        let entry = match entries.get_mut(name) {
+            Some(entry) => entry,
+            None => entries.entry(name.to_string()).or_default(),
+        };
+        entry.calls += 1;
+        entry.time += time;
+    }
+}
+
+/// Run `f`, charging its time to `name` under `kind` while profiling.
+pub fn measure<T>(kind: &'static str, name: &str, f: impl FnOnce() -> T) -> T {
+    let timer = timer();
+    let result = f();
+    charge(timer, kind, name);
+    result
+}
+
+/// Add the time of a whole step, which the report is a share of.
+pub fn add_total(time: Duration) {
+    if !profile_enabled() {
+        return;
+    }
+    if let Some(profile) = PROFILE.lock().unwrap().as_mut() {
+        profile.total += time;
+    }
+}
+
+/// `profile on|off|report [count]`: collect, stop collecting or show where
+/// the simulation spends its time. Turning it on starts afresh.
+pub fn profile(args: &[&str]) {
+    match args[0] {
+        "on" => {
+            *PROFILE.lock().unwrap() = Some(Profile::default());
+            ENABLED.store(true, Ordering::Relaxed);
+        }
+        "off" => ENABLED.store(false, Ordering::Relaxed),
+        "report" => {
+            let count = match args.get(1).map(|count| count.parse::<usize>()) {
+                None => 20,
+                Some(Ok(count)) => count,
+                Some(Err(_)) => {
+                    println!("!!Invalid count {}", args[1]);
+                    fail(Failure::Command);
+                    return;
+                }
+            };
+            report(count);
+        }
+        mode => {
+            println!("!!Unknown profile mode: {}", mode);
+            fail(Failure::Command);
+        }
+    }
+}
+
+/// The `count` entries that took the longest, with their calls, time, time
+/// per call and share of the stepping time.
+fn report(count: usize) {
+    let profile = PROFILE.lock().unwrap();
+    let Some(profile) = profile.as_ref() else {
+        println!("!!No profile, start one with profile on");
+        fail(Failure::Command);
+        return;
+    };
+    let mut entries: Vec<(&str, &String, &Entry)> = profile
+        .entries
+        .iter()
+        .flat_map(|(kind, entries)| {
+            entries
+                .iter()
+                .map(move |(name, entry)| (*kind, name, entry))
+        })
+        .collect();
+    entries.sort_by(|x, y| y.2.time.cmp(&x.2.time).then((x.0, x.1).cmp(&(y.0, y.1))));
+    entries.truncate(count);
+    let share = |time: Duration| match profile.total.is_zero() {
+        true => 0.0,
+        false => 100.0 * time.as_secs_f64() / profile.total.as_secs_f64(),
+    };
+    if json_output() {
+        let entries = entries.iter().map(|(kind, name, entry)| {
+            json!({
+                "kind": kind,
+                "name": name,
+                "calls": entry.calls,
+                "seconds": entry.time.as_secs_f64(),
+                "percent": share(entry.time),
+            })
+        });
+        return print_json(json!({
+            "seconds": profile.total.as_secs_f64(),
+            "entries": entries.collect::<Vec<Value>>(),
+        }));
+    }
+    println!("!!PROFILE: {:.3}s stepping", profile.total.as_secs_f64());
+    let rows = entries
+        .iter()
+        .map(|(kind, name, entry)| {
+            (
+                format!("{} {}", kind, name),
+                format!(
+                    "{} calls, {:.3}ms, {:.0}ns per call, {:.1}%",
+                    entry.calls,
+                    entry.time.as_secs_f64() * 1e3,
+                    entry.time.as_nanos() as f64 / entry.calls as f64,
+                    share(entry.time)
+                ),
+            )
+        })
+        .collect::<Vec<_>>();
+    print_table("\t", &rows);
+}
@@ -0,0 +1,337 @@
+use super::basetype::{bit_width, HandshakeValue, VALUE};
+use super::common::{cycle_count, fail, paint, Color, Failure, State};
+use super::env::HandshakeEnv;
+use super::hec;
+use multimap::MultiMap;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Write};
+use std::iter::zip;
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+
+trait Channel: std::io::Read + Write {}
+
+impl<T: std::io::Read + Write> Channel for T {}
+
+/// A connection to an RTL simulation, such as a Verilator testbench, of the
+/// top-level module of the dynamic HEC design.
+struct Bridge {
+    address: String,
+    reader: BufReader<Box<dyn Channel>>,
+    writer: Box<dyn Channel>,
+    cycles: usize,
+    mismatches: usize,
+}
+
+thread_local! {
+    /// The RTL simulation driven by the HEC model of this thread.
+    static RTL: RefCell<Option<Bridge>> = const { RefCell::new(None) };
+}
+
+fn connect(address: &str) -> std::io::Result<(Box<dyn Channel>, Box<dyn Channel>)> {
+    Ok(match address.strip_prefix("unix:") {
+        Some(path) => {
+            let stream = UnixStream::connect(path)?;
+            (Box::new(stream.try_clone()?), Box::new(stream))
+        }
+        None => {
+            let stream = TcpStream::connect(address)?;
+            stream.set_nodelay(true)?;
+            (Box::new(stream.try_clone()?), Box::new(stream))
+        }
+    })
+}
+
+impl Bridge {
+    fn send(&mut self, message: Value) -> std::io::Result<Value> {
+        writeln!(self.writer, "{}", message)?;
+        self.writer.flush()?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        serde_json::from_str(&line).map_err(std::io::Error::other)
+    }
+}
+
+/// The top-level ports of one direction with the unit ports wired to them,
+/// in name order.
+fn top_ports(ports: &MultiMap<String, String>) -> Vec<(String, Vec<String>)> {
+    let mut ports: Vec<(String, Vec<String>)> = ports
+        .iter_all()
+        .map(|(port, units)| (port.clone(), units.clone()))
+        .collect();
+    ports.sort();
+    ports
+}
+
+/// The value at a top-level port as the unit ports wired to it hold it. An
+/// input fanned out to several units is ready once all of them are.
+fn port_value(handshake: &mut HandshakeEnv, units: &[String]) -> HandshakeValue {
+    let mut value: Option<HandshakeValue> = None;
+    for port in units {
+        let (unit, port) = port.split_once('.').unwrap();
+        let unit_value = handshake.env.get_mut(unit).unwrap().get_value(port);
+        match &mut value {
+            Some(value) => value.ready &= unit_value.ready,
+            None => value = Some(unit_value),
+        }
+    }
+    value.unwrap_or_else(HandshakeValue::empty)
+}
+
+/// The values at the top-level `ports`.
+fn port_values(
+    handshake: &mut HandshakeEnv,
+    ports: Vec<(String, Vec<String>)>,
+) -> Vec<(String, HandshakeValue)> {
+    ports
+        .into_iter()
+        .map(|(port, units)| {
+            let value = port_value(handshake, &units);
+            (port, value)
+        })
+        .collect()
+}
+
+/// The scalars a compound value travels as, the lanes of a vector or the
+/// real and imaginary parts of a complex number.
+fn parts(value: VALUE) -> Option<Vec<VALUE>> {
+    match value {
+        VALUE::Vector(vector) => Some((0..vector.lanes()).map(|i| vector.lane(i)).collect()),
+        VALUE::C32(x) => Some(vec![VALUE::F32(x.re), VALUE::F32(x.im)]),
+        VALUE::C64(x) => Some(vec![VALUE::F64(x.re), VALUE::F64(x.im)]),
+        _ => None,
+    }
+}
+
+fn data_json(value: VALUE) -> Value {
+    if let Some(parts) = parts(value) {
+        return Value::Array(parts.into_iter().map(data_json).collect());
+    }
+    match value.to_bits() {
+        Some(bits) => json!(bits),
+        None => Value::Null,
+    }
+}
+
+/// Whether the bits the RTL reports match a value, compared at its width,
+/// and part by part for a vector or complex number. Control tokens match
+/// anything, X only null, and ERROR nothing.
+fn bits_match(value: VALUE, rtl: &Value) -> bool {
+    if let Some(parts) = parts(value) {
+        return rtl.as_array().is_some_and(|rtl| {
+            rtl.len() == parts.len() && zip(parts, rtl).all(|(part, rtl)| bits_match(part, rtl))
+        });
+    }
+    match value {
+        VALUE::Control => return true,
+        VALUE::X => return rtl.is_null(),
+        _ => (),
+    }
+    let Some(bits) = value.to_bits() else {
+        return false;
+    };
+    let width = bit_width(&value.get_type());
+    let mask = if width == 64 {
+        u64::MAX
+    } else {
+        (1 << width) - 1
+    };
+    rtl.as_u64().is_some_and(|rtl| rtl & mask == bits)
+}
+
+/// `rtl_connect <address>|off`: drive an RTL simulation of the dynamic HEC
+/// design listening on a TCP address or `unix:<path>` in lockstep with the
+/// model, and check its outputs and memories every cycle. The protocol is one
+/// line of JSON each way per cycle, see the README.
+pub fn rtl_connect(address: &str) {
+    if address == "off" {
+        rtl_disconnect();
+        return;
+    }
+    let names = |ports: &MultiMap<String, String>| -> Vec<String> {
+        top_ports(ports).into_iter().map(|(port, _)| port).collect()
+    };
+    let Some((inputs, outputs)) =
+        hec::with_top_dynamic(|handshake| (names(&handshake.inputs), names(&handshake.outputs)))
+    else {
+        println!("!!Load a dynamic HEC design first");
+        fail(Failure::Command);
+        return;
+    };
+    let hello = json!({
+        "hestia": 1,
+        "inputs": inputs,
+        "outputs": outputs,
+        "memories": hec::memory_names(),
+    });
+    rtl_disconnect();
+    let bridge = connect(address).and_then(|(reader, writer)| {
+        let mut bridge = Bridge {
+            address: address.to_string(),
+            reader: BufReader::new(reader),
+            writer,
+            cycles: 0,
+            mismatches: 0,
+        };
+        bridge.send(hello)?;
+        Ok(bridge)
+    });
+    match bridge {
+        Ok(bridge) => {
+            println!("Connected to the RTL simulation at {}", address);
+            RTL.set(Some(bridge));
+        }
+        Err(err) => {
+            println!("!!Failed to connect to {}: {}", address, err);
+            fail(Failure::Command);
+        }
+    }
+}
+
+fn rtl_disconnect() {
+    if let Some(bridge) = RTL.take() {
+        println!(
+            "Compared {} cycles with {}, {} mismatches",
+            bridge.cycles, bridge.address, bridge.mismatches
+        );
+    }
+}
+
+fn mismatch(bridge: &mut Bridge, cycle: usize, signal: &str, hestia: String, rtl: &Value) {
+    let message = format!(
+        "!!RTL Mismatch: {} at cycle {}: hestia {}, rtl {}",
+        signal, cycle, hestia, rtl
+    );
+    println!("{}", paint(Color::Red, message));
+    bridge.mismatches += 1;
+    fail(Failure::Mismatch);
+}
+
+/// Whether an RTL simulation runs in lockstep with the HEC model.
+pub fn rtl_connected() -> bool {
+    RTL.with_borrow(Option::is_some)
+}
+
+/// Send the inputs of the cycle the handshake network has settled on, the
+/// valid and data of the input ports and the ready of the output ports, and
+/// check the outputs and memories the RTL replies with. False on a mismatch
+/// or when the connection is lost.
+pub fn exchange() -> bool {
+    let Some(mut bridge) = RTL.take() else {
+        return true;
+    };
+    let cycle = cycle_count(State::HEC);
+    let (inputs, outputs) = hec::with_top_dynamic(|handshake| {
+        let inputs = top_ports(&handshake.inputs);
+        let outputs = top_ports(&handshake.outputs);
+        (
+            port_values(handshake, inputs),
+            port_values(handshake, outputs),
+        )
+    })
+    .unwrap_or_default();
+    let mut driven = Map::new();
+    for (port, value) in &inputs {
+        driven.insert(
+            port.clone(),
+            json!({"valid": value.valid, "data": data_json(value.data)}),
+        );
+    }
+    for (port, value) in &outputs {
+        driven.insert(port.clone(), json!({"ready": value.ready}));
+    }
+    let reply = match bridge.send(json!({"cycle": cycle, "inputs": driven})) {
+        Ok(reply) => reply,
+        Err(err) => {
+            println!("!!Lost the RTL simulation at {}: {}", bridge.address, err);
+            fail(Failure::Command);
+            return false;
+        }
+    };
+    bridge.cycles += 1;
+    let before = bridge.mismatches;
+    let rtl_ports = reply.get("outputs").and_then(Value::as_object);
+    for (port, value) in inputs.iter().chain(&outputs) {
+        let Some(rtl) = rtl_ports.and_then(|ports| ports.get(port)) else {
+            continue;
+        };
+        let is_input = inputs.iter().any(|(input, _)| input == port);
+        if is_input {
+            if let Some(ready) = rtl.get("ready") {
+                if ready.as_bool() != Some(value.ready) {
+                    let signal = format!("{}.ready", port);
+                    mismatch(&mut bridge, cycle, &signal, value.ready.to_string(), ready);
+                }
+            }
+            continue;
+        }
+        if let Some(valid) = rtl.get("valid") {
+            if valid.as_bool() != Some(value.valid) {
+                let signal = format!("{}.valid", port);
+                mismatch(&mut bridge, cycle, &signal, value.valid.to_string(), valid);
+            }
+        }
+        if let Some(data) = rtl.get("data").filter(|_| value.valid) {
+            if !bits_match(value.data, data) {
+                let signal = format!("{}.data", port);
+                mismatch(
+                    &mut bridge,
+                    cycle,
+                    &signal,
+                    format!("{:?}", value.data),
+                    data,
+                );
+            }
+        }
+    }
+    let rtl_memories = reply.get("memories").and_then(Value::as_object);
+    for (memory, words) in rtl_memories.into_iter().flatten() {
+        let Some(store) = hec::memory_store(memory) else {
+            let signal = format!("memory {}", memory);
+            mismatch(&mut bridge, cycle, &signal, "none".to_string(), words);
+            continue;
+        };
+        for word in words.as_array().into_iter().flatten() {
+            let (Some(address), Some(data)) = (word.get(0).and_then(Value::as_u64), word.get(1))
+            else {
+                continue;
+            };
+            let value = store.get(address as usize).unwrap_or(VALUE::ERROR);
+            if !bits_match(value, data) {
+                let signal = format!("{}[{}]", memory, address);
+                mismatch(&mut bridge, cycle, &signal, format!("{:?}", value), data);
+            }
+        }
+    }
+    let matched = bridge.mismatches == before;
+    RTL.set(Some(bridge));
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basetype::{Complex, DataType, Vector};
+
+    #[test]
+    fn compound_values_compare_by_part() {
+        let vector = Vector::from_lanes(DataType::I8, &[VALUE::I8(-1), VALUE::I8(2)]);
+        assert_eq!(data_json(vector), json!([255, 2]));
+        assert!(bits_match(vector, &json!([255, 2])));
+        assert!(!bits_match(vector, &json!([255, 3])));
+        assert!(!bits_match(vector, &json!([255])));
+        assert!(!bits_match(vector, &json!(255)));
+        let complex = VALUE::C32(Complex { re: 1.5, im: -2.0 });
+        let bits = json!([1.5f32.to_bits(), (-2.0f32).to_bits()]);
+        assert_eq!(data_json(complex), bits);
+        assert!(bits_match(complex, &bits));
+        assert!(!bits_match(complex, &json!([1.5f32.to_bits(), 0])));
+        assert!(bits_match(VALUE::X, &Value::Null));
+        assert!(!bits_match(VALUE::X, &json!(0)));
+        assert!(!bits_match(VALUE::ERROR, &json!(0)));
+        assert!(bits_match(VALUE::Control, &json!(0)));
+    }
+}
@@ -0,0 +1,441 @@
+use super::common::State;
+use super::error::{describe, ErrorCode, HestiaError};
+use serde_json::Value;
+
+/// The JSON type a field of the IR must have.
+#[derive(Clone, Copy)]
+enum Kind {
+    Str,
+    Int,
+    Bool,
+    Strs,
+    Object(&'static [Field]),
+    Objects(&'static [Field]),
+    /// Objects whose fields depend on their string field `key`, such as
+    /// operations on `op_type`
+    Tagged(&'static str, fn(&str, &Value) -> &'static [Field]),
+}
+
+struct Field {
+    key: &'static str,
+    kind: Kind,
+    required: bool,
+}
+
+const fn req(key: &'static str, kind: Kind) -> Field {
+    Field {
+        key,
+        kind,
+        required: true,
+    }
+}
+
+const fn opt(key: &'static str, kind: Kind) -> Field {
+    Field {
+        key,
+        kind,
+        required: false,
+    }
+}
+
+use Kind::*;
+
+const MEMORY: &[Field] = &[
+    req("name", Str),
+    req("size", Int),
+    req("type", Str),
+    opt("backing", Str),
+];
+const CONSTANT: &[Field] = &[req("name", Str), req("operands", Str), req("type", Str)];
+const COMPUTE: &[Field] = &[req("name", Str), req("type", Str), req("operands", Strs)];
+const OPERANDS: &[Field] = &[req("operands", Strs)];
+const CALL: &[Field] = &[
+    req("names", Strs),
+    req("function", Str),
+    req("operands", Strs),
+];
+
+const SOFTWARE: &[Field] = &[
+    opt("memory", Objects(MEMORY)),
+    opt("modules", Objects(SOFTWARE_MODULE)),
+];
+const SOFTWARE_MODULE: &[Field] = &[
+    req("name", Str),
+    req("args", Strs),
+    req("types", Strs),
+    req("body", SOFTWARE_BODY),
+];
+const SOFTWARE_BODY: Kind = Tagged("op_type", software_op);
+const SOFTWARE_FOR: &[Field] = &[
+    req("names", Strs),
+    req("iter_args", Strs),
+    req("iter_inits", Strs),
+    req("iter_name", Str),
+    req("lb", Str),
+    req("ub", Str),
+    req("step", Str),
+    req("body", SOFTWARE_BODY),
+];
+const SOFTWARE_IF: &[Field] = &[
+    req("condition", Str),
+    req("names", Strs),
+    req("body0", SOFTWARE_BODY),
+    req("body1", SOFTWARE_BODY),
+];
+const SOFTWARE_LOAD: &[Field] = &[req("name", Str), req("index", Str), req("memory", Str)];
+const SOFTWARE_STORE: &[Field] = &[req("value", Str), req("index", Str), req("memory", Str)];
+
+fn software_op(op_type: &str, _: &Value) -> &'static [Field] {
+    match op_type {
+        "for" => SOFTWARE_FOR,
+        "if" => SOFTWARE_IF,
+        "return" | "yield" => OPERANDS,
+        "load" => SOFTWARE_LOAD,
+        "store" => SOFTWARE_STORE,
+        "call" => CALL,
+        _ => COMPUTE,
+    }
+}
+
+const TOR: &[Field] = &[
+    opt("memory", Objects(MEMORY)),
+    opt("stream", Objects(&[req("name", Str), req("depth", Int)])),
+    opt("constants", Objects(CONSTANT)),
+    opt("modules", Tagged("strategy", tor_module)),
+];
+const TOR_GRAPH: &[Field] = &[
+    req("start", Int),
+    req("end", Int),
+    req(
+        "edge",
+        Objects(&[req("from", Int), req("to", Int), req("type", Str)]),
+    ),
+];
+const TOR_MODULE: &[Field] = &[
+    req("name", Str),
+    req("args", Strs),
+    req("types", Strs),
+    req("graph", Object(TOR_GRAPH)),
+    req("body", TOR_BODY),
+];
+const TOR_DYNAMIC: &[Field] = &[req("name", Str), req("args", Strs), req("types", Strs)];
+const TOR_DATAFLOW: &[Field] = &[
+    req("name", Str),
+    req("args", Strs),
+    req("types", Strs),
+    req("ret_types", Strs),
+    req("body", Tagged("op_type", dataflow_op)),
+];
+const TOR_BODY: Kind = Tagged("op_type", tor_op);
+const TOR_FOR: &[Field] = &[
+    req("start", Int),
+    req("end", Int),
+    req("names", Strs),
+    req("iter_args", Strs),
+    req("iter_inits", Strs),
+    req("iter_name", Str),
+    req("lb", Str),
+    req("ub", Str),
+    req("step", Str),
+    req("body", TOR_BODY),
+];
+const TOR_IF: &[Field] = &[
+    req("start", Int),
+    req("end", Int),
+    req("condition", Str),
+    req("names", Strs),
+    req("body0", TOR_BODY),
+    req("body1", TOR_BODY),
+];
+const TOR_RETURN: &[Field] = &[req("time", Int), req("operands", Strs)];
+const TOR_YIELD: &[Field] = &[req("time", Int), req("operands", Strs), opt("jump", Int)];
+const TOR_CALL: &[Field] = &[
+    req("start", Int),
+    req("names", Strs),
+    req("function", Str),
+    req("operands", Strs),
+];
+const TOR_LOAD: &[Field] = &[
+    req("start", Int),
+    req("end", Int),
+    req("name", Str),
+    req("index", Str),
+    req("memory", Str),
+];
+const TOR_STORE: &[Field] = &[
+    req("start", Int),
+    req("end", Int),
+    req("value", Str),
+    req("index", Str),
+    req("memory", Str),
+];
+const TOR_READ: &[Field] = &[
+    req("start", Int),
+    req("end", Int),
+    req("name", Str),
+    req("stream", Str),
+];
+const TOR_WRITE: &[Field] = &[
+    req("start", Int),
+    req("end", Int),
+    req("data", Str),
+    req("stream", Str),
+];
+const TOR_COMPUTE: &[Field] = &[
+    req("start", Int),
+    req("end", Int),
+    req("name", Str),
+    req("type", Str),
+    req("operands", Strs),
+];
+
+fn tor_module(strategy: &str, _: &Value) -> &'static [Field] {
+    match strategy {
+        "dynamic" => TOR_DYNAMIC,
+        "dataflow" => TOR_DATAFLOW,
+        _ => TOR_MODULE,
+    }
+}
+
+fn tor_op(op_type: &str, _: &Value) -> &'static [Field] {
+    match op_type {
+        "for" => TOR_FOR,
+        "if" => TOR_IF,
+        "return" => TOR_RETURN,
+        "yield" => TOR_YIELD,
+        "call" => TOR_CALL,
+        "load" => TOR_LOAD,
+        "store" => TOR_STORE,
+        "read" => TOR_READ,
+        "write" => TOR_WRITE,
+        _ => TOR_COMPUTE,
+    }
+}
+
+/// Only the callees of a dataflow function are read.
+const DATAFLOW_CALL: &[Field] = &[req("function", Str)];
+
+fn dataflow_op(op_type: &str, _: &Value) -> &'static [Field] {
+    match op_type {
+        "call" => DATAFLOW_CALL,
+        _ => &[],
+    }
+}
+
+const HEC: &[Field] = &[
+    opt("memory", Objects(MEMORY)),
+    opt(
+        "stream",
+        Objects(&[req("name", Str), req("depth", Int), req("type", Str)]),
+    ),
+    opt("constants", Objects(CONSTANT)),
+    opt("modules", Tagged("style", hec_module)),
+];
+const UNIT: &[Field] = &[req("op_type", Str), req("name", Str), req("types", Strs)];
+const INSTANCE: &[Field] = &[req("instance_name", Str), req("module_name", Str)];
+const HEC_BODY: Kind = Tagged("op_type", hec_op);
+const HEC_STG: &[Field] = &[
+    req("name", Str),
+    req("args", Strs),
+    req("types", Strs),
+    req("num_in", Int),
+    req("units", Objects(UNIT)),
+    req("instances", Objects(INSTANCE)),
+    req("init_state", Str),
+    req("states", Objects(HEC_STATE)),
+];
+const HEC_STATE: &[Field] = &[
+    req("state", Str),
+    req("ops", HEC_BODY),
+    opt(
+        "transition",
+        Object(&[
+            opt("default", Str),
+            opt("jump", Objects(&[req("dest", Str), req("cond", Str)])),
+            opt("done", Strs),
+        ]),
+    ),
+];
+const HEC_PIPELINE: &[Field] = &[
+    req("name", Str),
+    req("args", Strs),
+    req("types", Strs),
+    req("num_in", Int),
+    req("units", Objects(UNIT)),
+    req("instances", Objects(INSTANCE)),
+    req("pipeline_style", Str),
+];
+const HEC_PIPELINE_FOR: &[Field] = &[
+    req("name", Str),
+    req("args", Strs),
+    req("types", Strs),
+    req("num_in", Int),
+    req("units", Objects(UNIT)),
+    req("instances", Objects(INSTANCE)),
+    req("pipeline_style", Str),
+    req("ii", Int),
+    req("wires", Object(&[req("name", Str)])),
+    req("inits", Objects(&[req("dst", Str), req("src", Str)])),
+    req(
+        "stages",
+        Objects(&[req("stage", Str), req("ops", HEC_BODY)]),
+    ),
+];
+const HEC_HANDSHAKE: &[Field] = &[
+    req("name", Str),
+    req("args", Strs),
+    req("types", Strs),
+    req("num_in", Int),
+    req("units", Objects(UNIT)),
+    req("instances", Objects(INSTANCE)),
+    req("graph", Objects(ASSIGN)),
+    req("sinks", Strs),
+];
+const GO: &[Field] = &[req("instance", Str)];
+const DELIVER: &[Field] = &[req("dst_port", Str), req("dst_reg", Str), req("src", Str)];
+const ENABLE: &[Field] = &[req("port", Str), opt("stream", Bool)];
+const ASSIGN: &[Field] = &[req("dst", Str), req("src", Str), opt("condition", Str)];
+
+fn hec_module(style: &str, module: &Value) -> &'static [Field] {
+    match (style, module["pipeline_style"].as_str()) {
+        ("dataflow" | "STG", _) => HEC_STG,
+        ("handshake", _) => HEC_HANDSHAKE,
+        ("pipeline", Some("for")) => HEC_PIPELINE_FOR,
+        _ => HEC_PIPELINE,
+    }
+}
+
+fn hec_op(op_type: &str, _: &Value) -> &'static [Field] {
+    match op_type {
+        "go" => GO,
+        "deliver" => DELIVER,
+        "assign" => ASSIGN,
+        "enable" => ENABLE,
+        _ => COMPUTE,
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+fn expected(kind: Kind) -> &'static str {
+    match kind {
+        Str => "a string",
+        Int => "a non-negative integer",
+        Bool => "a boolean",
+        Strs => "an array of strings",
+        Object(_) => "an object",
+        Objects(_) | Tagged(..) => "an array of objects",
+    }
+}
+
+struct Validator {
+    errors: Vec<String>,
+}
+
+impl Validator {
+    /// Record a problem at `path`, naming the operation or module `owner` it
+    /// belongs to if any.
+    fn error(&mut self, path: &str, owner: &Option<String>, problem: String) {
+        let error = match owner {
+            Some(owner) => format!("{} in {}: {}", path, owner, problem),
+            None => format!("{}: {}", path, problem),
+        };
+        self.errors.push(error);
+    }
+
+    fn object(&mut self, ir: &Value, fields: &[Field], path: &str, owner: &Option<String>) {
+        let named = ir.get("name").is_some() || ir.get("op_type").is_some();
+        let owner = if named { &Some(describe(ir)) } else { owner };
+        for field in fields {
+            let path = format!("{}.{}", path, field.key);
+            match ir.get(field.key) {
+                None | Some(Value::Null) if field.required => {
+                    let problem = format!("missing {}", expected(field.kind));
+                    self.error(&path, owner, problem);
+                }
+                None | Some(Value::Null) => (),
+                Some(value) => self.value(value, field.kind, &path, owner),
+            }
+        }
+    }
+
+    fn value(&mut self, value: &Value, kind: Kind, path: &str, owner: &Option<String>) {
+        let valid = match kind {
+            Str => value.is_string(),
+            Int => value.is_u64(),
+            Bool => value.is_boolean(),
+            Strs => value
+                .as_array()
+                .is_some_and(|items| items.iter().all(Value::is_string)),
+            Object(fields) => {
+                if value.is_object() {
+                    self.object(value, fields, path, owner);
+                }
+                value.is_object()
+            }
+            Objects(_) | Tagged(..) => value.is_array(),
+        };
+        if !valid {
+            let problem = format!("expected {}, found {}", expected(kind), json_type(value));
+            self.error(path, owner, problem);
+            return;
+        }
+        let (Objects(_) | Tagged(..), Some(items)) = (kind, value.as_array()) else {
+            return;
+        };
+        for (index, item) in items.iter().enumerate() {
+            let path = format!("{}[{}]", path, index);
+            if !item.is_object() {
+                let problem = format!("expected an object, found {}", json_type(item));
+                self.error(&path, owner, problem);
+                continue;
+            }
+            match kind {
+                Objects(fields) => self.object(item, fields, &path, owner),
+                Tagged(key, variant) => match item[key].as_str() {
+                    Some(tag) => self.object(item, variant(tag, item), &path, owner),
+                    None => {
+                        let key_path = format!("{}.{}", path, key);
+                        let problem = match item.get(key) {
+                            Some(tag) => format!("expected a string, found {}", json_type(tag)),
+                            None => "missing a string".to_string(),
+                        };
+                        self.error(&key_path, owner, problem);
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Check that the IR of `level` has every field the interpreters read, with
+/// the right JSON type, before any of it is built. Errors name the JSON path
+/// of the field, e.g. `$.modules[0].body[3].operands`, and the operation it
+/// belongs to.
+pub fn validate(level: State, ir: &Value) -> Result<(), HestiaError> {
+    let fields = match level {
+        State::Software => SOFTWARE,
+        State::ToR => TOR,
+        _ => HEC,
+    };
+    let mut validator = Validator { errors: vec![] };
+    validator.object(ir, fields, "$", &None);
+    let Some(first) = validator.errors.first() else {
+        return Ok(());
+    };
+    let message = match validator.errors.len() {
+        1 => first.clone(),
+        count => format!("{} (and {} more)", first, count - 1),
+    };
+    Err(HestiaError::new(ErrorCode::MalformedIr, message))
+}
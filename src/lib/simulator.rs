@@ -0,0 +1,520 @@
+use super::basetype::VALUE;
+use super::common::{self, level_name, RunState, State};
+use super::equal::EqualSim;
+use super::hec::HecSim;
+use super::software::SoftwareSim;
+use super::tor::TorSim;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::thread::LocalKey;
+
+/// The state of one simulation: the design loaded at each level, where each
+/// run stands and the cosim mappings between them. Each part is borrowed on
+/// its own, so that a level function can reach the run state while it holds
+/// its level, but not the level it holds a second time.
+#[derive(Default)]
+pub struct Simulation {
+    pub(crate) software: RefCell<SoftwareSim>,
+    pub(crate) tor: RefCell<TorSim>,
+    pub(crate) hec: RefCell<HecSim>,
+    pub(crate) equal: RefCell<EqualSim>,
+    pub(crate) run: RefCell<RunState>,
+}
+
+impl Simulation {
+    /// Exchange the contents with `other`. Panics if a part of either is
+    /// borrowed.
+    fn swap(&self, other: &Simulation) {
+        self.software.swap(&other.software);
+        self.tor.swap(&other.tor);
+        self.hec.swap(&other.hec);
+        self.equal.swap(&other.equal);
+        self.run.swap(&other.run);
+    }
+}
+
+thread_local! {
+    /// The current simulation of the thread. Each thread that simulates, the
+    /// shell or a worker of a sweep, has its own.
+    static CURRENT: Simulation = Simulation::default();
+    /// Set while the thread steps a part of a simulation it was handed, see
+    /// `detached`.
+    static DETACHED: Cell<bool> = const { Cell::new(false) };
+    /// The sessions of the shell on this thread.
+    static SESSIONS: RefCell<Sessions> = RefCell::new(Sessions {
+        current: MAIN_SESSION.to_string(),
+        parked: BTreeMap::new(),
+        returns: vec![],
+    });
+}
+
+/// The value of a thread local for the rest of the thread, to borrow its
+/// cells for longer than a `with` closure. Only for a value that is not
+/// Sync, so that no reference to it leaves the thread.
+fn thread_value<T>(key: &'static LocalKey<T>) -> &'static T {
+    let value = key.with(|value| value as *const T);
+    // The value lives until the thread exits
+    unsafe { &*value }
+}
+
+/// A thread-local cell for the rest of the thread, see `thread_value`.
+pub(crate) fn thread_cell<T>(key: &'static LocalKey<RefCell<T>>) -> &'static RefCell<T> {
+    thread_value(key)
+}
+
+/// Run `f` on a worker that was handed all it steps, which must not reach
+/// the current simulation as other threads act on it.
+pub(crate) fn detached<R>(f: impl FnOnce() -> R) -> R {
+    let outer = DETACHED.replace(true);
+    let result = f();
+    DETACHED.set(outer);
+    result
+}
+
+/// The simulation the shell commands and the level functions act on.
+pub(crate) fn current() -> &'static Simulation {
+    assert!(!DETACHED.get(), "simulation reached from a detached thread");
+    thread_value(&CURRENT)
+}
+
+/// The shell's named simulations: the name of the current one and the
+/// others, set aside until `session` switches back to them.
+struct Sessions {
+    current: String,
+    parked: BTreeMap<String, Box<Simulation>>,
+    /// The sessions that `@<name>` commands will return to.
+    returns: Vec<String>,
+}
+
+pub(crate) const MAIN_SESSION: &str = "main";
+
+/// The name of the current session.
+pub(crate) fn session_name() -> String {
+    SESSIONS.with_borrow(|sessions| sessions.current.clone())
+}
+
+/// The names of all sessions, the current one included.
+pub(crate) fn session_names() -> Vec<String> {
+    SESSIONS.with_borrow(|sessions| {
+        let mut names: Vec<String> = sessions.parked.keys().cloned().collect();
+        names.push(sessions.current.clone());
+        names.sort();
+        names
+    })
+}
+
+/// Make the session `name` current, creating an empty one if there is none,
+/// and return whether it was created.
+pub(crate) fn switch_session(name: &str) -> bool {
+    SESSIONS.with_borrow_mut(|sessions| {
+        if sessions.current == name {
+            return false;
+        }
+        let mut simulation = sessions.parked.remove(name);
+        let created = simulation.is_none();
+        current().swap(simulation.get_or_insert_with(Box::default));
+        let previous = std::mem::replace(&mut sessions.current, name.to_string());
+        sessions.parked.insert(previous, simulation.unwrap());
+        created
+    })
+}
+
+/// Whether `name` is the current session or one an `@<name>` command
+/// returns to.
+pub(crate) fn session_in_use(name: &str) -> bool {
+    SESSIONS.with_borrow(|sessions| {
+        sessions.current == name || sessions.returns.iter().any(|session| session == name)
+    })
+}
+
+/// Drop a session that is not in use, returning whether there was one.
+pub(crate) fn close_session(name: &str) -> bool {
+    !session_in_use(name)
+        && SESSIONS.with_borrow_mut(|sessions| sessions.parked.remove(name).is_some())
+}
+
+/// Switches back to the session an `@<name>` command came from, also when
+/// the command panics.
+struct Visit;
+
+impl Drop for Visit {
+    fn drop(&mut self) {
+        let previous = SESSIONS.with_borrow_mut(|sessions| sessions.returns.pop().unwrap());
+        switch_session(&previous);
+    }
+}
+
+/// Run `f` in the session `name`, creating it if there is none, and return
+/// to the current session.
+pub(crate) fn in_session<R>(name: &str, f: impl FnOnce() -> R) -> R {
+    SESSIONS.with_borrow_mut(|sessions| sessions.returns.push(sessions.current.clone()));
+    let _visit = Visit;
+    switch_session(name);
+    f()
+}
+
+/// Swaps a simulator's own simulation in as the current one and back again,
+/// also when the call in between panics.
+struct Entered<'a> {
+    simulator: &'a Simulator,
+    simulation: Option<Box<Simulation>>,
+}
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        let simulation = self.simulation.take().unwrap();
+        current().swap(&simulation);
+        self.simulator.simulation.set(Some(simulation));
+    }
+}
+
+/// Run `f` on a new, empty simulation and return to the current one, also
+/// when `f` panics.
+pub(crate) fn scratch<R>(f: impl FnOnce() -> R) -> R {
+    let simulator = Simulator {
+        simulation: Cell::new(Some(Box::default())),
+    };
+    simulator.enter(f)
+}
+
+/// The simulator as a library: load IR files, call a function and run it,
+/// then inspect memories and variables of the current level. Each simulator
+/// has its own simulation, while breakpoints and watchpoints are shared by
+/// all of them on a thread and options by all of them.
+pub struct Simulator {
+    simulation: Cell<Option<Box<Simulation>>>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        common::init();
+        Simulator {
+            simulation: Cell::new(Some(Box::default())),
+        }
+    }
+
+    /// Run `f` on this simulator's simulation.
+    fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        let simulation = self.simulation.take().unwrap();
+        current().swap(&simulation);
+        let _entered = Entered {
+            simulator: self,
+            simulation: Some(simulation),
+        };
+        f()
+    }
+
+    fn load_level(&mut self, path: &str, level: State) -> Result<(), Box<dyn Error>> {
+        let (found, _) = common::read_ir(path)?;
+        if found != level {
+            return Err(format!("{} is a {} file", path, level_name(found)).into());
+        }
+        self.enter(|| common::parse_file(path))
+    }
+
+    /// Load an IR file of any level and switch to it.
+    pub fn load(&mut self, path: &str) -> Result<State, Box<dyn Error>> {
+        self.enter(|| common::parse_file(path))?;
+        Ok(self.level())
+    }
+
+    pub fn load_software(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.load_level(path, State::Software)
+    }
+
+    pub fn load_tor(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.load_level(path, State::ToR)
+    }
+
+    pub fn load_hec(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.load_level(path, State::HEC)
+    }
+
+    /// Add the modules of another HEC file to the loaded HEC design.
+    pub fn merge_hec(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.enter(|| common::merge_file(path))
+    }
+
+    /// Load the equal-point file used by `cosim`.
+    pub fn load_equal(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.enter(|| common::parse_equal(path))
+    }
+
+    pub fn level(&self) -> State {
+        self.enter(common::debug_state)
+    }
+
+    pub fn switch(&mut self, level: State) {
+        self.enter(|| common::switch(level_name(level)));
+    }
+
+    /// Start `function` of the current level with the given arguments.
+    pub fn call(&mut self, function: &str, args: &[&str]) -> Result<(), String> {
+        self.enter(|| common::call_function(function, args))
+    }
+
+    /// Advance by `count` steps (software) or cycles (ToR, HEC).
+    pub fn step(&mut self, count: usize) {
+        self.enter(|| common::step(count));
+    }
+
+    /// Run until the call returns or a breakpoint or watchpoint stops it.
+    pub fn run(&mut self) {
+        self.enter(common::execute_continue);
+    }
+
+    /// Steps or cycles simulated at the current level since it was loaded.
+    pub fn cycle(&self) -> usize {
+        self.enter(common::current_cycle)
+    }
+
+    pub fn memory_names(&self) -> Vec<String> {
+        self.enter(common::memory_names)
+    }
+
+    pub fn read_memory(&self, memory: &str) -> Option<Vec<VALUE>> {
+        self.enter(|| common::dump_memory(common::debug_state(), memory))
+    }
+
+    pub fn write_memory(&mut self, memory: &str, values: &[VALUE]) {
+        self.enter(|| common::restore_memory(common::debug_state(), memory, values));
+    }
+
+    /// Append `value` to a stream or FIFO, failing when it is full.
+    pub fn push_stream(&mut self, stream: &str, value: VALUE) -> Result<(), String> {
+        self.enter(|| common::push_stream(common::debug_state(), stream, value))
+    }
+
+    /// Values of `var` in every active instance of the current level.
+    pub fn read_variable(&self, var: &str) -> Vec<VALUE> {
+        self.enter(|| common::dump_variable(common::debug_state(), var))
+    }
+
+    pub fn write_variable(&mut self, var: &str, values: &[VALUE]) {
+        self.enter(|| common::restore_variable(common::debug_state(), var, values));
+    }
+
+    /// Stop `run` when the named op, function or state is reached.
+    pub fn set_breakpoint(&mut self, name: &str) {
+        common::set_breakpoint(&[name]);
+    }
+
+    pub fn unset_breakpoint(&mut self, name: &str) {
+        common::unset_breakpoint(name);
+    }
+
+    /// Whether an operation has failed, as the exit code of a batch run.
+    pub fn failed(&self) -> bool {
+        common::exit_code() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    type Outcome = (usize, Vec<Vec<VALUE>>);
+
+    /// spmv at `level`, called and with its inputs filled in.
+    fn start(level: &str) -> Simulator {
+        let mut simulator = Simulator::new();
+        simulator
+            .load(&format!("example/json/spmv/{}.json", level))
+            .unwrap();
+        simulator.call("main", &[]).unwrap();
+        fill(&mut simulator, &[""]);
+        simulator
+    }
+
+    /// Fill in the inputs of the copies of spmv whose names have `prefixes`.
+    fn fill(simulator: &mut Simulator, prefixes: &[&str]) {
+        for (prefix, memory) in prefixes.iter().flat_map(|p| [(p, "1"), (p, "2")]) {
+            let path = format!("example/data/spmv/in_{}.txt", memory);
+            simulator
+                .enter(|| common::load_memory_file(&format!("{}op_{}", prefix, memory), &path))
+                .unwrap();
+        }
+        ramp(simulator);
+    }
+
+    /// Fill the vectors of spmv, which it has no data files for, at the
+    /// current level.
+    fn ramp(simulator: &mut Simulator) {
+        for memory in simulator.memory_names() {
+            let Some(store) = simulator.read_memory(&memory) else {
+                continue;
+            };
+            if let [VALUE::F64(_), ..] = store[..] {
+                let ramp: Vec<VALUE> = (0..store.len()).map(|i| VALUE::F64(i as f64)).collect();
+                simulator.write_memory(&memory, &ramp);
+            }
+        }
+    }
+
+    /// The cycle and the memories of `simulator` at the current level, in the
+    /// order of their names.
+    fn outcome(simulator: &Simulator) -> Outcome {
+        let mut names = simulator.memory_names();
+        names.sort();
+        let memories = names
+            .iter()
+            .filter_map(|name| simulator.read_memory(name))
+            .collect();
+        (simulator.cycle(), memories)
+    }
+
+    fn run(level: &str) -> Outcome {
+        let mut simulator = start(level);
+        simulator.run();
+        outcome(&simulator)
+    }
+
+    #[test]
+    fn interleaved_simulators() {
+        let (tor, scf) = (run("tor"), run("scf"));
+        let mut a = start("tor");
+        let mut b = start("scf");
+        a.step(10000);
+        b.step(5000);
+        a.step(10000);
+        b.run();
+        a.run();
+        assert_eq!(outcome(&a), tor);
+        assert_eq!(outcome(&b), scf);
+    }
+
+    #[test]
+    fn simulations_on_two_threads() {
+        let tor = std::thread::spawn(|| run("tor"));
+        let scf = std::thread::spawn(|| run("scf"));
+        assert_eq!(tor.join().unwrap(), run("tor"));
+        assert_eq!(scf.join().unwrap(), run("scf"));
+    }
+
+    /// Prefix the memories and functions that operations use with `b_`.
+    fn rename(ir: &mut Value) {
+        match ir {
+            Value::Object(fields) => {
+                for (key, field) in fields.iter_mut() {
+                    match (key.as_str(), field) {
+                        ("memory" | "function", Value::String(name)) => name.insert_str(0, "b_"),
+                        (_, field) => rename(field),
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(rename),
+            _ => (),
+        }
+    }
+
+    /// spmv's ToR design with a copy of its memories and functions named
+    /// `b_…`, so that calling both mains gives instances that share nothing.
+    fn spmv_twice() -> String {
+        let text = std::fs::read_to_string("example/json/spmv/tor.json").unwrap();
+        let mut ir: Value = serde_json::from_str(&text).unwrap();
+        let mut copy = ir.clone();
+        rename(&mut copy);
+        for list in ["memory", "modules"] {
+            let copies = copy[list].as_array().unwrap().iter().map(|item| {
+                let mut item = item.clone();
+                item["name"] = format!("b_{}", item["name"].as_str().unwrap()).into();
+                item
+            });
+            ir[list].as_array_mut().unwrap().extend(copies);
+        }
+        let path = std::env::temp_dir().join(format!("hestia-spmv-{}.json", std::process::id()));
+        std::fs::write(&path, ir.to_string()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn parallel_instances_match_serial() {
+        let path = spmv_twice();
+        let mut outcomes = vec![];
+        for mode in ["off", "on"] {
+            assert!(common::set_option("parallel_instances", mode));
+            let mut simulator = Simulator::new();
+            simulator.load(&path).unwrap();
+            simulator.call("main", &[]).unwrap();
+            simulator.call("b_main", &[]).unwrap();
+            fill(&mut simulator, &["", "b_"]);
+            simulator.run();
+            outcomes.push(outcome(&simulator));
+        }
+        common::set_option("parallel_instances", "off");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(outcomes[0].0, run("tor").0);
+        assert_eq!(outcomes[0], outcomes[1]);
+    }
+
+    /// spmv's HEC design with a unit that multiplies the next row index by
+    /// one and an equal point comparing its result, three cycles later, with
+    /// the ToR operation computing the index. The paths of the HEC and the
+    /// equal file.
+    fn spmv_equal_point() -> (String, String) {
+        let text = std::fs::read_to_string("example/json/spmv/hec.json").unwrap();
+        let mut ir: Value = serde_json::from_str(&text).unwrap();
+        let modules = ir["modules"].as_array_mut().unwrap();
+        let main = modules.iter_mut().find(|module| module["name"] == "main");
+        let main = main.unwrap();
+        let unit = json!({"name": "eqp", "op_type": "mul_integer", "types": ["i32", "i32", "i32"]});
+        main["units"].as_array_mut().unwrap().push(unit);
+        let states = main["states"].as_array_mut().unwrap();
+        let state = states.iter_mut().find(|state| state["state"] == "s2");
+        state.unwrap()["ops"].as_array_mut().unwrap().extend([
+            json!({"dst": "eqp.operand0", "op_type": "assign", "src": "comb_5"}),
+            json!({"dst": "eqp.operand1", "op_type": "assign", "src": "op_5"}),
+        ]);
+        let equal = json!([{"start": "", "end": "s5", "start_node": 0, "end_node": 0,
+            "op": "op_12", "primitive": "eqp"}]);
+        let dir = std::env::temp_dir();
+        let hec = dir.join(format!("hestia-spmv-hec-{}.json", std::process::id()));
+        let equal_path = dir.join(format!("hestia-spmv-equal-{}.json", std::process::id()));
+        std::fs::write(&hec, ir.to_string()).unwrap();
+        std::fs::write(&equal_path, equal.to_string()).unwrap();
+        let path = |path: std::path::PathBuf| path.to_str().unwrap().to_string();
+        (path(hec), path(equal_path))
+    }
+
+    #[test]
+    fn cosim_threads_match_one_thread() {
+        let (hec, equal) = spmv_equal_point();
+        let mut outcomes = vec![];
+        for mode in ["off", "on"] {
+            assert!(common::set_option("cosim_threads", mode));
+            let mut simulator = start("tor");
+            simulator.load_hec(&hec).unwrap();
+            simulator.call("main", &[]).unwrap();
+            for memory in ["1", "2"] {
+                let path = format!("example/data/spmv/in_{}.txt", memory);
+                let name = format!("mem_global_{}", memory);
+                simulator
+                    .enter(|| common::load_memory_file(&name, &path))
+                    .unwrap();
+            }
+            ramp(&mut simulator);
+            simulator.load_equal(&equal).unwrap();
+            let (output, failed) = simulator.enter(|| common::capture(common::cosim));
+            assert!(!failed, "{}", output);
+            assert!(output.contains("Cosimulation success"));
+            let levels = [State::ToR, State::HEC].map(|level| {
+                simulator.switch(level);
+                outcome(&simulator)
+            });
+            outcomes.push(levels);
+        }
+        common::set_option("cosim_threads", "off");
+        std::fs::remove_file(&hec).unwrap();
+        std::fs::remove_file(&equal).unwrap();
+        assert_eq!(outcomes[0][0], run("tor"));
+        assert_eq!(outcomes[0], outcomes[1]);
+    }
+}
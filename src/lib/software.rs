@@ -1,24 +1,35 @@
 use super::basetype::*;
 use super::common::*;
-use serde_json::Value;
-use std::collections::HashMap;
+use super::error::{
+    backing_field, check_functions, fault, fault_pending, in_bounds, str_field, take_fault,
+    type_field, unassigned, unknown_index, HestiaError,
+};
+use super::intern::{Layout, Name};
+use super::profile;
+use super::simulator;
+use half::{bf16, f16};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::iter::zip;
+use std::sync::Arc;
 
 #[inline]
-fn construct_body(ir: &Value) -> Vec<Operation> {
+fn construct_body(ir: &Value) -> Result<Vec<Operation>, HestiaError> {
     ir.as_array()
         .unwrap()
         .iter()
-        .map(|x| match x["op_type"].as_str().unwrap() {
-            "for" => Operation::For(ForOp::new(x)),
-            "if" => Operation::If(IfOp::new(x)),
-            "return" => Operation::Return(ReturnOp::new(x)),
-            "yield" => Operation::Yield(YieldOp::new(x)),
-            "load" => Operation::Load(LoadOp::new(x)),
-            "store" => Operation::Store(StoreOp::new(x)),
-            "call" => Operation::Call(CallOp::new(x)),
-            _ => Operation::Compute(Computation::new(x)),
+        .map(|x| {
+            Ok(match str_field(x, "op_type")? {
+                "for" => Operation::For(ForOp::new(x)?),
+                "if" => Operation::If(IfOp::new(x)?),
+                "return" => Operation::Return(ReturnOp::new(x)),
+                "yield" => Operation::Yield(YieldOp::new(x)),
+                "load" => Operation::Load(LoadOp::new(x)),
+                "store" => Operation::Store(StoreOp::new(x)),
+                "call" => Operation::Call(CallOp::new(x)),
+                _ => Operation::Compute(Computation::new(x)?),
+            })
         })
         .collect()
 }
@@ -114,8 +125,8 @@ struct ForOp {
 }
 
 impl ForOp {
-    fn new(ir: &Value) -> Self {
-        ForOp {
+    fn new(ir: &Value) -> Result<Self, HestiaError> {
+        Ok(ForOp {
             iter_args: construct(&ir["iter_args"]),
             iter_inits: construct(&ir["iter_inits"]),
             lb: String::from(ir["lb"].as_str().unwrap()),
@@ -123,9 +134,9 @@ impl ForOp {
             step: String::from(ir["step"].as_str().unwrap()),
             iter_name: String::from(ir["iter_name"].as_str().unwrap()),
             names: construct(&ir["names"]),
-            ops: construct_body(&ir["body"]),
+            ops: construct_body(&ir["body"])?,
             op_now: 0,
-        }
+        })
     }
 }
 
@@ -138,13 +149,13 @@ struct IfOp {
 }
 
 impl IfOp {
-    fn new(ir: &Value) -> Self {
-        IfOp {
+    fn new(ir: &Value) -> Result<Self, HestiaError> {
+        Ok(IfOp {
             condition: String::from(ir["condition"].as_str().unwrap()),
             names: construct(&ir["names"]),
-            ops: [construct_body(&ir["body0"]), construct_body(&ir["body1"])],
+            ops: [construct_body(&ir["body0"])?, construct_body(&ir["body1"])?],
             op_now: (0, 0),
-        }
+        })
     }
 }
 
@@ -167,18 +178,22 @@ enum EVAL {
     Yield(Vec<VALUE>),
 }
 
+fn lookup(var: &str) -> Option<VALUE> {
+    sim().variable.get(var).copied()
+}
+
 impl Operation {
     fn mark(&self) -> bool {
         match self {
-            Operation::Compute(compute) => test_breakpoint(&compute.name),
-            Operation::Load(load) => test_breakpoint(&load.name),
+            Operation::Compute(compute) => test_breakpoint(&compute.name, &lookup),
+            Operation::Load(load) => test_breakpoint(&load.name, &lookup),
             Operation::Store(_) => false,
             Operation::Yield(_) => false,
             Operation::Return(_) => false,
             Operation::For(for_op) => {
                 let names = &for_op.names;
                 for name in names {
-                    if test_breakpoint(name) {
+                    if test_breakpoint(name, &lookup) {
                         return true;
                     }
                 }
@@ -187,7 +202,7 @@ impl Operation {
             Operation::If(if_op) => {
                 let names = &if_op.names;
                 for name in names {
-                    if test_breakpoint(name) {
+                    if test_breakpoint(name, &lookup) {
                         return true;
                     }
                 }
@@ -196,24 +211,42 @@ impl Operation {
             _ => false,
         }
     }
-    fn eval(&mut self) -> EVAL {
+    /// Execute the operation, of the body of `function` or of a loop or
+    /// branch of the innermost function on the stack if None.
+    fn eval(&mut self, function: Option<&str>) -> EVAL {
+        let timer = profile::timer();
+        let eval = self.run(function);
+        if timer.is_some() {
+            let (kind, name) = match self {
+                Operation::Compute(compute) => ("compute", compute.name.as_str()),
+                Operation::Load(load) => ("load", load.name.as_str()),
+                Operation::Store(store) => ("store", store.memory.as_str()),
+                Operation::Call(call) => ("call", call.function.as_str()),
+                _ => return eval,
+            };
+            profile::charge(timer, kind, name);
+        }
+        eval
+    }
+
+    fn run(&mut self, function: Option<&str>) -> EVAL {
         match self {
             Operation::Compute(compute) => {
-                operation_env(compute, &mut FakeEnv {});
+                operation_env(compute, &mut FakeEnv { function });
                 EVAL::Nothing
             }
             Operation::Load(load) => {
                 let name = &load.name;
                 let memory = &load.memory;
                 let index = get_value(&load.index);
-                set_value(name, get_mem_value(memory, index.as_u64() as usize));
+                set_value(name, get_mem_value(memory, index));
                 EVAL::Nothing
             }
             Operation::Store(store) => {
                 let value = &store.value;
                 let memory = &store.memory;
                 let index = get_value(&store.index);
-                set_mem_value(memory, index.as_u64() as usize, get_value(value));
+                set_mem_value(memory, index, get_value(value));
                 EVAL::Nothing
             }
             Operation::Yield(yield_op) => EVAL::Yield(get_all_value(&yield_op.operands)),
@@ -227,7 +260,8 @@ impl Operation {
                     for (arg, init) in zip(&for_op.iter_args, &for_op.iter_inits) {
                         set_value(arg, get_value(init));
                     }
-                    unsafe { &mut (*CURRENT_STACK) }
+                    sim()
+                        .current_stack
                         .push(Controller::For(RefCell::new(for_op.clone())));
                 }
                 EVAL::Nothing
@@ -239,21 +273,25 @@ impl Operation {
                 } else {
                     if_op.op_now = (1, 0);
                 }
-                unsafe { &mut (*CURRENT_STACK) }.push(Controller::If(RefCell::new(if_op.clone())));
+                sim()
+                    .current_stack
+                    .push(Controller::If(RefCell::new(if_op.clone())));
                 EVAL::Nothing
             }
             Operation::Call(call_op) => {
-                let function = unsafe { (*FUNCTION).get(&String::from(&call_op.function)) };
+                let function = sim()
+                    .function
+                    .get(&String::from(&call_op.function))
+                    .cloned();
                 let operands = get_all_value(&call_op.operands);
                 match function {
                     Some(module) => {
                         for (arg, value) in zip(&module.borrow().args, operands) {
                             set_value(arg, value)
                         }
-                        unsafe { &mut (*CURRENT_STACK) }.push(Controller::Call(
-                            RefCell::new(call_op.clone()),
-                            module.clone(),
-                        ));
+                        sim()
+                            .current_stack
+                            .push(Controller::Call(RefCell::new(call_op.clone()), module));
                     }
                     None => panic!("Unfound function {:?}", call_op.function),
                 }
@@ -269,12 +307,12 @@ impl Operation {
             Operation::Load(load) => drop_value(&load.name),
             Operation::For(for_op) => {
                 for name in for_op.names.iter() {
-                    drop_value(&name);
+                    drop_value(name);
                 }
             }
             Operation::If(if_op) => {
                 for name in if_op.names.iter() {
-                    drop_value(&name);
+                    drop_value(name);
                 }
             }
             _ => (),
@@ -284,6 +322,7 @@ impl Operation {
 
 #[derive(Debug, Clone)]
 struct Function {
+    name: String,
     ops: Vec<Operation>,
     args: Vec<String>,
     types: Vec<String>,
@@ -291,17 +330,18 @@ struct Function {
 }
 
 impl Function {
-    fn new(ir: &Value) -> Self {
-        Function {
+    fn new(ir: &Value) -> Result<Self, HestiaError> {
+        Ok(Function {
+            name: String::from(ir["name"].as_str().unwrap()),
             args: construct(&ir["args"]),
             types: construct(&ir["types"]),
-            ops: construct_body(&ir["body"]),
+            ops: construct_body(&ir["body"])?,
             op_now: 0,
-        }
+        })
     }
 }
 
-use std::cell::RefCell;
+use std::cell::{RefCell, RefMut};
 
 #[derive(Debug, Clone)]
 enum Controller {
@@ -312,113 +352,184 @@ enum Controller {
     _TEMP,
 }
 
-static mut MEMORY: *mut HashMap<String, Memory> = std::ptr::null_mut();
-static mut VARIABLE_VALUE: *mut HashMap<String, VALUE> = std::ptr::null_mut();
-static mut FUNCTION: *mut HashMap<String, RefCell<Function>> = std::ptr::null_mut();
-static mut OPERATION_STACK: *mut Vec<Controller> = std::ptr::null_mut();
-static mut CURRENT_STACK: *mut Vec<Controller> = std::ptr::null_mut();
+/// The software simulation: the loaded functions and memories, the values of
+/// the variables and the stack of controllers being executed.
+#[derive(Default)]
+pub struct SoftwareSim {
+    memory: HashMap<String, Memory>,
+    variable: HashMap<String, VALUE>,
+    function: HashMap<String, RefCell<Function>>,
+    operation_stack: Vec<Controller>,
+    current_stack: Vec<Controller>,
+    /// Frame selected by `frame`, `up` and `down`, counted from the innermost
+    /// controller on the operation stack. Cleared whenever the simulation
+    /// advances.
+    selected_frame: Option<usize>,
+    /// The functions lowered for the compiled backend
+    program: Option<Arc<Program>>,
+}
 
-fn get_mem_value(var: &String, index: usize) -> VALUE {
-    unsafe { &(*MEMORY) }.get(var).unwrap().get_value(index)
+fn sim() -> RefMut<'static, SoftwareSim> {
+    simulator::current().software.borrow_mut()
 }
 
-fn set_mem_value(var: &String, index: usize, value: VALUE) {
-    unsafe { &mut (*MEMORY) }
-        .get_mut(var)
-        .unwrap()
-        .update(index, value.clone());
+/// The word at `index`, X at an X index.
+fn get_mem_value(var: &String, index: VALUE) -> VALUE {
+    if index == VALUE::X {
+        return VALUE::X;
+    }
+    let index = index.as_u64() as usize;
+    let sim = sim();
+    let memory = sim.memory.get(var).unwrap();
+    if !in_bounds(var, memory, index) {
+        return VALUE::ERROR;
+    }
+    memory.get_value(index)
+}
+
+fn set_mem_value(var: &String, index: VALUE, value: VALUE) {
+    if index == VALUE::X {
+        fault(unknown_index(var));
+        return;
+    }
+    let index = index.as_u64() as usize;
+    let mut sim = sim();
+    let memory = sim.memory.get_mut(var).unwrap();
+    if in_bounds(var, memory, index) {
+        memory.update(index, value);
+    }
 }
 
-struct FakeEnv {}
+struct FakeEnv<'a> {
+    function: Option<&'a str>,
+}
 
 fn get_value(var: &String) -> VALUE {
-    unsafe { &(*VARIABLE_VALUE) }.get(var).unwrap().clone()
+    let value = sim().variable.get(var).copied();
+    match value {
+        Some(value) => value,
+        None => {
+            fault(unassigned(var));
+            VALUE::ERROR
+        }
+    }
 }
 
 fn set_value(var: &String, value: VALUE) {
-    unsafe { &mut (*VARIABLE_VALUE) }.insert(var.to_owned(), value);
+    sim().variable.insert(var.to_owned(), value);
 }
 
 fn get_all_value(ops: &Vec<String>) -> Vec<VALUE> {
     ops.iter().map(|x| get_value(x)).collect()
 }
 
-impl IsEnv for FakeEnv {
-    fn get_value(&self, var: &String) -> VALUE {
-        get_value(var)
+impl IsEnv for FakeEnv<'_> {
+    fn get_value(&self, var: Name) -> VALUE {
+        get_value(&var)
     }
 
-    fn set_value(&mut self, var: &String, value: VALUE) {
-        set_value(var, value)
+    fn set_value(&mut self, var: Name, value: VALUE) {
+        set_value(&var, value)
     }
 
-    fn get_all_value(&self, ops: &Vec<String>) -> Vec<VALUE> {
-        get_all_value(ops)
+    fn instance(&self) -> String {
+        if let Some(function) = self.function {
+            return function.to_string();
+        }
+        let sim = sim();
+        let mut stack = sim.operation_stack.iter().rev();
+        let innermost = stack.find_map(|controller| match controller {
+            Controller::Function(function) | Controller::Call(_, function) => Some(function),
+            _ => None,
+        });
+        innermost.map_or(String::new(), |function| function.borrow().name.clone())
     }
 }
 
 fn drop_value(var: &String) {
-    unsafe { &mut (*VARIABLE_VALUE) }.remove(var);
+    sim().variable.remove(var);
 }
 
 pub fn parse_software(ir: &Value) -> Result<(), Box<dyn Error>> {
-    unsafe {
-        init_global(&mut MEMORY);
-        init_global(&mut FUNCTION);
-        init_global(&mut VARIABLE_VALUE);
-        init_global(&mut OPERATION_STACK);
-        init_global(&mut CURRENT_STACK);
-    }
+    *sim() = SoftwareSim::default();
+    check_functions(ir)?;
     if let Value::Array(memorys) = &ir["memory"] {
         for memory in memorys {
             let name = memory["name"].as_str().unwrap();
             let size = memory["size"].as_u64().unwrap();
-            unsafe {
-                let data_type = memory["type"].as_str().unwrap();
-                match data_type {
-                    "i32" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::I32(0); size as usize]),
-                    ),
-                    "i64" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::I64(0); size as usize]),
-                    ),
-                    "u32" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::U32(0); size as usize]),
-                    ),
-                    "u64" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::U64(0); size as usize]),
-                    ),
-                    "bool" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::Bool(false); size as usize]),
-                    ),
-                    "f32" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::F32(0.0); size as usize]),
-                    ),
-                    "f64" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::F64(0.0); size as usize]),
-                    ),
-                    _ => None,
-                };
+            let data_type = type_field(memory, "type")?;
+            match data_type {
+                "i8" => sim()
+                    .memory
+                    .insert(String::from(name), Memory::new(VALUE::I8(0), size as usize)),
+                "u8" => sim()
+                    .memory
+                    .insert(String::from(name), Memory::new(VALUE::U8(0), size as usize)),
+                "i16" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::I16(0), size as usize),
+                ),
+                "u16" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::U16(0), size as usize),
+                ),
+                "i32" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::I32(0), size as usize),
+                ),
+                "i64" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::I64(0), size as usize),
+                ),
+                "u32" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::U32(0), size as usize),
+                ),
+                "u64" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::U64(0), size as usize),
+                ),
+                "index" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::Index(0), size as usize),
+                ),
+                "bool" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::Bool(false), size as usize),
+                ),
+                "f16" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::F16(f16::ZERO), size as usize),
+                ),
+                "bf16" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::BF16(bf16::ZERO), size as usize),
+                ),
+                "f32" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::F32(0.0), size as usize),
+                ),
+                "f64" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::F64(0.0), size as usize),
+                ),
+                _ => None,
+            };
+            if let Some(backing) = backing_field(memory, size as usize)? {
+                sim().memory.get_mut(name).unwrap().set_backing(backing);
             }
         }
     }
     if let Value::Array(modules) = &ir["modules"] {
         for module in modules {
-            unsafe {
-                (*FUNCTION).insert(
-                    String::from(module["name"].as_str().unwrap()),
-                    RefCell::new(Function::new(&module)),
-                );
-            }
+            sim().function.insert(
+                String::from(module["name"].as_str().unwrap()),
+                RefCell::new(Function::new(&module)?),
+            );
         }
     }
+    let program = Program::new(&sim().function);
+    sim().program = Some(Arc::new(program));
     Ok(())
 }
 
@@ -454,9 +565,9 @@ impl Controller {
     fn step(&self) -> STEP {
         match self {
             Controller::Function(function_ref) => {
-                let mut function = function_ref.borrow_mut();
+                let function = &mut *function_ref.borrow_mut();
                 let now = function.op_now;
-                match function.ops[now].eval() {
+                match function.ops[now].eval(Some(&function.name)) {
                     EVAL::Return(return_vals) => STEP::Return(return_vals),
                     EVAL::Nothing => {
                         function.op_now = now + 1;
@@ -466,9 +577,9 @@ impl Controller {
                 }
             }
             Controller::Call(_, function_ref) => {
-                let mut function = function_ref.borrow_mut();
+                let function = &mut *function_ref.borrow_mut();
                 let now = function.op_now;
-                match function.ops[now].eval() {
+                match function.ops[now].eval(Some(&function.name)) {
                     EVAL::Return(return_vals) => STEP::Return(return_vals),
                     EVAL::Nothing => {
                         function.op_now = now + 1;
@@ -480,7 +591,7 @@ impl Controller {
             Controller::For(for_ref) => {
                 let mut for_op = for_ref.borrow_mut();
                 let now = for_op.op_now;
-                match for_op.ops[now].eval() {
+                match for_op.ops[now].eval(None) {
                     EVAL::Yield(return_vals) => {
                         let iter_type = get_value(&for_op.lb).get_type();
                         let iter_name = get_value(&for_op.iter_name);
@@ -497,7 +608,9 @@ impl Controller {
                         } else {
                             for (name, value) in zip(&for_op.names, return_vals) {
                                 set_value(&name, value);
-                                println!("{:?}", name);
+                                if verbose(Verbosity::Trace) {
+                                    println!("{:?}", name);
+                                }
                             }
                             drop(for_op);
                             self.drop();
@@ -514,7 +627,7 @@ impl Controller {
             Controller::If(if_ref) => {
                 let mut if_op = if_ref.borrow_mut();
                 let now = if_op.op_now;
-                match if_op.ops[now.0][now.1].eval() {
+                match if_op.ops[now.0][now.1].eval(None) {
                     EVAL::Yield(return_vals) => {
                         for (name, value) in zip(&if_op.names, return_vals) {
                             set_value(&name, value);
@@ -562,52 +675,307 @@ impl Controller {
 }
 
 #[inline]
-pub fn show_function() {
-    for (function, _) in unsafe { &(*FUNCTION) }.iter() {
+pub fn show_function(patterns: &[&str]) {
+    let Some(filter) = NameFilter::new(patterns) else {
+        return;
+    };
+    let mut names = function_names();
+    names.retain(|function| filter.matches(function));
+    if json_output() {
+        return print_json(json!(names));
+    }
+    for function in names {
         println!("{}", function)
     }
 }
 
+/// Variables of the selected frame, or all of them if no frame is selected.
+fn scoped_variables() -> Vec<(String, VALUE)> {
+    let sim = sim();
+    let scope = sim.selected_frame.map(|frame| {
+        let stack = &sim.operation_stack;
+        stack[stack.len() - 1 - frame].variable_names()
+    });
+    sim.variable
+        .iter()
+        .filter(|(var, _)| scope.as_ref().is_none_or(|scope| scope.contains(*var)))
+        .map(|(var, value)| (var.clone(), *value))
+        .collect()
+}
+
 #[inline]
-pub fn show_variable(vars: &[&str]) {
+pub fn show_variable(filter: &NameFilter) {
+    let variables = scoped_variables();
+    if json_output() {
+        let variables = variables.iter().map(|(var, value)| (var, value));
+        return print_json(variables_to_json(variables, filter));
+    }
     println!("!!SHOW VALUE:");
-    if vars.len() == 0 {
-        for (var, value) in unsafe { &(*VARIABLE_VALUE) }.iter() {
-            println!("\t{} {:?}", var, value)
-        }
-    } else {
-        for var in vars {
-            println!(
-                "\t{} {:?}",
-                var,
-                unsafe { &(*VARIABLE_VALUE) }.get(*var).unwrap()
-            );
+    let mut rows = vec![];
+    for (var, value) in variables {
+        if filter.matches(&var) {
+            rows.push((var, format!("{:?}", value)));
         }
     }
+    print_table("\t", &rows);
 }
 
-pub fn show_memory(memorys: &[&str]) {
+pub fn show_memory(filter: &NameFilter) {
+    if json_output() {
+        let mut values = serde_json::Map::new();
+        for (mem, value) in sim().memory.iter() {
+            if filter.matches(mem) {
+                values.insert(mem.clone(), value.to_json());
+            }
+        }
+        return print_json(Value::Object(values));
+    }
     println!("!!SHOW MEMORY:");
-    if memorys.len() == 0 {
-        for (mem, value) in unsafe { &(*MEMORY) }.iter() {
-            println!("\t{} {:?}", mem, value)
+    let mut rows = vec![];
+    for (mem, value) in sim().memory.iter() {
+        if filter.matches(mem) {
+            rows.push((mem.clone(), format!("{:?}", value)));
+        }
+    }
+    print_table("\t", &rows);
+}
+
+impl Controller {
+    fn to_json(&self) -> Value {
+        match self {
+            Controller::Function(function) => {
+                let function = function.borrow();
+                json!({"kind": "function", "op": function.op_now, "ops": function.ops.len()})
+            }
+            Controller::For(for_op) => {
+                let for_op = for_op.borrow();
+                json!({"kind": "for", "iter": for_op.iter_name, "op": for_op.op_now})
+            }
+            Controller::If(if_op) => {
+                let if_op = if_op.borrow();
+                json!({"kind": "if", "condition": if_op.condition, "op": [if_op.op_now.0, if_op.op_now.1]})
+            }
+            Controller::Call(call_op, _) => {
+                let call_op = call_op.borrow();
+                json!({"kind": "call", "function": call_op.function, "names": call_op.names})
+            }
+            Controller::_TEMP => Value::Null,
+        }
+    }
+}
+
+impl Controller {
+    /// Names defined directly in the body of the function, loop or branch,
+    /// nested loops and branches only contributing their results.
+    fn variable_names(&self) -> HashSet<String> {
+        let mut names = vec![];
+        match self {
+            Controller::Function(function) | Controller::Call(_, function) => {
+                let function = function.borrow();
+                names.extend(function.args.iter().cloned());
+                body_names(&function.ops, &mut names);
+            }
+            Controller::For(for_op) => {
+                let for_op = for_op.borrow();
+                names.push(for_op.iter_name.clone());
+                names.extend(for_op.iter_args.iter().cloned());
+                body_names(&for_op.ops, &mut names);
+            }
+            Controller::If(if_op) => {
+                let if_op = if_op.borrow();
+                body_names(&if_op.ops[0], &mut names);
+                body_names(&if_op.ops[1], &mut names);
+            }
+            Controller::_TEMP => (),
+        }
+        names.into_iter().collect()
+    }
+
+    /// Names defined by the ops of the body that have already executed, in
+    /// order, after the arguments or loop variables.
+    fn executed_names(&self) -> Vec<String> {
+        let mut names = vec![];
+        match self {
+            Controller::Function(function) | Controller::Call(_, function) => {
+                let function = function.borrow();
+                names.extend(function.args.iter().cloned());
+                body_names(&function.ops[..function.op_now], &mut names);
+            }
+            Controller::For(for_op) => {
+                let for_op = for_op.borrow();
+                names.push(for_op.iter_name.clone());
+                names.extend(for_op.iter_args.iter().cloned());
+                body_names(&for_op.ops[..for_op.op_now], &mut names);
+            }
+            Controller::If(if_op) => {
+                let if_op = if_op.borrow();
+                let (branch, op) = if_op.op_now;
+                body_names(&if_op.ops[branch][..op], &mut names);
+            }
+            Controller::_TEMP => (),
         }
-    } else {
-        for mem in memorys {
-            println!("\t{} {:?}", mem, unsafe { &(*MEMORY) }.get(*mem).unwrap());
+        names
+    }
+
+    /// The name defined by the op about to execute, if it defines one.
+    fn current_op(&self) -> Option<String> {
+        let mut names = vec![];
+        match self {
+            Controller::Function(function) | Controller::Call(_, function) => {
+                let function = function.borrow();
+                let end = (function.op_now + 1).min(function.ops.len());
+                body_names(&function.ops[function.op_now.min(end)..end], &mut names);
+            }
+            Controller::For(for_op) => {
+                let for_op = for_op.borrow();
+                let end = (for_op.op_now + 1).min(for_op.ops.len());
+                body_names(&for_op.ops[for_op.op_now.min(end)..end], &mut names);
+            }
+            Controller::If(if_op) => {
+                let if_op = if_op.borrow();
+                let (branch, op) = if_op.op_now;
+                let ops = &if_op.ops[branch];
+                let end = (op + 1).min(ops.len());
+                body_names(&ops[op.min(end)..end], &mut names);
+            }
+            Controller::_TEMP => (),
+        }
+        names.into_iter().next()
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Controller::Function(function) => {
+                let function = function.borrow();
+                let (op, ops) = (function.op_now, function.ops.len());
+                format!("function {} at op {}/{}", function.name, op, ops)
+            }
+            Controller::Call(_, function) => {
+                let function = function.borrow();
+                let (op, ops) = (function.op_now, function.ops.len());
+                format!("call {} at op {}/{}", function.name, op, ops)
+            }
+            Controller::For(for_op) => {
+                let for_op = for_op.borrow();
+                let iter = lookup(&for_op.iter_name)
+                    .map_or(String::from("?"), |value| format!("{:?}", value));
+                let (op, ops) = (for_op.op_now, for_op.ops.len());
+                format!("for {} = {} at op {}/{}", for_op.iter_name, iter, op, ops)
+            }
+            Controller::If(if_op) => {
+                let if_op = if_op.borrow();
+                let (branch, op) = if_op.op_now;
+                let ops = if_op.ops[branch].len();
+                let branch = if branch == 0 { "then" } else { "else" };
+                format!("if {} {} at op {}/{}", if_op.condition, branch, op, ops)
+            }
+            Controller::_TEMP => String::new(),
         }
     }
 }
 
+/// `frame [<n>]`: select the n-th frame from the innermost one, or print the
+/// selected frame, which scopes `show_var` until the simulation advances.
+pub fn select_frame(frame: Option<usize>) {
+    let mut sim = sim();
+    let frame = frame.unwrap_or(sim.selected_frame.unwrap_or(0));
+    if frame >= sim.operation_stack.len() {
+        println!("!!No frame {}", frame);
+        fail(Failure::Command);
+        return;
+    }
+    sim.selected_frame = Some(frame);
+    let controller = sim.operation_stack[sim.operation_stack.len() - 1 - frame].clone();
+    drop(sim);
+    if json_output() {
+        return print_json(json!({"frame": frame, "controller": controller.to_json()}));
+    }
+    println!("#{} {}", frame, controller.describe());
+}
+
+/// `up` moves towards the outermost function, `down` back towards the
+/// innermost controller.
+pub fn move_frame(offset: isize) {
+    let frame = sim().selected_frame.unwrap_or(0) as isize + offset;
+    if frame < 0 {
+        println!("!!Bottom frame selected");
+        fail(Failure::Command);
+        return;
+    }
+    select_frame(Some(frame as usize));
+}
+
+/// The control stack from the innermost controller, each with the function it
+/// belongs to and its locals.
+pub fn frames() -> Vec<Frame> {
+    let mut frames = vec![];
+    let mut function = String::new();
+    let stack = sim().operation_stack.clone();
+    for controller in stack.iter() {
+        if let Controller::Function(called) | Controller::Call(_, called) = controller {
+            function.clone_from(&called.borrow().name);
+        }
+        let variables = controller
+            .executed_names()
+            .into_iter()
+            .filter_map(|var| lookup(&var).map(|value| (var, value)))
+            .collect();
+        frames.push(Frame {
+            function: function.clone(),
+            location: controller.describe(),
+            op: controller.current_op(),
+            variables,
+        });
+    }
+    frames.reverse();
+    frames
+}
+
+/// `info locals`: the variables already defined in the selected frame.
+pub fn show_locals() {
+    let (stack, frame) = {
+        let sim = sim();
+        (sim.operation_stack.clone(), sim.selected_frame.unwrap_or(0))
+    };
+    let Some(controller) = stack.len().checked_sub(frame + 1).map(|top| &stack[top]) else {
+        println!("!!No frame {}", frame);
+        fail(Failure::Command);
+        return;
+    };
+    let locals: Vec<(String, VALUE)> = controller
+        .executed_names()
+        .into_iter()
+        .filter_map(|var| lookup(&var).map(|value| (var, value)))
+        .collect();
+    if json_output() {
+        let mut object = serde_json::Map::new();
+        for (var, value) in &locals {
+            object.insert(var.clone(), value.to_json());
+        }
+        return print_json(Value::Object(object));
+    }
+    let rows: Vec<(String, String)> = locals
+        .into_iter()
+        .map(|(var, value)| (var, format!("{:?}", value)))
+        .collect();
+    print_table("\t", &rows);
+}
+
 pub fn show_stack() {
-    for controller in unsafe { &(*OPERATION_STACK) }.iter() {
+    if json_output() {
+        let stack = sim().operation_stack.iter().map(|c| c.to_json()).collect();
+        return print_json(Value::Array(stack));
+    }
+    for controller in sim().operation_stack.iter() {
         println!("{:#?}", controller)
     }
 }
 
 pub fn call_function(function_name: &str, args: &[&str]) -> Result<(), String> {
-    println!("Call {} with arguments: {:?}", function_name, args);
-    let function = unsafe { (*FUNCTION).get(&String::from(function_name)) };
+    if verbose(Verbosity::Normal) {
+        println!("Call {} with arguments: {:?}", function_name, args);
+    }
+    let function = sim().function.get(&String::from(function_name)).cloned();
     match function {
         None => Err(String::from("!!Undefined function")),
         Some(module) => {
@@ -616,48 +984,59 @@ pub fn call_function(function_name: &str, args: &[&str]) -> Result<(), String> {
             {
                 set_value(arg, build_value(arg_type, &String::from(value.to_owned())))
             }
-            unsafe {
-                (*OPERATION_STACK).push(Controller::Function(module.clone()));
-            }
+            sim().operation_stack.push(Controller::Function(module));
+            sim().selected_frame = None;
             Ok(())
         }
     }
 }
 
 pub fn step(depth: usize, breakpoint: bool) -> bool {
-    for _ in 0..depth {
-        let top = unsafe { (*OPERATION_STACK).pop() };
+    let mut progress = Progress::new();
+    let checks = step_checks(breakpoint);
+    sim().selected_frame = None;
+    for index in 0..depth {
+        if interrupted() {
+            println!("!!Interrupted after {} steps", index);
+            return false;
+        }
+        auto_checkpoint(State::Software);
+        progress.report(index, sim().operation_stack.len());
+        let top = sim().operation_stack.pop();
         if top.is_none() {
             return false;
         }
         let top = top.unwrap();
-        if breakpoint && top.mark() {
-            unsafe {
-                (*OPERATION_STACK).push(top);
-            }
+        if checks.breakpoints && top.mark() {
+            sim().operation_stack.push(top);
             return false;
         }
+        trace_ops(|| {
+            top.mark();
+        });
         let eval = top.step();
+        advance_cycle(State::Software);
+        let mut returned = false;
         match eval {
             STEP::Normal => {
                 let mut tmp: Vec<Controller> = vec![];
-                unsafe {
-                    (*OPERATION_STACK).push(top);
-                    while !(*CURRENT_STACK).is_empty() {
-                        tmp.push((*CURRENT_STACK).pop().unwrap());
-                    }
-                    while !tmp.is_empty() {
-                        (*OPERATION_STACK).push(tmp.pop().unwrap());
-                    }
+                sim().operation_stack.push(top);
+                while !sim().current_stack.is_empty() {
+                    tmp.push(sim().current_stack.pop().unwrap());
+                }
+                while !tmp.is_empty() {
+                    sim().operation_stack.push(tmp.pop().unwrap());
                 }
             }
             STEP::Return(return_val) => {
-                unsafe { &mut (*OPERATION_STACK) }.push(top);
+                sim().operation_stack.push(top);
                 loop {
-                    let top = unsafe { (*OPERATION_STACK).pop() }.unwrap();
+                    let top = sim().operation_stack.pop().unwrap();
                     match &top {
                         Controller::Function(function_ref) => {
-                            let _function = function_ref.borrow();
+                            let function = function_ref.borrow();
+                            returned =
+                                checks.returns && test_return_break(&function.name, &return_val);
                             top.drop();
                             println!("RETURN: ");
                             for val in &return_val {
@@ -665,7 +1044,10 @@ pub fn step(depth: usize, breakpoint: bool) -> bool {
                             }
                             break;
                         }
-                        Controller::Call(call_op, _) => {
+                        Controller::Call(call_op, function_ref) => {
+                            let function = function_ref.borrow();
+                            returned =
+                                checks.returns && test_return_break(&function.name, &return_val);
                             let call_op = call_op.borrow();
                             for (ret, val) in zip(&call_op.names, &return_val) {
                                 set_value(&ret, val.clone())
@@ -679,20 +1061,63 @@ pub fn step(depth: usize, breakpoint: bool) -> bool {
             }
             _ => (),
         }
-        unsafe {
-            assert!((*CURRENT_STACK).is_empty());
+        assert!(sim().current_stack.is_empty());
+        let memory_hit = checks.watches && watch_step();
+        if let Some(error) = take_fault() {
+            println!("!!{} at step {}", error, current_cycle());
+            fail(Failure::Command);
+            return false;
+        }
+        if have_halt_watchpoint() && test_watch_halt("", &lookup) {
+            println!("Watch at step {}", current_cycle());
+            return false;
         }
-        for var in unsafe { &*get_watchpoint() } {
-            if let Some(value) = unsafe { &(*VARIABLE_VALUE) }.get(var) {
+        if memory_hit {
+            println!("Watch at step {}", current_cycle());
+            return false;
+        }
+        if returned {
+            println!("Return break at step {}", current_cycle());
+            return false;
+        }
+    }
+    true
+}
+
+/// Print the watchpoints and the displays after a step, true if a watched
+/// memory stops stepping.
+fn watch_step() -> bool {
+    log_watch_csv(State::Software);
+    let memory_hit = watch_memory(State::Software);
+    for var in get_watchpoint() {
+        let value = sim().variable.get(&var).copied();
+        if let Some(value) = value {
+            if watch_table_enabled() {
+                record_watch(var.clone(), value);
+            } else {
                 println!("\t{} {:?}", var, value);
             }
         }
     }
-    true
+    if watch_table_enabled() {
+        draw_watch_table();
+    }
+    if display_enabled() {
+        display_changes(snapshot());
+    }
+    memory_hit
+}
+
+pub fn snapshot() -> BTreeMap<String, String> {
+    let sim = sim();
+    sim.variable
+        .iter()
+        .map(|(var, value)| (var.clone(), format!("{:?}", value)))
+        .collect()
 }
 
 pub fn show_operation() {
-    let top = unsafe { (*OPERATION_STACK).pop() };
+    let top = sim().operation_stack.pop();
     if top.is_none() {
         return;
     }
@@ -731,20 +1156,844 @@ pub fn show_operation() {
         }
         _ => (),
     }
-    unsafe {
-        (*OPERATION_STACK).push(top.unwrap());
+    sim().operation_stack.push(top.unwrap());
+}
+
+/// Keep stepping with breakpoints enabled while `running` holds.
+fn step_while(running: impl Fn() -> bool) {
+    let mut step_num = 0;
+    while running() {
+        if !step(1, true) {
+            return;
+        }
+        step_num += 1;
+        if step_num >= options().max_cycles {
+            println!("!!Stopped at max_cycles {}", options().max_cycles);
+            return;
+        }
+    }
+}
+
+pub fn step_over() {
+    let depth = sim().operation_stack.len();
+    if !step(1, false) {
+        return;
+    }
+    let call = sim()
+        .operation_stack
+        .get(depth..)
+        .is_some_and(|stack| stack.iter().any(|c| matches!(c, Controller::Call(..))));
+    if call {
+        step_while(|| sim().operation_stack.len() > depth);
+    }
+}
+
+pub fn step_out() {
+    let frame = sim()
+        .operation_stack
+        .iter()
+        .rposition(|c| matches!(c, Controller::Function(_) | Controller::Call(..)));
+    let Some(frame) = frame else {
+        return;
+    };
+    if !step(1, false) {
+        return;
     }
+    step_while(|| sim().operation_stack.len() > frame);
 }
 
 pub fn execute_continue() {
+    if options().software_backend == SoftwareBackend::Compiled && run_compiled() {
+        return;
+    }
     let mut step_num = 0;
+    let mut progress = Progress::new();
     while step(1, true) {
         step_num += 1;
+        progress.report(step_num, sim().operation_stack.len());
+        if step_num >= options().max_cycles {
+            println!("!!Stopped at max_cycles {}", options().max_cycles);
+            break;
+        }
     }
     // println!("Step size: {}", step_num);
 }
 
 pub fn load_memory(memory: &str, store: &[&str]) {
-    let memory = unsafe { &mut (*MEMORY) }.get_mut(memory).unwrap();
-    memory.set(store)
+    sim().memory.get_mut(memory).unwrap().set(store)
+}
+
+pub fn finish() -> bool {
+    sim().operation_stack.is_empty()
+}
+
+pub fn dump_memory(memory: &str) -> Option<Vec<VALUE>> {
+    sim().memory.get(memory).map(|mem| mem.dump())
+}
+
+pub fn memory_store(memory: &str) -> Option<PagedStore> {
+    sim().memory.get(memory).map(|mem| mem.store().clone())
+}
+
+pub fn restore_memory(memory: &str, store: &[VALUE]) {
+    if let Some(mem) = sim().memory.get_mut(memory) {
+        mem.restore(store)
+    }
+}
+
+pub fn swap_memory(memory: &str, words: &mut [(usize, VALUE)]) {
+    if let Some(mem) = sim().memory.get_mut(memory) {
+        mem.swap(words)
+    }
+}
+
+pub fn dump_variable(var: &str) -> Vec<VALUE> {
+    sim().variable.get(var).copied().into_iter().collect()
+}
+
+pub fn restore_variable(var: &str, values: &[VALUE]) {
+    if let Some(value) = values.first() {
+        set_value(&var.to_string(), *value);
+    }
+}
+
+/// Saved memories, variables and control stack of the software simulator.
+pub struct Checkpoint {
+    memory: HashMap<String, Memory>,
+    variable: HashMap<String, VALUE>,
+    stack: Vec<Controller>,
+}
+
+pub fn checkpoint() -> Checkpoint {
+    let sim = sim();
+    Checkpoint {
+        memory: sim.memory.clone(),
+        variable: sim.variable.clone(),
+        stack: sim.operation_stack.clone(),
+    }
+}
+
+pub fn restore(checkpoint: &Checkpoint) {
+    sim().memory = checkpoint.memory.clone();
+    sim().variable = checkpoint.variable.clone();
+    sim().operation_stack = checkpoint.stack.clone();
+    sim().selected_frame = None;
+}
+
+impl Controller {
+    /// Where the controller stands, for a state file.
+    fn to_state(&self) -> Value {
+        match self {
+            Controller::Function(function) => {
+                let function = function.borrow();
+                json!({"kind": "function", "function": function.name, "op": function.op_now})
+            }
+            Controller::Call(_, function) => {
+                let function = function.borrow();
+                json!({"kind": "call", "function": function.name, "op": function.op_now})
+            }
+            Controller::For(for_op) => json!({"kind": "for", "op": for_op.borrow().op_now}),
+            Controller::If(if_op) => {
+                let (branch, op) = if_op.borrow().op_now;
+                json!({"kind": "if", "op": [branch, op]})
+            }
+            Controller::_TEMP => Value::Null,
+        }
+    }
+
+    /// The loop, branch or call this controller pushed, which is the op
+    /// before its current one.
+    fn pushed_op(&self) -> Option<Operation> {
+        match self {
+            Controller::Function(function) | Controller::Call(_, function) => {
+                let function = function.borrow();
+                function.ops.get(function.op_now.checked_sub(1)?).cloned()
+            }
+            Controller::For(for_op) => {
+                let for_op = for_op.borrow();
+                for_op.ops.get(for_op.op_now.checked_sub(1)?).cloned()
+            }
+            Controller::If(if_op) => {
+                let if_op = if_op.borrow();
+                let (branch, op) = if_op.op_now;
+                if_op.ops.get(branch)?.get(op.checked_sub(1)?).cloned()
+            }
+            Controller::_TEMP => None,
+        }
+    }
+
+    /// Rebuild a controller from its state, given the one below it on the
+    /// stack.
+    fn from_state(state: &Value, parent: Option<&Controller>) -> Option<Controller> {
+        let function = |name: &Value| {
+            let mut function = sim().function.get(name.as_str()?)?.borrow().clone();
+            function.op_now = state["op"].as_u64()? as usize;
+            (function.op_now < function.ops.len()).then(|| RefCell::new(function))
+        };
+        let pushed = parent.and_then(|parent| parent.pushed_op());
+        match (state["kind"].as_str()?, pushed) {
+            ("function", None) if parent.is_none() => {
+                Some(Controller::Function(function(&state["function"])?))
+            }
+            ("call", Some(Operation::Call(call_op))) if call_op.function == state["function"] => {
+                Some(Controller::Call(
+                    RefCell::new(call_op),
+                    function(&state["function"])?,
+                ))
+            }
+            ("for", Some(Operation::For(mut for_op))) => {
+                for_op.op_now = state["op"].as_u64()? as usize;
+                (for_op.op_now < for_op.ops.len()).then(|| Controller::For(RefCell::new(for_op)))
+            }
+            ("if", Some(Operation::If(mut if_op))) => {
+                let branch = state["op"][0].as_u64()? as usize;
+                let op = state["op"][1].as_u64()? as usize;
+                if_op.op_now = (branch, op);
+                (op < if_op.ops.get(branch)?.len()).then(|| Controller::If(RefCell::new(if_op)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Checkpoint {
+    pub fn to_json(&self) -> Value {
+        let mut memory = serde_json::Map::new();
+        for (name, mem) in &self.memory {
+            memory.insert(name.clone(), mem.to_state());
+        }
+        let mut variable = serde_json::Map::new();
+        for (name, value) in &self.variable {
+            variable.insert(name.clone(), value_to_state(value));
+        }
+        json!({
+            "memory": memory,
+            "variable": variable,
+            "stack": self.stack.iter().map(|controller| controller.to_state()).collect::<Vec<_>>(),
+        })
+    }
+
+    /// The state saved by `to_json`, applied to the loaded program.
+    pub fn from_json(state: &Value) -> Result<Checkpoint, String> {
+        let mut checkpoint = checkpoint();
+        for (name, mem_state) in state["memory"].as_object().into_iter().flatten() {
+            let Some(mem) = checkpoint.memory.get_mut(name) else {
+                return Err(format!("No memory {}", name));
+            };
+            mem.restore_state(mem_state)
+                .ok_or(format!("Malformed state of memory {}", name))?;
+        }
+        checkpoint.variable.clear();
+        for (name, value) in state["variable"].as_object().into_iter().flatten() {
+            let value = value_from_state(value).ok_or(format!("Malformed value of {}", name))?;
+            checkpoint.variable.insert(name.clone(), value);
+        }
+        checkpoint.stack.clear();
+        for (depth, controller) in state["stack"].as_array().into_iter().flatten().enumerate() {
+            let controller =
+                Controller::from_state(controller, checkpoint.stack.last()).ok_or(format!(
+                    "Control stack does not match the program at depth {}",
+                    depth
+                ))?;
+            checkpoint.stack.push(controller);
+        }
+        Ok(checkpoint)
+    }
+}
+
+fn body_names(ops: &[Operation], names: &mut Vec<String>) {
+    for op in ops {
+        match op {
+            Operation::Compute(compute) => names.push(compute.name.to_string()),
+            Operation::Load(load) => names.push(load.name.clone()),
+            Operation::Call(call_op) => names.extend(call_op.names.iter().cloned()),
+            Operation::For(for_op) => names.extend(for_op.names.iter().cloned()),
+            Operation::If(if_op) => names.extend(if_op.names.iter().cloned()),
+            _ => (),
+        }
+    }
+}
+
+fn collect_names(ops: &Vec<Operation>, names: &mut Vec<String>) {
+    for op in ops {
+        match op {
+            Operation::Compute(compute) => names.push(compute.name.to_string()),
+            Operation::Load(load) => names.push(load.name.clone()),
+            Operation::Call(call_op) => names.extend(call_op.names.iter().cloned()),
+            Operation::For(for_op) => {
+                names.extend(for_op.names.iter().cloned());
+                names.push(for_op.iter_name.clone());
+                names.extend(for_op.iter_args.iter().cloned());
+                collect_names(&for_op.ops, names);
+            }
+            Operation::If(if_op) => {
+                names.extend(if_op.names.iter().cloned());
+                collect_names(&if_op.ops[0], names);
+                collect_names(&if_op.ops[1], names);
+            }
+            _ => (),
+        }
+    }
+}
+
+fn find_loop(ops: &[Operation], name: &str) -> Option<String> {
+    ops.iter().find_map(|op| match op {
+        Operation::For(for_op) => {
+            if for_op.iter_name == name || for_op.names.iter().any(|n| n == name) {
+                Some(for_op.iter_name.clone())
+            } else {
+                find_loop(&for_op.ops, name)
+            }
+        }
+        Operation::If(if_op) => {
+            find_loop(&if_op.ops[0], name).or_else(|| find_loop(&if_op.ops[1], name))
+        }
+        _ => None,
+    })
+}
+
+/// Induction variable of the for loop named by one of its results or by the
+/// induction variable itself.
+pub fn loop_iter_name(name: &str) -> Option<String> {
+    sim()
+        .function
+        .values()
+        .find_map(|function| find_loop(&function.borrow().ops, name))
+}
+
+pub fn function_names() -> Vec<String> {
+    sim().function.keys().cloned().collect()
+}
+
+/// The size in words and the bytes allocated of each memory.
+pub fn memory_usage() -> Vec<(String, usize, usize)> {
+    sim()
+        .memory
+        .iter()
+        .map(|(name, memory)| (name.clone(), memory.size(), memory.footprint()))
+        .collect()
+}
+
+pub fn memory_names() -> Vec<String> {
+    sim().memory.keys().cloned().collect()
+}
+
+pub fn signal_names() -> Vec<String> {
+    let mut names = vec![];
+    for function in sim().function.values() {
+        let function = function.borrow();
+        names.extend(function.args.iter().cloned());
+        collect_names(&function.ops, &mut names);
+    }
+    names
+}
+
+/// A software operation lowered to the slots of the variables it reads and
+/// writes, see `Program`.
+#[derive(Debug)]
+enum Code {
+    Compute {
+        compute: Computation,
+        operands: Vec<usize>,
+        name: usize,
+    },
+    Load {
+        name: usize,
+        index: usize,
+        memory: usize,
+    },
+    Store {
+        value: usize,
+        index: usize,
+        memory: usize,
+    },
+    For(Box<ForCode>),
+    If(Box<IfCode>),
+    Yield(Vec<usize>),
+    Return(Vec<usize>),
+    Call {
+        function: usize,
+        operands: Vec<usize>,
+        names: Vec<usize>,
+    },
+    /// An operation the interpreter has to run, such as a call of an
+    /// unknown function
+    Interpret,
+}
+
+#[derive(Debug)]
+struct ForCode {
+    lb: usize,
+    ub: usize,
+    step: usize,
+    iter_name: usize,
+    iter_args: Vec<usize>,
+    iter_inits: Vec<usize>,
+    names: Vec<usize>,
+    body: Vec<Code>,
+    /// The slots the loop clears when it ends, as `Controller::drop` does
+    drops: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct IfCode {
+    condition: usize,
+    names: Vec<usize>,
+    bodies: [Vec<Code>; 2],
+    drops: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct FunctionCode {
+    args: Vec<usize>,
+    body: Vec<Code>,
+    drops: Vec<usize>,
+}
+
+/// The functions lowered once at load for the compiled backend. All the
+/// variables share one frame of slots, as they share one map in the
+/// interpreter, so a run leaves the same variables behind.
+#[derive(Debug, Default)]
+struct Program {
+    layout: Layout,
+    /// The variable of each slot
+    names: Vec<Name>,
+    memories: Vec<String>,
+    functions: Vec<FunctionCode>,
+    function_index: HashMap<String, usize>,
+}
+
+impl Program {
+    fn new(functions: &HashMap<String, RefCell<Function>>) -> Self {
+        let mut program = Program::default();
+        let mut names: Vec<&String> = functions.keys().collect();
+        names.sort();
+        for (index, name) in names.iter().enumerate() {
+            program.function_index.insert(name.to_string(), index);
+        }
+        for name in names {
+            let function = functions[name].borrow();
+            let code = FunctionCode {
+                args: program.slots(&function.args),
+                body: program.lower(&function.ops),
+                drops: program.drops(&function.ops),
+            };
+            program.functions.push(code);
+        }
+        program
+    }
+
+    fn slot(&mut self, name: &str) -> usize {
+        let name = Name::new(name);
+        let slot = self.layout.insert(name);
+        if slot == self.names.len() {
+            self.names.push(name);
+        }
+        slot
+    }
+
+    fn slots(&mut self, names: &[String]) -> Vec<usize> {
+        names.iter().map(|name| self.slot(name)).collect()
+    }
+
+    fn memory(&mut self, name: &str) -> usize {
+        match self.memories.iter().position(|memory| memory == name) {
+            Some(index) => index,
+            None => {
+                self.memories.push(name.to_string());
+                self.memories.len() - 1
+            }
+        }
+    }
+
+    /// The slots `drop_body` clears for `ops`.
+    fn drops(&mut self, ops: &[Operation]) -> Vec<usize> {
+        let mut drops = vec![];
+        for op in ops {
+            match op {
+                Operation::Compute(compute) => drops.push(self.slot(&compute.name)),
+                Operation::Load(load) => drops.push(self.slot(&load.name)),
+                Operation::For(for_op) => drops.extend(self.slots(&for_op.names)),
+                Operation::If(if_op) => drops.extend(self.slots(&if_op.names)),
+                _ => (),
+            }
+        }
+        drops
+    }
+
+    fn lower(&mut self, ops: &[Operation]) -> Vec<Code> {
+        ops.iter().map(|op| self.lower_op(op)).collect()
+    }
+
+    fn lower_op(&mut self, op: &Operation) -> Code {
+        match op {
+            Operation::Compute(compute) => Code::Compute {
+                operands: compute
+                    .read_operands()
+                    .iter()
+                    .map(|operand| self.slot(operand.name()))
+                    .collect(),
+                name: self.slot(&compute.name),
+                compute: compute.clone(),
+            },
+            Operation::Load(load) => Code::Load {
+                name: self.slot(&load.name),
+                index: self.slot(&load.index),
+                memory: self.memory(&load.memory),
+            },
+            Operation::Store(store) => Code::Store {
+                value: self.slot(&store.value),
+                index: self.slot(&store.index),
+                memory: self.memory(&store.memory),
+            },
+            Operation::For(for_op) => {
+                let mut drops = self.drops(&for_op.ops);
+                drops.push(self.slot(&for_op.iter_name));
+                drops.extend(self.slots(&for_op.iter_args));
+                Code::For(Box::new(ForCode {
+                    lb: self.slot(&for_op.lb),
+                    ub: self.slot(&for_op.ub),
+                    step: self.slot(&for_op.step),
+                    iter_name: self.slot(&for_op.iter_name),
+                    iter_args: self.slots(&for_op.iter_args),
+                    iter_inits: self.slots(&for_op.iter_inits),
+                    names: self.slots(&for_op.names),
+                    body: self.lower(&for_op.ops),
+                    drops,
+                }))
+            }
+            Operation::If(if_op) => {
+                let mut drops = self.drops(&if_op.ops[0]);
+                drops.extend(self.drops(&if_op.ops[1]));
+                Code::If(Box::new(IfCode {
+                    condition: self.slot(&if_op.condition),
+                    names: self.slots(&if_op.names),
+                    bodies: [self.lower(&if_op.ops[0]), self.lower(&if_op.ops[1])],
+                    drops,
+                }))
+            }
+            Operation::Yield(yield_op) => Code::Yield(self.slots(&yield_op.operands)),
+            Operation::Return(return_op) => Code::Return(self.slots(&return_op.operands)),
+            Operation::Call(call_op) => match self.function_index.get(&call_op.function) {
+                Some(&function) => Code::Call {
+                    function,
+                    operands: self.slots(&call_op.operands),
+                    names: self.slots(&call_op.names),
+                },
+                None => Code::Interpret,
+            },
+            Operation::_TEMP => Code::Interpret,
+        }
+    }
+}
+
+/// How a body of compiled code ends, with the values in `Machine::values`.
+enum Flow {
+    Yield,
+    Return,
+}
+
+/// A compiled run gave up, to be run again by the interpreter: it faulted,
+/// reached `max_cycles`, was interrupted or met something only the
+/// interpreter does.
+struct Interpret;
+
+/// Calls nested deeper give up, as they would overflow the native stack.
+const MAX_CALL_DEPTH: usize = 256;
+
+/// Runs a `Program` on a frame of slots and the memories.
+struct Machine<'a> {
+    program: &'a Program,
+    frame: Vec<Option<VALUE>>,
+    memories: Vec<&'a mut Memory>,
+    /// Operands of the computation being evaluated
+    operands: Vec<VALUE>,
+    /// Values of the last yield, return or call
+    values: Vec<VALUE>,
+    steps: usize,
+    /// Steps allowed before `max_cycles`
+    budget: usize,
+    depth: usize,
+}
+
+impl Machine<'_> {
+    fn get(&self, slot: usize) -> Result<VALUE, Interpret> {
+        self.frame[slot].ok_or(Interpret)
+    }
+
+    /// A memory index, leaving X to the interpreter.
+    fn index(&self, slot: usize) -> Result<usize, Interpret> {
+        match self.get(slot)? {
+            VALUE::X => Err(Interpret),
+            index => Ok(index.as_u64() as usize),
+        }
+    }
+
+    fn gather(&mut self, slots: &[usize]) -> Result<(), Interpret> {
+        self.values.clear();
+        for &slot in slots {
+            let value = self.get(slot)?;
+            self.values.push(value);
+        }
+        Ok(())
+    }
+
+    /// Assign the gathered values to `slots`, as many as there are of both.
+    fn assign(&mut self, slots: &[usize]) {
+        for (&slot, &value) in zip(slots, &self.values) {
+            self.frame[slot] = Some(value);
+        }
+    }
+
+    fn clear(&mut self, slots: &[usize]) {
+        for &slot in slots {
+            self.frame[slot] = None;
+        }
+    }
+
+    /// Count a step, every operation run being one as in the interpreter.
+    fn step(&mut self) -> Result<(), Interpret> {
+        self.steps += 1;
+        if self.steps > self.budget || (self.steps.is_multiple_of(65536) && interrupted()) {
+            return Err(Interpret);
+        }
+        Ok(())
+    }
+
+    fn less(&mut self, x: VALUE, y: VALUE) -> bool {
+        self.operands.clear();
+        self.operands.extend([x, y]);
+        cmp_lt(&self.operands) == VALUE::Bool(true)
+    }
+
+    /// Run a body until it yields or returns. Running off its end panics in
+    /// the interpreter, so it is left to the interpreter.
+    fn run(&mut self, body: &[Code]) -> Result<Flow, Interpret> {
+        for code in body {
+            self.step()?;
+            match code {
+                Code::Compute {
+                    compute,
+                    operands,
+                    name,
+                } => {
+                    self.operands.clear();
+                    for &slot in operands {
+                        let value = self.get(slot)?;
+                        self.operands.push(value);
+                    }
+                    // The interpreter reports the function at fault
+                    if options().strict && compute.poisoned(&self.operands).is_some() {
+                        return Err(Interpret);
+                    }
+                    self.frame[*name] = Some(compute.evaluate(&self.operands));
+                    if fault_pending() {
+                        return Err(Interpret);
+                    }
+                }
+                Code::Load {
+                    name,
+                    index,
+                    memory,
+                } => {
+                    let index = self.index(*index)?;
+                    let memory = &self.memories[*memory];
+                    if index >= memory.size() {
+                        return Err(Interpret);
+                    }
+                    self.frame[*name] = Some(memory.get_value(index));
+                }
+                Code::Store {
+                    value,
+                    index,
+                    memory,
+                } => {
+                    let index = self.index(*index)?;
+                    let value = self.get(*value)?;
+                    let memory = &mut self.memories[*memory];
+                    if index >= memory.size() {
+                        return Err(Interpret);
+                    }
+                    memory.update(index, value);
+                }
+                Code::For(for_code) => {
+                    if let Flow::Return = self.run_for(for_code)? {
+                        return Ok(Flow::Return);
+                    }
+                }
+                Code::If(if_code) => {
+                    let branch = match self.get(if_code.condition)? {
+                        VALUE::Bool(true) => 0,
+                        _ => 1,
+                    };
+                    if let Flow::Return = self.run(&if_code.bodies[branch])? {
+                        return Ok(Flow::Return);
+                    }
+                    self.assign(&if_code.names);
+                    self.clear(&if_code.drops);
+                }
+                Code::Yield(operands) => {
+                    self.gather(operands)?;
+                    return Ok(Flow::Yield);
+                }
+                Code::Return(operands) => {
+                    self.gather(operands)?;
+                    return Ok(Flow::Return);
+                }
+                Code::Call {
+                    function,
+                    operands,
+                    names,
+                } => {
+                    let function = &self.program.functions[*function];
+                    if self.depth == MAX_CALL_DEPTH {
+                        return Err(Interpret);
+                    }
+                    self.gather(operands)?;
+                    self.assign(&function.args);
+                    self.depth += 1;
+                    let flow = self.run(&function.body)?;
+                    self.depth -= 1;
+                    if let Flow::Yield = flow {
+                        return Err(Interpret);
+                    }
+                    self.assign(names);
+                    self.clear(&function.drops);
+                }
+                Code::Interpret => return Err(Interpret),
+            }
+        }
+        Err(Interpret)
+    }
+
+    /// Run a loop to its end, which yields its results, or to a return.
+    fn run_for(&mut self, for_code: &ForCode) -> Result<Flow, Interpret> {
+        let lb = self.get(for_code.lb)?;
+        let ub = self.get(for_code.ub)?;
+        if !self.less(lb, ub) {
+            return Ok(Flow::Yield);
+        }
+        let iter_type = DataType::parse(&lb.get_type());
+        self.frame[for_code.iter_name] = Some(lb);
+        for (&arg, &init) in zip(&for_code.iter_args, &for_code.iter_inits) {
+            self.frame[arg] = Some(self.get(init)?);
+        }
+        loop {
+            if let Flow::Return = self.run(&for_code.body)? {
+                return Ok(Flow::Return);
+            }
+            let iter = self.get(for_code.iter_name)?;
+            let step = self.get(for_code.step)?;
+            let new_iter = add_as(iter_type, &[iter, step]);
+            let ub = self.get(for_code.ub)?;
+            if self.less(new_iter, ub) {
+                self.frame[for_code.iter_name] = Some(new_iter);
+                self.assign(&for_code.iter_args);
+            } else {
+                self.assign(&for_code.names);
+                self.clear(&for_code.drops);
+                return Ok(Flow::Yield);
+            }
+        }
+    }
+}
+
+/// Run the function just called to its return with the compiled backend, as
+/// a `continue` of the interpreter would with nothing to check between the
+/// steps. False if it cannot, leaving the state as it was for the
+/// interpreter to run.
+fn run_compiled() -> bool {
+    let checks = step_checks(true);
+    if checks.breakpoints
+        || checks.returns
+        || checks.watches
+        || checks.tracepoints
+        || have_halt_watchpoint()
+        || verbose(Verbosity::Trace)
+        || profile::profile_enabled()
+        || !sim().current_stack.is_empty()
+    {
+        return false;
+    }
+    let name = {
+        let sim = sim();
+        let [Controller::Function(function)] = sim.operation_stack.as_slice() else {
+            return false;
+        };
+        let function = function.borrow();
+        if function.op_now != 0 {
+            return false;
+        }
+        function.name.clone()
+    };
+    let Some(program) = sim().program.clone() else {
+        return false;
+    };
+    let Some(&index) = program.function_index.get(&name) else {
+        return false;
+    };
+    auto_checkpoint(State::Software);
+    let mut guard = sim();
+    let sim = &mut *guard;
+    let saved = sim.memory.clone();
+    let mut memories: HashMap<&String, &mut Memory> = sim.memory.iter_mut().collect();
+    let Some(memories) = program
+        .memories
+        .iter()
+        .map(|name| memories.remove(name))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+    let mut frame = vec![None; program.names.len()];
+    let mut others = HashMap::new();
+    for (name, value) in &sim.variable {
+        match program.layout.slot(Name::new(name)) {
+            Some(slot) => frame[slot] = Some(*value),
+            None => {
+                others.insert(name.clone(), *value);
+            }
+        }
+    }
+    let mut machine = Machine {
+        program: &program,
+        frame,
+        memories,
+        operands: vec![],
+        values: vec![],
+        steps: 0,
+        budget: options().max_cycles.saturating_sub(1),
+        depth: 0,
+    };
+    let function = &program.functions[index];
+    let flow = machine.run(&function.body);
+    let Machine {
+        mut frame,
+        values,
+        steps,
+        memories,
+        ..
+    } = machine;
+    drop(memories);
+    if !matches!(flow, Ok(Flow::Return)) {
+        take_fault();
+        sim.memory = saved;
+        return false;
+    }
+    for &slot in &function.drops {
+        frame[slot] = None;
+    }
+    sim.variable = others;
+    sim.variable.extend(
+        zip(&program.names, frame).filter_map(|(name, value)| Some((name.to_string(), value?))),
+    );
+    sim.operation_stack.clear();
+    drop(guard);
+    advance_cycles(State::Software, steps);
+    println!("RETURN: ");
+    for val in &values {
+        println!("{:?}", val);
+    }
+    auto_checkpoint(State::Software);
+    true
 }
@@ -0,0 +1,313 @@
+//! `hestia sweep`: run one design against many argument sets and memory
+//! images on worker threads, each with a simulation of its own, and tally
+//! which runs pass and how many cycles they took.
+//!
+//! A sweep file, in JSON, JSON5 or YAML, names the IR files to load, the
+//! function to call and the runs, whose files are relative to it:
+//!
+//! ```json
+//! {
+//!   "load": ["gemm/tor.json"],
+//!   "function": "main",
+//!   "runs": [
+//!     {"name": "ones", "args": [], "memory": {"op_0": "ones.txt"},
+//!      "expect": {"op_1": "ones_out.txt"}}
+//!   ]
+//! }
+//! ```
+//!
+//! A run passes when the function returns within the cycle limit without a
+//! fault, and every `expect` file, read like `load_memory_file`, agrees with
+//! what the memory holds.
+
+use super::basetype::{PagedStore, VALUE};
+use super::common::*;
+use super::error::take_fault;
+use super::simulator::scratch;
+use serde_json::{json, Value};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+struct Run {
+    name: String,
+    args: Vec<String>,
+    /// Memory images loaded before the call.
+    memory: Vec<(String, String)>,
+    /// Memory images the memories should hold after the run.
+    expect: Vec<(String, String)>,
+}
+
+struct Sweep {
+    /// The IR of each file, read once and loaded by every run.
+    files: Vec<(String, State, Value)>,
+    function: String,
+    runs: Vec<Run>,
+}
+
+pub struct Outcome {
+    pub name: String,
+    pub passed: bool,
+    pub cycles: usize,
+    /// Why the run failed.
+    pub error: Option<String>,
+    pub output: String,
+}
+
+fn strings(value: &Value, field: &str) -> Result<Vec<String>, String> {
+    match value {
+        Value::Null => Ok(vec![]),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(text) => Ok(text.clone()),
+                Value::Number(number) => Ok(number.to_string()),
+                _ => Err(format!("{} holds {}, not a string", field, item)),
+            })
+            .collect(),
+        _ => Err(format!("{} is not a list", field)),
+    }
+}
+
+/// The `memory name: file` pairs of `field`, with the files made relative
+/// to `dir`.
+fn images(run: &Value, field: &str, dir: &Path) -> Result<Vec<(String, String)>, String> {
+    match &run[field] {
+        Value::Null => Ok(vec![]),
+        Value::Object(images) => images
+            .iter()
+            .map(|(memory, path)| match path.as_str() {
+                Some(path) => Ok((memory.clone(), dir.join(path).to_string_lossy().into())),
+                None => Err(format!("{} of {} is not a file name", field, memory)),
+            })
+            .collect(),
+        _ => Err(format!("{} is not a map of memories to files", field)),
+    }
+}
+
+fn read_sweep(path: &str) -> Result<Sweep, String> {
+    let text = super::decompress::read_to_string(path).map_err(|err| err.to_string())?;
+    let sweep = parse_document(path, &text).map_err(|err| err.to_string())?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut files = vec![];
+    for file in strings(&sweep["load"], "load")? {
+        let file = dir.join(file).to_string_lossy().to_string();
+        let (level, ir) = read_ir(&file).map_err(|err| format!("{}: {}", file, err))?;
+        files.push((file, level, ir));
+    }
+    if files.is_empty() {
+        return Err("nothing to load".to_string());
+    }
+    let mut runs = vec![];
+    for (index, run) in sweep["runs"].as_array().into_iter().flatten().enumerate() {
+        let name = match &run["name"] {
+            Value::String(name) => name.clone(),
+            _ => format!("run_{}", index),
+        };
+        runs.push(Run {
+            args: strings(&run["args"], &format!("args of {}", name))?,
+            memory: images(run, "memory", dir)?,
+            expect: images(run, "expect", dir)?,
+            name,
+        });
+    }
+    Ok(Sweep {
+        files,
+        function: sweep["function"].as_str().unwrap_or("main").to_string(),
+        runs,
+    })
+}
+
+fn has_memory(memory: &str) -> bool {
+    memory_levels()
+        .into_iter()
+        .any(|level| memory_store(level, memory).is_some())
+}
+
+/// The first word of the memory images in `expect` that the memories do not
+/// hold, found by loading each image over its memory.
+fn compare(expect: &[(String, String)]) -> Result<(), String> {
+    for (memory, path) in expect {
+        let before = memory_levels()
+            .into_iter()
+            .filter_map(|level| Some((level, memory_store(level, memory)?)))
+            .collect::<Vec<_>>();
+        if before.is_empty() {
+            return Err(format!("Undefined memory {}", memory));
+        }
+        load_memory_file(memory, path).map_err(|err| format!("{}: {}", path, err))?;
+        for (level, before) in before {
+            let Some(after) = memory_store(level, memory) else {
+                continue;
+            };
+            let differences = before.differences(&after, usize::MAX);
+            let Some(&index) = differences.first() else {
+                continue;
+            };
+            let word = |store: &PagedStore| store.get(index).unwrap_or(VALUE::ERROR);
+            let mut error = format!(
+                "{}[{}] is {:?}, expected {:?}",
+                memory,
+                index,
+                word(&before),
+                word(&after)
+            );
+            if differences.len() > 1 {
+                error += &format!(", {} words differ", differences.len());
+            }
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+/// Load the design, call the function and run it, all on a new simulation.
+fn simulate_run(sweep: &Sweep, run: &Run, cycles: usize) -> (usize, Result<(), String>) {
+    scratch(|| {
+        for (path, level, ir) in &sweep.files {
+            if let Err(err) = load_ir(path, *level, ir) {
+                return (0, Err(format!("{}: {}", path, err)));
+            }
+        }
+        for (memory, path) in &run.memory {
+            if !has_memory(memory) {
+                return (0, Err(format!("Undefined memory {}", memory)));
+            }
+            if let Err(err) = load_memory_file(memory, path) {
+                return (0, Err(format!("{}: {}", path, err)));
+            }
+        }
+        let args = run.args.iter().map(String::as_str).collect::<Vec<_>>();
+        if let Err(err) = call_function(&sweep.function, &args) {
+            return (0, Err(err));
+        }
+        let level = debug_state();
+        let failed = failed_on_thread(|| step(cycles));
+        let cycles_run = cycle_count(level);
+        let result = if let Some(error) = take_fault() {
+            Err(error.to_string())
+        } else if failed {
+            Err("failed".to_string())
+        } else if !finished(level) {
+            Err(format!(
+                "{} did not return in {} cycles",
+                sweep.function, cycles
+            ))
+        } else {
+            compare(&run.expect)
+        };
+        (cycles_run, result)
+    })
+}
+
+fn execute(sweep: &Sweep, run: &Run, cycles: usize) -> Outcome {
+    let mut result = (0, Ok(()));
+    let output = buffer_output(None, || {
+        result = catch_unwind(AssertUnwindSafe(|| simulate_run(sweep, run, cycles)))
+            .unwrap_or_else(|panic| {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                (0, Err(format!("panicked: {}", message)))
+            });
+    });
+    let (cycles, result) = result;
+    // A failed step says why in its output
+    let error = result.err().map(|error| {
+        output
+            .lines()
+            .rev()
+            .find_map(|line| line.strip_prefix("!!"))
+            .filter(|_| error == "failed")
+            .map_or(error, str::to_string)
+    });
+    Outcome {
+        name: run.name.clone(),
+        passed: error.is_none(),
+        cycles,
+        error,
+        output,
+    }
+}
+
+/// Run every run of the sweep in `path` for up to `cycles` cycles on
+/// `threads` workers, in the order they are listed.
+pub fn run_sweep(path: &str, threads: usize, cycles: usize) -> Result<Vec<Outcome>, String> {
+    let sweep = read_sweep(path)?;
+    let next = AtomicUsize::new(0);
+    let outcomes = Mutex::new((0..sweep.runs.len()).map(|_| None).collect::<Vec<_>>());
+    std::thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, sweep.runs.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(run) = sweep.runs.get(index) else {
+                    break;
+                };
+                let outcome = execute(&sweep, run, cycles);
+                outcomes.lock().unwrap()[index] = Some(outcome);
+            });
+        }
+    });
+    Ok(outcomes
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// `hestia sweep`: run a sweep and print how each run went.
+pub fn sweep(path: &str, threads: usize, cycles: usize) {
+    let outcomes = match run_sweep(path, threads, cycles) {
+        Ok(outcomes) => outcomes,
+        Err(err) => {
+            println!("!!Failed to load sweep {}: {}", path, err);
+            return fail(Failure::Command);
+        }
+    };
+    let passed = outcomes.iter().filter(|outcome| outcome.passed).count();
+    let total: usize = outcomes.iter().map(|outcome| outcome.cycles).sum();
+    if passed < outcomes.len() {
+        fail(Failure::Command);
+    }
+    if json_output() {
+        let runs = outcomes
+            .iter()
+            .map(|outcome| {
+                json!({
+                    "name": outcome.name,
+                    "passed": outcome.passed,
+                    "cycles": outcome.cycles,
+                    "error": outcome.error,
+                    "output": outcome.output,
+                })
+            })
+            .collect::<Vec<_>>();
+        return print_json(json!({
+            "runs": runs,
+            "passed": passed,
+            "failed": outcomes.len() - passed,
+            "cycles": total,
+        }));
+    }
+    let rows = outcomes
+        .iter()
+        .map(|outcome| {
+            let status = match &outcome.error {
+                None => format!("pass, {} cycles", outcome.cycles),
+                Some(error) => format!("FAIL, {} cycles: {}", outcome.cycles, error),
+            };
+            (outcome.name.clone(), status)
+        })
+        .collect::<Vec<_>>();
+    print_table("", &rows);
+    println!(
+        "Passed {} of {} runs, {} cycles in all",
+        passed,
+        outcomes.len(),
+        total
+    );
+}
@@ -1,68 +1,98 @@
 use super::basetype::*;
 use super::common::*;
-use serde_json::Value;
+use super::equal::EqualValues;
+use super::error::{
+    backing_field, check_functions, fault, in_bounds, str_field, take_fault, type_field,
+    unassigned, unknown_index, ErrorCode, HestiaError,
+};
+use super::intern::{Layout, Name, Slots};
+use super::perfetto::{activity, instance_track, perfetto_enabled};
+use super::profile::{self, measure};
+use super::simulator;
+use super::vcd::{record, sample, vcd_enabled, Sample};
+use half::{bf16, f16};
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::cell::RefMut;
 use std::cmp::max;
 use std::collections::VecDeque;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::iter::zip;
+use std::ops::{Deref, Range};
 use std::vec;
 
+/// The operand named by `ir`, bound to the value of a constant of the
+/// loaded file.
+fn operand(ir: &Value) -> Operand {
+    let mut operand = Operand::new(ir.as_str().unwrap());
+    operand.bind(&sim().constant);
+    operand
+}
+
+fn operands(ir: &Value) -> Vec<Operand> {
+    let mut operands = Operand::list(ir);
+    for operand in &mut operands {
+        operand.bind(&sim().constant);
+    }
+    operands
+}
+
 #[derive(Debug, Clone)]
 struct CallOp {
-    names: Vec<String>,
+    names: Vec<Name>,
     function: String,
-    operands: Vec<String>,
+    operands: Vec<Operand>,
 }
 
 impl CallOp {
     fn new(ir: &Value) -> Self {
         CallOp {
-            names: construct(&ir["names"]),
+            names: Name::list(&ir["names"]),
             function: String::from(ir["function"].as_str().unwrap()),
-            operands: construct(&ir["operands"]),
+            operands: operands(&ir["operands"]),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 struct ReturnOp {
-    operands: Vec<String>,
+    operands: Vec<Operand>,
 }
 
 impl ReturnOp {
     fn new(ir: &Value) -> Self {
         ReturnOp {
-            operands: construct(&ir["operands"]),
+            operands: operands(&ir["operands"]),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 struct YieldOp {
-    operands: Vec<String>,
+    operands: Vec<Operand>,
 }
 
 impl YieldOp {
     fn new(ir: &Value) -> Self {
         YieldOp {
-            operands: construct(&ir["operands"]),
+            operands: operands(&ir["operands"]),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 struct LoadOp {
-    name: String,
-    index: String,
+    name: Name,
+    index: Operand,
     memory: String,
 }
 
 impl LoadOp {
     fn new(ir: &Value) -> Self {
         LoadOp {
-            name: String::from(ir["name"].as_str().unwrap()),
-            index: String::from(ir["index"].as_str().unwrap()),
+            name: Name::new(ir["name"].as_str().unwrap()),
+            index: operand(&ir["index"]),
             memory: String::from(ir["memory"].as_str().unwrap()),
         }
     }
@@ -70,14 +100,14 @@ impl LoadOp {
 
 #[derive(Debug, Clone)]
 struct ReadOp {
-    name: String,
+    name: Name,
     stream: String,
 }
 
 impl ReadOp {
     fn new(ir: &Value) -> Self {
         ReadOp {
-            name: String::from(ir["name"].as_str().unwrap()),
+            name: Name::new(ir["name"].as_str().unwrap()),
             stream: String::from(ir["stream"].as_str().unwrap()),
         }
     }
@@ -85,16 +115,16 @@ impl ReadOp {
 
 #[derive(Debug, Clone)]
 struct StoreOp {
-    value: String,
-    index: String,
+    value: Operand,
+    index: Operand,
     memory: String,
 }
 
 impl StoreOp {
     fn new(ir: &Value) -> Self {
         StoreOp {
-            value: String::from(ir["value"].as_str().unwrap()),
-            index: String::from(ir["index"].as_str().unwrap()),
+            value: operand(&ir["value"]),
+            index: operand(&ir["index"]),
             memory: String::from(ir["memory"].as_str().unwrap()),
         }
     }
@@ -102,14 +132,14 @@ impl StoreOp {
 
 #[derive(Debug, Clone)]
 struct WriteOp {
-    value: String,
+    value: Operand,
     stream: String,
 }
 
 impl WriteOp {
     fn new(ir: &Value) -> Self {
         WriteOp {
-            value: String::from(ir["data"].as_str().unwrap()),
+            value: operand(&ir["data"]),
             stream: String::from(ir["stream"].as_str().unwrap()),
         }
     }
@@ -117,25 +147,25 @@ impl WriteOp {
 
 #[derive(Debug, Clone)]
 struct ForOp {
-    iter_args: Vec<String>,
-    iter_inits: Vec<String>,
-    lb: String,
-    ub: String,
-    step: String,
-    names: Vec<String>,
-    iter_name: String,
+    iter_args: Vec<Name>,
+    iter_inits: Vec<Operand>,
+    lb: Operand,
+    ub: Operand,
+    step: Operand,
+    names: Vec<Name>,
+    iter_name: Name,
 }
 
 impl ForOp {
     fn new(ir: &Value) -> Self {
         ForOp {
-            iter_args: construct(&ir["iter_args"]),
-            iter_inits: construct(&ir["iter_inits"]),
-            lb: String::from(ir["lb"].as_str().unwrap()),
-            ub: String::from(ir["ub"].as_str().unwrap()),
-            step: String::from(ir["step"].as_str().unwrap()),
-            iter_name: String::from(ir["iter_name"].as_str().unwrap()),
-            names: construct(&ir["names"]),
+            iter_args: Name::list(&ir["iter_args"]),
+            iter_inits: operands(&ir["iter_inits"]),
+            lb: operand(&ir["lb"]),
+            ub: operand(&ir["ub"]),
+            step: operand(&ir["step"]),
+            iter_name: Name::new(ir["iter_name"].as_str().unwrap()),
+            names: Name::list(&ir["names"]),
         }
     }
 }
@@ -144,8 +174,8 @@ impl ForOp {
 struct IfOp {
     _start: usize,
     end: usize,
-    condition: String,
-    names: Vec<String>,
+    condition: Operand,
+    names: Vec<Name>,
     latency: Option<u32>,
 }
 
@@ -154,8 +184,8 @@ impl IfOp {
         IfOp {
             _start: ir["start"].as_u64().unwrap() as usize,
             end: ir["end"].as_u64().unwrap() as usize,
-            condition: String::from(ir["condition"].as_str().unwrap()),
-            names: construct(&ir["names"]),
+            condition: operand(&ir["condition"]),
+            names: Name::list(&ir["names"]),
             latency: None,
         }
     }
@@ -175,45 +205,73 @@ enum Operation {
 
 impl Operation {
     fn eval(&self, env: &mut Env) {
-        // println!("Begin EVAL {:?}", self);
+        let (kind, name) = match self {
+            Operation::Compute(compute) => ("compute", compute.name.as_str()),
+            Operation::Load(load) => ("load", load.name.as_str()),
+            Operation::Store(store) => ("store", store.memory.as_str()),
+            Operation::Read(read) => ("read", read.name.as_str()),
+            Operation::Write(write) => ("write", write.stream.as_str()),
+            _ => return self.run(env),
+        };
+        measure(kind, name, || self.run(env))
+    }
+
+    fn run(&self, env: &mut Env) {
+        tracing::trace!(op = ?self, "eval");
         match self {
             Operation::Compute(compute) => {
                 operation_env(compute, env);
-                if have_equalpoint() && contain_equalpoint(&compute.name) {
-                    unsafe { &mut *EQUALVALUE }
-                        .insert(compute.name.clone(), env.get_value(&compute.name));
+                if let Some(index) = equal_index(compute.name) {
+                    sim().equal_value.insert(index, env.get_value(compute.name));
                 }
             }
             Operation::Load(load) => {
                 let name = &load.name;
                 let memory = &load.memory;
-                let index = env.get_value(&load.index);
-                env.set_value(name, get_mem_value(memory, index.as_u64() as usize));
+                let index = env.read(load.index);
+                env.set_value(*name, get_mem_value(memory, index));
             }
             Operation::Store(store) => {
                 let value = &store.value;
                 let memory = &store.memory;
-                let index = env.get_value(&store.index);
-                set_mem_value(memory, index.as_u64() as usize, env.get_value(value));
+                let index = env.read(store.index);
+                set_mem_value(memory, index, env.read(*value));
             }
             Operation::Read(read) => {
                 let name = &read.name;
                 let stream = &read.stream;
-                env.set_value(name, pop_stream_value(stream));
+                let value = pop_stream_value(stream);
+                if stream_watched() {
+                    record_stream(stream, "pop", value, name);
+                }
+                env.set_value(*name, value);
             }
             Operation::Write(write) => {
-                let value = &write.value;
+                let value = env.read(write.value);
                 let stream = &write.stream;
-                push_stream_value(stream, env.get_value(value));
+                if stream_watched() {
+                    record_stream(stream, "push", value, write.value.name());
+                }
+                push_stream_value(stream, value);
             }
             _ => (),
         }
     }
 
-    fn mark(&self) -> bool {
+    /// The variable the operation writes, if any.
+    fn result(&self) -> Option<Name> {
+        match self {
+            Operation::Compute(compute) => Some(compute.name),
+            Operation::Load(load) => Some(load.name),
+            Operation::Read(read) => Some(read.name),
+            _ => None,
+        }
+    }
+
+    fn mark(&self, env: &Env) -> bool {
         match self {
-            Operation::Compute(compute) => test_breakpoint(&compute.name),
-            Operation::Load(load) => test_breakpoint(&load.name),
+            Operation::Compute(compute) => test_breakpoint(&compute.name, &|var| env.lookup(var)),
+            Operation::Load(load) => test_breakpoint(&load.name, &|var| env.lookup(var)),
             Operation::Store(_) => false,
             _ => false,
         }
@@ -222,8 +280,10 @@ impl Operation {
 
 #[derive(Debug, Clone)]
 enum Strategy {
-    Static(TimeGraph),
-    PipelineFor(TimeGraph, u32),
+    Static(Arc<TimeGraphDef>),
+    /// The time graph, the initiation interval and the pipeline as each
+    /// instance starts it, whose branch latencies are worked out once.
+    PipelineFor(Arc<TimeGraphDef>, u32, PipelineTimeGraph),
     PipelineFunc(u32),
     Dynamic,
     Dataflow(Vec<String>),
@@ -234,7 +294,8 @@ enum Strategy {
 struct Edge {
     to: usize,
     edge_type: String,
-    ops: Vec<Operation>,
+    /// The operations bound to the edge, in the `ops` of its time graph.
+    ops: Range<usize>,
     stream: Vec<StreamOperation>,
 }
 
@@ -251,6 +312,13 @@ impl StreamOperation {
             StreamOperation::Write(write) => !stream_full(&write.stream),
         }
     }
+
+    fn describe(&self) -> String {
+        match self {
+            StreamOperation::Read(read) => format!("reading {}", read.stream),
+            StreamOperation::Write(write) => format!("writing {}", write.stream),
+        }
+    }
 }
 
 impl Edge {
@@ -258,7 +326,7 @@ impl Edge {
         Edge {
             to: ir["to"].as_u64().unwrap() as usize,
             edge_type: String::from(ir["type"].as_str().unwrap()),
-            ops: vec![],
+            ops: 0..0,
             stream: vec![],
         }
     }
@@ -272,10 +340,17 @@ impl Edge {
         }
     }
 
-    fn check_stream(&self) -> bool {
+    /// `owner` is the function the edge belongs to, reported if it stalls.
+    fn check_stream(&self, owner: &str) -> bool {
         for stream in &self.stream {
             if !stream.check() {
-                println!("Stall for {:?}", &stream);
+                if stall_print() {
+                    println!(
+                        "{}",
+                        paint(Color::Yellow, format!("Stall for {:?}", &stream))
+                    );
+                }
+                stall(owner, &stream.describe());
                 return false;
             }
         }
@@ -283,64 +358,84 @@ impl Edge {
     }
 }
 
+/// The time graph of a function with the operations bound to its edges,
+/// shared by all instances of the function.
 #[derive(Debug, Clone)]
-struct TimeGraph {
+struct TimeGraphDef {
     start: usize,
     end: usize,
-    current: (usize, u32),
     graph: Vec<Vec<Edge>>,
     node: Vec<Control>,
-    if_node: Vec<Option<(IfOp, Vec<YieldOp>, bool)>>,
+    if_node: Vec<Option<(IfOp, Vec<YieldOp>)>>,
     name: String,
+    /// The operations of every edge, those of one edge next to each other.
+    ops: Vec<Operation>,
+    /// Operations bound to the edge at `(node, index)` while parsing, moved
+    /// into `ops` by `pack`.
+    bound: Vec<(usize, usize, Operation)>,
+    /// The memories and streams the operations use, found by `pack`.
+    footprint: Vec<String>,
+}
+
+/// Where an instance of a static function is in its time graph.
+#[derive(Debug, Clone)]
+struct TimeGraph {
+    def: Arc<TimeGraphDef>,
+    current: (usize, u32),
+    /// The ends of the `if`s the instance has reached.
+    reached: HashSet<usize>,
+}
+
+impl Deref for TimeGraph {
+    type Target = TimeGraphDef;
+
+    fn deref(&self) -> &TimeGraphDef {
+        &self.def
+    }
 }
 
+/// The stages of an instance of a pipelined loop in its time graph.
 #[derive(Debug, Clone)]
 struct PipelineTimeGraph {
-    _start: usize,
-    end: usize,
+    def: Arc<TimeGraphDef>,
     stages: Vec<(usize, u32, u32, bool)>,
-    graph: Vec<Vec<Edge>>,
-    node: Vec<Control>,
-    if_node: Vec<Option<(IfOp, Vec<YieldOp>, bool)>>,
     finish: bool,
     for_id: usize,
-    name: String,
+}
+
+impl Deref for PipelineTimeGraph {
+    type Target = TimeGraphDef;
+
+    fn deref(&self) -> &TimeGraphDef {
+        &self.def
+    }
 }
 
 impl PipelineTimeGraph {
-    fn new(graph: &TimeGraph) -> Self {
-        let mut own = PipelineTimeGraph {
-            _start: graph.start,
-            end: graph.end,
-            stages: vec![(graph.start, 0, 0, false)],
-            graph: graph.graph.clone(),
-            node: graph.node.clone(),
-            if_node: graph.if_node.clone(),
+    fn new(graph: &TimeGraphDef) -> Self {
+        let mut def = graph.clone();
+        for idx in 0..def.node.len() {
+            if let Control::BeginIf(_) = def.node[idx] {
+                def.get_aligned_latency(idx);
+            }
+        }
+        PipelineTimeGraph {
+            stages: vec![(def.start, 0, 0, false)],
+            def: Arc::new(def),
             finish: false,
             for_id: 0,
-            name: graph.name.clone(),
-        };
-
-        for idx in 0..own.node.len() {
-            if let Control::BeginIf(_) = own.node[idx] {
-                own.get_aligned_latency(idx);
-            }
         }
-
-        own
     }
 
     fn check_stream_one_stage(&self, index: usize, cur: usize, env: &Vec<Env>) -> bool {
-        if let Some((_, _, flag)) = &self.if_node[cur] {
-            if !self.stages[index].3 {
-                return true;
-            }
+        if self.if_node[cur].is_some() && !self.stages[index].3 {
+            return true;
         }
         match &self.node[cur] {
-            Control::Normal => self.graph[cur][0].check_stream(),
+            Control::Normal => self.graph[cur][0].check_stream(&self.name),
             Control::BeginFor(for_op) => {
-                let lb = env[index].get_value(&for_op.lb);
-                let ub = env[index].get_value(&for_op.ub);
+                let lb = env[index].read(for_op.lb);
+                let ub = env[index].read(for_op.ub);
                 if let VALUE::Bool(true) = cmp_lte(vec![lb, ub].as_ref()) {
                     for edge in &self.graph[cur] {
                         if edge.edge_type != "static-for" {
@@ -353,7 +448,7 @@ impl PipelineTimeGraph {
                 panic!("not found edge")
             }
             Control::BeginIf(if_op) => {
-                let cond = env[index].get_value(&if_op.condition);
+                let cond = env[index].read(if_op.condition);
                 if let VALUE::Bool(true) = cond {
                     if self.graph[cur][0].edge_type == "static"
                         || self.stages[index].1 > self.graph[cur][0].get_latency()
@@ -389,20 +484,21 @@ impl PipelineTimeGraph {
     }
 
     fn step_one_stage(&mut self, index: usize, env: &mut Vec<Env>) -> STEP {
+        let def = self.def.clone();
         let cur = self.stages[index];
-        println!("STEP {} {:?}", index, cur);
-        if let Some((if_op, yield_op, _)) = &mut self.if_node[cur.0] {
+        tracing::trace!(stage = index, node = ?cur, "step");
+        if let Some((if_op, yield_op)) = &def.if_node[cur.0] {
             if !cur.3 {
-                let cond = env[index].get_value(&if_op.condition);
+                let cond = env[index].read(if_op.condition);
                 let branch = if let VALUE::Bool(true) = cond { 0 } else { 1 };
                 if yield_op.len() > 1 {
                     let values = yield_op[branch]
                         .operands
                         .iter()
-                        .map(|x| env[index].get_value(x))
+                        .map(|x| env[index].read(*x))
                         .collect::<Vec<VALUE>>();
                     for (name, value) in zip(&if_op.names, values) {
-                        env[index].set_value(&name, value);
+                        env[index].set_value(*name, value);
                     }
                 }
                 self.stages[index].3 = true;
@@ -410,9 +506,9 @@ impl PipelineTimeGraph {
             } else {
             }
         }
-        match &mut self.node[cur.0] {
+        match &def.node[cur.0] {
             Control::Normal => {
-                let edge = &self.graph[cur.0][0];
+                let edge = &def.graph[cur.0][0];
                 /*if edge.ops.len() == 0 {
                     self.stages[index] = (edge.to, 0, cur.2);
                     return self.step_one_stage(index, env);
@@ -421,8 +517,11 @@ impl PipelineTimeGraph {
                     self.stages[index].1 += 1;
                     self.stages[index].2 += 1;
                     if cur.1 + 1 == edge.get_latency() {
-                        for op in &edge.ops {
+                        for op in def.edge_ops(edge) {
                             op.eval(&mut env[index]);
+                            if vcd_enabled() {
+                                record_write(&def.name, op, &env[index]);
+                            }
                         }
                         self.stages[index].0 = edge.to;
                         self.stages[index].1 = 0;
@@ -433,15 +532,15 @@ impl PipelineTimeGraph {
             Control::BeginFor(for_op) => {
                 self.stages[index].2 += 1;
                 self.for_id = cur.0;
-                let lb = env[index].get_value(&for_op.lb);
-                let ub = env[index].get_value(&for_op.ub);
+                let lb = env[index].read(for_op.lb);
+                let ub = env[index].read(for_op.ub);
                 if let VALUE::Bool(true) = cmp_lte(vec![lb, ub].as_ref()) {
-                    env[index].set_value(&for_op.iter_name, lb);
+                    env[index].set_value(for_op.iter_name, lb);
                     for (arg, init) in zip(&for_op.iter_args, &for_op.iter_inits) {
-                        let value = env[index].get_value(init);
-                        env[index].set_value(arg, value);
+                        let value = env[index].read(*init);
+                        env[index].set_value(*arg, value);
                     }
-                    for edge in &self.graph[cur.0] {
+                    for edge in &def.graph[cur.0] {
                         if edge.edge_type != "static-for" {
                             self.stages[index].0 = edge.to;
                             self.stages[index].1 = 0;
@@ -450,7 +549,7 @@ impl PipelineTimeGraph {
                         }
                     }
                 } else {
-                    for edge in &self.graph[cur.0] {
+                    for edge in &def.graph[cur.0] {
                         if edge.edge_type == "static-for" {
                             self.stages[index].0 = edge.to;
                             self.stages[index].1 = 0;
@@ -463,24 +562,24 @@ impl PipelineTimeGraph {
             }
             Control::BeginIf(if_op) => {
                 self.stages[index].2 += 1;
-                let cond = env[index].get_value(&if_op.condition);
+                let cond = env[index].read(if_op.condition);
                 if let VALUE::Bool(true) = cond {
                     self.stages[index].1 += 1;
-                    if self.graph[cur.0][0].edge_type == "static"
-                        || self.stages[index].1 > self.graph[cur.0][0].get_latency()
+                    if def.graph[cur.0][0].edge_type == "static"
+                        || self.stages[index].1 > def.graph[cur.0][0].get_latency()
                     {
-                        self.stages[index].0 = self.graph[cur.0][0].to;
+                        self.stages[index].0 = def.graph[cur.0][0].to;
                         self.stages[index].1 = 0;
                         self.stages[index].3 = false;
                         return self.step_one_stage(index, env);
                     }
                 } else {
                     self.stages[index].1 += 1;
-                    if self.graph[cur.0].len() > 1 {
-                        if self.graph[cur.0][1].edge_type == "static"
-                            || self.stages[index].1 > self.graph[cur.0][1].get_latency()
+                    if def.graph[cur.0].len() > 1 {
+                        if def.graph[cur.0][1].edge_type == "static"
+                            || self.stages[index].1 > def.graph[cur.0][1].get_latency()
                         {
-                            self.stages[index].0 = self.graph[cur.0][1].to;
+                            self.stages[index].0 = def.graph[cur.0][1].to;
                             self.stages[index].1 = 0;
                             self.stages[index].3 = false;
                             return self.step_one_stage(index, env);
@@ -503,18 +602,18 @@ impl PipelineTimeGraph {
                     .unwrap()
                     .operands
                     .iter()
-                    .map(|x| env[index].get_value(x))
+                    .map(|x| env[index].read(*x))
                     .collect::<Vec<VALUE>>();
                 if self.stages.len() == 1 {
                     for (name, value) in zip(&for_op.names, values) {
-                        env[index].set_value(&name, value);
+                        env[index].set_value(*name, value);
                     }
                 } else {
                     for (arg, value) in zip(&for_op.iter_args, values) {
-                        env[index + 1].set_value(arg, value);
+                        env[index + 1].set_value(*arg, value);
                     }
                 }
-                for edge in &self.graph[*start] {
+                for edge in &def.graph[*start] {
                     if edge.edge_type == "static-for" {
                         self.stages[index].0 = edge.to;
                         self.stages[index].1 = 0;
@@ -527,7 +626,7 @@ impl PipelineTimeGraph {
                 let values = return_op
                     .operands
                     .iter()
-                    .map(|op| env[index].get_value(op))
+                    .map(|op| env[index].read(*op))
                     .collect();
                 return STEP::Return(values);
             }
@@ -550,7 +649,7 @@ impl PipelineTimeGraph {
         step
     }
 
-    fn mark_one_stage(&self, index: usize) -> bool {
+    fn mark_one_stage(&self, index: usize, env: &Env) -> bool {
         let cur = self.stages[index];
 
         match &self.node[cur.0] {
@@ -558,8 +657,8 @@ impl PipelineTimeGraph {
                 let edge = &self.graph[cur.0][0];
                 if edge.ops.len() > 0 {
                     if cur.1 + 1 == edge.get_latency() {
-                        for op in &edge.ops {
-                            if op.mark() {
+                        for op in self.edge_ops(edge) {
+                            if op.mark(env) {
                                 return true;
                             }
                         }
@@ -570,7 +669,7 @@ impl PipelineTimeGraph {
             Control::BeginFor(for_op) => {
                 let names = &for_op.names;
                 for name in names {
-                    if test_breakpoint(name) {
+                    if test_breakpoint(name, &|var| env.lookup(var)) {
                         return true;
                     }
                 }
@@ -579,7 +678,7 @@ impl PipelineTimeGraph {
             Control::BeginIf(if_op) => {
                 let names = &if_op.names;
                 for name in names {
-                    if test_breakpoint(name) {
+                    if test_breakpoint(name, &|var| env.lookup(var)) {
                         return true;
                     }
                 }
@@ -590,9 +689,9 @@ impl PipelineTimeGraph {
         }
     }
 
-    fn mark(&self) -> bool {
+    fn mark(&self, env: &Vec<Env>) -> bool {
         for index in 0..self.stages.len() {
-            if self.mark_one_stage(index) {
+            if self.mark_one_stage(index, &env[index]) {
                 return true;
             }
         }
@@ -600,20 +699,21 @@ impl PipelineTimeGraph {
     }
 
     fn pipeline(&mut self, ii: u32, env: &mut Vec<Env>) {
+        let def = self.def.clone();
         if !self.finish {
             let last_stage = self.stages.last().unwrap();
             if last_stage.2 == ii + 1 {
-                if let Control::BeginFor(for_op) = &self.node[self.for_id] {
-                    let iter_type = env.last().unwrap().get_value(&for_op.lb).get_type();
-                    let iter_name = env.last().unwrap().get_value(&for_op.iter_name);
-                    let step = env.last().unwrap().get_value(&for_op.step);
+                if let Control::BeginFor(for_op) = &def.node[self.for_id] {
+                    let iter_type = env.last().unwrap().read(for_op.lb).get_type();
+                    let iter_name = env.last().unwrap().get_value(for_op.iter_name);
+                    let step = env.last().unwrap().read(for_op.step);
                     let new_iter = add(&iter_type, vec![iter_name, step].as_ref());
-                    let ub = env.last().unwrap().get_value(&for_op.ub);
+                    let ub = env.last().unwrap().read(for_op.ub);
                     if let VALUE::Bool(true) = cmp_lte(vec![new_iter, ub].as_ref()) {
                         let mut new_env = env.last().unwrap().clone();
-                        new_env.set_value(&for_op.iter_name, new_iter);
+                        new_env.set_value(for_op.iter_name, new_iter);
                         env.push(new_env);
-                        for edge in &self.graph[self.for_id] {
+                        for edge in &def.graph[self.for_id] {
                             if edge.edge_type != "static-for" {
                                 self.stages.push((edge.to, 0, 1, false));
                                 break;
@@ -627,63 +727,6 @@ impl PipelineTimeGraph {
         }
     }
 
-    fn get_latency(&mut self, start: usize, end: usize) -> u32 {
-        if start == end {
-            return 0;
-        }
-        let mut queue = VecDeque::from(vec![start]);
-        let mut dis = HashMap::new();
-        dis.insert(start, 0);
-
-        while !queue.is_empty() {
-            let cur = queue.pop_front().unwrap();
-            let cur_dis = dis.get(&cur).unwrap().to_owned();
-            if let Control::BeginIf(if_op) = &self.node[cur].clone() {
-                let aligned_dis = self.get_aligned_latency(cur);
-                queue.push_back(if_op.end);
-                dis.insert(if_op.end, cur_dis + aligned_dis);
-                if let Control::BeginIf(if_op) = &mut self.node[cur] {
-                    if_op.latency = Some(aligned_dis);
-                }
-            } else {
-                for edge in &self.graph[cur] {
-                    if edge.to == end {
-                        return edge.get_latency() + cur_dis;
-                    }
-                    if dis.get(&edge.to).is_none() {
-                        queue.push_back(edge.to);
-                        dis.insert(edge.to, cur_dis + edge.get_latency());
-                    }
-                }
-            }
-        }
-        panic!("Latency Not Found")
-    }
-
-    fn get_aligned_latency(&mut self, node: usize) -> u32 {
-        if let Control::BeginIf(if_op) = &self.node[node].clone() {
-            let path0 = self.get_latency(self.graph[node][0].to, if_op.end);
-            let path1 = if self.graph[node].len() == 1 {
-                0
-            } else {
-                self.get_latency(self.graph[node][1].to, if_op.end)
-            };
-            if path0 > path1 {
-                if self.graph[node].len() > 1 {
-                    self.graph[node][1].edge_type =
-                        String::from("static:") + &(path0 - path1).to_string();
-                }
-            } else if path0 < path1 {
-                self.graph[node][0].edge_type =
-                    String::from("static:") + &(path1 - path0).to_string();
-            }
-
-            max(path0, path1)
-        } else {
-            panic!("Latency Not Found")
-        }
-    }
-
     fn drop(&mut self, env: &mut Vec<Env>) -> STEP {
         if self.stages.len() > 1 {
             let first_stage = self.stages.first().unwrap();
@@ -719,19 +762,21 @@ enum STEP {
     Call(String, Vec<VALUE>),
 }
 
-impl TimeGraph {
+impl TimeGraphDef {
     fn new(ir: &Value) -> Self {
         let timegraph = &ir["graph"];
         let start = timegraph["start"].as_u64().unwrap() as usize;
         let end = timegraph["end"].as_u64().unwrap() as usize;
-        let mut graph = TimeGraph {
+        let mut graph = TimeGraphDef {
             start,
             end,
-            current: (start, 0),
             graph: vec![Vec::new(); end as usize + 1],
             node: vec![Control::Normal; end as usize + 1],
             if_node: vec![None; end as usize + 1],
             name: ir["name"].as_str().unwrap().to_string(),
+            ops: vec![],
+            bound: vec![],
+            footprint: vec![],
         };
         for edge in timegraph["edge"].as_array().unwrap() {
             graph.add_edge(edge)
@@ -739,167 +784,283 @@ impl TimeGraph {
         graph
     }
 
+    /// Move the operations bound while parsing into `ops`, those of each edge
+    /// next to each other in the order they were bound.
+    fn pack(&mut self) {
+        let mut bound = std::mem::take(&mut self.bound);
+        bound.sort_by_key(|(node, index, _)| (*node, *index));
+        self.ops = Vec::with_capacity(bound.len());
+        for (node, index, op) in bound {
+            let edge = &mut self.graph[node][index];
+            if edge.ops.is_empty() {
+                edge.ops = self.ops.len()..self.ops.len();
+            }
+            edge.ops.end += 1;
+            self.ops.push(op);
+        }
+        let mut footprint = self
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Load(load) => Some(&load.memory),
+                Operation::Store(store) => Some(&store.memory),
+                Operation::Read(read) => Some(&read.stream),
+                Operation::Write(write) => Some(&write.stream),
+                _ => None,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        footprint.sort();
+        footprint.dedup();
+        self.footprint = footprint;
+    }
+
+    fn edge_ops(&self, edge: &Edge) -> &[Operation] {
+        &self.ops[edge.ops.clone()]
+    }
+
     fn add_edge(&mut self, ir: &Value) {
         let edge = Edge::new(&ir);
         let from = ir["from"].as_u64().unwrap() as usize;
         self.graph[from].push(edge)
     }
 
-    fn add_operation(&mut self, start: usize, end: usize, op: Operation) {
+    fn add_operation(
+        &mut self,
+        start: usize,
+        end: usize,
+        op: Operation,
+    ) -> Result<(), HestiaError> {
         let mut queue = VecDeque::from(vec![start]);
         let mut vis = HashSet::new();
 
         while !queue.is_empty() {
             let cur = queue.pop_front().unwrap();
             vis.insert(cur);
-            for edge in &mut self.graph[cur] {
+            for (index, edge) in self.graph[cur].iter_mut().enumerate() {
                 if edge.to == end {
                     match op.clone() {
                         Operation::Read(read) => edge.stream.push(StreamOperation::Read(read)),
                         Operation::Write(write) => edge.stream.push(StreamOperation::Write(write)),
                         _ => (),
                     };
-                    edge.ops.push(op);
-                    return;
+                    self.bound.push((cur, index, op));
+                    return Ok(());
                 }
                 if vis.get(&edge.to).is_none() {
                     queue.push_back(edge.to);
                 }
             }
         }
-        panic!("{:?}", op);
+        Err(HestiaError::new(
+            ErrorCode::UnboundOperation,
+            format!(
+                "no edge from {} to {} for {:?} in function '{}'",
+                start, end, op, self.name
+            ),
+        ))
     }
 
-    fn insert_time(&mut self, ir: &Value, op: Operation) {
+    fn insert_time(&mut self, ir: &Value, op: Operation) -> Result<(), HestiaError> {
         let start = ir["start"].as_u64().unwrap() as usize;
         let end = ir["end"].as_u64().unwrap() as usize;
-        for edge in &mut self.graph[start] {
+        for (index, edge) in self.graph[start].iter_mut().enumerate() {
             if edge.to == end {
                 match op.clone() {
                     Operation::Read(read) => edge.stream.push(StreamOperation::Read(read)),
                     Operation::Write(write) => edge.stream.push(StreamOperation::Write(write)),
                     _ => (),
                 };
-                edge.ops.push(op);
-                return;
+                self.bound.push((start, index, op));
+                return Ok(());
             }
         }
-        self.add_operation(start, end, op);
+        self.add_operation(start, end, op)
     }
 
-    fn bind_operation(&mut self, ir: &Value) {
-        match ir["op_type"].as_str().unwrap() {
+    fn bind_operation(&mut self, ir: &Value) -> Result<(), HestiaError> {
+        match str_field(ir, "op_type")? {
             "for" => {
                 let start = ir["start"].as_u64().unwrap() as usize;
                 let end = ir["end"].as_u64().unwrap() as usize;
                 let for_op = ForOp::new(&ir);
                 self.node[start] = Control::BeginFor(for_op.clone());
                 self.node[end] = Control::EndFor(for_op.clone(), None, 0);
-                for edge in &mut self.graph[start] {
-                    if edge.edge_type != "static-for" {
-                        edge.ops.push(Operation::For(for_op));
-                        break;
-                    }
-                }
-                ir["body"]
-                    .as_array()
-                    .unwrap()
+                let index = self.graph[start]
                     .iter()
-                    .for_each(|x| self.bind_operation(x));
+                    .position(|edge| edge.edge_type != "static-for");
+                if let Some(index) = index {
+                    self.bound.push((start, index, Operation::For(for_op)));
+                }
+                for x in ir["body"].as_array().unwrap() {
+                    self.bind_operation(x)?;
+                }
             }
             "if" => {
                 let start = ir["start"].as_u64().unwrap() as usize;
                 let end = ir["end"].as_u64().unwrap() as usize;
-                let if_op = IfOp::new(&ir);
+                let if_op = IfOp::new(ir);
                 self.node[start] = Control::BeginIf(if_op.clone());
-                self.if_node[end] = Some((if_op.clone(), vec![], false));
-                ir["body0"]
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .for_each(|x| self.bind_operation(x));
-                ir["body1"]
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .for_each(|x| self.bind_operation(x));
+                self.if_node[end] = Some((if_op.clone(), vec![]));
+                for x in ir["body0"].as_array().unwrap() {
+                    self.bind_operation(x)?;
+                }
+                for x in ir["body1"].as_array().unwrap() {
+                    self.bind_operation(x)?;
+                }
             }
             "return" => {
                 let time = ir["time"].as_u64().unwrap() as usize;
-                self.node[time] = Control::Return(ReturnOp::new(&ir));
+                self.node[time] = Control::Return(ReturnOp::new(ir));
             }
             "yield" => {
                 let time = ir["time"].as_u64().unwrap() as usize;
-                if let Some((_, op, _)) = &mut self.if_node[time] {
+                if let Some((_, op)) = &mut self.if_node[time] {
                     if op.len() < 2 {
-                        op.push(YieldOp::new(&ir));
-                        return;
+                        op.push(YieldOp::new(ir));
+                        return Ok(());
                     }
                 }
                 if let Control::EndFor(_, op, start) = &mut self.node[time] {
-                    *op = Some(YieldOp::new(&ir));
+                    *op = Some(YieldOp::new(ir));
                     *start = ir["jump"].as_u64().unwrap() as usize;
                 }
             }
             "call" => {
                 let time = ir["start"].as_u64().unwrap() as usize;
-                self.node[time] = Control::Call(CallOp::new(&ir));
+                self.node[time] = Control::Call(CallOp::new(ir));
+            }
+            "load" => self.insert_time(ir, Operation::Load(LoadOp::new(ir)))?,
+            "store" => self.insert_time(ir, Operation::Store(StoreOp::new(ir)))?,
+            "read" => self.insert_time(ir, Operation::Read(ReadOp::new(ir)))?,
+            "write" => self.insert_time(ir, Operation::Write(WriteOp::new(ir)))?,
+            _ => {
+                let mut compute = Computation::new(ir)?;
+                compute.bind(&sim().constant);
+                self.insert_time(ir, Operation::Compute(compute))?
+            }
+        }
+        Ok(())
+    }
+
+    fn get_latency(&mut self, start: usize, end: usize) -> u32 {
+        if start == end {
+            return 0;
+        }
+        let mut queue = VecDeque::from(vec![start]);
+        let mut dis = HashMap::new();
+        dis.insert(start, 0);
+
+        while !queue.is_empty() {
+            let cur = queue.pop_front().unwrap();
+            let cur_dis = dis.get(&cur).unwrap().to_owned();
+            if let Control::BeginIf(if_op) = &self.node[cur].clone() {
+                let aligned_dis = self.get_aligned_latency(cur);
+                queue.push_back(if_op.end);
+                dis.insert(if_op.end, cur_dis + aligned_dis);
+                if let Control::BeginIf(if_op) = &mut self.node[cur] {
+                    if_op.latency = Some(aligned_dis);
+                }
+            } else {
+                for edge in &self.graph[cur] {
+                    if edge.to == end {
+                        return edge.get_latency() + cur_dis;
+                    }
+                    if dis.get(&edge.to).is_none() {
+                        queue.push_back(edge.to);
+                        dis.insert(edge.to, cur_dis + edge.get_latency());
+                    }
+                }
+            }
+        }
+        panic!("Latency Not Found")
+    }
+
+    fn get_aligned_latency(&mut self, node: usize) -> u32 {
+        if let Control::BeginIf(if_op) = &self.node[node].clone() {
+            let path0 = self.get_latency(self.graph[node][0].to, if_op.end);
+            let path1 = if self.graph[node].len() == 1 {
+                0
+            } else {
+                self.get_latency(self.graph[node][1].to, if_op.end)
+            };
+            if path0 > path1 {
+                if self.graph[node].len() > 1 {
+                    self.graph[node][1].edge_type =
+                        String::from("static:") + &(path0 - path1).to_string();
+                }
+            } else if path0 < path1 {
+                self.graph[node][0].edge_type =
+                    String::from("static:") + &(path1 - path0).to_string();
             }
-            "load" => self.insert_time(ir, Operation::Load(LoadOp::new(&ir))),
-            "store" => self.insert_time(ir, Operation::Store(StoreOp::new(&ir))),
-            "read" => self.insert_time(ir, Operation::Read(ReadOp::new(&ir))),
-            "write" => self.insert_time(ir, Operation::Write(WriteOp::new(&ir))),
-            _ => self.insert_time(ir, Operation::Compute(Computation::new(&ir))),
+
+            max(path0, path1)
+        } else {
+            panic!("Latency Not Found")
+        }
+    }
+}
+
+impl TimeGraph {
+    fn new(def: Arc<TimeGraphDef>) -> Self {
+        TimeGraph {
+            current: (def.start, 0),
+            def,
+            reached: HashSet::new(),
         }
     }
 
     fn get_call(&mut self, values: Vec<VALUE>, env: &mut Env) {
-        if let Control::Call(call_op) = &self.node[self.current.0] {
+        if let Control::Call(call_op) = &self.def.node[self.current.0] {
             for (name, value) in zip(&call_op.names, values) {
-                env.set_value(name, value);
+                env.set_value(*name, value);
             }
-            self.current = (self.graph[self.current.0][0].to, 0);
+            self.current = (self.def.graph[self.current.0][0].to, 0);
         }
     }
 
     fn step(&mut self, mini: bool, env: &mut Env) -> STEP {
+        let def = self.def.clone();
         let cur = self.current;
-        if let Some((if_op, yield_op, flag)) = &mut self.if_node[cur.0] {
+        if let Some((if_op, yield_op)) = &def.if_node[cur.0] {
             if cur.1 == 0 {
-                let cond = env.get_value(&if_op.condition);
+                let cond = env.read(if_op.condition);
                 let branch = if let VALUE::Bool(true) = cond { 0 } else { 1 };
                 if yield_op.len() > 1 {
                     let values = yield_op[branch]
                         .operands
                         .iter()
-                        .map(|x| env.get_value(x))
+                        .map(|x| env.read(*x))
                         .collect::<Vec<VALUE>>();
                     for (name, value) in zip(&if_op.names, values) {
-                        env.set_value(&name, value);
+                        env.set_value(*name, value);
                     }
                 }
-                *flag = true;
+                self.reached.insert(cur.0);
                 // return STEP::Nothing;
             } /* else {
                   *flag = false;
               }*/
         }
-        match &self.node[cur.0] {
+        match &def.node[cur.0] {
             Control::Normal => {
-                let edge = &self.graph[cur.0][0];
+                let edge = &def.graph[cur.0][0];
                 /*if edge.ops.len() == 0 {
                     self.current = (edge.to, 0);
                     return self.step(mini, env);
                 } else */
-                if edge.check_stream() {
+                if edge.check_stream(&def.name) {
                     if mini {
                         self.current.1 += 1;
                     }
                     if (!mini) || (self.current.1 == edge.get_latency()) {
                         let last_cycle = self.current.1;
-                        for op in &edge.ops {
+                        for op in def.edge_ops(edge) {
                             // println!("{:?}", op);
                             op.eval(env);
+                            if vcd_enabled() {
+                                record_write(&def.name, op, env);
+                            }
                         }
                         self.current = (edge.to, 0);
                         if !mini {
@@ -910,21 +1071,21 @@ impl TimeGraph {
                 STEP::Nothing
             }
             Control::BeginFor(for_op) => {
-                let lb = env.get_value(&for_op.lb);
-                let ub = env.get_value(&for_op.ub);
+                let lb = env.read(for_op.lb);
+                let ub = env.read(for_op.ub);
                 if let VALUE::Bool(true) = cmp_lte(vec![lb, ub].as_ref()) {
-                    env.set_value(&for_op.iter_name, lb);
+                    env.set_value(for_op.iter_name, lb);
                     for (arg, init) in zip(&for_op.iter_args, &for_op.iter_inits) {
-                        env.set_value(arg, env.get_value(init));
+                        env.set_value(*arg, env.read(*init));
                     }
-                    for edge in &self.graph[cur.0] {
+                    for edge in &def.graph[cur.0] {
                         if edge.edge_type != "static-for" {
                             self.current = (edge.to, 0);
                             break;
                         }
                     }
                 } else {
-                    for edge in &self.graph[cur.0] {
+                    for edge in &def.graph[cur.0] {
                         if edge.edge_type == "static-for" {
                             self.current = (edge.to, 0);
                             break;
@@ -934,15 +1095,15 @@ impl TimeGraph {
                 STEP::Nothing
             }
             Control::BeginIf(if_op) => {
-                let cond = env.get_value(&if_op.condition);
+                let cond = env.read(if_op.condition);
                 if let VALUE::Bool(true) = cond {
-                    self.current = (self.graph[cur.0][0].to, 0);
+                    self.current = (def.graph[cur.0][0].to, 0);
                     // return self.step(mini, env);
                 } else {
-                    if self.graph[cur.0].len() == 1 {
+                    if def.graph[cur.0].len() == 1 {
                         self.current = (if_op.end, 0);
                     } else {
-                        self.current = (self.graph[cur.0][1].to, 0);
+                        self.current = (def.graph[cur.0][1].to, 0);
                     }
                     // return self.step(mini, env);
                 }
@@ -950,11 +1111,7 @@ impl TimeGraph {
             }
             Control::Call(call_op) => STEP::Call(
                 call_op.function.clone(),
-                call_op
-                    .operands
-                    .iter()
-                    .map(|op| env.get_value(op))
-                    .collect(),
+                call_op.operands.iter().map(|op| env.read(*op)).collect(),
             ),
             Control::EndFor(for_op, yield_op, start) => {
                 if mini && self.current.1 == 0 {
@@ -968,19 +1125,19 @@ impl TimeGraph {
                     .unwrap()
                     .operands
                     .iter()
-                    .map(|x| env.get_value(x))
+                    .map(|x| env.read(*x))
                     .collect::<Vec<VALUE>>();
-                let iter_type = env.get_value(&for_op.lb).get_type();
-                let iter_name = env.get_value(&for_op.iter_name);
-                let step = env.get_value(&for_op.step);
+                let iter_type = env.read(for_op.lb).get_type();
+                let iter_name = env.get_value(for_op.iter_name);
+                let step = env.read(for_op.step);
                 let new_iter = add(&iter_type, vec![iter_name, step].as_ref());
-                let ub = env.get_value(&for_op.ub);
+                let ub = env.read(for_op.ub);
                 if let VALUE::Bool(true) = cmp_lte(vec![new_iter, ub].as_ref()) {
-                    env.set_value(&for_op.iter_name, new_iter);
+                    env.set_value(for_op.iter_name, new_iter);
                     for (arg, value) in zip(&for_op.iter_args, values) {
-                        env.set_value(arg, value);
+                        env.set_value(*arg, value);
                     }
-                    for edge in &self.graph[*start] {
+                    for edge in &def.graph[*start] {
                         if edge.edge_type != "static-for" {
                             self.current = (edge.to, 0);
                             break;
@@ -988,9 +1145,9 @@ impl TimeGraph {
                     }
                 } else {
                     for (name, value) in zip(&for_op.names, values) {
-                        env.set_value(&name, value);
+                        env.set_value(*name, value);
                     }
-                    for edge in &self.graph[*start] {
+                    for edge in &def.graph[*start] {
                         if edge.edge_type == "static-for" {
                             self.current = (edge.to, 0);
                             break;
@@ -999,25 +1156,21 @@ impl TimeGraph {
                 }
                 STEP::Count(2 - cycle)
             }
-            Control::Return(return_op) => STEP::Return(
-                return_op
-                    .operands
-                    .iter()
-                    .map(|op| env.get_value(op))
-                    .collect(),
-            ),
+            Control::Return(return_op) => {
+                STEP::Return(return_op.operands.iter().map(|op| env.read(*op)).collect())
+            }
         }
     }
 
-    fn mark(&self, mini: bool) -> bool {
+    fn mark(&self, mini: bool, env: &Env) -> bool {
         let cur = self.current;
 
         match &self.node[cur.0] {
             Control::Normal => {
                 let edge = &self.graph[cur.0][0];
                 if (!mini) || (self.current.1 + 1 == edge.get_latency()) {
-                    for op in &edge.ops {
-                        if op.mark() {
+                    for op in self.edge_ops(edge) {
+                        if op.mark(env) {
                             return true;
                         }
                     }
@@ -1027,7 +1180,7 @@ impl TimeGraph {
             Control::BeginFor(for_op) => {
                 let names = &for_op.names;
                 for name in names {
-                    if test_breakpoint(name) {
+                    if test_breakpoint(name, &|var| env.lookup(var)) {
                         return true;
                     }
                 }
@@ -1036,7 +1189,7 @@ impl TimeGraph {
             Control::BeginIf(if_op) => {
                 let names = &if_op.names;
                 for name in names {
-                    if test_breakpoint(name) {
+                    if test_breakpoint(name, &|var| env.lookup(var)) {
                         return true;
                     }
                 }
@@ -1045,7 +1198,7 @@ impl TimeGraph {
             Control::Call(call_op) => {
                 let names = &call_op.names;
                 for name in names {
-                    if test_breakpoint(name) {
+                    if test_breakpoint(name, &|var| env.lookup(var)) {
                         return true;
                     }
                 }
@@ -1058,55 +1211,70 @@ impl TimeGraph {
 
 #[derive(Debug, Clone)]
 struct Env {
-    variable: HashMap<String, VALUE>,
+    variable: Slots<VALUE>,
+    /// The function the instance runs
+    function: Name,
 }
 
 impl IsEnv for Env {
-    fn get_value(&self, var: &String) -> VALUE {
+    fn get_value(&self, var: Name) -> VALUE {
         // println!("{}", var);
-        if let Some(val) = self.variable.get(var) {
-            val.clone()
-        } else {
-            unsafe { &*CONSTANT }.get(var).unwrap().clone()
+        match self.variable.get(var) {
+            Some(value) => *value,
+            None => {
+                fault(unassigned(&var));
+                VALUE::ERROR
+            }
         }
     }
 
-    fn set_value(&mut self, var: &String, value: VALUE) {
-        self.variable.insert(var.to_owned(), value);
+    fn set_value(&mut self, var: Name, value: VALUE) {
+        self.variable.insert(var, value);
     }
 
-    fn get_all_value(&self, ops: &Vec<String>) -> Vec<VALUE> {
-        ops.iter().map(|x| self.get_value(x)).collect()
+    fn instance(&self) -> String {
+        self.function.to_string()
     }
 }
 
 impl Env {
-    fn new() -> Self {
+    fn new(layout: &Arc<Layout>, function: &str) -> Self {
         Env {
-            variable: HashMap::new(),
+            variable: Slots::new(layout.clone()),
+            function: Name::new(function),
         }
     }
+
+    /// The value of a variable or constant, for the shell. Operations read
+    /// constants bound to their operands instead.
+    fn lookup(&self, var: &str) -> Option<VALUE> {
+        let var = Name::new(var);
+        let value = self.variable.get(var).copied();
+        value.or_else(|| sim().constant.get(var).copied())
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Function {
     strategy: Strategy,
-    args: Vec<String>,
+    args: Vec<Name>,
     types: Vec<String>,
     name: String,
+    /// The slots of the arguments and the variables of the time graph in the
+    /// environment of each instance.
+    layout: Arc<Layout>,
 }
 
 impl Function {
-    fn new(ir: &Value) -> Self {
+    fn new(ir: &Value) -> Result<Self, HestiaError> {
         let strategy = match ir["strategy"].as_str().unwrap() {
             "static" => {
-                let mut graph = TimeGraph::new(&ir);
-                ir["body"]
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .for_each(|x| graph.bind_operation(x));
-                Strategy::Static(graph)
+                let mut graph = TimeGraphDef::new(&ir);
+                for x in ir["body"].as_array().unwrap() {
+                    graph.bind_operation(x)?;
+                }
+                graph.pack();
+                Strategy::Static(Arc::new(graph))
             }
             "dynamic" => Strategy::Dynamic,
             "dataflow" => {
@@ -1123,37 +1291,48 @@ impl Function {
                 )
             }
             str => {
-                let mut graph = TimeGraph::new(&ir);
-                ir["body"]
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .for_each(|x| graph.bind_operation(x));
+                let mut graph = TimeGraphDef::new(&ir);
+                for x in ir["body"].as_array().unwrap() {
+                    graph.bind_operation(x)?;
+                }
+                graph.pack();
                 let string = str.split(" ").collect::<Vec<&str>>();
                 if string[1] == "for" {
-                    Strategy::PipelineFor(graph, string[2].parse::<u32>().unwrap())
+                    let pipeline = PipelineTimeGraph::new(&graph);
+                    let ii = string[2].parse::<u32>().unwrap();
+                    Strategy::PipelineFor(Arc::new(graph), ii, pipeline)
                 } else {
                     Strategy::PipelineFunc(string[2].parse::<u32>().unwrap())
                 }
             }
         };
-        Function {
+        let args = Name::list(&ir["args"]);
+        let mut names = args.clone();
+        if let Strategy::Static(graph) | Strategy::PipelineFor(graph, _, _) = &strategy {
+            graph.collect_names(&mut names);
+        }
+        let mut layout = Layout::default();
+        for name in names {
+            layout.insert(name);
+        }
+        Ok(Function {
             strategy: strategy,
-            args: construct(&ir["args"]),
+            args,
             types: construct(&ir["types"]),
             name: ir["name"].as_str().unwrap().to_string(),
-        }
+            layout: Arc::new(layout),
+        })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct StaticInstance {
     graph: TimeGraph,
     env: Env,
     active: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct PipeForInstance {
     graph: PipelineTimeGraph,
     env: Vec<Env>,
@@ -1171,21 +1350,62 @@ impl PipeForInstance {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Instance {
-    Static(StaticInstance, Option<Arc<RefCell<Instance>>>),
-    PipeFor(PipeForInstance, Option<Arc<RefCell<Instance>>>),
+    Static(StaticInstance, Option<Rc<RefCell<Instance>>>),
+    PipeFor(PipeForInstance, Option<Rc<RefCell<Instance>>>),
 }
 
-impl Instance {
+/// What an instance steps, without the caller it returns to, to be stepped
+/// on a worker thread.
+enum Body<'a> {
+    Static(&'a mut StaticInstance),
+    PipeFor(&'a mut PipeForInstance),
+}
+
+impl Body<'_> {
+    fn function_name(&self) -> &str {
+        match self {
+            Body::Static(instance) => &instance.graph.name,
+            Body::PipeFor(instance) => &instance.graph.name,
+        }
+    }
+
     fn step(&mut self, mini: bool) -> STEP {
-        // println!("BEGIN STEP");
         match self {
-            Instance::Static(instance, _) => instance.graph.step(mini, &mut instance.env),
-            Instance::PipeFor(instance, _) => instance.step(),
+            Body::Static(instance) => instance.graph.step(mini, &mut instance.env),
+            Body::PipeFor(instance) => instance.step(),
+        }
+    }
+}
+
+impl Instance {
+    fn function_name(&self) -> &str {
+        match self {
+            Instance::Static(instance, _) => &instance.graph.name,
+            Instance::PipeFor(instance, _) => &instance.graph.name,
+        }
+    }
+
+    /// The memories and streams the instance uses.
+    fn footprint(&self) -> &[String] {
+        match self {
+            Instance::Static(instance, _) => &instance.graph.footprint,
+            Instance::PipeFor(instance, _) => &instance.graph.footprint,
         }
     }
 
+    fn body(&mut self) -> Body<'_> {
+        match self {
+            Instance::Static(instance, _) => Body::Static(instance),
+            Instance::PipeFor(instance, _) => Body::PipeFor(instance),
+        }
+    }
+
+    fn step(&mut self, mini: bool) -> STEP {
+        self.body().step(mini)
+    }
+
     fn get_call(&mut self, values: Vec<VALUE>) {
         match self {
             Instance::Static(instance, _) => instance.graph.get_call(values, &mut instance.env),
@@ -1213,86 +1433,122 @@ impl Instance {
 
     fn mark(&self, mini: bool) -> bool {
         match self {
-            Instance::Static(instance, _) => instance.graph.mark(mini),
-            Instance::PipeFor(instance, _) => instance.graph.mark(),
+            Instance::Static(instance, _) => instance.graph.mark(mini, &instance.env),
+            Instance::PipeFor(instance, _) => instance.graph.mark(&instance.env),
         }
     }
 }
 
 use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::Arc;
 
-static mut MEMORY: *mut HashMap<String, Memory> = std::ptr::null_mut();
-static mut STREAM: *mut HashMap<String, Stream> = std::ptr::null_mut();
-static mut ACTIVE: *mut Vec<Arc<RefCell<Instance>>> = std::ptr::null_mut();
-// static mut STALL: *mut Vec<Arc<RefCell<Instance>>> = std::ptr::null_mut();
-static mut FUNCTION: *mut HashMap<String, Function> = std::ptr::null_mut();
-static mut CONSTANT: *mut HashMap<String, VALUE> = std::ptr::null_mut();
-
-static mut EQUALPOINT: *mut HashSet<String> = std::ptr::null_mut();
-static mut EQUALVALUE: *mut HashMap<String, VALUE> = std::ptr::null_mut();
+/// The ToR simulation: the loaded functions, memories and streams and the
+/// instances running them.
+#[derive(Default)]
+pub struct TorSim {
+    memory: HashMap<String, Memory>,
+    stream: HashMap<String, Stream>,
+    active: Vec<Rc<RefCell<Instance>>>,
+    /// Cycles spent at each node of the time graph of each function since
+    /// the run was started by `call`.
+    node_cycles: HashMap<String, Vec<usize>>,
+    function: HashMap<String, Rc<Function>>,
+    constant: Slots<VALUE>,
+    /// The comparison of each operation compared in a cosim.
+    equal_point: HashMap<Name, usize>,
+    equal_value: EqualValues,
+}
 
-pub fn set_equalpoint(b: String) {
-    unsafe { &mut (*EQUALPOINT) }.insert(b);
+fn sim() -> RefMut<'static, TorSim> {
+    simulator::current().tor.borrow_mut()
 }
 
-pub fn have_equalpoint() -> bool {
-    !unsafe { &*EQUALPOINT }.is_empty()
+/// Compare the operations of `points` in a cosim, with `count` comparisons.
+pub fn set_equalpoints(points: HashMap<Name, usize>, count: usize) {
+    sim().equal_point = points;
+    sim().equal_value = EqualValues::new(count);
 }
 
-pub fn contain_equalpoint(a: &String) -> bool {
-    unsafe { &*EQUALPOINT }.contains(a)
+fn equal_index(name: Name) -> Option<usize> {
+    // `independent` leaves instances in order during a cosim
+    if on_worker() {
+        return None;
+    }
+    let points = &sim().equal_point;
+    if points.is_empty() {
+        return None;
+    }
+    points.get(&name).copied()
 }
 
 pub fn parse_tor(ir: &Value) -> Result<(), Box<dyn Error>> {
-    unsafe {
-        init_global(&mut ACTIVE);
-        // init_global(&mut STALL);
-        init_global(&mut FUNCTION);
-        init_global(&mut MEMORY);
-        init_global(&mut STREAM);
-        init_global(&mut CONSTANT);
-
-        init_global(&mut EQUALPOINT);
-        init_global(&mut EQUALVALUE);
-    }
+    *sim() = TorSim::default();
+    check_functions(ir)?;
     if let Value::Array(memorys) = &ir["memory"] {
         for memory in memorys {
             let name = memory["name"].as_str().unwrap();
             let size = memory["size"].as_u64().unwrap();
-            unsafe {
-                let data_type = memory["type"].as_str().unwrap();
-                match data_type {
-                    "i32" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::I32(0); size as usize]),
-                    ),
-                    "i64" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::I64(0); size as usize]),
-                    ),
-                    "u32" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::U32(0); size as usize]),
-                    ),
-                    "u64" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::U64(0); size as usize]),
-                    ),
-                    "f32" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::F32(0.0); size as usize]),
-                    ),
-                    "f64" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::F64(0.0); size as usize]),
-                    ),
-                    "bool" => (*MEMORY).insert(
-                        String::from(name),
-                        Memory::new(vec![VALUE::Bool(false); size as usize]),
-                    ),
-                    _ => None,
-                };
+            let data_type = type_field(memory, "type")?;
+            match data_type {
+                "i8" => sim()
+                    .memory
+                    .insert(String::from(name), Memory::new(VALUE::I8(0), size as usize)),
+                "u8" => sim()
+                    .memory
+                    .insert(String::from(name), Memory::new(VALUE::U8(0), size as usize)),
+                "i16" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::I16(0), size as usize),
+                ),
+                "u16" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::U16(0), size as usize),
+                ),
+                "i32" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::I32(0), size as usize),
+                ),
+                "i64" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::I64(0), size as usize),
+                ),
+                "u32" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::U32(0), size as usize),
+                ),
+                "u64" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::U64(0), size as usize),
+                ),
+                "index" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::Index(0), size as usize),
+                ),
+                "f16" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::F16(f16::ZERO), size as usize),
+                ),
+                "bf16" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::BF16(bf16::ZERO), size as usize),
+                ),
+                "f32" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::F32(0.0), size as usize),
+                ),
+                "f64" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::F64(0.0), size as usize),
+                ),
+                "bool" => sim().memory.insert(
+                    String::from(name),
+                    Memory::new(VALUE::Bool(false), size as usize),
+                ),
+                _ => None,
+            };
+            if let Some(backing) = backing_field(memory, size as usize)? {
+                sim().memory.get_mut(name).unwrap().set_backing(backing);
             }
         }
     }
@@ -1300,63 +1556,67 @@ pub fn parse_tor(ir: &Value) -> Result<(), Box<dyn Error>> {
         for stream in streams {
             let name = stream["name"].as_str().unwrap();
             let depth = stream["depth"].as_u64().unwrap();
-            unsafe {
-                (*STREAM).insert(String::from(name), Stream::new(depth as usize));
-            }
+            sim()
+                .stream
+                .insert(String::from(name), Stream::new(depth as usize));
         }
     }
     if let Value::Array(constants) = &ir["constants"] {
         for constant in constants {
-            let constant = Constant::new(&constant);
-            unsafe { &mut *CONSTANT }.insert(
-                constant.name,
+            let constant = Constant::new(constant)?;
+            sim().constant.insert(
+                Name::new(&constant.name),
                 build_value(&constant.ret_type, &constant.value),
             );
         }
     }
     if let Value::Array(modules) = &ir["modules"] {
         for module in modules {
-            unsafe {
-                (*FUNCTION).insert(
-                    String::from(module["name"].as_str().unwrap()),
-                    Function::new(&module),
-                );
-            }
+            let function = Rc::new(Function::new(module)?);
+            sim()
+                .function
+                .insert(String::from(module["name"].as_str().unwrap()), function);
         }
     }
     Ok(())
 }
 
 pub fn call_function(function_name: &str, args: &[&str]) -> Result<(), String> {
-    println!("Call {} with arguments: {:?}", function_name, args);
-    let function = unsafe { (*FUNCTION).get(&String::from(function_name)) };
+    if verbose(Verbosity::Normal) {
+        println!("Call {} with arguments: {:?}", function_name, args);
+    }
+    let function = sim().function.get(&String::from(function_name)).cloned();
     match function {
         None => Err(String::from("!!Undefined function")),
         Some(module) => {
-            let mut env = Env::new();
+            if sim().active.is_empty() {
+                sim().node_cycles.clear();
+            }
+            let mut env = Env::new(&module.layout, &module.name);
             for ((arg, arg_type), value) in zip(zip(&module.args, &module.types), args) {
-                env.set_value(arg, build_value(arg_type, &String::from(value.to_owned())))
+                env.set_value(*arg, build_value(arg_type, &String::from(value.to_owned())))
             }
             match &module.strategy {
                 Strategy::Static(graph) => {
                     let new_module = StaticInstance {
-                        graph: graph.clone(),
+                        graph: TimeGraph::new(graph.clone()),
                         active: true,
                         env,
                     };
                     let instance = Instance::Static(new_module, None);
-                    unsafe { &mut (*ACTIVE) }.push(Arc::new(RefCell::new(instance)));
+                    sim().active.push(Rc::new(RefCell::new(instance)));
                 }
-                Strategy::PipelineFor(graph, ii) => {
+                Strategy::PipelineFor(_, ii, pipeline) => {
                     let mut instance = PipeForInstance {
-                        graph: PipelineTimeGraph::new(graph),
+                        graph: pipeline.clone(),
                         env: vec![],
                         ii: *ii,
                         active: true,
                     };
                     instance.env.push(env);
-                    unsafe { &mut (*ACTIVE) }
-                        .push(Arc::new(RefCell::new(Instance::PipeFor(instance, None))));
+                    sim()
+                        .active
+                        .push(Rc::new(RefCell::new(Instance::PipeFor(instance, None))));
                 }
                 Strategy::Dataflow(functions) => {
                     println!("{:#?}", module);
@@ -1371,47 +1631,54 @@ pub fn call_function(function_name: &str, args: &[&str]) -> Result<(), String> {
     }
 }
 
-pub fn show_variable(vars: &[&str]) {
-    println!("!!SHOW VALUE:");
-    if vars.len() == 0 {
-        for instance in unsafe { &(*ACTIVE) }.iter() {
-            match &*(instance.borrow()) {
-                Instance::Static(instance, _) => {
-                    for var in &instance.env.variable {
-                        println!("\t{} {:?}", var.0, var.1);
-                    }
-                }
-                Instance::PipeFor(instance, _) => {
-                    for (index, env) in instance.env.iter().enumerate() {
-                        println!("\tStage {} at {:?}:", index, instance.graph.stages[index]);
-                        for var in &env.variable {
-                            println!("\t\t{} {:?}", var.0, var.1);
-                        }
-                    }
-                }
+fn variables_json(filter: &NameFilter) -> Value {
+    let mut instances = vec![];
+    let active = sim().active.clone();
+    for instance in &active {
+        match &*(instance.borrow()) {
+            Instance::Static(instance, _) => instances.push(json!({
+                "function": instance.graph.name,
+                "variables": variables_to_json(instance.env.variable.iter(), filter),
+            })),
+            Instance::PipeFor(instance, _) => {
+                let stages = instance.env.iter().enumerate().map(|(index, env)| {
+                    json!({
+                        "stage": instance.graph.stages[index],
+                        "variables": variables_to_json(env.variable.iter(), filter),
+                    })
+                });
+                instances.push(json!({
+                    "function": instance.graph.name,
+                    "stages": stages.collect::<Vec<Value>>(),
+                }))
             }
         }
-    } else {
-        for instance in unsafe { &(*ACTIVE) }.iter() {
-            match &*(instance.borrow()) {
-                Instance::Static(instance, _) => {
-                    for var in vars {
-                        let wrap = instance.env.variable.get(*var);
-                        if let Some(value) = wrap {
-                            println!("\t{} {:?}", var, value);
-                        }
-                    }
-                }
-                Instance::PipeFor(instance, _) => {
-                    for (index, env) in instance.env.iter().enumerate() {
-                        println!("\tStage {} at {:?}:", index, instance.graph.stages[index]);
-                        for var in vars {
-                            let wrap = env.variable.get(*var);
-                            if let Some(value) = wrap {
-                                println!("\t{} {:?}", var, value);
-                            }
-                        }
-                    }
+    }
+    Value::Array(instances)
+}
+
+pub fn show_variable(filter: &NameFilter) {
+    if json_output() {
+        return print_json(variables_json(filter));
+    }
+    println!("!!SHOW VALUE:");
+    let rows = |variable: &Slots<VALUE>| -> Vec<(String, String)> {
+        let mut rows = variable
+            .iter()
+            .filter(|(var, _)| filter.matches(var))
+            .map(|(var, value)| (var.to_string(), format!("{:?}", value)))
+            .collect::<Vec<_>>();
+        rows.sort();
+        rows
+    };
+    let active = sim().active.clone();
+    for instance in &active {
+        match &*(instance.borrow()) {
+            Instance::Static(instance, _) => print_table("\t", &rows(&instance.env.variable)),
+            Instance::PipeFor(instance, _) => {
+                for (index, env) in instance.env.iter().enumerate() {
+                    println!("\tStage {} at {:?}:", index, instance.graph.stages[index]);
+                    print_table("\t\t", &rows(&env.variable));
                 }
             }
         }
@@ -1419,73 +1686,393 @@ pub fn show_variable(vars: &[&str]) {
 }
 
 pub fn load_memory(memory: &str, store: &[&str]) {
-    let memory = unsafe { (*MEMORY).get_mut(memory).unwrap() };
-    memory.set(store)
+    sim().memory.get_mut(memory).unwrap().set(store)
 }
 
-pub fn show_memory(memorys: &[&str]) {
+pub fn show_memory(filter: &NameFilter) {
+    if json_output() {
+        let mut values = serde_json::Map::new();
+        for (mem, value) in sim().memory.iter() {
+            if filter.matches(mem) {
+                values.insert(mem.clone(), value.to_json());
+            }
+        }
+        for (stream, value) in sim().stream.iter() {
+            if filter.matches(stream) {
+                values.insert(stream.clone(), value.to_json());
+            }
+        }
+        return print_json(Value::Object(values));
+    }
     println!("!!SHOW MEMORY:");
-    if memorys.len() == 0 {
-        for (mem, value) in unsafe { &(*MEMORY) }.iter() {
-            println!("\t{} {:?}", mem, value)
+    let mut rows = vec![];
+    for (mem, value) in sim().memory.iter() {
+        if filter.matches(mem) {
+            rows.push((mem.clone(), format!("{:?}", value)));
         }
-        for (stream, value) in unsafe { &(*STREAM) }.iter() {
-            println!("\t{} {:?}", stream, value)
+    }
+    for (stream, value) in sim().stream.iter() {
+        if filter.matches(stream) {
+            rows.push((stream.clone(), format!("{:?}", value)));
         }
-    } else {
-        for name in memorys {
-            if let Some(store) = unsafe { &(*MEMORY) }.get(*name) {
-                println!("\t{} {:?}", name, store);
+    }
+    print_table("\t", &rows);
+}
+
+/// The memories and streams an instance stepping on a worker uses, taken out
+/// of the simulation for the cycle so that the worker owns them.
+#[derive(Default)]
+struct Footprint {
+    memory: HashMap<String, Memory>,
+    stream: HashMap<String, Stream>,
+    /// The stalls the instance reported, for `report_stall` once back.
+    stalls: Vec<(String, String)>,
+}
+
+impl Footprint {
+    /// Take the memories and streams of `names` out of the simulation.
+    fn take(names: &[String]) -> Self {
+        let mut footprint = Footprint::default();
+        for name in names {
+            if let Some(memory) = sim().memory.remove(name) {
+                footprint.memory.insert(name.clone(), memory);
             }
-            if let Some(store) = unsafe { &(*STREAM) }.get(*name) {
-                println!("\t{} {:?}", name, store);
+            if let Some(stream) = sim().stream.remove(name) {
+                footprint.stream.insert(name.clone(), stream);
             }
         }
+        footprint
     }
+
+    /// Put the memories and streams back and report the stalls.
+    fn restore(self) {
+        sim().memory.extend(self.memory);
+        sim().stream.extend(self.stream);
+        for (instance, stream) in self.stalls {
+            report_stall(&instance, &stream);
+        }
+    }
+}
+
+thread_local! {
+    /// The footprint of the instance stepping on this worker, if any.
+    static FOOTPRINT: RefCell<Option<Footprint>> = const { RefCell::new(None) };
+}
+
+/// Whether an instance steps on this thread apart from the simulation.
+fn on_worker() -> bool {
+    FOOTPRINT.with_borrow(Option::is_some)
+}
+
+/// Run `f` on the memory `name`, from the footprint on a worker.
+fn with_memory<R>(name: &str, f: impl FnOnce(&mut Memory) -> R) -> R {
+    FOOTPRINT.with_borrow_mut(|footprint| match footprint {
+        Some(footprint) => f(footprint.memory.get_mut(name).unwrap()),
+        None => f(sim().memory.get_mut(name).unwrap()),
+    })
+}
+
+/// Run `f` on the stream `name`, from the footprint on a worker.
+fn with_stream<R>(name: &str, f: impl FnOnce(&mut Stream) -> R) -> R {
+    FOOTPRINT.with_borrow_mut(|footprint| match footprint {
+        Some(footprint) => f(footprint.stream.get_mut(name).unwrap()),
+        None => f(sim().stream.get_mut(name).unwrap()),
+    })
 }
 
-fn get_mem_value(var: &String, index: usize) -> VALUE {
-    unsafe { &(*MEMORY) }.get(var).unwrap().get_value(index)
+/// The word at `index`, X at an X index.
+fn get_mem_value(var: &str, index: VALUE) -> VALUE {
+    if index == VALUE::X {
+        return VALUE::X;
+    }
+    let index = index.as_u64() as usize;
+    with_memory(var, |memory| {
+        if !in_bounds(var, memory, index) {
+            return VALUE::ERROR;
+        }
+        memory.get_value(index)
+    })
+}
+
+fn set_mem_value(var: &str, index: VALUE, value: VALUE) {
+    if index == VALUE::X {
+        fault(unknown_index(var));
+        return;
+    }
+    let index = index.as_u64() as usize;
+    with_memory(var, |memory| {
+        if in_bounds(var, memory, index) {
+            memory.update(index, value);
+        }
+    })
 }
 
-fn set_mem_value(var: &String, index: usize, value: VALUE) {
-    unsafe { &mut (*MEMORY) }
-        .get_mut(var)
-        .unwrap()
-        .update(index, value.clone());
+fn pop_stream_value(var: &str) -> VALUE {
+    with_stream(var, |stream| stream.pop())
 }
 
-fn pop_stream_value(var: &String) -> VALUE {
-    unsafe { &mut (*STREAM) }.get_mut(var).unwrap().pop()
+fn stream_empty(var: &str) -> bool {
+    with_stream(var, |stream| stream.is_empty())
 }
 
-fn stream_empty(var: &String) -> bool {
-    unsafe { &(*STREAM) }.get(var).unwrap().is_empty()
+fn stream_full(var: &str) -> bool {
+    with_stream(var, |stream| stream.is_full())
 }
 
-fn stream_full(var: &String) -> bool {
-    unsafe { &(*STREAM) }.get(var).unwrap().is_full()
+fn push_stream_value(var: &str, value: VALUE) {
+    with_stream(var, |stream| stream.push(value))
 }
 
-fn push_stream_value(var: &String, value: VALUE) {
-    unsafe { &mut (*STREAM) }.get_mut(var).unwrap().push(value)
+/// Report that `instance` stalls on `stream`, kept in the footprint on a
+/// worker.
+fn stall(instance: &str, stream: &str) {
+    FOOTPRINT.with_borrow_mut(|footprint| match footprint {
+        Some(footprint) => footprint
+            .stalls
+            .push((instance.to_string(), stream.to_string())),
+        None => report_stall(instance, stream),
+    })
+}
+
+/// Whether stream watches record the reads and writes, never on a worker as
+/// `independent` leaves instances with watched streams in order.
+fn stream_watched() -> bool {
+    !on_worker() && have_stream_watch()
+}
+
+/// What stepping an instance apart from the others did.
+struct Stepped {
+    step: STEP,
+    output: String,
+    fault: Option<HestiaError>,
+}
+
+/// Whether the active instances can step on threads of their own: more than
+/// one is active, none uses a memory or stream another one does, and nothing
+/// records what they do in this cycle as they do it.
+fn independent() -> bool {
+    let (active, compared) = {
+        let sim = sim();
+        (sim.active.clone(), !sim.equal_point.is_empty())
+    };
+    if active.len() < 2 || have_stream_watch() || vcd_enabled() || compared {
+        return false;
+    }
+    let mut used = HashSet::new();
+    active.iter().all(|instance| {
+        instance
+            .borrow()
+            .footprint()
+            .iter()
+            .all(|name| used.insert(name.clone()))
+    })
+}
+
+/// Step every active instance on the workers of the thread pool, in `cycle`.
+/// Each worker is handed the state of its instance and the memories and
+/// streams it uses, and reaches nothing else of the simulation.
+fn step_on_threads(active: &[Rc<RefCell<Instance>>], cycle: usize) -> Vec<Stepped> {
+    let span = tracing::Span::current();
+    let mut instances: Vec<RefMut<Instance>> = active
+        .iter()
+        .map(|instance| instance.borrow_mut())
+        .collect();
+    let work: Vec<(Body, Footprint)> = instances
+        .iter_mut()
+        .map(|instance| {
+            let footprint = Footprint::take(instance.footprint());
+            (instance.body(), footprint)
+        })
+        .collect();
+    let stepped: Vec<(Stepped, Footprint)> = work
+        .into_par_iter()
+        .map(|(mut body, footprint)| {
+            simulator::detached(|| {
+                let _cycle = span.enter();
+                let _instance =
+                    tracing::trace_span!("instance", function = body.function_name()).entered();
+                FOOTPRINT.set(Some(footprint));
+                let mut step = STEP::Nothing;
+                let output = buffer_output(Some(cycle), || {
+                    let timer = profile::timer();
+                    step = body.step(true);
+                    profile::charge(timer, "function", body.function_name());
+                });
+                let footprint = FOOTPRINT.take().unwrap();
+                let stepped = Stepped {
+                    step,
+                    output,
+                    fault: take_fault(),
+                };
+                (stepped, footprint)
+            })
+        })
+        .collect();
+    stepped
+        .into_iter()
+        .map(|(stepped, footprint)| {
+            footprint.restore();
+            stepped
+        })
+        .collect()
+}
+
+/// Step every active instance one after another, each with its output and
+/// fault kept apart like `step_on_threads` does.
+fn step_in_order(active: &[Rc<RefCell<Instance>>], cycle: usize) -> Vec<Stepped> {
+    let pending = take_fault();
+    let stepped = active
+        .iter()
+        .map(|instance| {
+            let mut step = STEP::Nothing;
+            let output = buffer_output(Some(cycle), || step = instance.borrow_mut().step(true));
+            Stepped {
+                step,
+                output,
+                fault: take_fault(),
+            }
+        })
+        .collect();
+    if let Some(error) = pending {
+        fault(error);
+    }
+    stepped
+}
+
+/// The instances, the streams they use and what stepping them did, to
+/// compare stepping them on threads with stepping them in order.
+fn describe_step(active: &[Rc<RefCell<Instance>>], stepped: &[Stepped]) -> String {
+    let mut text = String::new();
+    for (instance, stepped) in zip(active, stepped) {
+        let instance = instance.borrow();
+        text += &format!("{:?}\n{:?}\n{}\n", instance, stepped.step, stepped.output);
+        if let Some(error) = &stepped.fault {
+            text += &format!("{}\n", error);
+        }
+        for name in instance.footprint() {
+            if let Some(stream) = sim().stream.get(name) {
+                text += &format!("{}: {:?}\n", name, stream);
+            }
+        }
+    }
+    text
+}
+
+/// The memories of `names`.
+fn memories(names: &[String]) -> Vec<(String, Memory)> {
+    names
+        .iter()
+        .filter_map(|name| Some((name.clone(), sim().memory.get(name)?.clone())))
+        .collect()
+}
+
+/// Step the active instances on threads, then again in order from where they
+/// were, keeping the second and reporting a fault if the two differ.
+fn check_on_threads(active: &[Rc<RefCell<Instance>>], cycle: usize) -> Vec<Stepped> {
+    let instances: Vec<Instance> = active
+        .iter()
+        .map(|instance| instance.borrow().clone())
+        .collect();
+    let used: Vec<String> = instances
+        .iter()
+        .flat_map(|instance| instance.footprint().iter().cloned())
+        .collect();
+    let before = memories(&used);
+    let streams: Vec<(String, Stream)> = used
+        .iter()
+        .filter_map(|name| Some((name.clone(), sim().stream.get(name)?.clone())))
+        .collect();
+    let threaded = step_on_threads(active, cycle);
+    let threaded = describe_step(active, &threaded);
+    let written = memories(&used);
+    for (instance, before) in zip(active, instances) {
+        *instance.borrow_mut() = before;
+    }
+    sim().memory.extend(before);
+    sim().stream.extend(streams);
+    let stepped = step_in_order(active, cycle);
+    let same = threaded == describe_step(active, &stepped)
+        && written.iter().all(|(name, memory)| {
+            memory
+                .store()
+                .first_difference(sim().memory[name].store())
+                .is_none()
+        });
+    if !same {
+        let functions: Vec<String> = active
+            .iter()
+            .map(|instance| instance.borrow().function_name().to_string())
+            .collect();
+        fault(HestiaError::new(
+            ErrorCode::ParallelMismatch,
+            format!(
+                "stepping {} on threads differs from stepping them in order",
+                functions.join(", ")
+            ),
+        ));
+    }
+    stepped
+}
+
+/// Step the active instances apart from each other if the
+/// `parallel_instances` option allows it and `independent` holds, printing
+/// their output and raising their faults in order. Empty if they are to be
+/// stepped in the loop of `step` instead.
+fn step_apart() -> Vec<STEP> {
+    let mode = options().parallel_instances;
+    if mode == ParallelInstances::Off || !independent() {
+        return vec![];
+    }
+    let active = sim().active.clone();
+    let cycle = current_cycle();
+    let stepped = match mode {
+        ParallelInstances::Check => check_on_threads(&active, cycle),
+        _ => step_on_threads(&active, cycle),
+    };
+    stepped
+        .into_iter()
+        .map(|stepped| {
+            print!("{}", stepped.output);
+            if let Some(error) = stepped.fault {
+                fault(error);
+            }
+            stepped.step
+        })
+        .collect()
 }
 
 pub fn step(depth: usize, breakpoint: bool) -> bool {
     let mut cycle = 0;
+    let mut progress = Progress::new();
+    // The state after the last cycle, if every instance stalled in it
+    let mut stalled_state = None;
+    let checks = step_checks(breakpoint);
+    let watchpoint = get_watchpoint();
+    let watched: Vec<(&String, Name)> =
+        watchpoint.iter().map(|var| (var, Name::new(var))).collect();
     while cycle < depth {
-        if unsafe { &(*ACTIVE) }.is_empty() {
+        if interrupted() {
+            println!("!!Interrupted with cycle count: {}", cycle);
+            return false;
+        }
+        auto_checkpoint(State::ToR);
+        let _cycle = tracing::trace_span!("cycle", cycle = cycle_count(State::ToR)).entered();
+        progress.report(cycle, sim().active.len());
+        if sim().active.is_empty() {
             if breakpoint {
-                println!("Cycle count: {}", cycle);
+                if verbose(Verbosity::Normal) {
+                    println!("Cycle count: {}", cycle);
+                }
             }
             return false;
         }
 
+        let active = sim().active.clone();
         let mut mini = false;
-        if unsafe { &mut (*ACTIVE) }.len() > 1 {
+        if active.len() > 1 {
             mini = true;
         } else {
-            for instance_ref in unsafe { &mut (*ACTIVE) } {
+            for instance_ref in &active {
                 let instance = instance_ref.borrow();
                 if let Instance::PipeFor(_, _) = &*instance {
                     mini = true;
@@ -1494,8 +2081,8 @@ pub fn step(depth: usize, breakpoint: bool) -> bool {
             }
         }
 
-        if breakpoint {
-            for instance_ref in unsafe { &mut (*ACTIVE) } {
+        if checks.breakpoints {
+            for instance_ref in &active {
                 let instance = instance_ref.borrow();
                 if instance.mark(mini) {
                     println!("Break with cycle count: {}", cycle);
@@ -1503,14 +2090,36 @@ pub fn step(depth: usize, breakpoint: bool) -> bool {
                 }
             }
         }
+        trace_ops(|| {
+            for instance_ref in &active {
+                instance_ref.borrow().mark(mini);
+            }
+        });
+        let start = cycle;
         cycle += 1;
-
-        unsafe { &mut (*ACTIVE) }.drain(..).for_each(|instance| {
-            let step = instance.borrow_mut().step(true);
+        advance_cycle(State::ToR);
+
+        let mut returned = false;
+        let mut stepped = step_apart().into_iter();
+        // Instances that keep running are pushed back as they are stepped.
+        let old_active = std::mem::take(&mut sim().active);
+        old_active.into_iter().for_each(|instance| {
+            let _instance =
+                tracing::trace_span!("instance", function = instance.borrow().function_name())
+                    .entered();
+            let step = stepped.next().unwrap_or_else(|| {
+                let timer = profile::timer();
+                let step = instance.borrow_mut().step(true);
+                profile::charge(timer, "function", instance.borrow().function_name());
+                step
+            });
             match step {
                 STEP::Return(values) => {
-                    // println!("ToR Return: {:?}", values);
+                    tracing::trace!(?values, "return");
                     let mut caller = instance.borrow_mut();
+                    if checks.returns {
+                        returned |= test_return_break(caller.function_name(), &values);
+                    }
                     match &mut (*caller) {
                         Instance::Static(_, father) => match father {
                             None => (),
@@ -1519,7 +2128,7 @@ pub fn step(depth: usize, breakpoint: bool) -> bool {
                                 father.get_call(values);
                                 if !father.is_active() {
                                     father.set_active(true);
-                                    unsafe { &mut (*ACTIVE) }.push(father_ptr.clone());
+                                    sim().active.push(father_ptr.clone());
                                 }
                             }
                         },
@@ -1530,68 +2139,148 @@ pub fn step(depth: usize, breakpoint: bool) -> bool {
                                 father.get_call(values);
                                 if !father.is_active() {
                                     father.set_active(true);
-                                    unsafe { &mut (*ACTIVE) }.push(father_ptr.clone());
+                                    sim().active.push(father_ptr.clone());
                                 }
                             }
                         },
                     }
                 }
                 STEP::Call(function, args) => {
-                    let module = unsafe { &(*FUNCTION) }.get(&function).unwrap();
-                    let mut env = Env::new();
+                    tracing::trace!(callee = %function, ?args, "call");
+                    let module = sim().function[&function].clone();
+                    let mut env = Env::new(&module.layout, &module.name);
                     for (arg, value) in zip(&module.args, args) {
-                        env.set_value(arg, value)
+                        env.set_value(*arg, value)
                     }
                     instance.borrow_mut().set_active(false);
                     match &module.strategy {
                         Strategy::Static(graph) => {
                             let new_module = StaticInstance {
-                                graph: graph.clone(),
+                                graph: TimeGraph::new(graph.clone()),
                                 active: true,
                                 env,
                             };
-                            unsafe { &mut (*ACTIVE) }.push(Arc::new(RefCell::new(
-                                Instance::Static(new_module, Some(instance)),
-                            )));
+                            sim().active.push(Rc::new(RefCell::new(Instance::Static(
+                                new_module,
+                                Some(instance),
+                            ))));
                         }
-                        Strategy::PipelineFor(graph, ii) => {
+                        Strategy::PipelineFor(_, ii, pipeline) => {
                             let mut pipe_for = PipeForInstance {
-                                graph: PipelineTimeGraph::new(graph),
+                                graph: pipeline.clone(),
                                 env: vec![],
                                 ii: *ii,
                                 active: true,
                             };
                             pipe_for.env.push(env);
-                            unsafe { &mut (*ACTIVE) }.push(Arc::new(RefCell::new(
-                                Instance::PipeFor(pipe_for, Some(instance)),
-                            )));
+                            sim().active.push(Rc::new(RefCell::new(Instance::PipeFor(
+                                pipe_for,
+                                Some(instance),
+                            ))));
                         }
                         _ => (),
                     }
                 }
                 STEP::Count(count) => {
-                    cycle = cycle + (count - 1) as usize;
-                    unsafe { &mut (*ACTIVE) }.push(instance)
+                    cycle += (count - 1) as usize;
+                    advance_cycles(State::ToR, (count - 1) as usize);
+                    sim().active.push(instance)
                 }
-                _ => unsafe { &mut (*ACTIVE) }.push(instance),
+                _ => sim().active.push(instance),
             }
         });
-        if unsafe { !(*get_watchpoint()).is_empty() } {
-            for instance in unsafe { &(*ACTIVE) }.iter() {
-                match &*(instance.borrow()) {
-                    Instance::Static(instance, _) => {
-                        for var in unsafe { &*get_watchpoint() } {
-                            let wrap: Option<&VALUE> = instance.env.variable.get(var);
-                            if let Some(value) = wrap {
+        if vcd_enabled() {
+            sample_vcd(cycle - start);
+        }
+        if perfetto_enabled() {
+            sample_perfetto(cycle - start);
+        }
+        if !is_replaying() {
+            count_node_cycles(cycle - start);
+        }
+        let memory_hit = checks.watches && watch_cycle(&watched);
+        if let Some(error) = take_fault() {
+            println!("!!{} with cycle count: {}", error, cycle);
+            fail(Failure::Command);
+            return false;
+        }
+        if have_halt_watchpoint() && test_watch_halt_active() {
+            println!("Watch with cycle count: {}", cycle);
+            return false;
+        }
+        if memory_hit {
+            println!("Watch with cycle count: {}", cycle);
+            return false;
+        }
+        if returned {
+            println!("Return break with cycle count: {}", cycle);
+            return false;
+        }
+        if take_stream_hit() {
+            println!("Stream watch with cycle count: {}", cycle);
+            return false;
+        }
+        let stalled = checks.fast_forward && all_stalled(&active_functions());
+        if test_stall() {
+            println!("Stall with cycle count: {}", cycle);
+            return false;
+        }
+        if !stalled {
+            stalled_state = None;
+            continue;
+        }
+        // Every instance waits for a stream and nothing changed, so nothing will
+        let state = checkpoint().to_json();
+        if stalled_state.as_ref() == Some(&state) {
+            let skip = depth.saturating_sub(cycle);
+            advance_cycles(State::ToR, skip);
+            if !is_replaying() {
+                count_node_cycles(skip);
+            }
+            progress.skip(skip);
+            cycle = depth;
+        }
+        stalled_state = Some(state);
+    }
+    true
+}
+
+/// Print the watchpoints of the active instances and the displays after a
+/// cycle, true if a watched memory stops the step. `watched` are the
+/// watchpoints with their names interned once per step.
+fn watch_cycle(watched: &[(&String, Name)]) -> bool {
+    log_watch_csv(State::ToR);
+    let memory_hit = watch_memory(State::ToR);
+    if !watched.is_empty() {
+        let active = sim().active.clone();
+        for instance in &active {
+            match &*(instance.borrow()) {
+                Instance::Static(instance, _) => {
+                    for (var, name) in watched {
+                        let wrap: Option<&VALUE> = instance.env.variable.get(*name);
+                        if let Some(value) = wrap {
+                            if watch_table_enabled() {
+                                record_watch(format!("{} in {}", var, instance.graph.name), *value);
+                            } else {
                                 println!("{} in {}: {:?}", var, instance.graph.name, value);
                             }
                         }
                     }
-                    Instance::PipeFor(instance, _) => {
-                        for (index, env) in instance.env.iter().enumerate() {
-                            for var in unsafe { &*get_watchpoint() } {
-                                let wrap = env.variable.get(var);
-                                if let Some(value) = wrap {
+                }
+                Instance::PipeFor(instance, _) => {
+                    for (index, env) in instance.env.iter().enumerate() {
+                        for (var, name) in watched {
+                            let wrap = env.variable.get(*name);
+                            if let Some(value) = wrap {
+                                if watch_table_enabled() {
+                                    record_watch(
+                                        format!(
+                                            "{} in Stage {} of {}",
+                                            var, index, instance.graph.name
+                                        ),
+                                        *value,
+                                    );
+                                } else {
                                     println!(
                                         "\t{} in Stage {} at {:?} of {}: {:?}",
                                         var,
@@ -1607,17 +2296,265 @@ pub fn step(depth: usize, breakpoint: bool) -> bool {
                 }
             }
         }
+        if watch_table_enabled() {
+            draw_watch_table();
+        }
     }
-    true
+    if display_enabled() {
+        display_changes(snapshot());
+    }
+    memory_hit
+}
+
+/// The functions of the active instances.
+fn active_functions() -> Vec<String> {
+    sim()
+        .active
+        .iter()
+        .map(|instance| instance.borrow().function_name().to_string())
+        .collect()
+}
+
+/// Record the value written by `op` in the waveform of `graph`.
+fn record_write(graph: &str, op: &Operation, env: &Env) {
+    if let Some(name) = op.result() {
+        record(
+            format!("{}.{}", graph, name),
+            Sample::Value(env.get_value(name)),
+        );
+    }
+}
+
+/// Sample the time-graph node of every active instance, and the occupancy and
+/// node of every stage of active pipelines, into the waveform.
+fn sample_vcd(cycles: usize) {
+    let active = sim().active.clone();
+    for instance in &active {
+        match &*(instance.borrow()) {
+            Instance::Static(instance, _) => {
+                let graph = &instance.graph;
+                let node = VALUE::U32(graph.current.0 as u32);
+                sample(format!("{}.node", graph.name), Sample::Value(node));
+            }
+            Instance::PipeFor(instance, _) => {
+                let graph = &instance.graph;
+                let stages = VALUE::U32(graph.stages.len() as u32);
+                sample(format!("{}.stages", graph.name), Sample::Value(stages));
+                for (index, stage) in graph.stages.iter().enumerate() {
+                    let node = VALUE::U32(stage.0 as u32);
+                    sample(
+                        format!("{}.stage_{}", graph.name, index),
+                        Sample::Value(node),
+                    );
+                }
+            }
+        }
+    }
+    super::vcd::end_cycles(cycles);
+}
+
+fn add_node_cycles(function: &String, node: usize, cycles: usize, size: usize) {
+    let counts = &mut sim().node_cycles;
+    if !counts.contains_key(function) {
+        counts.insert(function.clone(), vec![0; size]);
+    }
+    counts.get_mut(function).unwrap()[node] += cycles;
+}
+
+fn count_node_cycles(cycles: usize) {
+    let active = sim().active.clone();
+    for instance in &active {
+        match &*(instance.borrow()) {
+            Instance::Static(instance, _) => {
+                let graph = &instance.graph;
+                add_node_cycles(&graph.name, graph.current.0, cycles, graph.graph.len());
+            }
+            Instance::PipeFor(instance, _) => {
+                let graph = &instance.graph;
+                for stage in &graph.stages {
+                    add_node_cycles(&graph.name, stage.0, cycles, graph.graph.len());
+                }
+            }
+        }
+    }
+}
+
+/// What an operation bound to an edge does, for `export_dot`.
+fn op_label(op: &Operation) -> Option<String> {
+    match op {
+        Operation::Store(store) => Some(format!("store {}", store.memory)),
+        Operation::Write(write) => Some(format!("write {}", write.stream)),
+        op => op.result().map(|name| name.to_string()),
+    }
+}
+
+fn node_label(node: usize, control: &Control) -> String {
+    match control {
+        Control::BeginFor(for_op) => format!(
+            "{}\nfor {} = {} to {} step {}",
+            node, for_op.iter_name, for_op.lb, for_op.ub, for_op.step
+        ),
+        Control::EndFor(for_op, _, _) => format!("{}\nend for {}", node, for_op.iter_name),
+        Control::BeginIf(if_op) => format!("{}\nif {}", node, if_op.condition),
+        Control::Return(_) => format!("{}\nreturn", node),
+        Control::Call(call_op) => format!("{}\ncall {}", node, call_op.function),
+        Control::Normal => node.to_string(),
+    }
+}
+
+/// The time graph of `function` as DOT: nodes with their control, edges with
+/// their latency and bound operations, and loop back edges dashed. With
+/// `counts`, nodes show the cycles the last run spent at them and unvisited
+/// ones are grey.
+pub fn dot(function: &str, counts: bool) -> Result<String, String> {
+    let Some(module) = sim().function.get(function).cloned() else {
+        return Err(format!("No function {}", function));
+    };
+    let graph = match &module.strategy {
+        Strategy::Static(graph) | Strategy::PipelineFor(graph, _, _) => graph,
+        _ => return Err(format!("{} has no time graph", function)),
+    };
+    let cycles = sim().node_cycles.get(function).cloned();
+    let mut dot = format!(
+        "digraph {} {{\n    node [shape=circle];\n",
+        dot_quote(function)
+    );
+    for (node, control) in graph.node.iter().enumerate() {
+        let mut label = node_label(node, control);
+        let mut attributes = vec![];
+        if node == graph.start || node == graph.end {
+            attributes.push("shape=doublecircle".to_string());
+        }
+        if counts {
+            let count = cycles.as_ref().map_or(0, |cycles| cycles[node]);
+            label.push_str(&format!("\n{} cycles", count));
+            if count == 0 {
+                attributes.push("color=grey fontcolor=grey".to_string());
+            }
+        }
+        attributes.insert(0, format!("label={}", dot_quote(&label)));
+        dot.push_str(&format!("    n{} [{}];\n", node, attributes.join(" ")));
+    }
+    for (from, edges) in graph.graph.iter().enumerate() {
+        for edge in edges {
+            let mut label = edge.edge_type.clone();
+            for op in graph.edge_ops(edge).iter().filter_map(op_label) {
+                label.push_str(&format!("\n{}", op));
+            }
+            dot.push_str(&format!(
+                "    n{} -> n{} [label={}];\n",
+                from,
+                edge.to,
+                dot_quote(&label)
+            ));
+        }
+    }
+    for (node, control) in graph.node.iter().enumerate() {
+        if let Control::EndFor(_, Some(_), start) = control {
+            dot.push_str(&format!("    n{} -> n{} [style=dashed];\n", node, start));
+        }
+    }
+    dot.push_str("}\n");
+    Ok(dot)
+}
+
+/// Mark each active instance, and each pipeline stage, busy at the node it is
+/// at in the trace.
+fn sample_perfetto(cycles: usize) {
+    let active = sim().active.clone();
+    for instance in &active {
+        let key = Rc::as_ptr(instance) as usize;
+        match &*(instance.borrow()) {
+            Instance::Static(instance, _) => {
+                let graph = &instance.graph;
+                let track = instance_track(key, &graph.name);
+                activity("tor", track, format!("@{}", graph.current.0));
+            }
+            Instance::PipeFor(instance, _) => {
+                let graph = &instance.graph;
+                let track = instance_track(key, &graph.name);
+                for (index, stage) in graph.stages.iter().enumerate() {
+                    let name = format!("{} stage {}", track, index);
+                    activity("tor", name, format!("@{}", stage.0));
+                }
+            }
+        }
+    }
+    super::perfetto::end_cycles(cycles);
+}
+
+fn test_watch_halt_active() -> bool {
+    let mut hit = false;
+    let active = sim().active.clone();
+    for instance in &active {
+        match &*(instance.borrow()) {
+            Instance::Static(instance, _) => {
+                hit |= test_watch_halt(&instance.graph.name, &|var| instance.env.lookup(var));
+            }
+            Instance::PipeFor(instance, _) => {
+                for (index, env) in instance.env.iter().enumerate() {
+                    let name = format!("Stage {} of {}", index, instance.graph.name);
+                    hit |= test_watch_halt(&name, &|var| env.lookup(var));
+                }
+            }
+        }
+    }
+    hit
+}
+
+/// Variables of the active instances, keyed like the watchpoint output.
+pub fn snapshot() -> BTreeMap<String, String> {
+    let mut values = BTreeMap::new();
+    let active = sim().active.clone();
+    for instance in &active {
+        match &*(instance.borrow()) {
+            Instance::Static(instance, _) => {
+                for (var, value) in instance.env.variable.iter() {
+                    let name = format!("{} in {}", var, instance.graph.name);
+                    values.insert(name, format!("{:?}", value));
+                }
+            }
+            Instance::PipeFor(instance, _) => {
+                for (index, env) in instance.env.iter().enumerate() {
+                    for (var, value) in env.variable.iter() {
+                        let name = format!("{} in Stage {} of {}", var, index, instance.graph.name);
+                        values.insert(name, format!("{:?}", value));
+                    }
+                }
+            }
+        }
+    }
+    values
 }
 
 pub fn show_active() {
-    for instance_ref in unsafe { &(*ACTIVE) }.iter() {
+    if json_output() {
+        let mut instances = vec![];
+        let active = sim().active.clone();
+        for instance_ref in &active {
+            instances.push(match &*instance_ref.borrow() {
+                Instance::Static(instance, _) => {
+                    let cur = instance.graph.current;
+                    let flag = instance.graph.if_node[cur.0]
+                        .as_ref()
+                        .map(|_| instance.graph.reached.contains(&cur.0));
+                    json!({"function": instance.graph.name, "node": cur, "flag": flag})
+                }
+                Instance::PipeFor(instance, _) => {
+                    json!({"function": instance.graph.name, "stages": instance.graph.stages})
+                }
+            });
+        }
+        return print_json(Value::Array(instances));
+    }
+    let active = sim().active.clone();
+    for instance_ref in &active {
         let instance = instance_ref.borrow();
         match &*instance {
             Instance::Static(instance, _) => {
                 let cur = instance.graph.current;
-                if let Some((_, _, flag)) = &instance.graph.if_node[cur.0] {
+                if instance.graph.if_node[cur.0].is_some() {
+                    let flag = instance.graph.reached.contains(&cur.0);
                     println!("Function {}: {:?} {}", instance.graph.name, cur, flag);
                 } else {
                     println!("Function {}: {:?}", instance.graph.name, cur);
@@ -1631,20 +2568,506 @@ pub fn show_active() {
     }
 }
 
+fn sorted_variables(env: &Env, prefix: &str) -> Vec<(String, VALUE)> {
+    let mut variables: Vec<(String, VALUE)> = env
+        .variable
+        .iter()
+        .map(|(var, value)| (format!("{}{}", prefix, var), *value))
+        .collect();
+    variables.sort_by(|a, b| a.0.cmp(&b.0));
+    variables
+}
+
+/// The active instances with their time-graph node, or the node of each
+/// pipeline stage, and the first op leaving it.
+pub fn frames() -> Vec<Frame> {
+    let first_op = |graph: &TimeGraphDef, node: usize| {
+        graph.graph[node]
+            .iter()
+            .flat_map(|edge| graph.edge_ops(edge))
+            .find_map(|op| op.result().map(|name| name.to_string()))
+    };
+    let mut frames = vec![];
+    let active = sim().active.clone();
+    for instance_ref in &active {
+        frames.push(match &*instance_ref.borrow() {
+            Instance::Static(instance, _) => {
+                let graph = &instance.graph;
+                Frame {
+                    function: graph.name.clone(),
+                    location: format!("node {}", graph.current.0),
+                    op: first_op(&graph.def, graph.current.0),
+                    variables: sorted_variables(&instance.env, ""),
+                }
+            }
+            Instance::PipeFor(instance, _) => {
+                let graph = &instance.graph;
+                let nodes: Vec<usize> = graph.stages.iter().map(|stage| stage.0).collect();
+                let mut variables = vec![];
+                for (index, env) in instance.env.iter().enumerate() {
+                    variables.extend(sorted_variables(env, &format!("stage{}.", index)));
+                }
+                Frame {
+                    function: graph.name.clone(),
+                    location: format!("stages at nodes {:?}", nodes),
+                    op: nodes.first().and_then(|node| first_op(&graph.def, *node)),
+                    variables,
+                }
+            }
+        });
+    }
+    frames
+}
+
 pub fn show_stall() {
     // for instance in unsafe { &(*STALL) }.iter() {
     //     println!("{:#?}", instance);
     // }
 }
 
+/// Keep stepping with breakpoints enabled while `running` holds.
+fn step_while(running: impl Fn() -> bool) {
+    let mut cycle = 0;
+    while running() {
+        if !step(1, true) {
+            return;
+        }
+        cycle += 1;
+        if cycle >= options().max_cycles {
+            println!("!!Stopped at max_cycles {}", options().max_cycles);
+            return;
+        }
+    }
+}
+
+/// An instance that issued a `Control::Call` is inactive until its callee
+/// returns, so stepping over is running until the active instances are back.
+pub fn step_over() {
+    let callers = sim().active.clone();
+    if !step(1, false) {
+        return;
+    }
+    step_while(|| callers.iter().any(|caller| !caller.borrow().is_active()));
+}
+
+/// Run until the most recently started active instance returns to its caller.
+pub fn step_out() {
+    let Some(current) = sim().active.last().cloned() else {
+        return;
+    };
+    if !step(1, false) {
+        return;
+    }
+    step_while(|| {
+        !current.borrow().is_active()
+            || sim()
+                .active
+                .iter()
+                .any(|instance| Rc::ptr_eq(instance, &current))
+    });
+}
+
 pub fn execute_continue() {
-    step(100000000000, true);
+    if step(options().max_cycles, true) {
+        println!("!!Stopped at max_cycles {}", options().max_cycles);
+    }
 }
 
 pub fn finish() -> bool {
-    unsafe { &(*ACTIVE) }.is_empty()
+    sim().active.is_empty()
+}
+
+/// The values reached at equal points since the last call.
+pub fn take_equal_values() -> Vec<(usize, VALUE)> {
+    sim().equal_value.drain().collect()
+}
+
+pub fn dump_memory(memory: &str) -> Option<Vec<VALUE>> {
+    sim().memory.get(memory).map(|mem| mem.dump())
+}
+
+pub fn memory_store(memory: &str) -> Option<PagedStore> {
+    sim().memory.get(memory).map(|mem| mem.store().clone())
+}
+
+pub fn restore_memory(memory: &str, store: &[VALUE]) {
+    if let Some(mem) = sim().memory.get_mut(memory) {
+        mem.restore(store)
+    }
+}
+
+pub fn swap_memory(memory: &str, words: &mut [(usize, VALUE)]) {
+    if let Some(mem) = sim().memory.get_mut(memory) {
+        mem.swap(words)
+    }
+}
+
+pub fn push_stream(stream: &str, value: VALUE) -> Result<(), String> {
+    let mut sim = sim();
+    let Some(queue) = sim.stream.get_mut(stream) else {
+        return Err(format!("No stream {}", stream));
+    };
+    if queue.is_full() {
+        return Err(format!("Stream {} is full", stream));
+    }
+    queue.push(value);
+    Ok(())
+}
+
+type InstanceCopies = HashMap<*const RefCell<Instance>, Rc<RefCell<Instance>>>;
+
+/// Copy an instance together with its callers, sharing the copies of callers
+/// that are reached more than once.
+fn clone_instance(
+    instance: &Rc<RefCell<Instance>>,
+    copies: &mut InstanceCopies,
+) -> Rc<RefCell<Instance>> {
+    let key = Rc::as_ptr(instance);
+    if let Some(copy) = copies.get(&key) {
+        return copy.clone();
+    }
+    let copy = match &*(instance.borrow()) {
+        Instance::Static(inner, father) => Instance::Static(
+            inner.clone(),
+            father.as_ref().map(|father| clone_instance(father, copies)),
+        ),
+        Instance::PipeFor(inner, father) => Instance::PipeFor(
+            inner.clone(),
+            father.as_ref().map(|father| clone_instance(father, copies)),
+        ),
+    };
+    let copy = Rc::new(RefCell::new(copy));
+    copies.insert(key, copy.clone());
+    copy
 }
 
-pub fn get_equalvalue() -> *mut HashMap<String, VALUE> {
-    unsafe { EQUALVALUE }
+fn clone_active(active: &[Rc<RefCell<Instance>>]) -> Vec<Rc<RefCell<Instance>>> {
+    let mut copies = HashMap::new();
+    active
+        .iter()
+        .map(|instance| clone_instance(instance, &mut copies))
+        .collect()
+}
+
+/// Saved memories, streams and instances of the ToR simulator.
+pub struct Checkpoint {
+    memory: HashMap<String, Memory>,
+    stream: HashMap<String, Stream>,
+    active: Vec<Rc<RefCell<Instance>>>,
+}
+
+pub fn checkpoint() -> Checkpoint {
+    let sim = sim();
+    Checkpoint {
+        memory: sim.memory.clone(),
+        stream: sim.stream.clone(),
+        active: clone_active(&sim.active),
+    }
+}
+
+pub fn restore(checkpoint: &Checkpoint) {
+    sim().memory = checkpoint.memory.clone();
+    sim().stream = checkpoint.stream.clone();
+    sim().active = clone_active(&checkpoint.active);
+}
+
+fn env_to_state(env: &Env) -> Value {
+    let mut values = serde_json::Map::new();
+    for (var, value) in env.variable.iter() {
+        values.insert(var.to_string(), value_to_state(value));
+    }
+    Value::Object(values)
+}
+
+fn env_from_state(state: &Value, function: &Function) -> Option<Env> {
+    let mut env = Env::new(&function.layout, &function.name);
+    for (var, value) in state.as_object()? {
+        env.set_value(Name::new(var), value_from_state(value)?);
+    }
+    Some(env)
+}
+
+/// Append the state of an instance after the ones of its callers, returning
+/// its index in `states`.
+fn instance_to_state(
+    instance: &Rc<RefCell<Instance>>,
+    ids: &mut HashMap<*const RefCell<Instance>, usize>,
+    states: &mut Vec<Value>,
+) -> usize {
+    let key = Rc::as_ptr(instance);
+    if let Some(id) = ids.get(&key) {
+        return *id;
+    }
+    let state = match &*instance.borrow() {
+        Instance::Static(inner, father) => {
+            let father = father
+                .as_ref()
+                .map(|father| instance_to_state(father, ids, states));
+            json!({
+                "kind": "static",
+                "function": inner.graph.name,
+                "father": father,
+                "active": inner.active,
+                "current": [inner.graph.current.0, inner.graph.current.1],
+                "env": env_to_state(&inner.env),
+            })
+        }
+        Instance::PipeFor(inner, father) => {
+            let father = father
+                .as_ref()
+                .map(|father| instance_to_state(father, ids, states));
+            json!({
+                "kind": "pipe_for",
+                "function": inner.graph.name,
+                "father": father,
+                "active": inner.active,
+                "stages": inner.graph.stages,
+                "finish": inner.graph.finish,
+                "for_id": inner.graph.for_id,
+                "env": inner.env.iter().map(env_to_state).collect::<Vec<_>>(),
+            })
+        }
+    };
+    ids.insert(key, states.len());
+    states.push(state);
+    states.len() - 1
+}
+
+/// Rebuild an instance of a loaded function from its state, its caller being
+/// one of the instances already rebuilt.
+fn instance_from_state(state: &Value, built: &[Rc<RefCell<Instance>>]) -> Option<Instance> {
+    let function = sim().function.get(state["function"].as_str()?)?.clone();
+    let father = match &state["father"] {
+        Value::Null => None,
+        id => Some(built.get(id.as_u64()? as usize)?.clone()),
+    };
+    let active = state["active"].as_bool()?;
+    match (state["kind"].as_str()?, &function.strategy) {
+        ("static", Strategy::Static(graph)) => {
+            let mut graph = TimeGraph::new(graph.clone());
+            let node = state["current"][0].as_u64()? as usize;
+            if node >= graph.node.len() {
+                return None;
+            }
+            graph.current = (node, state["current"][1].as_u64()? as u32);
+            let env = env_from_state(&state["env"], &function)?;
+            Some(Instance::Static(
+                StaticInstance { graph, env, active },
+                father,
+            ))
+        }
+        ("pipe_for", Strategy::PipelineFor(_, ii, pipeline)) => {
+            let mut graph = pipeline.clone();
+            graph.stages = serde_json::from_value(state["stages"].clone()).ok()?;
+            graph.finish = state["finish"].as_bool()?;
+            graph.for_id = state["for_id"].as_u64()? as usize;
+            let env = state["env"]
+                .as_array()?
+                .iter()
+                .map(|env| env_from_state(env, &function))
+                .collect::<Option<Vec<Env>>>()?;
+            let nodes = graph.node.len();
+            if env.len() != graph.stages.len()
+                || graph.for_id >= nodes
+                || graph.stages.iter().any(|stage| stage.0 >= nodes)
+            {
+                return None;
+            }
+            let instance = PipeForInstance {
+                graph,
+                env,
+                ii: *ii,
+                active,
+            };
+            Some(Instance::PipeFor(instance, father))
+        }
+        _ => None,
+    }
+}
+
+impl Checkpoint {
+    pub fn to_json(&self) -> Value {
+        let mut memory = serde_json::Map::new();
+        for (name, mem) in &self.memory {
+            memory.insert(name.clone(), mem.to_state());
+        }
+        let mut stream = serde_json::Map::new();
+        for (name, queue) in &self.stream {
+            stream.insert(name.clone(), queue.to_state());
+        }
+        let mut ids = HashMap::new();
+        let mut instances = vec![];
+        let active: Vec<usize> = self
+            .active
+            .iter()
+            .map(|instance| instance_to_state(instance, &mut ids, &mut instances))
+            .collect();
+        json!({
+            "memory": memory,
+            "stream": stream,
+            "instances": instances,
+            "active": active,
+        })
+    }
+
+    /// The state saved by `to_json`, applied to the loaded functions.
+    pub fn from_json(state: &Value) -> Result<Checkpoint, String> {
+        let mut checkpoint = checkpoint();
+        for (name, mem_state) in state["memory"].as_object().into_iter().flatten() {
+            let Some(mem) = checkpoint.memory.get_mut(name) else {
+                return Err(format!("No memory {}", name));
+            };
+            mem.restore_state(mem_state)
+                .ok_or(format!("Malformed state of memory {}", name))?;
+        }
+        for (name, queue_state) in state["stream"].as_object().into_iter().flatten() {
+            let Some(queue) = checkpoint.stream.get_mut(name) else {
+                return Err(format!("No stream {}", name));
+            };
+            queue
+                .restore_state(queue_state)
+                .ok_or(format!("Malformed state of stream {}", name))?;
+        }
+        let mut instances = vec![];
+        for (id, instance) in state["instances"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            let instance = instance_from_state(instance, &instances).ok_or(format!(
+                "Instance {} does not match the loaded functions",
+                id
+            ))?;
+            instances.push(Rc::new(RefCell::new(instance)));
+        }
+        checkpoint.active = state["active"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|id| {
+                id.as_u64()
+                    .and_then(|id| instances.get(id as usize))
+                    .cloned()
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or("Malformed list of active instances")?;
+        Ok(checkpoint)
+    }
+}
+
+pub fn dump_variable(var: &str) -> Vec<VALUE> {
+    let var = Name::new(var);
+    let mut values = vec![];
+    let active = sim().active.clone();
+    for instance in &active {
+        match &*(instance.borrow()) {
+            Instance::Static(instance, _) => values.extend(instance.env.variable.get(var)),
+            Instance::PipeFor(instance, _) => {
+                for env in instance.env.iter() {
+                    values.extend(env.variable.get(var))
+                }
+            }
+        }
+    }
+    values
+}
+
+pub fn restore_variable(var: &str, values: &[VALUE]) {
+    let var = Name::new(var);
+    let mut values = values.iter();
+    let active = sim().active.clone();
+    for instance in &active {
+        match &mut *(instance.borrow_mut()) {
+            Instance::Static(instance, _) => {
+                if let Some(value) = instance.env.variable.get_mut(var) {
+                    *value = *values.next().unwrap_or(value);
+                }
+            }
+            Instance::PipeFor(instance, _) => {
+                for env in instance.env.iter_mut() {
+                    if let Some(value) = env.variable.get_mut(var) {
+                        *value = *values.next().unwrap_or(value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl TimeGraphDef {
+    fn collect_names(&self, names: &mut Vec<Name>) {
+        for op in &self.ops {
+            match op {
+                Operation::Compute(compute) => names.push(compute.name),
+                Operation::Load(load) => names.push(load.name),
+                Operation::Read(read) => names.push(read.name),
+                _ => (),
+            }
+        }
+        for node in &self.node {
+            match node {
+                Control::BeginFor(for_op) => {
+                    names.extend(for_op.names.iter().cloned());
+                    names.push(for_op.iter_name);
+                    names.extend(for_op.iter_args.iter().cloned());
+                }
+                Control::BeginIf(if_op) => names.extend(if_op.names.iter().cloned()),
+                Control::Call(call_op) => names.extend(call_op.names.iter().cloned()),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Induction variable of the for loop named by one of its results or by the
+/// induction variable itself.
+pub fn loop_iter_name(name: &str) -> Option<String> {
+    for function in sim().function.values() {
+        let (Strategy::Static(graph) | Strategy::PipelineFor(graph, _, _)) = &function.strategy
+        else {
+            continue;
+        };
+        for node in &graph.node {
+            if let Control::BeginFor(for_op) = node {
+                if for_op.iter_name == name || for_op.names.iter().any(|n| n == name) {
+                    return Some(for_op.iter_name.to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+pub fn function_names() -> Vec<String> {
+    sim().function.keys().cloned().collect()
+}
+
+/// The size in words and the bytes allocated of each memory.
+pub fn memory_usage() -> Vec<(String, usize, usize)> {
+    sim()
+        .memory
+        .iter()
+        .map(|(name, memory)| (name.clone(), memory.size(), memory.footprint()))
+        .collect()
+}
+
+pub fn memory_names() -> Vec<String> {
+    let mut names: Vec<String> = sim().memory.keys().cloned().collect();
+    names.extend(sim().stream.keys().cloned());
+    names
+}
+
+pub fn signal_names() -> Vec<String> {
+    let mut names = vec![];
+    for function in sim().function.values() {
+        names.extend(function.args.iter().cloned());
+        match &function.strategy {
+            Strategy::Static(graph) | Strategy::PipelineFor(graph, _, _) => {
+                graph.collect_names(&mut names)
+            }
+            _ => (),
+        }
+    }
+    names.iter().map(|name| name.to_string()).collect()
 }
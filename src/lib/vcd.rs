@@ -0,0 +1,381 @@
+use super::basetype::VALUE;
+use super::common::{fail, Failure};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+/// A sampled signal: a simulated value, or a name such as the current state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sample {
+    Value(VALUE),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Bit,
+    Wire(usize),
+    Real,
+    Text,
+}
+
+impl Kind {
+    fn of(sample: Option<&Sample>) -> Kind {
+        match sample {
+            Some(Sample::Value(VALUE::Bool(_) | VALUE::Control)) | None => Kind::Bit,
+            Some(Sample::Value(VALUE::U8(_) | VALUE::I8(_))) => Kind::Wire(8),
+            Some(Sample::Value(VALUE::U16(_) | VALUE::I16(_))) => Kind::Wire(16),
+            Some(Sample::Value(VALUE::U32(_) | VALUE::I32(_))) => Kind::Wire(32),
+            Some(Sample::Value(VALUE::U64(_) | VALUE::I64(_) | VALUE::Index(_))) => Kind::Wire(64),
+            Some(Sample::Value(VALUE::F16(_) | VALUE::BF16(_) | VALUE::F32(_) | VALUE::F64(_))) => {
+                Kind::Real
+            }
+            Some(Sample::Value(VALUE::ERROR | VALUE::X)) => Kind::Bit,
+            Some(Sample::Text(_)) => Kind::Text,
+            // As `[1,2]` and `1.0+2.0i`
+            Some(Sample::Value(VALUE::Vector(_) | VALUE::C32(_) | VALUE::C64(_))) => Kind::Text,
+        }
+    }
+
+    fn declare(&self) -> (&'static str, usize) {
+        match self {
+            Kind::Bit => ("wire", 1),
+            Kind::Wire(width) => ("wire", *width),
+            Kind::Real => ("real", 64),
+            Kind::Text => ("string", 1),
+        }
+    }
+
+    /// The value change for `id`; unsampled cycles, ERROR and X become `x`,
+    /// which real signals cannot express and skip.
+    fn change(&self, sample: Option<&Sample>, id: &str) -> Option<String> {
+        let value = match sample {
+            Some(Sample::Value(VALUE::ERROR | VALUE::X)) | None => None,
+            Some(sample) => Some(sample),
+        };
+        Some(match (self, value) {
+            (Kind::Bit, Some(Sample::Value(VALUE::Control))) => format!("1{}", id),
+            (Kind::Bit, Some(Sample::Value(value)))
+                if matches!(
+                    value,
+                    VALUE::F16(_) | VALUE::BF16(_) | VALUE::F32(_) | VALUE::F64(_)
+                ) =>
+            {
+                format!("{}{}", if value.as_f64() != 0.0 { 1 } else { 0 }, id)
+            }
+            (Kind::Bit, Some(Sample::Value(value))) => {
+                format!("{}{}", if value.as_bool() { 1 } else { 0 }, id)
+            }
+            (Kind::Wire(width), Some(Sample::Value(value)))
+                if !matches!(
+                    value,
+                    VALUE::F16(_) | VALUE::BF16(_) | VALUE::F32(_) | VALUE::F64(_) | VALUE::Control
+                ) =>
+            {
+                match *width {
+                    32 => format!("b{:b} {}", value.as_u32(), id),
+                    64 => format!("b{:b} {}", value.as_u64(), id),
+                    _ => format!("b{:b} {}", value.as_u64() & ((1 << width) - 1), id),
+                }
+            }
+            (Kind::Wire(_), _) => format!("bx {}", id),
+            (Kind::Real, Some(Sample::Value(value))) => format!("r{} {}", value.as_f64(), id),
+            (Kind::Text, Some(Sample::Text(text))) => format!("s{} {}", text.replace(' ', "_"), id),
+            // Lanes as a JSON array, which has no spaces
+            (Kind::Text, Some(Sample::Value(value @ VALUE::Vector(_)))) => {
+                format!("s{} {}", value.to_json(), id)
+            }
+            (Kind::Text, Some(Sample::Value(value))) => format!("s{:?} {}", value, id),
+            (Kind::Bit, _) => format!("x{}", id),
+            (Kind::Text, _) => format!("sx {}", id),
+            (Kind::Real, _) => return None,
+        })
+    }
+}
+
+/// A signal declared in the header, with its identifier code.
+#[derive(Debug)]
+struct Declared {
+    kind: Kind,
+    id: String,
+}
+
+/// A waveform streamed to its file. The value changes of a cycle are written
+/// when it ends. The header declares the signals known so far, as VCD has no
+/// way to add one later: a signal is declared once it has a known value, from
+/// which its kind is taken, and the header is written again when one is.
+#[derive(Debug)]
+struct Vcd {
+    path: String,
+    file: BufWriter<File>,
+    /// Length of the header at the start of the file, None until written
+    header: Option<u64>,
+    declared: BTreeMap<String, Declared>,
+    /// The last sample of every signal seen, None while it was not sampled
+    last: BTreeMap<String, Option<Sample>>,
+    /// Changes in the current cycle, in the order they were made
+    changes: Vec<(String, Option<Sample>)>,
+    sampled: HashSet<String>,
+    held: HashSet<String>,
+    time: usize,
+    /// The last time marker written
+    written: Option<usize>,
+}
+
+thread_local! {
+    /// The waveform being recorded. Time advances by one per simulated cycle,
+    /// so it keeps growing across reloads and restored checkpoints.
+    static VCD: RefCell<Option<Vcd>> = const { RefCell::new(None) };
+}
+
+/// Run `f` on the waveform being recorded, if any. A waveform that cannot be
+/// written is reported and stopped.
+fn with_vcd(f: impl FnOnce(&mut Vcd) -> std::io::Result<()>) {
+    VCD.with_borrow_mut(|vcd| {
+        let Some(recording) = vcd else {
+            return;
+        };
+        if f(recording).is_err() {
+            println!("!!Failed to write file {}", recording.path);
+            fail(Failure::Command);
+            *vcd = None;
+        }
+    })
+}
+
+/// Identifier codes are built from the printable characters `!` to `~`.
+fn identifier(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+/// Whether a sample gives a signal a kind, unlike ERROR and X.
+fn known(sample: &Option<Sample>) -> bool {
+    !matches!(sample, None | Some(Sample::Value(VALUE::ERROR | VALUE::X)))
+}
+
+impl Vcd {
+    /// The declarations of the signals, nested in scopes by their dotted names.
+    fn header(&self) -> String {
+        let mut header = String::new();
+        header.push_str("$version hestia $end\n");
+        header.push_str("$timescale 1ns $end\n");
+        let mut scope: Vec<&str> = vec![];
+        for (name, declared) in &self.declared {
+            let mut path: Vec<&str> = name.split('.').collect();
+            let var = path.pop().unwrap();
+            let common = zip_len(&scope, &path);
+            for _ in common..scope.len() {
+                header.push_str("$upscope $end\n");
+            }
+            for module in &path[common..] {
+                header.push_str(&format!("$scope module {} $end\n", module));
+            }
+            scope = path;
+            let (var_type, width) = declared.kind.declare();
+            header.push_str(&format!(
+                "$var {} {} {} {} $end\n",
+                var_type, width, declared.id, var
+            ));
+        }
+        for _ in 0..scope.len() {
+            header.push_str("$upscope $end\n");
+        }
+        header.push_str("$enddefinitions $end\n");
+        header
+    }
+
+    /// Write the header, or replace it when signals were declared since,
+    /// keeping the value changes written after it.
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let header = self.header();
+        let Some(length) = self.header else {
+            self.file.write_all(header.as_bytes())?;
+            self.header = Some(header.len() as u64);
+            return Ok(());
+        };
+        self.file.flush()?;
+        let temporary = format!("{}.tmp", self.path);
+        let mut copy = BufWriter::new(File::create(&temporary)?);
+        copy.write_all(header.as_bytes())?;
+        let mut old = File::open(&self.path)?;
+        old.seek(SeekFrom::Start(length))?;
+        std::io::copy(&mut old, &mut copy)?;
+        copy.flush()?;
+        std::fs::rename(&temporary, &self.path)?;
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.header = Some(header.len() as u64);
+        Ok(())
+    }
+
+    /// Write a time marker for the current time, once.
+    fn mark_time(&mut self) -> std::io::Result<()> {
+        if self.written != Some(self.time) {
+            writeln!(self.file, "#{}", self.time)?;
+            self.written = Some(self.time);
+        }
+        Ok(())
+    }
+
+    fn change(&mut self, name: &str, sample: Sample) {
+        let last = self.last.get(name);
+        if last.is_none() || last.unwrap().as_ref() != Some(&sample) {
+            self.last.insert(name.to_string(), Some(sample.clone()));
+            self.changes.push((name.to_string(), Some(sample)));
+        }
+    }
+
+    /// Write the changes of the current cycle, declaring the signals that got
+    /// their first known value in it.
+    fn write_changes(&mut self) -> std::io::Result<()> {
+        let mut added = false;
+        for (name, sample) in &self.changes {
+            if known(sample) && !self.declared.contains_key(name) {
+                let declared = Declared {
+                    kind: Kind::of(sample.as_ref()),
+                    id: identifier(self.declared.len()),
+                };
+                self.declared.insert(name.clone(), declared);
+                added = true;
+            }
+        }
+        if added || self.header.is_none() {
+            self.write_header()?;
+        }
+        let changes = std::mem::take(&mut self.changes);
+        for (name, sample) in &changes {
+            let Some(declared) = self.declared.get(name) else {
+                continue;
+            };
+            if let Some(change) = declared.kind.change(sample.as_ref(), &declared.id) {
+                self.mark_time()?;
+                writeln!(self.file, "{}", change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn zip_len(a: &[&str], b: &[&str]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// Start recording a waveform to `path`, or stop with `off`. A dump already in
+/// progress is written out first.
+pub fn dump_vcd(path: &str) {
+    flush();
+    if path == "off" {
+        VCD.set(None);
+        return;
+    }
+    let Ok(file) = File::create(path) else {
+        println!("!!Failed to write file {}", path);
+        fail(Failure::Command);
+        return;
+    };
+    VCD.set(Some(Vcd {
+        path: path.to_string(),
+        file: BufWriter::new(file),
+        header: None,
+        declared: BTreeMap::new(),
+        last: BTreeMap::new(),
+        changes: vec![],
+        sampled: HashSet::new(),
+        held: HashSet::new(),
+        time: 0,
+        written: None,
+    }));
+}
+
+pub fn vcd_enabled() -> bool {
+    VCD.with_borrow(Option::is_some)
+}
+
+/// Record the value of a signal in the current cycle. Dotted names become
+/// nested scopes.
+pub fn sample(name: String, sample: Sample) {
+    with_vcd(|vcd| {
+        vcd.change(&name, sample);
+        vcd.sampled.insert(name);
+        Ok(())
+    })
+}
+
+/// Record a value that holds until it is next recorded, such as the result
+/// of an operation.
+pub fn record(name: String, sample: Sample) {
+    with_vcd(|vcd| {
+        vcd.change(&name, sample);
+        vcd.held.insert(name);
+        Ok(())
+    })
+}
+
+/// Close the current sample after `cycles` cycles and write its changes:
+/// signals that were not sampled in it become unknown until they are sampled
+/// again.
+pub fn end_cycles(cycles: usize) {
+    with_vcd(|vcd| {
+        for (name, last) in vcd.last.iter_mut() {
+            if !vcd.sampled.contains(name) && !vcd.held.contains(name) && last.is_some() {
+                *last = None;
+                vcd.changes.push((name.clone(), None));
+            }
+        }
+        vcd.write_changes()?;
+        vcd.sampled.clear();
+        vcd.time += cycles;
+        Ok(())
+    })
+}
+
+/// Mark the time reached and write out what is buffered, so that a viewer can
+/// reload the dump between runs.
+pub fn flush() {
+    with_vcd(|vcd| {
+        if vcd.header.is_none() {
+            vcd.write_header()?;
+        }
+        vcd.mark_time()?;
+        vcd.file.flush()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signals_declared_while_streaming() {
+        let path = std::env::temp_dir().join(format!("hestia-{}.vcd", std::process::id()));
+        let path = path.to_str().unwrap();
+        dump_vcd(path);
+        sample("f.node".to_string(), Sample::Value(VALUE::U32(1)));
+        end_cycles(1);
+        flush();
+        let first = std::fs::read_to_string(path).unwrap();
+        assert!(first.contains("$var wire 32 ! node $end"), "{}", first);
+        assert!(first.ends_with("b1 !\n#1\n"), "{}", first);
+        sample("f.node".to_string(), Sample::Value(VALUE::U32(2)));
+        record("f.x".to_string(), Sample::Value(VALUE::X));
+        end_cycles(1);
+        record("f.x".to_string(), Sample::Value(VALUE::Bool(true)));
+        end_cycles(2);
+        flush();
+        dump_vcd("off");
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let header = "$var wire 32 ! node $end\n$var wire 1 \" x $end\n$upscope $end\n$enddefinitions $end\n";
+        let changes = "#0\nb1 !\n#1\nb10 !\n#2\n1\"\nbx !\n#4\n";
+        assert!(text.contains(&format!("{}{}", header, changes)), "{}", text);
+    }
+}
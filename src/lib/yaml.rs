@@ -0,0 +1,414 @@
+use super::error::{ErrorCode, HestiaError};
+use super::json5::{integer, is_integer};
+use serde_json::{Map, Number, Value};
+
+/// A line with its comment and indentation removed.
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+fn error(line: usize, expected: &str) -> HestiaError {
+    let message = format!("line {}: expected {}", line, expected);
+    HestiaError::new(ErrorCode::InvalidSyntax, message)
+}
+
+/// The text before a `#` comment, which starts a line or follows a space
+/// outside quotes.
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (index, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &text[..index],
+            None => (),
+        }
+        previous = c;
+    }
+    text
+}
+
+/// The key and the rest of a `key: value` entry, if `text` is one.
+fn split_entry(text: &str) -> Option<(String, &str)> {
+    let (key, rest) = match text.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = text[1..].find(quote)? + 1;
+            (scalar_string(&text[..=end])?, &text[end + 1..])
+        }
+        _ => {
+            let colon = text
+                .match_indices(':')
+                .find(|(index, _)| {
+                    text[index + 1..].is_empty() || text[index + 1..].starts_with(' ')
+                })?
+                .0;
+            (text[..colon].trim_end().to_string(), &text[colon..])
+        }
+    };
+    let rest = rest.trim_start().strip_prefix(':')?;
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    Some((key, rest.trim()))
+}
+
+/// The text of a quoted scalar.
+fn scalar_string(text: &str) -> Option<String> {
+    if text.len() >= 2 && text.starts_with('\'') && text.ends_with('\'') {
+        return Some(text[1..text.len() - 1].replace("''", "'"));
+    }
+    if text.starts_with('"') {
+        return serde_json::from_str(text).ok();
+    }
+    None
+}
+
+/// A plain or quoted scalar: null, a boolean, a number or a string. Fails
+/// with what was expected instead for an integer that does not fit in 64
+/// bits.
+fn scalar(text: &str) -> Result<Value, String> {
+    if let Some(string) = scalar_string(text) {
+        return Ok(Value::String(string));
+    }
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => return Ok(Value::Null),
+        "true" | "True" | "TRUE" => return Ok(Value::Bool(true)),
+        "false" | "False" | "FALSE" => return Ok(Value::Bool(false)),
+        _ => (),
+    }
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (radix, integer_digits) = match digits.get(..2) {
+        Some("0x") => (16, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        _ => (10, digits),
+    };
+    if is_integer(integer_digits, radix) {
+        return match integer(negative, integer_digits, radix) {
+            Some(number) => Ok(Value::Number(number)),
+            None => Err(format!("an integer within 64 bits, not {}", text)),
+        };
+    }
+    let numeric = digits.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        && digits
+            .bytes()
+            .all(|b| b.is_ascii_digit() || b".eE+-".contains(&b));
+    let float = digits.parse::<f64>().ok().filter(|_| numeric);
+    let float = float.map(|value| if negative { -value } else { value });
+    Ok(match float.and_then(Number::from_f64) {
+        Some(number) => Value::Number(number),
+        None => Value::String(text.to_string()),
+    })
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    index: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn current(&self) -> Option<&Line<'a>> {
+        self.lines.get(self.index)
+    }
+
+    /// A flow collection such as `[i32, f64]` or `{name: x}`, which may go on
+    /// over the following lines until its brackets close.
+    fn flow(&mut self, text: &'a str) -> Result<Value, HestiaError> {
+        let number = self.lines[self.index].number;
+        let mut joined = text.to_string();
+        self.index += 1;
+        while !balanced(&joined) {
+            let Some(line) = self.current() else {
+                return Err(error(number, "a closing bracket"));
+            };
+            joined.push(' ');
+            joined.push_str(line.text);
+            self.index += 1;
+        }
+        let mut flow = Flow {
+            text: &joined,
+            pos: 0,
+        };
+        let value = flow.value().map_err(|expected| error(number, &expected))?;
+        if !flow.rest().trim().is_empty() {
+            return Err(error(number, "the end of the collection"));
+        }
+        Ok(value)
+    }
+
+    /// The value of an entry or item whose text is `text`: inline, or the
+    /// block on the following lines indented more than `indent`.
+    fn inline(&mut self, text: &'a str, indent: usize) -> Result<Value, HestiaError> {
+        if text.starts_with(['[', '{']) {
+            return self.flow(text);
+        }
+        if !text.is_empty() {
+            let number = self.lines[self.index].number;
+            self.index += 1;
+            return scalar(text).map_err(|expected| error(number, &expected));
+        }
+        self.index += 1;
+        match self.current() {
+            Some(line) if line.indent > indent => self.node(line.indent),
+            // A sequence may be as indented as the key it belongs to.
+            Some(line) if line.indent == indent && is_item(line.text) => self.node(indent),
+            _ => Ok(Value::Null),
+        }
+    }
+
+    fn node(&mut self, indent: usize) -> Result<Value, HestiaError> {
+        let Some(line) = self.current() else {
+            return Ok(Value::Null);
+        };
+        let text = line.text;
+        if is_item(text) {
+            return self.sequence(indent);
+        }
+        if !text.starts_with(['[', '{']) && split_entry(text).is_some() {
+            return self.mapping(indent);
+        }
+        self.inline(text, usize::MAX)
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Value, HestiaError> {
+        let mut items = vec![];
+        while let Some(line) = self.current() {
+            if line.indent != indent || !is_item(line.text) {
+                break;
+            }
+            let rest = line.text[1..].trim_start();
+            if rest.is_empty() {
+                items.push(self.inline(rest, indent)?);
+                continue;
+            }
+            // The item starts on the line of its dash, e.g. `- name: x`.
+            let offset = line.text.len() - rest.len();
+            self.lines[self.index].indent += offset;
+            self.lines[self.index].text = rest;
+            items.push(self.node(indent + offset)?);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<Value, HestiaError> {
+        let mut object = Map::new();
+        while let Some(line) = self.current() {
+            if line.indent < indent {
+                break;
+            }
+            let number = line.number;
+            if line.indent > indent {
+                return Err(error(number, "a key indented like the others"));
+            }
+            let Some((key, rest)) = split_entry(line.text) else {
+                return Err(error(number, "a 'key: value' entry"));
+            };
+            let value = self.inline(rest, indent)?;
+            if object.insert(key.clone(), value).is_some() {
+                return Err(error(number, &format!("a single '{}'", key)));
+            }
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+fn is_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+fn balanced(text: &str) -> bool {
+    let mut depth = 0;
+    let mut quote = None;
+    for c in text.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth -= 1,
+                _ => (),
+            },
+        }
+    }
+    depth <= 0
+}
+
+/// A flow collection, whose plain scalars end at `,`, `]`, `}` or `: `.
+struct Flow<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Flow<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn eat(&mut self, token: char) -> bool {
+        let rest = self.rest();
+        let trimmed = rest.trim_start();
+        if trimmed.starts_with(token) {
+            self.pos += rest.len() - trimmed.len() + 1;
+            return true;
+        }
+        false
+    }
+
+    fn scalar(&mut self, in_mapping: bool) -> Result<Value, String> {
+        let rest = self.rest();
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        let length = match trimmed.chars().next() {
+            Some(quote @ ('"' | '\'')) => match trimmed[1..].find(quote) {
+                Some(end) => end + 2,
+                None => return Err(format!("a closing {}", quote)),
+            },
+            _ => {
+                let end = trimmed.find([',', ']', '}']).unwrap_or(trimmed.len());
+                match trimmed[..end].find(": ").filter(|_| in_mapping) {
+                    Some(colon) => colon,
+                    None => end,
+                }
+            }
+        };
+        self.pos += length;
+        scalar(trimmed[..length].trim_end())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        if self.eat('[') {
+            let mut items = vec![];
+            while !self.eat(']') {
+                items.push(self.value()?);
+                if !self.eat(',') && !self.rest().trim_start().starts_with(']') {
+                    return Err("',' or ']'".to_string());
+                }
+            }
+            return Ok(Value::Array(items));
+        }
+        if self.eat('{') {
+            let mut object = Map::new();
+            while !self.eat('}') {
+                let key = match self.scalar(true)? {
+                    Value::String(key) => key,
+                    key => key.to_string(),
+                };
+                if !self.eat(':') {
+                    return Err("':' after a key".to_string());
+                }
+                object.insert(key, self.value()?);
+                if !self.eat(',') && !self.rest().trim_start().starts_with('}') {
+                    return Err("',' or '}'".to_string());
+                }
+            }
+            return Ok(Value::Object(object));
+        }
+        self.scalar(false)
+    }
+}
+
+/// Parse the block and flow styles of YAML that a JSON document can be
+/// written in. Anchors, tags and multi-line scalars are not supported.
+pub fn parse(text: &str) -> Result<Value, HestiaError> {
+    let mut lines = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let content = strip_comment(line).trim_end();
+        let trimmed = content.trim_start();
+        if trimmed.is_empty() || trimmed == "---" || trimmed == "..." {
+            continue;
+        }
+        if content.starts_with('\t') {
+            return Err(error(index + 1, "spaces instead of tabs"));
+        }
+        lines.push(Line {
+            number: index + 1,
+            indent: content.len() - trimmed.len(),
+            text: trimmed,
+        });
+    }
+    let Some(indent) = lines.first().map(|line| line.indent) else {
+        return Err(error(1, "a document"));
+    };
+    let mut parser = Parser { lines, index: 0 };
+    let value = parser.node(indent)?;
+    if let Some(line) = parser.current() {
+        return Err(error(line.number, "the end of the document"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn block_and_flow() {
+        let text = "
+# a sweep
+load: [a.json, 'b c.json']
+runs:
+  - name: base
+    args: [1, -2.5, true, ~]
+  - {name: \"x: y\", memory: {op_0: in.txt}}
+";
+        let expected = json!({
+            "load": ["a.json", "b c.json"],
+            "runs": [
+                {"name": "base", "args": [1, -2.5, true, null]},
+                {"name": "x: y", "memory": {"op_0": "in.txt"}},
+            ],
+        });
+        assert_eq!(parse(text).unwrap(), expected);
+    }
+
+    #[test]
+    fn integers() {
+        let text = "
+- 0x7f
+- -0x10
+- 0o17
+- +42
+- 18446744073709551615
+- -9223372036854775808
+- 0xg
+- 1e3
+";
+        let expected = json!([127, -16, 15, 42, u64::MAX, i64::MIN, "0xg", 1000.0]);
+        assert_eq!(parse(text).unwrap(), expected);
+    }
+
+    #[test]
+    fn integers_out_of_range() {
+        for (text, line) in [
+            ("a: 18446744073709551616", 1),
+            ("a: 1\nb: -9223372036854775809", 2),
+            ("- -0x8000000000000001", 1),
+            ("[1, 0x10000000000000000]", 1),
+        ] {
+            let message = parse(text).unwrap_err().to_string();
+            assert!(message.contains(&format!("line {}", line)), "{}", message);
+            assert!(message.contains("an integer within 64 bits"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        for (text, expected) in [
+            ("a:\n\tb: 1", "line 2: expected spaces instead of tabs"),
+            ("a: 1\na: 2", "line 2: expected a single 'a'"),
+            ("a: [1, 2", "line 1: expected a closing bracket"),
+            ("", "line 1: expected a document"),
+        ] {
+            let message = parse(text).unwrap_err().to_string();
+            assert!(message.contains(expected), "{}", message);
+        }
+    }
+}
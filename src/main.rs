@@ -1,156 +1,297 @@
-mod lib;
-use lib::common::*;
-use lib::hec;
-use lib::software;
-use lib::tor;
+// Shadow the std printing macro so that all output can be copied to the
+// `log` file, see `common::tee`.
+macro_rules! println {
+    () => {
+        ::hestia::common::tee(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        ::hestia::common::tee(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+mod command;
+mod completion;
+mod http;
+mod mi;
+mod script;
+mod server;
+use hestia::common::*;
 use std::process;
 
-fn get_input(stdin: &std::io::Stdin) -> String {
-    let mut command = String::new();
-    if stdin.read_line(&mut command).is_err() {
-        println!("Read Error!");
-        return String::new();
+use clap::{Args, Parser, Subcommand};
+use completion::HestiaHelper;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn history_path() -> Option<std::path::PathBuf> {
+    if let Ok(path) = env::var("HESTIA_HISTORY") {
+        return Some(path.into());
     }
-    return command.trim().to_string();
+    env::var("HOME")
+        .ok()
+        .map(|home| std::path::Path::new(&home).join(".hestia_history"))
 }
 
-use std::io::{Read, Write};
-
 fn execute_command(command: &String) {
-    let commands = [
-        "breakpoint",
-        "call",
-        "continue",
-        "load",
-        "load_memory",
-        "load_memory_file",
-        "load_equal",
-        "ready",
-        "show",
-        "show_active",
-        "show_breakpoint",
-        "show_mem",
-        "show_op",
-        "show_stack",
-        "show_stall",
-        "show_var",
-        "show_watchpoint",
-        "step",
-        "switch",
-        "unset_breakpoint",
-        "unset_watchpoint",
-        "valid",
-        "watch",
-    ];
-    let list = command.split(" ").collect::<Vec<&str>>();
-    match &list[0] as &str {
-        "help" => println!("{:#?}", commands),
-        "show" => software::show_function(),
-        "var" | "show_var" => show_variable(&list[1..]),
-        "stack" | "show_stack" => software::show_stack(),
-        "mem" | "show_mem" => show_memory(&list[1..]),
-        "op" | "show_op" => software::show_operation(),
-        "b" | "breakpoint" => set_breakpoint(String::from(list[1])),
-        "w" | "watch" => set_watchpoint(String::from(list[1])),
-        "c" | "continue" => execute_continue(),
-        "l" | "load" => {
-            if parse_file(list[1]).is_err() {
-                println!("!!Failed to load file {}", list[1]);
-            }
-        }
-        "show_active" => show_active(),
-        "show_stall" => tor::show_stall(),
-        "s" | "step" => {
-            if list.len() == 1 {
-                step(1)
-            } else {
-                step(list[1].parse::<usize>().unwrap())
-            }
-        }
-        "switch" => switch(list[1]),
-        "call" => {
-            if let Err(err) = call_function(list[1], &list[2..]) {
-                println!("{}", err);
-            }
-        }
-        "cosim" => cosim(),
-        "load_memory" => load_memory(list[1], &list[2..]),
-        "load_memory_file" => {
-            if load_memory_file(list[1], list[2]).is_err() {
-                println!("!!Failed to load file {}", list[2]);
-            }
-        }
-        "load_equal" => {
-            if parse_equal(list[1]).is_err() {
-                println!("!!Failed to load file {}", list[1]);
-            }
-        }
-        "show_breakpoint" => show_breakpoint(),
-        "unset_breakpoint" => unset_breakpoint(list[1]),
-        "show_watchpoint" => show_watchpoint(),
-        "unset_watchpoint" => unset_watchpoint(list[1]),
-        "ready" => hec::ready(list[1]),
-        "valid" => hec::valid(list[1], list[2]),
-        "invalid" => hec::invalid(list[1]),
-        "exit" | "quit" | "q" => process::exit(0),
-        _ => {
-            println!("!!Undefined instruction: {}", command);
+    // `@ref step 10` runs a command in another session
+    if let Some(rest) = command.strip_prefix("@") {
+        let (session, rest) = rest.split_once(" ").unwrap_or((rest, ""));
+        if session.is_empty() || rest.trim().is_empty() {
+            println!("!!Usage: @<session> <command>");
+            return fail(Failure::Command);
         }
+        return in_session(session, || execute_command(&rest.trim().to_string()));
     }
+    // Shell commands are passed through untouched, `$` belongs to the shell
+    if let Some(shell) = command.strip_prefix("!") {
+        return command::dispatch(&["!", shell.trim()]);
+    }
+    let command = &match command.split_once(" ") {
+        Some(("alias" | "define" | "repeat", _)) => command.clone(),
+        // `set $name <value>` names the variable it assigns rather than reading it
+        Some(("set", rest)) if rest.starts_with('$') => match rest.split_once(" ") {
+            Some((name, value)) => format!("set {} {}", name, expand_variables(value)),
+            None => command.clone(),
+        },
+        _ => expand_variables(command),
+    };
+    let list = command.split(" ").collect::<Vec<&str>>();
+    command::dispatch(&list);
 }
 
-fn execute_commands(commands: &Vec<&str>) {
-    for command in commands {
-        if command.len() == 0 {
-            continue;
+const MAX_MACRO_DEPTH: usize = 16;
+static MACRO_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+fn execute_macro(name: &str, body: Vec<String>, args: &[&str]) {
+    if MACRO_DEPTH.load(Ordering::SeqCst) >= MAX_MACRO_DEPTH {
+        println!("!!Macro {} nested too deeply", name);
+        fail(Failure::Command);
+        return;
+    }
+    MACRO_DEPTH.fetch_add(1, Ordering::SeqCst);
+    // A single-command alias forwards its arguments, e.g. `alias b breakpoint`
+    let forward = body.len() == 1 && !args.is_empty();
+    for command in &body {
+        if forward {
+            execute_command(&(command.clone() + " " + &args.join(" ")));
+        } else {
+            execute_command(command);
         }
-        execute_command(&String::from(*command));
     }
-}
-
-use std::fs::File;
-fn execute_commands_from_file(path: &String) -> std::io::Result<()> {
-    let mut file = File::open(path)?;
-    let mut lines = String::new();
-    file.read_to_string(&mut lines).unwrap();
-    let commands = lines
-        .split("\n")
-        .filter(|s| !s.starts_with("#"))
-        .collect::<Vec<&str>>();
-    // println!("{:?}", commands);
-    execute_commands(&commands);
-    Ok(())
+    MACRO_DEPTH.fetch_sub(1, Ordering::SeqCst);
 }
 
 // use std::time::Instant;
 use std::env;
 use std::vec;
 
+/// Multi-level debugger for software, ToR and HEC IRs.
+///
+/// In batch mode the exit code is 1 when a command fails or the design faults,
+/// 2 on a cosimulation mismatch and 3 when the simulator panics.
+#[derive(Parser)]
+#[command(name = "hestia", args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Execute a command script
+    Run(RunArgs),
+    /// Time the bundled designs and report cycles per second of each level
+    Bench(BenchArgs),
+    /// Run a design against many stimuli in parallel and report which pass
+    Sweep(SweepArgs),
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    /// Designs to run, all of them when none is given
+    designs: Vec<String>,
+    /// Cycles to step each design for
+    #[arg(long, default_value_t = 100000)]
+    cycles: usize,
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct SweepArgs {
+    /// Sweep file naming the design, the function and the runs
+    file: String,
+    /// Worker threads, one per CPU by default
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Cycles each run may take to return
+    #[arg(long, default_value_t = 1000000)]
+    cycles: usize,
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Command script to execute before the interactive shell starts
+    script: Option<String>,
+    /// Values bound to $1, $2, ... in the script
+    args: Vec<String>,
+    /// Exit after the script instead of starting the interactive shell
+    #[arg(long)]
+    batch: bool,
+    /// Print the show_* commands as JSON
+    #[arg(long)]
+    json: bool,
+    /// Disable ANSI colors, also implied when stdout is not a terminal
+    #[arg(long)]
+    no_color: bool,
+    /// Record the session to a script that the `replay` command re-executes
+    #[arg(long)]
+    record: Option<String>,
+    /// Speak GDB/MI on stdin and stdout instead of starting the shell
+    #[arg(long, conflicts_with = "batch")]
+    mi: bool,
+    /// Accept commands on a TCP address or a `unix:<path>` socket, one per
+    /// line, each answered with a line of JSON
+    #[arg(long, conflicts_with_all = ["batch", "mi"])]
+    listen: Option<String>,
+    /// Serve the simulation over HTTP on a TCP address, e.g. `GET /memory/A`
+    /// and `POST /step`, answering with JSON
+    #[arg(long, conflicts_with_all = ["batch", "mi", "listen"])]
+    http: Option<String>,
+}
+
+fn run_batch(script: &str, args: &[String]) -> i32 {
+    set_batch();
+    let result = std::panic::catch_unwind(|| script::execute_file(script, args));
+    match result {
+        Ok(Ok(())) => (),
+        Ok(Err(_)) => {
+            println!("!!Failed to load file {}", script);
+            fail(Failure::Command);
+        }
+        Err(_) => fail(Failure::Panic),
+    }
+    exit_code()
+}
+
 fn main() {
+    let cli = Cli::parse();
+    let run = match cli.command {
+        Some(Command::Run(run)) => run,
+        Some(Command::Bench(bench)) => {
+            init();
+            if bench.json {
+                set_option("output", "json");
+            }
+            if !std::io::stdout().is_terminal() {
+                set_option("color", "off");
+            }
+            hestia::bench::bench(&bench.designs, bench.cycles);
+            process::exit(exit_code());
+        }
+        Some(Command::Sweep(sweep)) => {
+            init();
+            if sweep.json {
+                set_option("output", "json");
+            }
+            if !std::io::stdout().is_terminal() {
+                set_option("color", "off");
+            }
+            let threads = sweep.threads.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |threads| threads.get())
+            });
+            hestia::sweep::sweep(&sweep.file, threads, sweep.cycles);
+            process::exit(exit_code());
+        }
+        None => cli.run,
+    };
     init();
+    if run.json {
+        set_option("output", "json");
+    }
+    if run.no_color || !std::io::stdout().is_terminal() {
+        set_option("color", "off");
+    }
+    if let Some(path) = &run.record {
+        record(path);
+    }
 
-    let args: Vec<String> = env::args().skip(1).collect();
-    if args.len() == 1 {
-        let _ = execute_commands_from_file(&args[0]);
-    } else {
-        // let _ = execute_commands_from_file(&"/home/ruifan/wuxi/hector/examples/polybench/syrk/tor.tcl".to_string());
+    if let Some(script) = &run.script {
+        if run.batch {
+            process::exit(run_batch(script, &run.args));
+        }
+        if script::execute_file(script, &run.args).is_err() {
+            println!("!!Failed to load file {}", script);
+        }
+    } else if run.batch {
+        println!("!!--batch requires a script");
+        process::exit(Failure::Command as i32);
+    }
+
+    if run.mi {
+        return mi::run();
+    }
+    if let Some(address) = &run.listen {
+        server::listen(address);
+        process::exit(exit_code());
+    }
+    if let Some(address) = &run.http {
+        http::listen(address);
+        process::exit(exit_code());
     }
 
     // let commands = vec![];
     // execute_commands(&commands);
 
-    let stdin = std::io::stdin();
-    loop {
-        print!("> ");
-        if std::io::stdout().flush().is_err() {
-            println!("Flush Error!");
-            break;
+    let mut editor = match Editor::<HestiaHelper, DefaultHistory>::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            println!("!!Failed to start line editor: {}", err);
+            return;
         }
-
-        let command = get_input(&stdin);
+    };
+    editor.set_helper(Some(HestiaHelper::new()));
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+    let mut last = String::new();
+    loop {
+        let command = match editor.readline(&prompt()) {
+            Ok(line) => line.trim().to_string(),
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                println!("Read Error! {}", err);
+                break;
+            }
+        };
+        // An empty line repeats the previous command, e.g. to keep stepping
+        let command = if command.is_empty() {
+            if last.is_empty() {
+                continue;
+            }
+            last.clone()
+        } else {
+            let _ = editor.add_history_entry(command.as_str());
+            command
+        };
         if command == "quit" || command == "exit" || command == "q" {
             break;
         }
         execute_command(&command);
+        last = command;
+    }
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
     }
 }
@@ -0,0 +1,590 @@
+use super::execute_command;
+use hestia::basetype::VALUE;
+use hestia::common::*;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// A GDB/MI style interface on stdin and stdout, for front-ends such as Emacs
+/// gdb-mi. Active instances are reported as threads, the software control
+/// stack as the frames of thread 1, and op names are resolved to the lines of
+/// the loaded IR files that define them.
+struct Session {
+    thread: usize,
+    breakpoints: Vec<Option<String>>,
+    variables: HashMap<String, String>,
+}
+
+/// Escape `text` as an MI c-string.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn unquote(text: &str) -> String {
+    let Some(inner) = text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+    else {
+        return text.to_string();
+    };
+    let mut unquoted = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unquoted.push('\n'),
+            Some('t') => unquoted.push('\t'),
+            Some(c) => unquoted.push(c),
+            None => (),
+        }
+    }
+    unquoted
+}
+
+fn console(output: &str) {
+    for line in output.lines() {
+        println!("~{}", quote(&format!("{}\n", line)));
+    }
+}
+
+fn frame_tuple(level: usize, frame: &Frame) -> String {
+    let mut fields = format!(
+        "level=\"{}\",func={},location={}",
+        level,
+        quote(&frame.function),
+        quote(&frame.location)
+    );
+    if let Some((file, line)) = frame.op.as_deref().and_then(source_line) {
+        let fullname = std::fs::canonicalize(&file)
+            .map_or(file.clone(), |path| path.to_string_lossy().to_string());
+        fields.push_str(&format!(
+            ",file={},fullname={},line=\"{}\"",
+            quote(&file),
+            quote(&fullname),
+            line
+        ));
+    }
+    format!("{{{}}}", fields)
+}
+
+fn value_tuple(name: &str, value: &VALUE, key: &str) -> String {
+    format!(
+        "{{{}={},value={},type={}}}",
+        key,
+        quote(name),
+        quote(&value.to_json().to_string()),
+        quote(&value.get_type())
+    )
+}
+
+/// Instances run side by side, while the software stack is one thread.
+fn threads() -> Vec<Vec<Frame>> {
+    let frames = frames();
+    if is_software() {
+        if frames.is_empty() {
+            return vec![];
+        }
+        return vec![frames];
+    }
+    frames.into_iter().map(|frame| vec![frame]).collect()
+}
+
+fn is_software() -> bool {
+    matches!(debug_state(), State::Software)
+}
+
+/// How the last run stopped, from the message it ended with.
+fn stop_reason(output: &str) -> &'static str {
+    for line in output.lines().rev() {
+        if line.starts_with("Return break") {
+            return "breakpoint-hit";
+        }
+        if line.starts_with("Watch") || line.starts_with("Stream watch") {
+            return "watchpoint-trigger";
+        }
+        if line.starts_with("!!Interrupted") {
+            return "signal-received";
+        }
+    }
+    "end-stepping-range"
+}
+
+impl Session {
+    fn selected_frame(&self) -> Option<Frame> {
+        let mut threads = threads();
+        let index = self.thread.saturating_sub(1);
+        if index >= threads.len() {
+            return None;
+        }
+        threads.swap_remove(index).into_iter().next()
+    }
+
+    fn lookup(&self, expression: &str) -> Option<VALUE> {
+        let frame = self.selected_frame()?;
+        frame
+            .variables
+            .iter()
+            .find(|(var, _)| var == expression.trim_start_matches('%'))
+            .map(|(_, value)| *value)
+    }
+
+    fn run(&mut self, token: &str, command: &str) {
+        println!("{}^running", token);
+        println!("*running,thread-id=\"all\"");
+        let hits = breakpoint_hits();
+        let (output, _) = capture(|| execute_command(&command.to_string()));
+        console(&output);
+        let threads = threads();
+        if threads.is_empty() {
+            println!("*stopped,reason=\"exited-normally\"");
+            return;
+        }
+        if self.thread == 0 || self.thread > threads.len() {
+            self.thread = 1;
+        }
+        let hit = breakpoint_hits()
+            .into_iter()
+            .find(|(name, count)| *count > hits.get(name).copied().unwrap_or(0))
+            .map(|(name, _)| {
+                let number = self.breakpoints.iter().position(|location| {
+                    location.as_deref().map(|b| b.trim_start_matches('%')) == Some(&name)
+                });
+                match number {
+                    Some(number) => format!("reason=\"breakpoint-hit\",bkptno=\"{}\"", number + 1),
+                    None => String::from("reason=\"breakpoint-hit\""),
+                }
+            });
+        let reason = hit.unwrap_or(format!("reason=\"{}\"", stop_reason(&output)));
+        let frame = frame_tuple(0, &threads[self.thread - 1][0]);
+        println!(
+            "*stopped,{},thread-id=\"{}\",stopped-threads=\"all\",frame={}",
+            reason, self.thread, frame
+        );
+    }
+
+    /// Run a CLI command, reporting its output on the console stream.
+    fn cli(&self, token: &str, command: &str) {
+        let (output, failed) = capture(|| execute_command(&command.to_string()));
+        if failed {
+            let message = output
+                .lines()
+                .find_map(|line| line.strip_prefix("!!"))
+                .unwrap_or("Command failed");
+            let rest: Vec<&str> = output
+                .lines()
+                .filter(|line| !line.starts_with("!!"))
+                .collect();
+            console(&rest.join("\n"));
+            println!("{}^error,msg={}", token, quote(message));
+        } else {
+            console(&output);
+            println!("{}^done", token);
+        }
+    }
+
+    fn breakpoint_tuple(&self, number: usize, name: &str) -> String {
+        let line = source_line(name).map_or(String::new(), |(file, line)| {
+            format!(",file={},line=\"{}\"", quote(&file), line)
+        });
+        format!(
+            "{{number=\"{}\",type=\"breakpoint\",disp=\"keep\",enabled=\"y\",func={}{}}}",
+            number,
+            quote(name),
+            line
+        )
+    }
+
+    fn execute(&mut self, token: &str, operation: &str, args: &[&str]) -> bool {
+        let mut options = vec![];
+        let mut rest = vec![];
+        let mut iter = args.iter().copied();
+        while let Some(arg) = iter.next() {
+            match arg {
+                "--thread" => {
+                    if let Some(id) = iter.next().and_then(|id| id.parse::<usize>().ok()) {
+                        self.thread = id;
+                    }
+                }
+                "--frame" => {
+                    iter.next();
+                }
+                arg if arg.starts_with("--") => options.push(arg),
+                arg => rest.push(arg),
+            }
+        }
+        let args = rest;
+        match operation {
+            "gdb-exit" => {
+                println!("{}^exit", token);
+                return false;
+            }
+            "gdb-set"
+            | "gdb-show"
+            | "environment-cd"
+            | "enable-pretty-printing"
+            | "inferior-tty-set" => println!("{}^done", token),
+            "list-features" => println!("{}^done,features=[\"thread-info\"]", token),
+            "file-exec-and-symbols" | "file-exec-file" => match args.first() {
+                Some(file) => self.cli(token, &format!("load {}", unquote(file))),
+                None => println!("{}^error,msg=\"No file given\"", token),
+            },
+            "exec-run" => {
+                let function = args.first().copied().unwrap_or("main");
+                let (output, failed) = capture(|| execute_command(&format!("call {}", function)));
+                console(&output);
+                if failed {
+                    println!("{}^error,msg={}", token, quote("Failed to call function"));
+                } else {
+                    self.run(token, "continue");
+                }
+            }
+            "exec-continue" => self.run(token, "continue"),
+            "exec-step" => self.run(token, &format!("step {}", args.first().unwrap_or(&"1"))),
+            "exec-next" => self.run(token, "next"),
+            "exec-finish" => self.run(token, "finish"),
+            "exec-step-instruction" | "exec-next-instruction" => self.run(token, "stepi"),
+            "exec-until" => match args.first() {
+                Some(location) => self.run(token, &format!("until {}", location)),
+                None => self.run(token, "next"),
+            },
+            "break-insert" => {
+                let mut condition = None;
+                let mut location = None;
+                let mut index = 0;
+                while index < args.len() {
+                    match args[index] {
+                        "-c" if index + 1 < args.len() => {
+                            condition = Some(unquote(args[index + 1]));
+                            index += 1;
+                        }
+                        "-t" | "-h" | "-f" | "-d" | "-a" => (),
+                        arg => location = Some(arg),
+                    }
+                    index += 1;
+                }
+                let Some(location) = location else {
+                    println!("{}^error,msg=\"No location given\"", token);
+                    return true;
+                };
+                let command = match &condition {
+                    Some(condition) => format!("breakpoint {} if {}", location, condition),
+                    None => format!("breakpoint {}", location),
+                };
+                let (output, failed) = capture(|| execute_command(&command));
+                if failed {
+                    let message = output.lines().find_map(|line| line.strip_prefix("!!"));
+                    println!("{}^error,msg={}", token, quote(message.unwrap_or("Failed")));
+                    return true;
+                }
+                self.breakpoints.push(Some(location.to_string()));
+                let bkpt = self.breakpoint_tuple(self.breakpoints.len(), location);
+                println!("{}^done,bkpt={}", token, bkpt);
+            }
+            "break-delete" => {
+                for number in &args {
+                    let slot = number
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|number| self.breakpoints.get_mut(number.wrapping_sub(1)));
+                    match slot.and_then(|slot| slot.take()) {
+                        Some(name) => unset_breakpoint(&name),
+                        None => {
+                            println!("{}^error,msg=\"No breakpoint number {}\"", token, number);
+                            return true;
+                        }
+                    }
+                }
+                println!("{}^done", token);
+            }
+            "break-list" => {
+                let body: Vec<String> = self
+                    .breakpoints
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, name)| {
+                        let name = name.as_ref()?;
+                        Some(format!("bkpt={}", self.breakpoint_tuple(index + 1, name)))
+                    })
+                    .collect();
+                println!(
+                    "{}^done,BreakpointTable={{nr_rows=\"{}\",nr_cols=\"3\",hdr=[{{col_name=\"number\"}},{{col_name=\"func\"}},{{col_name=\"line\"}}],body=[{}]}}",
+                    token,
+                    body.len(),
+                    body.join(",")
+                );
+            }
+            "thread-info" => {
+                let threads: Vec<String> = threads()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, frames)| {
+                        format!(
+                            "{{id=\"{}\",target-id={},frame={},state=\"stopped\"}}",
+                            index + 1,
+                            quote(&frames[0].function),
+                            frame_tuple(0, &frames[0])
+                        )
+                    })
+                    .collect();
+                println!(
+                    "{}^done,threads=[{}],current-thread-id=\"{}\"",
+                    token,
+                    threads.join(","),
+                    self.thread.max(1)
+                );
+            }
+            "thread-list-ids" => {
+                let ids: Vec<String> = (1..=threads().len())
+                    .map(|id| format!("thread-id=\"{}\"", id))
+                    .collect();
+                println!(
+                    "{}^done,thread-ids={{{}}},number-of-threads=\"{}\"",
+                    token,
+                    ids.join(","),
+                    ids.len()
+                );
+            }
+            "thread-select" => {
+                let id = args.first().and_then(|id| id.parse::<usize>().ok());
+                match id {
+                    Some(id) if id >= 1 && id <= threads().len() => {
+                        self.thread = id;
+                        let frame = self.selected_frame().unwrap();
+                        println!(
+                            "{}^done,new-thread-id=\"{}\",frame={}",
+                            token,
+                            id,
+                            frame_tuple(0, &frame)
+                        );
+                    }
+                    _ => println!("{}^error,msg=\"Invalid thread id\"", token),
+                }
+            }
+            "stack-list-frames" | "stack-info-depth" => {
+                let threads = threads();
+                let frames = threads
+                    .get(self.thread.max(1) - 1)
+                    .map_or(&[][..], |frames| frames.as_slice());
+                if operation == "stack-info-depth" {
+                    println!("{}^done,depth=\"{}\"", token, frames.len());
+                    return true;
+                }
+                let stack: Vec<String> = frames
+                    .iter()
+                    .enumerate()
+                    .map(|(level, frame)| format!("frame={}", frame_tuple(level, frame)))
+                    .collect();
+                println!("{}^done,stack=[{}]", token, stack.join(","));
+            }
+            "stack-select-frame" => {
+                if is_software() {
+                    self.cli(token, &format!("frame {}", args.first().unwrap_or(&"0")));
+                } else {
+                    println!("{}^done", token);
+                }
+            }
+            "stack-list-variables" | "stack-list-locals" => {
+                let key = if operation == "stack-list-locals" {
+                    "locals"
+                } else {
+                    "variables"
+                };
+                let no_values = options.contains(&"--no-values") || args.first() == Some(&"0");
+                let variables: Vec<String> = self
+                    .selected_frame()
+                    .map_or(vec![], |frame| frame.variables)
+                    .iter()
+                    .map(|(name, value)| {
+                        if no_values {
+                            format!("{{name={}}}", quote(name))
+                        } else {
+                            value_tuple(name, value, "name")
+                        }
+                    })
+                    .collect();
+                println!("{}^done,{}=[{}]", token, key, variables.join(","));
+            }
+            "data-evaluate-expression" => {
+                let expression = unquote(&args.join(" "));
+                match self.lookup(&expression) {
+                    Some(value) => println!(
+                        "{}^done,value={}",
+                        token,
+                        quote(&value.to_json().to_string())
+                    ),
+                    None => println!(
+                        "{}^error,msg={}",
+                        token,
+                        quote(&format!("No symbol \"{}\" in current context.", expression))
+                    ),
+                }
+            }
+            "var-create" => {
+                // -var-create {name | "-"} {frame-addr | "*" | "@"} expression
+                if args.len() < 3 {
+                    println!(
+                        "{}^error,msg=\"-var-create: Usage: NAME FRAME EXPRESSION.\"",
+                        token
+                    );
+                    return true;
+                }
+                let expression = unquote(&args[2..].join(" "));
+                let name = match args[0] {
+                    "-" => format!("var{}", self.variables.len() + 1),
+                    name => name.to_string(),
+                };
+                match self.describe_variable(&expression) {
+                    Some((numchild, value, value_type)) => {
+                        self.variables.insert(name.clone(), expression);
+                        println!(
+                            "{}^done,name={},numchild=\"{}\",value={},type={},thread-id=\"{}\"",
+                            token,
+                            quote(&name),
+                            numchild,
+                            quote(&value),
+                            quote(&value_type),
+                            self.thread.max(1)
+                        );
+                    }
+                    None => println!(
+                        "{}^error,msg=\"-var-create: unable to create variable object\"",
+                        token
+                    ),
+                }
+            }
+            "var-delete" => match args.first().and_then(|name| self.variables.remove(*name)) {
+                Some(_) => println!("{}^done,ndeleted=\"1\"", token),
+                None => println!("{}^error,msg=\"Variable object not found\"", token),
+            },
+            "var-evaluate-expression" => {
+                let expression = args.first().and_then(|name| self.variables.get(*name));
+                match expression.and_then(|expression| self.describe_variable(expression)) {
+                    Some((_, value, _)) => println!("{}^done,value={}", token, quote(&value)),
+                    None => println!("{}^error,msg=\"Variable object not found\"", token),
+                }
+            }
+            "var-list-children" => {
+                let Some(name) = args.first() else {
+                    println!("{}^error,msg=\"-var-list-children: Usage: NAME.\"", token);
+                    return true;
+                };
+                let Some(function) = self.variables.get(*name).cloned() else {
+                    println!("{}^error,msg=\"Variable object not found\"", token);
+                    return true;
+                };
+                let no_values = options.contains(&"--no-values") || args.get(1) == Some(&"0");
+                let children: Vec<String> = frames()
+                    .into_iter()
+                    .find(|frame| frame.function == function)
+                    .map_or(vec![], |frame| frame.variables)
+                    .iter()
+                    .map(|(var, value)| {
+                        let shown = if no_values {
+                            String::new()
+                        } else {
+                            format!(",value={}", quote(&value.to_json().to_string()))
+                        };
+                        format!(
+                            "child={{name={},exp={},numchild=\"0\"{},type={}}}",
+                            quote(&format!("{}.{}", name, var)),
+                            quote(var),
+                            shown,
+                            quote(&value.get_type())
+                        )
+                    })
+                    .collect();
+                println!(
+                    "{}^done,numchild=\"{}\",children=[{}],has_more=\"0\"",
+                    token,
+                    children.len(),
+                    children.join(",")
+                );
+            }
+            "interpreter-exec" => {
+                let command = unquote(&args[1..].join(" "));
+                if args.first() == Some(&"console") && !command.is_empty() {
+                    self.cli(token, &command);
+                } else {
+                    println!(
+                        "{}^error,msg=\"Only the console interpreter is supported\"",
+                        token
+                    );
+                }
+            }
+            _ => println!(
+                "{}^error,msg={}",
+                token,
+                quote(&format!("Undefined MI command: {}", operation))
+            ),
+        }
+        true
+    }
+
+    /// Number of children, value and type of an expression: a function name
+    /// stands for its instance, whose variables are its children.
+    fn describe_variable(&self, expression: &str) -> Option<(usize, String, String)> {
+        if let Some(frame) = frames()
+            .into_iter()
+            .find(|frame| frame.function == expression)
+        {
+            let children = frame.variables.len();
+            return Some((children, String::from("{...}"), String::from("instance")));
+        }
+        let value = self.lookup(expression)?;
+        Some((0, value.to_json().to_string(), value.get_type()))
+    }
+}
+
+/// Split an MI input line into its token and command.
+fn split_token(line: &str) -> (&str, &str) {
+    let digits = line
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(line.len());
+    line.split_at(digits)
+}
+
+pub fn run() {
+    set_option("color", "off");
+    let mut session = Session {
+        thread: 1,
+        breakpoints: vec![],
+        variables: HashMap::new(),
+    };
+    println!("(gdb) ");
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            println!("(gdb) ");
+            continue;
+        }
+        let (token, command) = split_token(line);
+        if let Some(command) = command.strip_prefix('-') {
+            let list: Vec<&str> = command.split_whitespace().collect();
+            if !session.execute(token, list[0], &list[1..]) {
+                return;
+            }
+        } else if matches!(command, "quit" | "exit" | "q") {
+            println!("{}^exit", token);
+            return;
+        } else {
+            session.cli(token, command);
+        }
+        println!("(gdb) ");
+    }
+}
@@ -0,0 +1,177 @@
+use super::execute_command;
+use hestia::common::*;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Clone)]
+enum Item {
+    Command(String),
+    Repeat(String, Vec<Item>),
+}
+
+fn parse_repeat(line: &str) -> Option<(String, &str)> {
+    let rest = line.strip_prefix("repeat ")?;
+    let (count, body) = rest.split_once("{")?;
+    Some((count.trim().to_string(), body.trim()))
+}
+
+fn parse_block(lines: &[&str], index: &mut usize, nested: bool) -> Result<Vec<Item>, String> {
+    let mut items = vec![];
+    while *index < lines.len() {
+        let line = lines[*index].trim();
+        *index += 1;
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+        if line == "}" {
+            if nested {
+                return Ok(items);
+            }
+            return Err(format!("unmatched '}}' at line {}", *index));
+        }
+        match parse_repeat(line) {
+            Some((count, body)) => {
+                if body.is_empty() {
+                    items.push(Item::Repeat(count, parse_block(lines, index, true)?));
+                } else {
+                    let body = body
+                        .strip_suffix("}")
+                        .ok_or(format!("missing '}}' at line {}", *index))?;
+                    let body = body
+                        .split(";")
+                        .map(|s| s.trim())
+                        .filter(|s| !s.is_empty())
+                        .map(|s| Item::Command(s.to_string()))
+                        .collect();
+                    items.push(Item::Repeat(count, body));
+                }
+            }
+            None => items.push(Item::Command(line.to_string())),
+        }
+    }
+    if nested {
+        Err("missing '}' at end of script".to_string())
+    } else {
+        Ok(items)
+    }
+}
+
+fn execute_items(items: &Vec<Item>) {
+    for item in items {
+        if should_stop() {
+            return;
+        }
+        match item {
+            Item::Command(command) => execute_command(command),
+            Item::Repeat(count, body) => match expand_variables(count).parse::<usize>() {
+                Ok(count) => {
+                    for _ in 0..count {
+                        if interrupted() {
+                            return;
+                        }
+                        execute_items(body);
+                    }
+                }
+                Err(_) => {
+                    println!("!!Invalid repeat count {}", count);
+                    fail(Failure::Command);
+                }
+            },
+        }
+    }
+}
+
+/// Run a `repeat N { ... }` typed on a single line.
+pub fn execute_repeat(command: &str) {
+    let lines = vec![command];
+    match parse_block(&lines, &mut 0, false) {
+        Ok(items) => execute_items(&items),
+        Err(err) => {
+            println!("!!{}", err);
+            fail(Failure::Command);
+        }
+    }
+}
+
+pub fn execute_script(lines: &str) {
+    let lines = lines.split("\n").collect::<Vec<&str>>();
+    match parse_block(&lines, &mut 0, false) {
+        Ok(items) => execute_items(&items),
+        Err(err) => {
+            println!("!!{}", err);
+            fail(Failure::Command);
+        }
+    }
+}
+
+/// Execute a command script, binding `$0` to its path and `$1`.. to `args`.
+pub fn execute_file(path: &str, args: &[String]) -> std::io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut lines = String::new();
+    file.read_to_string(&mut lines)?;
+    set_script_variable("0", path);
+    for (index, arg) in args.iter().enumerate() {
+        set_script_variable(&(index + 1).to_string(), arg);
+    }
+    execute_script(&lines);
+    Ok(())
+}
+
+/// Re-execute a recording made by `record` without printing, then report the
+/// cycle it ended at.
+pub fn replay(path: &str) {
+    record_input(&format!("replay {}", path));
+    match replaying(|| execute_file(path, &[])) {
+        Ok(()) => println!("Replayed {} to cycle {}", path, current_cycle()),
+        Err(_) => {
+            println!("!!Failed to load file {}", path);
+            fail(Failure::Command);
+        }
+    }
+}
+
+const MAX_SOURCE_DEPTH: usize = 16;
+static SOURCE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Relative paths that do not exist from the working directory are looked up
+/// next to the script doing the `source`.
+fn resolve_source(path: &str) -> String {
+    if Path::new(path).exists() || Path::new(path).is_absolute() {
+        return path.to_string();
+    }
+    let parent = get_script_variable("0").and_then(|script| {
+        Path::new(&script)
+            .parent()
+            .map(|parent| parent.join(path).to_string_lossy().to_string())
+    });
+    match parent {
+        Some(candidate) if Path::new(&candidate).exists() => candidate,
+        _ => path.to_string(),
+    }
+}
+
+pub fn source_file(path: &str, args: &[&str]) {
+    if SOURCE_DEPTH.load(Ordering::SeqCst) >= MAX_SOURCE_DEPTH {
+        println!("!!source {} nested too deeply", path);
+        fail(Failure::Command);
+        return;
+    }
+    let path = resolve_source(path);
+    let args = args
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect::<Vec<String>>();
+    let saved = take_positional_variables();
+    SOURCE_DEPTH.fetch_add(1, Ordering::SeqCst);
+    if execute_file(&path, &args).is_err() {
+        println!("!!Failed to load file {}", path);
+        fail(Failure::Command);
+    }
+    SOURCE_DEPTH.fetch_sub(1, Ordering::SeqCst);
+    take_positional_variables();
+    for (name, value) in saved {
+        set_script_variable(&name, &value);
+    }
+}
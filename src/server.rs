@@ -0,0 +1,78 @@
+use super::execute_command;
+use hestia::common::*;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+
+/// Answer one command: its printed output, whether it failed, the output
+/// parsed as JSON when it is, and where the simulation stands afterwards.
+pub fn reply(line: &str) -> Value {
+    let (output, failed) = capture(|| execute_command(&line.to_string()));
+    let result = serde_json::from_str::<Value>(output.trim()).unwrap_or(Value::Null);
+    let errors: Vec<&str> = output
+        .lines()
+        .filter_map(|line| line.strip_prefix("!!"))
+        .collect();
+    json!({
+        "ok": !failed,
+        "output": output,
+        "result": result,
+        "errors": errors,
+        "level": level_name(debug_state()),
+        "cycle": current_cycle(),
+    })
+}
+
+/// Serve one client until it disconnects or sends `exit`.
+fn serve<S: std::io::Read + Write>(reader: S, mut writer: S) -> std::io::Result<()> {
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if matches!(line, "quit" | "exit" | "q") {
+            break;
+        }
+        writeln!(writer, "{}", reply(line))?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Listen on `address`, a TCP address such as `127.0.0.1:7070` or a Unix
+/// socket given as `unix:<path>`, and serve clients one after another with
+/// the shell commands, one per line. Each reply is a line of JSON.
+pub fn listen(address: &str) {
+    set_option("color", "off");
+    let served = if let Some(path) = address.strip_prefix("unix:") {
+        // A socket left by an earlier server, never a file of another kind
+        let stale = std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket());
+        if stale {
+            let _ = std::fs::remove_file(path);
+        }
+        UnixListener::bind(path).map(|listener| {
+            println!("Listening on {}", address);
+            for stream in listener.incoming().flatten() {
+                if let Ok(reader) = stream.try_clone() {
+                    let _ = serve(reader, stream);
+                }
+            }
+        })
+    } else {
+        TcpListener::bind(address).map(|listener| {
+            println!("Listening on {}", address);
+            for stream in listener.incoming().flatten() {
+                if let Ok(reader) = stream.try_clone() {
+                    let _ = serve(reader, stream);
+                }
+            }
+        })
+    };
+    if let Err(err) = served {
+        println!("!!Failed to listen on {}: {}", address, err);
+        fail(Failure::Command);
+    }
+}